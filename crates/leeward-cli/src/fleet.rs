@@ -0,0 +1,416 @@
+//! Administration of several daemons at once
+//!
+//! Given more than one socket, or `--json`, `status`, `ping`, `drain` and
+//! `events` send their request to every daemon concurrently and report one
+//! row per socket, in the order the sockets were named. A daemon that
+//! cannot be reached or refuses the request has its error in its own row
+//! while the others carry on, and the command exits with the worst outcome
+//! of any of them.
+
+use crate::{DRAIN_POLL_INTERVAL, Wire, error_outcome, exit_with, send_request};
+use leeward_core::OutcomeCode;
+use leeward_core::config::default_socket_path;
+use leeward_core::protocol::{EventKind, Request, Response};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Outcomes a daemon can fail with here, least serious first
+const SEVERITY: [OutcomeCode; 4] = [
+    OutcomeCode::Daemon,
+    OutcomeCode::Protocol,
+    OutcomeCode::InvalidArgument,
+    OutcomeCode::ConnectionFailed,
+];
+
+/// Why one daemon has no answer in its row
+#[derive(Debug)]
+pub struct Failure {
+    pub outcome: OutcomeCode,
+    pub message: String,
+}
+
+impl Failure {
+    /// The daemon answered with something other than what was asked for
+    pub fn unexpected(response: Response) -> Self {
+        match response {
+            Response::Error { message, .. } => Self {
+                outcome: OutcomeCode::Daemon,
+                message,
+            },
+            _ => Self {
+                outcome: OutcomeCode::Protocol,
+                message: "unexpected response".to_string(),
+            },
+        }
+    }
+
+    /// Print the error and exit with its code, as a single-daemon command
+    /// would
+    pub fn exit(self) -> ! {
+        eprintln!("Error: {}", self.message);
+        exit_with(self.outcome)
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({ "message": self.message, "exit_code": self.outcome.code() })
+    }
+}
+
+impl From<leeward_core::LeewardError> for Failure {
+    fn from(error: leeward_core::LeewardError) -> Self {
+        Self {
+            outcome: error_outcome(&error),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(error: std::io::Error) -> Self {
+        leeward_core::LeewardError::from(error).into()
+    }
+}
+
+/// Daemons a command can address together
+#[derive(clap::Args)]
+pub struct Targets {
+    /// Socket path, repeatable to address several daemons at once (defaults
+    /// to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+    #[arg(short, long)]
+    socket: Vec<PathBuf>,
+
+    /// File of further socket paths, one per line; blank lines and lines
+    /// starting with # are skipped
+    #[arg(long, value_name = "FILE")]
+    sockets_from: Option<PathBuf>,
+
+    /// Print JSON, each row or event naming the socket it came from
+    #[arg(long)]
+    pub json: bool,
+}
+
+impl Targets {
+    /// The sockets named, in order, or the default socket if none are
+    ///
+    /// Exits if `--sockets-from` cannot be read.
+    pub fn sockets(&self) -> Vec<PathBuf> {
+        let mut sockets = self.socket.clone();
+        if let Some(file) = &self.sockets_from {
+            let list = std::fs::read_to_string(file).unwrap_or_else(|e| {
+                eprintln!("Error: cannot read {}: {e}", file.display());
+                exit_with(OutcomeCode::InvalidArgument);
+            });
+            sockets.extend(
+                list.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(PathBuf::from),
+            );
+        }
+        if sockets.is_empty() {
+            sockets.push(default_socket_path());
+        }
+        sockets
+    }
+
+    /// The one socket to address as a single daemon, or `None` if the
+    /// command fans out, which any `--json` output does too
+    pub fn single(&self, sockets: &[PathBuf]) -> Option<PathBuf> {
+        match sockets {
+            [socket] if !self.json => Some(socket.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Check `socket`'s path without exiting, unlike [`crate::connect`]
+fn check(socket: &Path) -> Result<(), Failure> {
+    leeward_core::socket::check_socket_path(socket).map_err(|e| Failure {
+        outcome: OutcomeCode::InvalidArgument,
+        message: e.to_string(),
+    })
+}
+
+/// Run `ask` against every socket concurrently and collect the answers in
+/// socket order
+async fn fan_out<T, F, Fut>(sockets: &[PathBuf], ask: F) -> Vec<Result<T, Failure>>
+where
+    T: Send + 'static,
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = Result<T, Failure>> + Send + 'static,
+{
+    let tasks: Vec<_> = sockets
+        .iter()
+        .map(|socket| tokio::spawn(ask(socket.clone())))
+        .collect();
+    let mut rows = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        rows.push(task.await.unwrap_or_else(|e| {
+            Err(Failure {
+                outcome: OutcomeCode::Unknown,
+                message: e.to_string(),
+            })
+        }));
+    }
+    rows
+}
+
+/// Print one row per socket, as JSON or as a table, then exit with the
+/// worst outcome if any daemon failed
+fn report<T>(
+    sockets: &[PathBuf],
+    rows: &[Result<T, Failure>],
+    json: bool,
+    header: &str,
+    line: impl Fn(&T) -> String,
+    value: impl Fn(&T) -> serde_json::Value,
+) {
+    if json {
+        let rows: Vec<_> = sockets
+            .iter()
+            .zip(rows)
+            .map(|(socket, row)| match row {
+                Ok(answer) => serde_json::json!({ "socket": socket, "ok": value(answer) }),
+                Err(failure) => serde_json::json!({ "socket": socket, "error": failure.json() }),
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(rows));
+    } else {
+        let width = sockets
+            .iter()
+            .map(|socket| socket.display().to_string().len())
+            .max()
+            .unwrap_or(0);
+        println!("{:<width$}  {header}", "SOCKET");
+        for (socket, row) in sockets.iter().zip(rows) {
+            let text = match row {
+                Ok(answer) => line(answer),
+                Err(failure) => format!("error: {}", failure.message),
+            };
+            println!("{:<width$}  {text}", socket.display().to_string());
+        }
+    }
+    if let Some(worst) = worst(rows.iter().filter_map(|row| row.as_ref().err())) {
+        exit_with(worst);
+    }
+}
+
+/// The most serious outcome among `failures`
+fn worst<'a>(failures: impl Iterator<Item = &'a Failure>) -> Option<OutcomeCode> {
+    failures
+        .map(|failure| failure.outcome)
+        .max_by_key(|outcome| {
+            SEVERITY
+                .iter()
+                .position(|known| known == outcome)
+                .unwrap_or(SEVERITY.len())
+        })
+}
+
+/// One daemon's worker counts
+struct Counts {
+    total: usize,
+    idle: usize,
+    busy: usize,
+    stale: usize,
+    draining: usize,
+    drained: u64,
+    snapshot_age_ms: u64,
+}
+
+async fn ask_status(socket: &Path, wire: Wire) -> Result<Counts, Failure> {
+    check(socket)?;
+    match send_request(socket, &Request::Status, wire).await? {
+        Response::Status {
+            total,
+            idle,
+            busy,
+            stale,
+            draining,
+            drained,
+            snapshot_age_ms,
+            ..
+        } => Ok(Counts {
+            total,
+            idle,
+            busy,
+            stale,
+            draining,
+            drained,
+            snapshot_age_ms,
+        }),
+        other => Err(Failure::unexpected(other)),
+    }
+}
+
+/// `leeward status` across `sockets`
+pub async fn status(sockets: &[PathBuf], json: bool, wire: Wire) {
+    let rows = fan_out(
+        sockets,
+        |socket| async move { ask_status(&socket, wire).await },
+    )
+    .await;
+    report(
+        sockets,
+        &rows,
+        json,
+        &format!(
+            "{:>5} {:>5} {:>5} {:>5} {:>8} {:>8}",
+            "TOTAL", "IDLE", "BUSY", "STALE", "DRAINING", "AGE(ms)"
+        ),
+        |c| {
+            format!(
+                "{:>5} {:>5} {:>5} {:>5} {:>8} {:>8}",
+                c.total, c.idle, c.busy, c.stale, c.draining, c.snapshot_age_ms
+            )
+        },
+        |c| {
+            serde_json::json!({
+                "total": c.total,
+                "idle": c.idle,
+                "busy": c.busy,
+                "stale": c.stale,
+                "draining": c.draining,
+                "drained": c.drained,
+                "snapshot_age_ms": c.snapshot_age_ms,
+            })
+        },
+    );
+}
+
+/// `leeward ping` across `sockets`, timing each round trip
+pub async fn ping(sockets: &[PathBuf], json: bool, wire: Wire) {
+    let rows = fan_out(sockets, |socket| async move {
+        check(&socket)?;
+        let start = Instant::now();
+        match send_request(&socket, &Request::Ping, wire).await? {
+            Response::Pong => Ok(start.elapsed()),
+            other => Err(Failure::unexpected(other)),
+        }
+    })
+    .await;
+    report(
+        sockets,
+        &rows,
+        json,
+        "PING",
+        |latency| format!("pong in {:.1}ms", latency.as_secs_f64() * 1000.0),
+        |latency| {
+            let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+            serde_json::json!({ "latency_us": micros })
+        },
+    );
+}
+
+/// What a drain did on one daemon
+struct Drained {
+    profile: String,
+    scheduled: usize,
+    template_rebuilt: bool,
+    /// Whether `--wait` saw it through
+    done: bool,
+}
+
+/// `leeward drain` across `sockets`; with `wait`, each row is reported
+/// once its own daemon has recycled every drained worker
+pub async fn drain(
+    sockets: &[PathBuf],
+    profile: &str,
+    reason: Option<&str>,
+    wait: bool,
+    json: bool,
+    wire: Wire,
+) {
+    let rows = fan_out(sockets, |socket| {
+        let request = Request::DrainProfile {
+            profile: profile.to_string(),
+            reason: reason.map(str::to_string),
+        };
+        async move {
+            check(&socket)?;
+            let status = match send_request(&socket, &request, wire).await? {
+                Response::Drain(status) => status,
+                other => return Err(Failure::unexpected(other)),
+            };
+            let mut drained = Drained {
+                profile: status.profile,
+                scheduled: status.scheduled,
+                template_rebuilt: status.template_rebuilt,
+                done: false,
+            };
+            while wait && !drained.done {
+                drained.done = ask_status(&socket, wire).await?.draining == 0;
+                if !drained.done {
+                    tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+                }
+            }
+            Ok(drained)
+        }
+    })
+    .await;
+    report(
+        sockets,
+        &rows,
+        json,
+        "DRAIN",
+        |d| {
+            let rebuilt = if d.template_rebuilt {
+                ", root template rebuilt"
+            } else {
+                ""
+            };
+            let done = if d.done { ", drained" } else { "" };
+            format!(
+                "{} workers of profile {}{rebuilt}{done}",
+                d.scheduled, d.profile
+            )
+        },
+        |d| {
+            serde_json::json!({
+                "profile": d.profile,
+                "scheduled": d.scheduled,
+                "template_rebuilt": d.template_rebuilt,
+                "drained": d.done,
+            })
+        },
+    );
+}
+
+/// `leeward events` across `sockets`, each line prefixed with the socket it
+/// came from, until every daemon has gone away
+pub async fn events(sockets: &[PathBuf], kinds: &[EventKind], json: bool, wire: Wire) {
+    let rows = fan_out(sockets, |socket| {
+        let kinds = kinds.to_vec();
+        async move {
+            check(&socket)?;
+            let prefix = socket.display().to_string();
+            let followed = crate::follow_events(&socket, kinds, wire, |event| {
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::json!({ "socket": prefix, "event": event })
+                    );
+                } else {
+                    println!("{prefix}  {}", crate::describe_event(&event));
+                }
+            })
+            .await;
+            if let Err(failure) = &followed {
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::json!({ "socket": prefix, "error": failure.json() })
+                    );
+                } else {
+                    println!("{prefix}  error: {}", failure.message);
+                }
+            }
+            followed
+        }
+    })
+    .await;
+    if let Some(worst) = worst(rows.iter().filter_map(|row| row.as_ref().err())) {
+        exit_with(worst);
+    }
+}
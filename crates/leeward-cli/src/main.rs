@@ -1,80 +1,1905 @@
 //! leeward CLI - Command line interface for the sandbox
 
-use clap::{Parser, Subcommand};
-use leeward_core::config::default_socket_path;
-use std::path::PathBuf;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+mod fleet;
+mod out_dir;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use leeward_core::config::{Interpreter, default_socket_path};
+use leeward_core::{ByteSize, LeewardError, OutcomeCode};
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 
-/// Send request to daemon and receive response
-async fn send_request(
-    socket_path: &PathBuf,
-    request: &leeward_core::protocol::Request,
-) -> Result<leeward_core::protocol::Response, Box<dyn std::error::Error>> {
-    // Connect to daemon
-    let mut stream = UnixStream::connect(socket_path).await?;
+/// Encoding used on the daemon socket
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Wire {
+    /// Length-prefixed msgpack (the default, and the fast path)
+    #[default]
+    Msgpack,
+    /// Newline-delimited JSON, for debugging
+    Json,
+}
+
+/// Input files larger than this are uploaded ahead of the request
+const UPLOAD_THRESHOLD: u64 = 256 * 1024;
+
+/// Connections an upload may use before it is given up on
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Age past which `leeward status` warns that the daemon's view of its
+/// pool has stopped updating, in ms
+const STALE_STATUS_MS: u64 = 5000;
+
+/// Send request to daemon and receive response
+async fn send_request(
+    socket_path: &Path,
+    request: &leeward_core::protocol::Request,
+    wire: Wire,
+) -> leeward_core::Result<leeward_core::protocol::Response> {
+    // Connect to daemon
+    let mut stream = connect(socket_path)?;
+
+    if matches!(wire, Wire::Json) {
+        return send_json_request(stream, request).await;
+    }
+
+    // Encode request
+    let request_bytes = leeward_core::protocol::encode(request)?;
+
+    // Send length prefix (4 bytes, big-endian)
+    let len = u32::try_from(request_bytes.len()).map_err(|_| {
+        leeward_core::LeewardError::InvalidRequest("request too large to frame".into())
+    })?;
+    stream.write_all(&len.to_be_bytes()).await?;
+
+    // Send request
+    stream.write_all(&request_bytes).await?;
+
+    // Read response length
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).await?;
+    let response_len = u32::from_be_bytes(len_buf) as usize;
+
+    // Read response
+    let mut response_buf = vec![0u8; response_len];
+    stream.read_exact(&mut response_buf).await?;
+
+    // Decode response
+    let response = leeward_core::protocol::decode(&response_buf)?;
+
+    Ok(response)
+}
+
+/// Connect to the daemon, checking the socket path first
+///
+/// Connecting to a Unix socket never waits for the daemon to accept, so
+/// the blocking connect is fine here.
+fn connect(socket_path: &Path) -> leeward_core::Result<UnixStream> {
+    if let Err(e) = leeward_core::socket::check_socket_path(socket_path) {
+        eprintln!("Error: {e}");
+        exit_with(OutcomeCode::InvalidArgument);
+    }
+    let stream = leeward_core::socket::connect(socket_path)?;
+    stream.set_nonblocking(true)?;
+    Ok(UnixStream::from_std(stream)?)
+}
+
+/// Send one JSON line and read one back
+async fn send_json_request(
+    mut stream: UnixStream,
+    request: &leeward_core::protocol::Request,
+) -> leeward_core::Result<leeward_core::protocol::Response> {
+    let mut request_bytes = leeward_core::protocol::encode_json(request)?;
+    request_bytes.push(b'\n');
+    stream.write_all(&request_bytes).await?;
+
+    let mut line = Vec::new();
+    BufReader::new(stream).read_until(b'\n', &mut line).await?;
+    tracing::debug!(response = %String::from_utf8_lossy(&line).trim_end(), "JSON response");
+
+    leeward_core::protocol::decode_json(&line)
+}
+
+/// Shells `leeward sh` can run
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Shell {
+    #[default]
+    Sh,
+    Bash,
+}
+
+impl From<Shell> for Interpreter {
+    fn from(shell: Shell) -> Self {
+        match shell {
+            Shell::Sh => Self::Sh,
+            Shell::Bash => Self::Bash,
+        }
+    }
+}
+
+/// Interpreters `leeward explain` can ask about
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Lang {
+    #[default]
+    Python,
+    Sh,
+    Bash,
+}
+
+impl From<Lang> for Interpreter {
+    fn from(lang: Lang) -> Self {
+        match lang {
+            Lang::Python => Self::Python,
+            Lang::Sh => Self::Sh,
+            Lang::Bash => Self::Bash,
+        }
+    }
+}
+
+/// Request for `leeward sh`: `command` if given, otherwise the script
+/// named by the first of `args`, staged as an input file and sourced
+fn shell_request(
+    command: Option<String>,
+    mut args: Vec<String>,
+    shell: Shell,
+) -> Result<leeward_core::protocol::RequestBuilder, Box<dyn std::error::Error>> {
+    use leeward_core::protocol::RequestBuilder;
+
+    let builder = if let Some(command) = command {
+        RequestBuilder::new(command)
+    } else {
+        let script = PathBuf::from(args.remove(0));
+        let name = script
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{} is not a script file", script.display()))?
+            .to_owned();
+        let contents = std::fs::read(&script)
+            .map_err(|e| format!("failed to read {}: {e}", script.display()))?;
+        // Normalized as the daemon would the code itself
+        let contents = match String::from_utf8(contents) {
+            Ok(mut text) => {
+                leeward_core::source::normalize(&mut text, shell.into());
+                text.into_bytes()
+            }
+            Err(e) => e.into_bytes(),
+        };
+
+        // Sourced rather than run, so it needs no second exec in the sandbox
+        RequestBuilder::new(format!(". './{}'", name.replace('\'', r"'\''")))
+            .with_file(name, contents)
+    };
+
+    Ok(args
+        .into_iter()
+        .fold(builder.interpreter(shell.into()), RequestBuilder::arg))
+}
+
+/// The daemon a command talks to, its info asked for when first needed
+struct Daemon<'a> {
+    socket: &'a Path,
+    wire: Wire,
+    /// Whether the daemon has been asked, since one that predates `Hello`
+    /// has no info to give
+    asked: bool,
+    info: Option<leeward_core::protocol::DaemonInfo>,
+}
+
+impl<'a> Daemon<'a> {
+    const fn new(socket: &'a Path, wire: Wire) -> Self {
+        Self {
+            socket,
+            wire,
+            asked: false,
+            info: None,
+        }
+    }
+
+    /// What the daemon advertises, if it says
+    async fn info(&mut self) -> Option<&leeward_core::protocol::DaemonInfo> {
+        use leeward_core::protocol::{Request, Response};
+
+        if !self.asked {
+            self.asked = true;
+            if let Ok(Response::Hello(info)) =
+                send_request(self.socket, &Request::Hello, self.wire).await
+            {
+                self.info = Some(info);
+            }
+        }
+        self.info.as_ref()
+    }
+}
+
+/// Stage each of `files` as an input file named after it
+///
+/// Small files go inline, within the daemon's limits; larger ones are
+/// uploaded first, a chunk at a time, and an upload cut off by a dropped
+/// connection resumes on a new one. Sizes are checked before anything is
+/// read, so a file the daemon cannot take fails early.
+async fn with_files(
+    daemon: &mut Daemon<'_>,
+    mut builder: leeward_core::protocol::RequestBuilder,
+    files: Vec<PathBuf>,
+) -> Result<leeward_core::protocol::RequestBuilder, Box<dyn std::error::Error>> {
+    use leeward_core::protocol::feature;
+
+    let mut inline = 0;
+    for path in files {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{} is not a file", path.display()))?
+            .to_owned();
+        let file = std::fs::File::open(&path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        let len = file.metadata()?.len();
+
+        let info = daemon.info().await;
+        let sizes = info.map(|info| info.limits.sizes);
+        let fits = sizes.is_none_or(|sizes| {
+            len <= sizes.max_file.bytes() && inline + len <= sizes.max_files.bytes()
+        });
+        if len <= UPLOAD_THRESHOLD && fits {
+            let contents = tokio::fs::read(&path)
+                .await
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            inline += len;
+            builder = builder.with_file(name, contents);
+            continue;
+        }
+        if let Some(info) = info.filter(|info| !info.supports(feature::EXEC_UPLOADS)) {
+            let max = info.limits.sizes.max_file.bytes();
+            return Err(format!(
+                "{} is {len} bytes, over the daemon's limit of {max} bytes for an input file sent \
+                 inline, and the daemon takes no uploads",
+                path.display()
+            )
+            .into());
+        }
+
+        let socket_path = daemon.socket.to_owned();
+        let upload_name = name.clone();
+        let id = tokio::task::spawn_blocking(move || upload(&socket_path, &upload_name, &file))
+            .await??;
+        builder = builder.with_upload(name, id);
+    }
+    Ok(builder)
+}
+
+/// Upload `file` as `name`, reconnecting to resume if the connection drops
+fn upload(socket_path: &Path, name: &str, file: &std::fs::File) -> leeward_core::Result<u64> {
+    let mut attempt = 1;
+    loop {
+        let uploaded = leeward_core::client::Client::connect(socket_path)
+            .and_then(|mut client| client.upload_file(name, file, false));
+        match uploaded {
+            Err(LeewardError::Io(e)) if attempt < UPLOAD_ATTEMPTS => {
+                tracing::debug!(error = %e, attempt, "upload interrupted, resuming");
+                attempt += 1;
+            }
+            uploaded => return uploaded,
+        }
+    }
+}
+
+/// Send an execute request and exit with its outcome, relaying its output
+/// and, unless `quiet`, how the daemon adjusted the request; a detached
+/// one prints its execution id and returns
+///
+/// A request with inputs is checked against the daemon's size limits
+/// first, and fails as the daemon would fail it.
+async fn execute(
+    daemon: &mut Daemon<'_>,
+    request: leeward_core::protocol::ExecuteRequest,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    let inputs = !request.files.is_empty() || request.stdin.is_some();
+    if inputs
+        || !request.required_features().is_empty()
+        || request.interpreter != Interpreter::default()
+    {
+        // Daemons that predate `Hello` advertise nothing, so they get no
+        // warnings or checks
+        if let Some(info) = daemon.info().await {
+            for name in info.unsupported(&request) {
+                eprintln!(
+                    "Warning: the daemon does not advertise {name}; the request may fail or run \
+                     without it"
+                );
+            }
+            info.limits.sizes.check(&request)?;
+        }
+    }
+
+    let request = Request::Execute(request);
+    let response = tokio::select! {
+        response = send_request(daemon.socket, &request, daemon.wire) => response?,
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("Cancelled");
+            exit_with(OutcomeCode::Cancelled);
+        }
+    };
+
+    if let Response::Detached { execution_id } = response {
+        println!("{execution_id}");
+        if !quiet {
+            eprintln!("Detached; fetch the result with `leeward fetch {execution_id}`");
+        }
+        return Ok(());
+    }
+    finish(response, quiet)
+}
+
+/// Send an execute request and exit with its outcome, as [`execute`]
+/// does, writing its output into `dir` rather than relaying it
+///
+/// Always sent as msgpack; see [`out_dir`] for what `dir` holds.
+async fn execute_to_dir(
+    socket: &Path,
+    request: leeward_core::protocol::ExecuteRequest,
+    dir: PathBuf,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = socket.to_owned();
+    let running = tokio::task::spawn_blocking(move || out_dir::execute(&socket, request, &dir));
+    let response = tokio::select! {
+        response = running => response??,
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("Cancelled");
+            exit_with(OutcomeCode::Cancelled);
+        }
+    };
+
+    if !quiet {
+        for adjustment in &response.adjustments {
+            eprintln!("Note: {adjustment}");
+        }
+    }
+    if !response.success {
+        eprintln!(
+            "Error: {}",
+            response.error.as_deref().unwrap_or("Unknown error")
+        );
+    }
+    exit_with(response.outcome());
+}
+
+/// Take the result of a detached execution and exit with its outcome, as
+/// `exec` would have
+async fn fetch(
+    socket_path: &Path,
+    execution_id: u64,
+    wire: Wire,
+    quiet: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = leeward_core::protocol::Request::FetchResult { execution_id };
+    finish(send_request(socket_path, &request, wire).await?, quiet)
+}
+
+/// Exit with the outcome of an execution, relaying its output and, unless
+/// `quiet`, how the daemon adjusted the request
+fn finish(response: leeward_core::protocol::Response, quiet: bool) -> ! {
+    use leeward_core::protocol::Response;
+
+    match response {
+        Response::Execute(resp) => {
+            relay(&resp, quiet);
+            exit_with(resp.outcome());
+        }
+        // The reason as the daemon gave it, naming the rule
+        Response::Error {
+            message,
+            kind: leeward_core::protocol::ErrorKind::PolicyRejected,
+        } => {
+            eprintln!("Rejected: {message}");
+            exit_with(OutcomeCode::InvalidRequest);
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+}
+
+/// Print an execution's output or error and, unless `quiet`, how the
+/// daemon adjusted the request
+fn relay(resp: &leeward_core::protocol::ExecuteResponse, quiet: bool) {
+    if !quiet {
+        for adjustment in &resp.adjustments {
+            eprintln!("Note: {adjustment}");
+        }
+    }
+    match (&resp.result, &resp.error) {
+        (Some(result), _) if resp.success => {
+            print!("{}", String::from_utf8_lossy(&result.stdout));
+            eprint!("{}", String::from_utf8_lossy(&result.stderr));
+            if !result.denials.is_empty() {
+                eprintln!(
+                    "sandbox denied: {}",
+                    leeward_core::denial::summary(&result.denials)
+                );
+            }
+        }
+        (_, error) => eprintln!("Error: {}", error.as_deref().unwrap_or("Unknown error")),
+    }
+}
+
+/// Run cells read from stdin on one connection until it ends
+///
+/// A blank line ends a cell. A line starting with `%` between cells
+/// changes the defaults the following cells get: `%timeout SECS`,
+/// `%memory MIB`, `%env NAME=VALUE`, or `%reset` to clear them.
+fn repl(socket_path: &Path, quiet: bool) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{ExecuteDefaults, Request, RequestBuilder, Response};
+    use std::io::BufRead;
+
+    let mut client = leeward_core::client::Client::connect(socket_path)?;
+    let mut defaults = ExecuteDefaults::default();
+    let prompt = std::io::stdin().is_terminal();
+    let mut lines = std::io::stdin().lock().lines();
+    let mut cell = String::new();
+    loop {
+        if prompt {
+            eprint!("{}", if cell.is_empty() { ">>> " } else { "... " });
+        }
+        match lines.next().transpose()?.as_deref() {
+            Some(line) if cell.is_empty() && line.starts_with('%') => {
+                let changed = match magic(line, defaults.clone()) {
+                    Ok(changed) => changed,
+                    Err(message) => {
+                        eprintln!("Error: {message}");
+                        continue;
+                    }
+                };
+                match client.set_defaults(changed.clone()) {
+                    Ok(adjustments) => {
+                        for adjustment in &adjustments {
+                            eprintln!("Note: {adjustment}");
+                        }
+                        defaults = changed;
+                    }
+                    Err(e) => eprintln!("Error: {e}"),
+                }
+            }
+            Some(line) if !line.trim().is_empty() => {
+                cell.push_str(line);
+                cell.push('\n');
+            }
+            end => {
+                if !cell.is_empty() {
+                    let request = RequestBuilder::from_source(std::mem::take(&mut cell)).build()?;
+                    match client.request(&Request::Execute(request))? {
+                        Response::Execute(resp) => relay(&resp, quiet),
+                        Response::Error { message, .. } => eprintln!("Error: {message}"),
+                        other => eprintln!("Unexpected response: {other:?}"),
+                    }
+                }
+                if end.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// `defaults` as changed by a REPL `%` line
+fn magic(
+    line: &str,
+    mut defaults: leeward_core::protocol::ExecuteDefaults,
+) -> Result<leeward_core::protocol::ExecuteDefaults, String> {
+    let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let value = value.trim();
+    let number = || {
+        value
+            .parse::<u64>()
+            .map_err(|_| format!("{name} takes a whole number, not {value:?}"))
+    };
+    match name {
+        "%timeout" => defaults.timeout = Some(std::time::Duration::from_secs(number()?)),
+        "%memory" => defaults.memory_limit = Some(ByteSize::mib(number()?)),
+        "%env" => {
+            let (key, value) = value
+                .split_once('=')
+                .ok_or_else(|| format!("%env takes NAME=VALUE, not {value:?}"))?;
+            defaults.env.retain(|(set, _)| set != key);
+            defaults.env.push((key.to_owned(), value.to_owned()));
+        }
+        "%reset" => defaults = leeward_core::protocol::ExecuteDefaults::default(),
+        _ => {
+            return Err(format!(
+                "unknown {name}; try %timeout, %memory, %env or %reset"
+            ));
+        }
+    }
+    Ok(defaults)
+}
+
+/// Ask the daemon what it is and supports, and print it
+async fn info(
+    socket_path: &Path,
+    json: bool,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    let info = match send_request(socket_path, &Request::Hello, wire).await {
+        Ok(Response::Hello(info)) => info,
+        Ok(Response::Error { message, .. }) => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        Ok(_) => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+        // Daemons that predate Hello hang up on it
+        Err(LeewardError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            eprintln!(
+                "Error: the daemon does not support `leeward info`; it may be older than this CLI"
+            );
+            exit_with(OutcomeCode::Protocol);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let limits = &info.limits;
+    println!(
+        "leeward-daemon {} (protocol {})",
+        info.version, info.protocol_version
+    );
+    if let Some(build) = &info.build {
+        println!(
+            "Built from {} at {} with {}",
+            build.git, build.built_at, build.rustc
+        );
+    }
+    if let Some(kernel) = &info.kernel {
+        println!("Kernel: {kernel}");
+    }
+    println!("Boot id: {}", info.boot_id);
+    println!("Languages: {}", info.languages.join(", "));
+    println!("Features:");
+    for feature in &info.features {
+        println!("  {feature}");
+    }
+    println!("Limits:");
+    println!("  {:<22}{}", "workers", limits.workers);
+    println!("  {:<22}{} bytes", "message size", limits.max_message_bytes);
+    println!("  {:<22}{} bytes", "code size", limits.max_code_bytes);
+    if let Some(bytes) = limits.fast_path_max_bytes {
+        println!("  {:<22}{} bytes", "fast path", bytes);
+    }
+    println!(
+        "  {:<22}{} ms",
+        "default timeout", limits.default_timeout_ms
+    );
+    match limits.default_memory_limit {
+        Some(limit) => println!("  {:<22}{limit}", "default memory limit"),
+        None => println!("  {:<22}none", "default memory limit"),
+    }
+    println!("  {:<22}{}", "/tmp size", limits.tmp_size);
+    if limits.max_request_wall.is_zero() {
+        println!("  {:<22}none", "request deadline");
+    } else {
+        println!("  {:<22}{}", "request deadline", limits.max_request_wall);
+    }
+    Ok(())
+}
+
+/// `on` or `off`
+fn parse_switch(state: &str) -> Result<bool, String> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected 'on' or 'off', got '{state}'")),
+    }
+}
+
+/// Print the log filter and debug flags in effect
+fn print_log_settings(filter: Option<&str>, debug_flags: &[leeward_core::protocol::DebugFlag]) {
+    let flags: Vec<_> = debug_flags.iter().map(|flag| flag.name()).collect();
+    println!("Log filter: {}", filter.unwrap_or("fixed"));
+    println!(
+        "Debug flags: {}",
+        if flags.is_empty() {
+            "none".to_owned()
+        } else {
+            flags.join(", ")
+        }
+    );
+}
+
+/// Send a log filter or debug flag change and print the outcome
+async fn adjust_logging(
+    socket: &Path,
+    request: &leeward_core::protocol::Request,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::Response;
+
+    match send_request(socket, request, wire).await? {
+        Response::LogSettings {
+            filter,
+            debug_flags,
+        } => print_log_settings(filter.as_deref(), &debug_flags),
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+    Ok(())
+}
+
+/// Print executions in flight per client and the limits they are held to,
+/// the shared memory slots leased, then the daemon's log settings
+async fn print_inflight(socket: &Path, wire: Wire) {
+    use leeward_core::protocol::{Request, Response};
+
+    let limit = |limit: usize| {
+        if limit == 0 {
+            "none".to_owned()
+        } else {
+            limit.to_string()
+        }
+    };
+    match send_request(socket, &Request::StatusDetailed, wire).await {
+        Ok(Response::StatusDetailed {
+            inflight,
+            max_inflight_per_connection,
+            max_inflight_per_peer_uid,
+            log_filter,
+            debug_flags,
+            shm_slots,
+            standby,
+            ..
+        }) => {
+            println!(
+                "In flight (limit {} per connection, {} per uid):",
+                limit(max_inflight_per_connection),
+                limit(max_inflight_per_peer_uid)
+            );
+            for peer in inflight {
+                println!("  uid {}: {}", peer.uid, peer.inflight);
+            }
+            if standby > 0 {
+                println!("Standby workers: {standby}");
+            }
+            if shm_slots.total > 0 {
+                println!(
+                    "Shared memory slots: {} of {} leased, {} reclaimed",
+                    shm_slots.leased, shm_slots.total, shm_slots.reclaimed
+                );
+            }
+            print_log_settings(log_filter.as_deref(), &debug_flags);
+        }
+        // Daemons that predate StatusDetailed answer with an error or hang up
+        Ok(Response::Error { message, .. }) => tracing::debug!("no in-flight details: {}", message),
+        Err(e) => tracing::debug!("no in-flight details: {}", e),
+        Ok(_) => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+}
+
+/// Event kinds `leeward events` can filter on
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum EventFilter {
+    /// Pool saturation alerts and their resolutions
+    Alert,
+    /// Workers that died, with the stage they died in
+    WorkerDied,
+    /// Changes to the daemon's effective policy
+    PolicyChange,
+}
+
+impl From<EventFilter> for leeward_core::protocol::EventKind {
+    fn from(filter: EventFilter) -> Self {
+        match filter {
+            EventFilter::Alert => Self::Alert,
+            EventFilter::WorkerDied => Self::WorkerDied,
+            EventFilter::PolicyChange => Self::PolicyChange,
+        }
+    }
+}
+
+/// Print a policy change and the fields it changed, one per line
+fn print_policy_change(change: &leeward_core::policy::PolicyChange) {
+    let before = change.fingerprint_before.as_deref().unwrap_or("-");
+    let reason = change
+        .reason
+        .as_deref()
+        .map(|reason| format!(" ({reason})"))
+        .unwrap_or_default();
+    println!(
+        "{} {}{reason}: {before} -> {}",
+        change.timestamp_ms, change.actor, change.fingerprint_after
+    );
+    for field in &change.changes {
+        println!(
+            "  {}: {} -> {} [{}]",
+            field.field,
+            field.before.as_deref().unwrap_or("unset"),
+            field.after.as_deref().unwrap_or("unset"),
+            field.provenance
+        );
+    }
+}
+
+/// One line describing `event`, as `leeward events` prints it
+fn describe_event(event: &leeward_core::protocol::Event) -> String {
+    event.alert.as_ref().map_or_else(
+        || format!("{} {:?}: {}", event.timestamp_ms, event.kind, event.message),
+        |alert| {
+            format!(
+                "{} alert {:?} {}: {}",
+                event.timestamp_ms, alert.state, alert.alert, event.message
+            )
+        },
+    )
+}
+
+/// Subscribe to daemon events and print them until the daemon goes away
+async fn stream_events(
+    socket_path: &Path,
+    kinds: Vec<leeward_core::protocol::EventKind>,
+    wire: Wire,
+) {
+    if let Err(failure) = follow_events(socket_path, kinds, wire, |event| {
+        println!("{}", describe_event(&event));
+    })
+    .await
+    {
+        failure.exit();
+    }
+}
+
+/// Subscribe to daemon events and pass each to `on_event` until the
+/// daemon goes away
+async fn follow_events(
+    socket_path: &Path,
+    kinds: Vec<leeward_core::protocol::EventKind>,
+    wire: Wire,
+    mut on_event: impl FnMut(leeward_core::protocol::Event),
+) -> Result<(), fleet::Failure> {
+    use leeward_core::protocol::{self, Request, Response};
+
+    let stream = connect(socket_path)?;
+    let mut stream = BufReader::new(stream);
+    let request = Request::Subscribe { kinds };
+
+    match wire {
+        Wire::Msgpack => {
+            let request_bytes = protocol::encode(&request)?;
+            let len = u32::try_from(request_bytes.len()).map_err(|_| {
+                leeward_core::LeewardError::InvalidRequest("request too large to frame".into())
+            })?;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(&request_bytes).await?;
+        }
+        Wire::Json => {
+            let mut request_bytes = protocol::encode_json(&request)?;
+            request_bytes.push(b'\n');
+            stream.write_all(&request_bytes).await?;
+        }
+    }
+
+    loop {
+        let response: Response = match wire {
+            Wire::Msgpack => {
+                let mut len_buf = [0u8; 4];
+                if stream.read_exact(&mut len_buf).await.is_err() {
+                    return Ok(()); // Daemon went away
+                }
+                let mut response_buf = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+                stream.read_exact(&mut response_buf).await?;
+                protocol::decode(&response_buf)?
+            }
+            Wire::Json => {
+                let mut line = Vec::new();
+                if stream.read_until(b'\n', &mut line).await? == 0 {
+                    return Ok(()); // Daemon went away
+                }
+                protocol::decode_json(&line)?
+            }
+        };
+
+        match response {
+            Response::Subscribed => tracing::debug!("subscribed to daemon events"),
+            Response::Event(event) => on_event(event),
+            other => return Err(fleet::Failure::unexpected(other)),
+        }
+    }
+}
+
+/// How long each self-test probe may run
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Report host features and, for `--self-test`, run every escape probe
+/// through the daemon so its actual sandbox config is what gets tested
+async fn doctor(
+    socket_path: &Path,
+    self_test: bool,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::escape::{KernelFeature, PROBES, ProbeReport, Verdict};
+    use leeward_core::protocol::{Request, RequestBuilder, Response};
+
+    println!("Kernel features:");
+    for feature in KernelFeature::ALL {
+        let state = if feature.available() {
+            "available"
+        } else {
+            "missing"
+        };
+        println!("  {:<16}{}", feature.name(), state);
+    }
+
+    // Workers are non-dumpable and filter ptrace either way; Yama decides
+    // what the rest of the host may attach to, debug-profile samplers
+    // included
+    let yama = match leeward_core::escape::yama_ptrace_scope() {
+        None => "not enabled; only the sandbox's own filter stops tracing".to_owned(),
+        Some(0) => "0, any process may attach to others of its user; 1 is recommended".to_owned(),
+        Some(1) => "1, descendants only (recommended)".to_owned(),
+        Some(2) => "2, CAP_SYS_PTRACE only; debug-profile samplers must run as root".to_owned(),
+        Some(3) => "3, no attaching at all; the debug profile cannot trace".to_owned(),
+        Some(scope) => format!("{scope}, unknown"),
+    };
+    println!("  {:<16}{}", "yama ptrace", yama);
+
+    match send_request(socket_path, &Request::Ping, wire).await? {
+        Response::Pong => println!("Daemon: reachable at {}", socket_path.display()),
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+
+    if !self_test {
+        return Ok(());
+    }
+
+    println!("Self-test:");
+    let mut failed = false;
+    for probe in PROBES {
+        let verdict = if let Some(feature) = probe.missing_feature() {
+            Verdict::Skipped(format!("{} unavailable", feature.name()))
+        } else {
+            let request = Request::Execute(
+                RequestBuilder::new(probe.script(socket_path))
+                    .timeout(PROBE_TIMEOUT)
+                    .build()?,
+            );
+            match send_request(socket_path, &request, wire).await? {
+                Response::Execute(resp) => match resp.result {
+                    Some(result) => Verdict::from_result(&result),
+                    None => Verdict::Inconclusive(resp.error.unwrap_or_default()),
+                },
+                Response::Error { message, .. } => Verdict::Inconclusive(message),
+                _ => Verdict::Inconclusive("unexpected response".into()),
+            }
+        };
+
+        let report = ProbeReport { probe, verdict };
+        let note = if report.contained() && !report.as_expected() {
+            format!(" (expected {} from the {})", probe.expected, probe.layer)
+        } else {
+            String::new()
+        };
+        println!("  {:<16}{}{}", probe.name, report.verdict, note);
+        failed |= matches!(
+            report.verdict,
+            Verdict::Escaped(_) | Verdict::Inconclusive(_)
+        );
+    }
+
+    if failed {
+        exit_with(OutcomeCode::Exited(1));
+    }
+    Ok(())
+}
+
+/// Snippet `leeward bench` times
+const BENCH_SNIPPET: &str = "pass";
+
+/// Latency of one path in `leeward bench`
+struct BenchStats {
+    mean: std::time::Duration,
+    p50: std::time::Duration,
+    p99: std::time::Duration,
+}
+
+impl BenchStats {
+    fn new(mut samples: Vec<std::time::Duration>) -> Self {
+        samples.sort();
+        let at = |percentile: usize| samples[(samples.len() - 1) * percentile / 100];
+        Self {
+            mean: samples.iter().sum::<std::time::Duration>()
+                / u32::try_from(samples.len()).unwrap_or(u32::MAX),
+            p50: at(50),
+            p99: at(99),
+        }
+    }
+}
+
+impl std::fmt::Display for BenchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mean {:>8.1?}  p50 {:>8.1?}  p99 {:>8.1?}",
+            self.mean, self.p50, self.p99
+        )
+    }
+}
+
+/// Time `requests` executions of `code`, one at a time
+async fn bench_path(
+    socket_path: &Path,
+    code: &str,
+    requests: u32,
+    wire: Wire,
+) -> Result<BenchStats, Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, RequestBuilder, Response};
+
+    let request = Request::Execute(RequestBuilder::new(code).build()?);
+    let mut samples = Vec::new();
+    for _ in 0..requests {
+        let start = std::time::Instant::now();
+        match send_request(socket_path, &request, wire).await? {
+            Response::Execute(_) => samples.push(start.elapsed()),
+            Response::Error { message, .. } => {
+                eprintln!("Error: {message}");
+                exit_with(OutcomeCode::Daemon);
+            }
+            _ => {
+                eprintln!("Unexpected response");
+                exit_with(OutcomeCode::Protocol);
+            }
+        }
+    }
+    Ok(BenchStats::new(samples))
+}
+
+/// Compare fast-path and queued latency for a tiny snippet
+///
+/// The queued run sends the same snippet padded with a comment past
+/// [`FAST_PATH_MAX_BYTES`](leeward_core::protocol::FAST_PATH_MAX_BYTES),
+/// so only the dispatch path differs. Both match when the daemon runs
+/// without `fast_path`.
+async fn bench(
+    socket_path: &Path,
+    requests: u32,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let padded = format!(
+        "{BENCH_SNIPPET}\n#{}",
+        "x".repeat(leeward_core::protocol::FAST_PATH_MAX_BYTES)
+    );
+
+    // One warm-up each, so neither pays for a cold worker
+    bench_path(socket_path, BENCH_SNIPPET, 1, wire).await?;
+    bench_path(socket_path, &padded, 1, wire).await?;
+
+    println!("{requests} requests of {BENCH_SNIPPET:?} each:");
+    let fast = bench_path(socket_path, BENCH_SNIPPET, requests, wire).await?;
+    println!("  {:<12}{}", "fast path", fast);
+    let queued = bench_path(socket_path, &padded, requests, wire).await?;
+    println!("  {:<12}{}", "queued", queued);
+    Ok(())
+}
+
+/// Profile the script at `file` and print what it needs
+async fn profile(
+    socket_path: &Path,
+    file: &std::path::Path,
+    timeout: u64,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, RequestBuilder, Response};
+
+    let request = Request::Execute(
+        RequestBuilder::from_file(file)?
+            .timeout(std::time::Duration::from_secs(timeout))
+            .profile_mode(true)
+            .build()?,
+    );
+
+    match send_request(socket_path, &request, wire).await? {
+        Response::Execute(resp) => match (&resp.result, &resp.profile) {
+            (Some(result), Some(profile)) if resp.success => {
+                print_profile(result, profile)?;
+                Ok(())
+            }
+            _ => {
+                eprintln!(
+                    "Error: {}",
+                    resp.error
+                        .as_deref()
+                        .unwrap_or("no profile in the response")
+                );
+                exit_with(resp.outcome());
+            }
+        },
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+}
+
+/// Print a profiling run's report and the config it suggests
+fn print_profile(
+    result: &leeward_core::ExecutionResult,
+    profile: &leeward_core::profile::WorkloadProfile,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::profile::{FileAccess, suggest, syscall_name};
+
+    println!(
+        "Exited {} after {:.1?}: {} KiB peak, {:.1?} CPU",
+        result.exit_code,
+        result.duration,
+        profile.memory_peak / 1024,
+        std::time::Duration::from_micros(profile.cpu_time_us)
+    );
+    if !result.stderr.is_empty() {
+        eprint!("{}", String::from_utf8_lossy(&result.stderr));
+    }
+
+    let mut syscalls: Vec<_> = profile.syscalls.iter().collect();
+    syscalls.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    println!(
+        "\nSyscalls: {} distinct, {} made (* not in the worker allowlist)",
+        syscalls.len(),
+        syscalls.iter().map(|(_, count)| **count).sum::<u64>()
+    );
+    for (nr, count) in syscalls {
+        let name = syscall_name(*nr).map_or_else(|| nr.to_string(), str::to_owned);
+        let unlisted = if profile.unlisted_syscalls.contains(nr) {
+            " *"
+        } else {
+            ""
+        };
+        println!("  {name:<20}{count:>8}{unlisted}");
+    }
+
+    println!(
+        "\nFiles: {} opened, {} refused by Landlock",
+        profile.files.len(),
+        profile.landlock_denied.len()
+    );
+    for path in &profile.landlock_denied {
+        let access = match profile.files.get(path) {
+            Some(FileAccess::Write) => "write",
+            _ => "read",
+        };
+        println!("  {access:<6}{}", path.display());
+    }
+
+    println!("\nNetwork sockets: {}", profile.inet_sockets);
+    println!("Processes and threads started: {}", profile.processes);
+    if !profile.workspace.is_empty() {
+        println!("Workspace:");
+        for (name, size) in &profile.workspace {
+            println!("  {name} ({size} bytes)");
+        }
+    }
+
+    println!("\nSuggested sandbox config:");
+    println!("{}", serde_json::to_string_pretty(&suggest(profile))?);
+    Ok(())
+}
+
+/// Exit with `outcome`'s code
+fn exit_with(outcome: OutcomeCode) -> ! {
+    std::process::exit(outcome.code().into())
+}
+
+/// Exit code for an error that ended a command early
+///
+/// I/O failures here are all on the way to or from the daemon.
+fn error_outcome(error: &(dyn std::error::Error + 'static)) -> OutcomeCode {
+    match error.downcast_ref::<LeewardError>() {
+        Some(LeewardError::Io(_)) => OutcomeCode::ConnectionFailed,
+        Some(error) => error.into(),
+        None if error.is::<std::io::Error>() => OutcomeCode::ConnectionFailed,
+        None => OutcomeCode::Protocol,
+    }
+}
+
+/// The exit code table shown by `leeward exec --help`
+fn exit_codes_help() -> String {
+    let mut help = String::from("Exit codes:\n");
+    let passthrough = format!("0-{}", OutcomeCode::MAX_EXIT_STATUS);
+    let _ = writeln!(
+        help,
+        "  {:<8}{}",
+        passthrough,
+        OutcomeCode::Exited(0).description()
+    );
+    for outcome in OutcomeCode::RESERVED {
+        let _ = writeln!(help, "  {:<8}{}", outcome.code(), outcome.description());
+    }
+    help
+}
+
+/// How often `leeward drain --wait` checks on the drain
+const DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Print the settings an execution would run with, and where each came
+/// from, without running anything
+async fn explain(
+    socket: &Path,
+    lang: Lang,
+    timeout: Option<u64>,
+    memory: Option<u64>,
+    tz: Option<String>,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    let mut builder =
+        leeward_core::protocol::RequestBuilder::new(String::new()).interpreter(lang.into());
+    if let Some(secs) = timeout {
+        builder = builder.timeout(std::time::Duration::from_secs(secs));
+    }
+    if let Some(mib) = memory {
+        builder = builder.memory_limit(ByteSize::mib(mib));
+    }
+    if let Some(zone) = tz {
+        builder = builder.timezone(zone);
+    }
+    let request = Request::ExplainPolicy {
+        request: builder.build()?,
+    };
+
+    match send_request(socket, &request, wire).await? {
+        Response::Policy { fields } => {
+            let width = fields
+                .iter()
+                .map(|field| field.field.len())
+                .max()
+                .unwrap_or(0);
+            let value_width = fields
+                .iter()
+                .map(|field| field.value.len())
+                .max()
+                .unwrap_or(0);
+            for field in fields {
+                println!(
+                    "{:<width$}  {:<value_width$}  {}",
+                    field.field, field.value, field.provenance
+                );
+            }
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        other => eprintln!("Unexpected response: {other:?}"),
+    }
+    Ok(())
+}
+
+/// Print the status of one daemon's pool, and with `detailed` each
+/// worker's config and the executions in flight
+async fn status(
+    socket: &Path,
+    detailed: bool,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    let request = Request::Status;
+
+    match send_request(socket, &request, wire).await? {
+        Response::Status {
+            total,
+            idle,
+            busy,
+            stale,
+            draining,
+            drained,
+            handover,
+            snapshot_age_ms,
+        } => {
+            // Older daemons send no age and read the pool itself
+            if snapshot_age_ms >= STALE_STATUS_MS {
+                eprintln!(
+                    "Warning: status data is {}s old; the pool may be wedged",
+                    snapshot_age_ms / 1000
+                );
+            }
+            println!("Workers: {total} total, {idle} idle, {busy} busy");
+            if stale > 0 {
+                println!("Stale: {stale} workers on an old config");
+            }
+            if draining > 0 || drained > 0 {
+                println!("Drain: {draining} pending, {drained} drained");
+            }
+            if let Some(handover) = handover {
+                print_handover(&handover);
+            }
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+
+    if detailed {
+        let request = Request::ListWorkers;
+
+        match send_request(socket, &request, wire).await? {
+            Response::WorkerList {
+                workers,
+                current_fingerprint,
+            } => {
+                println!("Config: {}", short_fingerprint(&current_fingerprint));
+                for worker in workers {
+                    let marker = if worker.drain_pending {
+                        "  DRAINING"
+                    } else if worker.config_fingerprint == current_fingerprint {
+                        ""
+                    } else {
+                        "  STALE"
+                    };
+                    // The boot id is the same for all, so the seq tells processes apart
+                    let process = worker
+                        .uid
+                        .map(|uid| format!(" #{}", uid.seq))
+                        .unwrap_or_default();
+                    println!(
+                        "  worker {}{}: {:?}, config {}{}",
+                        worker.id,
+                        process,
+                        worker.state,
+                        short_fingerprint(&worker.config_fingerprint),
+                        marker
+                    );
+                }
+            }
+            Response::Error { message, .. } => {
+                eprintln!("Error: {message}");
+                exit_with(OutcomeCode::Daemon);
+            }
+            _ => {
+                eprintln!("Unexpected response");
+                exit_with(OutcomeCode::Protocol);
+            }
+        }
+
+        print_inflight(socket, wire).await;
+    }
+    Ok(())
+}
+
+/// Print each worker with its latest timing breakdown
+async fn workers(socket: &Path, wire: Wire) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    let request = Request::ListWorkers;
+
+    match send_request(socket, &request, wire).await? {
+        Response::WorkerList { workers, .. } => {
+            for worker in workers {
+                println!(
+                    "Worker {}: {:?}, pid {:?}, {} executions",
+                    worker.id, worker.state, worker.pid, worker.execution_count
+                );
+                if !worker.denied_syscalls.is_empty() {
+                    let denied: Vec<_> = worker
+                        .denied_syscalls
+                        .iter()
+                        .map(|(syscall, count)| format!("{syscall} x{count}"))
+                        .collect();
+                    println!("  denied: {}", denied.join(", "));
+                }
+                if let Some(t) = worker.last_timing {
+                    println!(
+                        "  setup: ns={}us mount={}us landlock={}us seccomp={}us",
+                        t.namespace_setup_us,
+                        t.mount_setup_us,
+                        t.landlock_setup_us,
+                        t.seccomp_setup_us
+                    );
+                    println!(
+                        "  last:  recv={}us startup={}us exec={}us send={}us",
+                        t.code_recv_us, t.python_import_us, t.execution_us, t.result_send_us
+                    );
+                    println!(
+                        "  pipes: code peak={}B result peak={}B",
+                        t.code_pipe_peak, t.result_pipe_peak
+                    );
+                }
+            }
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+    Ok(())
+}
+
+/// Print the daemon's policy changes, oldest first
+async fn history(socket: &Path, json: bool, wire: Wire) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
+
+    match send_request(socket, &Request::PolicyChanges, wire).await? {
+        Response::PolicyChanges { changes } => {
+            for change in changes {
+                if json {
+                    println!("{}", serde_json::to_string(&change)?);
+                } else {
+                    print_policy_change(&change);
+                }
+            }
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        other => eprintln!("Unexpected response: {other:?}"),
+    }
+    Ok(())
+}
+
+/// A request for `code`, read from stdin if it is `-`, under `timeout`
+/// and `memory` MiB
+async fn code_request(
+    code: String,
+    timeout: u64,
+    memory: Option<u64>,
+) -> std::io::Result<leeward_core::protocol::RequestBuilder> {
+    let builder = if code == "-" {
+        let mut source = String::new();
+        tokio::io::stdin().read_to_string(&mut source).await?;
+        leeward_core::protocol::RequestBuilder::from_source(source)
+    } else {
+        leeward_core::protocol::RequestBuilder::new(code)
+    };
+    Ok(with_limits(builder, timeout, memory))
+}
 
-    // Encode request
-    let request_bytes = leeward_core::protocol::encode(request)?;
+/// `builder` under a timeout of `timeout` seconds and, if given, a memory
+/// limit of `memory` MiB
+const fn with_limits(
+    builder: leeward_core::protocol::RequestBuilder,
+    timeout: u64,
+    memory: Option<u64>,
+) -> leeward_core::protocol::RequestBuilder {
+    let builder = builder.timeout(std::time::Duration::from_secs(timeout));
+    match memory {
+        Some(mib) => builder.memory_limit(ByteSize::mib(mib)),
+        None => builder,
+    }
+}
 
-    // Send length prefix (4 bytes, big-endian)
-    let len = request_bytes.len() as u32;
-    stream.write_all(&len.to_be_bytes()).await?;
+/// Recycle the daemon's idle workers running an old config
+async fn recycle_stale(socket: &Path, wire: Wire) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
 
-    // Send request
-    stream.write_all(&request_bytes).await?;
+    match send_request(socket, &Request::RecycleStale, wire).await? {
+        Response::RecycleStale { scheduled } => {
+            println!("Recycling {scheduled} stale workers");
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+    Ok(())
+}
 
-    // Read response length
-    let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf).await?;
-    let response_len = u32::from_be_bytes(len_buf) as usize;
+/// Check that one daemon answers
+async fn ping(socket: &Path, wire: Wire) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
 
-    // Read response
-    let mut response_buf = vec![0u8; response_len];
-    stream.read_exact(&mut response_buf).await?;
+    match send_request(socket, &Request::Ping, wire).await? {
+        Response::Pong => println!("Pong!"),
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+    Ok(())
+}
 
-    // Decode response
-    let response = leeward_core::protocol::decode(&response_buf)?;
+/// Drain a profile, then with `wait` report progress until it is done
+async fn drain(
+    socket: &Path,
+    profile: String,
+    reason: Option<String>,
+    wait: bool,
+    wire: Wire,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use leeward_core::protocol::{Request, Response};
 
-    Ok(response)
+    match send_request(socket, &Request::DrainProfile { profile, reason }, wire).await? {
+        Response::Drain(status) => {
+            let rebuilt = if status.template_rebuilt {
+                ", root template rebuilt"
+            } else {
+                ""
+            };
+            println!(
+                "Draining {} workers of profile {}{}",
+                status.scheduled, status.profile, rebuilt
+            );
+        }
+        Response::Error { message, .. } => {
+            eprintln!("Error: {message}");
+            exit_with(OutcomeCode::Daemon);
+        }
+        _ => {
+            eprintln!("Unexpected response");
+            exit_with(OutcomeCode::Protocol);
+        }
+    }
+
+    if !wait {
+        return Ok(());
+    }
+    loop {
+        match send_request(socket, &Request::Status, wire).await? {
+            Response::Status { draining: 0, .. } => {
+                println!("Drained");
+                return Ok(());
+            }
+            Response::Status { draining, .. } => {
+                println!("{draining} workers still to drain");
+            }
+            Response::Error { message, .. } => {
+                eprintln!("Error: {message}");
+                exit_with(OutcomeCode::Daemon);
+            }
+            _ => {
+                eprintln!("Unexpected response");
+                exit_with(OutcomeCode::Protocol);
+            }
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Describe the overlap of an old and a new daemon during a handover
+fn print_handover(handover: &leeward_core::protocol::HandoverStatus) {
+    use leeward_core::protocol::HandoverRole;
+
+    let peer = handover
+        .peer_pid
+        .map_or_else(|| "unknown pid".to_string(), |pid| format!("pid {pid}"));
+    match handover.role {
+        HandoverRole::Successor => println!(
+            "Handover: took over from {}, which is finishing {} connections with its own workers",
+            peer, handover.predecessor_connections
+        ),
+        HandoverRole::Predecessor => println!(
+            "Handover: handed over to {}; finishing {} connections, then exiting",
+            peer, handover.predecessor_connections
+        ),
+    }
+}
+
+/// Print the CLI's release and, if `verbose`, where it came from
+fn print_version(verbose: bool) {
+    let build = leeward_core::build_info!();
+    println!("leeward {}", build.version);
+    if !verbose {
+        return;
+    }
+    println!("  {:<10}{}", "git", build.git);
+    println!("  {:<10}{}", "built", build.built_at);
+    println!("  {:<10}{}", "rustc", build.rustc);
+    println!("  {:<10}{}", "target", build.target);
+    println!("  {:<10}{}", "profile", build.profile);
+    println!("  {:<10}{}", "features", build.features.join(", "));
+}
+
+/// First 12 hex digits, enough to tell configs apart at a glance
+fn short_fingerprint(fingerprint: &str) -> &str {
+    fingerprint.get(..12).unwrap_or(fingerprint)
 }
 
 #[derive(Parser)]
 #[command(name = "leeward")]
-#[command(author, version, about = "Linux-native sandbox for untrusted code execution")]
+#[command(
+    author,
+    version,
+    about = "Linux-native sandbox for untrusted code execution"
+)]
+#[command(disable_version_flag = true)]
 struct Cli {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With --version, also print the commit, build time, toolchain and
+    /// features this CLI was built with
+    #[arg(long, requires = "version")]
+    verbose: bool,
+
+    /// Socket encoding; `json` is meant for debugging
+    #[arg(long, global = true, value_enum, default_value_t)]
+    wire: Wire,
+
+    /// Don't note where the daemon changed or ignored part of a request
+    #[arg(short, long, global = true)]
+    quiet: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Execute Python code
+    ///
+    /// Exits with the program's exit status, or with one of the codes below
+    /// if it did not run to completion.
+    #[command(after_help = exit_codes_help())]
     Exec {
         /// Code to execute (or - for stdin)
         code: String,
 
-        /// Socket path (defaults to LEEWARD_SOCKET env var or /run/leeward/leeward.sock)
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Timeout in seconds
+        #[arg(short, long, default_value = "30")]
+        timeout: u64,
+
+        /// Memory limit in MiB (defaults to the daemon's)
+        #[arg(short, long)]
+        memory: Option<u64>,
+
+        /// Print a Python traceback shortly before the timeout
+        #[arg(long)]
+        traceback_on_timeout: bool,
+
+        /// Local file to stage into the sandbox workdir (repeatable)
+        #[arg(long = "file", value_name = "PATH")]
+        files: Vec<PathBuf>,
+
+        /// Run under the debug profile, in a worker of its own where the
+        /// code's processes may trace one another (daemon's own user only)
+        #[arg(long)]
+        debug_profile: bool,
+
+        /// Print the execution id and return at once, leaving the result
+        /// on the daemon for `leeward fetch`
+        #[arg(long)]
+        detach: bool,
+
+        /// Write stdout, stderr and an index.json of the result into DIR
+        /// instead of the terminal
+        #[arg(long, value_name = "DIR", conflicts_with = "detach")]
+        out_dir: Option<PathBuf>,
+    },
+
+    /// Take the result of a detached execution
+    ///
+    /// Exits like `exec` would have. The daemon forgets the result once it
+    /// is fetched; an execution still running fails with "still running".
+    #[command(after_help = exit_codes_help())]
+    Fetch {
+        /// Execution id printed by `leeward exec --detach`
+        execution_id: u64,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Run a shell command or script
+    ///
+    /// With -c, runs CMD and passes ARGS as $1, $2, ... Otherwise the first
+    /// argument is a local script, staged into the sandbox workdir and run
+    /// with the rest. Input piped to leeward is the program's stdin. Exits
+    /// like `exec`.
+    #[command(after_help = exit_codes_help())]
+    Sh {
+        /// Command to run, as with `sh -c`
+        #[arg(short = 'c', value_name = "CMD")]
+        command: Option<String>,
+
+        /// Script (without -c), then arguments for the program
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            required_unless_present = "command"
+        )]
+        args: Vec<String>,
+
+        /// Shell to run it with
+        #[arg(long, value_enum, default_value_t)]
+        lang: Shell,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
         #[arg(short, long)]
         socket: Option<PathBuf>,
 
         /// Timeout in seconds
         #[arg(short, long, default_value = "30")]
         timeout: u64,
+
+        /// Memory limit in MiB (defaults to the daemon's)
+        #[arg(short, long)]
+        memory: Option<u64>,
+
+        /// Local file to stage into the sandbox workdir (repeatable)
+        #[arg(long = "file", value_name = "PATH")]
+        files: Vec<PathBuf>,
+    },
+
+    /// Run Python cells one after another on a single connection
+    ///
+    /// Reads stdin, a blank line ending each cell. Lines starting with `%`
+    /// set defaults for the cells that follow: `%timeout SECS`,
+    /// `%memory MIB`, `%env NAME=VALUE`, or `%reset` to clear them.
+    Repl {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Show the settings an execution would run with and where each came
+    /// from: built-in default, daemon config, request, or clamped by a limit
+    ///
+    /// Nothing is run.
+    Explain {
+        /// Interpreter to ask about
+        #[arg(long, value_enum, default_value_t)]
+        lang: Lang,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Timeout in seconds (defaults to the daemon's)
+        #[arg(short, long)]
+        timeout: Option<u64>,
+
+        /// Memory limit in MiB (defaults to the daemon's)
+        #[arg(short, long)]
+        memory: Option<u64>,
+
+        /// IANA timezone, such as Europe/Paris (defaults to the daemon's)
+        #[arg(long)]
+        tz: Option<String>,
     },
 
     /// Get daemon status
+    ///
+    /// Given several daemons, prints a table with a row for each.
     Status {
-        /// Socket path (defaults to LEEWARD_SOCKET env var or /run/leeward/leeward.sock)
+        #[command(flatten)]
+        targets: fleet::Targets,
+
+        /// List each worker and flag those running an old config (a single
+        /// daemon only)
+        #[arg(long)]
+        detailed: bool,
+    },
+
+    /// Recycle idle workers still running an old config
+    RecycleStale {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
         #[arg(short, long)]
         socket: Option<PathBuf>,
     },
 
-    /// Ping the daemon
-    Ping {
-        /// Socket path (defaults to LEEWARD_SOCKET env var or /run/leeward/leeward.sock)
+    /// Recycle every worker of a profile once it finishes its current
+    /// execution, after an interpreter or runtime upgrade
+    Drain {
+        #[command(flatten)]
+        targets: fleet::Targets,
+
+        /// Profile whose workers to drain
+        #[arg(long, default_value = leeward_core::protocol::DEFAULT_PROFILE)]
+        profile: String,
+
+        /// Why, for the daemon's log
+        #[arg(long)]
+        reason: Option<String>,
+
+        /// Wait until every worker has been recycled
+        #[arg(long)]
+        wait: bool,
+    },
+
+    /// Change the daemon's log filter without restarting it
+    ///
+    /// Takes a `RUST_LOG` style directive, such as `debug` or
+    /// `leeward=debug,leeward_daemon::pool=trace`. A config reload (SIGHUP)
+    /// restores the filter the daemon started with, unless --persist is
+    /// given. Only the daemon's own user, or root, may change it.
+    LogLevel {
+        /// Filter directive
+        directive: String,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Keep the filter across config reloads
+        #[arg(long)]
+        persist: bool,
+    },
+
+    /// Turn the daemon's log lines for one hot point on or off
+    ///
+    /// `pipe-frames` logs every frame exchanged with a worker, `scheduler`
+    /// every execution queued and dispatched, `seccomp-denials` every
+    /// syscall a supervisor refuses, `pipe-stalls` every worker pipe left
+    /// full too long. A config reload (SIGHUP) turns the
+    /// flag back off, unless --persist is given. Only the daemon's own
+    /// user, or root, may change them.
+    Debug {
+        /// Flag to change
+        flag: leeward_core::protocol::DebugFlag,
+
+        /// `on` or `off`
+        #[arg(value_parser = parse_switch)]
+        state: bool,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Keep the flag so across config reloads
+        #[arg(long)]
+        persist: bool,
+    },
+
+    /// List workers with their latest timing breakdown
+    Workers {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Follow daemon events, such as pool saturation alerts
+    Events {
+        #[command(flatten)]
+        targets: fleet::Targets,
+
+        /// Only show these kinds of event (repeatable; all if omitted)
+        #[arg(long, value_enum)]
+        kind: Vec<EventFilter>,
+    },
+
+    /// List what the daemon has kept of its history
+    ///
+    /// With --policy-changes, each point at which its effective policy
+    /// changed: startup, config reloads and profile drains, with what
+    /// changed them and the config fields that differ. Secret-bearing
+    /// fields show only as `<set>`.
+    History {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// List policy changes, the only history the daemon keeps
+        #[arg(long, required = true)]
+        policy_changes: bool,
+
+        /// Print them as JSON, one per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check the host and the daemon's sandbox
+    ///
+    /// Reports the kernel features the sandbox relies on, and Yama's ptrace
+    /// scope, which governs what the rest of the host may attach to; 1 is
+    /// recommended. With --self-test, every known escape technique is tried through the
+    /// daemon, against the sandbox it is configured with. Exits 1 if any
+    /// probe escaped or gave no verdict.
+    Doctor {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Run the sandbox escape probes
+        #[arg(long)]
+        self_test: bool,
+    },
+
+    /// Compare request latency on the daemon's fast path and through its queue
+    Bench {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Requests timed per path
+        #[arg(
+            short = 'n',
+            long,
+            default_value = "200",
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        requests: u32,
+    },
+
+    /// Run a script unfiltered and report what it needs from the sandbox
+    ///
+    /// The daemon runs the script in a worker of its own with every syscall
+    /// counted and every path it opens checked against the worker's Landlock
+    /// rules, without denying any of them. Prints what it did and a config snippet that would let
+    /// it run in a worker. Only the daemon's own user, or root, may profile.
+    Profile {
+        /// Script to profile
+        file: PathBuf,
+
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+
+        /// Timeout in seconds
+        #[arg(short, long, default_value = "30")]
+        timeout: u64,
+    },
+
+    /// Show the daemon's version, features and limits
+    Info {
+        /// Socket path (defaults to `LEEWARD_SOCKET` env var or /run/leeward/leeward.sock)
         #[arg(short, long)]
         socket: Option<PathBuf>,
+
+        /// Print the daemon's advertisement as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Ping the daemon
+    Ping {
+        #[command(flatten)]
+        targets: fleet::Targets,
     },
 
     /// Run code directly (without daemon, for testing)
@@ -93,107 +1918,256 @@ enum Commands {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("leeward=info".parse()?),
+                .add_directive("leeward=info".parse().expect("valid log directive")),
         )
         .init();
 
-    let cli = Cli::parse();
+    let mut cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // Usage errors exit like any other invalid argument; --help and --version do not
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            exit_with(OutcomeCode::InvalidArgument);
+        }
+        Err(e) => e.exit(),
+    };
+
+    if cli.version {
+        print_version(cli.verbose);
+        return;
+    }
+    let Some(command) = cli.command.take() else {
+        let _ = Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .print();
+        exit_with(OutcomeCode::InvalidArgument);
+    };
+
+    if let Err(e) = run(cli, command).await {
+        eprintln!("Error: {e}");
+        exit_with(error_outcome(&*e));
+    }
+}
+
+/// Carry out `command`, one arm per subcommand
+#[allow(clippy::too_many_lines)]
+async fn run(cli: Cli, command: Commands) -> Result<(), Box<dyn std::error::Error>> {
+    let wire = cli.wire;
 
-    match cli.command {
+    match command {
         Commands::Exec {
             code,
             socket,
             timeout,
+            memory,
+            traceback_on_timeout,
+            files,
+            debug_profile,
+            detach,
+            out_dir,
         } => {
             let socket = socket.unwrap_or_else(default_socket_path);
 
-            let request = leeward_core::protocol::Request::Execute(
-                leeward_core::protocol::ExecuteRequest {
-                    code: Some(code),
-                    shm_slot_id: None,
-                    timeout: Some(std::time::Duration::from_secs(timeout)),
-                    memory_limit: None,
-                    files: Vec::new(),
-                }
-            );
+            let builder = code_request(code, timeout, memory)
+                .await?
+                .soft_timeout_traceback(traceback_on_timeout)
+                .debug_profile(debug_profile)
+                .detach(detach);
+            let mut daemon = Daemon::new(&socket, wire);
+            let builder = with_files(&mut daemon, builder, files).await?;
 
-            match send_request(&socket, &request).await? {
-                leeward_core::protocol::Response::Execute(resp) => {
-                    if resp.success {
-                        if let Some(result) = resp.result {
-                            print!("{}", String::from_utf8_lossy(&result.stdout));
-                            eprint!("{}", String::from_utf8_lossy(&result.stderr));
-                            std::process::exit(result.exit_code);
-                        }
-                    } else {
-                        eprintln!("Error: {}", resp.error.unwrap_or_else(|| "Unknown error".into()));
-                        std::process::exit(1);
-                    }
-                }
-                leeward_core::protocol::Response::Error { message } => {
-                    eprintln!("Error: {}", message);
-                    std::process::exit(1);
-                }
-                _ => {
-                    eprintln!("Unexpected response");
-                    std::process::exit(1);
-                }
+            match out_dir {
+                Some(dir) => execute_to_dir(&socket, builder.build()?, dir, cli.quiet).await?,
+                None => execute(&mut daemon, builder.build()?, cli.quiet).await?,
+            }
+        }
+
+        Commands::Fetch {
+            execution_id,
+            socket,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            fetch(&socket, execution_id, wire, cli.quiet).await?;
+        }
+
+        Commands::Sh {
+            command,
+            args,
+            lang,
+            socket,
+            timeout,
+            memory,
+            files,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+
+            let mut builder = with_limits(shell_request(command, args, lang)?, timeout, memory);
+            if !std::io::stdin().is_terminal() {
+                let mut input = Vec::new();
+                tokio::io::stdin().read_to_end(&mut input).await?;
+                builder = builder.stdin(input);
             }
+            let mut daemon = Daemon::new(&socket, wire);
+            let builder = with_files(&mut daemon, builder, files).await?;
+
+            execute(&mut daemon, builder.build()?, cli.quiet).await?;
         }
 
-        Commands::Status { socket } => {
+        Commands::Repl { socket } => {
             let socket = socket.unwrap_or_else(default_socket_path);
-            let request = leeward_core::protocol::Request::Status;
+            let quiet = cli.quiet;
+            tokio::task::spawn_blocking(move || repl(&socket, quiet).map_err(|e| e.to_string()))
+                .await??;
+        }
 
-            match send_request(&socket, &request).await? {
-                leeward_core::protocol::Response::Status { total, idle, busy } => {
-                    println!("Workers: {} total, {} idle, {} busy", total, idle, busy);
-                }
-                leeward_core::protocol::Response::Error { message } => {
-                    eprintln!("Error: {}", message);
-                    std::process::exit(1);
+        Commands::Explain {
+            lang,
+            socket,
+            timeout,
+            memory,
+            tz,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            explain(&socket, lang, timeout, memory, tz, wire).await?;
+        }
+
+        Commands::Status { targets, detailed } => {
+            let sockets = targets.sockets();
+            let Some(socket) = targets.single(&sockets) else {
+                if detailed {
+                    eprintln!("Error: --detailed takes a single daemon");
+                    exit_with(OutcomeCode::InvalidArgument);
                 }
-                _ => {
-                    eprintln!("Unexpected response");
-                    std::process::exit(1);
+                fleet::status(&sockets, targets.json, wire).await;
+                return Ok(());
+            };
+            status(&socket, detailed, wire).await?;
+        }
+
+        Commands::RecycleStale { socket } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            recycle_stale(&socket, wire).await?;
+        }
+
+        Commands::Drain {
+            targets,
+            profile,
+            reason,
+            wait,
+        } => {
+            let sockets = targets.sockets();
+            match targets.single(&sockets) {
+                Some(socket) => drain(&socket, profile, reason, wait, wire).await?,
+                None => {
+                    fleet::drain(
+                        &sockets,
+                        &profile,
+                        reason.as_deref(),
+                        wait,
+                        targets.json,
+                        wire,
+                    )
+                    .await;
                 }
             }
         }
 
-        Commands::Ping { socket } => {
+        Commands::LogLevel {
+            directive,
+            socket,
+            persist,
+        } => {
             let socket = socket.unwrap_or_else(default_socket_path);
-            let request = leeward_core::protocol::Request::Ping;
+            let request = leeward_core::protocol::Request::SetLogFilter { directive, persist };
+            adjust_logging(&socket, &request, wire).await?;
+        }
 
-            match send_request(&socket, &request).await? {
-                leeward_core::protocol::Response::Pong => {
-                    println!("Pong!");
-                }
-                leeward_core::protocol::Response::Error { message } => {
-                    eprintln!("Error: {}", message);
-                    std::process::exit(1);
-                }
-                _ => {
-                    eprintln!("Unexpected response");
-                    std::process::exit(1);
-                }
+        Commands::Debug {
+            flag,
+            state,
+            socket,
+            persist,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            let request = leeward_core::protocol::Request::SetDebug {
+                flag,
+                enabled: state,
+                persist,
+            };
+            adjust_logging(&socket, &request, wire).await?;
+        }
+
+        Commands::Workers { socket } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            workers(&socket, wire).await?;
+        }
+
+        Commands::Events { targets, kind } => {
+            let sockets = targets.sockets();
+            let kinds: Vec<_> = kind.into_iter().map(Into::into).collect();
+            match targets.single(&sockets) {
+                Some(socket) => stream_events(&socket, kinds, wire).await,
+                None => fleet::events(&sockets, &kinds, targets.json, wire).await,
             }
         }
 
+        Commands::History {
+            socket,
+            policy_changes: _,
+            json,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            history(&socket, json, wire).await?;
+        }
+
+        Commands::Doctor { socket, self_test } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            doctor(&socket, self_test, wire).await?;
+        }
+
+        Commands::Bench { socket, requests } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            bench(&socket, requests, wire).await?;
+        }
+
+        Commands::Profile {
+            file,
+            socket,
+            timeout,
+        } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            profile(&socket, &file, timeout, wire).await?;
+        }
+
+        Commands::Info { socket, json } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            info(&socket, json, wire).await?;
+        }
+
+        Commands::Ping { targets } => {
+            let sockets = targets.sockets();
+            let Some(socket) = targets.single(&sockets) else {
+                fleet::ping(&sockets, targets.json, wire).await;
+                return Ok(());
+            };
+            ping(&socket, wire).await?;
+        }
+
         Commands::Run {
             code,
             timeout,
             network,
         } => {
             println!("Running directly (no daemon)");
-            println!("Code: {}", code);
-            println!(
-                "Timeout: {}s, Network: {}",
-                timeout, network
-            );
+            println!("Code: {code}");
+            println!("Timeout: {timeout}s, Network: {network}");
 
             // TODO: Use leeward_core directly to execute
             let _config = leeward_core::SandboxConfig::builder()
@@ -0,0 +1,124 @@
+//! Execution output written into a directory instead of the terminal
+//!
+//! `leeward exec --out-dir DIR` writes stdout to `stdout.txt` and stderr to
+//! `stderr.txt`, each under a `.partial` suffix until the execution has
+//! finished and both are whole, then `index.json` with what the result
+//! was: outcome, exit code, time and memory taken, and each file's size and
+//! SHA-256. An execution that fails part way leaves its files with the
+//! suffix and an index with `"complete": false` and the error, so nothing
+//! partial is taken for a whole result. Files from an earlier run into the
+//! same directory are removed first.
+
+use leeward_core::client::Client;
+use leeward_core::protocol::{self, ExecuteRequest, ExecuteResponse};
+use leeward_core::{LeewardError, Result};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+pub const STDOUT: &str = "stdout.txt";
+pub const STDERR: &str = "stderr.txt";
+pub const INDEX: &str = "index.json";
+
+/// On a file's name until it is known to be whole
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Run `request` on a new connection to `socket`, writing its output into
+/// `dir`, and return its response
+pub fn execute(socket: &Path, request: ExecuteRequest, dir: &Path) -> Result<ExecuteResponse> {
+    std::fs::create_dir_all(dir)?;
+    for name in [STDOUT, STDERR, INDEX] {
+        remove(&dir.join(name))?;
+        remove(&partial(dir, name))?;
+    }
+    let stdout = BufWriter::new(File::create(partial(dir, STDOUT))?);
+    let stderr = BufWriter::new(File::create(partial(dir, STDERR))?);
+
+    let ran = Client::connect(socket)
+        .and_then(|mut client| client.execute_to_writer(request, stdout, stderr));
+    match ran {
+        Ok(response) => {
+            for name in [STDOUT, STDERR] {
+                std::fs::rename(partial(dir, name), dir.join(name))?;
+            }
+            write_index(dir, &index(dir, &response)?)?;
+            Ok(response)
+        }
+        Err(e) => {
+            let index = json!({
+                "complete": false,
+                "error": e.to_string(),
+                "stdout": stream(dir, STDOUT, false)?,
+                "stderr": stream(dir, STDERR, false)?,
+            });
+            write_index(dir, &index)?;
+            Err(e)
+        }
+    }
+}
+
+fn partial(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}{PARTIAL_SUFFIX}"))
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The index of a finished execution whose streams are in `dir`
+fn index(dir: &Path, response: &ExecuteResponse) -> Result<Value> {
+    let outcome = response.outcome();
+    let result = response.result.as_ref();
+    let denials: Vec<String> = result.map_or_else(Vec::new, |result| {
+        result.denials.iter().map(ToString::to_string).collect()
+    });
+    Ok(json!({
+        "complete": true,
+        "success": response.success,
+        "outcome": outcome.code(),
+        "outcome_description": outcome.description(),
+        "error": response.error,
+        "exit_code": result.map(|result| result.exit_code),
+        "duration_ms": result.map(|result| result.duration.as_millis()),
+        "memory_peak": result.map(|result| result.memory_peak),
+        "cpu_time_us": result.map(|result| result.cpu_time_us),
+        "timed_out": result.is_some_and(|result| result.timed_out),
+        "oom_killed": result.is_some_and(|result| result.oom_killed),
+        "stdout": stream(dir, STDOUT, result.is_some_and(|result| result.stdout_truncated))?,
+        "stderr": stream(dir, STDERR, result.is_some_and(|result| result.stderr_truncated))?,
+        "denials": denials,
+        "adjustments": response.adjustments.iter().map(ToString::to_string).collect::<Vec<_>>(),
+    }))
+}
+
+/// Where the stream `name` in `dir` ended up, how long it is and its hash
+fn stream(dir: &Path, name: &str, truncated: bool) -> Result<Value> {
+    let (file, path) = match File::open(dir.join(name)) {
+        Ok(file) => (file, name.to_owned()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let path = partial(dir, name);
+            (File::open(&path)?, format!("{name}{PARTIAL_SUFFIX}"))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(json!({
+        "file": path,
+        "bytes": file.metadata()?.len(),
+        "sha256": protocol::sha256_hex_reader(file)?,
+        "truncated": truncated,
+    }))
+}
+
+/// Write `index` into `dir` whole, staged under the partial suffix
+fn write_index(dir: &Path, index: &Value) -> Result<()> {
+    let bytes =
+        serde_json::to_vec_pretty(index).map_err(|e| LeewardError::Io(io::Error::other(e)))?;
+    let staged = partial(dir, INDEX);
+    std::fs::write(&staged, bytes)?;
+    std::fs::rename(staged, dir.join(INDEX))?;
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Scratch directories for the tests in `tests/`
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty directory of its own for a test, removed with everything in it
+/// on drop
+pub struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    /// Make `leeward-<name>-<pid>` in the temporary directory, emptying
+    /// whatever an earlier run left there
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("leeward-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,175 @@
+//! Commands given several sockets ask every daemon at once and report a
+//! row for each, exiting with the worst outcome among them
+
+use leeward_core::OutcomeCode;
+use leeward_daemon::testing::TestDaemon;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn leeward(args: &[&str], sockets: &[&Path]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_leeward"));
+    command.args(args);
+    for socket in sockets {
+        command.arg("--socket").arg(socket);
+    }
+    command.env_remove("LEEWARD_SOCKET").output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn json(output: &Output) -> Vec<serde_json::Value> {
+    serde_json::from_slice::<serde_json::Value>(&output.stdout)
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone()
+}
+
+/// A socket path nothing listens on
+fn missing(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "leeward-cli-fleet-{name}-{}.sock",
+        std::process::id()
+    ))
+}
+
+fn daemons() -> (TestDaemon, TestDaemon) {
+    (
+        TestDaemon::builder().mock().workers(1).spawn().unwrap(),
+        TestDaemon::builder().mock().workers(2).spawn().unwrap(),
+    )
+}
+
+#[test]
+fn status_has_a_row_per_daemon_in_order() {
+    let (one, two) = daemons();
+    let output = leeward(&["status"], &[two.socket(), one.socket()]);
+    assert!(output.status.success(), "{output:?}");
+    let table = stdout(&output);
+    let lines: Vec<_> = table.lines().collect();
+    assert_eq!(lines.len(), 3, "{table}");
+    assert!(lines[0].starts_with("SOCKET"), "{table}");
+    assert!(
+        lines[1].starts_with(&two.socket().display().to_string()),
+        "{table}"
+    );
+    assert_eq!(lines[1].split_whitespace().nth(1), Some("2"), "{table}");
+    assert_eq!(lines[2].split_whitespace().nth(1), Some("1"), "{table}");
+
+    let output = leeward(&["status", "--json"], &[one.socket(), two.socket()]);
+    assert!(output.status.success(), "{output:?}");
+    let rows = json(&output);
+    assert_eq!(rows[0]["socket"], one.socket().display().to_string());
+    assert_eq!(rows[0]["ok"]["total"], 1);
+    assert_eq!(rows[1]["ok"]["total"], 2);
+}
+
+#[test]
+fn an_unreachable_daemon_fails_its_row_and_the_exit_code() {
+    let (one, two) = daemons();
+    let gone = missing("status");
+    let output = leeward(&["status"], &[one.socket(), &gone, two.socket()]);
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::ConnectionFailed.code().into()),
+        "{output:?}"
+    );
+    let table = stdout(&output);
+    let lines: Vec<_> = table.lines().collect();
+    assert_eq!(lines.len(), 4, "{table}");
+    assert!(lines[2].contains("error:"), "{table}");
+    assert!(
+        !lines[1].contains("error:") && !lines[3].contains("error:"),
+        "{table}"
+    );
+
+    let output = leeward(&["ping", "--json"], &[one.socket(), &gone]);
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::ConnectionFailed.code().into()),
+        "{output:?}"
+    );
+    let rows = json(&output);
+    assert!(rows[0]["ok"]["latency_us"].is_u64(), "{rows:?}");
+    assert_eq!(
+        rows[1]["error"]["exit_code"],
+        OutcomeCode::ConnectionFailed.code()
+    );
+
+    // Events report the failure inline too, then exit once no daemon is left
+    let output = leeward(&["events", "--json"], &[&gone, &missing("events")]);
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::ConnectionFailed.code().into()),
+        "{output:?}"
+    );
+    assert_eq!(stdout(&output).lines().count(), 2, "{output:?}");
+}
+
+#[test]
+fn sockets_can_come_from_a_file() {
+    let (one, two) = daemons();
+    let list = std::env::temp_dir().join(format!("leeward-cli-fleet-list-{}", std::process::id()));
+    std::fs::write(
+        &list,
+        format!(
+            "# fleet\n{}\n\n{}\n",
+            one.socket().display(),
+            two.socket().display()
+        ),
+    )
+    .unwrap();
+    let output = leeward(&["ping", "--sockets-from", list.to_str().unwrap()], &[]);
+    std::fs::remove_file(&list).unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let table = stdout(&output);
+    assert_eq!(
+        table
+            .lines()
+            .filter(|line| line.contains("pong in"))
+            .count(),
+        2,
+        "{table}"
+    );
+
+    let output = leeward(&["ping", "--sockets-from", list.to_str().unwrap()], &[]);
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::InvalidArgument.code().into()),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn drains_wait_on_every_daemon() {
+    let (one, two) = daemons();
+    let output = leeward(
+        &["drain", "--wait", "--json"],
+        &[one.socket(), two.socket()],
+    );
+    assert!(output.status.success(), "{output:?}");
+    for row in json(&output) {
+        assert_eq!(row["ok"]["drained"], true, "{row}");
+    }
+    assert_eq!(two.live_workers(), 2);
+}
+
+#[test]
+fn detail_needs_a_single_daemon() {
+    let (one, two) = daemons();
+    let output = leeward(&["status", "--detailed"], &[one.socket(), two.socket()]);
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::InvalidArgument.code().into()),
+        "{output:?}"
+    );
+
+    // One socket keeps the familiar output
+    let output = leeward(&["status"], &[one.socket()]);
+    assert!(
+        stdout(&output).starts_with("Workers: 1 total"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,123 @@
+//! `leeward exec --out-dir` writes an execution's output to files, within
+//! a client memory limit, and marks them partial when the execution never
+//! finished
+
+mod common;
+
+use leeward_core::OutcomeCode;
+use leeward_core::protocol::{self, MAX_CODE_SIZE};
+use leeward_daemon::testing::TestDaemon;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Data segment, in bytes, the CLI may grow to while it writes the output
+const CLIENT_MEMORY: libc::rlim_t = 64 * 1024 * 1024;
+
+/// Run `leeward exec - --out-dir dir` with `code` on stdin, its data
+/// segment held to [`CLIENT_MEMORY`]
+fn exec_to_dir(socket: &Path, dir: &Path, code: &str) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_leeward"));
+    command
+        .args(["exec", "-", "--out-dir"])
+        .arg(dir)
+        .arg("--socket")
+        .arg(socket)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // SAFETY: setrlimit is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            let limit = libc::rlimit {
+                rlim_cur: CLIENT_MEMORY,
+                rlim_max: CLIENT_MEMORY,
+            };
+            if libc::setrlimit(libc::RLIMIT_DATA, &raw const limit) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(code.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn index(dir: &Path) -> serde_json::Value {
+    serde_json::from_slice(&std::fs::read(dir.join("index.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn output_is_written_to_files_with_an_index() {
+    let daemon = TestDaemon::builder().mock().workers(1).spawn().unwrap();
+    let scratch = common::Scratch::new("cli-out-whole");
+    // Left for the CLI to make
+    let dir = scratch.join("out");
+    // Mock workers echo the code, so this is as much output as code can be
+    let line = "print('the quick brown fox jumps over the lazy dog')\n";
+    let code = line.repeat(MAX_CODE_SIZE / line.len());
+
+    let output = exec_to_dir(daemon.socket(), &dir, &code);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"");
+
+    let stdout = std::fs::read(dir.join("stdout.txt")).unwrap();
+    assert_eq!(
+        protocol::sha256_hex(&stdout),
+        protocol::sha256_hex(code.as_bytes())
+    );
+    assert_eq!(std::fs::read(dir.join("stderr.txt")).unwrap(), b"");
+    assert!(!dir.join("stdout.txt.partial").exists());
+    assert!(!dir.join("stderr.txt.partial").exists());
+
+    let index = index(&dir);
+    assert_eq!(index["complete"], true);
+    assert_eq!(index["success"], true);
+    assert_eq!(index["exit_code"], 0);
+    assert_eq!(index["stdout"]["file"], "stdout.txt");
+    assert_eq!(index["stdout"]["bytes"], code.len());
+    assert_eq!(
+        index["stdout"]["sha256"],
+        protocol::sha256_hex(code.as_bytes())
+    );
+    assert_eq!(index["stdout"]["truncated"], false);
+    assert_eq!(index["stderr"]["bytes"], 0);
+}
+
+#[test]
+fn files_of_an_execution_that_never_finished_are_marked_partial() {
+    let dir = common::Scratch::new("cli-out-partial");
+    // Left by an earlier run, and not to be taken for this one's
+    std::fs::write(dir.join("stdout.txt"), b"stale").unwrap();
+    let socket = dir.join("nothing-listens.sock");
+
+    let output = exec_to_dir(&socket, &dir, "print(1)\n");
+    assert_eq!(
+        output.status.code(),
+        Some(OutcomeCode::ConnectionFailed.code().into())
+    );
+
+    assert!(!dir.join("stdout.txt").exists());
+    assert!(dir.join("stdout.txt.partial").exists());
+    let index = index(&dir);
+    assert_eq!(index["complete"], false);
+    assert!(
+        index["error"]
+            .as_str()
+            .is_some_and(|error| !error.is_empty())
+    );
+    assert_eq!(index["stdout"]["file"], "stdout.txt.partial");
+    assert_eq!(index["stdout"]["bytes"], 0);
+}
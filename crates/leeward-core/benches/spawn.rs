@@ -0,0 +1,92 @@
+//! Worker spawn and first-execution latency, with and without a root template
+//!
+//! Needs root for namespaces and mounts: `sudo cargo bench -p leeward-core`
+
+use criterion::{BenchmarkId, Criterion};
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::RootTemplate;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn main() {
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: spawn benchmarks need root");
+        return;
+    }
+
+    let config = SandboxConfig::default();
+    let template = Arc::new(RootTemplate::build(&config).expect("failed to build root template"));
+
+    let mut c = Criterion::default().configure_from_args();
+    bench_spawn(&mut c, &config, &template);
+    bench_first_execution(&mut c, &config, &template);
+    c.final_summary();
+}
+
+fn new_worker(config: &SandboxConfig, template: Option<&Arc<RootTemplate>>) -> Worker {
+    let worker = Worker::new(0, config.clone());
+    match template {
+        Some(template) => worker.with_root_template(Arc::clone(template)),
+        None => worker,
+    }
+}
+
+fn kill(worker: &Worker) {
+    if let Some(pid) = worker.pid {
+        // SAFETY: Killing and reaping a worker we spawned
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+fn bench_spawn(c: &mut Criterion, config: &SandboxConfig, template: &Arc<RootTemplate>) {
+    let mut group = c.benchmark_group("spawn");
+
+    for (name, template) in [("host_root", None), ("root_template", Some(template))] {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let mut worker = new_worker(config, template);
+                    let started = Instant::now();
+                    worker.spawn().expect("spawn failed");
+                    total += started.elapsed();
+                    kill(&worker);
+                }
+                total
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_first_execution(c: &mut Criterion, config: &SandboxConfig, template: &Arc<RootTemplate>) {
+    let mut group = c.benchmark_group("first_execution");
+    group.sample_size(10);
+
+    for (name, template) in [("host_root", None), ("root_template", Some(template))] {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let mut worker = new_worker(config, template);
+                    worker.spawn().expect("spawn failed");
+                    let started = Instant::now();
+                    worker
+                        .execute("pass", &ExecuteOptions::default())
+                        .expect("execution failed");
+                    total += started.elapsed();
+                    kill(&worker);
+                }
+                total
+            });
+        });
+    }
+
+    group.finish();
+}
@@ -0,0 +1,100 @@
+// Build provenance, shared by the build scripts of the binary crates with
+// `include!`. Emits the `LEEWARD_BUILD_*` variables read by
+// `leeward_core::build_info!`.
+//
+// Packagers building outside a git checkout can set `LEEWARD_GIT_DESCRIBE`;
+// `SOURCE_DATE_EPOCH` pins the build time for reproducible builds.
+
+fn emit_provenance() {
+    use std::process::Command;
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let run = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .current_dir(&manifest_dir)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|out| out.trim().to_owned())
+            .filter(|out| !out.is_empty())
+    };
+
+    println!("cargo:rerun-if-env-changed=LEEWARD_GIT_DESCRIBE");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git = std::env::var("LEEWARD_GIT_DESCRIBE")
+        .ok()
+        .filter(|describe| !describe.is_empty())
+        .or_else(|| run("git", &["describe", "--tags", "--always", "--dirty"]))
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    // Rerun when the checked out commit moves, not on every source change,
+    // so an unchanged tree does not relink for a new timestamp
+    if let Some(git_dir) = run("git", &["rev-parse", "--absolute-git-dir"]) {
+        let git_dir = std::path::Path::new(&git_dir);
+        let mut watched = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+        if let Some(head) = run("git", &["symbolic-ref", "-q", "HEAD"]) {
+            watched.push(git_dir.join(head));
+        }
+        for path in watched.into_iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        });
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc = run(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_owned());
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| name.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .collect();
+    features.sort_unstable();
+
+    println!("cargo:rustc-env=LEEWARD_BUILD_GIT={git}");
+    println!("cargo:rustc-env=LEEWARD_BUILD_TIME={}", rfc3339(epoch));
+    println!("cargo:rustc-env=LEEWARD_BUILD_RUSTC={rustc}");
+    println!(
+        "cargo:rustc-env=LEEWARD_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=LEEWARD_BUILD_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_default()
+    );
+    println!("cargo:rustc-env=LEEWARD_BUILD_FEATURES={}", features.join(","));
+}
+
+/// `secs` since the epoch as `YYYY-MM-DDTHH:MM:SSZ`
+fn rfc3339(secs: u64) -> String {
+    let days = i64::try_from(secs / 86_400).unwrap_or(0);
+    let time = secs % 86_400;
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's
+    // `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,69 @@
+//! Landlock on its own, applied before `exec` so the program run inherits it
+//!
+//! Restricts a child to reading everywhere and writing one directory, then
+//! has it exec a shell that tries to write both there and elsewhere. A
+//! ruleset cannot be taken back once applied, and applying it after the
+//! program started leaves whatever it opened first unchecked, so it goes
+//! on between fork and exec.
+//!
+//! ```text
+//! cargo run -p leeward-core --example landlock_only
+//! ```
+//!
+//! Exits 0 when the write outside was refused, or 77 without Landlock.
+
+use leeward_core::isolation::{LandlockConfig, LandlockStatus};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+fn main() {
+    let probed = LandlockStatus::probe();
+    if !probed.enforced() {
+        eprintln!("skipping: Landlock is not enforced here ({probed})");
+        std::process::exit(77);
+    }
+
+    let allowed =
+        std::env::temp_dir().join(format!("leeward-landlock-allowed-{}", std::process::id()));
+    let denied =
+        std::env::temp_dir().join(format!("leeward-landlock-denied-{}", std::process::id()));
+    std::fs::create_dir_all(&allowed).unwrap();
+    std::fs::create_dir_all(&denied).unwrap();
+
+    // Running a program needs its own right, so the shell's directories get it
+    let config = LandlockConfig::default()
+        .ro("/")
+        .exec("/bin")
+        .exec("/usr")
+        .rw(&allowed);
+    let mut command = Command::new("/bin/sh");
+    command
+        .arg("-c")
+        .arg(r#"echo ok > "$1/file" && ! echo escaped > "$2/file""#)
+        .arg("sh")
+        .arg(&allowed)
+        .arg(&denied)
+        .stderr(Stdio::null());
+    // SAFETY: This program has one thread, so nothing holds a lock the
+    // child could wait on between fork and exec
+    unsafe {
+        command.pre_exec(move || config.apply().map(drop).map_err(std::io::Error::other));
+    }
+    let status = command.status().unwrap();
+
+    assert!(
+        status.success(),
+        "the shell's writes went the wrong way: {status}"
+    );
+    assert_eq!(
+        std::fs::read_to_string(allowed.join("file")).unwrap(),
+        "ok\n"
+    );
+    assert!(
+        !denied.join("file").exists(),
+        "Landlock let a write outside through"
+    );
+    std::fs::remove_dir_all(&allowed).unwrap();
+    std::fs::remove_dir_all(&denied).unwrap();
+    println!("{probed}: writes held to {}", allowed.display());
+}
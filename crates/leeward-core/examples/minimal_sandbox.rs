@@ -0,0 +1,99 @@
+//! The isolation layers in the order a worker applies them
+//!
+//! Clones a child that enters new namespaces, mounts a tmpfs for its
+//! writes, restricts itself to that tmpfs with Landlock, and installs a
+//! seccomp filter last. Each layer needs what the one after it takes away:
+//! mounting needs syscalls seccomp denies, and Landlock must see the
+//! mounts it refers to. The child reports what Landlock let it write, then
+//! installs the filter and makes a syscall the filter kills it for.
+//!
+//! ```text
+//! cargo run -p leeward-core --example minimal_sandbox
+//! ```
+//!
+//! Exits 0 when every layer held, or 77 if this host lacks user
+//! namespaces, Landlock or seccomp.
+
+use leeward_core::ByteSize;
+use leeward_core::escape::KernelFeature;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::{LandlockConfig, MountConfig, NamespaceConfig, SeccompConfig};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+fn main() {
+    for feature in [
+        KernelFeature::UserNamespaces,
+        KernelFeature::Landlock,
+        KernelFeature::Seccomp,
+    ] {
+        if !feature.available() {
+            eprintln!("skipping: no {} here", feature.name());
+            std::process::exit(77);
+        }
+    }
+
+    let scratch =
+        std::env::temp_dir().join(format!("leeward-minimal-sandbox-{}", std::process::id()));
+    let outside =
+        std::env::temp_dir().join(format!("leeward-minimal-outside-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+
+    let (mut parent, mut child) = UnixStream::pair().unwrap();
+    let namespaces = NamespaceConfig {
+        // A new pid namespace only takes the children of whoever enters it
+        pid: false,
+        ..NamespaceConfig::default()
+    };
+    let mounts = MountConfig::default().tmpfs(&scratch, ByteSize::mib(1));
+    let landlock = LandlockConfig::default().ro("/").rw(&scratch);
+    let seccomp = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::default()
+    };
+
+    let (inside, blocked) = (scratch.join("inside.txt"), outside.join("outside.txt"));
+    let pid = clone_worker(0, move || {
+        namespaces.enter()?;
+        mounts.apply()?;
+        landlock.apply()?;
+        let wrote_inside = std::fs::write(&inside, b"mine").is_ok();
+        let wrote_outside = std::fs::write(&blocked, b"escaped").is_ok();
+        child.write_all(&[u8::from(wrote_inside), u8::from(wrote_outside)])?;
+
+        seccomp.apply()?;
+        // Not in the Python profile, so the filter kills the child here
+        // SAFETY: A syscall made to be refused
+        unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+        Ok(())
+    })
+    .unwrap();
+
+    let mut report = [0u8; 2];
+    let reported = parent.read_exact(&mut report);
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(
+        reported.is_ok(),
+        "the child died before reporting: status {status}"
+    );
+
+    assert_eq!(report[0], 1, "writing into the tmpfs was refused");
+    assert_eq!(
+        report[1], 0,
+        "Landlock let a write outside the tmpfs through"
+    );
+    assert!(
+        !scratch.join("inside.txt").exists(),
+        "the tmpfs write reached the host"
+    );
+    assert!(
+        libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS,
+        "seccomp did not kill the child: status {status}"
+    );
+    std::fs::remove_dir_all(&scratch).unwrap();
+    std::fs::remove_dir_all(&outside).unwrap();
+    println!("namespaces, mounts, Landlock and seccomp all held");
+}
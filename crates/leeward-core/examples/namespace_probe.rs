@@ -0,0 +1,143 @@
+//! Which namespaces this host lets a process create, and what each hides
+//!
+//! Clones a child into each namespace in turn and has it check the one
+//! thing that namespace changes: its pid, its hostname, its network
+//! interfaces, its mounts, its System V IPC and its ids. A namespace the
+//! kernel refuses is reported and skipped.
+//!
+//! ```text
+//! cargo run -p leeward-core --example namespace_probe
+//! ```
+//!
+//! Exits 0 when every namespace created behaved, or 77 if none could be.
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::{MountConfig, NamespaceConfig};
+use leeward_core::{ByteSize, LeewardError};
+use nix::sched::CloneFlags;
+use std::path::{Path, PathBuf};
+
+/// Where the mount namespace's probe mounts a tmpfs
+fn mount_point() -> PathBuf {
+    std::env::temp_dir().join(format!("leeward-namespace-probe-{}", std::process::id()))
+}
+
+/// A namespace, the flag creating it, and a check run inside it
+type Probe = (&'static str, CloneFlags, fn() -> bool);
+
+const PROBES: &[Probe] = &[
+    ("pid", CloneFlags::CLONE_NEWPID, || std::process::id() == 1),
+    ("uts", CloneFlags::CLONE_NEWUTS, || {
+        const NAME: &[u8] = b"leeward-probe";
+        // SAFETY: sethostname reads NAME's bytes only
+        let set = unsafe { libc::sethostname(NAME.as_ptr().cast(), NAME.len()) } == 0;
+        set && std::fs::read("/proc/sys/kernel/hostname")
+            .is_ok_and(|name| name.trim_ascii_end() == NAME)
+    }),
+    // The host's interfaces are gone, leaving only a loopback
+    ("net", CloneFlags::CLONE_NEWNET, || {
+        std::fs::read_to_string("/proc/net/dev").is_ok_and(|dev| {
+            dev.lines()
+                .skip(2)
+                .map(|line| line.trim_start().split(':').next())
+                .eq([Some("lo")])
+        })
+    }),
+    ("mount", CloneFlags::CLONE_NEWNS, || {
+        MountConfig::default()
+            .tmpfs(mount_point(), ByteSize::mib(1))
+            .apply()
+            .is_ok()
+            && mounted(&mount_point())
+    }),
+    // The host's segment is not there
+    ("ipc", CloneFlags::CLONE_NEWIPC, || {
+        std::fs::read_to_string("/proc/sysvipc/shm").is_ok_and(|shm| shm.lines().count() == 1)
+    }),
+];
+
+/// Whether something is mounted at `path` in this mount namespace
+fn mounted(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    std::fs::read_to_string("/proc/self/mountinfo").is_ok_and(|mounts| {
+        mounts
+            .lines()
+            .any(|line| line.split(' ').nth(4) == Some(&*path))
+    })
+}
+
+fn main() {
+    std::fs::create_dir_all(mount_point()).unwrap();
+    // SAFETY: A new segment, removed below
+    let segment = unsafe { libc::shmget(libc::IPC_PRIVATE, 4096, libc::IPC_CREAT | 0o600) };
+    assert!(segment >= 0, "shmget failed");
+
+    let mut created = 0;
+    let mut user = NamespaceConfig {
+        pid: false,
+        mount: false,
+        net: false,
+        ipc: false,
+        uts: false,
+        ..NamespaceConfig::default()
+    };
+    user.uid_map = vec![(0, nix::unistd::getuid().as_raw(), 1)];
+    user.gid_map = vec![(0, nix::unistd::getgid().as_raw(), 1)];
+    // Entered first, so the others need no privilege on the host
+    let user_check = || nix::unistd::geteuid().is_root();
+    if probe("user", 0, || user.enter(), user_check) {
+        created += 1;
+    }
+    for &(name, flag, check) in PROBES {
+        let flags = flag | CloneFlags::CLONE_NEWUSER;
+        #[allow(clippy::cast_sign_loss)]
+        let flags = flags.bits() as u64;
+        if probe(name, flags, || user.write_id_maps(None), check) {
+            created += 1;
+        }
+    }
+    // SAFETY: Removing the segment made above
+    unsafe { libc::shmctl(segment, libc::IPC_RMID, std::ptr::null_mut()) };
+    assert!(
+        !mounted(&mount_point()),
+        "the probe's tmpfs is mounted on the host"
+    );
+    std::fs::remove_dir(mount_point()).unwrap();
+    if created == 0 {
+        eprintln!("skipping: no namespaces could be created here");
+        std::process::exit(77);
+    }
+}
+
+/// Clone into `flags`, run `enter` then `check` there, and say whether the
+/// namespace was created; panics if it was but `check` failed
+fn probe(
+    name: &str,
+    flags: u64,
+    enter: impl FnOnce() -> leeward_core::Result<()>,
+    check: impl FnOnce() -> bool,
+) -> bool {
+    let pid = match clone_worker(flags, || {
+        enter()?;
+        if check() {
+            Ok(())
+        } else {
+            Err(LeewardError::Namespace(format!(
+                "{name} namespace check failed"
+            )))
+        }
+    }) {
+        Ok(pid) => pid,
+        Err(e) => {
+            println!("{name:>5}: unavailable ({e})");
+            return false;
+        }
+    };
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    let entered = libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+    assert!(entered, "{name} namespace did not behave: status {status}");
+    println!("{name:>5}: isolated");
+    true
+}
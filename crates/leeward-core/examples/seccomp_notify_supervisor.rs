@@ -0,0 +1,93 @@
+//! A supervisor deciding the syscalls a seccomp filter sends it
+//!
+//! Clones a child whose filter sends what the allowlist refuses to a
+//! listener, which the child hands to this process before making such a
+//! call. The filter has to go on last, once the child has set up whatever
+//! else it needs, and the handover itself must be allowed, or the child
+//! waits on a supervisor that never gets the listener.
+//!
+//! ```text
+//! cargo run -p leeward-core --example seccomp_notify_supervisor
+//! ```
+//!
+//! Exits 0 once the child saw the supervisor's answer, or 77 if seccomp
+//! user notifications are unavailable.
+
+use leeward_core::escape::KernelFeature;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::seccomp::{SeccompNotifyFd, SeccompResponse};
+use leeward_core::isolation::{SeccompConfig, SyscallRule};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+
+fn main() {
+    if !KernelFeature::Seccomp.available() {
+        eprintln!("skipping: no seccomp here");
+        std::process::exit(77);
+    }
+
+    let mut config = SeccompConfig {
+        log_denials: false,
+        notify_denials: true,
+        ..SeccompConfig::default()
+    };
+    config
+        .allowed_syscalls
+        .push(SyscallRule::allow(libc::SYS_sendmsg));
+
+    let (parent, child) = UnixStream::pair().unwrap();
+    let pid = clone_worker(0, move || {
+        // Without a listener there is no one to send it to; say so and stop
+        let Some(listener) = config.apply()? else {
+            leeward_core::socket::send_with_fds(child.as_fd(), b"n", &[])?;
+            return Ok(());
+        };
+        leeward_core::socket::send_with_fds(child.as_fd(), b"l", &[listener.as_fd()])?;
+        drop(listener);
+        // SAFETY: A syscall the supervisor answers, then exiting with whether it refused
+        unsafe {
+            let ret = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+            let refused = ret == -1 && *libc::__errno_location() == libc::EACCES;
+            libc::_exit(i32::from(!refused))
+        }
+    })
+    .unwrap();
+
+    let mut kind = [0u8; 1];
+    let (_, mut fds) = leeward_core::socket::recv_with_fds(parent.as_fd(), &mut kind).unwrap();
+    let Some(fd) = fds.pop() else {
+        reap(pid);
+        eprintln!("skipping: no seccomp user notifications here");
+        std::process::exit(77);
+    };
+    let listener = SeccompNotifyFd::from(fd);
+
+    let notification = listener
+        .wait_notification()
+        .unwrap()
+        .expect("the child exited first");
+    assert_eq!(notification.pid, u32::try_from(pid).unwrap());
+    assert_eq!(notification.syscall, libc::SYS_socket);
+    assert_eq!(notification.args[0], libc::AF_INET as u64);
+    println!("child {pid} asked for socket(AF_INET), answering EACCES");
+    assert!(
+        listener
+            .send_response(&notification, SeccompResponse::DenyWithEacces)
+            .unwrap()
+    );
+
+    let status = reap(pid);
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "the child did not see EACCES: status {status}"
+    );
+    // The child is gone, so nothing is left to notify about
+    assert!(listener.wait_notification().unwrap().is_none());
+}
+
+fn reap(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    status
+}
@@ -0,0 +1,89 @@
+//! Embedder-driven seccomp supervision: refuse writes to "secret" files
+//!
+//! Runs a shell command with its file opens routed to this process, and
+//! denies any open for writing whose file name contains "secret". Reads and
+//! every other file go through untouched.
+//!
+//! ```text
+//! cargo run -p leeward-core --example secret_guard -- <dir> [command]
+//! ```
+//!
+//! The command runs in `<dir>` and defaults to writing `notes.txt` and
+//! `secret.txt` there. Exits with the command's status, or 77 if seccomp
+//! user notifications are unavailable.
+
+use leeward_core::isolation::seccomp::{self, PendingNotification, SeccompResponse};
+use std::path::Path;
+use std::process::Command;
+
+const DEFAULT_COMMAND: &str = "echo ok > notes.txt; echo leak > secret.txt";
+
+/// Syscalls that can open a file for writing
+#[cfg(target_arch = "x86_64")]
+const OPEN_SYSCALLS: &[i64] = &[libc::SYS_open, libc::SYS_openat, libc::SYS_creat];
+#[cfg(not(target_arch = "x86_64"))]
+const OPEN_SYSCALLS: &[i64] = &[libc::SYS_openat];
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(dir) = args.next() else {
+        eprintln!("usage: secret_guard <dir> [command]");
+        std::process::exit(64);
+    };
+    let script = args.next().unwrap_or_else(|| DEFAULT_COMMAND.to_string());
+
+    let mut command = Command::new("/bin/sh");
+    command.arg("-c").arg(script).current_dir(dir);
+
+    let (mut child, stream) = match seccomp::spawn_supervised(&mut command, OPEN_SYSCALLS) {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            eprintln!("secret_guard: {e}");
+            std::process::exit(77);
+        }
+    };
+
+    // Ends once the command and everything it started have exited
+    for pending in stream {
+        let pending = pending.expect("failed to receive notification");
+        let response = decide(&pending);
+        // `false` just means the process died while we were deciding
+        pending.respond(response).expect("failed to respond");
+    }
+
+    let status = child.wait().expect("failed to wait for command");
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+fn decide(pending: &PendingNotification) -> SeccompResponse {
+    let (path_arg, flags) = match pending.syscall {
+        libc::SYS_openat => (pending.args[1], pending.args[2]),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open => (pending.args[0], pending.args[1]),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_creat => (pending.args[0], libc::O_WRONLY as u64),
+        _ => return SeccompResponse::Allow,
+    };
+
+    let writes =
+        flags & (libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC) as u64 != 0;
+    if !writes {
+        return SeccompResponse::Allow;
+    }
+
+    // A path we cannot read is treated as a secret
+    let Ok(path) = pending.read_path(path_arg) else {
+        return SeccompResponse::DenyWithEacces;
+    };
+    if is_secret(&path) {
+        eprintln!("secret_guard: denied write to {}", path.display());
+        SeccompResponse::DenyWithEacces
+    } else {
+        SeccompResponse::Allow
+    }
+}
+
+fn is_secret(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().contains("secret"))
+}
@@ -0,0 +1,51 @@
+//! A request and its response through a shared memory slot, across processes
+//!
+//! The region is a memfd the daemon creates and leases slots of. A client
+//! maps it writable to put code in its request slot, and reads the output
+//! back from the response slot the worker filled. Here a forked child
+//! plays the worker: it maps the same fd, reads the request and answers.
+//!
+//! ```text
+//! cargo run -p leeward-core --example shm_roundtrip
+//! ```
+//!
+//! Exits 0 when the response came back whole.
+
+use leeward_core::shm::{LeaseError, MappedSharedMemory, SharedMemoryRegion};
+
+/// The connection the slot is leased to
+const OWNER: u64 = 1;
+
+fn main() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let slot = region.allocate_slot(OWNER).unwrap();
+    let client = MappedSharedMemory::new(region.as_raw_fd(), false).unwrap();
+    client.write_request(&slot, b"print('hello')").unwrap();
+
+    // SAFETY: fork in a single-threaded program; the child only touches the mapping and exits
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let worker = MappedSharedMemory::new(region.as_raw_fd(), false).unwrap();
+        let code = worker.read_request(&slot).unwrap();
+        let ok = code == b"print('hello')" && worker.write_response(&slot, b"hello\n").is_ok();
+        // SAFETY: Exiting the child
+        unsafe { libc::_exit(i32::from(!ok)) };
+    }
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "worker failed: status {status}"
+    );
+
+    assert_eq!(client.read_response(&slot).unwrap(), b"hello\n");
+    region.free_slot(OWNER, &slot).unwrap();
+    // A freed lease is refused rather than reaching the slot's next holder
+    assert!(matches!(
+        region.check(OWNER, &slot),
+        Err(LeaseError::GenerationMismatch { .. })
+    ));
+    println!("slot {} round trip done", slot.slot_id);
+}
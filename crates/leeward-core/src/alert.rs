@@ -0,0 +1,152 @@
+//! Threshold alerts on sampled pool health
+//!
+//! An [`AlertMonitor`] is fed a [`PoolSample`] at a fixed interval and
+//! reports threshold crossings as [`AlertEvent`]s. An alert fires on the
+//! first sample at or above its threshold and resolves only after
+//! `clear_samples` consecutive samples below it, so a value hovering around
+//! the threshold produces one firing/resolution pair rather than a stream.
+
+use crate::protocol::{AlertEvent, AlertKind, AlertState};
+
+/// Consecutive samples below the threshold needed to resolve an alert
+const DEFAULT_CLEAR_SAMPLES: u32 = 3;
+
+/// Alert thresholds; `None` disables an alert
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertThresholds {
+    /// Requests waiting for a worker
+    pub queue_depth: Option<u64>,
+    /// Wait of the oldest queued request, in ms
+    pub queue_wait_ms: Option<u64>,
+    /// Workers in the `Dead` state
+    pub worker_dead_count: Option<u64>,
+    /// Workers running an interpreter binary that has changed on disk
+    pub interpreter_changed: Option<u64>,
+}
+
+impl AlertThresholds {
+    /// Threshold for `kind`, if enabled
+    #[must_use]
+    pub const fn get(&self, kind: AlertKind) -> Option<u64> {
+        match kind {
+            AlertKind::QueueDepth => self.queue_depth,
+            AlertKind::QueueWait => self.queue_wait_ms,
+            AlertKind::WorkerDead => self.worker_dead_count,
+            AlertKind::InterpreterChanged => self.interpreter_changed,
+            // Raised by the daemon's task supervisor, not by sampling
+            AlertKind::TaskFailing => None,
+        }
+    }
+}
+
+/// Pool health at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolSample {
+    /// Requests waiting for a worker
+    pub queue_depth: u64,
+    /// Wait of the oldest queued request, in ms (0 if none)
+    pub queue_wait_ms: u64,
+    /// Workers in the `Dead` state
+    pub dead_workers: u64,
+    /// Live workers spawned from an interpreter binary that has since been
+    /// replaced on disk
+    pub outdated_interpreters: u64,
+}
+
+impl PoolSample {
+    /// Sampled value for `kind`
+    #[must_use]
+    pub const fn get(&self, kind: AlertKind) -> u64 {
+        match kind {
+            AlertKind::QueueDepth => self.queue_depth,
+            AlertKind::QueueWait => self.queue_wait_ms,
+            AlertKind::WorkerDead => self.dead_workers,
+            AlertKind::InterpreterChanged => self.outdated_interpreters,
+            AlertKind::TaskFailing => 0,
+        }
+    }
+}
+
+/// Per-alert state between samples
+#[derive(Debug, Clone, Copy, Default)]
+struct Track {
+    firing: bool,
+    /// Consecutive samples below the threshold while firing
+    below: u32,
+}
+
+/// Turns pool samples into firing and resolution events
+#[derive(Debug, Clone)]
+pub struct AlertMonitor {
+    thresholds: AlertThresholds,
+    clear_samples: u32,
+    tracks: [Track; AlertKind::ALL.len()],
+}
+
+impl AlertMonitor {
+    #[must_use]
+    pub fn new(thresholds: AlertThresholds) -> Self {
+        Self {
+            thresholds,
+            clear_samples: DEFAULT_CLEAR_SAMPLES,
+            tracks: Default::default(),
+        }
+    }
+
+    /// Require `samples` consecutive samples below the threshold to resolve
+    #[must_use]
+    pub fn with_clear_samples(mut self, samples: u32) -> Self {
+        self.clear_samples = samples.max(1);
+        self
+    }
+
+    /// Alerts currently firing
+    #[must_use]
+    pub fn firing(&self) -> Vec<AlertKind> {
+        AlertKind::ALL
+            .into_iter()
+            .zip(&self.tracks)
+            .filter_map(|(kind, track)| track.firing.then_some(kind))
+            .collect()
+    }
+
+    /// Feed one sample, returning the alerts that fired or resolved
+    pub fn evaluate(&mut self, sample: &PoolSample) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for (kind, track) in AlertKind::ALL.into_iter().zip(&mut self.tracks) {
+            let Some(threshold) = self.thresholds.get(kind) else {
+                continue;
+            };
+            let value = sample.get(kind);
+
+            let state = if value >= threshold {
+                track.below = 0;
+                if track.firing {
+                    continue;
+                }
+                track.firing = true;
+                AlertState::Firing
+            } else {
+                if !track.firing {
+                    continue;
+                }
+                track.below += 1;
+                if track.below < self.clear_samples {
+                    continue;
+                }
+                *track = Track::default();
+                AlertState::Resolved
+            };
+
+            events.push(AlertEvent {
+                alert: kind,
+                state,
+                value,
+                threshold,
+            });
+        }
+
+        events
+    }
+}
@@ -0,0 +1,434 @@
+//! Blocking client for the daemon socket
+//!
+//! A [`Client`] holds one msgpack connection and sends requests over it in
+//! turn. The daemon's [`DaemonInfo`] is asked for once per connection and
+//! kept, so checking what the daemon supports before each request costs
+//! nothing after the first.
+//!
+//! [`Client::upload`] stages an input file too large to send inline,
+//! sending only what the daemon is missing, so uploading the same file
+//! again on a new connection picks up where a dropped one stopped.
+//!
+//! [`Client::with_defaults`] stores options the connection's executions
+//! get when they leave them unset, such as a timeout, so they need not be
+//! repeated on every request.
+//!
+//! [`Client::execute_to_writer`] hands an execution's output to writers,
+//! such as files, instead of returning it.
+//!
+//! [`Client::execute`] checks a request against the daemon's
+//! [`SizeLimits`](protocol::SizeLimits) before sending it, and refuses one
+//! that is too large with the [`ErrorKind`] and message the daemon would
+//! answer it with, without uploading it first. The limits come from the
+//! daemon's info, asked for again on every new connection, so a daemon that
+//! raises them is picked up on the next one.
+//!
+//! # Requests left unfinished
+//!
+//! The daemon answers a connection's requests one at a time, in order, and
+//! the protocol carries no request ids, so a response can only be matched to
+//! its request by its place on the connection. A request that fails after
+//! being sent and before its response is read in full, because
+//! [`Client::set_timeout`] ran out, the daemon went away or the frame was
+//! refused, leaves that response still to come. The client is then
+//! poisoned: its next request first opens a new connection, forgets the
+//! daemon's info and sets the defaults given to [`Client::set_defaults`]
+//! again, so a late response is never read as the answer to anything. The
+//! daemon still runs what was abandoned to its end, and its answer goes to
+//! the closed connection; there is no request to cancel an execution yet.
+//! A connection the daemon handed over to a new process is replaced the
+//! same way.
+
+use crate::policy::PolicyField;
+use crate::protocol::{
+    self, Adjustment, DaemonInfo, ErrorKind, ExecuteDefaults, ExecuteRequest, ExecuteResponse,
+    Request, Response, UploadStatus, feature,
+};
+use crate::{LeewardError, Result};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Largest chunk [`Client::upload`] sends in one request
+pub const UPLOAD_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// A connection to the daemon
+#[derive(Debug)]
+pub struct Client {
+    path: PathBuf,
+    stream: UnixStream,
+    info: Option<DaemonInfo>,
+    /// Set again on each new connection
+    defaults: Option<ExecuteDefaults>,
+    timeout: Option<Duration>,
+    /// Whether a request was sent whose response was not read in full
+    poisoned: bool,
+}
+
+impl Client {
+    /// Connect to the daemon listening on `path`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if nothing is listening on `path`.
+    pub fn connect(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            stream: crate::socket::connect(path)?,
+            info: None,
+            defaults: None,
+            timeout: None,
+            poisoned: false,
+        })
+    }
+
+    /// Give up on a request once sending it or waiting for its response
+    /// takes longer than `timeout`, or never with `None`, the default
+    ///
+    /// A request that times out fails with [`LeewardError::Io`] of kind
+    /// `WouldBlock` and poisons the client; see the module docs.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if `timeout` is zero.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.set_write_timeout(timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Whether the last request was left unfinished, so the next one opens
+    /// a new connection
+    #[must_use]
+    pub const fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Send `request` and wait for its response
+    ///
+    /// On a poisoned client, a new connection is opened first. If this
+    /// fails after sending anything, the client is poisoned, and whatever
+    /// the daemon does with `request` happens without an answer. Once the
+    /// daemon's info is known, a request larger than it reads is refused
+    /// with [`LeewardError::Refused`] without being sent.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the connection fails or times out,
+    /// [`LeewardError::Protocol`] if a message cannot be encoded or decoded,
+    /// and [`LeewardError::Refused`] for a request too large to send.
+    pub fn request(&mut self, request: &Request) -> Result<Response> {
+        if self.poisoned {
+            self.reconnect()?;
+        }
+        let body = protocol::encode(request)?;
+        if let Some(info) = &self.info {
+            info.limits.sizes.check_request_len(body.len())?;
+        }
+        let len = u32::try_from(body.len())
+            .map_err(|_| LeewardError::InvalidRequest("request too large to frame".into()))?;
+        self.poisoned = true;
+        self.stream.write_all(&len.to_be_bytes())?;
+        self.stream.write_all(&body)?;
+
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > protocol::MAX_MESSAGE_SIZE {
+            return Err(LeewardError::Execution(format!(
+                "response of {len} bytes exceeds the message size limit"
+            )));
+        }
+        let mut body = vec![0u8; len];
+        self.stream.read_exact(&mut body)?;
+        // The whole frame is read, so the next one is the next response
+        let response: Response = protocol::decode(&body)?;
+        self.poisoned = matches!(
+            response,
+            Response::Error {
+                kind: ErrorKind::HandedOver,
+                ..
+            }
+        );
+        Ok(response)
+    }
+
+    /// Run `request` and wait for its result
+    ///
+    /// A request over one of the daemon's size limits is refused before it
+    /// is sent, and any the daemon refuses fail with
+    /// [`LeewardError::Refused`]. Abandoning it, by a timeout or otherwise,
+    /// poisons the client as [`Client::request`] does, so its result can
+    /// never be mistaken for the next one's. A detached request is answered
+    /// with its id instead; send it with [`Client::request`].
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Refused`] for a request over a size limit or refused by
+    /// the daemon, and any error of [`Client::request`].
+    pub fn execute(&mut self, request: ExecuteRequest) -> Result<ExecuteResponse> {
+        // Daemons that predate Hello hang up on it, and get the request
+        // unchecked on a new connection
+        if let Ok(info) = self.daemon_info() {
+            info.limits.sizes.check(&request)?;
+        }
+        match self.request(&Request::Execute(request))? {
+            Response::Execute(response) => Ok(response),
+            Response::Error { message, kind } => Err(LeewardError::Refused { kind, message }),
+            other => Err(LeewardError::Execution(format!(
+                "unexpected answer to an execution: {other:?}"
+            ))),
+        }
+    }
+
+    /// Run `request` as [`Client::execute`] does, writing its stdout to
+    /// `stdout` and its stderr to `stderr` rather than returning them
+    ///
+    /// The result comes back with both streams empty. The daemon sends a
+    /// result in one frame of at most [`protocol::MAX_MESSAGE_SIZE`], so
+    /// that bounds what this holds, and each stream is dropped once
+    /// written. Failing to write leaves what the writers took so far.
+    ///
+    /// # Errors
+    ///
+    /// As [`Client::execute`], and [`LeewardError::Io`] if a writer fails.
+    pub fn execute_to_writer(
+        &mut self,
+        request: ExecuteRequest,
+        mut stdout: impl Write,
+        mut stderr: impl Write,
+    ) -> Result<ExecuteResponse> {
+        let mut response = self.execute(request)?;
+        if let Some(result) = response.result.as_mut() {
+            stdout.write_all(&std::mem::take(&mut result.stdout))?;
+            stderr.write_all(&std::mem::take(&mut result.stderr))?;
+        }
+        stdout.flush()?;
+        stderr.flush()?;
+        Ok(response)
+    }
+
+    /// Replace the connection left by an unfinished request with a new one
+    /// set up the same way
+    fn reconnect(&mut self) -> Result<()> {
+        tracing::debug!(
+            path = %self.path.display(),
+            "reconnecting after an unfinished or handed over request"
+        );
+        self.stream = crate::socket::connect(&self.path)?;
+        self.poisoned = false;
+        self.info = None;
+        let restored = self.set_timeout(self.timeout).and_then(|()| {
+            let defaults = self.defaults.clone();
+            defaults.map_or(Ok(()), |defaults| self.set_defaults(defaults).map(drop))
+        });
+        // Try again next time rather than go on without them
+        self.poisoned |= restored.is_err();
+        restored
+    }
+
+    /// Who the daemon is and what it supports, asked for on first use
+    ///
+    /// Daemons that predate `Hello` close the connection instead of
+    /// answering, which fails with [`LeewardError::Io`]; connect again to
+    /// keep using them.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the daemon answers with an error, and any
+    /// error of [`Client::request`].
+    pub fn daemon_info(&mut self) -> Result<&DaemonInfo> {
+        let info = match self.info.take() {
+            Some(info) => info,
+            None => match self.request(&Request::Hello)? {
+                Response::Hello(info) => info,
+                Response::Error { message, .. } => return Err(LeewardError::Execution(message)),
+                other => {
+                    return Err(LeewardError::Execution(format!(
+                        "unexpected answer to hello: {other:?}"
+                    )));
+                }
+            },
+        };
+        Ok(self.info.insert(info))
+    }
+
+    /// This client, its executions getting `defaults` for what they leave
+    /// unset
+    ///
+    /// # Errors
+    ///
+    /// As [`Client::set_defaults`].
+    pub fn with_defaults(mut self, defaults: ExecuteDefaults) -> Result<Self> {
+        self.set_defaults(defaults)?;
+        Ok(self)
+    }
+
+    /// Replace the defaults this connection's executions get for what they
+    /// leave unset, returning how the daemon changed them to fit its limits
+    ///
+    /// The daemon clears them on `Hello`, so its info is asked for first
+    /// rather than after. They are set again on any new connection the
+    /// client opens.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the daemon does not support defaults or
+    /// refuses these, and any error of [`Client::request`].
+    pub fn set_defaults(&mut self, defaults: ExecuteDefaults) -> Result<Vec<Adjustment>> {
+        if !self.daemon_info()?.supports(feature::EXEC_DEFAULTS) {
+            return Err(LeewardError::Execution(
+                "the daemon does not support connection defaults".into(),
+            ));
+        }
+        match self.request(&Request::SetDefaults {
+            defaults: defaults.clone(),
+        })? {
+            Response::Defaults { adjustments, .. } => {
+                self.defaults = Some(defaults);
+                Ok(adjustments)
+            }
+            Response::Error { message, .. } => Err(LeewardError::Execution(message)),
+            other => Err(LeewardError::Execution(format!(
+                "unexpected answer to defaults: {other:?}"
+            ))),
+        }
+    }
+
+    /// Settings `request` would run with on this connection, each with
+    /// where it came from, without running it
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the daemon refuses `request`, and any
+    /// error of [`Client::request`].
+    pub fn explain_policy(
+        &mut self,
+        request: protocol::ExecuteRequest,
+    ) -> Result<Vec<PolicyField>> {
+        match self.request(&Request::ExplainPolicy { request })? {
+            Response::Policy { fields } => Ok(fields),
+            Response::Error { message, .. } => Err(LeewardError::Execution(message)),
+            other => Err(LeewardError::Execution(format!(
+                "unexpected answer to a policy explanation: {other:?}"
+            ))),
+        }
+    }
+
+    /// Upload `data` as `name` and commit it, returning the upload id to
+    /// reference from `ExecuteRequest.uploads`
+    ///
+    /// An unfinished upload of the same name and contents is resumed rather
+    /// than started over. Without `reusable`, the first execution that uses
+    /// the upload consumes it.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the daemon refuses the upload, and any
+    /// error of [`Client::request`].
+    pub fn upload(&mut self, name: &str, data: &[u8], reusable: bool) -> Result<u64> {
+        let total_len = data.len() as u64;
+        self.upload_from(
+            name,
+            total_len,
+            protocol::sha256_hex(data),
+            reusable,
+            |start, end| {
+                usize::try_from(start)
+                    .ok()
+                    .zip(usize::try_from(end).ok())
+                    .and_then(|(start, end)| data.get(start..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| {
+                        LeewardError::Execution(format!(
+                            "daemon asked for bytes {start}..{end} of a {total_len} byte upload"
+                        ))
+                    })
+            },
+        )
+    }
+
+    /// Upload the contents of `file` as `name`, as [`Client::upload`] does,
+    /// reading one chunk at a time rather than the whole file into memory
+    ///
+    /// The file is read twice, to hash it from the start and to send it,
+    /// and must not change in between.
+    ///
+    /// # Errors
+    ///
+    /// As [`Client::upload`], and [`LeewardError::Io`] if `file` cannot be
+    /// read.
+    pub fn upload_file(&mut self, name: &str, file: &std::fs::File, reusable: bool) -> Result<u64> {
+        use std::io::{Seek, SeekFrom};
+        use std::os::unix::fs::FileExt;
+
+        let total_len = file.metadata()?.len();
+        let mut reader = file;
+        reader.seek(SeekFrom::Start(0))?;
+        let sha256 = protocol::sha256_hex_reader(reader.take(total_len))?;
+
+        self.upload_from(name, total_len, sha256, reusable, |start, end| {
+            let len = usize::try_from(end - start)
+                .map_err(|_| LeewardError::InvalidRequest("chunk too large".into()))?;
+            let mut chunk = vec![0; len];
+            file.read_exact_at(&mut chunk, start)?;
+            Ok(chunk)
+        })
+    }
+
+    /// Begin an upload of `total_len` bytes hashing to `sha256`, send the
+    /// bytes the daemon is missing, read through `chunk` from a start to an
+    /// end offset, and commit it
+    fn upload_from(
+        &mut self,
+        name: &str,
+        total_len: u64,
+        sha256: String,
+        reusable: bool,
+        mut chunk: impl FnMut(u64, u64) -> Result<Vec<u8>>,
+    ) -> Result<u64> {
+        let status = self.upload_request(&Request::UploadBegin {
+            name: name.to_owned(),
+            total_len,
+            sha256,
+            reusable,
+        })?;
+        if status.committed {
+            return Ok(status.id);
+        }
+
+        for range in &status.missing {
+            let mut offset = range.start;
+            while offset < range.end {
+                let end = range.end.min(offset + UPLOAD_CHUNK_BYTES as u64);
+                if end > total_len {
+                    return Err(LeewardError::Execution(format!(
+                        "daemon asked for bytes {offset}..{end} of a {total_len} byte upload"
+                    )));
+                }
+                self.upload_request(&Request::UploadChunk {
+                    id: status.id,
+                    offset,
+                    data: chunk(offset, end)?,
+                })?;
+                offset = end;
+            }
+        }
+
+        self.upload_request(&Request::UploadCommit { id: status.id })
+            .map(|status| status.id)
+    }
+
+    /// Send an upload request, expecting the upload's status back
+    fn upload_request(&mut self, request: &Request) -> Result<UploadStatus> {
+        match self.request(request)? {
+            Response::Upload(status) => Ok(status),
+            Response::Error { message, .. } => Err(LeewardError::Execution(message)),
+            other => Err(LeewardError::Execution(format!(
+                "unexpected answer to an upload: {other:?}"
+            ))),
+        }
+    }
+}
@@ -1,140 +0,0 @@
-//! Sandbox configuration
-
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::time::Duration;
-
-/// Configuration for a sandbox instance
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SandboxConfig {
-    /// Path to Python interpreter
-    pub python_path: PathBuf,
-
-    /// Additional paths to bind mount read-only
-    pub ro_binds: Vec<PathBuf>,
-
-    /// Paths to bind mount read-write
-    pub rw_binds: Vec<PathBuf>,
-
-    /// Maximum execution time
-    pub timeout: Duration,
-
-    /// Allow network access
-    pub allow_network: bool,
-
-    /// Working directory inside sandbox
-    pub workdir: PathBuf,
-
-    /// Environment variables
-    pub env: Vec<(String, String)>,
-}
-
-impl Default for SandboxConfig {
-    fn default() -> Self {
-        Self {
-            python_path: find_python(),
-            ro_binds: vec![
-                PathBuf::from("/usr"),
-                PathBuf::from("/lib"),
-                PathBuf::from("/lib64"),
-            ],
-            rw_binds: vec![],
-            timeout: Duration::from_secs(30),
-            allow_network: false,
-            workdir: PathBuf::from("/home/sandbox"),
-            env: vec![
-                ("PATH".into(), "/usr/bin:/bin".into()),
-                ("HOME".into(), "/home/sandbox".into()),
-                ("TMPDIR".into(), "/tmp".into()),
-            ],
-        }
-    }
-}
-
-impl SandboxConfig {
-    /// Create a new config builder
-    #[must_use]
-    pub fn builder() -> SandboxConfigBuilder {
-        SandboxConfigBuilder::default()
-    }
-}
-
-/// Builder for SandboxConfig
-#[derive(Debug, Default)]
-pub struct SandboxConfigBuilder {
-    config: SandboxConfig,
-}
-
-impl SandboxConfigBuilder {
-    #[must_use]
-    pub fn python_path(mut self, path: impl Into<PathBuf>) -> Self {
-        self.config.python_path = path.into();
-        self
-    }
-
-    #[must_use]
-    pub fn timeout(mut self, duration: Duration) -> Self {
-        self.config.timeout = duration;
-        self
-    }
-
-    #[must_use]
-    pub fn timeout_secs(self, secs: u64) -> Self {
-        self.timeout(Duration::from_secs(secs))
-    }
-
-    #[must_use]
-    pub fn allow_network(mut self, allow: bool) -> Self {
-        self.config.allow_network = allow;
-        self
-    }
-
-    #[must_use]
-    pub fn ro_bind(mut self, path: impl Into<PathBuf>) -> Self {
-        self.config.ro_binds.push(path.into());
-        self
-    }
-
-    #[must_use]
-    pub fn rw_bind(mut self, path: impl Into<PathBuf>) -> Self {
-        self.config.rw_binds.push(path.into());
-        self
-    }
-
-    #[must_use]
-    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        self.config.env.push((key.into(), value.into()));
-        self
-    }
-
-    #[must_use]
-    pub fn build(self) -> SandboxConfig {
-        self.config
-    }
-}
-
-/// Find Python executable in PATH
-fn find_python() -> PathBuf {
-    if let Ok(path_var) = std::env::var("PATH") {
-        for dir in path_var.split(':') {
-            for name in &["python3", "python"] {
-                let candidate = PathBuf::from(dir).join(name);
-                if candidate.exists() {
-                    return candidate;
-                }
-            }
-        }
-    }
-    PathBuf::from("python3")
-}
-
-/// Get default socket path from LEEWARD_SOCKET env var or system default
-///
-/// Returns:
-/// - `$LEEWARD_SOCKET` if set (for development)
-/// - `/run/leeward/leeward.sock` otherwise (production)
-pub fn default_socket_path() -> PathBuf {
-    std::env::var("LEEWARD_SOCKET")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/run/leeward/leeward.sock"))
-}
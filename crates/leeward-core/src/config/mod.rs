@@ -0,0 +1,821 @@
+//! Sandbox configuration
+//!
+//! Every path the config names is checked through [`paths`].
+
+pub mod network;
+pub mod paths;
+
+pub use self::network::{Ipv4Net, NetworkConfig};
+use self::paths::{CanonicalPath, PathPolicy};
+use crate::isolation::mounts::HIDEPID_INVISIBLE;
+use crate::units::ByteSize;
+use crate::{LeewardError, Result};
+#[cfg(feature = "protocol")]
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Host directory zone names are looked up in
+pub const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Zone the interpreter runs in when none is configured
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+
+/// Scratch directory `TMPDIR` always points at
+pub const TMP_DIR: &str = "/tmp";
+
+/// Size of the `/tmp` tmpfs when none is configured
+pub const DEFAULT_TMP_SIZE: ByteSize = ByteSize::mib(64);
+
+/// Period a [`SandboxConfig::cpu_percent`] quota is measured over when none
+/// is configured
+pub const DEFAULT_CPU_PERIOD: Duration = Duration::from_millis(100);
+
+/// How long a worker pipe stays full before it counts as stalled when no
+/// threshold is configured
+pub const DEFAULT_PIPE_STALL_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Largest [`SandboxConfig::pipe_buffer_size`] accepted
+pub const MAX_PIPE_BUFFER_SIZE: ByteSize = ByteSize::gib(1);
+
+/// How long past the deadline the watchdog fires when none is configured
+pub const DEFAULT_WATCHDOG_MARGIN: Duration = Duration::from_secs(2);
+
+/// Configuration for a sandbox instance
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[allow(clippy::struct_excessive_bools)]
+pub struct SandboxConfig {
+    /// Path to Python interpreter
+    pub python_path: PathBuf,
+
+    /// Additional paths to bind mount read-only
+    pub ro_binds: Vec<PathBuf>,
+
+    /// Paths to bind mount read-write
+    pub rw_binds: Vec<PathBuf>,
+
+    /// Maximum execution time
+    pub timeout: Duration,
+
+    /// How long before the timeout a requested soft-timeout traceback is dumped
+    pub traceback_margin: Duration,
+
+    /// How long past the deadline the worker's watchdog kills code its
+    /// timeout did not
+    #[cfg_attr(feature = "protocol", serde(default = "default_watchdog_margin"))]
+    pub watchdog_margin: Duration,
+
+    /// Allow network access
+    pub allow_network: bool,
+
+    /// Working directory inside sandbox
+    pub workdir: PathBuf,
+
+    /// Environment variables
+    pub env: Vec<(String, String)>,
+
+    /// Address-space limit for the interpreter
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub memory_limit: Option<ByteSize>,
+
+    /// Nice value for the interpreter (inherited if unset)
+    ///
+    /// Raising it needs no privileges; going below the daemon's own value
+    /// needs `CAP_SYS_NICE`.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub nice: Option<i8>,
+
+    /// CPU scheduling policy for the interpreter (inherited if unset)
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub sched_policy: Option<SchedPolicy>,
+
+    /// IANA timezone for the interpreter, such as `Europe/Paris`
+    ///
+    /// Sets `TZ`, and in a root template also provides `/etc/localtime`.
+    /// Unset means [`DEFAULT_TIMEZONE`], never the host's zone.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub timezone: Option<String>,
+
+    /// Size of the `/tmp` tmpfs
+    ///
+    /// In a root template, `/tmp` is its own tmpfs, separate from the
+    /// workspace and emptied before each execution, so filling it never
+    /// costs the workspace any space.
+    #[cfg_attr(
+        feature = "protocol",
+        serde(default = "default_tmp_size", alias = "tmp_size_bytes")
+    )]
+    pub tmp_size: ByteSize,
+
+    /// Debug profile: let the code's processes trace one another
+    ///
+    /// Off, the worker is not dumpable and `ptrace`, `process_vm_readv` and
+    /// `process_vm_writev` fail with `EPERM`. On, both are lifted, so a
+    /// debugger or a sampler such as `py-spy` run from the host can attach
+    /// to the worker and its interpreter, and results are marked
+    /// [`debug`](crate::ExecutionResult::debug).
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub debug: bool,
+
+    /// Resident memory past which a worker process makes way for a fresh
+    /// one after its current execution (unlimited if unset)
+    ///
+    /// The worker process, not the interpreter it starts, so this catches
+    /// what builds up in the worker across executions.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub worker_max_rss: Option<ByteSize>,
+
+    /// CPU the code's processes may use together, in percent of one CPU,
+    /// where the worker has a cgroup (unlimited if unset)
+    ///
+    /// Written to the cgroup's `cpu.max` as a quota of each
+    /// [`cpu_period`](Self::cpu_period); 200 is two CPUs' worth.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub cpu_percent: Option<u32>,
+
+    /// Period the `cpu_percent` quota is measured over, from 1 ms to 1 s
+    #[cfg_attr(feature = "protocol", serde(default = "default_cpu_period"))]
+    pub cpu_period: Duration,
+
+    /// Most processes and threads the worker's cgroup may hold at once, the
+    /// worker's own included (unlimited if unset)
+    ///
+    /// Written to the cgroup's `pids.max`; an execution that hits it fails
+    /// with [`LeewardError::Execution`].
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub max_pids: Option<u32>,
+
+    /// Percent of an execution's memory limit at which the program is sent
+    /// `SIGUSR1`, once, where the worker has a cgroup (never if unset)
+    ///
+    /// Python code can free memory in time with
+    /// `signal.signal(signal.SIGUSR1, handler)`. A program without a
+    /// handler is not sent the signal, which would kill it. The cgroup is
+    /// sampled every 10 ms, so a fast enough spike reaches the limit
+    /// unwarned; the limit itself is enforced as before. Requests can opt
+    /// out.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub pressure_signal_percent: Option<u8>,
+
+    /// Buffer size of each pipe between the daemon and a worker (the
+    /// kernel's, 64 KiB, if unset)
+    ///
+    /// Set with `F_SETPIPE_SZ` as the worker spawns, which rounds it up to
+    /// a power of two pages. Past `/proc/sys/fs/pipe-max-size` the kernel
+    /// refuses it to unprivileged daemons, and the worker keeps the
+    /// default with a warning.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub pipe_buffer_size: Option<ByteSize>,
+
+    /// How long a worker pipe may stay full before it is logged as
+    /// stalled, with the `pipe-stalls` debug flag on
+    #[cfg_attr(feature = "protocol", serde(default = "default_pipe_stall_threshold"))]
+    pub pipe_stall_threshold: Duration,
+
+    /// What the worker's network namespace has in it when `allow_network`
+    /// is off; see [`network`]
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub network: NetworkConfig,
+
+    /// TCP ports the code may connect to, held so by Landlock on kernels
+    /// with ABI 4 or newer; empty leaves connecting unrestricted
+    ///
+    /// With `allow_network`, `[443]` lets code reach HTTPS servers and
+    /// nothing else over TCP. UDP, and so DNS, is not restricted.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub allowed_tcp_connect_ports: Vec<u16>,
+
+    /// TCP ports the code may bind, like `allowed_tcp_connect_ports`
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub allowed_tcp_bind_ports: Vec<u16>,
+
+    /// Run the code in a time namespace of the worker's, where the
+    /// monotonic and boot clocks read 0 when the worker was spawned
+    ///
+    /// Code can then neither tell how long the host has been up nor time
+    /// it against the host's clocks. The offsets are fixed at spawn, so the
+    /// clocks keep running across the worker's executions: code run by a
+    /// pooled worker reads the time since that worker was spawned, which may
+    /// be hours, and only the first execution after a spawn sees values
+    /// near 0. Needs Linux 5.6 or newer; workers fail to spawn without time
+    /// namespaces.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub freeze_clock: bool,
+
+    /// Fail to spawn workers when a path in `ro_binds` or `rw_binds` does
+    /// not exist, instead of leaving it out of the sandbox
+    ///
+    /// The error names the path. Off by default, so a config listing
+    /// `/lib64` still works on hosts without one.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub strict_paths: bool,
+
+    /// Mount a `/proc` of the worker's pid namespace, listing only the
+    /// processes in the sandbox
+    ///
+    /// Mounted by a process that stays as the namespace's init, so the
+    /// code's processes are never pid 1. Without it the sandbox has an
+    /// empty `/proc` under a root template, or the host's without one.
+    #[cfg_attr(feature = "protocol", serde(default = "default_mount_proc"))]
+    pub mount_proc: bool,
+
+    /// `hidepid` for that `/proc`: 0 lists every process in the sandbox,
+    /// 1 hides the details of other users' and 2 does not list them
+    #[cfg_attr(feature = "protocol", serde(default = "default_proc_hidepid"))]
+    pub proc_hidepid: u8,
+
+    /// Private and link-local networks a controlled network still lets
+    /// workers reach
+    ///
+    /// A controlled network drops traffic to `10.0.0.0/8`, `172.16.0.0/12`,
+    /// `192.168.0.0/16` and `169.254.0.0/16`, cloud metadata included,
+    /// other than DNS to its nameserver. Networks listed here are let
+    /// through.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub egress_allow_private: Vec<Ipv4Net>,
+}
+
+#[cfg(feature = "protocol")]
+const fn default_tmp_size() -> ByteSize {
+    DEFAULT_TMP_SIZE
+}
+
+#[cfg(feature = "protocol")]
+const fn default_watchdog_margin() -> Duration {
+    DEFAULT_WATCHDOG_MARGIN
+}
+
+#[cfg(feature = "protocol")]
+const fn default_cpu_period() -> Duration {
+    DEFAULT_CPU_PERIOD
+}
+
+#[cfg(feature = "protocol")]
+const fn default_pipe_stall_threshold() -> Duration {
+    DEFAULT_PIPE_STALL_THRESHOLD
+}
+
+#[cfg(feature = "protocol")]
+const fn default_mount_proc() -> bool {
+    true
+}
+
+#[cfg(feature = "protocol")]
+const fn default_proc_hidepid() -> u8 {
+    HIDEPID_INVISIBLE
+}
+
+/// Linux CPU scheduling policies an interpreter may run under
+///
+/// None of them are real-time, and a process can always move itself to
+/// `Batch` or `Idle` without privileges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "protocol", serde(rename_all = "snake_case"))]
+pub enum SchedPolicy {
+    /// `SCHED_OTHER`, the default time-sharing policy
+    Other,
+    /// `SCHED_BATCH`, for CPU-bound work that can wait a little longer
+    Batch,
+    /// `SCHED_IDLE`, only runs when nothing else wants the CPU
+    Idle,
+}
+
+impl SchedPolicy {
+    /// The policy's `SCHED_*` value
+    #[must_use]
+    pub const fn as_raw(self) -> libc::c_int {
+        match self {
+            Self::Other => libc::SCHED_OTHER,
+            Self::Batch => libc::SCHED_BATCH,
+            Self::Idle => libc::SCHED_IDLE,
+        }
+    }
+}
+
+/// Program that runs submitted code
+///
+/// Shells get the code as `-c` and their arguments as `$1`, `$2`, and so
+/// on. Only Python has soft-timeout tracebacks and tells a startup failure
+/// under the memory limit from one in the code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "protocol", serde(rename_all = "snake_case"))]
+pub enum Interpreter {
+    /// The config's `python_path`
+    #[default]
+    Python,
+    /// `/bin/sh`
+    Sh,
+    /// `/bin/bash`
+    Bash,
+}
+
+impl Interpreter {
+    /// Every interpreter
+    pub const ALL: [Self; 3] = [Self::Python, Self::Sh, Self::Bash];
+
+    /// Name on the wire, as in `DaemonInfo.languages`
+    #[must_use]
+    pub const fn wire_name(self) -> &'static str {
+        match self {
+            Self::Python => "python",
+            Self::Sh => "sh",
+            Self::Bash => "bash",
+        }
+    }
+
+    /// Name used in messages
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Python => "Python",
+            Self::Sh => "sh",
+            Self::Bash => "bash",
+        }
+    }
+
+    /// Path of the program, given the sandbox config
+    #[must_use]
+    pub fn program(self, config: &SandboxConfig) -> &Path {
+        match self {
+            Self::Python => &config.python_path,
+            Self::Sh => Path::new("/bin/sh"),
+            Self::Bash => Path::new("/bin/bash"),
+        }
+    }
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            python_path: find_python(),
+            ro_binds: vec![
+                PathBuf::from("/usr"),
+                PathBuf::from("/lib"),
+                PathBuf::from("/lib64"),
+            ],
+            rw_binds: vec![],
+            timeout: Duration::from_secs(30),
+            traceback_margin: Duration::from_secs(1),
+            watchdog_margin: DEFAULT_WATCHDOG_MARGIN,
+            allow_network: false,
+            workdir: PathBuf::from("/home/sandbox"),
+            env: vec![
+                ("PATH".into(), "/usr/bin:/bin".into()),
+                ("HOME".into(), "/home/sandbox".into()),
+            ],
+            memory_limit: None,
+            nice: None,
+            sched_policy: None,
+            timezone: None,
+            tmp_size: DEFAULT_TMP_SIZE,
+            debug: false,
+            worker_max_rss: None,
+            cpu_percent: None,
+            cpu_period: DEFAULT_CPU_PERIOD,
+            max_pids: None,
+            pressure_signal_percent: None,
+            pipe_buffer_size: None,
+            pipe_stall_threshold: DEFAULT_PIPE_STALL_THRESHOLD,
+            network: NetworkConfig::default(),
+            allowed_tcp_connect_ports: Vec::new(),
+            allowed_tcp_bind_ports: Vec::new(),
+            freeze_clock: false,
+            strict_paths: false,
+            mount_proc: true,
+            proc_hidepid: HIDEPID_INVISIBLE,
+            egress_allow_private: Vec::new(),
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Create a new config builder
+    #[must_use]
+    pub fn builder() -> SandboxConfigBuilder {
+        SandboxConfigBuilder::default()
+    }
+
+    /// Defaults cut down for test suites: a 10s timeout and an 8 MiB `/tmp`,
+    /// so a runaway test fails fast and many workers fit in memory
+    #[must_use]
+    pub fn minimal_for_tests() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            tmp_size: ByteSize::mib(8),
+            ..Self::default()
+        }
+    }
+
+    /// The `cpu.max` quota and period for [`Self::cpu_percent`], in
+    /// microseconds, if set
+    ///
+    /// The quota is never under the 1 ms the kernel takes.
+    #[must_use]
+    pub fn cpu_quota(&self) -> Option<(u64, u64)> {
+        let period = u64::try_from(self.cpu_period.as_micros()).unwrap_or(u64::MAX);
+        let quota = self.cpu_percent?;
+        Some((
+            period
+                .saturating_mul(u64::from(quota))
+                .div_ceil(100)
+                .max(1000),
+            period,
+        ))
+    }
+
+    /// The configured timezone, or [`DEFAULT_TIMEZONE`]
+    #[must_use]
+    pub fn timezone_name(&self) -> &str {
+        self.timezone.as_deref().unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    /// Check the config for mistakes that would only show up inside a worker
+    ///
+    /// The default zone needs no file, since glibc knows UTC without one,
+    /// and binds that do not exist are skipped.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] for an unknown timezone, a zero `tmp_size`,
+    /// which tmpfs would take as no limit at all, a zero `cpu_percent` or
+    /// `max_pids`, a `cpu_period` the kernel refuses, a
+    /// `pressure_signal_percent` outside 1 to 99, a `pipe_buffer_size` of 0 or
+    /// over [`MAX_PIPE_BUFFER_SIZE`], a bind that is relative, has `..` in it
+    /// or leads through a dangling symlink, a workdir with `..` in it, a
+    /// controlled network with `allow_network` on or an `egress_cidr` too small
+    /// for one worker's link, or TCP port rules in a build without Landlock.
+    pub fn validate(&self) -> Result<()> {
+        if self.tmp_size.is_zero() {
+            return Err(LeewardError::Config(
+                "tmp_size must be greater than 0".into(),
+            ));
+        }
+        if self.cpu_percent == Some(0) {
+            return Err(LeewardError::Config(
+                "cpu_percent must be greater than 0".into(),
+            ));
+        }
+        if !(Duration::from_millis(1)..=Duration::from_secs(1)).contains(&self.cpu_period) {
+            return Err(LeewardError::Config(format!(
+                "cpu_period must be from 1ms to 1s, not {:?}",
+                self.cpu_period
+            )));
+        }
+        if self.max_pids == Some(0) {
+            return Err(LeewardError::Config(
+                "max_pids must be greater than 0".into(),
+            ));
+        }
+        if let Some(percent) = self
+            .pressure_signal_percent
+            .filter(|percent| !(1..=99).contains(percent))
+        {
+            return Err(LeewardError::Config(format!(
+                "pressure_signal_percent must be from 1 to 99, not {percent}"
+            )));
+        }
+        if let Some(size) = self
+            .pipe_buffer_size
+            .filter(|&size| size.is_zero() || size > MAX_PIPE_BUFFER_SIZE)
+        {
+            return Err(LeewardError::Config(format!(
+                "pipe_buffer_size must be greater than 0 and at most \
+                 {MAX_PIPE_BUFFER_SIZE}, not {size}"
+            )));
+        }
+        if let NetworkConfig::Controlled { egress_cidr, .. } = self.network {
+            if self.allow_network {
+                return Err(LeewardError::Config(
+                    "a controlled network needs allow_network off".into(),
+                ));
+            }
+            if egress_cidr.prefix() > 30 {
+                return Err(LeewardError::Config(format!(
+                    "egress_cidr must be /30 or larger, not {egress_cidr}"
+                )));
+            }
+        }
+        if !cfg!(feature = "landlock")
+            && (!self.allowed_tcp_connect_ports.is_empty()
+                || !self.allowed_tcp_bind_ports.is_empty())
+        {
+            return Err(LeewardError::Config(
+                "TCP port rules need the landlock feature".into(),
+            ));
+        }
+        if self.proc_hidepid > HIDEPID_INVISIBLE {
+            return Err(LeewardError::Config(format!(
+                "proc_hidepid must be 0, 1 or 2, not {}",
+                self.proc_hidepid
+            )));
+        }
+        if let Some(timezone) = &self.timezone {
+            find_zone(timezone).map_err(LeewardError::Config)?;
+        }
+        for (field, binds) in [("ro_binds", &self.ro_binds), ("rw_binds", &self.rw_binds)] {
+            for path in binds {
+                // Bound at the same path in the sandbox, where `..` means nothing
+                CanonicalPath::resolve(path, &PathPolicy::host())
+                    .and_then(|_| CanonicalPath::resolve(path, &PathPolicy::sandbox()))
+                    .map_err(|e| LeewardError::Config(format!("{field}: {e}")))?;
+            }
+        }
+        CanonicalPath::resolve(&self.workdir, &PathPolicy::sandbox())
+            .map_err(|e| LeewardError::Config(format!("workdir: {e}")))?;
+        Ok(())
+    }
+
+    /// Stable hash of this config, used to spot workers running an old policy
+    #[cfg(feature = "protocol")]
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        crate::fingerprint::fingerprint(self)
+    }
+}
+
+/// Builder for `SandboxConfig`
+#[derive(Debug, Default)]
+pub struct SandboxConfigBuilder {
+    config: SandboxConfig,
+}
+
+impl SandboxConfigBuilder {
+    #[must_use]
+    pub fn python_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.python_path = path.into();
+        self
+    }
+
+    #[must_use]
+    pub const fn timeout(mut self, duration: Duration) -> Self {
+        self.config.timeout = duration;
+        self
+    }
+
+    #[must_use]
+    pub const fn timeout_secs(self, secs: u64) -> Self {
+        self.timeout(Duration::from_secs(secs))
+    }
+
+    #[must_use]
+    pub const fn traceback_margin(mut self, margin: Duration) -> Self {
+        self.config.traceback_margin = margin;
+        self
+    }
+
+    #[must_use]
+    pub const fn watchdog_margin(mut self, margin: Duration) -> Self {
+        self.config.watchdog_margin = margin;
+        self
+    }
+
+    #[must_use]
+    pub const fn memory_limit(mut self, limit: ByteSize) -> Self {
+        self.config.memory_limit = Some(limit);
+        self
+    }
+
+    /// See [`SandboxConfig::cpu_percent`]
+    #[must_use]
+    pub const fn cpu_percent(mut self, percent: u32) -> Self {
+        self.config.cpu_percent = Some(percent);
+        self
+    }
+
+    /// See [`SandboxConfig::cpu_period`]
+    #[must_use]
+    pub const fn cpu_period(mut self, period: Duration) -> Self {
+        self.config.cpu_period = period;
+        self
+    }
+
+    /// See [`SandboxConfig::max_pids`]
+    #[must_use]
+    pub const fn max_pids(mut self, limit: u32) -> Self {
+        self.config.max_pids = Some(limit);
+        self
+    }
+
+    /// See [`SandboxConfig::pressure_signal_percent`]
+    #[must_use]
+    pub const fn pressure_signal_percent(mut self, percent: u8) -> Self {
+        self.config.pressure_signal_percent = Some(percent);
+        self
+    }
+
+    /// See [`SandboxConfig::pipe_buffer_size`]
+    #[must_use]
+    pub const fn pipe_buffer_size(mut self, size: ByteSize) -> Self {
+        self.config.pipe_buffer_size = Some(size);
+        self
+    }
+
+    /// See [`SandboxConfig::pipe_stall_threshold`]
+    #[must_use]
+    pub const fn pipe_stall_threshold(mut self, threshold: Duration) -> Self {
+        self.config.pipe_stall_threshold = threshold;
+        self
+    }
+
+    #[must_use]
+    pub const fn nice(mut self, nice: i8) -> Self {
+        self.config.nice = Some(nice);
+        self
+    }
+
+    #[must_use]
+    pub const fn sched_policy(mut self, policy: SchedPolicy) -> Self {
+        self.config.sched_policy = Some(policy);
+        self
+    }
+
+    #[must_use]
+    pub const fn tmp_size(mut self, size: ByteSize) -> Self {
+        self.config.tmp_size = size;
+        self
+    }
+
+    #[must_use]
+    pub fn workdir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.workdir = path.into();
+        self
+    }
+
+    #[must_use]
+    pub fn timezone(mut self, name: impl Into<String>) -> Self {
+        self.config.timezone = Some(name.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn allow_network(mut self, allow: bool) -> Self {
+        self.config.allow_network = allow;
+        self
+    }
+
+    /// See [`SandboxConfig::network`]
+    #[must_use]
+    pub const fn network(mut self, network: NetworkConfig) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    /// See [`SandboxConfig::allowed_tcp_connect_ports`]
+    #[must_use]
+    pub fn allowed_tcp_connect_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.config.allowed_tcp_connect_ports = ports.into_iter().collect();
+        self
+    }
+
+    /// See [`SandboxConfig::freeze_clock`]
+    #[must_use]
+    pub const fn freeze_clock(mut self, freeze: bool) -> Self {
+        self.config.freeze_clock = freeze;
+        self
+    }
+
+    /// See [`SandboxConfig::mount_proc`]
+    #[must_use]
+    pub const fn mount_proc(mut self, mount: bool) -> Self {
+        self.config.mount_proc = mount;
+        self
+    }
+
+    /// See [`SandboxConfig::proc_hidepid`]
+    #[must_use]
+    pub const fn proc_hidepid(mut self, hidepid: u8) -> Self {
+        self.config.proc_hidepid = hidepid;
+        self
+    }
+
+    /// See [`SandboxConfig::egress_allow_private`]
+    #[must_use]
+    pub fn egress_allow_private(mut self, networks: impl IntoIterator<Item = Ipv4Net>) -> Self {
+        self.config.egress_allow_private = networks.into_iter().collect();
+        self
+    }
+
+    /// See [`SandboxConfig::strict_paths`]
+    #[must_use]
+    pub const fn strict_paths(mut self, strict: bool) -> Self {
+        self.config.strict_paths = strict;
+        self
+    }
+
+    /// See [`SandboxConfig::allowed_tcp_bind_ports`]
+    #[must_use]
+    pub fn allowed_tcp_bind_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.config.allowed_tcp_bind_ports = ports.into_iter().collect();
+        self
+    }
+
+    /// See [`SandboxConfig::worker_max_rss`]
+    #[must_use]
+    pub const fn worker_max_rss(mut self, limit: ByteSize) -> Self {
+        self.config.worker_max_rss = Some(limit);
+        self
+    }
+
+    /// Use the debug profile, see [`SandboxConfig::debug`]
+    #[must_use]
+    pub const fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    #[must_use]
+    pub fn ro_bind(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.ro_binds.push(path.into());
+        self
+    }
+
+    #[must_use]
+    pub fn rw_bind(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.rw_binds.push(path.into());
+        self
+    }
+
+    #[must_use]
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.env.push((key.into(), value.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> SandboxConfig {
+        self.config
+    }
+}
+
+/// Host zone file for the IANA zone `name`
+///
+/// # Errors
+///
+/// [`LeewardError::InvalidRequest`] unless `name` is a plain zone name (no `.`
+/// components or unusual characters) whose file, after following any links, is
+/// a compiled zone inside [`ZONEINFO_DIR`].
+pub fn zoneinfo_path(name: &str) -> Result<PathBuf> {
+    find_zone(name).map_err(LeewardError::InvalidRequest)
+}
+
+fn find_zone(name: &str) -> std::result::Result<PathBuf, String> {
+    use std::io::Read;
+
+    let plain = |component: &str| {
+        !component.is_empty()
+            && !component.starts_with(['.', '-'])
+            && component
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_-+".contains(c))
+    };
+    if name.len() > 255 || !name.split('/').all(plain) {
+        return Err(format!("invalid timezone name {name:?}"));
+    }
+
+    let dir = Path::new(ZONEINFO_DIR)
+        .canonicalize()
+        .map_err(|e| format!("no zoneinfo at {ZONEINFO_DIR}: {e}"))?;
+    let unknown = || format!("unknown timezone {name:?}");
+    let path = dir.join(name).canonicalize().map_err(|_| unknown())?;
+
+    // Every compiled zone starts with this magic; tables and directories don't
+    let mut magic = [0u8; 4];
+    let is_zone = path.starts_with(&dir)
+        && std::fs::File::open(&path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok_and(|()| &magic == b"TZif");
+    if !is_zone {
+        return Err(unknown());
+    }
+    Ok(path)
+}
+
+/// Find Python executable in PATH
+fn find_python() -> PathBuf {
+    if let Ok(path_var) = std::env::var("PATH") {
+        for dir in path_var.split(':') {
+            for name in &["python3", "python"] {
+                let candidate = PathBuf::from(dir).join(name);
+                if candidate.exists() {
+                    return candidate;
+                }
+            }
+        }
+    }
+    PathBuf::from("python3")
+}
+
+/// Get default socket path from `LEEWARD_SOCKET` env var or system default
+///
+/// Returns:
+/// - `$LEEWARD_SOCKET` if set (for development)
+/// - `/run/leeward/leeward.sock` otherwise (production)
+pub fn default_socket_path() -> PathBuf {
+    std::env::var("LEEWARD_SOCKET").map_or_else(
+        |_| PathBuf::from("/run/leeward/leeward.sock"),
+        PathBuf::from,
+    )
+}
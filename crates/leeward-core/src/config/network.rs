@@ -0,0 +1,149 @@
+//! What network a worker without host networking gets
+//!
+//! [`NetworkConfig::LoopbackOnly`] is the default: the worker's network
+//! namespace has `lo` up and nothing else. [`NetworkConfig::Controlled`]
+//! gives each worker a veth link to the host, which masquerades its
+//! traffic out, so code can make outgoing connections without sharing the
+//! host's interfaces. Networks are written the way `ip` takes them, such
+//! as `10.200.0.0/16`.
+
+#[cfg(feature = "protocol")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Why a network could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NetworkError {
+    #[error("{0:?} is not an IPv4 address followed by /prefix")]
+    Malformed(String),
+    #[error("prefix length {0} is over 32")]
+    Prefix(u8),
+}
+
+/// An IPv4 network, such as `10.200.0.0/16`
+///
+/// Host bits given with the address are cleared, so `10.200.1.1/16` is
+/// `10.200.0.0/16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Net {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Net {
+    /// The network `address` is in, `prefix` bits long
+    ///
+    /// # Errors
+    ///
+    /// [`NetworkError::Prefix`] if `prefix` is over 32.
+    pub fn new(address: Ipv4Addr, prefix: u8) -> Result<Self, NetworkError> {
+        if prefix > 32 {
+            return Err(NetworkError::Prefix(prefix));
+        }
+        Ok(Self {
+            network: Ipv4Addr::from(address.to_bits() & Self::mask(prefix)),
+            prefix,
+        })
+    }
+
+    /// First address of the network
+    #[must_use]
+    pub const fn network(&self) -> Ipv4Addr {
+        self.network
+    }
+
+    #[must_use]
+    pub const fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Number of addresses in the network
+    #[must_use]
+    pub const fn size(&self) -> u64 {
+        1 << (32 - self.prefix)
+    }
+
+    /// Address `offset` into the network, if it is that large
+    #[must_use]
+    pub fn nth(&self, offset: u64) -> Option<Ipv4Addr> {
+        let offset = u32::try_from(offset)
+            .ok()
+            .filter(|&offset| u64::from(offset) < self.size())?;
+        Some(Ipv4Addr::from(self.network.to_bits() + offset))
+    }
+
+    #[must_use]
+    pub const fn contains(&self, address: Ipv4Addr) -> bool {
+        address.to_bits() & Self::mask(self.prefix) == self.network.to_bits()
+    }
+
+    const fn mask(prefix: u8) -> u32 {
+        match prefix {
+            0 => 0,
+            prefix => u32::MAX << (32 - prefix),
+        }
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || NetworkError::Malformed(s.to_owned());
+        let (address, prefix) = s.trim().split_once('/').ok_or_else(malformed)?;
+        let address = address.parse().map_err(|_| malformed())?;
+        // Digits only, so `+8` is not taken for 8
+        if prefix.is_empty() || !prefix.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(malformed());
+        }
+        Self::new(address, prefix.parse().map_err(|_| malformed())?)
+    }
+}
+
+#[cfg(feature = "protocol")]
+impl Serialize for Ipv4Net {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "protocol")]
+impl<'de> Deserialize<'de> for Ipv4Net {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Network a worker gets when [`SandboxConfig::allow_network`] is off
+///
+/// [`SandboxConfig::allow_network`]: super::SandboxConfig::allow_network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "protocol", serde(rename_all = "snake_case"))]
+pub enum NetworkConfig {
+    /// No usable interface at all
+    None,
+    /// `lo` only, so code can reach what it serves on `localhost`
+    #[default]
+    LoopbackOnly,
+    /// `lo` and a veth link to the host, which masquerades traffic out
+    ///
+    /// Each worker's link is a /30 out of `egress_cidr`, picked by worker
+    /// id, so the network bounds how many workers there can be and must
+    /// not be in use on the host. `dns` is the only nameserver in the
+    /// worker's `/etc/resolv.conf`. Workers reach neither each other nor
+    /// the host, nor private networks other than
+    /// [`SandboxConfig::egress_allow_private`](super::SandboxConfig::egress_allow_private).
+    Controlled { egress_cidr: Ipv4Net, dns: Ipv4Addr },
+}
@@ -0,0 +1,486 @@
+//! Resolving user-supplied paths the same way everywhere
+//!
+//! Bind sources, Landlock rules, the workdir and input file names all come
+//! from a config or a request. Each is resolved through
+//! [`CanonicalPath::resolve`] under a [`PathPolicy`] saying whether
+//! symlinks may be followed, whether the path must exist and which roots it
+//! must stay below, so a relative path, a dangling symlink or a symlink
+//! leading somewhere else is caught the same way whichever module uses it.
+//!
+//! Resolution walks the path one component at a time, expanding symlinks
+//! as the kernel would, so an error can name the component that failed and
+//! what it led to. Lexical policies never touch the filesystem, for paths
+//! that only mean something inside the sandbox.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// Most symlinks followed while resolving one path (`MAXSYMLINKS`)
+pub const MAX_SYMLINKS: usize = 40;
+
+/// How a path is resolved and what it may resolve to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPolicy {
+    anchor: Anchor,
+    symlinks: Symlinks,
+    existence: Existence,
+    kind: Option<PathKind>,
+    roots: Vec<PathBuf>,
+}
+
+/// What a path may be relative to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anchor {
+    /// Only absolute paths
+    Absolute,
+    /// Only relative paths, resolved from the current directory
+    Relative,
+    /// Either; relative paths are taken from this absolute base
+    Under(PathBuf),
+}
+
+/// What to do with symlinks along the path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Follow them wherever they lead, as the kernel would
+    Follow,
+    /// Fail on the first one
+    Refuse,
+}
+
+/// Whether the path has to exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Existence {
+    /// Fail if any component is missing
+    Required,
+    /// A missing tail is fine, but not one reached through a symlink
+    Optional,
+    /// Never look at the filesystem; `..` is refused, since only the
+    /// filesystem could say where it leads
+    Lexical,
+}
+
+/// What an existing path must be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathKind {
+    File,
+    Dir,
+}
+
+impl fmt::Display for PathKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::File => "file",
+            Self::Dir => "directory",
+        })
+    }
+}
+
+impl PathPolicy {
+    /// A host path named by the config, such as a bind source
+    ///
+    /// Absolute, symlinks followed, and allowed to be missing unless a
+    /// symlink on the way is dangling.
+    #[must_use]
+    pub const fn host() -> Self {
+        Self {
+            anchor: Anchor::Absolute,
+            symlinks: Symlinks::Follow,
+            existence: Existence::Optional,
+            kind: None,
+            roots: Vec::new(),
+        }
+    }
+
+    /// A path inside the sandbox, such as the workdir, checked as written
+    #[must_use]
+    pub const fn sandbox() -> Self {
+        Self {
+            anchor: Anchor::Absolute,
+            symlinks: Symlinks::Refuse,
+            existence: Existence::Lexical,
+            kind: None,
+            roots: Vec::new(),
+        }
+    }
+
+    /// A name relative to some directory, such as an input file, checked as
+    /// written
+    #[must_use]
+    pub const fn relative_name() -> Self {
+        Self {
+            anchor: Anchor::Relative,
+            symlinks: Symlinks::Refuse,
+            existence: Existence::Lexical,
+            kind: None,
+            roots: Vec::new(),
+        }
+    }
+
+    /// Take relative paths from `base` instead of refusing them
+    #[must_use]
+    pub fn relative_to(mut self, base: impl Into<PathBuf>) -> Self {
+        self.anchor = Anchor::Under(base.into());
+        self
+    }
+
+    /// Fail on any symlink instead of following it
+    #[must_use]
+    pub const fn no_symlinks(mut self) -> Self {
+        self.symlinks = Symlinks::Refuse;
+        self
+    }
+
+    /// Require the path to exist
+    #[must_use]
+    pub const fn existing(mut self) -> Self {
+        self.existence = Existence::Required;
+        self
+    }
+
+    /// Require an existing path to be a directory
+    #[must_use]
+    pub const fn dir(mut self) -> Self {
+        self.kind = Some(PathKind::Dir);
+        self
+    }
+
+    /// Require an existing path to be a file (or device) rather than a
+    /// directory
+    #[must_use]
+    pub const fn file(mut self) -> Self {
+        self.kind = Some(PathKind::File);
+        self
+    }
+
+    /// Require the path to resolve to `root` or below it; with several
+    /// roots, any one will do
+    #[must_use]
+    pub fn within(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn anchor(&self) -> &Anchor {
+        &self.anchor
+    }
+
+    #[must_use]
+    pub const fn symlinks(&self) -> Symlinks {
+        self.symlinks
+    }
+
+    #[must_use]
+    pub const fn existence(&self) -> Existence {
+        self.existence
+    }
+}
+
+/// A path resolved under a [`PathPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalPath {
+    raw: PathBuf,
+    path: PathBuf,
+    exists: bool,
+}
+
+impl CanonicalPath {
+    /// Resolve `raw` under `policy`
+    ///
+    /// # Errors
+    ///
+    /// A [`PathError`] naming the component that broke the policy and, for
+    /// symlinks, where it pointed.
+    pub fn resolve(raw: impl AsRef<Path>, policy: &PathPolicy) -> Result<Self, PathError> {
+        let raw = raw.as_ref();
+        let fail = |kind| PathError {
+            raw: raw.to_path_buf(),
+            kind,
+        };
+
+        if raw.as_os_str().is_empty() {
+            return Err(fail(PathErrorKind::Empty));
+        }
+        if raw.as_os_str().as_bytes().contains(&0) {
+            return Err(fail(PathErrorKind::Nul));
+        }
+        let joined = match &policy.anchor {
+            Anchor::Absolute if raw.is_relative() => return Err(fail(PathErrorKind::NotAbsolute)),
+            Anchor::Relative if raw.is_absolute() => return Err(fail(PathErrorKind::NotRelative)),
+            Anchor::Under(base) if raw.is_relative() => base.join(raw),
+            _ => raw.to_path_buf(),
+        };
+
+        let (path, exists) = if policy.existence == Existence::Lexical {
+            (lexical(&joined).map_err(fail)?, false)
+        } else {
+            let joined = if joined.is_relative() {
+                std::env::current_dir()
+                    .map_err(|e| fail(PathErrorKind::io(Path::new("."), &e)))?
+                    .join(joined)
+            } else {
+                joined
+            };
+            walk(&joined, policy).map_err(fail)?
+        };
+
+        if let (true, Some(kind)) = (exists, policy.kind) {
+            let is_dir = std::fs::metadata(&path)
+                .map_err(|e| fail(PathErrorKind::io(&path, &e)))?
+                .is_dir();
+            if is_dir != (kind == PathKind::Dir) {
+                return Err(fail(PathErrorKind::WrongKind {
+                    resolved: path,
+                    expected: kind,
+                }));
+            }
+        }
+
+        if !policy.roots.is_empty() {
+            let roots: Vec<PathBuf> = policy
+                .roots
+                .iter()
+                .map(|root| match policy.existence {
+                    Existence::Lexical => lexical(root).unwrap_or_else(|_| root.clone()),
+                    _ => root.canonicalize().unwrap_or_else(|_| root.clone()),
+                })
+                .collect();
+            if !roots.iter().any(|root| path.starts_with(root)) {
+                return Err(fail(PathErrorKind::OutsideRoots {
+                    resolved: path,
+                    roots,
+                }));
+            }
+        }
+
+        Ok(Self {
+            raw: raw.to_path_buf(),
+            path,
+            exists,
+        })
+    }
+
+    /// The path as it was given
+    #[must_use]
+    pub fn raw(&self) -> &Path {
+        &self.raw
+    }
+
+    /// The resolved path, free of symlinks, `.` and `..` (lexical policies
+    /// only drop `.`)
+    #[must_use]
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether it was found; always false under a lexical policy
+    #[must_use]
+    pub const fn exists(&self) -> bool {
+        self.exists
+    }
+
+    #[must_use]
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl AsRef<Path> for CanonicalPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A path that broke its [`PathPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("path {}: {kind}", .raw.display())]
+pub struct PathError {
+    /// The path as it was given
+    pub raw: PathBuf,
+    pub kind: PathErrorKind,
+}
+
+/// What was wrong with a path
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PathErrorKind {
+    #[error("empty path")]
+    Empty,
+
+    #[error("contains a NUL byte")]
+    Nul,
+
+    #[error("must be absolute")]
+    NotAbsolute,
+
+    #[error("must be relative")]
+    NotRelative,
+
+    #[error("has a `..` component, which is not resolved here")]
+    ParentDir,
+
+    #[error("{} does not exist", .component.display())]
+    Missing { component: PathBuf },
+
+    #[error("{} is a symlink to {}", .link.display(), .target.display())]
+    Symlink { link: PathBuf, target: PathBuf },
+
+    #[error("{} is a dangling symlink to {}", .link.display(), .target.display())]
+    Dangling { link: PathBuf, target: PathBuf },
+
+    #[error("more than {MAX_SYMLINKS} symlinks, the last at {}", .link.display())]
+    TooManySymlinks { link: PathBuf },
+
+    #[error("{} is not a directory", .component.display())]
+    NotADirectory { component: PathBuf },
+
+    #[error("resolves to {}, which is not a {expected}", .resolved.display())]
+    WrongKind {
+        resolved: PathBuf,
+        expected: PathKind,
+    },
+
+    #[error("resolves to {}, outside {}", .resolved.display(), display_all(.roots))]
+    OutsideRoots {
+        resolved: PathBuf,
+        roots: Vec<PathBuf>,
+    },
+
+    #[error("cannot inspect {}: {message}", .component.display())]
+    Io {
+        component: PathBuf,
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+}
+
+impl PathErrorKind {
+    fn io(component: &Path, error: &std::io::Error) -> Self {
+        Self::Io {
+            component: component.to_path_buf(),
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+fn display_all(paths: &[PathBuf]) -> String {
+    let paths: Vec<_> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    paths.join(", ")
+}
+
+/// `path` with `.` dropped, refusing `..`
+fn lexical(path: &Path) -> Result<PathBuf, PathErrorKind> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => return Err(PathErrorKind::ParentDir),
+            Component::CurDir => {}
+            other => normal.push(other),
+        }
+    }
+    Ok(normal)
+}
+
+/// A symlink being expanded: the stack depth its target starts above
+struct Expansion {
+    base: usize,
+    link: PathBuf,
+    target: PathBuf,
+}
+
+/// Resolve the absolute `path` one component at a time; returns the
+/// resolved path and whether it exists
+fn walk(path: &Path, policy: &PathPolicy) -> Result<(PathBuf, bool), PathErrorKind> {
+    // Components still to resolve, the next one last
+    let mut pending: Vec<OsString> = Vec::new();
+    push_components(&mut pending, path);
+
+    let mut resolved = PathBuf::from("/");
+    let mut expanding: Vec<Expansion> = Vec::new();
+    let mut followed = 0;
+    let mut missing: Option<PathBuf> = None;
+
+    while let Some(component) = pending.pop() {
+        // Symlinks whose whole target has been used up
+        while expanding.last().is_some_and(|e| e.base > pending.len()) {
+            expanding.pop();
+        }
+
+        if component == ".." {
+            if let Some(missing) = missing {
+                return Err(PathErrorKind::Missing { component: missing });
+            }
+            resolved.pop();
+            continue;
+        }
+
+        let next = resolved.join(&component);
+        if missing.is_some() {
+            resolved = next;
+            continue;
+        }
+
+        match std::fs::symlink_metadata(&next) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = std::fs::read_link(&next).map_err(|e| PathErrorKind::io(&next, &e))?;
+                if policy.symlinks == Symlinks::Refuse {
+                    return Err(PathErrorKind::Symlink { link: next, target });
+                }
+                followed += 1;
+                if followed > MAX_SYMLINKS {
+                    return Err(PathErrorKind::TooManySymlinks { link: next });
+                }
+                if target.is_absolute() {
+                    resolved = PathBuf::from("/");
+                }
+                expanding.push(Expansion {
+                    base: pending.len(),
+                    link: next,
+                    target: target.clone(),
+                });
+                push_components(&mut pending, &target);
+            }
+            Ok(metadata) => {
+                if !metadata.is_dir() && !pending.is_empty() {
+                    return Err(PathErrorKind::NotADirectory { component: next });
+                }
+                resolved = next;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Reported against the link the caller named, not one it led to
+                if let Some(outer) = expanding.first() {
+                    return Err(PathErrorKind::Dangling {
+                        link: outer.link.clone(),
+                        target: outer.target.clone(),
+                    });
+                }
+                if policy.existence == Existence::Required {
+                    return Err(PathErrorKind::Missing { component: next });
+                }
+                missing = Some(next.clone());
+                resolved = next;
+            }
+            Err(e) => return Err(PathErrorKind::io(&next, &e)),
+        }
+    }
+
+    Ok((resolved, missing.is_none()))
+}
+
+/// Push the components of `path` so the first is popped first
+fn push_components(pending: &mut Vec<OsString>, path: &Path) {
+    let start = pending.len();
+    pending.extend(path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_os_string()),
+        Component::ParentDir => Some("..".into()),
+        Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+    }));
+    pending[start..].reverse();
+}
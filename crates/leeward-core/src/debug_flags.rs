@@ -0,0 +1,106 @@
+//! Named debug toggles for targeted verbosity
+//!
+//! Each [`DebugFlag`] turns on extra log lines at one hot point, logged at
+//! `info` so they get through the usual filter, instead of raising the whole
+//! process to `trace`. Checking a flag is one relaxed atomic load.
+//!
+//! Flags are per process: a worker forked while a flag is on keeps its own
+//! copy, so only the daemon's side of each hot point is covered.
+
+#[cfg(feature = "protocol")]
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Flags turned on, one bit each
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+
+/// A hot point whose log lines can be turned on by itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "protocol",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum DebugFlag {
+    /// Every frame sent to or received from a worker, with its length
+    PipeFrames,
+    /// Each execution queued for a worker and dispatched to one
+    Scheduler,
+    /// Each syscall a seccomp supervisor refuses, as it is refused
+    SeccompDenials,
+    /// Each worker pipe left full past the config's stall threshold
+    PipeStalls,
+}
+
+impl DebugFlag {
+    /// Every flag
+    pub const ALL: [Self; 4] = [
+        Self::PipeFrames,
+        Self::Scheduler,
+        Self::SeccompDenials,
+        Self::PipeStalls,
+    ];
+
+    /// Name on the wire and on the command line, such as `pipe-frames`
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::PipeFrames => "pipe-frames",
+            Self::Scheduler => "scheduler",
+            Self::SeccompDenials => "seccomp-denials",
+            Self::PipeStalls => "pipe-stalls",
+        }
+    }
+
+    /// Whether the flag is on in this process
+    #[inline]
+    #[must_use]
+    pub fn enabled(self) -> bool {
+        ENABLED.load(Ordering::Relaxed) & self.bit() != 0
+    }
+
+    /// Turn the flag on or off in this process
+    pub fn set(self, enabled: bool) {
+        if enabled {
+            ENABLED.fetch_or(self.bit(), Ordering::Relaxed);
+        } else {
+            ENABLED.fetch_and(!self.bit(), Ordering::Relaxed);
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl std::fmt::Display for DebugFlag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for DebugFlag {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|flag| flag.name() == name)
+            .ok_or_else(|| {
+                let known: Vec<_> = Self::ALL.iter().map(|flag| flag.name()).collect();
+                format!(
+                    "unknown debug flag '{name}'; known flags are {}",
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Flags on in this process, in [`DebugFlag::ALL`] order
+#[must_use]
+pub fn enabled() -> Vec<DebugFlag> {
+    DebugFlag::ALL
+        .into_iter()
+        .filter(|flag| flag.enabled())
+        .collect()
+}
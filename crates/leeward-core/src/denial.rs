@@ -0,0 +1,198 @@
+//! Per-execution record of what the sandbox refused
+//!
+//! Whoever refuses an operation on the code's behalf (the seccomp notify
+//! supervisor, or an embedder answering a worker's notifications) records
+//! it in the worker's shared [`DenialLog`], and the worker attaches it to
+//! the execution's [`ExecutionResult::denials`](crate::ExecutionResult::denials).
+//!
+//! The log is bounded against code that provokes denials in a loop. Only the
+//! first [`MAX_DESCRIBED`] denials are looked at closely enough to say what
+//! was refused, and at most [`MAX_DENIALS`] distinct operations are listed.
+//! Past either bound a denial only counts against its syscall. An execution
+//! without denials costs one atomic swap.
+
+use crate::result::{Denial, DenialLayer};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Distinct operations listed per execution
+pub const MAX_DENIALS: usize = 32;
+
+/// Denials per execution that are described; later ones are only counted
+pub const MAX_DESCRIBED: u64 = 1024;
+
+/// Denials of one execution, shared between the worker handle and whoever
+/// refuses syscalls for it
+///
+/// Call [`DenialLog::begin`] before each execution and
+/// [`DenialLog::take`] after it. [`DenialLog::totals`] keeps counting
+/// across executions.
+#[derive(Debug, Default)]
+pub struct DenialLog {
+    /// Set by the first denial of the current execution
+    any: AtomicBool,
+    state: Mutex<State>,
+    /// Denials per syscall since the log was created
+    totals: Mutex<BTreeMap<String, u64>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Denials described so far
+    described: u64,
+    /// Distinct operations, in the order first refused
+    listed: Vec<Denial>,
+    /// One `syscall(...)` counter per syscall past the bounds
+    overflow: Vec<Denial>,
+}
+
+impl DenialLog {
+    /// Forget what an earlier execution left behind
+    pub fn begin(&self) {
+        if self.any.swap(false, Ordering::SeqCst) {
+            *self.lock() = State::default();
+        }
+    }
+
+    /// Record that `layer` refused `syscall`
+    ///
+    /// `describe` names the operation, e.g. `connect(1.2.3.4:443)`. It is
+    /// only called within [`MAX_DESCRIBED`], so it may read the process's
+    /// memory.
+    pub fn record(&self, layer: DenialLayer, syscall: &str, describe: impl FnOnce() -> String) {
+        self.any.store(true, Ordering::SeqCst);
+        *lock(&self.totals).entry(syscall.to_string()).or_default() += 1;
+        let mut state = self.lock();
+
+        if state.described < MAX_DESCRIBED {
+            state.described += 1;
+            let what = describe();
+            if let Some(denial) = state
+                .listed
+                .iter_mut()
+                .find(|denial| denial.layer == layer && denial.what == what)
+            {
+                denial.count += 1;
+                return;
+            }
+            if state.listed.len() < MAX_DENIALS {
+                state.listed.push(Denial {
+                    layer,
+                    what,
+                    count: 1,
+                });
+                return;
+            }
+        }
+
+        let counter = state.overflow.iter_mut().find(|denial| {
+            denial.layer == layer && denial.what.strip_suffix("(...)") == Some(syscall)
+        });
+        match counter {
+            Some(denial) => denial.count += 1,
+            None => state.overflow.push(Denial {
+                layer,
+                what: format!("{syscall}(...)"),
+                count: 1,
+            }),
+        }
+    }
+
+    /// The denials since [`DenialLog::begin`], listed operations first
+    #[must_use]
+    pub fn take(&self) -> Vec<Denial> {
+        if !self.any.swap(false, Ordering::SeqCst) {
+            return Vec::new();
+        }
+        let State {
+            listed, overflow, ..
+        } = std::mem::take(&mut *self.lock());
+        let mut denials = listed;
+        denials.extend(overflow);
+        denials
+    }
+
+    /// Denials per syscall over every execution so far, by syscall name
+    #[must_use]
+    pub fn totals(&self) -> BTreeMap<String, u64> {
+        lock(&self.totals).clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// One line listing `denials`, e.g. `connect(1.2.3.4:443) x3, socket(AF_INET6, SOCK_DGRAM)`
+#[must_use]
+pub fn summary(denials: &[Denial]) -> String {
+    denials
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Generate [`syscall_name`]'s lookup from `SYS_*` constant names
+macro_rules! syscall_names {
+    ($nr:expr; $($name:ident),* $(,)?) => {
+        match $nr {
+            $(libc::$name => Some(&stringify!($name)[4..]),)*
+            _ => None,
+        }
+    };
+}
+
+/// Name of syscall `nr` on this architecture, for the common ones
+#[must_use]
+pub fn syscall_name(nr: i64) -> Option<&'static str> {
+    #[cfg(target_arch = "x86_64")]
+    if let Some(name) = syscall_names!(nr;
+        SYS_open, SYS_stat, SYS_lstat, SYS_poll, SYS_access, SYS_pipe, SYS_select,
+        SYS_dup2, SYS_alarm, SYS_fork, SYS_vfork, SYS_rename, SYS_mkdir, SYS_rmdir,
+        SYS_creat, SYS_link, SYS_unlink, SYS_symlink, SYS_readlink, SYS_chmod,
+        SYS_chown, SYS_lchown, SYS_getdents, SYS_epoll_wait, SYS_epoll_create,
+        SYS_arch_prctl, SYS_time, SYS_getpgrp, SYS_inotify_init, SYS_eventfd,
+        SYS_signalfd, SYS_utimes, SYS_getitimer, SYS_setitimer,
+    ) {
+        return Some(name);
+    }
+
+    syscall_names!(nr;
+        SYS_read, SYS_write, SYS_close, SYS_fstat, SYS_lseek, SYS_mmap, SYS_mprotect,
+        SYS_munmap, SYS_brk, SYS_rt_sigaction, SYS_rt_sigprocmask, SYS_rt_sigreturn,
+        SYS_rt_sigsuspend, SYS_rt_sigtimedwait, SYS_ioctl, SYS_pread64, SYS_pwrite64,
+        SYS_readv, SYS_writev, SYS_sched_yield, SYS_mremap, SYS_msync, SYS_mincore,
+        SYS_madvise, SYS_dup, SYS_dup3, SYS_nanosleep, SYS_getpid, SYS_sendfile,
+        SYS_socket, SYS_connect, SYS_accept, SYS_accept4, SYS_sendto, SYS_recvfrom,
+        SYS_sendmsg, SYS_recvmsg, SYS_shutdown, SYS_bind, SYS_listen, SYS_getsockname,
+        SYS_getpeername, SYS_socketpair, SYS_setsockopt, SYS_getsockopt, SYS_clone,
+        SYS_clone3, SYS_execve, SYS_execveat, SYS_exit, SYS_exit_group, SYS_wait4,
+        SYS_waitid, SYS_kill, SYS_tgkill, SYS_uname, SYS_fcntl, SYS_flock, SYS_fsync,
+        SYS_fdatasync, SYS_truncate, SYS_ftruncate, SYS_getcwd, SYS_chdir, SYS_fchdir,
+        SYS_fchmod, SYS_fchown, SYS_umask, SYS_gettimeofday, SYS_getrlimit,
+        SYS_setrlimit, SYS_prlimit64, SYS_getrusage, SYS_sysinfo, SYS_getuid,
+        SYS_getgid, SYS_setuid, SYS_setgid, SYS_geteuid, SYS_getegid, SYS_setpgid,
+        SYS_getppid, SYS_setsid, SYS_getgroups, SYS_sigaltstack, SYS_statfs,
+        SYS_fstatfs, SYS_getpriority, SYS_setpriority, SYS_sched_getaffinity,
+        SYS_sched_setaffinity, SYS_sched_getscheduler, SYS_sched_setscheduler,
+        SYS_sched_getparam, SYS_prctl, SYS_capget, SYS_gettid, SYS_futex,
+        SYS_set_tid_address, SYS_set_robust_list, SYS_get_robust_list,
+        SYS_restart_syscall, SYS_epoll_create1, SYS_epoll_ctl, SYS_epoll_pwait,
+        SYS_timerfd_create, SYS_eventfd2, SYS_pipe2, SYS_ppoll, SYS_pselect6,
+        SYS_openat, SYS_openat2, SYS_mkdirat, SYS_unlinkat, SYS_renameat,
+        SYS_renameat2, SYS_linkat, SYS_symlinkat, SYS_readlinkat, SYS_newfstatat,
+        SYS_faccessat, SYS_faccessat2, SYS_getdents64, SYS_utimensat, SYS_fchmodat,
+        SYS_fchownat, SYS_statx, SYS_clock_gettime, SYS_clock_getres,
+        SYS_clock_nanosleep, SYS_getrandom, SYS_memfd_create, SYS_membarrier,
+        SYS_copy_file_range, SYS_splice, SYS_mlock, SYS_munlock, SYS_rseq,
+        SYS_pidfd_open, SYS_close_range, SYS_seccomp,
+    )
+}
@@ -1,5 +1,6 @@
 //! Error types for leeward-core
 
+use crate::isolation::fatal::WorkerDeath;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,15 +8,26 @@ pub enum LeewardError {
     #[error("namespace error: {0}")]
     Namespace(String),
 
+    #[cfg(feature = "seccomp")]
     #[error("seccomp error: {0}")]
     Seccomp(String),
 
+    #[cfg(feature = "landlock")]
     #[error("landlock error: {0}")]
     Landlock(String),
 
     #[error("mount error: {0}")]
     Mount(String),
 
+    #[cfg(feature = "cgroups")]
+    #[error("cgroup error: {0}")]
+    Cgroup(String),
+
+    /// No cgroup v2 hierarchy where one was expected
+    #[cfg(feature = "cgroups")]
+    #[error("cgroup v2 unavailable: {0}")]
+    CgroupUnavailable(String),
+
     #[error("execution error: {0}")]
     Execution(String),
 
@@ -33,4 +45,93 @@ pub enum LeewardError {
 
     #[error("configuration error: {0}")]
     Config(String),
+
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("worker died during {0}")]
+    WorkerDied(WorkerDeath),
+
+    /// The daemon refused a request, or the client refused it before
+    /// sending it as the daemon would have
+    #[cfg(feature = "protocol")]
+    #[error("{message}")]
+    Refused {
+        kind: crate::protocol::ErrorKind,
+        message: String,
+    },
+
+    /// A message could not be encoded for, or decoded from, the wire
+    #[cfg(feature = "protocol")]
+    #[error("failed to {direction} message: {source}")]
+    Protocol {
+        direction: Direction,
+        #[source]
+        source: CodecError,
+    },
+}
+
+/// Which way a message failed to cross the wire
+#[cfg(feature = "protocol")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Encode,
+    Decode,
+}
+
+#[cfg(feature = "protocol")]
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Encode => "encode",
+            Self::Decode => "decode",
+        })
+    }
+}
+
+/// The codec error behind [`LeewardError::Protocol`]
+#[cfg(feature = "protocol")]
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error(transparent)]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+
+    #[error(transparent)]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(feature = "protocol")]
+impl From<rmp_serde::encode::Error> for LeewardError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Self::Protocol {
+            direction: Direction::Encode,
+            source: e.into(),
+        }
+    }
+}
+
+#[cfg(feature = "protocol")]
+impl From<rmp_serde::decode::Error> for LeewardError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        Self::Protocol {
+            direction: Direction::Decode,
+            source: e.into(),
+        }
+    }
+}
+
+impl LeewardError {
+    /// OS error behind this error, if it kept one
+    #[must_use]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Self::Io(e) => e.raw_os_error(),
+            Self::Nix(errno) => Some(*errno as i32),
+            Self::WorkerDied(death) => (death.errno != 0).then_some(death.errno),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,462 @@
+//! Sandbox escape probes
+//!
+//! Each [`EscapeProbe`] is a short Python program that tries one known way
+//! out of the sandbox and prints a verdict line saying how far it got. A
+//! probe that is stopped reports the errno it hit, so a test can tell which
+//! layer did the stopping (Landlock answers `EACCES`, a missing path
+//! `ENOENT`, a namespace boundary `ESRCH`) and notice when that changes.
+//!
+//! [`SandboxProbe`] runs probes through any function that executes code in
+//! a sandbox, which lets the same list back the regression tests and
+//! `leeward doctor --self-test` against a running daemon.
+
+use crate::{ExecutionResult, OutcomeCode, Result};
+use nix::errno::Errno;
+use std::fmt::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the line a probe prints with its verdict
+const VERDICT_PREFIX: &str = "LEEWARD-PROBE ";
+
+/// Placeholder in probe scripts for the socket to reach back to
+const SOCKET_PLACEHOLDER: &str = "@SOCKET_HEX@";
+
+/// Helpers shared by every probe script
+///
+/// `call` runs one step of the attack and reports the first error as the
+/// denial; reaching the end of the script means the step list succeeded.
+const PRELUDE: &str = r#"
+import ctypes, errno, os, sys
+
+libc = ctypes.CDLL(None, use_errno=True)
+
+def report(verdict, detail):
+    print("LEEWARD-PROBE", verdict, detail, flush=True)
+    os._exit(0)
+
+def denied(err):
+    report("denied", err)
+
+def escaped(detail):
+    report("escaped", detail)
+
+def unsupported(reason):
+    report("unsupported", reason)
+
+def call(fn, *args):
+    try:
+        return fn(*args)
+    except OSError as e:
+        denied(e.errno)
+
+def check(ret):
+    if ret == -1:
+        denied(ctypes.get_errno())
+    return ret
+"#;
+
+/// Kernel features a probe depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelFeature {
+    /// Landlock, compiled in and enabled in the kernel
+    Landlock,
+    /// User namespaces
+    UserNamespaces,
+    /// Seccomp filters, compiled in and enabled in the kernel
+    Seccomp,
+}
+
+impl KernelFeature {
+    /// Every feature, for reporting
+    pub const ALL: [Self; 3] = [Self::Landlock, Self::UserNamespaces, Self::Seccomp];
+
+    /// Whether this host (and this build, for Landlock and seccomp) has the
+    /// feature
+    #[must_use]
+    pub fn available(self) -> bool {
+        match self {
+            Self::Landlock => cfg!(feature = "landlock") && landlock_abi().is_some(),
+            Self::UserNamespaces => {
+                Path::new("/proc/self/ns/user").exists()
+                    && std::fs::read_to_string("/proc/sys/user/max_user_namespaces")
+                        .ok()
+                        .and_then(|max| max.trim().parse::<u64>().ok())
+                        .is_some_and(|max| max > 0)
+            }
+            Self::Seccomp => {
+                cfg!(feature = "seccomp")
+                    && std::fs::read_to_string("/proc/self/status")
+                        .is_ok_and(|status| status.lines().any(|line| line.starts_with("Seccomp:")))
+            }
+        }
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Landlock => "landlock",
+            Self::UserNamespaces => "user namespaces",
+            Self::Seccomp => "seccomp",
+        }
+    }
+}
+
+/// Yama's `kernel.yama.ptrace_scope`, or None if Yama is not enabled
+///
+/// 0 lets a process attach to any other of its user, 1 only to its
+/// descendants, 2 only with `CAP_SYS_PTRACE`, and 3 not at all.
+#[must_use]
+pub fn yama_ptrace_scope() -> Option<u8> {
+    std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()
+        .and_then(|scope| scope.trim().parse().ok())
+}
+
+/// Landlock ABI version the kernel supports, if any
+pub(crate) fn landlock_abi() -> Option<i64> {
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+
+    // SAFETY: Querying the ABI version takes no ruleset and creates no fd
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    (version > 0).then_some(version)
+}
+
+/// How an escape attempt was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// A step failed with this errno
+    Errno(Errno),
+    /// The interpreter was killed before it could report
+    Killed,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Errno(errno) => write!(f, "{errno:?}"),
+            Self::Killed => f.write_str("killed"),
+        }
+    }
+}
+
+/// One known escape technique
+#[derive(Debug)]
+pub struct EscapeProbe {
+    /// Short identifier, also the name of its regression test
+    pub name: &'static str,
+    /// What the probe attempts
+    pub description: &'static str,
+    /// The denial a correctly configured sandbox produces
+    pub expected: Denial,
+    /// Layer expected to produce it
+    pub layer: &'static str,
+    /// Kernel features the technique (or its expected denial) needs
+    pub requires: &'static [KernelFeature],
+    body: &'static str,
+}
+
+impl EscapeProbe {
+    /// Python source for this probe
+    ///
+    /// `socket` is the path the reach-back probe tries to connect to; the
+    /// other probes ignore it.
+    #[must_use]
+    pub fn script(&self, socket: &Path) -> String {
+        let hex = socket
+            .as_os_str()
+            .as_bytes()
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            });
+        format!("{PRELUDE}{}", self.body.replace(SOCKET_PLACEHOLDER, &hex))
+    }
+
+    /// First required feature this host lacks
+    #[must_use]
+    pub fn missing_feature(&self) -> Option<KernelFeature> {
+        self.requires
+            .iter()
+            .copied()
+            .find(|feature| !feature.available())
+    }
+}
+
+/// Every probe, in the order `leeward doctor --self-test` runs them
+pub const PROBES: &[EscapeProbe] = &[
+    EscapeProbe {
+        name: "proc_self_mem",
+        description: "write to its own memory through /proc/self/mem",
+        expected: Denial::Errno(Errno::EACCES),
+        layer: "landlock",
+        requires: &[KernelFeature::Landlock],
+        body: r#"
+buf = ctypes.create_string_buffer(b"original")
+fd = call(os.open, "/proc/self/mem", os.O_RDWR)
+call(os.lseek, fd, ctypes.addressof(buf), os.SEEK_SET)
+call(os.write, fd, b"modified")
+escaped("wrote to its own memory through /proc/self/mem")
+"#,
+    },
+    EscapeProbe {
+        name: "chroot_escape",
+        description: "chroot, chdir out of the new root, and read /etc/passwd",
+        expected: Denial::Errno(Errno::EACCES),
+        layer: "landlock",
+        requires: &[KernelFeature::Landlock],
+        body: r#"
+import tempfile
+jail = call(tempfile.mkdtemp)
+call(os.chroot, jail)
+for _ in range(64):
+    call(os.chdir, "..")
+call(os.chroot, ".")
+call(os.open, "/etc/passwd", os.O_RDONLY)
+escaped("read /etc/passwd after leaving a chroot")
+"#,
+    },
+    EscapeProbe {
+        name: "daemon_socket",
+        description: "connect back to the daemon's unix socket",
+        expected: Denial::Errno(Errno::ENOENT),
+        layer: "root template",
+        requires: &[],
+        body: r#"
+import socket
+path = bytes.fromhex("@SOCKET_HEX@")
+sock = call(socket.socket, socket.AF_UNIX, socket.SOCK_STREAM)
+call(sock.connect, path)
+escaped("connected to the daemon socket")
+"#,
+    },
+    EscapeProbe {
+        name: "sysrq_trigger",
+        description: "open /proc/sysrq-trigger for writing",
+        expected: Denial::Errno(Errno::EACCES),
+        layer: "landlock",
+        requires: &[KernelFeature::Landlock],
+        body: r#"
+call(os.open, "/proc/sysrq-trigger", os.O_WRONLY)
+escaped("opened /proc/sysrq-trigger for writing (nothing written)")
+"#,
+    },
+    EscapeProbe {
+        name: "userns_mount",
+        description: "regain capabilities in a new user namespace and mount",
+        expected: Denial::Errno(Errno::EPERM),
+        layer: "landlock",
+        requires: &[KernelFeature::UserNamespaces, KernelFeature::Landlock],
+        body: r#"
+CLONE_NEWUSER = 0x10000000
+CLONE_NEWNS = 0x00020000
+check(libc.unshare(CLONE_NEWUSER | CLONE_NEWNS))
+check(libc.mount(b"none", b"/tmp", b"tmpfs", 0, None))
+escaped("mounted a tmpfs from a new user namespace")
+"#,
+    },
+    EscapeProbe {
+        name: "setns_host",
+        description: "join the host's mount namespace through /proc/1/ns",
+        expected: Denial::Errno(Errno::EACCES),
+        layer: "landlock",
+        requires: &[KernelFeature::Landlock],
+        body: r#"
+fd = call(os.open, "/proc/1/ns/mnt", os.O_RDONLY)
+check(libc.setns(fd, 0))
+escaped("joined the mount namespace of /proc/1")
+"#,
+    },
+    EscapeProbe {
+        name: "memfd_exec",
+        description: "copy the interpreter into a memfd and execute it",
+        expected: Denial::Errno(Errno::EACCES),
+        layer: "landlock",
+        requires: &[KernelFeature::Landlock],
+        body: r#"
+if not hasattr(os, "memfd_create") or os.execve not in os.supports_fd:
+    unsupported("interpreter cannot exec a memfd")
+with open(sys.executable, "rb") as f:
+    image = f.read()
+fd = call(os.memfd_create, "probe", 0)
+call(os.write, fd, image)
+code = "print('LEEWARD-PROBE escaped executed a memfd', flush=True)"
+call(os.execve, fd, [sys.executable, "-c", code], {})
+"#,
+    },
+    EscapeProbe {
+        name: "ptrace_worker",
+        description: "attach to the worker that launched the interpreter",
+        expected: Denial::Errno(Errno::EPERM),
+        layer: "seccomp",
+        requires: &[KernelFeature::Seccomp],
+        body: r#"
+PTRACE_ATTACH = 16
+PTRACE_DETACH = 17
+worker = ctypes.c_long(os.getppid())
+check(libc.ptrace(ctypes.c_long(PTRACE_ATTACH), worker, None, None))
+os.waitpid(worker.value, 0x40000000)  # __WALL
+libc.ptrace(ctypes.c_long(PTRACE_DETACH), worker, None, None)
+escaped("attached to the worker with ptrace")
+"#,
+    },
+    EscapeProbe {
+        name: "ptrace_sibling",
+        description: "attach to another process of the same execution",
+        expected: Denial::Errno(Errno::EPERM),
+        layer: "seccomp",
+        requires: &[KernelFeature::Seccomp],
+        // The child is killed before reporting, so the execution does not wait on it
+        body: r#"
+import signal
+PTRACE_ATTACH = 16
+PTRACE_DETACH = 17
+child = os.fork()
+if child == 0:
+    signal.pause()
+    os._exit(0)
+ret = libc.ptrace(ctypes.c_long(PTRACE_ATTACH), ctypes.c_long(child), None, None)
+err = ctypes.get_errno()
+if ret == 0:
+    os.waitpid(child, 0)
+    libc.ptrace(ctypes.c_long(PTRACE_DETACH), ctypes.c_long(child), None, None)
+os.kill(child, signal.SIGKILL)
+os.waitpid(child, 0)
+if ret == -1:
+    denied(err)
+escaped("attached to a sibling process with ptrace")
+"#,
+    },
+];
+
+/// Look up a probe by name
+#[must_use]
+pub fn probe(name: &str) -> Option<&'static EscapeProbe> {
+    PROBES.iter().find(|probe| probe.name == name)
+}
+
+/// What happened when a probe ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The attempt was stopped
+    Denied(Denial),
+    /// The attempt worked
+    Escaped(String),
+    /// The probe could not run here
+    Skipped(String),
+    /// The probe ran but gave no verdict
+    Inconclusive(String),
+}
+
+impl Verdict {
+    /// Read the verdict a probe reported in `result`
+    #[must_use]
+    pub fn from_result(result: &ExecutionResult) -> Self {
+        let stdout = result.stdout_str();
+        let reported = stdout
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix(VERDICT_PREFIX))
+            .map(|line| line.split_once(' ').unwrap_or((line, "")));
+
+        match reported {
+            Some(("denied", errno)) => errno.parse().map_or_else(
+                |_| Self::Inconclusive(format!("bad errno in verdict: {errno}")),
+                |errno| Self::Denied(Denial::Errno(Errno::from_raw(errno))),
+            ),
+            Some(("escaped", detail)) => Self::Escaped(detail.to_string()),
+            Some(("unsupported", reason)) => Self::Skipped(reason.to_string()),
+            Some((verdict, _)) => Self::Inconclusive(format!("unknown verdict: {verdict}")),
+            None if result.outcome() == OutcomeCode::Killed => Self::Denied(Denial::Killed),
+            None => Self::Inconclusive(format!(
+                "no verdict ({}): {}",
+                result.outcome(),
+                result.stderr_str().trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Denied(denial) => write!(f, "contained ({denial})"),
+            Self::Escaped(detail) => write!(f, "ESCAPED: {detail}"),
+            Self::Skipped(reason) => write!(f, "skipped: {reason}"),
+            Self::Inconclusive(reason) => write!(f, "inconclusive: {reason}"),
+        }
+    }
+}
+
+/// Outcome of one probe
+#[derive(Debug, Clone)]
+pub struct ProbeReport {
+    pub probe: &'static EscapeProbe,
+    pub verdict: Verdict,
+}
+
+impl ProbeReport {
+    /// Whether the probe was stopped by the expected mechanism
+    #[must_use]
+    pub fn as_expected(&self) -> bool {
+        self.verdict == Verdict::Denied(self.probe.expected)
+    }
+
+    /// Whether the probe was stopped at all
+    #[must_use]
+    pub const fn contained(&self) -> bool {
+        matches!(self.verdict, Verdict::Denied(_))
+    }
+}
+
+/// Runs escape probes through a function that executes code in a sandbox
+pub struct SandboxProbe<F> {
+    run: F,
+    socket: PathBuf,
+}
+
+impl<F> SandboxProbe<F>
+where
+    F: FnMut(&str) -> Result<ExecutionResult>,
+{
+    /// Probe the sandbox `run` executes code in
+    #[must_use]
+    pub fn new(run: F) -> Self {
+        Self {
+            run,
+            socket: crate::config::default_socket_path(),
+        }
+    }
+
+    /// Socket the reach-back probe targets (the default daemon socket otherwise)
+    #[must_use]
+    pub fn socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket = path.into();
+        self
+    }
+
+    /// Run one probe, skipping it if the host lacks a feature it needs
+    pub fn run(&mut self, probe: &'static EscapeProbe) -> ProbeReport {
+        let verdict = match probe.missing_feature() {
+            Some(feature) => Verdict::Skipped(format!("{} unavailable", feature.name())),
+            None => match (self.run)(&probe.script(&self.socket)) {
+                Ok(result) => Verdict::from_result(&result),
+                Err(e) => Verdict::Inconclusive(e.to_string()),
+            },
+        };
+        ProbeReport { probe, verdict }
+    }
+
+    /// Run every probe in [`PROBES`]
+    pub fn run_all(&mut self) -> Vec<ProbeReport> {
+        PROBES.iter().map(|probe| self.run(probe)).collect()
+    }
+}
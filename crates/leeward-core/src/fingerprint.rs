@@ -0,0 +1,58 @@
+//! Stable fingerprints of configuration values
+//!
+//! A fingerprint is the SHA-256 of a canonical JSON rendering of the value,
+//! with object keys sorted. It depends only on field names and values, so it
+//! survives restarts and reordering of struct fields.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// Hex-encoded fingerprint of any serializable value
+#[must_use]
+pub fn fingerprint<T: Serialize + ?Sized>(value: &T) -> String {
+    let mut canonical = String::new();
+    // Serializing plain config data into a `Value` cannot fail
+    if let Ok(value) = serde_json::to_value(value) {
+        write_canonical(&value, &mut canonical);
+    }
+
+    Sha256::digest(canonical.as_bytes())
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Render JSON with object keys in sorted order
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
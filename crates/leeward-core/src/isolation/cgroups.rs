@@ -0,0 +1,776 @@
+//! cgroup v2 memory, CPU and process accounting for workers
+//!
+//! A worker given a cgroup root starts each process it spawns in a
+//! [`CgroupHandle`] of its own there, named after the process's uid, and so
+//! is everything that process starts. Where the kernel cannot clone straight
+//! into a cgroup, the process is moved in right after. Each execution then reports the
+//! cgroup's peak memory and whether the kernel OOM-killed anything in it,
+//! instead of the interpreter's peak RSS. The cgroup is a leaf of a
+//! delegated subtree, so nothing else is charged to it, and is removed
+//! once its process is gone; [`remove_stale`] catches those that were not.
+//!
+//! Each execution's memory limit is also written to the cgroup's
+//! `memory.max`, so what the code's processes and the files it writes to
+//! tmpfs take together is held to it, not only each interpreter's address
+//! space. Worker cgroups get no swap, so the limit cannot be swapped past.
+//! Where the `cpu` and `pids` controllers can be enabled too, the sandbox's
+//! CPU share goes to `cpu.max` and its process limit to `pids.max`, and
+//! executions report the CPU time the cgroup used. A worker is frozen
+//! through `cgroup.freeze` before it is killed, and can be paused the same
+//! way.
+//!
+//! `memory.peak` is read through the file descriptor it was reset on, so it
+//! covers one execution only; that needs Linux 6.12. Where the file is
+//! missing (before Linux 5.19) or cannot be reset, a [`PeakWatch`] samples
+//! `memory.current` every [`SAMPLE_INTERVAL`] instead, which misses spikes
+//! shorter than that.
+//!
+//! A [`PressureWatch`] samples `memory.current` the same way to warn an
+//! execution that it nears its limit. An [`OomEventReceiver`] needs no
+//! sampling: the kernel marks `memory.events` modified as its counters
+//! change, which inotify passes on.
+
+use crate::{ByteSize, LeewardError, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, mpsc};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// How often `memory.current` is sampled where `memory.peak` cannot be used
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long [`CgroupHandle::freeze`] waits for every process to stop
+pub const FREEZE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often `cgroup.events` is read while waiting for a freeze
+const FREEZE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A cgroup v2 directory with the memory controller enabled
+#[derive(Debug, Clone)]
+pub struct CgroupHandle {
+    dir: PathBuf,
+}
+
+impl CgroupHandle {
+    /// The cgroup at `dir`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] unless the memory controller is enabled there,
+    /// which its parent's `cgroup.subtree_control` decides.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let cgroup = Self { dir: dir.into() };
+        if !cgroup.dir.join("memory.current").exists() {
+            return Err(cgroup.error("no memory controller", &std::io::ErrorKind::NotFound.into()));
+        }
+        Ok(cgroup)
+    }
+
+    /// The cgroup `name` under `parent`, created if need be, with the
+    /// memory controller enabled for it
+    ///
+    /// `parent` must have no processes of its own, as cgroup v2 requires of
+    /// any cgroup that hands controllers down.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::CgroupUnavailable`] off a cgroup v2 hierarchy, and
+    /// [`LeewardError::Cgroup`] if the cgroup cannot be created or given its
+    /// controllers.
+    pub fn create(parent: &Path, name: &str) -> Result<Self> {
+        if !on_cgroup2(parent) {
+            return Err(LeewardError::CgroupUnavailable(format!(
+                "{} is not on a cgroup v2 hierarchy",
+                parent.display()
+            )));
+        }
+        let parent = Self {
+            dir: parent.to_path_buf(),
+        };
+        parent.write("cgroup.subtree_control", "+memory")?;
+        for controller in ["cpu", "pids"] {
+            if let Err(e) = parent.write("cgroup.subtree_control", &format!("+{controller}")) {
+                tracing::debug!(controller, error = %e, "cgroup controller not enabled");
+            }
+        }
+        let dir = parent.dir.join(name);
+        match std::fs::create_dir(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(parent.error(&format!("failed to create {name}"), &e)),
+        }
+        Self::open(dir)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cgroup's directory, opened for
+    /// [`clone_worker_into`](super::clone3::clone_worker_into)
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if the directory cannot be opened.
+    pub fn directory(&self) -> Result<OwnedFd> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(&self.dir)
+            .map(OwnedFd::from)
+            .map_err(|e| self.error("failed to open", &e))
+    }
+
+    /// Remove the cgroup, which fails while any process is left in it
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if any process is left in the cgroup.
+    pub fn remove(&self) -> Result<()> {
+        std::fs::remove_dir(&self.dir).map_err(|e| self.error("failed to remove", &e))
+    }
+
+    /// Stop every process in the cgroup, returning once `cgroup.events`
+    /// says they all are
+    ///
+    /// Frozen processes still die of `SIGKILL`.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if they are not all stopped within
+    /// [`FREEZE_TIMEOUT`], or without `cgroup.freeze` (before Linux 5.2).
+    pub fn freeze(&self) -> Result<()> {
+        self.write("cgroup.freeze", "1")?;
+        let deadline = std::time::Instant::now() + FREEZE_TIMEOUT;
+        while !self.is_frozen()? {
+            if std::time::Instant::now() >= deadline {
+                return Err(LeewardError::Cgroup(format!(
+                    "{}: not frozen within {FREEZE_TIMEOUT:?}",
+                    self.dir.display()
+                )));
+            }
+            std::thread::sleep(FREEZE_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Let the processes stopped by [`CgroupHandle::freeze`] run again
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `cgroup.freeze` cannot be written.
+    pub fn thaw(&self) -> Result<()> {
+        self.write("cgroup.freeze", "0")
+    }
+
+    /// Whether every process in the cgroup is frozen
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `cgroup.events` cannot be read.
+    pub fn is_frozen(&self) -> Result<bool> {
+        Ok(self
+            .read("cgroup.events")?
+            .lines()
+            .any(|line| line == "frozen 1"))
+    }
+
+    /// Hold the memory charged to the cgroup to `limit`, or to nothing but
+    /// the parent's with `None`
+    ///
+    /// Past the limit the kernel reclaims what it can, then OOM-kills.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.max` cannot be written.
+    pub fn set_memory_max(&self, limit: Option<ByteSize>) -> Result<()> {
+        self.write("memory.max", &max_value(limit))
+    }
+
+    /// Hold the swap the cgroup may use to `limit`, or to nothing but the
+    /// parent's with `None`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.swap.max` cannot be written, or is
+    /// missing where the kernel does not account swap.
+    pub fn set_swap_max(&self, limit: Option<ByteSize>) -> Result<()> {
+        self.write("memory.swap.max", &max_value(limit))
+    }
+
+    /// Let the cgroup run for `quota_us` of every `period_us` microseconds,
+    /// summed over all CPUs, through `cpu.max`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `cpu.max` cannot be written, or is missing
+    /// without the `cpu` controller.
+    pub fn set_cpu_quota(&self, period_us: u64, quota_us: u64) -> Result<()> {
+        self.write("cpu.max", &format!("{quota_us} {period_us}"))
+    }
+
+    /// Hold the processes and threads in the cgroup to `limit`, or to
+    /// nothing but the parent's with `None`
+    ///
+    /// Past the limit `fork` and `clone` fail with `EAGAIN`.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `pids.max` cannot be written.
+    pub fn set_pids_max(&self, limit: Option<u32>) -> Result<()> {
+        self.write(
+            "pids.max",
+            &limit.map_or_else(|| "max".to_owned(), |limit| limit.to_string()),
+        )
+    }
+
+    /// Processes and threads in the cgroup now
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `pids.current` cannot be read or parsed.
+    pub fn pids_current(&self) -> Result<u32> {
+        let current = self.read("pids.current")?;
+        let current = parse(&current, "pids.current", self)?;
+        Ok(u32::try_from(current).unwrap_or(u32::MAX))
+    }
+
+    /// Times a `fork` or `clone` failed here for `pids.max`, from the `max`
+    /// counter of `pids.events`, or 0 without the `pids` controller
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `pids.events` exists but cannot be read.
+    pub fn pids_limit_hits(&self) -> Result<u64> {
+        let events = match std::fs::read_to_string(self.dir.join("pids.events")) {
+            Ok(events) => events,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(self.error("failed to read pids.events", &e)),
+        };
+        events
+            .lines()
+            .find_map(|line| line.strip_prefix("max "))
+            .map_or(Ok(0), |count| parse(count, "pids.events", self))
+    }
+
+    /// CPU time the cgroup has used so far, from `cpu.stat`
+    ///
+    /// Fields the kernel leaves out, such as `throttled_usec` without the
+    /// `cpu` controller, read as 0.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `cpu.stat` cannot be read.
+    pub fn cpu_stat(&self) -> Result<CpuStat> {
+        let stat = self.read("cpu.stat")?;
+        let mut cpu = CpuStat::default();
+        for line in stat.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let field = match key {
+                "usage_usec" => &mut cpu.usage_usec,
+                "user_usec" => &mut cpu.user_usec,
+                "system_usec" => &mut cpu.system_usec,
+                "throttled_usec" => &mut cpu.throttled_usec,
+                _ => continue,
+            };
+            *field = parse(value, "cpu.stat", self)?;
+        }
+        Ok(cpu)
+    }
+
+    /// Move process `pid`, and the children it starts from now on, here
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `cgroup.procs` cannot be written, e.g. once
+    /// `pid` has exited.
+    pub fn add_process(&self, pid: i32) -> Result<()> {
+        self.write("cgroup.procs", &pid.to_string())
+    }
+
+    /// Memory charged to the cgroup now, in bytes
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.current` cannot be read or parsed.
+    pub fn memory_current(&self) -> Result<u64> {
+        let current = self.read("memory.current")?;
+        parse(&current, "memory.current", self)
+    }
+
+    /// Most memory ever charged to the cgroup, in bytes, or `None` on a
+    /// kernel without `memory.peak`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.peak` exists but cannot be read or
+    /// parsed.
+    pub fn memory_peak(&self) -> Result<Option<u64>> {
+        match std::fs::read_to_string(self.dir.join("memory.peak")) {
+            Ok(peak) => parse(&peak, "memory.peak", self).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.error("failed to read memory.peak", &e)),
+        }
+    }
+
+    /// Processes the kernel has OOM-killed in the cgroup so far, from the
+    /// `oom_kill` counter of `memory.events`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.events` cannot be read.
+    pub fn oom_kills(&self) -> Result<u64> {
+        let events = self.read("memory.events")?;
+        events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .map_or(Ok(0), |count| parse(count, "memory.events", self))
+    }
+
+    /// Whether the kernel OOM-killed anything here since the `oom_kill`
+    /// counter read `since`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.events` cannot be read.
+    pub fn was_oom_killed(&self, since: u64) -> Result<bool> {
+        Ok(self.oom_kills()? > since)
+    }
+
+    /// Start measuring one execution: its peak memory and CPU time from
+    /// now on, and whether anything is OOM-killed or held to `pids.max`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if the starting counters cannot be read.
+    pub fn watch(&self) -> Result<PeakWatch> {
+        let oom_kills = self.oom_kills()?;
+        let pids_limit_hits = self.pids_limit_hits()?;
+        let cpu_usage = self.cpu_stat().ok().map(|cpu| cpu.usage_usec);
+        let peak = self
+            .reset_peak()
+            .map_or_else(|| Peak::Sampled(Sampler::start(self.clone())), Peak::Reset);
+        Ok(PeakWatch {
+            cgroup: self.clone(),
+            oom_kills,
+            pids_limit_hits,
+            cpu_usage,
+            peak,
+        })
+    }
+
+    /// Call `warn` with `memory.current`, once, the first time a sample
+    /// taken every [`SAMPLE_INTERVAL`] reaches `threshold` bytes
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if the sampling thread cannot be started.
+    pub fn watch_pressure(
+        &self,
+        threshold: u64,
+        warn: impl FnOnce(u64) + Send + 'static,
+    ) -> Result<PressureWatch> {
+        let (stop, stopped) = mpsc::channel();
+        let cgroup = self.clone();
+        let thread = std::thread::Builder::new()
+            .name("leeward-pressure".into())
+            .spawn(move || {
+                loop {
+                    // A sample that fails is skipped like the peak's
+                    if let Some(current) = cgroup
+                        .memory_current()
+                        .ok()
+                        .filter(|&current| current >= threshold)
+                    {
+                        warn(current);
+                        return Some(current);
+                    }
+                    if stopped.recv_timeout(SAMPLE_INTERVAL) != Err(mpsc::RecvTimeoutError::Timeout)
+                    {
+                        return None;
+                    }
+                }
+            })
+            .map_err(|e| self.error("failed to start the memory pressure watch", &e))?;
+        Ok(PressureWatch {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Start waiting for the kernel to OOM-kill something here, from an
+    /// inotify watch on `memory.events`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if the inotify watch cannot be set up.
+    pub fn subscribe_oom_events(&self) -> Result<OomEventReceiver> {
+        let events = self.dir.join("memory.events");
+        let inotify =
+            watch_modify(&events).map_err(|e| self.error("failed to watch memory.events", &e))?;
+        // Read once the watch is in place, so no kill in between goes unseen
+        let since = self.oom_kills()?;
+        let (stopped, stop) = crate::pipe::create_pipe()?;
+        let waker = Arc::new(Mutex::new(None));
+        let thread = {
+            let waker = Arc::clone(&waker);
+            std::thread::Builder::new()
+                .name("leeward-oom".into())
+                .spawn(move || wake_on_events(&inotify, &stopped, &waker))
+                .map_err(|e| self.error("failed to start the OOM event watch", &e))?
+        };
+        Ok(OomEventReceiver {
+            cgroup: self.clone(),
+            since,
+            waker,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// `memory.peak`, opened and reset so reading it covers only what comes
+    /// next, if the kernel has and can reset it
+    fn reset_peak(&self) -> Option<std::fs::File> {
+        let mut peak = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.dir.join("memory.peak"))
+            .ok()?;
+        peak.write_all(b"reset\n").ok()?;
+        Some(peak)
+    }
+
+    fn read(&self, file: &str) -> Result<String> {
+        std::fs::read_to_string(self.dir.join(file))
+            .map_err(|e| self.error(&format!("failed to read {file}"), &e))
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        std::fs::write(self.dir.join(file), value).map_err(|e| {
+            if on_cgroup2(&self.dir) {
+                self.error(&format!("failed to write {file}"), &e)
+            } else {
+                LeewardError::CgroupUnavailable(format!(
+                    "{}: not on a cgroup v2 hierarchy: {e}",
+                    self.dir.display()
+                ))
+            }
+        })
+    }
+
+    fn error(&self, context: &str, e: &std::io::Error) -> LeewardError {
+        LeewardError::Cgroup(format!("{}: {context}: {e}", self.dir.display()))
+    }
+}
+
+/// Remove each cgroup directly under `root` whose name `stale` picks,
+/// returning the paths removed
+///
+/// Only cgroups with no process left can be removed; the others stay for
+/// a later pass. Failures are logged.
+pub fn remove_stale(root: &Path, stale: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!(root = %root.display(), error = %e, "failed to list cgroups");
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| entry.file_name().to_str().is_some_and(&stale))
+        .filter_map(|entry| {
+            let path = entry.path();
+            match std::fs::remove_dir(&path) {
+                Ok(()) => Some(path),
+                Err(e) => {
+                    tracing::debug!(
+                        path = %path.display(),
+                        error = %e,
+                        "stale cgroup not removed yet"
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Whether `dir` is on a cgroup v2 hierarchy
+fn on_cgroup2(dir: &Path) -> bool {
+    nix::sys::statfs::statfs(dir)
+        .is_ok_and(|fs| fs.filesystem_type() == nix::sys::statfs::CGROUP2_SUPER_MAGIC)
+}
+
+/// What a `*.max` file takes for `limit`
+fn max_value(limit: Option<ByteSize>) -> String {
+    limit.map_or_else(|| "max".to_owned(), |limit| limit.bytes().to_string())
+}
+
+/// A number a cgroup file holds, named `file` in errors
+fn parse(value: &str, file: &str, cgroup: &CgroupHandle) -> Result<u64> {
+    value.trim().parse().map_err(|e| {
+        LeewardError::Cgroup(format!("{}: unreadable {file}: {e}", cgroup.dir.display()))
+    })
+}
+
+/// CPU time a cgroup has used, in microseconds, from `cpu.stat`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuStat {
+    /// User and system time together
+    pub usage_usec: u64,
+    pub user_usec: u64,
+    pub system_usec: u64,
+    /// Time the cgroup was held back by `cpu.max`
+    pub throttled_usec: u64,
+}
+
+/// What a cgroup saw of one execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CgroupUsage {
+    /// Most memory charged at once, in bytes
+    pub memory_peak: u64,
+    /// Whether the kernel OOM-killed anything
+    pub oom_killed: bool,
+    /// CPU time used, in microseconds, unless `cpu.stat` could not be read
+    pub cpu_time_us: Option<u64>,
+    /// Whether a `fork` or `clone` failed for `pids.max`
+    pub pids_limited: bool,
+}
+
+/// One execution being measured, from [`CgroupHandle::watch`]
+#[derive(Debug)]
+pub struct PeakWatch {
+    cgroup: CgroupHandle,
+    oom_kills: u64,
+    pids_limit_hits: u64,
+    /// `usage_usec` when the watch started
+    cpu_usage: Option<u64>,
+    peak: Peak,
+}
+
+#[derive(Debug)]
+enum Peak {
+    /// `memory.peak`, reset when the watch started
+    Reset(std::fs::File),
+    /// The largest `memory.current` seen so far
+    Sampled(Sampler),
+}
+
+impl PeakWatch {
+    /// Stop measuring and say what the execution used
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if the final counters cannot be read.
+    pub fn finish(self) -> Result<CgroupUsage> {
+        let memory_peak = match self.peak {
+            Peak::Reset(mut file) => {
+                let mut peak = String::new();
+                file.seek(SeekFrom::Start(0))
+                    .and_then(|_| file.read_to_string(&mut peak))
+                    .map_err(|e| self.cgroup.error("failed to read memory.peak", &e))?;
+                parse(&peak, "memory.peak", &self.cgroup)?
+            }
+            Peak::Sampled(sampler) => sampler.stop().max(self.cgroup.memory_current()?),
+        };
+        let cpu_time_us = self
+            .cpu_usage
+            .zip(self.cgroup.cpu_stat().ok())
+            .map(|(before, now)| now.usage_usec.saturating_sub(before));
+        Ok(CgroupUsage {
+            memory_peak,
+            oom_killed: self.cgroup.was_oom_killed(self.oom_kills)?,
+            cpu_time_us,
+            pids_limited: self.cgroup.pids_limit_hits()? > self.pids_limit_hits,
+        })
+    }
+}
+
+/// Memory pressure being watched for, from [`CgroupHandle::watch_pressure`]
+#[derive(Debug)]
+pub struct PressureWatch {
+    stop: mpsc::Sender<()>,
+    thread: Option<std::thread::JoinHandle<Option<u64>>>,
+}
+
+impl PressureWatch {
+    /// Stop watching; returns the memory charged when the warning was
+    /// given, if it was
+    #[must_use]
+    pub fn stop(mut self) -> Option<u64> {
+        let _ = self.stop.send(());
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .flatten()
+    }
+}
+
+/// OOM kills in a cgroup, from [`CgroupHandle::subscribe_oom_events`]
+///
+/// As a future it resolves to how many processes the kernel OOM-killed
+/// there since it was subscribed, once there are any.
+#[derive(Debug)]
+pub struct OomEventReceiver {
+    cgroup: CgroupHandle,
+    /// The `oom_kill` counter when subscribed
+    since: u64,
+    /// Woken when `memory.events` changes
+    waker: Arc<Mutex<Option<Waker>>>,
+    /// Closed to stop the thread watching `memory.events`
+    stop: Option<std::fs::File>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl OomEventReceiver {
+    /// Processes OOM-killed since subscribing, without waiting
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] if `memory.events` cannot be read.
+    pub fn kills(&self) -> Result<u64> {
+        Ok(self.cgroup.oom_kills()?.saturating_sub(self.since))
+    }
+
+    /// The cgroup being watched
+    #[must_use]
+    pub const fn cgroup(&self) -> &CgroupHandle {
+        &self.cgroup
+    }
+}
+
+impl Future for OomEventReceiver {
+    type Output = Result<u64>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Registered before reading, so a change in between still wakes it
+        *self
+            .waker
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(cx.waker().clone());
+        match self.kills() {
+            Ok(0) => Poll::Pending,
+            kills => Poll::Ready(kills),
+        }
+    }
+}
+
+impl Drop for OomEventReceiver {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// An inotify descriptor watching `path` for modification
+fn watch_modify(path: &Path) -> std::io::Result<OwnedFd> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: inotify_init1 with constant flags
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: A new descriptor nothing else owns
+    let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: Watching a NUL-terminated path on our own descriptor
+    if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), libc::IN_MODIFY) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(inotify)
+}
+
+/// Wake whatever `waker` holds each time `inotify` has events, until
+/// `stopped` closes
+fn wake_on_events(inotify: &OwnedFd, stopped: &std::fs::File, waker: &Mutex<Option<Waker>>) {
+    let mut events = [0u8; 4096];
+    loop {
+        let mut fds = [inotify.as_raw_fd(), stopped.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        // SAFETY: poll on a valid array of pollfds
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if ret < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        if fds[1].revents != 0 || fds[0].revents & (libc::POLLERR | libc::POLLNVAL) != 0 {
+            return;
+        }
+        // Only that there were events matters
+        // SAFETY: Reading into a buffer of its size from our own descriptor
+        while unsafe {
+            libc::read(
+                inotify.as_raw_fd(),
+                events.as_mut_ptr().cast(),
+                events.len(),
+            )
+        } > 0
+        {}
+        let waker = waker
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A thread keeping the largest `memory.current` it samples
+#[derive(Debug)]
+struct Sampler {
+    stop: mpsc::Sender<()>,
+    thread: Option<std::thread::JoinHandle<u64>>,
+}
+
+impl Sampler {
+    /// Sample `cgroup` until stopped; if no thread can be started, only
+    /// the sample taken when stopping counts
+    fn start(cgroup: CgroupHandle) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("leeward-cgroup".into())
+            .spawn(move || {
+                let mut peak = 0;
+                loop {
+                    // A sample that fails is skipped; the final one is checked
+                    peak = cgroup
+                        .memory_current()
+                        .map_or(peak, |current| peak.max(current));
+                    if stopped.recv_timeout(SAMPLE_INTERVAL) != Err(mpsc::RecvTimeoutError::Timeout)
+                    {
+                        return peak;
+                    }
+                }
+            })
+            .map_err(|e| tracing::warn!(error = %e, "no memory sampler; peak is the final sample"))
+            .ok();
+        Self { stop, thread }
+    }
+
+    /// The largest sample taken
+    fn stop(mut self) -> u64 {
+        let _ = self.stop.send(());
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or(0)
+    }
+}
@@ -2,8 +2,14 @@
 
 use crate::{LeewardError, Result};
 use libc::pid_t;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net::UnixStream;
 
-/// clone3 clone_args structure (from linux/sched.h)
+/// Start the child in the cgroup [`CloneArgs::cgroup`] is open on (Linux 5.7)
+pub const CLONE_INTO_CGROUP: u64 = 0x2_0000_0000;
+
+/// clone3 `clone_args` structure (from linux/sched.h)
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct CloneArgs {
@@ -11,6 +17,10 @@ pub struct CloneArgs {
     pub flags: u64,
     /// File descriptor for pidfd
     pub pidfd: u64,
+    /// Where to store the child's TID in the child
+    pub child_tid: u64,
+    /// Where to store the child's TID in the parent
+    pub parent_tid: u64,
     /// Signal to deliver on child termination
     pub exit_signal: u64,
     /// Stack pointer (0 = copy parent stack)
@@ -19,10 +29,12 @@ pub struct CloneArgs {
     pub stack_size: u64,
     /// TLS pointer
     pub tls: u64,
-    /// Pointer to set_tid array
+    /// Pointer to `set_tid` array
     pub set_tid: u64,
-    /// Size of set_tid array
+    /// Size of `set_tid` array
     pub set_tid_size: u64,
+    /// Directory of the cgroup to start in, with [`CLONE_INTO_CGROUP`]
+    pub cgroup: u64,
 }
 
 /// clone3 syscall number
@@ -30,6 +42,10 @@ const SYS_CLONE3: i64 = 435;
 
 /// Wrapper around the clone3 syscall
 ///
+/// # Errors
+///
+/// [`LeewardError::Namespace`] if the kernel refuses the clone.
+///
 /// # Safety
 /// This function makes a raw syscall and forks the process
 pub unsafe fn clone3(args: &CloneArgs) -> Result<pid_t> {
@@ -37,7 +53,7 @@ pub unsafe fn clone3(args: &CloneArgs) -> Result<pid_t> {
     let ret = unsafe {
         libc::syscall(
             SYS_CLONE3,
-            args as *const CloneArgs,
+            std::ptr::from_ref::<CloneArgs>(args),
             std::mem::size_of::<CloneArgs>(),
         )
     };
@@ -49,30 +65,140 @@ pub unsafe fn clone3(args: &CloneArgs) -> Result<pid_t> {
         )));
     }
 
-    Ok(ret as pid_t)
+    pid_t::try_from(ret).map_err(|_| LeewardError::Namespace(format!("clone3 returned {ret}")))
 }
 
 /// Helper to create a pre-forked worker with namespaces
-pub fn clone_worker(
+///
+/// The child exits 0 once `child_fn` returns. If it fails or panics, the
+/// child reports it through [`fatal`](super::fatal) and exits with the
+/// status of the stage it was in.
+///
+/// # Errors
+///
+/// [`LeewardError::Namespace`] if the kernel refuses the clone.
+pub fn clone_worker(namespace_flags: u64, child_fn: impl FnOnce() -> Result<()>) -> Result<pid_t> {
+    clone_worker_into(namespace_flags, None, child_fn).map(|(pid, _)| pid)
+}
+
+/// [`clone_worker`], starting the child in the cgroup v2 directory
+/// `cgroup` is open on, so it is accounted there from its first page
+///
+/// Where the kernel cannot do that (before Linux 5.7, or `cgroup` is not
+/// a cgroup v2 directory it may move processes into), the child starts in
+/// the caller's cgroup instead. The flag returned says whether it is in
+/// `cgroup`; if not, move it with
+/// [`CgroupHandle::add_process`](super::CgroupHandle::add_process).
+///
+/// # Errors
+///
+/// As [`clone_worker`].
+pub fn clone_worker_into(
     namespace_flags: u64,
+    cgroup: Option<BorrowedFd<'_>>,
     child_fn: impl FnOnce() -> Result<()>,
-) -> Result<pid_t> {
-    let args = CloneArgs {
+) -> Result<(pid_t, bool)> {
+    let mut args = CloneArgs {
         flags: namespace_flags,
         exit_signal: libc::SIGCHLD as u64,
         ..Default::default()
     };
+    if let Some(cgroup) = cgroup {
+        args.flags |= CLONE_INTO_CGROUP;
+        args.cgroup = u64::from(cgroup.as_raw_fd().unsigned_abs());
+    }
 
     // SAFETY: We're forking the process with clone3
-    let pid = unsafe { clone3(&args)? };
+    let pid = match unsafe { clone3(&args) } {
+        Err(e) if args.flags & CLONE_INTO_CGROUP != 0 => {
+            tracing::debug!(error = %e, "cannot clone into the cgroup, starting outside it");
+            args.flags &= !CLONE_INTO_CGROUP;
+            args.cgroup = 0;
+            // SAFETY: As above
+            unsafe { clone3(&args)? }
+        }
+        cloned => cloned?,
+    };
 
     if pid == 0 {
         // Child process
-        drop(child_fn());
+        super::fatal::begin();
+        if let Err(e) = child_fn() {
+            super::fatal::die(e.raw_os_error().unwrap_or(0), &e);
+        }
         // SAFETY: Exiting child process
         unsafe { libc::_exit(0) };
     }
 
     // Parent process
-    Ok(pid)
+    Ok((pid, args.flags & CLONE_INTO_CGROUP != 0))
+}
+
+/// Close every descriptor above stderr except those in `keep`
+///
+/// For a cloned child, so it holds nothing of the parent's it was not
+/// handed on purpose.
+pub fn close_inherited(keep: &[RawFd]) {
+    let mut keep: Vec<u32> = keep
+        .iter()
+        .map(|fd| fd.unsigned_abs())
+        .filter(|&fd| fd > 2)
+        .collect();
+    keep.sort_unstable();
+    let mut first = 3;
+    for fd in keep {
+        if fd > first {
+            // SAFETY: Closing descriptors this process does not use
+            unsafe { libc::syscall(libc::SYS_close_range, first, fd - 1, 0) };
+        }
+        first = fd + 1;
+    }
+    // SAFETY: As above
+    unsafe { libc::syscall(libc::SYS_close_range, first, u32::MAX, 0) };
+}
+
+/// [`clone_worker_into`], running `parent_fn` with the child's pid before
+/// the child gets to `child_fn`
+///
+/// The child waits on a socket until `parent_fn` returns, so the parent
+/// can set up what the child needs from outside, such as the id maps of a
+/// user namespace the child was cloned into with `CLONE_NEWUSER`; see
+/// [`NamespaceConfig::write_id_maps`](super::NamespaceConfig::write_id_maps).
+/// If `parent_fn` fails, the child exits without running `child_fn` and is
+/// reaped before the error is returned.
+///
+/// # Errors
+///
+/// As [`clone_worker`], and `parent_fn`'s error.
+pub fn clone_worker_with(
+    namespace_flags: u64,
+    cgroup: Option<BorrowedFd<'_>>,
+    parent_fn: impl FnOnce(pid_t) -> Result<()>,
+    child_fn: impl FnOnce() -> Result<()>,
+) -> Result<(pid_t, bool)> {
+    let (mut proceed, mut wait) = UnixStream::pair()?;
+    let proceed_fd = proceed.as_raw_fd();
+    let (pid, in_cgroup) = clone_worker_into(namespace_flags, cgroup, move || {
+        // Only the parent's end left open, so a parent that gives up shows
+        // as end of file
+        // SAFETY: The child's copy of the parent's descriptor, used for nothing else
+        unsafe { libc::close(proceed_fd) };
+        let mut go = [0u8; 1];
+        if wait.read(&mut go)? == 0 {
+            return Err(LeewardError::Namespace(
+                "parent failed to set up the child".into(),
+            ));
+        }
+        drop(wait);
+        child_fn()
+    })?;
+
+    if let Err(e) = parent_fn(pid).and_then(|()| Ok(proceed.write_all(&[1])?)) {
+        drop(proceed);
+        let mut status = 0;
+        // SAFETY: Reaping our own child, which exits on seeing the socket close
+        unsafe { libc::waitpid(pid, &raw mut status, 0) };
+        return Err(e);
+    }
+    Ok((pid, in_cgroup))
 }
@@ -0,0 +1,269 @@
+//! Last words of a dying worker process
+//!
+//! A process started with [`clone_worker`](super::clone3::clone_worker)
+//! records which [`Stage`] it is in as it goes. If it fails or panics, it
+//! writes one fixed-size frame naming the stage, errno and message to the fd
+//! given to [`report_to`], then exits with the stage's status. The frame is
+//! built on the stack and sent with a single `write(2)`, so reporting never
+//! allocates and works from a panic hook.
+//!
+//! The parent reads the frame with [`WorkerDeath::decode`]. If the process
+//! died before writing one, [`WorkerDeath::from_wait_status`] still names
+//! the stage from the exit status.
+
+#[cfg(feature = "protocol")]
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write as _};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+
+/// Marks a frame as last words rather than a msgpack control message
+const MAGIC: [u8; 8] = *b"\0leeward";
+
+/// Length of a frame body; with its prefix, well under `PIPE_BUF`
+pub const FRAME_LEN: usize = 512;
+
+/// Where the message starts in a frame body
+const MESSAGE_OFFSET: usize = 15;
+
+/// Exit status of a process that died in the first stage
+const EXIT_BASE: i32 = 80;
+
+/// Stage the current process is in
+static STAGE: AtomicU8 = AtomicU8::new(Stage::Startup as u8);
+
+/// Where to write the frame, or -1 for nowhere
+static REPORT_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// What a worker process was doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "protocol", serde(rename_all = "snake_case"))]
+#[repr(u8)]
+pub enum Stage {
+    /// Before any isolation layer
+    Startup,
+    /// Entering namespaces
+    Namespaces,
+    /// Attaching the root template and scratch mounts
+    Mounts,
+    /// Applying Landlock rules
+    Landlock,
+    /// Installing the seccomp filter
+    Seccomp,
+    /// Isolated and serving executions
+    Exec,
+}
+
+impl Stage {
+    /// Every stage, in the order a worker goes through them
+    pub const ALL: [Self; 6] = [
+        Self::Startup,
+        Self::Namespaces,
+        Self::Mounts,
+        Self::Landlock,
+        Self::Seccomp,
+        Self::Exec,
+    ];
+
+    /// Stage applying the isolation layer called `name`
+    #[must_use]
+    pub fn from_layer(name: &str) -> Self {
+        match name {
+            "namespaces" | "loopback" | "network" => Self::Namespaces,
+            "mounts" | "resolv.conf" | "proc" => Self::Mounts,
+            "landlock" => Self::Landlock,
+            "seccomp" => Self::Seccomp,
+            _ => Self::Startup,
+        }
+    }
+
+    /// Status a process dying in this stage exits with
+    #[must_use]
+    pub const fn exit_code(self) -> i32 {
+        EXIT_BASE + self as i32
+    }
+
+    /// Stage a process exiting with `code` died in, if it is one of ours
+    #[must_use]
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|stage| stage.exit_code() == code)
+    }
+
+    /// Label used in logs and errors
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Startup => "startup",
+            Self::Namespaces => "namespaces",
+            Self::Mounts => "mounts",
+            Self::Landlock => "landlock",
+            Self::Seccomp => "seccomp",
+            Self::Exec => "exec",
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|stage| *stage as u8 == value)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a worker process died, as it reported it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+pub struct WorkerDeath {
+    pub stage: Stage,
+    /// OS error behind the failure, or 0 if there was none
+    pub errno: i32,
+    pub message: String,
+}
+
+impl WorkerDeath {
+    /// Decode a frame body written by a dying process
+    ///
+    /// Returns `None` for anything else, such as a control message.
+    #[must_use]
+    pub fn decode(body: &[u8]) -> Option<Self> {
+        if body.len() != FRAME_LEN || body[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let stage = Stage::from_u8(body[8])?;
+        let errno = i32::from_be_bytes(body[9..13].try_into().ok()?);
+        let len = usize::from(u16::from_be_bytes(body[13..15].try_into().ok()?));
+        let message = body.get(MESSAGE_OFFSET..MESSAGE_OFFSET + len)?;
+
+        Some(Self {
+            stage,
+            errno,
+            message: String::from_utf8_lossy(message).into_owned(),
+        })
+    }
+
+    /// Death implied by a `waitpid` status, for processes that left no frame
+    ///
+    /// Only exit statuses from [`Stage::exit_code`] name a stage; anything
+    /// else returns `None`.
+    #[must_use]
+    pub fn from_wait_status(status: i32) -> Option<Self> {
+        if !libc::WIFEXITED(status) {
+            return None;
+        }
+        let code = libc::WEXITSTATUS(status);
+        Stage::from_exit_code(code).map(|stage| Self {
+            stage,
+            errno: 0,
+            message: format!("exited with status {code}"),
+        })
+    }
+}
+
+impl fmt::Display for WorkerDeath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.stage, self.message)?;
+        if self.errno != 0 {
+            write!(f, " (os error {})", self.errno)?;
+        }
+        Ok(())
+    }
+}
+
+/// Record that the current process has moved on to `stage`
+pub fn enter(stage: Stage) {
+    STAGE.store(stage as u8, Ordering::Relaxed);
+}
+
+/// Stage the current process is in
+#[must_use]
+pub fn current() -> Stage {
+    Stage::from_u8(STAGE.load(Ordering::Relaxed)).unwrap_or(Stage::Startup)
+}
+
+/// Write the current process's last words to `fd` if it dies
+///
+/// `fd` is duplicated, so the report still goes out after the caller's
+/// copy is closed.
+///
+/// # Errors
+///
+/// The error of duplicating `fd`, e.g. `EBADF`.
+pub fn report_to(fd: RawFd) -> std::io::Result<()> {
+    // SAFETY: fcntl only duplicates the fd; a bad fd fails with EBADF
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let old = REPORT_FD.swap(dup, Ordering::Relaxed);
+    if old >= 0 {
+        // SAFETY: The old duplicate was ours and nothing else refers to it
+        unsafe { libc::close(old) };
+    }
+    Ok(())
+}
+
+/// Start afresh in a newly cloned process, reporting any panic through [`die`]
+///
+/// The stage and report fd are copies of the parent's, so both are reset.
+pub(crate) fn begin() {
+    enter(Stage::Startup);
+    REPORT_FD.store(-1, Ordering::Relaxed);
+    std::panic::set_hook(Box::new(|info| die(0, info)));
+}
+
+/// Write the last-words frame, if anyone listens, and exit with the stage's status
+pub(crate) fn die(errno: i32, message: &dyn fmt::Display) -> ! {
+    let stage = current();
+    let fd = REPORT_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let frame = encode(stage, errno, message);
+        // SAFETY: Writing a stack buffer; the frame fits in one atomic pipe write
+        unsafe { libc::write(fd, frame.as_ptr().cast(), frame.len()) };
+    }
+    // SAFETY: Skips destructors and atexit handlers, which a clone of the
+    // parent must not run
+    unsafe { libc::_exit(stage.exit_code()) }
+}
+
+/// Length prefix and frame body, as they go on the pipe
+fn encode(stage: Stage, errno: i32, message: &dyn fmt::Display) -> [u8; 4 + FRAME_LEN] {
+    let mut frame = [0u8; 4 + FRAME_LEN];
+    frame[..4].copy_from_slice(&u32::try_from(FRAME_LEN).unwrap_or(u32::MAX).to_be_bytes());
+    let body = &mut frame[4..];
+    body[..MAGIC.len()].copy_from_slice(&MAGIC);
+    body[8] = stage as u8;
+    body[9..13].copy_from_slice(&errno.to_be_bytes());
+
+    let mut text = Truncating {
+        buf: &mut body[MESSAGE_OFFSET..],
+        len: 0,
+    };
+    let _ = write!(text, "{message}");
+    let len = u16::try_from(text.len).unwrap_or(u16::MAX);
+    body[13..15].copy_from_slice(&len.to_be_bytes());
+    frame
+}
+
+/// Formats into a fixed buffer, dropping whatever does not fit
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut take = s.len().min(self.buf.len() - self.len);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        Ok(())
+    }
+}
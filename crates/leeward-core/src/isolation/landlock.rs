@@ -1,13 +1,79 @@
-//! Landlock filesystem sandboxing
+//! Landlock filesystem and network sandboxing
+//!
+//! The ruleset handles every access right of the newest Landlock ABI both
+//! the kernel and this build know, probed at runtime, so a 5.13 kernel
+//! still gets the rights of ABI 1 and a newer one gets truncation and
+//! device ioctls too. TCP `connect` and `bind` can be held to given ports
+//! from ABI 4. [`LandlockConfig::apply`] reports what it enforced as
+//! a [`LandlockStatus`]; a kernel without Landlock is not an error.
 
 use crate::Result;
-use std::path::PathBuf;
+use crate::config::paths::{CanonicalPath, PathPolicy};
 use landlock::{
-    Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
-    RulesetStatus, ABI
+    ABI, Access, AccessFs, AccessNet, BitFlags, NetPort, Ruleset, RulesetAttr, RulesetCreated,
+    RulesetCreatedAttr, RulesetStatus,
 };
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-/// Configuration for Landlock filesystem restrictions
+/// Newest Landlock ABI this build knows the access rights of
+pub const LATEST_ABI: u8 = 6;
+
+/// First Landlock ABI with TCP port rules
+pub const NET_ABI: u8 = 4;
+
+/// How much of what this build can restrict Landlock enforces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Every access right this build knows
+    Full,
+    /// Some, as the kernel's ABI is older than [`LATEST_ABI`] or a rule
+    /// could not be added whole
+    Partial,
+    /// None, as the kernel has no Landlock
+    NotEnforced,
+}
+
+/// The Landlock ABI used and how much it enforces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LandlockStatus {
+    /// ABI the ruleset was built for, `None` without Landlock
+    pub abi: Option<u8>,
+    pub enforcement: Enforcement,
+}
+
+impl LandlockStatus {
+    /// What the running kernel can enforce, without restricting anything
+    #[must_use]
+    pub fn probe() -> Self {
+        let abi = crate::escape::landlock_abi()
+            .map(|version| u8::try_from(version).unwrap_or(u8::MAX).min(LATEST_ABI));
+        let enforcement = match abi {
+            None => Enforcement::NotEnforced,
+            Some(LATEST_ABI) => Enforcement::Full,
+            Some(_) => Enforcement::Partial,
+        };
+        Self { abi, enforcement }
+    }
+
+    /// Whether Landlock restricts anything at all
+    #[must_use]
+    pub fn enforced(&self) -> bool {
+        self.enforcement != Enforcement::NotEnforced
+    }
+}
+
+impl fmt::Display for LandlockStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.abi, self.enforcement) {
+            (None, _) | (_, Enforcement::NotEnforced) => f.write_str("not enforced"),
+            (Some(abi), Enforcement::Full) => write!(f, "fully enforced with ABI {abi}"),
+            (Some(abi), Enforcement::Partial) => write!(f, "partially enforced with ABI {abi}"),
+        }
+    }
+}
+
+/// Configuration for Landlock filesystem and network restrictions
 #[derive(Debug, Clone, Default)]
 pub struct LandlockConfig {
     /// Paths with read-only access
@@ -16,6 +82,22 @@ pub struct LandlockConfig {
     pub rw_paths: Vec<PathBuf>,
     /// Paths with execute permission
     pub exec_paths: Vec<PathBuf>,
+    /// Forbid executing any file, even below `exec_paths`
+    ///
+    /// Execute stays in the handled access set but no rule grants it, so
+    /// every `execve` fails with `EACCES`. Applying fails if the kernel cannot
+    /// enforce the ruleset at all, rather than silently allowing execution.
+    pub deny_exec: bool,
+    /// TCP ports `connect` may reach; empty leaves connecting unrestricted
+    ///
+    /// Enforced from Landlock ABI 4; before it the ruleset is only
+    /// partially enforced.
+    pub tcp_connect_ports: Vec<u16>,
+    /// TCP ports `bind` may take, like `tcp_connect_ports`
+    pub tcp_bind_ports: Vec<u16>,
+    /// Fail on a path that does not exist instead of skipping it, and make
+    /// the layer required, so a worker missing one does not spawn
+    pub strict: bool,
 }
 
 impl LandlockConfig {
@@ -40,103 +122,243 @@ impl LandlockConfig {
         self
     }
 
+    /// Forbid execution of any file
+    #[must_use]
+    pub const fn deny_exec(mut self) -> Self {
+        self.deny_exec = true;
+        self
+    }
+
+    /// See [`LandlockConfig::strict`]
+    #[must_use]
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Allow connecting to TCP `port`, and no port not allowed so
+    #[must_use]
+    pub fn connect_tcp(mut self, port: u16) -> Self {
+        self.tcp_connect_ports.push(port);
+        self
+    }
+
+    /// Allow binding TCP `port`, and no port not allowed so
+    #[must_use]
+    pub fn bind_tcp(mut self, port: u16) -> Self {
+        self.tcp_bind_ports.push(port);
+        self
+    }
+
     /// Apply Landlock restrictions to the current process
-    pub fn apply(&self) -> Result<()> {
+    ///
+    /// Without Landlock in the kernel nothing is restricted and the status
+    /// says so, unless `deny_exec` is set, which then fails.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Landlock`](crate::LeewardError::Landlock) if the ruleset
+    /// cannot be built or enforced, or without Landlock when `deny_exec` is
+    /// set.
+    pub fn apply(&self) -> Result<LandlockStatus> {
         tracing::debug!(
             ro = self.ro_paths.len(),
             rw = self.rw_paths.len(),
             exec = self.exec_paths.len(),
+            deny_exec = self.deny_exec,
+            tcp_connect = self.tcp_connect_ports.len(),
+            tcp_bind = self.tcp_bind_ports.len(),
             "applying landlock rules"
         );
 
-        // Check if Landlock is supported - use V2 for now (Linux 5.19+)
-        let abi = ABI::V2;
-        tracing::debug!("Using Landlock ABI version: {:?}", abi);
+        let probed = LandlockStatus::probe();
+        let Some(version) = probed.abi else {
+            if self.deny_exec {
+                return Err(crate::LeewardError::Landlock(
+                    "deny_exec requested but this kernel has no Landlock".into(),
+                ));
+            }
+            tracing::warn!("Landlock not supported by this kernel, not enforced");
+            return Ok(probed);
+        };
+        let abi = ABI::from(i32::from(version));
+        tracing::debug!(abi = version, "using Landlock ABI");
 
-        // Create ruleset with all filesystem access flags we want to control
+        // Create ruleset with all filesystem access flags we want to control,
+        // and the TCP ones with port rules, which the kernel drops before ABI 4
         let mut ruleset = Ruleset::default()
             .handle_access(AccessFs::from_all(abi))
-            .map_err(|e| crate::LeewardError::Landlock(format!("failed to create ruleset: {e}")))?
+            .map_err(|e| crate::LeewardError::Landlock(format!("failed to create ruleset: {e}")))?;
+        let net_access = self.net_access();
+        if !net_access.is_empty() {
+            if version < NET_ABI {
+                tracing::warn!(
+                    abi = version,
+                    "TCP port rules need Landlock ABI {NET_ABI}, not enforced"
+                );
+            }
+            ruleset = ruleset.handle_access(net_access).map_err(|e| {
+                crate::LeewardError::Landlock(format!("failed to create ruleset: {e}"))
+            })?;
+        }
+        let ruleset = ruleset
             .create()
             .map_err(|e| crate::LeewardError::Landlock(format!("failed to create ruleset: {e}")))?;
+        let ruleset = self.add_port_rules(self.add_path_rules(ruleset, abi)?)?;
 
+        // Enforce the ruleset
+        let status = ruleset.restrict_self().map_err(|e| {
+            crate::LeewardError::Landlock(format!("failed to enforce landlock: {e}"))
+        })?;
+
+        let enforced = enforced(&status.ruleset, probed);
+        if !enforced.enforced() && self.deny_exec {
+            return Err(crate::LeewardError::Landlock(
+                "deny_exec requested but the Landlock ruleset could not be enforced".into(),
+            ));
+        }
+        if enforced.enforced() {
+            tracing::info!("Landlock ruleset {enforced}");
+        } else {
+            tracing::warn!("Landlock ruleset could not be enforced");
+        }
+        Ok(enforced)
+    }
+
+    /// Add the rules for `ro_paths`, `rw_paths` and `exec_paths`
+    fn add_path_rules(&self, mut ruleset: RulesetCreated, abi: ABI) -> Result<RulesetCreated> {
         // Add read-only paths
         let ro_access = AccessFs::ReadFile | AccessFs::ReadDir;
         for path in &self.ro_paths {
-            if path.exists() {
-                let file = std::fs::File::open(path)
-                    .map_err(|e| crate::LeewardError::Landlock(format!("failed to open {}: {e}", path.display())))?;
+            if let Some(file) = open_rule_path(path, self.strict)? {
                 ruleset = ruleset
                     .add_rule(landlock::PathBeneath::new(file, ro_access))
-                    .map_err(|e| crate::LeewardError::Landlock(format!(
-                        "failed to add ro rule for {}: {e}",
-                        path.display()
-                    )))?;
+                    .map_err(|e| {
+                        crate::LeewardError::Landlock(format!(
+                            "failed to add ro rule for {}: {e}",
+                            path.display()
+                        ))
+                    })?;
                 tracing::debug!("added read-only access for {}", path.display());
             }
         }
 
-        // Add read-write paths
-        let rw_access = AccessFs::ReadFile
-            | AccessFs::WriteFile
-            | AccessFs::ReadDir
-            | AccessFs::RemoveDir
-            | AccessFs::RemoveFile
-            | AccessFs::MakeChar
-            | AccessFs::MakeDir
-            | AccessFs::MakeReg
-            | AccessFs::MakeSock
-            | AccessFs::MakeFifo
-            | AccessFs::MakeBlock
-            | AccessFs::MakeSym;
+        // Add read-write paths: everything but execution, so newer rights
+        // such as truncation work where writing does
+        let writable: BitFlags<AccessFs> = AccessFs::from_all(abi) & !AccessFs::Execute;
 
         for path in &self.rw_paths {
-            if path.exists() {
-                let file = std::fs::File::open(path)
-                    .map_err(|e| crate::LeewardError::Landlock(format!("failed to open {}: {e}", path.display())))?;
+            if let Some(file) = open_rule_path(path, self.strict)? {
                 ruleset = ruleset
-                    .add_rule(landlock::PathBeneath::new(file, rw_access))
-                    .map_err(|e| crate::LeewardError::Landlock(format!(
-                        "failed to add rw rule for {}: {e}",
-                        path.display()
-                    )))?;
+                    .add_rule(landlock::PathBeneath::new(file, writable))
+                    .map_err(|e| {
+                        crate::LeewardError::Landlock(format!(
+                            "failed to add rw rule for {}: {e}",
+                            path.display()
+                        ))
+                    })?;
                 tracing::debug!("added read-write access for {}", path.display());
             }
         }
 
-        // Add execute paths
+        // Add execute paths, unless execution is denied outright
         let exec_access = AccessFs::Execute | AccessFs::ReadFile;
-        for path in &self.exec_paths {
-            if path.exists() {
-                let file = std::fs::File::open(path)
-                    .map_err(|e| crate::LeewardError::Landlock(format!("failed to open {}: {e}", path.display())))?;
+        let exec_paths: &[PathBuf] = if self.deny_exec {
+            if !self.exec_paths.is_empty() {
+                tracing::warn!(
+                    count = self.exec_paths.len(),
+                    "deny_exec is set, ignoring configured exec paths"
+                );
+            }
+            &[]
+        } else {
+            &self.exec_paths
+        };
+
+        for path in exec_paths {
+            if let Some(file) = open_rule_path(path, self.strict)? {
                 ruleset = ruleset
                     .add_rule(landlock::PathBeneath::new(file, exec_access))
-                    .map_err(|e| crate::LeewardError::Landlock(format!(
-                        "failed to add exec rule for {}: {e}",
-                        path.display()
-                    )))?;
+                    .map_err(|e| {
+                        crate::LeewardError::Landlock(format!(
+                            "failed to add exec rule for {}: {e}",
+                            path.display()
+                        ))
+                    })?;
                 tracing::debug!("added execute access for {}", path.display());
             }
         }
 
-        // Enforce the ruleset
-        let status = ruleset
-            .restrict_self()
-            .map_err(|e| crate::LeewardError::Landlock(format!("failed to enforce landlock: {e}")))?;
+        Ok(ruleset)
+    }
 
-        match status.ruleset {
-            RulesetStatus::NotEnforced => {
-                tracing::warn!("Landlock ruleset could not be enforced");
-            }
-            RulesetStatus::PartiallyEnforced => {
-                tracing::info!("Landlock ruleset partially enforced");
-            }
-            RulesetStatus::FullyEnforced => {
-                tracing::info!("Landlock ruleset fully enforced");
-            }
+    /// TCP access rights the ruleset handles: each one given ports
+    fn net_access(&self) -> BitFlags<AccessNet> {
+        let mut access = BitFlags::empty();
+        if !self.tcp_connect_ports.is_empty() {
+            access |= AccessNet::ConnectTcp;
+        }
+        if !self.tcp_bind_ports.is_empty() {
+            access |= AccessNet::BindTcp;
         }
+        access
+    }
 
-        Ok(())
+    /// Add the rules for `tcp_connect_ports` and `tcp_bind_ports`
+    fn add_port_rules(&self, mut ruleset: RulesetCreated) -> Result<RulesetCreated> {
+        let ports = self
+            .tcp_connect_ports
+            .iter()
+            .map(|&port| (port, AccessNet::ConnectTcp))
+            .chain(
+                self.tcp_bind_ports
+                    .iter()
+                    .map(|&port| (port, AccessNet::BindTcp)),
+            );
+        for (port, access) in ports {
+            ruleset = ruleset.add_rule(NetPort::new(port, access)).map_err(|e| {
+                crate::LeewardError::Landlock(format!(
+                    "failed to add rule for TCP port {port}: {e}"
+                ))
+            })?;
+        }
+        Ok(ruleset)
+    }
+}
+
+/// What a ruleset built for the `probed` ABI enforced, given its status
+fn enforced(ruleset: &RulesetStatus, probed: LandlockStatus) -> LandlockStatus {
+    let enforcement = match *ruleset {
+        RulesetStatus::NotEnforced => Enforcement::NotEnforced,
+        RulesetStatus::PartiallyEnforced => Enforcement::Partial,
+        RulesetStatus::FullyEnforced => probed.enforcement,
+    };
+    LandlockStatus {
+        abi: probed
+            .abi
+            .filter(|_| enforcement != Enforcement::NotEnforced),
+        enforcement,
+    }
+}
+
+/// Open what `path` leads to for a rule, or `None` if it does not exist
+/// and `strict` is not set
+fn open_rule_path(path: &Path, strict: bool) -> Result<Option<std::fs::File>> {
+    let resolved = CanonicalPath::resolve(path, &PathPolicy::host())
+        .map_err(|e| crate::LeewardError::Landlock(e.to_string()))?;
+    if !resolved.exists() {
+        if strict {
+            return Err(crate::LeewardError::Landlock(format!(
+                "{} does not exist",
+                path.display()
+            )));
+        }
+        tracing::debug!(path = %path.display(), "skipping missing Landlock path");
+        return Ok(None);
     }
+    std::fs::File::open(resolved.as_path())
+        .map(Some)
+        .map_err(|e| {
+            crate::LeewardError::Landlock(format!("failed to open {}: {e}", path.display()))
+        })
 }
@@ -1,19 +1,168 @@
 //! Linux isolation primitives
 //!
 //! This module contains the core isolation mechanisms:
+//! - `cgroups` - cgroup v2 memory, CPU and process accounting for workers
 //! - `clone3` - clone3 syscall for process creation
+//! - `fatal` - how a worker reports the stage it died in
 //! - `namespace` - Linux namespaces (user, pid, mount, net, ipc)
-//! - `seccomp` - syscall filtering with SECCOMP_USER_NOTIF
+//! - `seccomp` - syscall filtering with `SECCOMP_USER_NOTIF`
 //! - `landlock` - filesystem access control
 //! - `mounts` - filesystem setup with bind mounts and tmpfs
+//! - `netns` - loopback and controlled egress in a new network namespace
+//! - `registry` - template roots in use, and reaping leaked ones
+//! - `template` - shared sandbox root cloned into each worker
+//!
+//! `seccomp`, `landlock` and `cgroups` are behind the cargo features of the
+//! same name.
 
+#[cfg(feature = "cgroups")]
+pub mod cgroups;
 pub mod clone3;
+pub mod fatal;
+#[cfg(feature = "landlock")]
 pub mod landlock;
 pub mod mounts;
 pub mod namespace;
+pub mod netns;
+pub mod registry;
+#[cfg(feature = "seccomp")]
 pub mod seccomp;
+pub mod template;
 
-pub use self::landlock::LandlockConfig;
-pub use self::mounts::MountConfig;
+#[cfg(feature = "cgroups")]
+pub use self::cgroups::{CgroupHandle, CpuStat, OomEventReceiver, PressureWatch};
+#[cfg(feature = "landlock")]
+pub use self::landlock::{Enforcement, LandlockConfig, LandlockStatus};
+pub use self::mounts::{BindOptions, MountConfig, MountPropagation, OverlayMount};
 pub use self::namespace::NamespaceConfig;
-pub use self::seccomp::SeccompConfig;
+pub use self::netns::{
+    ControlledNetwork, NetworkNamespaceSetup, NetworkSlot, NetworkSlots, ResolvConf,
+};
+#[cfg(feature = "seccomp")]
+pub use self::seccomp::{
+    ArgCmp, ArgConstraint, ArgWidth, SeccompConfig, SyscallProfile, SyscallRule,
+};
+pub use self::template::RootTemplate;
+
+use crate::Result;
+use std::sync::Arc;
+
+/// A single isolation step applied to the current process
+///
+/// Lets the worker assemble its setup sequence from whichever layers are
+/// compiled in, without naming optional modules directly.
+pub trait IsolationLayer {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Apply the layer to the current process
+    ///
+    /// # Errors
+    ///
+    /// The layer's error; the process is then left partly isolated and should
+    /// exit.
+    fn apply_layer(&self) -> Result<()>;
+
+    /// Whether a failure to apply this layer must abort worker setup
+    fn required(&self) -> bool {
+        true
+    }
+}
+
+impl<T: IsolationLayer + ?Sized> IsolationLayer for Arc<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        (**self).apply_layer()
+    }
+
+    fn required(&self) -> bool {
+        (**self).required()
+    }
+}
+
+impl IsolationLayer for NamespaceConfig {
+    fn name(&self) -> &'static str {
+        "namespaces"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.enter()
+    }
+}
+
+impl IsolationLayer for NetworkNamespaceSetup {
+    fn name(&self) -> &'static str {
+        "loopback"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        Self::configure_loopback()
+    }
+
+    // Without loopback only code that uses localhost breaks; the namespace
+    // still keeps the worker off the network
+    fn required(&self) -> bool {
+        false
+    }
+}
+
+impl IsolationLayer for ControlledNetwork {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.setup_worker_side()
+    }
+}
+
+impl IsolationLayer for ResolvConf {
+    fn name(&self) -> &'static str {
+        "resolv.conf"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.bind()
+    }
+}
+
+impl IsolationLayer for MountConfig {
+    fn name(&self) -> &'static str {
+        "mounts"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.apply()
+    }
+}
+
+#[cfg(feature = "landlock")]
+impl IsolationLayer for LandlockConfig {
+    fn name(&self) -> &'static str {
+        "landlock"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.apply().map(drop)
+    }
+
+    // Landlock is nice to have but not critical if we have seccomp +
+    // namespaces, unless a missing path was asked to be fatal
+    fn required(&self) -> bool {
+        self.strict
+    }
+}
+
+#[cfg(feature = "seccomp")]
+impl IsolationLayer for SeccompConfig {
+    fn name(&self) -> &'static str {
+        "seccomp"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.apply().map(drop)
+    }
+}
@@ -1,50 +1,364 @@
-//! Filesystem mounting and pivot_root
-
+//! Filesystem mounting and `pivot_root`
+//!
+//! Besides bind mounts and tmpfs, a [`MountConfig`] can stack an overlay
+//! over a read-only lower directory many workers share, each writing to an
+//! upper directory of its own, usually on a tmpfs, so what one worker
+//! changes is seen by no other and gone with it.
+//!
+//! It can also give the sandbox a `/dev` of its own with only the devices
+//! code expects, see [`MountConfig::minimal_dev`], and a `/proc`, see
+//! [`MountConfig::proc`]. Both are mounted before pivoting, so under a new
+//! root they go at `dev` and `proc` in it.
+
+use crate::config::paths::{CanonicalPath, PathPolicy};
+use crate::units::ByteSize;
 use crate::{LeewardError, Result};
-use std::path::PathBuf;
 use std::ffi::CString;
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 
 /// Configuration for filesystem mounts
 #[derive(Debug, Clone, Default)]
 pub struct MountConfig {
-    /// New root path for pivot_root
+    /// New root path for `pivot_root`
     pub new_root: PathBuf,
     /// Read-only bind mounts
-    pub ro_binds: Vec<(PathBuf, PathBuf)>,
+    pub ro_binds: Vec<(PathBuf, PathBuf, BindOptions)>,
     /// Read-write bind mounts
-    pub rw_binds: Vec<(PathBuf, PathBuf)>,
+    pub rw_binds: Vec<(PathBuf, PathBuf, BindOptions)>,
     /// tmpfs mounts with size limits
-    pub tmpfs: Vec<(PathBuf, u64)>,
+    pub tmpfs: Vec<(PathBuf, ByteSize)>,
+    /// Overlay mounts, made after the tmpfs mounts their upper directories
+    /// are usually on
+    pub overlays: Vec<OverlayMount>,
+    /// Where to make a minimal `/dev`, after the overlays, with the names of
+    /// the [`DEV_NODES`] it holds
+    pub dev: Option<(PathBuf, Vec<String>)>,
+    /// Mount a new `devpts` instance at `pts` under [`Self::dev`], for
+    /// pseudo-terminals
+    pub allow_pts: bool,
+    /// Fail on a bind source that does not exist instead of skipping it
+    pub strict: bool,
+    /// Where to mount `proc`, with its `hidepid` setting, last before
+    /// `pivot_root`
+    pub proc: Option<(PathBuf, u8)>,
+    /// How mount events travel between this mount namespace and the one it
+    /// was copied from, set on every mount before anything is mounted
+    pub propagation: MountPropagation,
+}
+
+/// Propagation of mount events between a mount namespace and its peers
+///
+/// A namespace copied from one whose mounts are `shared`, the systemd
+/// default, starts as their peer, so mounts made on either side show up
+/// on the other.
+///
+/// With a [`MountConfig::new_root`], only [`Private`](Self::Private) and
+/// [`Slave`](Self::Slave) are allowed: `pivot_root` refuses a root whose
+/// mounts are shared, and the bind of the new root onto itself would
+/// otherwise show up outside.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MountPropagation {
+    /// Nothing travels either way
+    #[default]
+    Private,
+    /// Mounts from outside come in, but none go out, as for a container
+    /// that must see volumes mounted after it started
+    Slave,
+    /// Mounts travel both ways
+    Shared,
+    /// Left as the namespace was copied
+    Unchanged,
+}
+
+impl MountPropagation {
+    /// The flag setting this propagation, if it changes anything
+    const fn flag(self) -> Option<libc::c_ulong> {
+        match self {
+            Self::Private => Some(libc::MS_PRIVATE),
+            Self::Slave => Some(libc::MS_SLAVE),
+            Self::Shared => Some(libc::MS_SHARED),
+            Self::Unchanged => None,
+        }
+    }
+}
+
+/// An overlay of `upper` on `lower`, mounted at `merged`
+///
+/// Writes go to `upper`, and `work` is the scratch directory overlayfs
+/// needs on the same filesystem as `upper`. The directories are created
+/// if missing. `merged` may be `lower` itself, to make a read-only tree
+/// writable where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayMount {
+    pub lower: PathBuf,
+    pub upper: PathBuf,
+    pub work: PathBuf,
+    pub merged: PathBuf,
+}
+
+/// Mount flags of a bind mount besides read-only
+///
+/// The default, for library and data directories, is `nosuid` and `nodev`
+/// but executable, bound with everything mounted under the source. The
+/// flags are set on the bind itself, not on the mounts under it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct BindOptions {
+    /// Ignore set-user-ID and set-group-ID bits
+    pub nosuid: bool,
+    /// Make device nodes unusable
+    pub nodev: bool,
+    /// Refuse to execute files
+    pub noexec: bool,
+    /// Bind the mounts under the source too
+    pub rec: bool,
+}
+
+impl Default for BindOptions {
+    fn default() -> Self {
+        Self {
+            nosuid: true,
+            nodev: true,
+            noexec: false,
+            rec: true,
+        }
+    }
+}
+
+impl BindOptions {
+    /// For binding a device node, which `nodev` would leave unusable
+    pub const DEVICE: Self = Self {
+        nosuid: true,
+        nodev: false,
+        noexec: false,
+        rec: true,
+    };
+
+    /// For scratch directories such as `/tmp`, where nothing should run
+    pub const SCRATCH: Self = Self {
+        nosuid: true,
+        nodev: true,
+        noexec: true,
+        rec: true,
+    };
+
+    /// The per-mount flags these ask for
+    fn flags(self) -> libc::c_ulong {
+        [
+            (self.nosuid, libc::MS_NOSUID),
+            (self.nodev, libc::MS_NODEV),
+            (self.noexec, libc::MS_NOEXEC),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag)
+    }
 }
 
+/// Character devices in a minimal `/dev`, with their major and minor
+/// numbers
+pub const DEV_NODES: [(&str, u32, u32); 5] = [
+    ("null", 1, 3),
+    ("zero", 1, 5),
+    ("full", 1, 7),
+    ("random", 1, 8),
+    ("urandom", 1, 9),
+];
+
+/// Links in a minimal `/dev` to the standard streams, and to where they lead
+pub const DEV_LINKS: [(&str, &str); 4] = [
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+    ("fd", "/proc/self/fd"),
+];
+
+/// The highest `hidepid` setting: processes of other users are not listed
+/// in `/proc` at all
+pub const HIDEPID_INVISIBLE: u8 = 2;
+
+/// Size of the tmpfs a minimal `/dev` is on, which holds only nodes and links
+const DEV_TMPFS_SIZE: ByteSize = ByteSize::kib(64);
+
+/// Where [`MountConfig::python_overlay`] puts its upper and work
+/// directories, on a tmpfs of their own
+pub const OVERLAY_SCRATCH: &str = "/run/leeward/overlay";
+
 impl MountConfig {
-    /// Add a read-only bind mount
+    /// Add a read-only bind mount with the default [`BindOptions`]
     #[must_use]
-    pub fn ro_bind(mut self, src: impl Into<PathBuf>, dst: impl Into<PathBuf>) -> Self {
-        self.ro_binds.push((src.into(), dst.into()));
+    pub fn ro_bind(self, src: impl Into<PathBuf>, dst: impl Into<PathBuf>) -> Self {
+        self.ro_bind_with_opts(src, dst, BindOptions::default())
+    }
+
+    /// Add a read-write bind mount with the default [`BindOptions`]
+    #[must_use]
+    pub fn rw_bind(self, src: impl Into<PathBuf>, dst: impl Into<PathBuf>) -> Self {
+        self.rw_bind_with_opts(src, dst, BindOptions::default())
+    }
+
+    /// Add a read-only bind mount with `options`
+    #[must_use]
+    pub fn ro_bind_with_opts(
+        mut self,
+        src: impl Into<PathBuf>,
+        dst: impl Into<PathBuf>,
+        options: BindOptions,
+    ) -> Self {
+        self.ro_binds.push((src.into(), dst.into(), options));
         self
     }
 
-    /// Add a read-write bind mount
+    /// Add a read-write bind mount with `options`
     #[must_use]
-    pub fn rw_bind(mut self, src: impl Into<PathBuf>, dst: impl Into<PathBuf>) -> Self {
-        self.rw_binds.push((src.into(), dst.into()));
+    pub fn rw_bind_with_opts(
+        mut self,
+        src: impl Into<PathBuf>,
+        dst: impl Into<PathBuf>,
+        options: BindOptions,
+    ) -> Self {
+        self.rw_binds.push((src.into(), dst.into(), options));
         self
     }
 
-    /// Add a tmpfs mount with size limit in bytes
+    /// Add a tmpfs mount with size limit
     #[must_use]
-    pub fn tmpfs(mut self, path: impl Into<PathBuf>, size_bytes: u64) -> Self {
-        self.tmpfs.push((path.into(), size_bytes));
+    pub fn tmpfs(mut self, path: impl Into<PathBuf>, size: ByteSize) -> Self {
+        self.tmpfs.push((path.into(), size));
         self
     }
 
-    /// Setup all mounts and perform pivot_root
+    /// Add an overlay of `upper` on `lower` at `merged`, with `work` as
+    /// overlayfs's scratch directory; see [`OverlayMount`]
+    #[must_use]
+    pub fn overlay(
+        mut self,
+        lower: impl Into<PathBuf>,
+        upper: impl Into<PathBuf>,
+        work: impl Into<PathBuf>,
+        merged: impl Into<PathBuf>,
+    ) -> Self {
+        self.overlays.push(OverlayMount {
+            lower: lower.into(),
+            upper: upper.into(),
+            work: work.into(),
+            merged: merged.into(),
+        });
+        self
+    }
+
+    /// Mounts making the interpreter tree at `python_root` writable where
+    /// it is, with writes held in a tmpfs of `tmpfs_size` at
+    /// [`OVERLAY_SCRATCH`]
+    ///
+    /// Code can then install packages or write bytecode caches next to the
+    /// standard library without touching the host's copy, which every
+    /// worker shares, and what it writes is gone with the worker.
+    #[must_use]
+    pub fn python_overlay(python_root: &Path, tmpfs_size: ByteSize) -> Self {
+        let scratch = Path::new(OVERLAY_SCRATCH);
+        Self::default().tmpfs(scratch, tmpfs_size).overlay(
+            python_root,
+            scratch.join("upper"),
+            scratch.join("work"),
+            python_root,
+        )
+    }
+
+    /// Mounts giving `dst` a minimal `/dev`: a `nosuid`, `noexec` tmpfs
+    /// holding only [`DEV_NODES`] and the [`DEV_LINKS`] to the standard
+    /// streams
+    ///
+    /// Devices are made with `mknod` where this process may, as root on
+    /// the host, and bound from the host's `/dev` where it may not, as in a
+    /// user namespace. Like every mount this has to happen before the
+    /// seccomp filter goes on, which denies `mount` and `mknod`.
+    #[must_use]
+    pub fn minimal_dev(dst: &Path) -> Self {
+        let nodes = DEV_NODES.iter().map(|(name, _, _)| (*name).to_owned());
+        Self {
+            dev: Some((dst.to_path_buf(), nodes.collect())),
+            ..Self::default()
+        }
+    }
+
+    /// Mounts giving `dst` a `proc` filesystem, `nosuid`, `noexec` and
+    /// `nodev`, with `hidepid` 0 to list every process, 1 to list them but
+    /// keep other users' details to themselves, or 2 to not list other
+    /// users' processes at all
+    ///
+    /// `proc` shows the pid namespace of the process mounting it, so mount
+    /// it from a process in the namespace, such as the first one cloned
+    /// into it, not from the one that unshared it.
+    #[must_use]
+    pub fn proc(dst: &Path, hidepid: u8) -> Self {
+        Self {
+            proc: Some((dst.to_path_buf(), hidepid)),
+            ..Self::default()
+        }
+    }
+
+    /// See [`MountConfig::allow_pts`]
+    #[must_use]
+    pub const fn allow_pts(mut self, allow: bool) -> Self {
+        self.allow_pts = allow;
+        self
+    }
+
+    /// See [`MountConfig::propagation`]
+    #[must_use]
+    pub const fn propagation(mut self, propagation: MountPropagation) -> Self {
+        self.propagation = propagation;
+        self
+    }
+
+    /// See [`MountConfig::strict`]
+    #[must_use]
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Setup all mounts and perform `pivot_root`
+    ///
+    /// Run it in a mount namespace of its own: it first sets every mount's
+    /// [`MountPropagation`], private unless asked otherwise, so nothing it
+    /// mounts shows up anywhere else, and mounts nothing if that fails. The
+    /// new root need not be a mount point; it is bound onto itself, as
+    /// `pivot_root` needs.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Mount`] if a mount fails, and without mounting anything
+    /// if there is a new root and the propagation is neither private nor slave.
     pub fn apply(&self) -> Result<()> {
+        if self.new_root != PathBuf::new()
+            && matches!(
+                self.propagation,
+                MountPropagation::Shared | MountPropagation::Unchanged
+            )
+        {
+            return Err(LeewardError::Mount(format!(
+                "a new root needs private or slave mounts, not {:?}",
+                self.propagation
+            )));
+        }
+        // With shared propagation, the systemd default, mounts would
+        // otherwise travel between the sandbox and the host both ways
+        if let Some(flag) = self.propagation.flag() {
+            set_propagation(std::path::Path::new("/"), flag)?;
+        }
         self.setup_root()?;
         self.setup_binds()?;
         self.setup_tmpfs()?;
+        self.setup_overlays()?;
+        if let Some((dev, nodes)) = &self.dev {
+            setup_dev(dev, nodes, self.allow_pts)?;
+        }
+        if let Some((path, hidepid)) = &self.proc {
+            mount_proc(path, *hidepid)?;
+        }
         self.do_pivot_root()?;
         Ok(())
     }
@@ -57,11 +371,20 @@ impl MountConfig {
             std::fs::create_dir_all(&self.new_root)
                 .map_err(|e| LeewardError::Mount(format!("failed to create new root: {e}")))?;
 
+            // pivot_root needs a mount point, which a plain directory is not
+            let itself = BindOptions {
+                nosuid: false,
+                nodev: false,
+                noexec: false,
+                rec: true,
+            };
+            mount_bind(&self.new_root, &self.new_root, itself)?;
+
             // Create essential directories
             for dir in &["proc", "sys", "dev", "tmp", "home", "home/sandbox"] {
                 let path = self.new_root.join(dir);
                 std::fs::create_dir_all(&path)
-                    .map_err(|e| LeewardError::Mount(format!("failed to create {}: {e}", dir)))?;
+                    .map_err(|e| LeewardError::Mount(format!("failed to create {dir}: {e}")))?;
             }
         }
 
@@ -69,35 +392,39 @@ impl MountConfig {
     }
 
     fn setup_binds(&self) -> Result<()> {
-        for (src, dst) in &self.ro_binds {
-            tracing::debug!(?src, ?dst, "ro bind mount");
+        for (src, dst, options) in &self.ro_binds {
+            tracing::debug!(?src, ?dst, ?options, "ro bind mount");
 
-            if src.exists() {
+            if let Some(src) = bind_source(src, self.strict)? {
+                let src = src.as_path();
                 // Ensure destination exists
                 if let Some(parent) = dst.parent() {
-                    std::fs::create_dir_all(parent)
-                        .map_err(|e| LeewardError::Mount(format!("failed to create mount point: {e}")))?;
+                    std::fs::create_dir_all(parent).map_err(|e| {
+                        LeewardError::Mount(format!("failed to create mount point: {e}"))
+                    })?;
                 }
 
                 // Bind mount
-                mount_bind(src, dst)?;
+                mount_bind(src, dst, *options)?;
                 // Remount read-only
                 mount_remount_ro(dst)?;
             }
         }
 
-        for (src, dst) in &self.rw_binds {
-            tracing::debug!(?src, ?dst, "rw bind mount");
+        for (src, dst, options) in &self.rw_binds {
+            tracing::debug!(?src, ?dst, ?options, "rw bind mount");
 
-            if src.exists() {
+            if let Some(src) = bind_source(src, self.strict)? {
+                let src = src.as_path();
                 // Ensure destination exists
                 if let Some(parent) = dst.parent() {
-                    std::fs::create_dir_all(parent)
-                        .map_err(|e| LeewardError::Mount(format!("failed to create mount point: {e}")))?;
+                    std::fs::create_dir_all(parent).map_err(|e| {
+                        LeewardError::Mount(format!("failed to create mount point: {e}"))
+                    })?;
                 }
 
                 // Bind mount
-                mount_bind(src, dst)?;
+                mount_bind(src, dst, *options)?;
             }
         }
         Ok(())
@@ -105,23 +432,39 @@ impl MountConfig {
 
     fn setup_tmpfs(&self) -> Result<()> {
         for (path, size) in &self.tmpfs {
-            tracing::debug!(?path, size, "tmpfs mount");
+            tracing::debug!(?path, %size, "tmpfs mount");
 
             // Ensure mount point exists
-            std::fs::create_dir_all(path)
-                .map_err(|e| LeewardError::Mount(format!("failed to create tmpfs mount point: {e}")))?;
+            std::fs::create_dir_all(path).map_err(|e| {
+                LeewardError::Mount(format!("failed to create tmpfs mount point: {e}"))
+            })?;
 
             mount_tmpfs(path, *size)?;
         }
         Ok(())
     }
 
+    fn setup_overlays(&self) -> Result<()> {
+        for overlay in &self.overlays {
+            tracing::debug!(?overlay, "overlay mount");
+
+            for dir in [&overlay.upper, &overlay.work, &overlay.merged] {
+                std::fs::create_dir_all(dir).map_err(|e| {
+                    LeewardError::Mount(format!("failed to create {}: {e}", dir.display()))
+                })?;
+            }
+            mount_overlay(overlay)?;
+        }
+        Ok(())
+    }
+
     fn do_pivot_root(&self) -> Result<()> {
         tracing::debug!(root = ?self.new_root, "pivot_root");
 
         if self.new_root == PathBuf::new() {
             return Ok(()); // Skip pivot_root if no new root specified
         }
+        let host = identity(std::path::Path::new("/"))?;
 
         let put_old = self.new_root.join("put_old");
         std::fs::create_dir_all(&put_old)
@@ -140,20 +483,83 @@ impl MountConfig {
         std::fs::remove_dir("/put_old")
             .map_err(|e| LeewardError::Mount(format!("failed to remove put_old: {e}")))?;
 
+        // The root may be a directory on the host's filesystem, bound onto
+        // itself, but never the host's root
+        if identity(std::path::Path::new("/"))? == host {
+            return Err(LeewardError::Mount(
+                "/ is still the host's root after pivot_root".into(),
+            ));
+        }
         Ok(())
     }
 }
 
 // Helper functions for mount operations
 
-fn path_to_cstring(path: &std::path::Path) -> Result<CString> {
+/// Where a bind source leads, or `None` if there is nothing there to bind
+///
+/// Relative sources and dangling symlinks fail; sources that are simply
+/// missing fail if `strict` is set and are skipped with a warning if not.
+pub(crate) fn bind_source(src: &std::path::Path, strict: bool) -> Result<Option<CanonicalPath>> {
+    let src = CanonicalPath::resolve(src, &PathPolicy::host())
+        .map_err(|e| LeewardError::Mount(format!("bind source {e}")))?;
+    if !src.exists() {
+        if strict {
+            return Err(LeewardError::Mount(format!(
+                "bind source {} does not exist",
+                src.raw().display()
+            )));
+        }
+        tracing::warn!(src = %src.raw().display(), "skipping missing bind source");
+        return Ok(None);
+    }
+    Ok(Some(src))
+}
+
+/// Device of the filesystem `path` is on
+pub(crate) fn device(path: &std::path::Path) -> Result<libc::dev_t> {
+    nix::sys::stat::stat(path)
+        .map(|stat| stat.st_dev)
+        .map_err(|e| LeewardError::Mount(format!("failed to stat {}: {e}", path.display())))
+}
+
+/// Device and inode of `path`
+fn identity(path: &std::path::Path) -> Result<(libc::dev_t, libc::ino_t)> {
+    nix::sys::stat::stat(path)
+        .map(|stat| (stat.st_dev, stat.st_ino))
+        .map_err(|e| LeewardError::Mount(format!("failed to stat {}: {e}", path.display())))
+}
+
+/// Fail unless `/` has left `host`, the device of the root pivoted away
+/// from
+///
+/// A root that was never mounted over, so still on the host's filesystem,
+/// would leave the sandbox looking at host directories.
+pub(crate) fn check_pivoted(host: libc::dev_t) -> Result<()> {
+    if device(std::path::Path::new("/"))? == host {
+        return Err(LeewardError::Mount(
+            "/ is still on the host root's filesystem after pivot_root".into(),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn path_to_cstring(path: &std::path::Path) -> Result<CString> {
     CString::new(path.as_os_str().as_bytes())
         .map_err(|e| LeewardError::Mount(format!("invalid path {}: {}", path.display(), e)))
 }
 
-fn mount_bind(src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
+/// Bind `src` at `dst`, then set the flags `options` ask for on the bind
+///
+/// The kernel ignores per-mount flags when binding, so they take a remount.
+pub(crate) fn mount_bind(
+    src: &std::path::Path,
+    dst: &std::path::Path,
+    options: BindOptions,
+) -> Result<()> {
     let src_c = path_to_cstring(src)?;
     let dst_c = path_to_cstring(dst)?;
+    let rec = if options.rec { libc::MS_REC } else { 0 };
 
     // SAFETY: mount syscall with bind flag
     let ret = unsafe {
@@ -161,7 +567,7 @@ fn mount_bind(src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
             src_c.as_ptr(),
             dst_c.as_ptr(),
             std::ptr::null(),
-            libc::MS_BIND | libc::MS_REC,
+            libc::MS_BIND | rec,
             std::ptr::null(),
         )
     };
@@ -175,26 +581,106 @@ fn mount_bind(src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
         )));
     }
 
+    match options.flags() {
+        0 => Ok(()),
+        flags => remount(dst, flags),
+    }
+}
+
+pub(crate) fn mount_remount_ro(path: &std::path::Path) -> Result<()> {
+    remount(path, libc::MS_RDONLY)
+}
+
+/// Add the per-mount `flags` to the mount at `path`
+///
+/// A remount replaces every per-mount flag, so those the mount has are
+/// kept; in a user namespace, dropping one inherited from the host fails.
+fn remount(path: &std::path::Path, flags: libc::c_ulong) -> Result<()> {
+    let path_c = path_to_cstring(path)?;
+    let current = mount_flags(path)?;
+
+    // SAFETY: mount syscall changing per-mount flags only
+    let ret = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            path_c.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REMOUNT | current | flags,
+            std::ptr::null(),
+        )
+    };
+
+    if ret != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to remount {} with flags {flags:#x}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
     Ok(())
 }
 
-fn mount_remount_ro(path: &std::path::Path) -> Result<()> {
+/// The per-mount flags of the mount at `path`, as `mount` takes them
+pub(crate) fn mount_flags(path: &std::path::Path) -> Result<libc::c_ulong> {
+    const FLAGS: [(libc::c_ulong, libc::c_ulong); 7] = [
+        (libc::ST_RDONLY, libc::MS_RDONLY),
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ];
+    let path_c = path_to_cstring(path)?;
+    // SAFETY: statvfs writes only the struct it is given
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: statvfs with a valid path and struct
+    if unsafe { libc::statvfs(path_c.as_ptr(), &raw mut stat) } != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to statvfs {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(FLAGS
+        .into_iter()
+        .filter(|(st, _)| stat.f_flag & st != 0)
+        .fold(0, |flags, (_, ms)| flags | ms))
+}
+
+/// Mount options giving a tmpfs exactly `size`
+///
+/// In bytes, since rounding to a coarser unit would turn anything under
+/// it into `size=0`, which tmpfs takes as no limit at all.
+#[must_use]
+pub fn tmpfs_options(size: ByteSize) -> String {
+    format!("size={}", size.bytes())
+}
+
+/// Stop mounts under `path` propagating to or from other namespaces
+pub(crate) fn make_rprivate(path: &std::path::Path) -> Result<()> {
+    set_propagation(path, libc::MS_PRIVATE)
+}
+
+/// Give every mount under `path` the propagation `flag` sets
+fn set_propagation(path: &std::path::Path, flag: libc::c_ulong) -> Result<()> {
     let path_c = path_to_cstring(path)?;
 
-    // SAFETY: mount syscall to remount read-only
+    // SAFETY: mount syscall changing propagation only
     let ret = unsafe {
         libc::mount(
             std::ptr::null(),
             path_c.as_ptr(),
             std::ptr::null(),
-            libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+            libc::MS_REC | flag,
             std::ptr::null(),
         )
     };
 
     if ret != 0 {
         return Err(LeewardError::Mount(format!(
-            "failed to remount {} read-only: {}",
+            "failed to set the propagation of mounts under {} ({flag:#x}): {}",
             path.display(),
             std::io::Error::last_os_error()
         )));
@@ -203,13 +689,17 @@ fn mount_remount_ro(path: &std::path::Path) -> Result<()> {
     Ok(())
 }
 
-fn mount_tmpfs(path: &std::path::Path, size: u64) -> Result<()> {
+pub(crate) fn mount_tmpfs(path: &std::path::Path, size: ByteSize) -> Result<()> {
+    mount_tmpfs_with(path, size, 0)
+}
+
+/// Mount a tmpfs of `size` at `path` with the `MS_*` mount `flags`
+fn mount_tmpfs_with(path: &Path, size: ByteSize, flags: libc::c_ulong) -> Result<()> {
     let path_c = path_to_cstring(path)?;
-    let fstype = CString::new("tmpfs")
-        .map_err(|e| LeewardError::Mount(format!("invalid fstype: {e}")))?;
+    let fstype =
+        CString::new("tmpfs").map_err(|e| LeewardError::Mount(format!("invalid fstype: {e}")))?;
 
-    let size_mb = size / (1024 * 1024);
-    let options = CString::new(format!("size={}M", size_mb))
+    let options = CString::new(tmpfs_options(size))
         .map_err(|e| LeewardError::Mount(format!("invalid options: {e}")))?;
 
     // SAFETY: mount syscall with tmpfs
@@ -218,8 +708,8 @@ fn mount_tmpfs(path: &std::path::Path, size: u64) -> Result<()> {
             fstype.as_ptr(),
             path_c.as_ptr(),
             fstype.as_ptr(),
-            0,
-            options.as_ptr() as *const libc::c_void,
+            flags,
+            options.as_ptr().cast::<libc::c_void>(),
         )
     };
 
@@ -234,7 +724,237 @@ fn mount_tmpfs(path: &std::path::Path, size: u64) -> Result<()> {
     Ok(())
 }
 
-fn pivot_root(new_root: &std::path::Path, put_old: &std::path::Path) -> Result<()> {
+/// Mount a `nosuid`, `noexec` tmpfs at `dev` and fill it with the
+/// [`DEV_NODES`] in `nodes` and [`DEV_LINKS`], and a `devpts` instance if
+/// `pts` is set
+fn setup_dev(dev: &Path, nodes: &[String], pts: bool) -> Result<()> {
+    tracing::debug!(?dev, ?nodes, pts, "minimal /dev");
+    let nodes = dev_nodes(nodes)?;
+    let create = |path: &Path| {
+        std::fs::create_dir_all(path)
+            .map_err(|e| LeewardError::Mount(format!("failed to create {}: {e}", path.display())))
+    };
+    create(dev)?;
+    // Not nodev: the devices on it are what it is for
+    mount_tmpfs_with(dev, DEV_TMPFS_SIZE, libc::MS_NOSUID | libc::MS_NOEXEC)?;
+    chmod(dev, 0o755)?;
+
+    for (name, major, minor) in nodes {
+        make_device(&dev.join(name), major, minor)?;
+    }
+    dev_links(dev)?;
+
+    if pts {
+        let pts_dir = dev.join("pts");
+        create(&pts_dir)?;
+        mount_devpts(&pts_dir)?;
+        std::os::unix::fs::symlink("pts/ptmx", dev.join("ptmx")).map_err(|e| {
+            LeewardError::Mount(format!("failed to link {}/ptmx: {e}", dev.display()))
+        })?;
+    }
+    Ok(())
+}
+
+/// The [`DEV_NODES`] named in `names`
+fn dev_nodes(names: &[String]) -> Result<Vec<(&'static str, u32, u32)>> {
+    names
+        .iter()
+        .map(|name| {
+            DEV_NODES
+                .iter()
+                .find(|(known, _, _)| known == name)
+                .copied()
+                .ok_or_else(|| {
+                    LeewardError::Mount(format!(
+                        "{name} is not one of the devices a minimal /dev can hold"
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Make the character device `path`, or bind the host's device of the same
+/// name over an empty file where `mknod` is not allowed
+fn make_device(path: &Path, major: u32, minor: u32) -> Result<()> {
+    let path_c = path_to_cstring(path)?;
+    // SAFETY: mknod with a valid path
+    let ret = unsafe {
+        libc::mknod(
+            path_c.as_ptr(),
+            libc::S_IFCHR | 0o666,
+            libc::makedev(major, minor),
+        )
+    };
+    if ret == 0 {
+        // mknod's mode is masked by the umask
+        return chmod(path, 0o666);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::EPERM) {
+        return Err(LeewardError::Mount(format!(
+            "failed to make {}: {error}",
+            path.display()
+        )));
+    }
+
+    let host = Path::new("/dev").join(path.file_name().unwrap_or_default());
+    std::fs::File::create(path).map_err(|e| {
+        LeewardError::Mount(format!(
+            "failed to create mount point {}: {e}",
+            path.display()
+        ))
+    })?;
+    mount_bind(&host, path, BindOptions::DEVICE)
+}
+
+/// Link the standard streams in `dev`, see [`DEV_LINKS`]
+pub(crate) fn dev_links(dev: &Path) -> Result<()> {
+    for (name, target) in DEV_LINKS {
+        std::os::unix::fs::symlink(target, dev.join(name)).map_err(|e| {
+            LeewardError::Mount(format!("failed to link {}/{name}: {e}", dev.display()))
+        })?;
+    }
+    Ok(())
+}
+
+fn chmod(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| LeewardError::Mount(format!("failed to chmod {}: {e}", path.display())))
+}
+
+/// Mount `proc` at `path` with `hidepid`, see [`MountConfig::proc`]
+pub(crate) fn mount_proc(path: &Path, hidepid: u8) -> Result<()> {
+    tracing::debug!(?path, hidepid, "proc mount");
+    if hidepid > HIDEPID_INVISIBLE {
+        return Err(LeewardError::Mount(format!(
+            "hidepid must be 0, 1 or 2, not {hidepid}"
+        )));
+    }
+    std::fs::create_dir_all(path)
+        .map_err(|e| LeewardError::Mount(format!("failed to create {}: {e}", path.display())))?;
+
+    let path_c = path_to_cstring(path)?;
+    let fstype =
+        CString::new("proc").map_err(|e| LeewardError::Mount(format!("invalid fstype: {e}")))?;
+    let options = CString::new(format!("hidepid={hidepid}"))
+        .map_err(|e| LeewardError::Mount(format!("invalid options: {e}")))?;
+
+    // SAFETY: mount syscall with proc
+    let ret = unsafe {
+        libc::mount(
+            fstype.as_ptr(),
+            path_c.as_ptr(),
+            fstype.as_ptr(),
+            libc::MS_NOSUID | libc::MS_NOEXEC | libc::MS_NODEV,
+            options.as_ptr().cast::<libc::c_void>(),
+        )
+    };
+
+    if ret != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to mount proc at {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+fn mount_devpts(path: &Path) -> Result<()> {
+    let path_c = path_to_cstring(path)?;
+    let fstype =
+        CString::new("devpts").map_err(|e| LeewardError::Mount(format!("invalid fstype: {e}")))?;
+    // A new instance, so the sandbox sees none of the host's terminals
+    let options = CString::new("newinstance,ptmxmode=0666,mode=0620")
+        .map_err(|e| LeewardError::Mount(format!("invalid options: {e}")))?;
+
+    // SAFETY: mount syscall with devpts
+    let ret = unsafe {
+        libc::mount(
+            fstype.as_ptr(),
+            path_c.as_ptr(),
+            fstype.as_ptr(),
+            libc::MS_NOSUID | libc::MS_NOEXEC,
+            options.as_ptr().cast::<libc::c_void>(),
+        )
+    };
+
+    if ret != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to mount devpts at {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Mount options for `overlay`
+///
+/// # Errors
+///
+/// [`LeewardError::Mount`] for a directory with a comma, colon or backslash in
+/// its path, which overlayfs would read as a separator.
+pub fn overlay_options(overlay: &OverlayMount) -> Result<String> {
+    let mut options = String::new();
+    for (key, dir) in [
+        ("lowerdir", &overlay.lower),
+        ("upperdir", &overlay.upper),
+        ("workdir", &overlay.work),
+    ] {
+        let dir = dir
+            .to_str()
+            .filter(|dir| !dir.contains([',', ':', '\\']))
+            .ok_or_else(|| {
+                LeewardError::Mount(format!(
+                    "overlay {key} {} cannot be given to overlayfs",
+                    dir.display()
+                ))
+            })?;
+        if !options.is_empty() {
+            options.push(',');
+        }
+        options.push_str(key);
+        options.push('=');
+        options.push_str(dir);
+    }
+    Ok(options)
+}
+
+pub(crate) fn mount_overlay(overlay: &OverlayMount) -> Result<()> {
+    let merged_c = path_to_cstring(&overlay.merged)?;
+    let fstype =
+        CString::new("overlay").map_err(|e| LeewardError::Mount(format!("invalid fstype: {e}")))?;
+
+    let options = CString::new(overlay_options(overlay)?)
+        .map_err(|e| LeewardError::Mount(format!("invalid options: {e}")))?;
+
+    // SAFETY: mount syscall with overlay
+    let ret = unsafe {
+        libc::mount(
+            fstype.as_ptr(),
+            merged_c.as_ptr(),
+            fstype.as_ptr(),
+            0,
+            options.as_ptr().cast::<libc::c_void>(),
+        )
+    };
+
+    if ret != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to mount overlay at {}: {}",
+            overlay.merged.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn pivot_root(new_root: &std::path::Path, put_old: &std::path::Path) -> Result<()> {
     let new_root_c = path_to_cstring(new_root)?;
     let put_old_c = path_to_cstring(put_old)?;
 
@@ -257,13 +977,11 @@ fn pivot_root(new_root: &std::path::Path, put_old: &std::path::Path) -> Result<(
     Ok(())
 }
 
-fn umount2(path: &std::path::Path, flags: i32) -> Result<()> {
+pub(crate) fn umount2(path: &std::path::Path, flags: i32) -> Result<()> {
     let path_c = path_to_cstring(path)?;
 
     // SAFETY: umount2 syscall
-    let ret = unsafe {
-        libc::umount2(path_c.as_ptr(), flags)
-    };
+    let ret = unsafe { libc::umount2(path_c.as_ptr(), flags) };
 
     if ret != 0 {
         return Err(LeewardError::Mount(format!(
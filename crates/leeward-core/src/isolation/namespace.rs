@@ -1,10 +1,42 @@
 //! Linux namespace isolation
+//!
+//! A new user namespace starts with no ids mapped, so everything in it
+//! runs as the overflow uid and anything needing a real one, such as
+//! `chown`, fails with `EPERM`. The maps of a [`NamespaceConfig`] are
+//! written right after the namespace is created: by the process itself
+//! after [`NamespaceConfig::enter`], or by its parent with
+//! [`NamespaceConfig::write_id_maps`] when the namespace came from
+//! [`clone_worker_with`](super::clone3::clone_worker_with).
+//!
+//! A new time namespace shifts `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME` by
+//! offsets that can only be set before any process is in it. `unshare`
+//! leaves the caller where it is and puts its children in the namespace,
+//! so [`NamespaceConfig::enter`] sets them with [`freeze_clocks`] before
+//! there are any: the children's clocks start from 0 there.
+//!
+//! A new pid namespace likewise takes the caller's children, the first of
+//! which is its init: once that exits, nothing more can be forked into the
+//! namespace. [`spawn_init`] starts one that stays, doing in the namespace
+//! what only a process in it can, such as mounting its `/proc`.
 
 use crate::{LeewardError, Result};
+use libc::pid_t;
 use nix::sched::CloneFlags;
+use nix::unistd::{getgid, getuid};
+use std::fmt::Write;
+use std::io::{Read, Write as _};
+use std::path::PathBuf;
+
+/// Uid and gid the sandbox runs as under the default maps
+pub const SANDBOX_ID: u32 = 1000;
+
+/// One line of an id map: the first id inside the namespace, the host id
+/// it maps to, and how many ids follow
+pub type IdMapping = (u32, u32, u32);
 
 /// Configuration for namespace isolation
 #[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct NamespaceConfig {
     /// Create new user namespace
     pub user: bool,
@@ -18,6 +50,15 @@ pub struct NamespaceConfig {
     pub ipc: bool,
     /// Create new UTS namespace
     pub uts: bool,
+    /// Create new time namespace (Linux 5.6+), which only children enter,
+    /// their monotonic and boot clocks starting from 0
+    pub time: bool,
+    /// Uids mapped into the new user namespace, as (sandbox uid, host
+    /// uid, count)
+    pub uid_map: Vec<IdMapping>,
+    /// Gids mapped into the new user namespace, as (sandbox gid, host
+    /// gid, count)
+    pub gid_map: Vec<IdMapping>,
 }
 
 impl Default for NamespaceConfig {
@@ -29,12 +70,26 @@ impl Default for NamespaceConfig {
             net: true,
             ipc: true,
             uts: true,
+            time: false,
+            uid_map: vec![(SANDBOX_ID, getuid().as_raw(), 1)],
+            gid_map: vec![(SANDBOX_ID, getgid().as_raw(), 1)],
         }
     }
 }
 
 impl NamespaceConfig {
-    /// Convert to nix CloneFlags
+    /// Every namespace, with `sandbox_uid` inside mapped to `host_uid` and
+    /// the same id as a gid to the caller's gid
+    #[must_use]
+    pub fn single_user_map(host_uid: u32, sandbox_uid: u32) -> Self {
+        Self {
+            uid_map: vec![(sandbox_uid, host_uid, 1)],
+            gid_map: vec![(sandbox_uid, getgid().as_raw(), 1)],
+            ..Self::default()
+        }
+    }
+
+    /// Convert to nix `CloneFlags`
     #[must_use]
     pub fn to_clone_flags(&self) -> CloneFlags {
         let mut flags = CloneFlags::empty();
@@ -57,16 +112,191 @@ impl NamespaceConfig {
         if self.uts {
             flags |= CloneFlags::CLONE_NEWUTS;
         }
+        if self.time {
+            flags |= CloneFlags::from_bits_retain(libc::CLONE_NEWTIME);
+        }
 
         flags
     }
 
-    /// Enter new namespaces using unshare
+    /// Enter new namespaces using unshare, mapping ids in a new user
+    /// namespace and freezing the clocks of a new time namespace
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if the namespaces cannot be unshared or set
+    /// up.
     pub fn enter(&self) -> Result<()> {
         let flags = self.to_clone_flags();
-        nix::sched::unshare(flags).map_err(|e| {
-            LeewardError::Namespace(format!("failed to unshare namespaces: {e}"))
-        })?;
+        nix::sched::unshare(flags)
+            .map_err(|e| LeewardError::Namespace(format!("failed to unshare namespaces: {e}")))?;
+        if self.user {
+            self.write_id_maps(None)?;
+        }
+        if self.time {
+            freeze_clocks(None)?;
+        }
+        Ok(())
+    }
+
+    /// Write the id maps of process `pid`, or of this process, which must
+    /// be new to its user namespace
+    ///
+    /// `setgroups` is denied first, as the kernel requires before an
+    /// unprivileged process writes a gid map, then the gid map and the uid
+    /// map are written in that order. An empty map is left unwritten.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if a map cannot be written, e.g. once it was
+    /// written already.
+    pub fn write_id_maps(&self, pid: Option<pid_t>) -> Result<()> {
+        let proc = pid.map_or_else(
+            || PathBuf::from("/proc/self"),
+            |pid| PathBuf::from(format!("/proc/{pid}")),
+        );
+        let write = |file: &str, contents: &str| {
+            std::fs::write(proc.join(file), contents).map_err(|e| {
+                LeewardError::Namespace(format!("failed to write {}/{file}: {e}", proc.display()))
+            })
+        };
+
+        match write("setgroups", "deny") {
+            // Before Linux 3.19 there is nothing to deny
+            Err(_) if !proc.join("setgroups").exists() => {}
+            written => written?,
+        }
+        if !self.gid_map.is_empty() {
+            write("gid_map", &id_map(&self.gid_map))?;
+        }
+        if !self.uid_map.is_empty() {
+            write("uid_map", &id_map(&self.uid_map))?;
+        }
         Ok(())
     }
 }
+
+/// `mappings` as the kernel reads an id map, one line each; it takes the
+/// whole map in a single write
+fn id_map(mappings: &[IdMapping]) -> String {
+    let mut map = String::new();
+    for (inside, outside, count) in mappings {
+        let _ = writeln!(map, "{inside} {outside} {count}");
+    }
+    map
+}
+
+/// Set the offsets of the time namespace the children of process `pid`, or
+/// of this process, go into, so their `CLOCK_MONOTONIC` and
+/// `CLOCK_BOOTTIME` read 0 now
+///
+/// Offsets hide how long the host has been up; `CLOCK_REALTIME` is not
+/// namespaced and is left alone.
+///
+/// # Errors
+///
+/// [`LeewardError::Namespace`] once a process has entered the namespace, and
+/// where the kernel has no time namespaces.
+pub fn freeze_clocks(pid: Option<pid_t>) -> Result<()> {
+    let path = pid.map_or_else(
+        || PathBuf::from("/proc/self/timens_offsets"),
+        |pid| PathBuf::from(format!("/proc/{pid}/timens_offsets")),
+    );
+    let mut offsets = String::new();
+    for (name, clock) in [
+        ("monotonic", libc::CLOCK_MONOTONIC),
+        ("boottime", libc::CLOCK_BOOTTIME),
+    ] {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: clock_gettime writes only the timespec it is given
+        if unsafe { libc::clock_gettime(clock, &raw mut now) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(LeewardError::Namespace(format!(
+                "failed to read the {name} clock: {e}"
+            )));
+        }
+        let (secs, nanos) = match now.tv_nsec {
+            0 => (-now.tv_sec, 0),
+            nanos => (-now.tv_sec - 1, NANOS_PER_SEC - nanos),
+        };
+        let _ = writeln!(offsets, "{name} {secs} {nanos}");
+    }
+    // The kernel takes one clock a line, but all of them in a single write
+    std::fs::write(&path, offsets)
+        .map_err(|e| LeewardError::Namespace(format!("failed to write {}: {e}", path.display())))
+}
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// What the init process reports once `setup` succeeded
+const INIT_READY: &[u8] = b"ready";
+
+/// Start the init of the pid namespace this process unshared, run `setup`
+/// in it, and return its pid once `setup` succeeded
+///
+/// The init then stays for as long as this process lives, reaping the
+/// processes orphaned in the namespace, and is killed when this process
+/// dies, taking everything else in the namespace with it. It keeps none of
+/// this process's descriptors.
+///
+/// # Errors
+///
+/// `setup`'s error, after which the namespace cannot be forked into, and
+/// [`LeewardError::Namespace`] if the init cannot be started.
+pub fn spawn_init(setup: impl FnOnce() -> Result<()>) -> Result<pid_t> {
+    use std::os::fd::AsRawFd;
+
+    let (mut status_rx, mut status_tx) = crate::pipe::create_pipe()?;
+    let pid = super::clone3::clone_worker(0, move || {
+        // SAFETY: prctl with constant arguments
+        unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+        super::clone3::close_inherited(&[status_tx.as_raw_fd()]);
+
+        let status = match setup() {
+            Ok(()) => INIT_READY.to_vec(),
+            Err(e) => e.to_string().into_bytes(),
+        };
+        status_tx.write_all(&status)?;
+        drop(status_tx);
+        if status != INIT_READY {
+            return Ok(());
+        }
+        reap_forever()
+    })?;
+    // The write end moved into the closure, dropped here once cloned
+
+    let mut status = Vec::new();
+    status_rx.read_to_end(&mut status)?;
+    if status == INIT_READY {
+        return Ok(pid);
+    }
+    // SAFETY: Reaping the init that gave up
+    unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+    if status.is_empty() {
+        return Err(LeewardError::Namespace(
+            "pid namespace init died during setup".into(),
+        ));
+    }
+    Err(LeewardError::Namespace(format!(
+        "pid namespace init setup failed: {}",
+        String::from_utf8_lossy(&status)
+    )))
+}
+
+/// Reap every child as it exits, never returning
+fn reap_forever() -> ! {
+    use nix::sys::signal::{SigSet, Signal};
+
+    // Blocked, a SIGCHLD stays pending for sigwait however it is handled
+    let mut chld = SigSet::empty();
+    chld.add(Signal::SIGCHLD);
+    let _ = chld.thread_block();
+    loop {
+        // SAFETY: Reaping whatever has exited, without waiting
+        while unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) } > 0 {}
+        let _ = chld.wait();
+    }
+}
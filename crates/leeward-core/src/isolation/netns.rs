@@ -0,0 +1,682 @@
+//! Network namespace setup
+//!
+//! A new network namespace has only a loopback interface, and it starts
+//! down, so even `localhost` is unreachable: connecting to it fails with
+//! `ENETUNREACH` and libraries that resolve or bind it break.
+//! [`NetworkNamespaceSetup::configure_loopback`] brings `lo` up over
+//! rtnetlink and gives it `127.0.0.1/8` and `::1/128`, leaving the
+//! namespace without any route out.
+//!
+//! [`ControlledNetwork`] adds a way out: a veth link whose host end routes
+//! and masquerades the worker's traffic to the internet, but not to other
+//! workers, the host or private networks, and a resolv.conf of its own.
+
+use super::mounts::{BindOptions, mount_bind, mount_remount_ro};
+use crate::config::{Ipv4Net, NetworkConfig, TMP_DIR};
+use crate::{LeewardError, Result, SandboxConfig};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Name of the worker's end of a controlled network's veth link
+pub const WORKER_LINK: &str = "veth1";
+
+/// Where the resolver reads its nameservers from
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Size of `struct nlmsghdr`
+const HEADER_LEN: usize = 16;
+
+/// `NLM_F_REQUEST | NLM_F_ACK`, for a request the kernel acknowledges
+const NLM_F_REQUEST_ACK: u16 = 0x5;
+
+/// `NLM_F_CREATE | NLM_F_EXCL`, for a link, address or route that must be new
+const NLM_F_CREATE_EXCL: u16 = 0x600;
+
+/// `IFA_F_PERMANENT`, as `ip addr add` sets it
+const IFA_F_PERMANENT: u8 = 0x80;
+
+/// `VETH_INFO_PEER`, the peer's link message nested in a veth request
+const VETH_INFO_PEER: u16 = 1;
+
+/// Egress networks whose table this process has installed, with the
+/// ruleset installed
+static MASQUERADED: Mutex<Vec<(Ipv4Net, String)>> = Mutex::new(Vec::new());
+
+/// Private, shared address space, loopback, link-local and metadata
+/// networks a controlled network keeps workers from reaching
+pub const PRIVATE_NETWORKS: [&str; 6] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+];
+
+/// Sets up the worker's network namespace
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkNamespaceSetup;
+
+impl NetworkNamespaceSetup {
+    /// Bring up `lo` in the current network namespace with its IPv4 and
+    /// IPv6 loopback addresses
+    ///
+    /// Addresses the kernel already gave `lo` as it came up are left as
+    /// they are, as is IPv6 on a host without it.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if `lo` is missing or netlink refuses to
+    /// bring it up.
+    pub fn configure_loopback() -> Result<()> {
+        let index = index_of("lo")?;
+        let mut netlink = Netlink::open().map_err(|e| failed("open a netlink socket", &e))?;
+        netlink
+            .request(libc::RTM_NEWLINK, 0, &link_up(index))
+            .map_err(|e| failed("bring up lo", &e))?;
+
+        let v4 = address(
+            libc::AF_INET,
+            8,
+            &Ipv4Addr::LOCALHOST.octets(),
+            index,
+            libc::RT_SCOPE_HOST,
+        );
+        match netlink.request(libc::RTM_NEWADDR, NLM_F_CREATE_EXCL, &v4) {
+            Err(e) if e.raw_os_error() != Some(libc::EEXIST) => {
+                return Err(failed("add 127.0.0.1/8 to lo", &e));
+            }
+            _ => {}
+        }
+        let v6 = address(
+            libc::AF_INET6,
+            128,
+            &Ipv6Addr::LOCALHOST.octets(),
+            index,
+            libc::RT_SCOPE_HOST,
+        );
+        match netlink.request(libc::RTM_NEWADDR, NLM_F_CREATE_EXCL, &v6) {
+            Err(e) if !matches!(e.raw_os_error(), Some(libc::EEXIST | libc::EAFNOSUPPORT)) => {
+                Err(failed("add ::1/128 to lo", &e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Hands out the slots of an egress network, one per worker process with
+/// a controlled network
+///
+/// A slot is held for as long as its [`NetworkSlot`] lives and then goes
+/// back to be handed out again, lowest first, so the egress network only
+/// needs room for the worker processes alive at once, whatever their ids.
+#[derive(Debug, Default)]
+pub struct NetworkSlots {
+    state: Mutex<SlotState>,
+}
+
+#[derive(Debug, Default)]
+struct SlotState {
+    /// Slots given back, to hand out before any new one
+    free: BTreeSet<u32>,
+    /// The lowest slot never handed out
+    next: u32,
+}
+
+impl NetworkSlots {
+    /// The ones workers not given any draw from, shared by the process
+    #[must_use]
+    pub fn process() -> Arc<Self> {
+        static PROCESS: OnceLock<Arc<NetworkSlots>> = OnceLock::new();
+        Arc::clone(PROCESS.get_or_init(Arc::default))
+    }
+
+    /// Take the lowest free slot until the returned lease is dropped
+    #[must_use]
+    pub fn lease(self: &Arc<Self>) -> NetworkSlot {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let slot = state.free.pop_first().unwrap_or_else(|| {
+            state.next = state.next.saturating_add(1);
+            state.next - 1
+        });
+        drop(state);
+        NetworkSlot {
+            slots: Arc::clone(self),
+            slot,
+        }
+    }
+}
+
+/// A slot of an egress network, given back when dropped
+#[derive(Debug)]
+pub struct NetworkSlot {
+    slots: Arc<NetworkSlots>,
+    slot: u32,
+}
+
+impl NetworkSlot {
+    #[must_use]
+    pub const fn get(&self) -> u32 {
+        self.slot
+    }
+}
+
+impl Drop for NetworkSlot {
+    fn drop(&mut self) {
+        self.slots
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .free
+            .insert(self.slot);
+    }
+}
+
+/// A worker's way out through a veth link to the host
+///
+/// Worker `slot`, leased from [`NetworkSlots`], gets the `slot`th /30 of the egress network: the host
+/// end of its link, `lw<pid>`, takes the first address and is the
+/// worker's gateway, and [`WORKER_LINK`] in the worker's namespace takes
+/// the second. The host forwards and masquerades whatever comes from the
+/// egress network through an nftables table of its own, installed with
+/// `nft` once per process, see [`ControlledNetwork::ruleset`].
+///
+/// The same table filters that traffic: nothing is forwarded from one
+/// worker's link to another's, nothing from the egress network reaches
+/// the host itself, and [`PRIVATE_NETWORKS`] are dropped unless allowed
+/// with [`ControlledNetwork::allow_private`]. DNS to the nameserver gets
+/// through all three.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlledNetwork {
+    egress_cidr: Ipv4Net,
+    dns: Ipv4Addr,
+    slot: u32,
+    allow_private: Vec<Ipv4Net>,
+}
+
+impl ControlledNetwork {
+    #[must_use]
+    pub const fn new(egress_cidr: Ipv4Net, dns: Ipv4Addr, slot: u32) -> Self {
+        Self {
+            egress_cidr,
+            dns,
+            slot,
+            allow_private: Vec::new(),
+        }
+    }
+
+    /// The same network in `slot` instead
+    #[must_use]
+    pub const fn in_slot(mut self, slot: u32) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    /// Let workers reach `networks` despite [`PRIVATE_NETWORKS`]
+    #[must_use]
+    pub fn allow_private(mut self, networks: impl IntoIterator<Item = Ipv4Net>) -> Self {
+        self.allow_private = networks.into_iter().collect();
+        self
+    }
+
+    /// The controlled network `config` gives worker `slot`, if any
+    #[must_use]
+    pub fn for_worker(config: &SandboxConfig, slot: u32) -> Option<Self> {
+        match config.network {
+            NetworkConfig::Controlled { egress_cidr, dns } if !config.allow_network => Some(
+                Self::new(egress_cidr, dns, slot)
+                    .allow_private(config.egress_allow_private.iter().copied()),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Addresses of the host and worker ends of the link
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if the egress network has no room for this
+    /// worker's link.
+    pub fn addresses(&self) -> Result<(Ipv4Addr, Ipv4Addr)> {
+        let base = u64::from(self.slot) * 4;
+        match (
+            self.egress_cidr.nth(base + 1),
+            self.egress_cidr.nth(base + 2),
+        ) {
+            (Some(gateway), Some(address)) => Ok((gateway, address)),
+            _ => Err(LeewardError::Namespace(format!(
+                "{} has no room for a link for worker {}",
+                self.egress_cidr, self.slot
+            ))),
+        }
+    }
+
+    /// Name of the host end of `worker_pid`'s link
+    #[must_use]
+    pub fn host_link(worker_pid: libc::pid_t) -> String {
+        format!("lw{worker_pid}")
+    }
+
+    /// Create the link into `worker_pid`'s network namespace and route and
+    /// masquerade what comes out of it (runs in the parent)
+    ///
+    /// The link goes away with the namespace.
+    ///
+    /// # Errors
+    ///
+    /// As [`ControlledNetwork::create_link`], and [`LeewardError::Namespace`]
+    /// if forwarding cannot be turned on or `nft` refuses the ruleset.
+    pub fn setup_host_side(&self, worker_pid: libc::pid_t) -> Result<()> {
+        self.create_link(worker_pid)?;
+        self.masquerade()
+    }
+
+    /// Create the link into `worker_pid`'s network namespace and bring up
+    /// its host end, without masquerading anything
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if the link cannot be created, addressed or
+    /// brought up.
+    pub fn create_link(&self, worker_pid: libc::pid_t) -> Result<()> {
+        let (gateway, _) = self.addresses()?;
+        let name = Self::host_link(worker_pid);
+        let mut netlink = Netlink::open().map_err(|e| failed("open a netlink socket", &e))?;
+        netlink
+            .request(
+                libc::RTM_NEWLINK,
+                NLM_F_CREATE_EXCL,
+                &veth_pair(&name, worker_pid),
+            )
+            .map_err(|e| failed(&format!("create {name}"), &e))?;
+        let index = index_of(&name)?;
+        let host = address(
+            libc::AF_INET,
+            30,
+            &gateway.octets(),
+            index,
+            libc::RT_SCOPE_UNIVERSE,
+        );
+        netlink
+            .request(libc::RTM_NEWADDR, NLM_F_CREATE_EXCL, &host)
+            .map_err(|e| failed(&format!("add {gateway}/30 to {name}"), &e))?;
+        netlink
+            .request(libc::RTM_NEWLINK, 0, &link_up(index))
+            .map_err(|e| failed(&format!("bring up {name}"), &e))
+    }
+
+    /// Bring up loopback and the worker's end of the link, with a default
+    /// route through the host (runs in the worker's network namespace)
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Namespace`] if loopback or the link cannot be
+    /// configured.
+    pub fn setup_worker_side(&self) -> Result<()> {
+        NetworkNamespaceSetup::configure_loopback()?;
+        let (gateway, own) = self.addresses()?;
+        let index = index_of(WORKER_LINK)?;
+        let mut netlink = Netlink::open().map_err(|e| failed("open a netlink socket", &e))?;
+        let worker = address(
+            libc::AF_INET,
+            30,
+            &own.octets(),
+            index,
+            libc::RT_SCOPE_UNIVERSE,
+        );
+        netlink
+            .request(libc::RTM_NEWADDR, NLM_F_CREATE_EXCL, &worker)
+            .map_err(|e| failed(&format!("add {own}/30 to {WORKER_LINK}"), &e))?;
+        netlink
+            .request(libc::RTM_NEWLINK, 0, &link_up(index))
+            .map_err(|e| failed(&format!("bring up {WORKER_LINK}"), &e))?;
+        netlink
+            .request(
+                libc::RTM_NEWROUTE,
+                NLM_F_CREATE_EXCL,
+                &default_route(gateway, index),
+            )
+            .map_err(|e| failed(&format!("add a default route via {gateway}"), &e))
+    }
+
+    /// The resolv.conf the worker gets
+    #[must_use]
+    pub const fn resolv_conf(&self) -> ResolvConf {
+        ResolvConf { dns: self.dns }
+    }
+
+    /// The `nft` script replacing the egress network's table, in one
+    /// transaction, with one that masquerades its traffic and filters it
+    ///
+    /// Forwarded traffic between `lw` links is dropped whatever network
+    /// they are on, and so is what comes in from the egress network to the
+    /// host, bar DNS, and what it sends to [`PRIVATE_NETWORKS`]. Workers
+    /// get no IPv6 address, so an `ip6` table drops all IPv6 from `lw`
+    /// links, forwarded or to the host.
+    #[must_use]
+    pub fn ruleset(&self) -> String {
+        let table = format!(
+            "leeward_{}",
+            self.egress_cidr.to_string().replace(['.', '/'], "_")
+        );
+        let cidr = self.egress_cidr;
+        let dns = format!(
+            "ip saddr {cidr} ip daddr {} meta l4proto {{ tcp, udp }} th dport 53 accept",
+            self.dns
+        );
+        let allowed = if self.allow_private.is_empty() {
+            String::new()
+        } else {
+            let networks: Vec<String> =
+                self.allow_private.iter().map(ToString::to_string).collect();
+            format!(
+                "\t\tip saddr {cidr} ip daddr {{ {} }} accept\n",
+                networks.join(", ")
+            )
+        };
+        format!(
+            "table ip {table}\n\
+             delete table ip {table}\n\
+             table ip {table} {{\n\
+             \tchain postrouting {{\n\
+             \t\ttype nat hook postrouting priority srcnat; policy accept;\n\
+             \t\tip saddr {cidr} masquerade\n\
+             \t}}\n\
+             \tchain forward {{\n\
+             \t\ttype filter hook forward priority filter; policy accept;\n\
+             \t\tiifname \"lw*\" oifname \"lw*\" drop\n\
+             \t\t{dns}\n\
+             {allowed}\
+             \t\tip saddr {cidr} ip daddr {{ {private} }} drop\n\
+             \t}}\n\
+             \tchain input {{\n\
+             \t\ttype filter hook input priority filter; policy accept;\n\
+             \t\tiifname \"lw*\" {dns}\n\
+             \t\tiifname \"lw*\" ip saddr {cidr} drop\n\
+             \t}}\n\
+             }}\n\
+             table ip6 {table}\n\
+             delete table ip6 {table}\n\
+             table ip6 {table} {{\n\
+             \tchain forward {{\n\
+             \t\ttype filter hook forward priority filter; policy accept;\n\
+             \t\tiifname \"lw*\" drop\n\
+             \t}}\n\
+             \tchain input {{\n\
+             \t\ttype filter hook input priority filter; policy accept;\n\
+             \t\tiifname \"lw*\" drop\n\
+             \t}}\n\
+             }}\n",
+            private = PRIVATE_NETWORKS.join(", "),
+        )
+    }
+
+    /// Turn on forwarding and install the egress network's table, unless
+    /// this process already has the same one
+    fn masquerade(&self) -> Result<()> {
+        let mut done = MASQUERADED
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let script = self.ruleset();
+        if done
+            .iter()
+            .any(|(cidr, installed)| *cidr == self.egress_cidr && *installed == script)
+        {
+            return Ok(());
+        }
+        let mut nft = Command::new("nft")
+            .args(["-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| failed("run nft", &e))?;
+        if let Some(mut stdin) = nft.stdin.take() {
+            stdin
+                .write_all(script.as_bytes())
+                .map_err(|e| failed("write to nft", &e))?;
+        }
+        let output = nft.wait_with_output().map_err(|e| failed("run nft", &e))?;
+        if !output.status.success() {
+            return Err(LeewardError::Namespace(format!(
+                "nft failed to masquerade {}: {}",
+                self.egress_cidr,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")
+            .map_err(|e| failed("turn on IPv4 forwarding", &e))?;
+        // Held until here, so workers spawning at once run nft only once
+        done.retain(|(cidr, _)| *cidr != self.egress_cidr);
+        done.push((self.egress_cidr, script));
+        drop(done);
+        Ok(())
+    }
+}
+
+/// A resolv.conf naming one nameserver, bound over [`RESOLV_CONF`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvConf {
+    dns: Ipv4Addr,
+}
+
+impl ResolvConf {
+    /// Bind it read-only over [`RESOLV_CONF`] in the current mount
+    /// namespace, which must have `/tmp` of its own to stage it in and a
+    /// file at [`RESOLV_CONF`] to bind over
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Mount`] if the file cannot be staged or bound.
+    pub fn bind(&self) -> Result<()> {
+        let staged = Path::new(TMP_DIR).join(".leeward-resolv.conf");
+        std::fs::write(&staged, format!("nameserver {}\n", self.dns)).map_err(|e| {
+            LeewardError::Mount(format!("failed to write {}: {e}", staged.display()))
+        })?;
+        let target = Path::new(RESOLV_CONF);
+        let bound = mount_bind(&staged, target, BindOptions::default())
+            .and_then(|()| mount_remount_ro(target));
+        // The mount keeps the file; only the name in /tmp goes
+        let _ = std::fs::remove_file(&staged);
+        bound
+    }
+}
+
+fn failed(what: &str, e: &io::Error) -> LeewardError {
+    LeewardError::Namespace(format!("failed to {what}: {e}"))
+}
+
+/// Index of the interface called `name` in the current network namespace
+fn index_of(name: &str) -> Result<u32> {
+    let c_name = std::ffi::CString::new(name)
+        .map_err(|_| LeewardError::Namespace(format!("bad interface name {name:?}")))?;
+    // SAFETY: if_nametoindex reads a NUL-terminated name
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(failed(&format!("find {name}"), &io::Error::last_os_error()));
+    }
+    Ok(index)
+}
+
+/// A `NETLINK_ROUTE` socket talking to the kernel
+struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Netlink {
+    fn open() -> io::Result<Self> {
+        // SAFETY: socket with constant arguments
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: A descriptor we just opened and nothing else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Send a `kind` request carrying `payload` and wait for the kernel's
+    /// acknowledgement, failing with the error it answers with
+    fn request(&mut self, kind: u16, flags: u16, payload: &[u8]) -> io::Result<()> {
+        self.seq += 1;
+        let len =
+            u32::try_from(HEADER_LEN + payload.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+        message.extend_from_slice(&len.to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST_ACK | flags).to_ne_bytes());
+        message.extend_from_slice(&self.seq.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+
+        // SAFETY: Sending from a buffer we own; an unbound netlink socket
+        // sends to the kernel
+        let sent = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                message.as_ptr().cast(),
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut reply = [0u8; 4096];
+        loop {
+            // SAFETY: Receiving into a buffer we own, within its length
+            let received = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    reply.as_mut_ptr().cast(),
+                    reply.len(),
+                    0,
+                )
+            };
+            let received = usize::try_from(received).map_err(|_| io::Error::last_os_error())?;
+            if let Some(error) = self.ack(&reply[..received]) {
+                return match error {
+                    0 => Ok(()),
+                    errno => Err(io::Error::from_raw_os_error(-errno)),
+                };
+            }
+        }
+    }
+
+    /// Error code of the acknowledgement to the last request among the
+    /// messages in `reply`, 0 for success
+    fn ack(&self, mut reply: &[u8]) -> Option<i32> {
+        while reply.len() >= HEADER_LEN + 4 {
+            let field =
+                |at: usize| u32::from_ne_bytes(reply[at..at + 4].try_into().unwrap_or_default());
+            let len = (field(0) as usize).max(HEADER_LEN);
+            let kind = u16::from_ne_bytes([reply[4], reply[5]]);
+            if i32::from(kind) == libc::NLMSG_ERROR && field(8) == self.seq {
+                return Some(i32::from_ne_bytes(field(HEADER_LEN).to_ne_bytes()));
+            }
+            reply = reply.get(len.next_multiple_of(4)..).unwrap_or_default();
+        }
+        None
+    }
+}
+
+/// `struct ifinfomsg` setting `IFF_UP` on interface `index`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn link_up(index: u32) -> Vec<u8> {
+    let mut message = Vec::with_capacity(16);
+    message.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    message.extend_from_slice(&0u16.to_ne_bytes());
+    message.extend_from_slice(&index.to_ne_bytes());
+    message.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    message.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    message
+}
+
+/// `struct ifaddrmsg` assigning `address/prefix` on interface `index`,
+/// with the `IFA_LOCAL` and `IFA_ADDRESS` attributes
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn address(family: libc::c_int, prefix: u8, address: &[u8], index: u32, scope: u8) -> Vec<u8> {
+    let mut message = vec![family as u8, prefix, IFA_F_PERMANENT, scope];
+    message.extend_from_slice(&index.to_ne_bytes());
+    attribute(&mut message, libc::IFA_LOCAL, address);
+    attribute(&mut message, libc::IFA_ADDRESS, address);
+    message
+}
+
+/// `struct ifinfomsg` creating a veth link called `name` whose peer,
+/// [`WORKER_LINK`], goes straight into `pid`'s network namespace
+fn veth_pair(name: &str, pid: libc::pid_t) -> Vec<u8> {
+    let mut message = vec![0; 16];
+    attribute(&mut message, libc::IFLA_IFNAME, &nul_terminated(name));
+    nested(&mut message, libc::IFLA_LINKINFO, |info| {
+        attribute(info, libc::IFLA_INFO_KIND, b"veth");
+        nested(info, libc::IFLA_INFO_DATA, |data| {
+            nested(data, VETH_INFO_PEER, |peer| {
+                peer.extend_from_slice(&[0; 16]);
+                attribute(peer, libc::IFLA_IFNAME, &nul_terminated(WORKER_LINK));
+                attribute(peer, libc::IFLA_NET_NS_PID, &pid.to_ne_bytes());
+            });
+        });
+    });
+    message
+}
+
+/// `struct rtmsg` adding a default route via `gateway` out of interface `index`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn default_route(gateway: Ipv4Addr, index: u32) -> Vec<u8> {
+    let mut message = vec![
+        libc::AF_INET as u8,
+        0,
+        0,
+        0,
+        libc::RT_TABLE_MAIN,
+        libc::RTPROT_BOOT,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+    ];
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    attribute(&mut message, libc::RTA_GATEWAY, &gateway.octets());
+    attribute(&mut message, libc::RTA_OIF, &index.to_ne_bytes());
+    message
+}
+
+fn nul_terminated(name: &str) -> Vec<u8> {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Append a `struct rtattr` of `kind` carrying `data`, padded to 4 bytes
+#[allow(clippy::cast_possible_truncation)]
+fn attribute(message: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    message.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(data);
+    message.resize(message.len().next_multiple_of(4), 0);
+}
+
+/// Append an attribute of `kind` holding whatever `fill` appends
+#[allow(clippy::cast_possible_truncation)]
+fn nested(message: &mut Vec<u8>, kind: u16, fill: impl FnOnce(&mut Vec<u8>)) {
+    let start = message.len();
+    attribute(message, kind, &[]);
+    fill(message);
+    let len = (message.len() - start) as u16;
+    message[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+}
@@ -0,0 +1,334 @@
+//! Host state the sandbox creates, and reaping what leaked of it
+//!
+//! The only state a daemon leaves on the host outside its workers' own
+//! namespaces is the directory of each [`RootTemplate`](super::RootTemplate),
+//! named `leeward-root-<pid>-<n>` under [`roots_dir`]. Its mounts live in
+//! the template keeper's namespace and go with it, but a crashed daemon
+//! leaves the directory behind, and a bug could leave a mount on it in the
+//! daemon's own namespace.
+//!
+//! [`roots_dir`] sits in the temp dir, where any user could create a name
+//! first. It is only used once [`open_private_dir`] has found it to be a
+//! directory of the daemon's own user that no one else may enter, and each
+//! root is made in it with [`create_root`], so no other user can swap a
+//! root for a symlink between its creation and the mounts made on it.
+//!
+//! Every root is registered with a [`RootClaim`] before it is created and
+//! deregistered only after it is removed, so [`reconcile`] can tell a
+//! leaked root from one in use. Roots of other processes are only reaped
+//! once that process is gone. Nothing is ever removed recursively: a root
+//! that is not empty once its mounts are detached is left alone.
+
+use super::mounts::umount2;
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use std::collections::BTreeSet;
+use std::io;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Name prefix of template roots under [`roots_dir`]
+pub const ROOT_PREFIX: &str = "leeward-root-";
+
+/// Roots registered by this process and not yet removed
+static LIVE: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Name prefix of the roots dir in the temp dir, followed by the uid
+pub const ROOTS_DIR_PREFIX: &str = "leeward-roots-";
+
+/// Where template roots are created: a directory of this user's in the
+/// temp dir, to be opened with [`open_roots_dir`]
+#[must_use]
+pub fn roots_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("{ROOTS_DIR_PREFIX}{}", euid()))
+}
+
+/// Open [`roots_dir`], creating it if it is missing
+///
+/// Not held open: workers are forked from the daemon, and one inheriting a
+/// descriptor of a host directory could reach the host through it.
+///
+/// # Errors
+///
+/// As [`open_private_dir`].
+pub fn open_roots_dir() -> io::Result<OwnedFd> {
+    open_private_dir(&roots_dir())
+}
+
+/// Open the directory at `path`, creating it with mode 0700 if missing,
+/// and refuse it unless it is a directory rather than a symlink, belongs
+/// to this user, and is closed to every other user
+///
+/// What is checked is the directory opened, so it cannot be swapped for
+/// another between the check and its use.
+///
+/// # Errors
+///
+/// `PermissionDenied` if it is not such a directory, and any I/O error creating
+/// or opening it.
+pub fn open_private_dir(path: &Path) -> io::Result<OwnedFd> {
+    match std::fs::DirBuilder::new().mode(0o700).create(path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let dir = nix::fcntl::open(
+        path,
+        OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .map_err(|e| {
+        io::Error::new(
+            io::Error::from(e).kind(),
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+
+    let stat = nix::sys::stat::fstat(&dir)?;
+    if stat.st_uid != euid() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} belongs to uid {}, not this user",
+                path.display(),
+                stat.st_uid
+            ),
+        ));
+    }
+    if stat.st_mode & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is open to other users (mode {:o})",
+                path.display(),
+                stat.st_mode & 0o7777
+            ),
+        ));
+    }
+    Ok(dir)
+}
+
+/// Create the directory `name` in `dir`, which must not exist yet, and
+/// open it
+///
+/// Refused if what `name` leads to once created is a symlink or not this
+/// user's, as it would be had it been swapped in between.
+///
+/// # Errors
+///
+/// `PermissionDenied` if it is refused, and any I/O error creating or opening
+/// it, e.g. `AlreadyExists`.
+pub fn create_root(dir: impl AsFd, name: &str) -> io::Result<OwnedFd> {
+    let dir = dir.as_fd();
+    nix::sys::stat::mkdirat(dir, name, Mode::S_IRWXU)?;
+    let root = nix::fcntl::openat(
+        dir,
+        name,
+        OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    if nix::sys::stat::fstat(&root)?.st_uid != euid() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{name} was replaced by another user's directory"),
+        ));
+    }
+    Ok(root)
+}
+
+/// A template root registered as in use, removed and deregistered on drop
+///
+/// Register before creating the directory, so there is never a moment it
+/// exists unregistered.
+#[derive(Debug)]
+pub struct RootClaim {
+    path: PathBuf,
+    /// Process that registered it; forked children inherit a copy
+    owner: u32,
+}
+
+impl RootClaim {
+    /// Register `path` as in use by this process
+    #[must_use]
+    pub fn register(path: PathBuf) -> Self {
+        lock().insert(path.clone());
+        Self {
+            path,
+            owner: std::process::id(),
+        }
+    }
+
+    /// The registered path
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RootClaim {
+    fn drop(&mut self) {
+        if self.owner != std::process::id() {
+            return;
+        }
+        let _ = std::fs::remove_dir(&self.path);
+        lock().remove(&self.path);
+    }
+}
+
+/// Something [`reconcile`] removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reaped {
+    /// A mount on or under a leaked root, detached
+    Mount(PathBuf),
+    /// A leaked root directory, removed
+    Root(PathBuf),
+    /// The cgroup of a worker process that is gone, removed
+    Cgroup(PathBuf),
+}
+
+impl Reaped {
+    /// Short name for logs and metrics
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Mount(_) => "mount",
+            Self::Root(_) => "root",
+            Self::Cgroup(_) => "cgroup",
+        }
+    }
+
+    /// The path removed or detached
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Mount(path) | Self::Root(path) | Self::Cgroup(path) => path,
+        }
+    }
+}
+
+/// Detach the mounts of, and remove, every root under `dir` that is not
+/// in use and was last modified at least `grace` ago
+///
+/// A root is in use if this process registered it, or if it belongs to
+/// another process that is still running. Failures are logged and the
+/// root is tried again next time.
+pub fn reconcile(dir: &Path, grace: Duration) -> Vec<Reaped> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    // Held throughout, so no root is registered or released mid-check
+    let live = lock();
+
+    let orphans: Vec<PathBuf> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let owner = root_owner(&entry.file_name().to_string_lossy())?;
+            let path = entry.path();
+            let in_use = if owner == std::process::id() {
+                live.contains(&path)
+            } else {
+                process_exists(owner)
+            };
+            let age = entry
+                .metadata()
+                .ok()?
+                .modified()
+                .ok()?
+                .elapsed()
+                .unwrap_or_default();
+            (!in_use && age >= grace).then_some(path)
+        })
+        .collect();
+    if orphans.is_empty() {
+        return Vec::new();
+    }
+
+    let mounts = mount_points();
+    let mut reaped = Vec::new();
+    for root in orphans {
+        // Mountinfo shows resolved paths; deepest first, so nothing is
+        // detached from under another
+        let resolved = root.canonicalize().unwrap_or_else(|_| root.clone());
+        let mut under: Vec<&PathBuf> = mounts
+            .iter()
+            .filter(|mount| mount.starts_with(&resolved))
+            .collect();
+        under.sort_by_key(|mount| std::cmp::Reverse(mount.components().count()));
+        for mount in under {
+            match umount2(mount, libc::MNT_DETACH) {
+                Ok(()) => reaped.push(Reaped::Mount(mount.clone())),
+                Err(e) => {
+                    tracing::warn!(mount = ?mount, error = %e, "failed to detach leaked mount");
+                }
+            }
+        }
+        match std::fs::remove_dir(&root) {
+            Ok(()) => reaped.push(Reaped::Root(root)),
+            Err(e) => {
+                tracing::warn!(root = ?root, error = %e, "failed to remove leaked template root");
+            }
+        }
+    }
+    reaped
+}
+
+fn euid() -> u32 {
+    // SAFETY: geteuid has no failure modes
+    unsafe { libc::geteuid() }
+}
+
+fn lock() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    LIVE.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Pid of the process that created the root named `name`, if it is one
+fn root_owner(name: &str) -> Option<u32> {
+    let (pid, n) = name.strip_prefix(ROOT_PREFIX)?.split_once('-')?;
+    n.parse::<u32>().ok()?;
+    pid.parse().ok()
+}
+
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    // EPERM still means the process is there
+    !matches!(
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None),
+        Err(nix::errno::Errno::ESRCH)
+    )
+}
+
+/// Mount points in this process's mount namespace
+fn mount_points() -> Vec<PathBuf> {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|point| PathBuf::from(unescape(point)))
+        .collect()
+}
+
+/// Undo the octal escapes mountinfo uses for space, tab, newline and `\`
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        let escape = rest
+            .get(at + 1..at + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        if let Some(byte) = escape {
+            out.push(char::from(byte));
+            rest = &rest[at + 4..];
+        } else {
+            out.push('\\');
+            rest = &rest[at + 1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -1,159 +1,1344 @@
-//! Seccomp-BPF syscall filtering with SECCOMP_USER_NOTIF support
+//! Seccomp-BPF syscall filtering with `SECCOMP_USER_NOTIF` support
+//!
+//! Syscalls listed in [`SeccompConfig::notify_syscalls`] are routed to a
+//! listener fd instead of being decided by the filter. Workers hand their
+//! listener to the daemon, where the built-in [`Supervisor`] answers it
+//! unless an embedder asked for the [`NotificationStream`] to drive
+//! themselves. [`spawn_supervised`] does the same for a plain command, and
+//! [`spawn_observed`] routes every syscall to watch what a command does,
+//! and [`observe_stream`] lets through what such a listener is sent.
+//! With [`SeccompConfig::notify_denials`], the allowlist itself carries the
+//! listener, and what it does not allow is sent there too instead of
+//! killing the caller.
+//!
+//! Unless [`SeccompConfig::allow_debugging`] is set, a second filter fails
+//! [`DEBUGGING_SYSCALLS`] with `EPERM`, so code in the sandbox can neither
+//! trace another process nor read its memory.
+//!
+//! Allowlists start from a [`SyscallProfile`], through
+//! [`SeccompConfig::with_profile`]; the default is [`SyscallProfile::Python`].
+//!
+//! A syscall may be allowed only with some arguments, such as `socket` for
+//! `AF_UNIX` alone, and failed with `EPERM` for others whatever the
+//! allowlist says, such as `ioctl` with `TIOCSTI`; see
+//! [`SeccompConfig::allow_with_args`] and [`SeccompConfig::deny_with_args`].
 
+use crate::debug_flags::DebugFlag;
+use crate::denial::{DenialLog, syscall_name};
+use crate::network::ConnectionTracker;
+use crate::result::DenialLayer;
 use crate::{LeewardError, Result};
-use std::os::unix::io::RawFd;
-use std::collections::BTreeMap;
 use seccompiler::{
-    SeccompAction, SeccompFilter, SeccompRule, TargetArch
+    SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter, SeccompRule,
+    TargetArch,
 };
+use std::collections::BTreeMap;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::Arc;
 
 /// Configuration for seccomp filtering
 #[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct SeccompConfig {
     /// Use NOTIFY mode instead of KILL (allows supervisor intervention)
     pub notify_mode: bool,
-    /// Syscalls to allow
-    pub allowed_syscalls: Vec<i64>,
+    /// Syscalls to allow, each with the arguments it is allowed with
+    ///
+    /// A syscall is allowed if any of its rules matches.
+    pub allowed_syscalls: Vec<SyscallRule>,
     /// Log denied syscalls before killing
     pub log_denials: bool,
+    /// Syscalls routed to a listener for a supervisor to decide
+    ///
+    /// Only used with `notify_mode`. The allowlist still applies on top: a
+    /// syscall it blocks is never routed.
+    pub notify_syscalls: Vec<i64>,
+    /// Let [`DEBUGGING_SYSCALLS`] through, for the debug profile
+    ///
+    /// Otherwise they fail with `EPERM` whatever the allowlist says, so no
+    /// process can trace or read the memory of another.
+    pub allow_debugging: bool,
+    /// Syscalls failed with `ENOSYS`, as on a kernel without them, so libc
+    /// falls back to older ones the allowlist can check the arguments of
+    pub enosys_syscalls: Vec<i64>,
+    /// Send syscalls the allowlist does not allow to the listener instead
+    /// of killing or logging them
+    ///
+    /// Only used with `notify_mode`. Routed syscalls then reach the
+    /// listener whatever the allowlist says about them, so the supervisor
+    /// must tell them from denials, as
+    /// [`Supervisor::denying_unrouted`] does. Whatever hands the listener
+    /// over after [`SeccompConfig::apply`] must be allowed, or it waits on
+    /// a supervisor that never gets the listener. On a kernel without user
+    /// notifications the allowlist kills or logs as it otherwise would.
+    pub notify_denials: bool,
+    /// Syscalls failed with `EPERM` when their arguments meet one of these
+    /// rules, whatever the allowlist says
+    pub denied_syscalls: Vec<SyscallRule>,
+}
+
+/// A syscall to allow, if its arguments meet every constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallRule {
+    pub syscall: i64,
+    /// Constraints on the arguments, none to allow any
+    pub conditions: Vec<ArgConstraint>,
+}
+
+impl SyscallRule {
+    /// Allow `syscall` whatever its arguments
+    #[must_use]
+    pub const fn allow(syscall: i64) -> Self {
+        Self {
+            syscall,
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Allow `syscall` only if its arguments meet all of `conditions`
+    #[must_use]
+    pub const fn when(syscall: i64, conditions: Vec<ArgConstraint>) -> Self {
+        Self {
+            syscall,
+            conditions,
+        }
+    }
+
+    /// `openat` for reading only: no write access, and nothing created
+    /// or truncated
+    #[must_use]
+    pub fn allow_no_new_fds_openat() -> Self {
+        let mask = libc::O_ACCMODE | libc::O_CREAT | libc::O_TRUNC;
+        Self::when(
+            libc::SYS_openat,
+            vec![ArgConstraint::int(
+                2,
+                ArgCmp::MaskedEq {
+                    mask: flags(mask),
+                    value: flags(libc::O_RDONLY),
+                },
+            )],
+        )
+    }
+
+    /// `mmap` of anonymous memory only, never a file, and never executable
+    #[must_use]
+    pub fn allow_mmap_anon_only() -> Self {
+        Self::when(
+            libc::SYS_mmap,
+            vec![
+                ArgConstraint::int(
+                    2,
+                    ArgCmp::MaskedEq {
+                        mask: flags(libc::PROT_EXEC),
+                        value: 0,
+                    },
+                ),
+                ArgConstraint::int(
+                    3,
+                    ArgCmp::MaskedEq {
+                        mask: flags(libc::MAP_ANONYMOUS),
+                        value: flags(libc::MAP_ANONYMOUS),
+                    },
+                ),
+            ],
+        )
+    }
+
+    /// `mmap` of anything but executable memory
+    #[must_use]
+    pub fn allow_mmap_no_exec() -> Self {
+        Self::when(
+            libc::SYS_mmap,
+            vec![ArgConstraint::int(
+                2,
+                ArgCmp::MaskedEq {
+                    mask: flags(libc::PROT_EXEC),
+                    value: 0,
+                },
+            )],
+        )
+    }
+
+    /// `clone` for processes and threads, but never into new namespaces
+    #[must_use]
+    pub fn allow_clone_no_namespaces() -> Self {
+        let namespaces = libc::CLONE_NEWNS
+            | libc::CLONE_NEWCGROUP
+            | libc::CLONE_NEWUTS
+            | libc::CLONE_NEWIPC
+            | libc::CLONE_NEWUSER
+            | libc::CLONE_NEWPID
+            | libc::CLONE_NEWNET;
+        Self::when(
+            libc::SYS_clone,
+            vec![ArgConstraint::new(
+                0,
+                ArgCmp::MaskedEq {
+                    mask: flags(namespaces),
+                    value: 0,
+                },
+            )],
+        )
+    }
+
+    /// `socket` for Unix domain sockets only
+    #[must_use]
+    pub fn allow_unix_sockets() -> Self {
+        Self::when(
+            libc::SYS_socket,
+            vec![ArgConstraint::int(0, ArgCmp::Eq(flags(libc::AF_UNIX)))],
+        )
+    }
+
+    /// `ioctl` with `TIOCSTI`, which pushes input into the terminal it is
+    /// made on for whoever reads it next; a rule to deny
+    ///
+    /// The kernel takes the request as an `unsigned int`, so only its low
+    /// 32 bits are compared and setting the upper ones does not get past.
+    #[must_use]
+    pub fn tiocsti() -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let request = libc::TIOCSTI as u32;
+        Self::when(
+            libc::SYS_ioctl,
+            vec![ArgConstraint::int(1, ArgCmp::Eq(u64::from(request)))],
+        )
+    }
+
+    /// The seccompiler rule, or `None` if the syscall is unconditional
+    fn to_seccomp(&self) -> Result<Option<SeccompRule>> {
+        if self.conditions.is_empty() {
+            return Ok(None);
+        }
+        let rule = self
+            .conditions
+            .iter()
+            .map(|constraint| constraint.to_seccomp())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .and_then(SeccompRule::new)
+            .map_err(|e| {
+                LeewardError::Seccomp(format!("invalid rule for syscall {}: {e}", self.syscall))
+            })?;
+        Ok(Some(rule))
+    }
+}
+
+impl From<i64> for SyscallRule {
+    fn from(syscall: i64) -> Self {
+        Self::allow(syscall)
+    }
+}
+
+/// A flag or constant from libc as an argument value
+#[allow(clippy::cast_sign_loss)]
+const fn flags(value: libc::c_int) -> u64 {
+    value as u32 as u64
+}
+
+/// A comparison of one syscall argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgConstraint {
+    /// Argument index, 0 to 5
+    pub arg: u8,
+    pub width: ArgWidth,
+    pub cmp: ArgCmp,
+}
+
+impl ArgConstraint {
+    /// Compare all 64 bits of argument `arg`, for pointers and sizes
+    #[must_use]
+    pub const fn new(arg: u8, cmp: ArgCmp) -> Self {
+        Self {
+            arg,
+            width: ArgWidth::Long,
+            cmp,
+        }
+    }
+
+    /// Compare the low 32 bits of argument `arg`, for `int` flags and
+    /// constants, whose upper bits the kernel ignores
+    #[must_use]
+    pub const fn int(arg: u8, cmp: ArgCmp) -> Self {
+        Self {
+            arg,
+            width: ArgWidth::Int,
+            cmp,
+        }
+    }
+
+    fn to_seccomp(self) -> std::result::Result<SeccompCondition, seccompiler::BackendError> {
+        let len = match self.width {
+            ArgWidth::Int => SeccompCmpArgLen::Dword,
+            ArgWidth::Long => SeccompCmpArgLen::Qword,
+        };
+        let (op, value) = match self.cmp {
+            ArgCmp::Eq(value) => (SeccompCmpOp::Eq, value),
+            ArgCmp::MaskedEq { mask, value } => (SeccompCmpOp::MaskedEq(mask), value),
+            ArgCmp::Lt(value) => (SeccompCmpOp::Lt, value),
+            ArgCmp::Gt(value) => (SeccompCmpOp::Gt, value),
+            ArgCmp::Ne(value) => (SeccompCmpOp::Ne, value),
+        };
+        SeccompCondition::new(self.arg, len, op, value)
+    }
+}
+
+/// How much of an argument an [`ArgConstraint`] compares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgWidth {
+    /// The low 32 bits
+    Int,
+    /// All 64 bits
+    Long,
+}
+
+/// What an argument is compared with, unsigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgCmp {
+    Eq(u64),
+    /// The bits of `mask` equal those of `value`
+    MaskedEq {
+        mask: u64,
+        value: u64,
+    },
+    Lt(u64),
+    Gt(u64),
+    Ne(u64),
+}
+
+/// Syscalls that attach to or read and write the memory of another process
+pub const DEBUGGING_SYSCALLS: [i64; 3] = [
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+];
+
+/// A vetted allowlist to start a [`SeccompConfig`] from
+///
+/// Every profile but [`Self::MinimalIO`] includes [`Self::Python`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyscallProfile {
+    /// Reading and writing fds already open, opening files read-only,
+    /// memory that is never executable, and exiting
+    MinimalIO,
+    /// What `CPython` needs for the standard library, threads and
+    /// subprocesses included, but no sockets
+    Python,
+    /// [`Self::Python`] plus memory-mapped files and the memory hints
+    /// numpy and pandas use
+    DataScience,
+    /// [`Self::Python`] plus sockets, which namespaces and the network
+    /// config still decide the reach of
+    Networking,
+    /// Every syscall; only [`DEBUGGING_SYSCALLS`] stay refused
+    Unrestricted,
+}
+
+/// One past the highest syscall number on any supported architecture
+const SYSCALL_LIMIT: i64 = 512;
+
+impl SyscallProfile {
+    pub const ALL: [Self; 5] = [
+        Self::MinimalIO,
+        Self::Python,
+        Self::DataScience,
+        Self::Networking,
+        Self::Unrestricted,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::MinimalIO => "minimal-io",
+            Self::Python => "python",
+            Self::DataScience => "data-science",
+            Self::Networking => "networking",
+            Self::Unrestricted => "unrestricted",
+        }
+    }
+
+    /// Changes whenever the syscalls the profile allows do, so callers
+    /// can tell a config built from an older one
+    #[must_use]
+    pub const fn version(self) -> &'static str {
+        match self {
+            // 1 was the allowlist the default config had before profiles
+            Self::Python | Self::DataScience | Self::Networking => "2",
+            Self::MinimalIO | Self::Unrestricted => "1",
+        }
+    }
+
+    /// The syscalls allowed, with the arguments they are allowed with
+    #[must_use]
+    pub fn rules(self) -> Vec<SyscallRule> {
+        match self {
+            Self::MinimalIO => minimal_io_syscalls(),
+            Self::Python => python_syscalls(),
+            Self::DataScience => {
+                let mut rules = python_syscalls();
+                rules.extend(
+                    [
+                        libc::SYS_memfd_create,
+                        libc::SYS_fadvise64,
+                        libc::SYS_msync,
+                        libc::SYS_mincore,
+                        libc::SYS_ftruncate,
+                        libc::SYS_fallocate,
+                        libc::SYS_get_mempolicy,
+                    ]
+                    .map(SyscallRule::allow),
+                );
+                rules
+            }
+            Self::Networking => {
+                let mut rules = python_syscalls();
+                rules.extend(
+                    [
+                        libc::SYS_socket,
+                        libc::SYS_socketpair,
+                        libc::SYS_connect,
+                        libc::SYS_bind,
+                        libc::SYS_listen,
+                        libc::SYS_accept,
+                        libc::SYS_accept4,
+                        libc::SYS_getsockname,
+                        libc::SYS_getpeername,
+                        libc::SYS_getsockopt,
+                        libc::SYS_setsockopt,
+                        libc::SYS_sendto,
+                        libc::SYS_recvfrom,
+                        libc::SYS_sendmsg,
+                        libc::SYS_recvmsg,
+                        libc::SYS_sendmmsg,
+                        libc::SYS_recvmmsg,
+                        libc::SYS_shutdown,
+                    ]
+                    .map(SyscallRule::allow),
+                );
+                rules
+            }
+            Self::Unrestricted => (0..SYSCALL_LIMIT).map(SyscallRule::allow).collect(),
+        }
+    }
+
+    /// Syscalls failed with `ENOSYS` instead, see
+    /// [`SeccompConfig::enosys_syscalls`]
+    #[must_use]
+    pub fn enosys_syscalls(self) -> Vec<i64> {
+        match self {
+            // clone3 takes its flags behind a pointer the filter cannot
+            // read, so make libc use clone, whose flags it can
+            Self::Python | Self::DataScience | Self::Networking => vec![libc::SYS_clone3],
+            Self::MinimalIO | Self::Unrestricted => Vec::new(),
+        }
+    }
+
+    /// Syscalls failed with `EPERM` for some arguments, see
+    /// [`SeccompConfig::denied_syscalls`]
+    #[must_use]
+    pub fn denied_syscalls(self) -> Vec<SyscallRule> {
+        match self {
+            Self::Python | Self::DataScience | Self::Networking => vec![SyscallRule::tiocsti()],
+            Self::MinimalIO | Self::Unrestricted => Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for SyscallProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.name(), self.version())
+    }
 }
 
 impl Default for SeccompConfig {
     fn default() -> Self {
+        let profile = SyscallProfile::Python;
         Self {
             notify_mode: true,
-            allowed_syscalls: default_python_syscalls(),
+            allowed_syscalls: profile.rules(),
             log_denials: true,
+            notify_syscalls: Vec::new(),
+            allow_debugging: false,
+            enosys_syscalls: profile.enosys_syscalls(),
+            notify_denials: false,
+            denied_syscalls: profile.denied_syscalls(),
         }
     }
 }
 
 impl SeccompConfig {
+    /// The recommended start: `profile`'s allowlist, with the other
+    /// settings left at their defaults
+    #[must_use]
+    pub fn with_profile(profile: SyscallProfile) -> Self {
+        Self {
+            allowed_syscalls: profile.rules(),
+            enosys_syscalls: profile.enosys_syscalls(),
+            denied_syscalls: profile.denied_syscalls(),
+            ..Self::default()
+        }
+    }
+
+    /// Allow `syscall` when its arguments meet `constraint`, on top of any
+    /// other rule for it
+    ///
+    /// A syscall is allowed if any of its rules matches, so this narrows
+    /// nothing while the syscall is also allowed outright: drop that rule
+    /// from [`Self::allowed_syscalls`] first to allow only this form.
+    #[must_use]
+    pub fn allow_with_args(mut self, syscall: i64, constraint: ArgConstraint) -> Self {
+        self.allowed_syscalls
+            .push(SyscallRule::when(syscall, vec![constraint]));
+        self
+    }
+
+    /// Fail `syscall` with `EPERM` when its arguments meet `constraint`,
+    /// however the allowlist allows it
+    #[must_use]
+    pub fn deny_with_args(mut self, syscall: i64, constraint: ArgConstraint) -> Self {
+        self.denied_syscalls
+            .push(SyscallRule::when(syscall, vec![constraint]));
+        self
+    }
+
     /// Apply the seccomp filter to the current process
     ///
-    /// If notify_mode is true, returns a file descriptor for receiving
+    /// If `notify_mode` is true and `notify_syscalls` is not empty, or
+    /// `notify_denials` is set, returns a file descriptor for receiving
     /// seccomp notifications. The supervisor can poll this fd and decide
-    /// what to do with blocked syscalls.
+    /// what to do with the routed syscalls.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the filter cannot be compiled or the kernel
+    /// refuses to install it.
     pub fn apply(&self) -> Result<Option<SeccompNotifyFd>> {
         tracing::debug!(
             notify = self.notify_mode,
             syscalls = self.allowed_syscalls.len(),
+            routed = self.notify_syscalls.len(),
+            denials = self.notify_denials,
             "applying seccomp filter"
         );
 
-        // Build the filter
-        let filter = self.build_filter()?;
+        if self.notify_mode && self.notify_denials {
+            return self.apply_notifying();
+        }
+        let bpf_prog = self.compile()?;
 
-        // Apply the filter
-        // Note: SECCOMP_USER_NOTIF requires kernel 5.0+ and special handling
-        // For now, we'll use basic filtering with KILL action for denied syscalls
-        // Convert filter to BPF program and apply it
-        let bpf_prog: seccompiler::BpfProgram = filter
-            .try_into()
-            .map_err(|e| LeewardError::Seccomp(format!("failed to compile filter to BPF: {e}")))?;
+        // The listener goes first, since the allowlist may block seccomp() itself
+        let listener = if self.notify_mode && !self.notify_syscalls.is_empty() {
+            let fd = install_listener(&listener_program(&self.notify_syscalls)?).map_err(|e| {
+                LeewardError::Seccomp(format!("failed to install notification listener: {e}"))
+            })?;
+            Some(SeccompNotifyFd::from(fd))
+        } else {
+            None
+        };
 
+        self.install_errno_filters()?;
         seccompiler::apply_filter(&bpf_prog)
             .map_err(|e| LeewardError::Seccomp(format!("failed to apply seccomp filter: {e}")))?;
 
-        tracing::info!("seccomp filter applied with {} allowed syscalls", self.allowed_syscalls.len());
+        tracing::info!(
+            "seccomp filter applied with {} allowed syscall rules",
+            self.allowed_syscalls.len()
+        );
 
-        // SECCOMP_USER_NOTIF would require:
-        // 1. Using raw seccomp() syscall with SECCOMP_FILTER_FLAG_NEW_LISTENER
-        // 2. Getting notification fd from kernel
-        // 3. Setting up notification handler thread
-        // For now, return None as we're using basic filtering
-        Ok(None)
+        Ok(listener)
     }
 
-    /// Build the seccomp filter
-    fn build_filter(&self) -> Result<SeccompFilter> {
-        let mut rules = BTreeMap::new();
+    /// [`SeccompConfig::apply`] with the allowlist sending what it does not
+    /// allow, and the routed syscalls, to its own listener
+    fn apply_notifying(&self) -> Result<Option<SeccompNotifyFd>> {
+        let program = self.compile_notifying()?;
+        // Last, so installing the others never waits on the listener
+        self.install_errno_filters()?;
+        match install_listener(&program) {
+            Ok(fd) => {
+                tracing::info!("seccomp filter applied with denials sent to its listener");
+                Ok(Some(SeccompNotifyFd::from(fd)))
+            }
+            // Before Linux 5.0, or under a filter that already has a listener
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::EBUSY)) => {
+                tracing::warn!(
+                    error = %e,
+                    "no seccomp user notifications here, denials are not sent to a listener"
+                );
+                seccompiler::apply_filter(&self.compile()?).map_err(|e| {
+                    LeewardError::Seccomp(format!("failed to apply seccomp filter: {e}"))
+                })?;
+                Ok(None)
+            }
+            Err(e) => Err(LeewardError::Seccomp(format!(
+                "failed to install notifying filter: {e}"
+            ))),
+        }
+    }
 
-        // For each allowed syscall, create a rule with Allow action
-        // SeccompRule::new only takes conditions, the action is Allow by default for matched rules
-        for &syscall_num in &self.allowed_syscalls {
-            rules.insert(
-                syscall_num,
-                vec![SeccompRule::new(vec![])
-                    .map_err(|e| LeewardError::Seccomp(format!("failed to create rule: {e}")))?],
-            );
+    /// Install the filters failing syscalls with an errno whatever the
+    /// allowlist says, stacked since the kernel takes the most restrictive
+    /// verdict
+    fn install_errno_filters(&self) -> Result<()> {
+        if !self.allow_debugging {
+            let program = match_program(
+                &DEBUGGING_SYSCALLS,
+                libc::SECCOMP_RET_ERRNO | libc::EPERM.unsigned_abs(),
+            )?;
+            install_program(&program, 0).map_err(|e| {
+                LeewardError::Seccomp(format!("failed to install debugging filter: {e}"))
+            })?;
+        }
+
+        if !self.enosys_syscalls.is_empty() {
+            let program = match_program(
+                &self.enosys_syscalls,
+                libc::SECCOMP_RET_ERRNO | libc::ENOSYS.unsigned_abs(),
+            )?;
+            install_program(&program, 0).map_err(|e| {
+                LeewardError::Seccomp(format!("failed to install ENOSYS filter: {e}"))
+            })?;
+        }
+
+        if !self.denied_syscalls.is_empty() {
+            let program: seccompiler::BpfProgram = rule_filter(
+                &self.denied_syscalls,
+                SeccompAction::Allow,
+                SeccompAction::Errno(libc::EPERM.unsigned_abs()),
+            )?
+            .try_into()
+            .map_err(|e| {
+                LeewardError::Seccomp(format!("failed to compile denial filter to BPF: {e}"))
+            })?;
+            seccompiler::apply_filter(&program).map_err(|e| {
+                LeewardError::Seccomp(format!("failed to install denial filter: {e}"))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check that the filter allows and blocks the given syscalls
+    ///
+    /// Each syscall is probed in its own forked child: the child applies this
+    /// filter, invokes the syscall with zeroed arguments and reports whether
+    /// it was blocked (`EACCES` or killed by `SIGSYS`). Arguments are
+    /// never meaningful, so "allowed" only means the filter let the call reach
+    /// the kernel - the syscall itself may still fail with e.g. `EFAULT`.
+    ///
+    /// The calling process is never filtered.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the filter cannot be compiled or a probe
+    /// cannot be forked.
+    pub fn run_validation(&self, allowed: &[i64], blocked: &[i64]) -> Result<ValidationReport> {
+        // Compile before forking so the child never allocates
+        let bpf_prog = self.compile()?;
+        let mut report = ValidationReport::default();
+
+        for &syscall in allowed {
+            match probe_syscall(&bpf_prog, syscall)? {
+                ProbeOutcome::Reached => report.passed.push(syscall),
+                ProbeOutcome::Blocked => report.unexpected_blocks.push(syscall),
+                ProbeOutcome::SetupFailed => report.failed.push(syscall),
+            }
         }
 
-        // Default action for unmatched syscalls
+        for &syscall in blocked {
+            match probe_syscall(&bpf_prog, syscall)? {
+                ProbeOutcome::Blocked => report.passed.push(syscall),
+                ProbeOutcome::Reached | ProbeOutcome::SetupFailed => report.failed.push(syscall),
+            }
+        }
+
+        tracing::debug!(
+            passed = report.passed.len(),
+            failed = report.failed.len(),
+            unexpected_blocks = report.unexpected_blocks.len(),
+            "seccomp validation finished"
+        );
+
+        Ok(report)
+    }
+
+    /// Build the filter and compile it to a BPF program
+    fn compile(&self) -> Result<seccompiler::BpfProgram> {
         let default_action = if self.log_denials {
             SeccompAction::Log // Log and deny
         } else {
             SeccompAction::KillThread // Kill the thread
         };
+        self.build_filter(&self.allowed_syscalls, default_action)?
+            .try_into()
+            .map_err(|e| LeewardError::Seccomp(format!("failed to compile filter to BPF: {e}")))
+    }
+
+    /// Compile the allowlist without the routed syscalls, returning
+    /// `SECCOMP_RET_USER_NOTIF` for everything it does not allow
+    ///
+    /// Seccompiler has no such action, so a trace marked with
+    /// [`NOTIFY_MARK`] stands in for it and is replaced once compiled.
+    fn compile_notifying(&self) -> Result<Vec<libc::sock_filter>> {
+        let unrouted: Vec<_> = self
+            .allowed_syscalls
+            .iter()
+            .filter(|rule| !self.notify_syscalls.contains(&rule.syscall))
+            .cloned()
+            .collect();
+        let program: seccompiler::BpfProgram = self
+            .build_filter(&unrouted, SeccompAction::Trace(NOTIFY_MARK))?
+            .try_into()
+            .map_err(|e| LeewardError::Seccomp(format!("failed to compile filter to BPF: {e}")))?;
 
-        // Get current architecture
-        let arch = get_arch();
+        let ret = bpf_code(libc::BPF_RET | libc::BPF_K);
+        let marked = libc::SECCOMP_RET_TRACE | NOTIFY_MARK;
+        Ok(program
+            .into_iter()
+            .map(|insn| {
+                let k = if insn.code == ret && insn.k == marked {
+                    libc::SECCOMP_RET_USER_NOTIF
+                } else {
+                    insn.k
+                };
+                jump(insn.code, k, insn.jt, insn.jf)
+            })
+            .collect())
+    }
 
-        // Create the filter
-        SeccompFilter::new(
-            rules,
-            default_action,
-            SeccompAction::Allow, // Bad architecture action
-            arch,
-        )
+    /// Every syscall allowed with some arguments, in rule order
+    #[must_use]
+    pub fn syscalls(&self) -> Vec<i64> {
+        let mut syscalls: Vec<i64> = Vec::new();
+        for rule in &self.allowed_syscalls {
+            if !syscalls.contains(&rule.syscall) {
+                syscalls.push(rule.syscall);
+            }
+        }
+        syscalls
+    }
+
+    /// Build the seccomp filter allowing `allowed`, and returning
+    /// `default_action` for the rest
+    fn build_filter(
+        &self,
+        allowed: &[SyscallRule],
+        default_action: SeccompAction,
+    ) -> Result<SeccompFilter> {
+        let mut allowed = allowed.to_vec();
+        if self.allow_debugging {
+            allowed.extend(DEBUGGING_SYSCALLS.map(SyscallRule::allow));
+        }
+        // Let them past the allowlist, or its verdict beats ENOSYS
+        allowed.extend(self.enosys_syscalls.iter().copied().map(SyscallRule::allow));
+        rule_filter(&allowed, default_action, SeccompAction::Allow)
+    }
+}
+
+/// A filter returning `matched` for syscalls that meet one of `rules`, and
+/// `default_action` for the rest
+fn rule_filter(
+    rules: &[SyscallRule],
+    default_action: SeccompAction,
+    matched: SeccompAction,
+) -> Result<SeccompFilter> {
+    // Seccompiler matches a syscall with an empty rule chain
+    // unconditionally, and one with rules if any of them matches, so an
+    // unconditional rule empties the chain for good
+    let mut chains: BTreeMap<i64, Option<Vec<SeccompRule>>> = BTreeMap::new();
+    for rule in rules {
+        let chain = chains
+            .entry(rule.syscall)
+            .or_insert_with(|| Some(Vec::new()));
+        match (rule.to_seccomp()?, chain.as_mut()) {
+            (Some(conditional), Some(chain)) => chain.push(conditional),
+            (None, _) => *chain = None,
+            (Some(_), None) => {}
+        }
+    }
+    let chains = chains
+        .into_iter()
+        .map(|(syscall, chain)| (syscall, chain.unwrap_or_default()))
+        .collect();
+
+    SeccompFilter::new(chains, default_action, matched, get_arch())
         .map_err(|e| LeewardError::Seccomp(format!("failed to create seccomp filter: {e}")))
+}
+
+/// Outcome of [`SeccompConfig::run_validation`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Syscalls that behaved as expected
+    pub passed: Vec<i64>,
+    /// Syscalls expected to be blocked that got through, or whose probe
+    /// could not install the filter
+    pub failed: Vec<i64>,
+    /// Syscalls expected to be allowed that the filter blocked
+    pub unexpected_blocks: Vec<i64>,
+}
+
+impl ValidationReport {
+    /// Check if every probe behaved as expected
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty() && self.unexpected_blocks.is_empty()
+    }
+}
+
+/// Result of probing a single syscall under a filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProbeOutcome {
+    /// The syscall reached the kernel
+    Reached,
+    /// The filter denied the syscall or killed the caller
+    Blocked,
+    /// The child could not install the filter
+    SetupFailed,
+}
+
+/// Child exit code when the probed syscall was denied
+const PROBE_EXIT_BLOCKED: i32 = 100;
+/// Child exit code when the filter could not be applied
+const PROBE_EXIT_SETUP: i32 = 101;
+/// How long a probe may run before it is considered to have reached the kernel
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Fork a child that applies `bpf_prog` and invokes `syscall`
+fn probe_syscall(bpf_prog: &seccompiler::BpfProgram, syscall: i64) -> Result<ProbeOutcome> {
+    // SAFETY: fork syscall; the child only makes raw syscalls before _exit
+    let pid = unsafe { libc::fork() };
+
+    if pid == -1 {
+        return Err(LeewardError::Seccomp(format!(
+            "failed to fork validation probe: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    if pid == 0 {
+        // Child process
+        if seccompiler::apply_filter(bpf_prog).is_err() {
+            // SAFETY: Exiting child process
+            unsafe { libc::_exit(PROBE_EXIT_SETUP) };
+        }
+
+        // SAFETY: Invoking the probed syscall with zeroed arguments
+        let ret = unsafe { libc::syscall(syscall, 0, 0, 0, 0, 0, 0) };
+        // SAFETY: Reading errno of this thread
+        let errno = unsafe { *libc::__errno_location() };
+
+        let code = if ret == -1 && errno == libc::EACCES {
+            PROBE_EXIT_BLOCKED
+        } else {
+            0
+        };
+        // SAFETY: Exiting child process
+        unsafe { libc::_exit(code) };
+    }
+
+    // Parent process
+    let started = std::time::Instant::now();
+    let mut status = 0;
+    loop {
+        // SAFETY: waitpid on our own child
+        let ret = unsafe { libc::waitpid(pid, &raw mut status, libc::WNOHANG) };
+        if ret == pid {
+            break;
+        }
+        if ret == -1 {
+            return Err(LeewardError::Seccomp(format!(
+                "failed to wait for validation probe: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        if started.elapsed() > PROBE_TIMEOUT {
+            // The syscall is blocking in the kernel (e.g. pause), so it got through
+            // SAFETY: killing and reaping our own child
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+                libc::waitpid(pid, &raw mut status, 0);
+            }
+            return Ok(ProbeOutcome::Reached);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    let outcome = if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS {
+        ProbeOutcome::Blocked
+    } else if libc::WIFEXITED(status) {
+        match libc::WEXITSTATUS(status) {
+            PROBE_EXIT_BLOCKED => ProbeOutcome::Blocked,
+            PROBE_EXIT_SETUP => ProbeOutcome::SetupFailed,
+            _ => ProbeOutcome::Reached,
+        }
+    } else {
+        ProbeOutcome::Reached
+    };
+
+    tracing::trace!(syscall, ?outcome, "seccomp probe finished");
+
+    Ok(outcome)
+}
+
+/// `SECCOMP_IOCTL_NOTIF_RECV`, not exported by libc
+const SECCOMP_IOCTL_NOTIF_RECV: libc::Ioctl = 0xc050_2100_u32 as libc::Ioctl;
+/// `SECCOMP_IOCTL_NOTIF_SEND`, not exported by libc
+const SECCOMP_IOCTL_NOTIF_SEND: libc::Ioctl = 0xc018_2101_u32 as libc::Ioctl;
+/// `SECCOMP_IOCTL_NOTIF_ID_VALID`, not exported by libc
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::Ioctl = 0x4008_2102;
+/// `SECCOMP_IOCTL_NOTIF_ADDFD`, not exported by libc
+const SECCOMP_IOCTL_NOTIF_ADDFD: libc::Ioctl = 0x4018_2103;
+/// `SECCOMP_USER_NOTIF_FLAG_CONTINUE` in the width of a response's flags
+#[allow(clippy::cast_possible_truncation)]
+const FLAG_CONTINUE: u32 = libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32;
+
+/// `AUDIT_ARCH_*` of the native syscall ABI, not exported by libc
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Bit set in x32 syscall numbers, which share the x86-64 audit arch
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Data of the trace action standing in for `SECCOMP_RET_USER_NOTIF` until
+/// a compiled allowlist is patched
+const NOTIFY_MARK: u32 = 0xffff;
+
+/// Build a filter that sends `syscalls` to a listener and allows the rest
+///
+/// Meant to be stacked with an allowlist: the kernel applies the most
+/// restrictive verdict, so syscalls the allowlist kills never reach the
+/// listener.
+fn listener_program(syscalls: &[i64]) -> Result<Vec<libc::sock_filter>> {
+    match_program(syscalls, libc::SECCOMP_RET_USER_NOTIF)
+}
+
+/// Build a filter that returns `action` for `syscalls` and allows the rest
+fn match_program(syscalls: &[i64], action: u32) -> Result<Vec<libc::sock_filter>> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    // Jump offsets are a u8, counted from the instruction after the jump
+    let count = u8::try_from(syscalls.len())
+        .map_err(|_| LeewardError::Seccomp("too many syscalls matched by one filter".into()))?;
+
+    let ld = bpf_code(BPF_LD | BPF_W | BPF_ABS);
+    let jeq = bpf_code(BPF_JMP | BPF_JEQ | BPF_K);
+    let ret = bpf_code(BPF_RET | BPF_K);
+
+    // offsetof(struct seccomp_data, arch) and offsetof(struct seccomp_data, nr)
+    let mut program = vec![
+        stmt(ld, 4),
+        jump(jeq, AUDIT_ARCH, 1, 0),
+        stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(ld, 0),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(
+            bpf_code(BPF_JMP | libc::BPF_JGE | BPF_K),
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    for (index, &syscall) in (0..count).zip(syscalls) {
+        let nr = u32::try_from(syscall)
+            .map_err(|_| LeewardError::Seccomp(format!("invalid syscall number {syscall}")))?;
+        // Skip the remaining comparisons and the allow to land on the action
+        program.push(jump(jeq, nr, count - index, 0));
+    }
+
+    program.push(stmt(ret, libc::SECCOMP_RET_ALLOW));
+    program.push(stmt(ret, action));
+
+    Ok(program)
+}
+
+/// A BPF statement
+const fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+/// A BPF jump, `jt`/`jf` instructions ahead of the next one
+const fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// Install a listener filter on the calling thread and return its fd
+///
+/// Sets `no_new_privs` first, as unprivileged filters require. Only makes
+/// raw syscalls, so it is safe to call between fork and exec.
+fn install_listener(program: &[libc::sock_filter]) -> std::io::Result<OwnedFd> {
+    let fd = install_program(program, libc::SECCOMP_FILTER_FLAG_NEW_LISTENER)?;
+
+    // SAFETY: The kernel returned a new listener fd (close-on-exec) we now own
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Install a filter on the calling thread with `flags`, returning what
+/// `seccomp()` did
+///
+/// Sets `no_new_privs` first, as unprivileged filters require. Only makes
+/// raw syscalls.
+fn install_program(
+    program: &[libc::sock_filter],
+    flags: libc::c_ulong,
+) -> std::io::Result<libc::c_long> {
+    let fprog = libc::sock_fprog {
+        len: u16::try_from(program.len())
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?,
+        filter: program.as_ptr().cast_mut(),
+    };
+
+    // SAFETY: prctl with constant arguments
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: seccomp syscall with a program that outlives the call
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            flags,
+            &raw const fprog,
+        )
+    };
+
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// Spawn `command` with `syscalls` routed to the returned stream
+///
+/// This is the embedded-mode entry point: no worker and no other isolation,
+/// just a listener filter installed in the child right before `exec`. The
+/// child's syscalls before `exec` are not routed. Notifications block the
+/// child until answered, so start consuming the stream right away.
+///
+/// # Errors
+///
+/// [`LeewardError::Seccomp`] if the listener filter cannot be compiled, and
+/// [`LeewardError::Io`] if `command` cannot be spawned, including when the
+/// child cannot install the filter.
+pub fn spawn_supervised(
+    command: &mut Command,
+    syscalls: &[i64],
+) -> Result<(Child, NotificationStream)> {
+    let program = listener_program(syscalls)?;
+    let (parent, child) = UnixStream::pair()?;
+    let child_fd = child.as_raw_fd();
+
+    // SAFETY: The hook only makes raw syscalls on memory prepared before fork
+    unsafe {
+        command.pre_exec(move || {
+            let listener = install_listener(&program)?;
+            crate::pipe::send_fd(child_fd, listener.as_raw_fd())
+        });
+    }
+
+    let spawned = command.spawn()?;
+    drop(child);
+
+    let listener = crate::pipe::recv_fd(parent.as_raw_fd(), libc::MSG_DONTWAIT)?
+        .ok_or_else(|| LeewardError::Seccomp("child did not hand over its listener".into()))?;
+
+    Ok((
+        spawned,
+        NotificationStream::new(SeccompNotifyFd::from(listener)),
+    ))
+}
+
+/// Build a filter that sends every syscall to a listener
+///
+/// The one exception is `sendmsg` on `handover`, the socket the child
+/// passes the listener fd over before anyone can answer it.
+fn observer_program(handover: RawFd) -> Result<Vec<libc::sock_filter>> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let handover = u32::try_from(handover)
+        .map_err(|_| LeewardError::Seccomp(format!("invalid handover fd {handover}")))?;
+    let sendmsg = u32::try_from(libc::SYS_sendmsg)
+        .map_err(|_| LeewardError::Seccomp("invalid sendmsg syscall number".into()))?;
+
+    let ld = bpf_code(BPF_LD | BPF_W | BPF_ABS);
+    let jeq = bpf_code(BPF_JMP | BPF_JEQ | BPF_K);
+    let ret = bpf_code(BPF_RET | BPF_K);
+
+    let mut program = vec![
+        stmt(ld, 4),
+        jump(jeq, AUDIT_ARCH, 1, 0),
+        stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(ld, 0),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(
+            bpf_code(BPF_JMP | libc::BPF_JGE | BPF_K),
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    // offsetof(struct seccomp_data, args[0]), low word on little-endian
+    program.extend([
+        jump(jeq, sendmsg, 0, 3),
+        stmt(ld, 16),
+        jump(jeq, handover, 0, 1),
+        stmt(ret, libc::SECCOMP_RET_ALLOW),
+        stmt(ret, libc::SECCOMP_RET_USER_NOTIF),
+    ]);
+
+    Ok(program)
+}
+
+/// Spawn `command` and hand every syscall it makes to `observe`
+///
+/// For watching trusted code, not confining it: every notification is
+/// allowed once `observe` has seen it, and the child gets no other
+/// isolation. This includes the `exec` itself and the few syscalls the
+/// standard library makes right before it. Notifications are answered on a
+/// thread of its own, which returns `state` once no process is left under
+/// the filter.
+///
+/// # Errors
+///
+/// [`LeewardError::Seccomp`] if the filter cannot be compiled or the child
+/// never hands over its listener, and [`LeewardError::Io`] if `command` cannot
+/// be spawned.
+pub fn spawn_observed<T: Send + 'static>(
+    command: &mut Command,
+    state: T,
+    observe: fn(&mut T, &PendingNotification),
+) -> Result<(Child, std::thread::JoinHandle<T>)> {
+    let (parent, child) = UnixStream::pair()?;
+    let program = observer_program(child.as_raw_fd())?;
+    let child_fd = child.as_raw_fd();
+
+    // SAFETY: The hook only makes raw syscalls on memory prepared before fork
+    unsafe {
+        command.pre_exec(move || {
+            let listener = install_listener(&program)?;
+            crate::pipe::send_fd(child_fd, listener.as_raw_fd())
+        });
     }
+
+    std::thread::scope(|scope| {
+        // The exec blocks on its notification, so spawn returns only once
+        // the listener is being answered
+        let spawning = scope.spawn(move || {
+            let spawned = command.spawn();
+            drop(child);
+            spawned
+        });
+
+        // Blocks until the child sends the listener, or the spawn fails
+        // and the last copy of its end is dropped
+        let Ok(Some(listener)) = crate::pipe::recv_fd(parent.as_raw_fd(), 0) else {
+            return match spawning.join() {
+                Ok(Err(e)) => Err(e.into()),
+                Ok(Ok(mut spawned)) => {
+                    let _ = spawned.kill();
+                    let _ = spawned.wait();
+                    Err(LeewardError::Seccomp(
+                        "child did not hand over its listener".into(),
+                    ))
+                }
+                Err(_) => Err(LeewardError::Seccomp("spawning thread panicked".into())),
+            };
+        };
+
+        let answering = observe_stream(
+            NotificationStream::new(SeccompNotifyFd::from(listener)),
+            state,
+            observe,
+        )?;
+
+        match spawning.join() {
+            Ok(spawned) => Ok((spawned?, answering)),
+            Err(_) => Err(LeewardError::Seccomp("spawning thread panicked".into())),
+        }
+    })
+}
+
+/// Hand every notification of `stream` to `observe` and let the syscall
+/// through, on a thread of its own
+///
+/// The thread returns `state` once no process is left under the filter.
+///
+/// # Errors
+///
+/// Fails if the thread cannot be started.
+pub fn observe_stream<T: Send + 'static>(
+    stream: NotificationStream,
+    state: T,
+    mut observe: impl FnMut(&mut T, &PendingNotification) + Send + 'static,
+) -> Result<std::thread::JoinHandle<T>> {
+    let answering = std::thread::Builder::new()
+        .name("leeward-observer".into())
+        .spawn(move || {
+            let mut state = state;
+            for pending in stream {
+                let Ok(pending) = pending else { break };
+                observe(&mut state, &pending);
+                drop(pending.respond(SeccompResponse::Allow));
+            }
+            state
+        })?;
+    Ok(answering)
 }
 
-/// File descriptor for receiving seccomp notifications
+/// Install a filter sending every syscall of the calling thread to a
+/// listener, and return the listener
 ///
-/// When a process attempts a blocked syscall with SECCOMP_RET_USER_NOTIF,
-/// the kernel sends a notification to this fd. The supervisor can:
-/// - Return EACCES to continue execution
-/// - Return different error code
-/// - Allow the syscall (with caution)
-/// - Terminate the process
+/// Nothing is allowed or denied by the filter itself, so this confines
+/// nothing; whatever answers the listener decides. `sendmsg` on `handover`
+/// is the one syscall let through, so the listener can be passed on over
+/// it before anyone answers.
+pub(crate) fn install_observer(handover: RawFd) -> Result<OwnedFd> {
+    install_listener(&observer_program(handover)?)
+        .map_err(|e| LeewardError::Seccomp(format!("failed to install observing listener: {e}")))
+}
+
+/// Listener fd for seccomp user notifications
+///
+/// Processes under a filter returning `SECCOMP_RET_USER_NOTIF` block in the
+/// kernel until the holder of this fd answers. Dropping it fails every
+/// pending and future notification with `ENOSYS`.
 #[derive(Debug)]
 pub struct SeccompNotifyFd {
-    fd: RawFd,
+    fd: OwnedFd,
 }
 
-impl SeccompNotifyFd {
-    /// Create from raw file descriptor
-    pub fn from_raw_fd(fd: RawFd) -> Self {
+impl From<OwnedFd> for SeccompNotifyFd {
+    fn from(fd: OwnedFd) -> Self {
         Self { fd }
     }
+}
 
-    /// Get the raw file descriptor
-    pub fn as_raw_fd(&self) -> RawFd {
-        self.fd
+impl AsRawFd for SeccompNotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
     }
+}
 
-    /// Wait for a seccomp notification
-    pub fn wait_notification(&self) -> Result<SeccompNotification> {
-        // TODO: Read seccomp_notif structure from fd
-        // struct seccomp_notif {
-        //     __u64 id;
-        //     __u32 pid;
-        //     __u32 flags;
-        //     struct seccomp_data data;
-        // };
+impl AsFd for SeccompNotifyFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl SeccompNotifyFd {
+    /// Wait for the next seccomp notification
+    ///
+    /// Returns `None` once no process is left under the filter. Notifications
+    /// whose process died before they could be received are skipped.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the listener cannot be polled or read.
+    pub fn wait_notification(&self) -> Result<Option<SeccompNotification>> {
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: poll on a single pollfd we own
+            let ready = unsafe { libc::poll(&raw mut pollfd, 1, -1) };
+            if ready < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(notify_error("failed to poll listener", &err));
+            }
+            if pollfd.revents & libc::POLLIN == 0 && pollfd.revents & libc::POLLHUP != 0 {
+                return Ok(None);
+            }
 
-        tracing::debug!("waiting for seccomp notification");
+            // RECV requires the buffer zeroed, and sized for the kernel's
+            // struct, which may have grown past ours
+            let mut buf = notif_buffer::<libc::seccomp_notif>(notif_sizes().seccomp_notif);
+            // SAFETY: ioctl filling in a buffer at least as large as the kernel's seccomp_notif
+            let ret = unsafe {
+                libc::ioctl(self.as_raw_fd(), SECCOMP_IOCTL_NOTIF_RECV, buf.as_mut_ptr())
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    // Interrupted, or the process died between poll and receive
+                    Some(libc::EINTR | libc::ENOENT) => continue,
+                    _ => return Err(notify_error("failed to receive notification", &err)),
+                }
+            }
+            // SAFETY: The buffer is aligned for and at least as large as a
+            // seccomp_notif, whose leading fields the kernel filled in
+            let notif = unsafe { buf.as_ptr().cast::<libc::seccomp_notif>().read() };
 
-        // Placeholder
-        Ok(SeccompNotification {
-            id: 0,
-            pid: 0,
-            syscall: 0,
-            args: [0; 6],
-        })
+            let notification = SeccompNotification {
+                id: notif.id,
+                pid: notif.pid,
+                syscall: i64::from(notif.data.nr),
+                args: notif.data.args,
+            };
+            tracing::trace!(
+                id = notification.id,
+                pid = notification.pid,
+                syscall = notification.syscall,
+                "received seccomp notification"
+            );
+            return Ok(Some(notification));
+        }
     }
 
     /// Send a response to a seccomp notification
-    pub fn send_response(&self, notif: &SeccompNotification, response: SeccompResponse) -> Result<()> {
-        // TODO: Write seccomp_notif_resp structure to fd
-        // struct seccomp_notif_resp {
-        //     __u64 id;
-        //     __s64 val;  // Return value or error code
-        //     __s32 error;  // errno value
-        //     __u32 flags;
-        // };
+    ///
+    /// Returns `false` if the notification is no longer pending, because
+    /// its process died or was interrupted by a signal while waiting.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the kernel refuses the response for any
+    /// other reason.
+    pub fn send_response(
+        &self,
+        notif: &SeccompNotification,
+        response: SeccompResponse,
+    ) -> Result<bool> {
+        let (val, error, flags) = match response {
+            SeccompResponse::DenyWithEacces => (0, -libc::EACCES, 0),
+            SeccompResponse::DenyWithError(errno) => (0, -errno, 0),
+            SeccompResponse::Allow => (0, 0, FLAG_CONTINUE),
+            SeccompResponse::ContinueWithValue(val) => (val, 0, 0),
+        };
+        let resp = libc::seccomp_notif_resp {
+            id: notif.id,
+            val,
+            error,
+            flags,
+        };
+        // Fields the kernel has and we don't stay zero
+        let mut buf = notif_buffer::<libc::seccomp_notif_resp>(notif_sizes().seccomp_notif_resp);
+        // SAFETY: The buffer is aligned for and at least as large as a seccomp_notif_resp
+        unsafe {
+            buf.as_mut_ptr()
+                .cast::<libc::seccomp_notif_resp>()
+                .write(resp);
+        };
 
         tracing::debug!(
             id = notif.id,
@@ -163,7 +1348,332 @@ impl SeccompNotifyFd {
             "sending seccomp response"
         );
 
-        Ok(())
+        // SAFETY: ioctl reading a buffer at least as large as the kernel's seccomp_notif_resp
+        let ret =
+            unsafe { libc::ioctl(self.as_raw_fd(), SECCOMP_IOCTL_NOTIF_SEND, buf.as_mut_ptr()) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOENT) {
+                tracing::debug!(
+                    id = notif.id,
+                    pid = notif.pid,
+                    "notification target is gone"
+                );
+                return Ok(false);
+            }
+            return Err(notify_error("failed to send response", &err));
+        }
+
+        Ok(true)
+    }
+
+    /// Check that notification `id` is still pending
+    ///
+    /// A pending notification pins its process, so its pid has not been
+    /// reused for another process.
+    #[must_use]
+    pub fn id_valid(&self, id: u64) -> bool {
+        let mut id = id;
+        // SAFETY: ioctl reading a u64 we own
+        unsafe { libc::ioctl(self.as_raw_fd(), SECCOMP_IOCTL_NOTIF_ID_VALID, &raw mut id) == 0 }
+    }
+}
+
+/// Sizes of the kernel's notification structs, asked once with
+/// `SECCOMP_GET_NOTIF_SIZES`
+///
+/// Sizes are zero where the kernel cannot say, leaving the ones libc was
+/// built against.
+fn notif_sizes() -> libc::seccomp_notif_sizes {
+    static SIZES: std::sync::OnceLock<libc::seccomp_notif_sizes> = std::sync::OnceLock::new();
+    *SIZES.get_or_init(|| {
+        let mut sizes = libc::seccomp_notif_sizes {
+            seccomp_notif: 0,
+            seccomp_notif_resp: 0,
+            seccomp_data: 0,
+        };
+        // SAFETY: seccomp filling in a seccomp_notif_sizes we own
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_GET_NOTIF_SIZES,
+                0,
+                &raw mut sizes,
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            tracing::debug!(error = %err, "seccomp notification sizes unknown, using our own");
+        }
+        sizes
+    })
+}
+
+/// Zeroed buffer for a `T` the kernel knows as `kernel_size` bytes, aligned
+/// for either and large enough for both
+fn notif_buffer<T>(kernel_size: u16) -> Vec<u64> {
+    let len = size_of::<T>().max(usize::from(kernel_size));
+    vec![0; len.div_ceil(size_of::<u64>())]
+}
+
+/// Wrap an OS error from the listener
+fn notify_error(context: &str, err: &std::io::Error) -> LeewardError {
+    LeewardError::Seccomp(format!("{context}: {err}"))
+}
+
+/// Stream of seccomp notifications for an embedder to decide on
+///
+/// Iterating blocks until a notification arrives and ends once no process
+/// is left under the filter. The stream is also [`AsFd`], so it can be
+/// registered with an event loop and polled for readability instead.
+///
+/// Each item is a [`PendingNotification`] that must be answered with
+/// [`PendingNotification::respond`]; the process stays blocked in its
+/// syscall until then. The process can die at any point while a decision is
+/// being made: `respond` then reports `false`, and the memory helpers fail.
+///
+/// # ID revalidation
+///
+/// The pid in a notification is only meaningful while the notification is
+/// pending. Once the process dies its pid can be reused, so anything read
+/// through the pid (`/proc/<pid>/...`) must be followed by a check that the
+/// notification is still valid (`SECCOMP_IOCTL_NOTIF_ID_VALID`) before the
+/// result is trusted. [`PendingNotification::read_mem`] and
+/// [`PendingNotification::add_fd`] do this; embedders reading `/proc`
+/// themselves must call [`PendingNotification::is_valid`] afterwards.
+///
+/// Memory read this way can still be changed by other threads of the
+/// process before the syscall runs, so [`SeccompResponse::Allow`] based on
+/// pointer arguments is advisory, not a security boundary. Denying is
+/// always safe.
+#[derive(Debug)]
+pub struct NotificationStream {
+    listener: Arc<SeccompNotifyFd>,
+    denials: Option<Arc<DenialLog>>,
+}
+
+impl NotificationStream {
+    /// Stream notifications from `listener`
+    #[must_use]
+    pub fn new(listener: SeccompNotifyFd) -> Self {
+        Self {
+            listener: Arc::new(listener),
+            denials: None,
+        }
+    }
+
+    /// Record every syscall denied through this stream in `denials`
+    #[must_use]
+    pub fn recording(mut self, denials: Arc<DenialLog>) -> Self {
+        self.denials = Some(denials);
+        self
+    }
+}
+
+impl Iterator for NotificationStream {
+    type Item = Result<PendingNotification>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.listener.wait_notification() {
+            Ok(Some(notification)) => Some(Ok(PendingNotification {
+                notification,
+                listener: Arc::clone(&self.listener),
+                denials: self.denials.clone(),
+                answered: false,
+            })),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl AsFd for NotificationStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+impl AsRawFd for NotificationStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// A notification waiting for a decision
+///
+/// Dereferences to the [`SeccompNotification`]. Dropping it unanswered
+/// denies the syscall with `EACCES`, so a panicking or buggy embedder fails
+/// closed instead of leaving the process blocked. Denials are recorded if
+/// the stream is [`NotificationStream::recording`].
+#[derive(Debug)]
+pub struct PendingNotification {
+    notification: SeccompNotification,
+    listener: Arc<SeccompNotifyFd>,
+    denials: Option<Arc<DenialLog>>,
+    answered: bool,
+}
+
+impl std::ops::Deref for PendingNotification {
+    type Target = SeccompNotification;
+
+    fn deref(&self) -> &Self::Target {
+        &self.notification
+    }
+}
+
+impl PendingNotification {
+    /// Whether the process is still waiting on this notification
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.listener.id_valid(self.notification.id)
+    }
+
+    /// Read up to `len` bytes at `addr` in the process's memory
+    ///
+    /// Stops early at unmapped memory. Fails if the process died, so the
+    /// bytes always come from the process that made the syscall.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the process died or nothing is mapped at
+    /// `addr`.
+    pub fn read_mem(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        use std::os::unix::fs::FileExt;
+
+        let mem = std::fs::File::open(format!("/proc/{}/mem", self.notification.pid))
+            .map_err(|e| LeewardError::Seccomp(format!("failed to open process memory: {e}")))?;
+        // The open may have raced with the pid being reused
+        if !self.is_valid() {
+            return Err(self.gone());
+        }
+
+        let mut buf = vec![0u8; len];
+        let mut read = 0;
+        while read < len {
+            match mem.read_at(&mut buf[read..], addr + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                // Nothing mapped at the (remaining) address
+                Err(e) if e.raw_os_error() == Some(libc::EIO) && read > 0 => break,
+                Err(e) => {
+                    return Err(LeewardError::Seccomp(format!(
+                        "failed to read process memory: {e}"
+                    )));
+                }
+            }
+        }
+        buf.truncate(read);
+
+        Ok(buf)
+    }
+
+    /// Read the NUL-terminated path at `addr`, e.g. an `openat` argument
+    ///
+    /// # Errors
+    ///
+    /// As [`PendingNotification::read_mem`], and [`LeewardError::Seccomp`] if
+    /// no NUL ends the path within `PATH_MAX` bytes.
+    pub fn read_path(&self, addr: u64) -> Result<PathBuf> {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut bytes = self.read_mem(addr, libc::PATH_MAX as usize)?;
+        let end = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| LeewardError::Seccomp("path argument is not NUL-terminated".into()))?;
+        bytes.truncate(end);
+
+        Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+    }
+
+    /// Install a copy of `fd` in the process and return its number there
+    ///
+    /// Combined with [`SeccompResponse::ContinueWithValue`], this lets the
+    /// supervisor open a file itself and hand back the result of e.g.
+    /// `openat` without the process ever making the syscall.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Seccomp`] if the process died or the kernel refuses the
+    /// descriptor.
+    pub fn add_fd(&self, fd: BorrowedFd<'_>, cloexec: bool) -> Result<RawFd> {
+        let srcfd = u32::try_from(fd.as_raw_fd())
+            .map_err(|_| LeewardError::Seccomp(format!("invalid fd {}", fd.as_raw_fd())))?;
+        let mut addfd = libc::seccomp_notif_addfd {
+            id: self.notification.id,
+            flags: 0,
+            srcfd,
+            newfd: 0,
+            newfd_flags: if cloexec { libc::O_CLOEXEC as u32 } else { 0 },
+        };
+
+        // SAFETY: ioctl reading a seccomp_notif_addfd we own
+        let ret = unsafe {
+            libc::ioctl(
+                self.listener.as_raw_fd(),
+                SECCOMP_IOCTL_NOTIF_ADDFD,
+                &raw mut addfd,
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOENT) {
+                return Err(self.gone());
+            }
+            return Err(notify_error("failed to add fd", &err));
+        }
+
+        Ok(ret)
+    }
+
+    /// Answer the notification, unblocking the process
+    ///
+    /// Returns `false` if the process died or was interrupted while the
+    /// decision was being made, in which case there is nobody to answer.
+    ///
+    /// # Errors
+    ///
+    /// As [`SeccompNotifyFd::send_response`].
+    pub fn respond(mut self, response: SeccompResponse) -> Result<bool> {
+        self.answered = true;
+        if response.is_denial() {
+            self.record_denial();
+        }
+        self.listener.send_response(&self.notification, response)
+    }
+
+    /// Note the denial in the stream's log, if it keeps one
+    fn record_denial(&self) {
+        let syscall = syscall_label(self.notification.syscall);
+        if DebugFlag::SeccompDenials.enabled() {
+            tracing::info!(
+                pid = self.notification.pid,
+                "seccomp denied {}",
+                describe(self, &syscall)
+            );
+        }
+        if let Some(denials) = &self.denials {
+            denials.record(DenialLayer::Seccomp, &syscall, || describe(self, &syscall));
+        }
+    }
+
+    fn gone(&self) -> LeewardError {
+        LeewardError::Seccomp(format!(
+            "process {} is no longer waiting on notification {}",
+            self.notification.pid, self.notification.id
+        ))
+    }
+}
+
+impl Drop for PendingNotification {
+    fn drop(&mut self) {
+        if !self.answered {
+            self.record_denial();
+            drop(
+                self.listener
+                    .send_response(&self.notification, SeccompResponse::DenyWithEacces),
+            );
+        }
     }
 }
 
@@ -181,7 +1691,7 @@ pub struct SeccompNotification {
 }
 
 /// Response to a seccomp notification
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum SeccompResponse {
     /// Deny with EACCES
     DenyWithEacces,
@@ -189,20 +1699,260 @@ pub enum SeccompResponse {
     DenyWithError(i32),
     /// Allow the syscall (use with extreme caution)
     Allow,
-    /// Continue with specific return value
+    /// Skip the syscall and return this value instead
     ContinueWithValue(i64),
 }
 
-impl Drop for SeccompNotifyFd {
-    fn drop(&mut self) {
-        unsafe {
-            libc::close(self.fd);
+impl SeccompResponse {
+    /// Whether the syscall fails with an error
+    #[must_use]
+    pub const fn is_denial(self) -> bool {
+        matches!(self, Self::DenyWithEacces | Self::DenyWithError(_))
+    }
+}
+
+/// A BPF instruction code, which the kernel keeps in 16 bits
+#[allow(clippy::cast_possible_truncation)]
+const fn bpf_code(code: u32) -> u16 {
+    code as u16
+}
+
+/// Name of syscall `nr`, or its number
+fn syscall_label(nr: i64) -> String {
+    syscall_name(nr).map_or_else(|| format!("syscall {nr}"), str::to_owned)
+}
+
+/// The `int` in the low 32 bits of a syscall argument
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+pub(crate) const fn int_arg(arg: u64) -> libc::c_int {
+    arg as u32 as libc::c_int
+}
+
+/// `syscall` with its decisive argument, e.g. `connect(1.2.3.4:443)`
+///
+/// The argument is left out if the process's memory cannot be read.
+fn describe(pending: &PendingNotification, syscall: &str) -> String {
+    let args = pending.args;
+    let path = |addr| {
+        pending
+            .read_path(addr)
+            .ok()
+            .map(|path| path.display().to_string())
+    };
+    let address = |addr, len: u64| {
+        let len = usize::try_from(len).ok()?.min(SOCKADDR_MAX);
+        sockaddr(&pending.read_mem(addr, len).ok()?)
+    };
+
+    let argument = match pending.syscall {
+        libc::SYS_socket => Some(format!(
+            "{}, {}",
+            family_name(int_arg(args[0])),
+            socket_type_name(int_arg(args[1]))
+        )),
+        libc::SYS_connect | libc::SYS_bind => address(args[1], args[2]),
+        libc::SYS_sendto => address(args[4], args[5]),
+        libc::SYS_accept | libc::SYS_accept4 => Some(format!("fd {}", int_arg(args[0]))),
+        libc::SYS_execve => path(args[0]),
+        libc::SYS_openat
+        | libc::SYS_openat2
+        | libc::SYS_execveat
+        | libc::SYS_mkdirat
+        | libc::SYS_unlinkat
+        | libc::SYS_newfstatat
+        | libc::SYS_faccessat
+        | libc::SYS_faccessat2 => path(args[1]),
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open
+        | libc::SYS_creat
+        | libc::SYS_stat
+        | libc::SYS_lstat
+        | libc::SYS_access
+        | libc::SYS_mkdir
+        | libc::SYS_unlink => path(args[0]),
+        _ => None,
+    };
+    format!("{syscall}({})", argument.unwrap_or_default())
+}
+
+/// Size of `sockaddr_storage`, more than any address needs
+const SOCKADDR_MAX: usize = 128;
+
+/// Render a `sockaddr` as `1.2.3.4:443`, `[::1]:80` or a socket path
+fn sockaddr(bytes: &[u8]) -> Option<String> {
+    use std::net::{SocketAddrV4, SocketAddrV6};
+
+    let family = u16::from_ne_bytes(bytes.get(..2)?.try_into().ok()?);
+    let port = || Some(u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?));
+    match i32::from(family) {
+        libc::AF_INET => {
+            let ip: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+            Some(SocketAddrV4::new(ip.into(), port()?).to_string())
+        }
+        libc::AF_INET6 => {
+            let ip: [u8; 16] = bytes.get(8..24)?.try_into().ok()?;
+            Some(SocketAddrV6::new(ip.into(), port()?, 0, 0).to_string())
+        }
+        libc::AF_UNIX => {
+            let path = bytes.get(2..)?;
+            // Abstract socket names start with a NUL, shown as @
+            let (prefix, path) = match path.split_first() {
+                Some((0, name)) => ("@", name),
+                _ => ("", path),
+            };
+            let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+            Some(format!("{prefix}{}", String::from_utf8_lossy(&path[..end])))
+        }
+        _ => None,
+    }
+}
+
+/// `AF_*` name of an address family
+fn family_name(family: libc::c_int) -> String {
+    match family {
+        libc::AF_UNIX => "AF_UNIX".into(),
+        libc::AF_INET => "AF_INET".into(),
+        libc::AF_INET6 => "AF_INET6".into(),
+        libc::AF_NETLINK => "AF_NETLINK".into(),
+        libc::AF_PACKET => "AF_PACKET".into(),
+        other => other.to_string(),
+    }
+}
+
+/// `SOCK_*` name of a socket type
+fn socket_type_name(kind: libc::c_int) -> String {
+    // Leave out SOCK_NONBLOCK and SOCK_CLOEXEC
+    match kind & 0xf {
+        libc::SOCK_STREAM => "SOCK_STREAM".into(),
+        libc::SOCK_DGRAM => "SOCK_DGRAM".into(),
+        libc::SOCK_RAW => "SOCK_RAW".into(),
+        libc::SOCK_SEQPACKET => "SOCK_SEQPACKET".into(),
+        other => other.to_string(),
+    }
+}
+
+/// Decides on the notifications a [`Supervisor`] would let through, see
+/// [`Supervisor::with_policy`]
+pub type Policy = Arc<dyn Fn(&SeccompNotification) -> SeccompResponse + Send + Sync>;
+
+/// Policy failing every syscall it is asked about with `EACCES`
+#[must_use]
+pub const fn deny_all(_: &SeccompNotification) -> SeccompResponse {
+    SeccompResponse::DenyWithEacces
+}
+
+/// Built-in policy for a worker's notifications
+///
+/// Accounts `socket`/`accept`/`accept4` against the worker's
+/// [`ConnectionTracker`], failing them with `EMFILE` over the limit,
+/// counts `connect` there, and lets everything else through unless a [`Policy`] says otherwise.
+/// Embedders that take a worker's stream can pass the notifications they
+/// do not handle to [`Supervisor::handle`].
+#[derive(Clone)]
+pub struct Supervisor {
+    connections: Arc<ConnectionTracker>,
+    /// The only syscalls let through, for a listener that also gets denials
+    routed: Option<Vec<i64>>,
+    policy: Option<Policy>,
+}
+
+impl std::fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("connections", &self.connections)
+            .field("routed", &self.routed)
+            .field("policy", &self.policy.is_some())
+            .finish()
+    }
+}
+
+impl Supervisor {
+    #[must_use]
+    pub const fn new(connections: Arc<ConnectionTracker>) -> Self {
+        Self {
+            connections,
+            routed: None,
+            policy: None,
+        }
+    }
+
+    /// Ask `policy` about every notification instead of letting it through
+    ///
+    /// A syscall `policy` denies is not accounted as a connection; one it
+    /// allows still fails over the connection limit.
+    #[must_use]
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Fail every syscall but `routed` with `EPERM`, for a listener
+    /// installed with [`SeccompConfig::notify_denials`]
+    #[must_use]
+    pub fn denying_unrouted(mut self, routed: Vec<i64>) -> Self {
+        self.routed = Some(routed);
+        self
+    }
+
+    /// Decide on a notification
+    #[must_use]
+    pub fn decide(&self, notification: &SeccompNotification) -> SeccompResponse {
+        if self
+            .routed
+            .as_ref()
+            .is_some_and(|routed| !routed.contains(&notification.syscall))
+        {
+            return SeccompResponse::DenyWithError(libc::EPERM);
+        }
+        let decided = self
+            .policy
+            .as_ref()
+            .map_or(SeccompResponse::Allow, |policy| policy(notification));
+        if decided.is_denial() {
+            return decided;
+        }
+        self.connections
+            .on_syscall(notification.syscall)
+            .map_or(decided, SeccompResponse::DenyWithError)
+    }
+
+    /// Decide on and answer a notification
+    ///
+    /// # Errors
+    ///
+    /// As [`PendingNotification::respond`].
+    pub fn handle(&self, pending: PendingNotification) -> Result<bool> {
+        let response = self.decide(&pending);
+        pending.respond(response)
+    }
+
+    /// Answer notifications until no process is left under the filter
+    pub fn run(&self, stream: NotificationStream) {
+        for pending in stream {
+            let outcome = pending.and_then(|pending| self.handle(pending));
+            if let Err(e) = outcome {
+                tracing::warn!("seccomp supervisor stopped: {}", e);
+                return;
+            }
         }
+        tracing::debug!("seccomp supervisor finished, no process left under the filter");
+    }
+
+    /// Run on a thread of its own
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the thread cannot be started.
+    pub fn spawn(self, stream: NotificationStream) -> Result<std::thread::JoinHandle<()>> {
+        std::thread::Builder::new()
+            .name("leeward-supervisor".into())
+            .spawn(move || self.run(stream))
+            .map_err(LeewardError::from)
     }
 }
 
 /// Get the current architecture for seccomp
-fn get_arch() -> TargetArch {
+const fn get_arch() -> TargetArch {
     #[cfg(target_arch = "x86_64")]
     return TargetArch::x86_64;
 
@@ -213,37 +1963,137 @@ fn get_arch() -> TargetArch {
     compile_error!("Unsupported architecture for seccomp");
 }
 
-/// Default syscalls needed for Python to run
-fn default_python_syscalls() -> Vec<i64> {
-    vec![
+/// [`SyscallProfile::MinimalIO`]
+fn minimal_io_syscalls() -> Vec<SyscallRule> {
+    let mut rules: Vec<SyscallRule> = [
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_close,
+        libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_lseek,
+        libc::SYS_munmap,
+        libc::SYS_brk,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_futex,
+        libc::SYS_getrandom,
+        libc::SYS_clock_gettime,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+    ]
+    .into_iter()
+    .map(SyscallRule::allow)
+    .collect();
+    rules.push(SyscallRule::allow_no_new_fds_openat());
+    rules.push(SyscallRule::allow_mmap_no_exec());
+    rules
+}
+
+/// [`SyscallProfile::Python`]
+fn python_syscalls() -> Vec<SyscallRule> {
+    let mut rules: Vec<SyscallRule> = [
         libc::SYS_read,
         libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
         libc::SYS_close,
         libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
         libc::SYS_lseek,
         libc::SYS_mmap,
         libc::SYS_mprotect,
         libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_madvise,
         libc::SYS_brk,
         libc::SYS_rt_sigaction,
         libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_setitimer,
         libc::SYS_ioctl,
         libc::SYS_access,
+        libc::SYS_faccessat2,
         libc::SYS_dup,
         libc::SYS_dup2,
+        libc::SYS_dup3,
+        libc::SYS_pipe2,
+        libc::SYS_poll,
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_wait,
+        libc::SYS_epoll_pwait,
+        libc::SYS_eventfd2,
         libc::SYS_getpid,
+        libc::SYS_getppid,
+        libc::SYS_gettid,
+        libc::SYS_getpgrp,
         libc::SYS_getuid,
         libc::SYS_getgid,
         libc::SYS_geteuid,
         libc::SYS_getegid,
         libc::SYS_fcntl,
         libc::SYS_openat,
-        libc::SYS_newfstatat,
+        libc::SYS_getcwd,
+        libc::SYS_chdir,
+        libc::SYS_readlink,
+        libc::SYS_readlinkat,
+        libc::SYS_mkdir,
+        libc::SYS_mkdirat,
+        libc::SYS_rmdir,
+        libc::SYS_rename,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_fchmod,
+        libc::SYS_umask,
+        libc::SYS_fsync,
+        libc::SYS_fstatfs,
+        libc::SYS_uname,
+        libc::SYS_sysinfo,
+        libc::SYS_prlimit64,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_sched_yield,
         libc::SYS_exit,
         libc::SYS_exit_group,
         libc::SYS_futex,
         libc::SYS_getrandom,
         libc::SYS_clock_gettime,
         libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        // Threads and subprocesses: glibc sets these up for each thread,
+        // and Python waits on its children
+        libc::SYS_set_tid_address,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_arch_prctl,
+        libc::SYS_execve,
+        libc::SYS_vfork,
+        libc::SYS_close_range,
+        libc::SYS_wait4,
+        libc::SYS_tgkill,
+        // The worker empties /tmp and measures its scratch mounts between executions
+        libc::SYS_getdents64,
+        libc::SYS_unlink,
+        libc::SYS_unlinkat,
+        libc::SYS_statfs,
+        // and removes what is left of the last one's processes and System V IPC
+        libc::SYS_kill,
+        libc::SYS_shmctl,
+        libc::SYS_semctl,
+        libc::SYS_msgctl,
     ]
+    .into_iter()
+    .map(SyscallRule::allow)
+    .collect();
+    rules.push(SyscallRule::allow_clone_no_namespaces());
+    rules
 }
@@ -0,0 +1,494 @@
+//! Shared sandbox root built once and cloned into every worker
+//!
+//! A [`RootTemplate`] assembles the bind-mount layout from a
+//! [`SandboxConfig`] a single time, inside a private mount namespace held
+//! open by a small keeper process. Workers clone the assembled tree with
+//! `open_tree(OPEN_TREE_CLONE)` and attach it with `move_mount`, then layer
+//! their own scratch tmpfs mounts on top and pivot into it: one for the
+//! workspace and one, sized separately, for `/tmp`.
+//!
+//! The template snapshots the mount *structure*: mounts added or removed on
+//! the host after it was built are not seen by workers. File *contents*
+//! under the bind mounts are not copied, so edits to host files remain
+//! visible to every worker.
+
+use super::clone3;
+use super::mounts::{
+    BindOptions, bind_source, check_pivoted, dev_links, device, make_rprivate, mount_bind,
+    mount_remount_ro, mount_tmpfs, path_to_cstring, pivot_root, umount2,
+};
+use super::netns::{ControlledNetwork, RESOLV_CONF};
+use super::registry::{self, RootClaim};
+use crate::config::paths::{CanonicalPath, PathPolicy};
+use crate::config::{TMP_DIR, zoneinfo_path};
+use crate::units::ByteSize;
+use crate::{LeewardError, Result, SandboxConfig};
+use nix::fcntl::OFlag;
+use nix::sched::CloneFlags;
+use nix::sys::stat::Mode;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Size of the template's own tmpfs, which only holds mount points
+const TEMPLATE_TMPFS_SIZE: ByteSize = ByteSize::mib(1);
+
+/// Size of each worker's workspace tmpfs
+pub(crate) const WORKSPACE_TMPFS_SIZE: ByteSize = ByteSize::mib(64);
+
+/// Message the keeper sends once the template is assembled
+const READY: &str = "ready";
+
+/// Where the configured zone file appears in the template
+const LOCALTIME: &str = "/etc/localtime";
+
+/// Device nodes bound into every template
+const DEVICES: [&str; 5] = [
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+];
+
+/// Tells apart the roots of templates built by one process
+static NEXT_TEMPLATE: AtomicU32 = AtomicU32::new(0);
+
+/// `MOVE_MOUNT_F_EMPTY_PATH`, not exported by libc on every target
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+/// A fully assembled sandbox root shared read-only by all workers
+#[derive(Debug)]
+pub struct RootTemplate {
+    /// Keeper process holding the template's mount namespace
+    keeper: libc::pid_t,
+    /// Handle on the keeper's mount namespace
+    mnt_ns: File,
+    /// Location of the template root, valid in both namespaces, which is
+    /// removed once the keeper is gone
+    root: RootClaim,
+    /// Paths that get a fresh tmpfs in each worker, with its size
+    scratch: Vec<(PathBuf, ByteSize)>,
+    /// Process that built the template and owns the keeper
+    owner: u32,
+}
+
+impl RootTemplate {
+    /// Assemble the template from the config's bind mounts
+    ///
+    /// Sources that do not exist on the host are skipped.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Mount`] naming the offending path if any part of the
+    /// layout cannot be built.
+    pub fn build(config: &SandboxConfig) -> Result<Self> {
+        // Numbered, so a rebuilt template never shares a root with the one
+        // it replaces while workers still use that
+        let name = format!(
+            "{}{}-{}",
+            registry::ROOT_PREFIX,
+            std::process::id(),
+            NEXT_TEMPLATE.fetch_add(1, Ordering::Relaxed)
+        );
+        let root = registry::roots_dir().join(&name);
+        let claim = RootClaim::register(root.clone());
+        let root_dir = registry::open_roots_dir()
+            .and_then(|dir| registry::create_root(dir, &name))
+            .map_err(|e| {
+                LeewardError::Mount(format!(
+                    "failed to create template root {}: {e}",
+                    root.display()
+                ))
+            })?;
+
+        config.validate()?;
+        let binds = template_binds(config)?;
+        // /tmp first, so a workdir under it is mounted on top
+        let scratch = vec![
+            (PathBuf::from(TMP_DIR), config.tmp_size),
+            (config.workdir.clone(), WORKSPACE_TMPFS_SIZE),
+        ];
+        let (mut status_rx, mut status_tx) = crate::pipe::create_pipe()?;
+
+        // `root_dir` moves into the keeper, so it is closed here once the
+        // keeper is cloned, before any worker could be forked with it
+        let keeper_root = root;
+        let keeper_scratch = scratch.clone();
+        let keeper = clone3::clone_worker(0, move || {
+            // SAFETY: prctl with constant arguments
+            unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+
+            let status = match assemble(&keeper_root, &root_dir, &binds, &keeper_scratch) {
+                Ok(()) => READY.to_string(),
+                Err(e) => e.to_string(),
+            };
+            status_tx.write_all(status.as_bytes())?;
+            drop(status_tx);
+
+            if status == READY {
+                loop {
+                    // SAFETY: pause has no preconditions
+                    unsafe { libc::pause() };
+                }
+            }
+            Ok(())
+        })?;
+
+        let mut status = String::new();
+        status_rx.read_to_string(&mut status)?;
+
+        if status != READY {
+            // SAFETY: Reaping our own child, which exits after reporting
+            unsafe { libc::waitpid(keeper, std::ptr::null_mut(), 0) };
+
+            return Err(if status.is_empty() {
+                LeewardError::Mount("template keeper exited during setup".into())
+            } else {
+                LeewardError::Mount(format!("failed to build root template: {status}"))
+            });
+        }
+
+        let mnt_ns = File::open(format!("/proc/{keeper}/ns/mnt"));
+        let template = Self {
+            keeper,
+            mnt_ns: match mnt_ns {
+                Ok(file) => file,
+                Err(e) => {
+                    // SAFETY: Killing and reaping our own child
+                    unsafe {
+                        libc::kill(keeper, libc::SIGKILL);
+                        libc::waitpid(keeper, std::ptr::null_mut(), 0);
+                    }
+                    return Err(e.into());
+                }
+            },
+            root: claim,
+            scratch,
+            owner: std::process::id(),
+        };
+
+        tracing::info!(root = ?template.root(), keeper, "root template ready");
+        Ok(template)
+    }
+
+    /// Where the template root lives
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// Descriptor a worker needs kept to [`attach`](Self::attach) the template
+    pub(crate) fn namespace_fd(&self) -> RawFd {
+        self.mnt_ns.as_raw_fd()
+    }
+
+    /// Clone the template into the current mount namespace at `target`
+    ///
+    /// The calling process must be single-threaded and have its own mount
+    /// namespace.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Mount`] if the template cannot be cloned or mounted at
+    /// `target`.
+    pub fn attach(&self, target: &Path) -> Result<()> {
+        let own_ns = File::open("/proc/self/ns/mnt")?;
+
+        nix::sched::setns(&self.mnt_ns, CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Mount(format!("failed to enter template namespace: {e}")))?;
+        let tree = open_tree_clone(self.root());
+        // Always return to our own namespace, even if the clone failed
+        nix::sched::setns(&own_ns, CloneFlags::CLONE_NEWNS).map_err(|e| {
+            LeewardError::Mount(format!("failed to return from template namespace: {e}"))
+        })?;
+        let tree = tree?;
+
+        std::fs::create_dir_all(target).map_err(|e| {
+            LeewardError::Mount(format!("failed to create {}: {e}", target.display()))
+        })?;
+        move_mount(&tree, target)
+    }
+
+    /// Attach the template, mount scratch space, and pivot into it
+    ///
+    /// Whatever the host has at the root's path is hidden under the
+    /// template at once. Fails if `/` is still on the host root's device
+    /// afterwards.
+    fn enter(&self) -> Result<()> {
+        let host = device(Path::new("/"))?;
+        make_rprivate(Path::new("/"))?;
+        self.attach(self.root())?;
+        let attached = device(self.root())?;
+        if attached == host {
+            return Err(LeewardError::Mount(format!(
+                "template root {} is still on the host root's filesystem after attaching",
+                self.root().display()
+            )));
+        }
+
+        for (path, size) in &self.scratch {
+            let target = self.root().join(relative(path));
+            // Only needed when an earlier scratch mount hid the mount point
+            create_dir(&target)?;
+            mount_tmpfs(&target, *size)?;
+        }
+
+        std::env::set_current_dir(self.root())
+            .map_err(|e| LeewardError::Mount(format!("failed to chdir to template root: {e}")))?;
+        // Stack the old root under the new one, then detach it
+        pivot_root(Path::new("."), Path::new("."))?;
+        umount2(Path::new("."), libc::MNT_DETACH)?;
+        std::env::set_current_dir("/")
+            .map_err(|e| LeewardError::Mount(format!("failed to chdir to /: {e}")))?;
+
+        check_pivoted(host)
+    }
+}
+
+impl super::IsolationLayer for RootTemplate {
+    fn name(&self) -> &'static str {
+        "mounts"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        self.enter()
+    }
+}
+
+impl Drop for RootTemplate {
+    fn drop(&mut self) {
+        // Forked workers inherit a copy; only the builder owns the keeper
+        if self.owner != std::process::id() {
+            return;
+        }
+
+        // SAFETY: Killing and reaping our own child
+        unsafe {
+            libc::kill(self.keeper, libc::SIGKILL);
+            libc::waitpid(self.keeper, std::ptr::null_mut(), 0);
+        }
+        // The root's claim, dropped after this, removes it
+    }
+}
+
+/// A bind mount in the template: host source, path in the sandbox, writable
+type Bind = (PathBuf, PathBuf, bool);
+
+/// Bind mounts making up the template, sources resolved on the host
+///
+/// A source is bound where the config names it, so a symlinked `/lib`
+/// still shows up as `/lib`, but what is bound is where it leads.
+fn template_binds(config: &SandboxConfig) -> Result<Vec<Bind>> {
+    let mut binds: Vec<Bind> = Vec::new();
+    for path in &config.ro_binds {
+        add_bind(&mut binds, path, false, config.strict_paths)?;
+    }
+
+    // The interpreter must be reachable even if no bind covers it; compared
+    // resolved, so a bind of a symlinked /lib covers what is really under /usr
+    if let Some(python_dir) = config.python_path.parent() {
+        let resolved = CanonicalPath::resolve(python_dir, &PathPolicy::host())
+            .map_or_else(|_| python_dir.to_path_buf(), CanonicalPath::into_path_buf);
+        if !binds.iter().any(|(src, _, _)| resolved.starts_with(src)) {
+            add_bind(&mut binds, python_dir, false, false)?;
+        }
+    }
+
+    // The host's /etc/localtime is never seen; without tzdata the TZ variable still says UTC
+    if let Ok(zone) = zoneinfo_path(config.timezone_name()) {
+        binds.push((zone, PathBuf::from(LOCALTIME), false));
+    }
+
+    for dev in DEVICES {
+        add_bind(&mut binds, Path::new(dev), false, false)?;
+    }
+
+    // Somewhere for a controlled network's resolv.conf to be bound over
+    if ControlledNetwork::for_worker(config, 0).is_some() {
+        binds.push((
+            PathBuf::from("/dev/null"),
+            PathBuf::from(RESOLV_CONF),
+            false,
+        ));
+    }
+    for path in &config.rw_binds {
+        add_bind(&mut binds, path, true, config.strict_paths)?;
+    }
+    Ok(binds)
+}
+
+/// Bind `path` at the same place in the template, unless it is missing
+/// and `strict` is not set
+fn add_bind(binds: &mut Vec<Bind>, path: &Path, writable: bool, strict: bool) -> Result<()> {
+    if let Some(src) = bind_source(path, strict)? {
+        let dst = CanonicalPath::resolve(path, &PathPolicy::sandbox())
+            .map_err(|e| LeewardError::Mount(format!("bind target {e}")))?;
+        binds.push((src.into_path_buf(), dst.into_path_buf(), writable));
+    }
+    Ok(())
+}
+
+/// Build the template inside a fresh mount namespace (runs in the keeper)
+///
+/// The tmpfs under it all goes on `root_dir`, the directory created for
+/// the root, and `root` must lead to that tmpfs afterwards.
+fn assemble(
+    root: &Path,
+    root_dir: &OwnedFd,
+    binds: &[Bind],
+    scratch: &[(PathBuf, ByteSize)],
+) -> Result<()> {
+    nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+        .map_err(|e| LeewardError::Mount(format!("failed to unshare mount namespace: {e}")))?;
+    make_rprivate(Path::new("/"))?;
+
+    // `root_dir` belongs to the namespace left behind, so it cannot be
+    // mounted on; the directory found at `root` here must be the same one
+    let stat = |fd: &OwnedFd| {
+        nix::sys::stat::fstat(fd)
+            .map(|stat| (stat.st_dev, stat.st_ino))
+            .map_err(|e| LeewardError::Mount(format!("failed to stat template root: {e}")))
+    };
+    let found = nix::fcntl::open(
+        root,
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .map_err(|e| {
+        LeewardError::Mount(format!(
+            "failed to open template root {}: {e}",
+            root.display()
+        ))
+    })?;
+    let created = stat(root_dir)?;
+    if stat(&found)? != created {
+        return Err(LeewardError::Mount(format!(
+            "template root {} was replaced after it was created",
+            root.display()
+        )));
+    }
+    mount_tmpfs(
+        Path::new(&format!("/proc/self/fd/{}", found.as_raw_fd())),
+        TEMPLATE_TMPFS_SIZE,
+    )?;
+    if device(root)? == created.0 {
+        return Err(LeewardError::Mount(format!(
+            "template root {} does not lead to the tmpfs mounted on it",
+            root.display()
+        )));
+    }
+
+    for dir in ["proc", "sys", "dev"]
+        .iter()
+        .map(Path::new)
+        .chain(scratch.iter().map(|(p, _)| relative(p)))
+    {
+        create_dir(&root.join(dir))?;
+    }
+
+    for (src, dst, writable) in binds {
+        let dst = root.join(relative(dst));
+        if src.is_dir() {
+            create_dir(&dst)?;
+        } else {
+            if let Some(parent) = dst.parent() {
+                create_dir(parent)?;
+            }
+            File::create(&dst).map_err(|e| {
+                LeewardError::Mount(format!(
+                    "failed to create mount point {}: {e}",
+                    dst.display()
+                ))
+            })?;
+        }
+
+        // Devices bound in are meant to be used
+        let is_device = src
+            .metadata()
+            .is_ok_and(|meta| meta.file_type().is_char_device());
+        mount_bind(
+            src,
+            &dst,
+            if is_device {
+                BindOptions::DEVICE
+            } else {
+                BindOptions::default()
+            },
+        )?;
+        if !writable {
+            mount_remount_ro(&dst)?;
+        }
+    }
+    dev_links(&root.join("dev"))?;
+
+    // Freeze the layout itself so workers cannot add to the shared tmpfs
+    mount_remount_ro(root)
+}
+
+/// Strip the leading `/` so a host path can be joined under the template
+fn relative(path: &Path) -> &Path {
+    path.strip_prefix("/").unwrap_or(path)
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path)
+        .map_err(|e| LeewardError::Mount(format!("failed to create {}: {e}", path.display())))
+}
+
+fn open_tree_clone(path: &Path) -> Result<OwnedFd> {
+    let path_c = path_to_cstring(path)?;
+
+    // SAFETY: open_tree syscall with a valid path
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_open_tree,
+            libc::AT_FDCWD,
+            path_c.as_ptr(),
+            libc::OPEN_TREE_CLONE | libc::OPEN_TREE_CLOEXEC | libc::AT_RECURSIVE as libc::c_uint,
+        )
+    };
+
+    if ret < 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to clone template {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    let fd = RawFd::try_from(ret)
+        .map_err(|_| LeewardError::Mount(format!("open_tree returned {ret}")))?;
+    // SAFETY: open_tree returned a new file descriptor we now own
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn move_mount(tree: &OwnedFd, target: &Path) -> Result<()> {
+    let empty = path_to_cstring(Path::new(""))?;
+    let target_c = path_to_cstring(target)?;
+
+    // SAFETY: move_mount syscall attaching a detached tree we own
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_move_mount,
+            tree.as_raw_fd(),
+            empty.as_ptr(),
+            libc::AT_FDCWD,
+            target_c.as_ptr(),
+            MOVE_MOUNT_F_EMPTY_PATH,
+        )
+    };
+
+    if ret != 0 {
+        return Err(LeewardError::Mount(format!(
+            "failed to attach template at {}: {}",
+            target.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
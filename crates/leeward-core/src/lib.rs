@@ -4,26 +4,72 @@
 //!
 //! This crate provides the core isolation mechanisms:
 //! - Linux namespaces via clone3 (user, pid, mount, net, ipc)
-//! - seccomp user notifications (SECCOMP_USER_NOTIF)
+//! - seccomp user notifications (`SECCOMP_USER_NOTIF`)
 //! - Landlock filesystem restrictions
 //! - Shared memory for zero-copy results (memfd + mmap)
 //! - Pipe-based code delivery to pre-forked workers
+//!
+//! # Features
+//!
+//! Everything is enabled by default. Embedders that only need a subset of the
+//! primitives can turn off `default-features` and pick from:
+//! - `seccomp` - syscall filtering (`isolation::seccomp`)
+//! - `landlock` - filesystem access control (`isolation::landlock`)
+//! - `shm` - shared memory regions (`shm`)
+//! - `cgroups` - cgroups v2 resource control
+//! - `protocol` - serde support, the wire protocol (`protocol`), a blocking
+//!   daemon client (`client`), pool alerts (`alert`) and the pre-forked
+//!   worker (`worker`), which encodes results with msgpack and whose
+//!   executions can be frozen (`preempt`)
+//!
+//! Namespaces, mounts, clone3, pipes and socket addressing are always
+//! available.
+//!
+//! There is no `no_std` mode. Even with every feature off, namespaces go
+//! through `nix`, errors wrap `std::io::Error`, and logging uses `tracing`,
+//! all of which need `std`. Targets without a standard library should drive
+//! the raw syscalls themselves rather than through this crate.
 
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 #![allow(clippy::module_name_repetitions)]
 
+#[cfg(feature = "protocol")]
+pub mod alert;
+#[cfg(feature = "protocol")]
+pub mod client;
 pub mod config;
+pub mod debug_flags;
+pub mod denial;
 pub mod error;
+pub mod escape;
+#[cfg(feature = "protocol")]
+pub mod fingerprint;
 pub mod isolation;
+pub mod network;
 pub mod pipe;
+#[cfg(feature = "protocol")]
+pub mod policy;
+#[cfg(feature = "protocol")]
+pub mod preempt;
+#[cfg(feature = "protocol")]
+pub mod profile;
+#[cfg(feature = "protocol")]
 pub mod protocol;
+pub mod provenance;
 pub mod result;
+#[cfg(feature = "shm")]
 pub mod shm;
+pub mod socket;
+pub mod source;
+pub mod units;
+#[cfg(feature = "protocol")]
 pub mod worker;
+pub mod workspace;
 
 pub use config::SandboxConfig;
 pub use error::LeewardError;
-pub use result::ExecutionResult;
+pub use result::{ExecutionResult, OutcomeCode};
+pub use units::{ByteSize, DurationSecs};
 
 /// Crate-level result type
 pub type Result<T> = std::result::Result<T, LeewardError>;
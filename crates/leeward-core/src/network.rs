@@ -0,0 +1,189 @@
+//! Per-execution network accounting
+//!
+//! Only meaningful when networking is enabled for the sandbox. Socket creation
+//! and `connect` calls are counted by whoever observes them (the seccomp
+//! notify supervisor, or a proxy handing out connections) through a shared
+//! [`ConnectionTracker`]; traffic is measured from the worker's network
+//! namespace interface counters.
+
+use crate::result::NetworkUsage;
+use crate::{LeewardError, Result};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Limit value meaning "no connection limit"
+const UNLIMITED: u32 = u32::MAX;
+
+/// Counts connections opened by one execution and enforces its limit
+///
+/// Shared between the worker handle and the observers that see socket
+/// creation, so all state is atomic. Call [`ConnectionTracker::begin`] before
+/// each execution to reset the counters.
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    /// Maximum connections for the current execution
+    limit: AtomicU32,
+    /// Connections opened by the current execution
+    opened: AtomicU32,
+    /// Attempts rejected because the limit was reached
+    rejected: AtomicU64,
+    /// `connect` calls made by the current execution
+    connects: AtomicU32,
+}
+
+impl Default for ConnectionTracker {
+    fn default() -> Self {
+        Self {
+            limit: AtomicU32::new(UNLIMITED),
+            opened: AtomicU32::new(0),
+            rejected: AtomicU64::new(0),
+            connects: AtomicU32::new(0),
+        }
+    }
+}
+
+impl ConnectionTracker {
+    /// Reset counters for a new execution with an optional limit
+    pub fn begin(&self, max_connections: Option<u32>) {
+        self.limit
+            .store(max_connections.unwrap_or(UNLIMITED), Ordering::SeqCst);
+        self.opened.store(0, Ordering::SeqCst);
+        self.rejected.store(0, Ordering::SeqCst);
+        self.connects.store(0, Ordering::SeqCst);
+    }
+
+    /// Account for a new connection, failing with `EMFILE` once over the limit
+    ///
+    /// Rejected attempts are not counted as opened.
+    ///
+    /// # Errors
+    ///
+    /// `EMFILE` once the execution has opened as many connections as its limit
+    /// allows.
+    pub fn try_open(&self) -> std::result::Result<(), i32> {
+        let limit = self.limit.load(Ordering::SeqCst);
+        let mut opened = self.opened.load(Ordering::SeqCst);
+        while opened < limit {
+            match self.opened.compare_exchange_weak(
+                opened,
+                opened + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(current) => opened = current,
+            }
+        }
+
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Err(libc::EMFILE)
+    }
+
+    /// Account for an intercepted syscall
+    ///
+    /// `socket`, `accept` and `accept4` create connection fds and count
+    /// against the limit. `connect` is counted on its own and never
+    /// refused: it reuses a socket that already counted, so counting it
+    /// against the limit too would charge one connection twice. Anything
+    /// else passes through. Returns the errno to fail the syscall with, if
+    /// any.
+    pub fn on_syscall(&self, syscall: i64) -> Option<i32> {
+        if syscall == libc::SYS_connect {
+            self.connects.fetch_add(1, Ordering::Relaxed);
+            None
+        } else if is_connection_syscall(syscall) {
+            self.try_open().err()
+        } else {
+            None
+        }
+    }
+
+    /// Connections opened by the current execution
+    #[must_use]
+    pub fn opened(&self) -> u32 {
+        self.opened.load(Ordering::SeqCst)
+    }
+
+    /// Attempts rejected by the limit during the current execution
+    #[must_use]
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// `connect` calls made by the current execution
+    #[must_use]
+    pub fn connects(&self) -> u32 {
+        self.connects.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether a syscall creates a connection fd
+#[must_use]
+pub const fn is_connection_syscall(syscall: i64) -> bool {
+    matches!(
+        syscall,
+        libc::SYS_socket | libc::SYS_accept | libc::SYS_accept4
+    )
+}
+
+/// Byte counters summed over a network namespace's non-loopback interfaces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    /// Bytes received
+    pub rx_bytes: u64,
+    /// Bytes transmitted
+    pub tx_bytes: u64,
+}
+
+impl InterfaceCounters {
+    /// Read the counters of the network namespace `pid` lives in
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if `/proc/<pid>/net/dev` cannot be read,
+    /// e.g. once `pid` has exited.
+    pub fn read_for_pid(pid: i32) -> Result<Self> {
+        let path = format!("/proc/{pid}/net/dev");
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| LeewardError::Execution(format!("failed to read {path}: {e}")))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Parse `/proc/net/dev`, skipping the loopback interface
+    #[must_use]
+    pub fn parse(contents: &str) -> Self {
+        let mut counters = Self::default();
+
+        // Two header lines, then "iface: rx_bytes rx_packets ... tx_bytes ..."
+        for line in contents.lines().skip(2) {
+            let Some((iface, stats)) = line.split_once(':') else {
+                continue;
+            };
+            if iface.trim() == "lo" {
+                continue;
+            }
+
+            let fields: Vec<u64> = stats
+                .split_whitespace()
+                .map(|f| f.parse().unwrap_or(0))
+                .collect();
+            if fields.len() >= 9 {
+                counters.rx_bytes += fields[0];
+                counters.tx_bytes += fields[8];
+            }
+        }
+
+        counters
+    }
+
+    /// Usage between an earlier snapshot and this one, with the sockets
+    /// `connections` counted in between
+    #[must_use]
+    pub fn usage_since(&self, before: &Self, connections: &ConnectionTracker) -> NetworkUsage {
+        NetworkUsage {
+            connections_opened: connections.opened(),
+            connects: connections.connects(),
+            bytes_sent: self.tx_bytes.saturating_sub(before.tx_bytes),
+            bytes_received: self.rx_bytes.saturating_sub(before.rx_bytes),
+        }
+    }
+}
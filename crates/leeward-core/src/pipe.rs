@@ -1,8 +1,38 @@
 //! Pipe-based communication for worker code execution
+//!
+//! A [`PipeWatch`] samples how full a worker's pipes are with `FIONREAD`
+//! while it executes, to show which side holds the other up.
 
-use crate::{LeewardError, Result};
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use crate::debug_flags::DebugFlag;
+use crate::{ByteSize, LeewardError, Result};
 use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+
+/// Bytes moved per read or write when streaming a file to a worker
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// Sent on the code pipe while a program runs, to stop it
+///
+/// Control bytes are never the first byte of a length prefix, which is
+/// always 0 for code under 16 MiB.
+pub const FREEZE: u8 = b'F';
+
+/// Sent on the code pipe to resume a program stopped with [`FREEZE`]
+pub const THAW: u8 = b'T';
+
+/// Sent on the code pipe while a program runs, to warn it that its memory
+/// is nearly used up
+pub const PRESSURE: u8 = b'P';
+
+/// How often a [`PipeWatch`] samples the pipes
+pub const OCCUPANCY_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Free space under which a pipe counts as full, since writes fill the
+/// buffer a page at a time
+const FULL_MARGIN: usize = 4096;
 
 /// A pair of pipes for bidirectional communication with a worker
 #[derive(Debug)]
@@ -18,6 +48,10 @@ pub struct WorkerPipe {
 
 impl WorkerPipe {
     /// Create a new pair of pipes
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the pipes cannot be created.
     pub fn new() -> Result<Self> {
         let (code_rx, code_tx) = create_pipe()?;
         let (result_rx, result_tx) = create_pipe()?;
@@ -30,7 +64,27 @@ impl WorkerPipe {
         })
     }
 
+    /// Resize both pipes to hold `size` bytes, rounded up by the kernel
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] if `size` does not fit a `c_int`, and
+    /// [`LeewardError::Io`] if the kernel refuses it, e.g. past
+    /// `/proc/sys/fs/pipe-max-size`.
+    pub fn resize(&self, size: ByteSize) -> Result<()> {
+        let size = libc::c_int::try_from(size.bytes())
+            .map_err(|_| LeewardError::Config(format!("pipe size {size} is too large")))?;
+        for pipe in [&self.code_tx, &self.result_tx] {
+            // SAFETY: F_SETPIPE_SZ on a pipe we own
+            if unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_SETPIPE_SZ, size) } < 0 {
+                return Err(LeewardError::Io(std::io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
     /// Split the pipe into parent and child ends
+    #[must_use]
     pub fn split(self) -> (ParentPipe, ChildPipe) {
         let parent = ParentPipe {
             code_tx: self.code_tx,
@@ -57,9 +111,17 @@ pub struct ParentPipe {
 
 impl ParentPipe {
     /// Send code to the worker
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if `code` is too long to frame, and
+    /// [`LeewardError::Io`] if the pipe cannot be written.
     pub fn send_code(&mut self, code: &[u8]) -> Result<()> {
         // Send length prefix (4 bytes, big-endian)
-        let len_bytes = (code.len() as u32).to_be_bytes();
+        let len = u32::try_from(code.len()).map_err(|_| {
+            LeewardError::Execution(format!("{} bytes of code is too long", code.len()))
+        })?;
+        let len_bytes = len.to_be_bytes();
         self.code_tx.write_all(&len_bytes)?;
 
         // Send code
@@ -67,10 +129,61 @@ impl ParentPipe {
 
         self.code_tx.flush()?;
 
+        if DebugFlag::PipeFrames.enabled() {
+            tracing::info!(len = code.len(), "sent code frame to worker");
+        }
+
+        Ok(())
+    }
+
+    /// Stream the first `len` bytes of `file` after the code frame that
+    /// announced them
+    ///
+    /// Reads at explicit offsets, so a file shared with other executions
+    /// needs no cursor of its own.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if `file` has fewer than `len` bytes or the pipe
+    /// cannot be written.
+    pub fn send_stream(&mut self, file: &std::fs::File, len: u64) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let mut buf = vec![0u8; STREAM_CHUNK];
+        let mut offset = 0;
+        while offset < len {
+            let take =
+                usize::try_from(len - offset).map_or(STREAM_CHUNK, |left| left.min(STREAM_CHUNK));
+            file.read_exact_at(&mut buf[..take], offset)?;
+            self.code_tx.write_all(&buf[..take])?;
+            offset += take as u64;
+        }
+        self.code_tx.flush()?;
+
+        if DebugFlag::PipeFrames.enabled() {
+            tracing::info!(len, "streamed file to worker");
+        }
+
         Ok(())
     }
 
+    /// A second handle on the code pipe, for sending [`FREEZE`], [`THAW`]
+    /// and [`PRESSURE`] while this one waits on a result
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the descriptor cannot be duplicated.
+    pub fn control(&self) -> Result<std::fs::File> {
+        Ok(self.code_tx.try_clone()?)
+    }
+
     /// Receive result from the worker
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the frame is over 10 MiB, and
+    /// [`LeewardError::Io`] if the pipe cannot be read, e.g. once the worker
+    /// died.
     pub fn recv_result(&mut self) -> Result<Vec<u8>> {
         // Read length prefix
         let mut len_bytes = [0u8; 4];
@@ -80,8 +193,7 @@ impl ParentPipe {
 
         if len > 10 * 1024 * 1024 {
             return Err(LeewardError::Execution(format!(
-                "result too large: {} bytes",
-                len
+                "result too large: {len} bytes"
             )));
         }
 
@@ -89,15 +201,93 @@ impl ParentPipe {
         let mut result = vec![0u8; len];
         self.result_rx.read_exact(&mut result)?;
 
+        if DebugFlag::PipeFrames.enabled() {
+            tracing::info!(len, "received result frame from worker");
+        }
+
         Ok(result)
     }
 
-    /// Get raw file descriptor for code transmission (for io_uring)
+    /// Read and throw away whatever the worker has written so far, without
+    /// waiting for more, returning how many bytes that was
+    pub fn discard_results(&mut self) -> usize {
+        let mut buf = [0u8; 4096];
+        let mut discarded = 0;
+        loop {
+            let mut fds = [libc::pollfd {
+                fd: self.result_rx.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            // SAFETY: Polling a pipe we own, without waiting
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) };
+            if ready <= 0 || fds[0].revents & libc::POLLIN == 0 {
+                return discarded;
+            }
+            match self.result_rx.read(&mut buf) {
+                Ok(0) | Err(_) => return discarded,
+                Ok(read) => discarded += read,
+            }
+        }
+    }
+
+    /// Start sampling how full both pipes are into `gauge`, logging a pipe
+    /// that stays full for `stall_after` under [`DebugFlag::PipeStalls`]
+    ///
+    /// `worker_id` and `execution_id` go in the log lines.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the pipes cannot be duplicated or the sampling
+    /// thread cannot be started.
+    pub fn watch(
+        &self,
+        gauge: Arc<PipeGauge>,
+        stall_after: Duration,
+        worker_id: u32,
+        execution_id: Option<u64>,
+    ) -> Result<PipeWatch> {
+        let pipes = [
+            Sampled::new("code", self.code_tx.try_clone()?)?,
+            Sampled::new("result", self.result_rx.try_clone()?)?,
+        ];
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("leeward-pipes".into())
+            .spawn(move || {
+                let mut pipes = pipes;
+                let mut stopping = false;
+                loop {
+                    let [code, result] = pipes
+                        .each_mut()
+                        .map(|pipe| pipe.sample(stall_after, worker_id, execution_id));
+                    gauge.code.store(code, Ordering::Relaxed);
+                    gauge.result.store(result, Ordering::Relaxed);
+                    // One last sample once stopped, so the gauge is current
+                    if stopping {
+                        return PipePeaks {
+                            code: pipes[0].peak,
+                            result: pipes[1].peak,
+                        };
+                    }
+                    stopping = stopped.recv_timeout(OCCUPANCY_INTERVAL)
+                        != Err(mpsc::RecvTimeoutError::Timeout);
+                }
+            })?;
+        Ok(PipeWatch {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Get raw file descriptor for code transmission (for `io_uring`)
+    #[must_use]
     pub fn code_tx_fd(&self) -> RawFd {
         self.code_tx.as_raw_fd()
     }
 
-    /// Get raw file descriptor for result reception (for io_uring)
+    /// Get raw file descriptor for result reception (for `io_uring`)
+    #[must_use]
     pub fn result_rx_fd(&self) -> RawFd {
         self.result_rx.as_raw_fd()
     }
@@ -114,17 +304,38 @@ pub struct ChildPipe {
 
 impl ChildPipe {
     /// Wait for and receive code from daemon
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the frame is over 1 MiB, and
+    /// [`LeewardError::Io`] if the pipe cannot be read, e.g. once the daemon
+    /// closed it.
     pub fn recv_code(&mut self) -> Result<Vec<u8>> {
-        // Read length prefix
+        let len_bytes = self.read_prefix()?;
+
+        self.read_code_body(u32::from_be_bytes(len_bytes) as usize)
+    }
+
+    /// Read a length prefix, skipping control bytes that arrived after the
+    /// program they were meant for had finished
+    fn read_prefix(&mut self) -> Result<[u8; 4]> {
         let mut len_bytes = [0u8; 4];
-        self.code_rx.read_exact(&mut len_bytes)?;
+        loop {
+            self.code_rx.read_exact(&mut len_bytes[..1])?;
+            if !matches!(len_bytes[0], FREEZE | THAW | PRESSURE) {
+                break;
+            }
+        }
+        self.code_rx.read_exact(&mut len_bytes[1..])?;
 
-        let len = u32::from_be_bytes(len_bytes) as usize;
+        Ok(len_bytes)
+    }
 
+    /// Read a code body of `len` bytes following its length prefix
+    fn read_code_body(&mut self, len: usize) -> Result<Vec<u8>> {
         if len > 1024 * 1024 {
             return Err(LeewardError::Execution(format!(
-                "code too large: {} bytes",
-                len
+                "code too large: {len} bytes"
             )));
         }
 
@@ -135,10 +346,60 @@ impl ChildPipe {
         Ok(code)
     }
 
+    /// Receive code along with the time spent reading it
+    ///
+    /// The clock starts once the length prefix arrives, so idle time waiting
+    /// for work is not counted.
+    ///
+    /// # Errors
+    ///
+    /// As [`ChildPipe::recv_code`].
+    pub fn recv_code_timed(&mut self) -> Result<(Vec<u8>, std::time::Duration)> {
+        let len_bytes = self.read_prefix()?;
+        let started = std::time::Instant::now();
+
+        let code = self.read_code_body(u32::from_be_bytes(len_bytes) as usize)?;
+
+        Ok((code, started.elapsed()))
+    }
+
+    /// Read a stream of `len` bytes following a code frame into `sink`
+    ///
+    /// The whole stream is read even once `sink` fails, so the pipe stays in
+    /// step with the daemon. The outer error is the pipe's; the inner one is
+    /// the first `sink` returned.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if the pipe cannot be read.
+    pub fn recv_stream(&mut self, len: u64, sink: &mut dyn Write) -> Result<std::io::Result<()>> {
+        let mut buf = vec![0u8; STREAM_CHUNK];
+        let mut left = len;
+        let mut written = Ok(());
+        while left > 0 {
+            let take = usize::try_from(left).map_or(STREAM_CHUNK, |left| left.min(STREAM_CHUNK));
+            self.code_rx.read_exact(&mut buf[..take])?;
+            if written.is_ok() {
+                written = sink.write_all(&buf[..take]);
+            }
+            left -= take as u64;
+        }
+
+        Ok(written)
+    }
+
     /// Send result back to daemon
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if `result` is too long to frame, and
+    /// [`LeewardError::Io`] if the pipe cannot be written.
     pub fn send_result(&mut self, result: &[u8]) -> Result<()> {
         // Send length prefix
-        let len_bytes = (result.len() as u32).to_be_bytes();
+        let len = u32::try_from(result.len()).map_err(|_| {
+            LeewardError::Execution(format!("{} bytes of result is too long", result.len()))
+        })?;
+        let len_bytes = len.to_be_bytes();
         self.result_tx.write_all(&len_bytes)?;
 
         // Send result
@@ -149,15 +410,164 @@ impl ChildPipe {
         Ok(())
     }
 
+    /// The code pipe, for reading control bytes while a program runs
+    #[cfg(feature = "protocol")]
+    pub(crate) const fn control(&self) -> &std::fs::File {
+        &self.code_rx
+    }
+
+    /// Get raw file descriptor for sending results
+    #[must_use]
+    pub fn result_tx_fd(&self) -> RawFd {
+        self.result_tx.as_raw_fd()
+    }
+
     /// Get raw file descriptors (for passing to child process)
+    #[must_use]
     pub fn into_raw_fds(self) -> (RawFd, RawFd) {
         use std::os::unix::io::IntoRawFd;
         (self.code_rx.into_raw_fd(), self.result_tx.into_raw_fd())
     }
 }
 
+/// Bytes waiting in a worker's pipes, as last sampled by a [`PipeWatch`]
+#[derive(Debug, Default)]
+pub struct PipeGauge {
+    code: AtomicUsize,
+    result: AtomicUsize,
+}
+
+impl PipeGauge {
+    /// Code and uploaded files the worker has yet to read
+    #[must_use]
+    pub fn code(&self) -> usize {
+        self.code.load(Ordering::Relaxed)
+    }
+
+    /// Results the daemon has yet to read
+    #[must_use]
+    pub fn result(&self) -> usize {
+        self.result.load(Ordering::Relaxed)
+    }
+}
+
+/// Most bytes a [`PipeWatch`] saw waiting in each pipe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipePeaks {
+    pub code: usize,
+    pub result: usize,
+}
+
+/// Pipes being sampled, from [`ParentPipe::watch`]
+///
+/// Dropping it stops the sampling too, without waiting.
+#[derive(Debug)]
+pub struct PipeWatch {
+    stop: mpsc::Sender<()>,
+    thread: Option<std::thread::JoinHandle<PipePeaks>>,
+}
+
+impl PipeWatch {
+    /// Stop sampling, returning the peaks seen; the gauge keeps a sample
+    /// taken as it stops
+    #[must_use]
+    pub fn stop(mut self) -> PipePeaks {
+        let _ = self.stop.send(());
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// One pipe a [`PipeWatch`] samples
+struct Sampled {
+    name: &'static str,
+    file: std::fs::File,
+    capacity: usize,
+    peak: usize,
+    /// When the pipe was first seen full, if it still is
+    full_since: Option<Instant>,
+    /// Whether the current stall has been logged
+    logged: bool,
+}
+
+impl Sampled {
+    fn new(name: &'static str, file: std::fs::File) -> Result<Self> {
+        Ok(Self {
+            name,
+            capacity: capacity(file.as_raw_fd())?,
+            file,
+            peak: 0,
+            full_since: None,
+            logged: false,
+        })
+    }
+
+    /// Bytes waiting now, noting the peak and logging a stall once it has
+    /// lasted `stall_after`
+    fn sample(
+        &mut self,
+        stall_after: Duration,
+        worker_id: u32,
+        execution_id: Option<u64>,
+    ) -> usize {
+        // A sample that fails reads as empty
+        let bytes = queued(self.file.as_raw_fd()).unwrap_or(0);
+        self.peak = self.peak.max(bytes);
+        if bytes + FULL_MARGIN <= self.capacity {
+            self.full_since = None;
+            self.logged = false;
+            return bytes;
+        }
+        let full_for = self.full_since.get_or_insert_with(Instant::now).elapsed();
+        if !self.logged && full_for >= stall_after {
+            self.logged = true;
+            if DebugFlag::PipeStalls.enabled() {
+                tracing::info!(
+                    worker_id,
+                    execution_id,
+                    pipe = self.name,
+                    bytes,
+                    full_ms = full_for.as_millis(),
+                    "worker pipe stalled full"
+                );
+            }
+        }
+        bytes
+    }
+}
+
+/// Bytes waiting to be read in the pipe `fd` is either end of
+///
+/// # Errors
+///
+/// The `FIONREAD` error, e.g. if `fd` is not open.
+pub fn queued(fd: RawFd) -> std::io::Result<usize> {
+    let mut bytes: libc::c_int = 0;
+    // SAFETY: FIONREAD writes one int
+    if unsafe { libc::ioctl(fd, libc::FIONREAD, &raw mut bytes) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(usize::try_from(bytes).unwrap_or(0))
+}
+
+/// Bytes the pipe `fd` is either end of can hold
+///
+/// # Errors
+///
+/// The `F_GETPIPE_SZ` error, e.g. if `fd` is not a pipe.
+pub fn capacity(fd: RawFd) -> std::io::Result<usize> {
+    // SAFETY: F_GETPIPE_SZ only reads the pipe's size
+    let size = unsafe { libc::fcntl(fd, libc::F_GETPIPE_SZ) };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(usize::try_from(size).unwrap_or(0))
+}
+
 /// Create a pipe (returns read end, write end)
-fn create_pipe() -> Result<(std::fs::File, std::fs::File)> {
+pub(crate) fn create_pipe() -> Result<(std::fs::File, std::fs::File)> {
     let mut fds = [0i32; 2];
 
     // SAFETY: pipe2 syscall
@@ -173,3 +583,102 @@ fn create_pipe() -> Result<(std::fs::File, std::fs::File)> {
 
     Ok((read_end, write_end))
 }
+
+/// Control buffer large enough for one `SCM_RIGHTS` fd, suitably aligned
+#[cfg(feature = "seccomp")]
+#[repr(C)]
+union FdControl {
+    buf: [u8; 24],
+    _align: libc::cmsghdr,
+}
+
+/// Bytes of the one descriptor [`send_fd`] passes
+#[cfg(feature = "seccomp")]
+#[allow(clippy::cast_possible_truncation)]
+const FD_LEN: u32 = std::mem::size_of::<RawFd>() as u32;
+
+/// Pass `fd` over a unix socket with `SCM_RIGHTS`
+///
+/// Only makes syscalls on stack buffers, so it is safe to call between
+/// fork and exec.
+#[cfg(feature = "seccomp")]
+pub(crate) fn send_fd(socket: RawFd, fd: RawFd) -> std::io::Result<()> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&raw mut byte).cast(),
+        iov_len: 1,
+    };
+    let mut control = FdControl { buf: [0; 24] };
+
+    // SAFETY: msghdr is plain data; every pointer in it outlives the sendmsg call
+    let ret = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = (&raw mut control).cast();
+        msg.msg_controllen = libc::CMSG_SPACE(FD_LEN) as usize;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(FD_LEN) as usize;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+
+        libc::sendmsg(socket, &raw const msg, libc::MSG_NOSIGNAL)
+    };
+
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive an fd sent with [`send_fd`], or `None` if the peer sent none
+///
+/// With `MSG_DONTWAIT` in `flags`, returns `None` instead of blocking when
+/// nothing is queued.
+#[cfg(feature = "seccomp")]
+pub(crate) fn recv_fd(
+    socket: RawFd,
+    flags: libc::c_int,
+) -> std::io::Result<Option<std::os::fd::OwnedFd>> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&raw mut byte).cast(),
+        iov_len: 1,
+    };
+    let mut control = FdControl { buf: [0; 24] };
+
+    // SAFETY: msghdr is plain data; every pointer in it outlives the recvmsg call
+    let (ret, msg) = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = (&raw mut control).cast();
+        msg.msg_controllen = std::mem::size_of::<FdControl>();
+
+        let ret = libc::recvmsg(socket, &raw mut msg, flags | libc::MSG_CMSG_CLOEXEC);
+        (ret, msg)
+    };
+
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+
+    // SAFETY: The kernel filled in the control buffer msg points to
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Ok(None);
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+        Ok(Some(std::os::fd::OwnedFd::from_raw_fd(fd)))
+    }
+}
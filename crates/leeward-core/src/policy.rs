@@ -0,0 +1,382 @@
+//! Where each setting an execution runs with came from
+//!
+//! The merges that decide an execution's settings take a [`PolicyTrace`].
+//! Executions pass one that is [off](PolicyTrace::off) and costs nothing;
+//! [`Request::ExplainPolicy`](crate::protocol::Request::ExplainPolicy)
+//! passes one that records, so what it reports is what the same merges
+//! would do, not a copy of them.
+//!
+//! A [`PolicyChange`] records a point at which the config in effect
+//! changed, with a [`diff`] of its fields that tells built-in values from
+//! configured ones the same way.
+
+use crate::SandboxConfig;
+use crate::protocol::Adjustment;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Which tier a setting's value comes from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Provenance {
+    /// Built in, left as is by the daemon's config
+    Default,
+    /// The daemon's config, which differs from the built-in value
+    DaemonConfig,
+    /// The connection's [`ExecuteDefaults`](crate::protocol::ExecuteDefaults)
+    ConnectionDefault,
+    /// The request itself
+    Request,
+    /// Asked for by the request or a default, then cut down by a limit
+    Clamped {
+        /// The limit, as the daemon explains it
+        limit: String,
+    },
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::DaemonConfig => f.write_str("daemon config"),
+            Self::ConnectionDefault => f.write_str("connection default"),
+            Self::Request => f.write_str("request"),
+            Self::Clamped { limit } => write!(f, "clamped: {limit}"),
+        }
+    }
+}
+
+/// One resolved setting, in [`Response::Policy`](crate::protocol::Response::Policy)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyField {
+    /// Setting, as named on the wire
+    pub field: String,
+    /// Value the execution would run with
+    pub value: String,
+    /// Where the value came from
+    pub provenance: Provenance,
+}
+
+/// Records where each setting came from as the merges decide it
+#[derive(Debug, Default)]
+pub struct PolicyTrace {
+    /// `None` when off
+    fields: Option<Vec<PolicyField>>,
+    /// Built-in config, made the first time a config value is recorded
+    defaults: Option<Box<SandboxConfig>>,
+}
+
+impl PolicyTrace {
+    /// A trace that records nothing
+    #[must_use]
+    pub const fn off() -> Self {
+        Self {
+            fields: None,
+            defaults: None,
+        }
+    }
+
+    /// A trace that records
+    #[must_use]
+    pub const fn recording() -> Self {
+        Self {
+            fields: Some(Vec::new()),
+            defaults: None,
+        }
+    }
+
+    /// Whether anything is recorded
+    #[must_use]
+    pub const fn is_recording(&self) -> bool {
+        self.fields.is_some()
+    }
+
+    /// `field` is `value`, from `provenance`, whatever was recorded for it
+    /// before
+    pub fn record(&mut self, field: &str, provenance: Provenance, value: impl FnOnce() -> String) {
+        if let Some(fields) = self.fields.as_mut() {
+            let value = value();
+            match fields.iter_mut().find(|recorded| recorded.field == field) {
+                Some(recorded) => {
+                    recorded.value = value;
+                    recorded.provenance = provenance;
+                }
+                None => fields.push(PolicyField {
+                    field: field.to_owned(),
+                    value,
+                    provenance,
+                }),
+            }
+        }
+    }
+
+    /// `field` is `value`, passed down from an earlier merge: from the
+    /// request, unless that merge recorded otherwise
+    pub fn requested(&mut self, field: &str, value: impl FnOnce() -> String) {
+        if let Some(fields) = self.fields.as_mut() {
+            let value = value();
+            match fields.iter_mut().find(|recorded| recorded.field == field) {
+                Some(recorded) => recorded.value = value,
+                None => fields.push(PolicyField {
+                    field: field.to_owned(),
+                    value,
+                    provenance: Provenance::Request,
+                }),
+            }
+        }
+    }
+
+    /// `field` is `value`, from the daemon's config, which `is_default`
+    /// says matches the built-in config
+    pub fn configured(
+        &mut self,
+        field: &str,
+        value: impl FnOnce() -> String,
+        is_default: impl FnOnce(&SandboxConfig) -> bool,
+    ) {
+        if !self.is_recording() {
+            return;
+        }
+        let defaults = self.defaults.get_or_insert_with(Box::default);
+        let provenance = if is_default(defaults) {
+            Provenance::Default
+        } else {
+            Provenance::DaemonConfig
+        };
+        self.record(field, provenance, value);
+    }
+
+    /// The value of a setting the request may override over the config
+    ///
+    /// `requested` wins when set; `is_default` says whether `configured`
+    /// matches the built-in config.
+    pub fn pick<T>(
+        &mut self,
+        field: &str,
+        requested: Option<T>,
+        configured: T,
+        describe: impl Fn(&T) -> String,
+        is_default: impl FnOnce(&SandboxConfig) -> bool,
+    ) -> T {
+        if let Some(value) = requested {
+            self.requested(field, || describe(&value));
+            value
+        } else {
+            self.configured(field, || describe(&configured), is_default);
+            configured
+        }
+    }
+
+    /// Record what `adjustments` applied, each from `provenance`
+    pub fn adjusted(
+        &mut self,
+        adjustments: &[Adjustment],
+        provenance: impl Fn(&Adjustment) -> Provenance,
+    ) {
+        for adjustment in adjustments {
+            self.record(&adjustment.field, provenance(adjustment), || {
+                adjustment.applied.clone()
+            });
+        }
+    }
+
+    /// What was recorded, in the order first recorded
+    #[must_use]
+    pub fn into_fields(self) -> Vec<PolicyField> {
+        self.fields.unwrap_or_default()
+    }
+}
+
+/// Config fields whose values a [`PolicyChange`] never shows, unless the
+/// daemon is configured with others: only whether they are set
+pub const SECRET_FIELDS: [&str; 1] = ["env"];
+
+/// Shown for a secret-bearing field that is set
+pub const REDACTED: &str = "<set>";
+
+/// What changed the effective policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "actor", rename_all = "snake_case")]
+pub enum PolicyActor {
+    /// The daemon starting, with the config it started with
+    Startup,
+    /// A signal, such as `SIGHUP` reloading the config
+    Signal { signal: String },
+    /// A request over the socket, named as on the wire, from `peer_uid`
+    /// if the kernel said
+    Request {
+        request: String,
+        peer_uid: Option<u32>,
+    },
+}
+
+impl fmt::Display for PolicyActor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Startup => f.write_str("startup"),
+            Self::Signal { signal } => f.write_str(signal),
+            Self::Request {
+                request,
+                peer_uid: Some(uid),
+            } => write!(f, "{request} from uid {uid}"),
+            Self::Request {
+                request,
+                peer_uid: None,
+            } => write!(f, "{request} from an unknown uid"),
+        }
+    }
+}
+
+/// One config field a [`PolicyChange`] touched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    /// Dotted path into the config, with list items by index, such as
+    /// `timeout` or `ro_binds.2`
+    pub field: String,
+    /// Value before, `None` if unset
+    pub before: Option<String>,
+    /// Value after, `None` if unset
+    pub after: Option<String>,
+    /// Where the value after comes from: [`Provenance::Default`] or
+    /// [`Provenance::DaemonConfig`]
+    pub provenance: Provenance,
+}
+
+/// A point at which the effective policy changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyChange {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub actor: PolicyActor,
+    /// Config fingerprint before, `None` at startup
+    pub fingerprint_before: Option<String>,
+    pub fingerprint_after: String,
+    /// Why, if the actor said
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Fields that differ, against the built-in config at startup
+    pub changes: Vec<FieldChange>,
+}
+
+impl PolicyChange {
+    /// `actor` moving from `before` to `after`, stamped with the current
+    /// time, with the fields under `secret_fields` redacted
+    #[must_use]
+    pub fn new(
+        actor: PolicyActor,
+        before: Option<&SandboxConfig>,
+        after: &SandboxConfig,
+        secret_fields: &[impl AsRef<str>],
+    ) -> Self {
+        let defaults = SandboxConfig::default();
+        let changes = diff(before.unwrap_or(&defaults), after, secret_fields);
+        Self {
+            timestamp_ms: crate::protocol::now_ms(),
+            actor,
+            fingerprint_before: before.map(SandboxConfig::fingerprint),
+            fingerprint_after: after.fingerprint(),
+            reason: None,
+            changes,
+        }
+    }
+
+    /// The same change, saying why
+    #[must_use]
+    pub fn with_reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+}
+
+/// Fields that differ between `before` and `after`, in path order
+///
+/// Nested values are compared leaf by leaf. A field at or under one of
+/// `secret_fields` shows as [`REDACTED`] when set, whatever its value.
+#[must_use]
+pub fn diff(
+    before: &SandboxConfig,
+    after: &SandboxConfig,
+    secret_fields: &[impl AsRef<str>],
+) -> Vec<FieldChange> {
+    let [before, after, defaults] = [before, after, &SandboxConfig::default()].map(leaves);
+    let secret = |field: &str| {
+        secret_fields.iter().any(|secret| {
+            let secret = secret.as_ref();
+            field
+                .strip_prefix(secret)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    };
+
+    let fields: std::collections::BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| {
+            let shown = |value: Option<&String>| {
+                value.map(|value| {
+                    if secret(field) {
+                        REDACTED.to_owned()
+                    } else {
+                        value.clone()
+                    }
+                })
+            };
+            let provenance = if after.get(field) == defaults.get(field) {
+                Provenance::Default
+            } else {
+                Provenance::DaemonConfig
+            };
+            FieldChange {
+                field: field.clone(),
+                before: shown(before.get(field)),
+                after: shown(after.get(field)),
+                provenance,
+            }
+        })
+        .collect()
+}
+
+/// Every set leaf of `config`, by dotted path
+fn leaves(config: &SandboxConfig) -> std::collections::BTreeMap<String, String> {
+    fn walk(
+        path: String,
+        value: serde_json::Value,
+        out: &mut std::collections::BTreeMap<String, String>,
+    ) {
+        let join = |key: &dyn fmt::Display| {
+            if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{path}.{key}")
+            }
+        };
+        match value {
+            serde_json::Value::Null => {}
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    walk(join(&key), value, out);
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (index, value) in items.into_iter().enumerate() {
+                    walk(join(&index), value, out);
+                }
+            }
+            serde_json::Value::String(value) => {
+                out.insert(path, value);
+            }
+            value => {
+                out.insert(path, value.to_string());
+            }
+        }
+    }
+
+    let mut out = std::collections::BTreeMap::new();
+    // Serializing plain config data into a `Value` cannot fail
+    if let Ok(value) = serde_json::to_value(config) {
+        walk(String::new(), value, &mut out);
+    }
+    out
+}
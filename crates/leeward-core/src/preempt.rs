@@ -0,0 +1,169 @@
+//! Freezing a worker's running program to make room for other work
+//!
+//! The daemon writes single [`FREEZE`] and [`THAW`] bytes to the worker's
+//! code pipe while an execution that allows it runs. The worker, waiting on
+//! the program, stops and resumes the program's whole process group with
+//! `SIGSTOP` and `SIGCONT`, and leaves the time spent stopped out of both
+//! the timeout and the reported duration.
+//!
+//! Freezing is conservative. Only executions started with
+//! [`ExecuteOptions::preemptible`](crate::worker::ExecuteOptions::preemptible)
+//! can be frozen, and never once they have opened a network connection,
+//! which may be in the middle of a request; without connection counting
+//! (the `seccomp` feature) no execution that may use the network is frozen
+//! at all.
+
+use crate::Result;
+use crate::network::ConnectionTracker;
+use crate::pipe::{FREEZE, THAW};
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What [`Preemption::freeze`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freeze {
+    /// The program was told to stop
+    Frozen,
+    /// It was frozen already
+    AlreadyFrozen,
+    /// No preemptible execution is running
+    Idle,
+    /// The program may be in the middle of a network request
+    HoldsNetwork,
+}
+
+/// Freezes and thaws the execution running on one worker
+///
+/// Shared between the worker, which arms it for each preemptible execution,
+/// and whoever decides when to freeze. Lasts across respawns.
+#[derive(Debug, Default)]
+pub struct Preemption {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Second handle on the current worker process's code pipe
+    control: Option<std::fs::File>,
+    /// The running execution may be frozen
+    armed: bool,
+    frozen: bool,
+    network: Network,
+}
+
+/// Whether the running execution may hold network connections
+#[derive(Debug, Default)]
+enum Network {
+    #[default]
+    Off,
+    /// Connections it opens are counted here
+    Counted(Arc<ConnectionTracker>),
+    /// It may open connections no one counts
+    Uncounted,
+}
+
+impl Preemption {
+    /// Stop the running execution, if it may be stopped
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`](crate::LeewardError::Io), only if the worker's pipe
+    /// is gone.
+    pub fn freeze(&self) -> Result<Freeze> {
+        let mut state = self.lock();
+        if !state.armed {
+            return Ok(Freeze::Idle);
+        }
+        if state.frozen {
+            return Ok(Freeze::AlreadyFrozen);
+        }
+        let holds_network = match &state.network {
+            Network::Off => false,
+            Network::Counted(connections) => connections.opened() > 0,
+            Network::Uncounted => true,
+        };
+        if holds_network {
+            return Ok(Freeze::HoldsNetwork);
+        }
+
+        state.send(FREEZE)?;
+        state.frozen = true;
+        drop(state);
+        Ok(Freeze::Frozen)
+    }
+
+    /// Resume the running execution; returns whether it was frozen
+    ///
+    /// # Errors
+    ///
+    /// As [`Preemption::freeze`].
+    pub fn thaw(&self) -> Result<bool> {
+        let mut state = self.lock();
+        if !state.armed || !state.frozen {
+            return Ok(false);
+        }
+        state.send(THAW)?;
+        state.frozen = false;
+        drop(state);
+        Ok(true)
+    }
+
+    /// Whether the running execution is frozen
+    #[must_use]
+    pub fn is_frozen(&self) -> bool {
+        self.lock().frozen
+    }
+
+    /// Send control bytes to a newly spawned worker through `control`
+    pub(crate) fn attach(&self, control: std::fs::File) {
+        *self.lock() = State {
+            control: Some(control),
+            ..State::default()
+        };
+    }
+
+    /// Forget the worker process, which is gone or going
+    pub(crate) fn detach(&self) {
+        *self.lock() = State::default();
+    }
+
+    /// Allow freezing until [`Self::disarm`], for an execution that may use
+    /// the network if `connections` is set
+    pub(crate) fn arm(&self, connections: Option<Arc<ConnectionTracker>>) {
+        let mut state = self.lock();
+        state.armed = true;
+        state.frozen = false;
+        state.network = match connections {
+            None => Network::Off,
+            Some(connections) if cfg!(feature = "seccomp") => Network::Counted(connections),
+            Some(_) => Network::Uncounted,
+        };
+    }
+
+    /// Stop allowing freezing, once the execution has finished
+    ///
+    /// Bytes already sent and never read are skipped by the worker.
+    pub(crate) fn disarm(&self) {
+        let mut state = self.lock();
+        state.armed = false;
+        state.frozen = false;
+        state.network = Network::Off;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl State {
+    fn send(&mut self, byte: u8) -> Result<()> {
+        let control = self
+            .control
+            .as_mut()
+            .ok_or_else(|| crate::LeewardError::Execution("worker pipe not initialized".into()))?;
+        control.write_all(&[byte])?;
+        Ok(())
+    }
+}
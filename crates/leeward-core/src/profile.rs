@@ -0,0 +1,416 @@
+//! Accounting-only runs that measure what a workload needs
+//!
+//! [`profile_worker`] runs code in a worker with nothing denied and
+//! everything watched. The worker keeps its namespaces, mounts and cgroup,
+//! but every syscall goes through a seccomp listener that counts it and
+//! lets it through. Paths the code opens are checked against the Landlock
+//! rules a worker would apply, as a dry run that refuses nothing. Resource
+//! use and the files left in the workspace are recorded too. The resulting
+//! [`WorkloadProfile`] is plain data, and [`suggest`] turns it into the
+//! config changes the workload would need to run in an ordinary worker.
+//!
+//! Without Landlock or a syscall filter that denies anything, a profiling
+//! run is only for code its caller already trusts that far.
+
+use crate::SandboxConfig;
+pub use crate::denial::syscall_name;
+#[cfg(feature = "seccomp")]
+use crate::isolation::seccomp::int_arg;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+/// How a path was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FileAccess {
+    Read,
+    /// Opened for writing, created, renamed or removed
+    Write,
+}
+
+/// What a workload did during a profiling run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkloadProfile {
+    /// Times each syscall was made, by number
+    pub syscalls: BTreeMap<i64, u64>,
+    /// Paths opened, with the strongest access asked for
+    pub files: BTreeMap<PathBuf, FileAccess>,
+    /// Paths in `files` a worker's Landlock rules would refuse
+    pub landlock_denied: Vec<PathBuf>,
+    /// Syscalls made that a worker's seccomp allowlist does not contain
+    pub unlisted_syscalls: Vec<i64>,
+    /// IPv4 and IPv6 sockets created
+    pub inet_sockets: u64,
+    /// Processes and threads started
+    pub processes: u64,
+    /// Files in the workspace afterwards, with their sizes in bytes
+    pub workspace: BTreeMap<String, u64>,
+    /// Peak memory of the worker's cgroup, in bytes
+    pub memory_peak: u64,
+    /// CPU time of the worker's cgroup, in microseconds
+    pub cpu_time_us: u64,
+}
+
+impl WorkloadProfile {
+    /// Count a syscall and note what it touched
+    #[cfg(feature = "seccomp")]
+    fn record(&mut self, pending: &crate::isolation::seccomp::PendingNotification) {
+        let args = pending.args;
+        *self.syscalls.entry(pending.syscall).or_default() += 1;
+
+        let paths: &[(Option<u64>, u64, FileAccess)] = match pending.syscall {
+            libc::SYS_openat => &[(Some(args[0]), args[1], open_access(args[2]))],
+            libc::SYS_openat2 => {
+                // struct open_how starts with the flags
+                let flags = pending
+                    .read_mem(args[2], 8)
+                    .ok()
+                    .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+                    .map_or(0, u64::from_ne_bytes);
+                &[(Some(args[0]), args[1], open_access(flags))]
+            }
+            libc::SYS_execve => &[(None, args[0], FileAccess::Read)],
+            libc::SYS_execveat => &[(Some(args[0]), args[1], FileAccess::Read)],
+            libc::SYS_mkdirat | libc::SYS_unlinkat => {
+                &[(Some(args[0]), args[1], FileAccess::Write)]
+            }
+            libc::SYS_renameat | libc::SYS_renameat2 => &[
+                (Some(args[0]), args[1], FileAccess::Write),
+                (Some(args[2]), args[3], FileAccess::Write),
+            ],
+            #[cfg(target_arch = "x86_64")]
+            libc::SYS_open => &[(None, args[0], open_access(args[1]))],
+            #[cfg(target_arch = "x86_64")]
+            libc::SYS_creat | libc::SYS_mkdir | libc::SYS_rmdir | libc::SYS_unlink => {
+                &[(None, args[0], FileAccess::Write)]
+            }
+            #[cfg(target_arch = "x86_64")]
+            libc::SYS_rename => &[
+                (None, args[0], FileAccess::Write),
+                (None, args[1], FileAccess::Write),
+            ],
+            libc::SYS_socket => {
+                if matches!(int_arg(args[0]), libc::AF_INET | libc::AF_INET6) {
+                    self.inet_sockets += 1;
+                }
+                &[]
+            }
+            libc::SYS_clone | libc::SYS_clone3 => {
+                self.processes += 1;
+                &[]
+            }
+            #[cfg(target_arch = "x86_64")]
+            libc::SYS_fork | libc::SYS_vfork => {
+                self.processes += 1;
+                &[]
+            }
+            _ => &[],
+        };
+
+        for &(dirfd, addr, access) in paths {
+            if let Some(path) = resolve(pending, dirfd, addr) {
+                let entry = self.files.entry(path).or_insert(access);
+                *entry = (*entry).max(access);
+            }
+        }
+    }
+}
+
+/// Access asked for by `open` flags
+#[cfg(feature = "seccomp")]
+const fn open_access(flags: u64) -> FileAccess {
+    let write = libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC;
+    if int_arg(flags) & write == 0 {
+        FileAccess::Read
+    } else {
+        FileAccess::Write
+    }
+}
+
+/// Absolute path named by a `dirfd` and path pointer pair
+///
+/// Syscalls without a `dirfd` pass `None`. Relative paths are resolved
+/// through the process's `/proc` entries, so the notification must still
+/// be pending afterwards for them to count.
+#[cfg(feature = "seccomp")]
+fn resolve(
+    pending: &crate::isolation::seccomp::PendingNotification,
+    dirfd: Option<u64>,
+    addr: u64,
+) -> Option<PathBuf> {
+    let path = pending.read_path(addr).ok()?;
+    if path.is_absolute() {
+        return Some(normalize(&path));
+    }
+
+    let base = match dirfd.map(int_arg) {
+        None | Some(libc::AT_FDCWD) => format!("/proc/{}/cwd", pending.pid),
+        Some(fd) => format!("/proc/{}/fd/{fd}", pending.pid),
+    };
+    let base = std::fs::read_link(base).ok()?;
+    pending.is_valid().then(|| normalize(&base.join(path)))
+}
+
+/// `path` without `.` and `..` components, resolved lexically
+#[cfg(feature = "seccomp")]
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+/// Config changes that would let a profiled workload run in a worker
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    /// Every syscall the workload made, by name where known
+    pub syscalls: Vec<String>,
+    /// Directories to add to `ro_binds`
+    pub ro_binds: Vec<PathBuf>,
+    /// Directories to add to `rw_binds`
+    pub rw_binds: Vec<PathBuf>,
+    /// Whether the workload needs `allow_network`
+    pub allow_network: bool,
+}
+
+/// Pseudo-filesystems whose entries are bound one by one
+const PSEUDO_FS: [&str; 3] = ["/dev", "/proc", "/sys"];
+
+/// Most components of a suggested bind
+const BIND_DEPTH: usize = 3;
+
+/// Suggest config changes from what a workload did
+///
+/// Only paths Landlock would have refused get a bind. Each bind is the
+/// path's directory, cut to [`BIND_DEPTH`] components so a package's
+/// files share one, except under `/dev`, `/proc` and `/sys` where the path
+/// itself is bound. A directory any refused path was written to is bound
+/// read-write, and binds inside another bind of at least the same access
+/// are left out.
+#[must_use]
+pub fn suggest(profile: &WorkloadProfile) -> Suggestion {
+    let mut readable = BTreeSet::new();
+    let mut writable = BTreeSet::new();
+    for path in &profile.landlock_denied {
+        let bind = bind_for(path);
+        match profile.files.get(path) {
+            Some(FileAccess::Write) => writable.insert(bind),
+            _ => readable.insert(bind),
+        };
+    }
+
+    let covered = |path: &Path, by: &BTreeSet<PathBuf>| {
+        by.iter()
+            .any(|other| other != path && path.starts_with(other))
+    };
+    let read_write = writable
+        .iter()
+        .filter(|path| !covered(path, &writable))
+        .cloned()
+        .collect();
+    let read_only = readable
+        .iter()
+        .filter(|path| {
+            !covered(path, &readable) && !writable.iter().any(|dir| path.starts_with(dir))
+        })
+        .cloned()
+        .collect();
+
+    Suggestion {
+        syscalls: profile
+            .syscalls
+            .keys()
+            .map(|&nr| syscall_name(nr).map_or_else(|| nr.to_string(), str::to_owned))
+            .collect(),
+        ro_binds: read_only,
+        rw_binds: read_write,
+        allow_network: profile.inet_sockets > 0,
+    }
+}
+
+/// Directory to bind so that `path` can be opened
+fn bind_for(path: &Path) -> PathBuf {
+    if PSEUDO_FS.iter().any(|fs| path.starts_with(fs)) {
+        return path.to_path_buf();
+    }
+    let names = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count();
+    let depth = names.saturating_sub(1).clamp(1, BIND_DEPTH);
+    let mut bind = PathBuf::from("/");
+    bind.extend(
+        path.components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .take(depth),
+    );
+    bind
+}
+
+/// Whether a worker's Landlock rules would let it open `path` this way
+///
+/// Mirrors the rules a worker applies: read access beside the interpreter
+/// and under `ro_binds`, full access under `rw_binds` and `/tmp`.
+#[cfg(feature = "landlock")]
+#[must_use]
+pub fn landlock_allows(config: &SandboxConfig, path: &Path, access: FileAccess) -> bool {
+    let writable = config
+        .rw_binds
+        .iter()
+        .map(PathBuf::as_path)
+        .chain([Path::new("/tmp")]);
+    let mut readable = config
+        .python_path
+        .parent()
+        .into_iter()
+        .chain(config.ro_binds.iter().map(PathBuf::as_path));
+
+    writable.into_iter().any(|dir| path.starts_with(dir))
+        || (access == FileAccess::Read && readable.any(|dir| path.starts_with(dir)))
+}
+
+/// Without Landlock, workers refuse no paths
+#[cfg(not(feature = "landlock"))]
+#[must_use]
+pub const fn landlock_allows(_config: &SandboxConfig, _path: &Path, _access: FileAccess) -> bool {
+    true
+}
+
+/// Count the syscalls made under the worker process `pid`, from the
+/// listener its seccomp layer sends over `channel`
+///
+/// Those of the worker's own threads are left out, so only the code it
+/// runs is counted.
+#[cfg(feature = "seccomp")]
+pub(crate) fn observe_worker(
+    channel: &std::os::unix::net::UnixStream,
+    pid: libc::pid_t,
+) -> crate::Result<std::thread::JoinHandle<WorkloadProfile>> {
+    use crate::isolation::seccomp::{NotificationStream, SeccompNotifyFd, observe_stream};
+    use std::os::fd::AsRawFd;
+
+    // Blocks until the worker sends it, or dies and closes its end
+    let listener = crate::pipe::recv_fd(channel.as_raw_fd(), 0)?.ok_or_else(|| {
+        crate::LeewardError::Seccomp("worker did not hand over its listener".into())
+    })?;
+    let stream = NotificationStream::new(SeccompNotifyFd::from(listener));
+    // Whether each thread seen is one of the worker's own
+    let mut own = BTreeMap::new();
+    observe_stream(
+        stream,
+        WorkloadProfile::default(),
+        move |profile, pending| {
+            let worker = *own.entry(pending.pid).or_insert_with(|| {
+                Path::new(&format!("/proc/{pid}/task/{}", pending.pid)).exists()
+            });
+            if !worker {
+                profile.record(pending);
+            }
+        },
+    )
+}
+
+/// Run code once in `worker`, with every syscall counted and nothing
+/// denied
+///
+/// `worker` must be [`observed`](crate::worker::Worker::observed). It is
+/// spawned here and stopped afterwards, taking anything the code left
+/// running with it. Input files, the timeout and the memory limit apply
+/// as in any execution. Peak memory and CPU time are those of the
+/// worker's cgroup, and 0 without one.
+///
+/// # Errors
+///
+/// Fails if the worker cannot be spawned, was not observed, or could not run
+/// the code.
+#[cfg(feature = "seccomp")]
+pub fn profile_worker(
+    worker: &mut crate::worker::Worker,
+    code: &str,
+    options: &crate::worker::ExecuteOptions,
+) -> crate::Result<(crate::ExecutionResult, WorkloadProfile)> {
+    use crate::LeewardError;
+
+    worker.spawn()?;
+    let result = worker.execute(code, options);
+    #[cfg(feature = "cgroups")]
+    let measured = worker.cgroup().is_some();
+    #[cfg(not(feature = "cgroups"))]
+    let measured = false;
+    // The workspace is only reachable through the worker's own root
+    let mut workspace = BTreeMap::new();
+    if let Some(pid) = worker.pid {
+        let workdir = worker
+            .config()
+            .workdir
+            .strip_prefix("/")
+            .unwrap_or(&worker.config().workdir);
+        scan_workspace(
+            &Path::new(&format!("/proc/{pid}/root")).join(workdir),
+            "",
+            0,
+            &mut workspace,
+        );
+    }
+    worker.stop();
+
+    let observer = worker
+        .take_observer()
+        .ok_or_else(|| LeewardError::Seccomp("the worker's syscalls were not observed".into()))?;
+    let mut profile = observer
+        .join()
+        .map_err(|_| LeewardError::Seccomp("profiling observer panicked".into()))?;
+    let result = result?;
+
+    if measured {
+        profile.memory_peak = result.memory_peak;
+        profile.cpu_time_us = result.cpu_time_us;
+    }
+    profile.workspace = workspace;
+    let config = worker.config();
+    profile.landlock_denied = profile
+        .files
+        .iter()
+        .filter(|(path, access)| !landlock_allows(config, path, **access))
+        .map(|(path, _)| path.clone())
+        .collect();
+    let allowed = crate::isolation::SeccompConfig::default().syscalls();
+    profile.unlisted_syscalls = profile
+        .syscalls
+        .keys()
+        .copied()
+        .filter(|nr| !allowed.contains(nr))
+        .collect();
+
+    Ok((result, profile))
+}
+
+/// Record the regular files under `dir`, without following symlinks
+#[cfg(feature = "seccomp")]
+fn scan_workspace(dir: &Path, prefix: &str, depth: usize, found: &mut BTreeMap<String, u64>) {
+    if depth >= crate::workspace::MAX_DEPTH {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => {
+                scan_workspace(&entry.path(), &format!("{name}/"), depth + 1, found);
+            }
+            Ok(kind) if kind.is_file() => {
+                found.insert(name, entry.metadata().map_or(0, |meta| meta.len()));
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,24 +1,629 @@
 //! Wire protocol for daemon communication
 //!
 //! Supports both traditional msgpack and zero-copy shared memory modes
+//!
+//! Msgpack frames (4-byte big-endian length, then the message) are the
+//! default and the only encoding tuned for performance. For debugging and
+//! clients without a msgpack library, a connection whose first byte is `{`
+//! switches to newline-delimited JSON instead: one [`Request`] or
+//! [`Response`] per line, with binary fields base64-encoded. Both encodings
+//! share [`MAX_MESSAGE_SIZE`].
 
-use crate::ExecutionResult;
+use crate::config::Interpreter;
+use crate::error::Direction;
+use crate::isolation::fatal::WorkerDeath;
+use crate::policy::{PolicyChange, PolicyField};
+use crate::profile::WorkloadProfile;
+use crate::units::{ByteSize, DurationSecs};
+use crate::worker::{GoodbyeReason, WorkerState, WorkerTiming, WorkerUid};
+use crate::{ExecutionResult, LeewardError, OutcomeCode};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
 use std::time::Duration;
 
+pub use crate::debug_flags::DebugFlag;
+pub use crate::provenance::BuildInfo;
+
+/// Largest code payload accepted in a request
+///
+/// Leaves room under the worker pipe's 1 MiB frame limit for the rest of the job.
+pub const MAX_CODE_SIZE: usize = 1000 * 1024;
+
+/// Largest encoded message accepted on the socket, in either encoding
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest request, counting code, stdin and input files, that the daemon's
+/// fast path may run without queueing
+pub const FAST_PATH_MAX_BYTES: usize = 1024;
+
+/// Largest output, stdout and stderr together, an execution returns
+///
+/// Leaves room under the worker pipe's 10 MiB result frame for the rest of
+/// the result.
+pub const MAX_OUTPUT_SIZE: usize = 8 * 1024 * 1024;
+
+/// Version of this protocol, bumped when a change would confuse older peers
+///
+/// Additions that older peers ignore, such as new optional fields or new
+/// features, leave it unchanged.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Names of features a daemon may advertise in [`DaemonInfo::features`]
+///
+/// Plain strings namespaced by area, so a client can check for a feature
+/// newer than itself, and unknown names are simply not advertised.
+pub mod feature {
+    /// Input files written into the workspace (`ExecuteRequest.files`)
+    pub const EXEC_FILES: &str = "exec.files";
+    /// Input files uploaded ahead of the request, through
+    /// [`Request::UploadBegin`](super::Request::UploadBegin)
+    pub const EXEC_UPLOADS: &str = "exec.uploads";
+    /// Data fed to the program's stdin
+    pub const EXEC_STDIN: &str = "exec.stdin";
+    /// Extra environment variables
+    pub const EXEC_ENV: &str = "exec.env";
+    /// Program arguments
+    pub const EXEC_ARGS: &str = "exec.args";
+    /// Per-request timezone
+    pub const EXEC_TIMEZONE: &str = "exec.timezone";
+    /// Per-request memory limit
+    pub const EXEC_MEMORY_LIMIT: &str = "exec.memory_limit";
+    /// Per-request socket limit, when networking is enabled
+    pub const EXEC_MAX_CONNECTIONS: &str = "exec.max_connections";
+    /// Python traceback shortly before the timeout
+    pub const EXEC_TRACEBACK: &str = "exec.soft_timeout_traceback";
+    /// Scheduling priority hints
+    pub const EXEC_PRIORITY: &str = "exec.priority";
+    /// [`RequestPriority::Batch`](super::RequestPriority::Batch)
+    pub const EXEC_BATCH: &str = "exec.batch";
+    /// Profiling runs (`ExecuteRequest.profile_mode`)
+    pub const EXEC_PROFILE: &str = "exec.profile";
+    /// Runs under the debug profile (`ExecuteRequest.debug_profile`)
+    pub const EXEC_DEBUG: &str = "exec.debug";
+    /// Executions answered at once with their id, their result kept for
+    /// [`Request::FetchResult`](super::Request::FetchResult)
+    pub const EXEC_DETACH: &str = "exec.detach";
+    /// Per-connection defaults for executions, through
+    /// [`Request::SetDefaults`](super::Request::SetDefaults)
+    pub const EXEC_DEFAULTS: &str = "exec.defaults";
+    /// Where an execution's settings would come from, through
+    /// [`Request::ExplainPolicy`](super::Request::ExplainPolicy)
+    pub const EXEC_EXPLAIN: &str = "exec.explain";
+    /// Small requests run inline when a worker is idle
+    pub const EXEC_FAST_PATH: &str = "exec.fast_path";
+    /// Code passed in a shared memory slot leased with
+    /// [`Request::ShmSetup`](super::Request::ShmSetup)
+    pub const EXEC_SHM: &str = "exec.shm";
+    /// Newline-delimited JSON on the socket
+    pub const WIRE_JSON: &str = "wire.json";
+    /// Pool alert events through [`Request::Subscribe`](super::Request::Subscribe)
+    pub const EVENTS_ALERTS: &str = "events.alerts";
+    /// Worker death events through [`Request::Subscribe`](super::Request::Subscribe)
+    pub const EVENTS_WORKER_DIED: &str = "events.worker_died";
+    /// Events for workers that exited on their own, told apart from deaths,
+    /// through [`Request::Subscribe`](super::Request::Subscribe)
+    pub const EVENTS_WORKER_EXITED: &str = "events.worker_exited";
+    /// Policy change events through
+    /// [`Request::Subscribe`](super::Request::Subscribe)
+    pub const EVENTS_POLICY_CHANGE: &str = "events.policy_change";
+    /// The latest policy changes through
+    /// [`Request::PolicyChanges`](super::Request::PolicyChanges)
+    pub const HISTORY_POLICY_CHANGES: &str = "history.policy_changes";
+    /// Recycling workers that run an old config
+    pub const POOL_RECYCLE_STALE: &str = "pool.recycle_stale";
+    /// Draining a profile's workers through
+    /// [`Request::DrainProfile`](super::Request::DrainProfile)
+    pub const POOL_DRAIN: &str = "pool.drain";
+    /// Capacity probes through [`Request::Health`](super::Request::Health)
+    pub const POOL_HEALTH: &str = "pool.health";
+    /// Handing the socket to a new daemon process through
+    /// [`Request::Handover`](super::Request::Handover)
+    pub const DAEMON_HANDOVER: &str = "daemon.handover";
+    /// Changing the log filter and debug flags of a running daemon through
+    /// [`Request::SetLogFilter`](super::Request::SetLogFilter) and
+    /// [`Request::SetDebug`](super::Request::SetDebug)
+    pub const DAEMON_LOG_CONTROL: &str = "daemon.log_control";
+    /// Workers rooted in a shared template with their own scratch mounts
+    pub const POOL_ROOT_TEMPLATE: &str = "pool.root_template";
+    /// Batch executions frozen while other work runs, announced through
+    /// [`Request::Subscribe`](super::Request::Subscribe)
+    pub const POOL_TIME_SLICING: &str = "pool.time_slicing";
+    /// Workers run under a seccomp filter
+    pub const SANDBOX_SECCOMP: &str = "sandbox.seccomp";
+    /// Workers run under Landlock, which this kernel supports
+    pub const SANDBOX_LANDLOCK: &str = "sandbox.landlock";
+    /// Sandboxed code may use the network
+    pub const SANDBOX_NETWORK: &str = "sandbox.network";
+}
+
 /// Request to execute code
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ExecuteRequest {
     /// Python code to execute (or None if using shared memory)
     pub code: Option<String>,
     /// Shared memory slot ID (if using shared memory mode)
     pub shm_slot_id: Option<u32>,
+    /// Generation of the slot's lease, from [`ShmLease::generation`]
+    #[serde(default)]
+    pub shm_generation: Option<u32>,
+    /// What to do with output too big for the slot's response half,
+    /// instead of the daemon's `shm_overflow`
+    #[serde(default)]
+    pub shm_overflow: Option<ShmOverflowPolicy>,
     /// Optional timeout override
     pub timeout: Option<Duration>,
     /// Optional memory limit override
-    pub memory_limit: Option<u64>,
+    pub memory_limit: Option<ByteSize>,
     /// Input files (path -> content)
+    #[serde(with = "binary::files")]
     pub files: Vec<(String, Vec<u8>)>,
+    /// Maximum sockets the execution may open when networking is enabled
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    /// Dump a Python traceback to stderr shortly before the timeout
+    #[serde(default)]
+    pub soft_timeout_traceback: bool,
+    /// Data fed to the code's stdin
+    #[serde(default, with = "binary::option")]
+    pub stdin: Option<Vec<u8>>,
+    /// Extra environment variables
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Scheduling priority hint
+    #[serde(default)]
+    pub priority: RequestPriority,
+    /// Hex-encoded SHA-256 of the code, when the client computed it
+    #[serde(default)]
+    pub code_hash: Option<String>,
+    /// IANA timezone overriding the sandbox config's
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Run in a worker of its own with every syscall counted and none
+    /// denied, and attach a [`WorkloadProfile`] to the response
+    ///
+    /// Only honoured for clients running as the daemon's own user or root.
+    #[serde(default)]
+    pub profile_mode: bool,
+    /// Run in a worker of its own under the debug profile, where the code's
+    /// processes may trace one another and a sampler such as `py-spy` can
+    /// attach from the host; see [`SandboxConfig::debug`](crate::SandboxConfig::debug)
+    ///
+    /// Only honoured for clients running as the daemon's own user or root.
+    #[serde(default)]
+    pub debug_profile: bool,
+    /// Program that runs the code
+    #[serde(default)]
+    pub interpreter: Interpreter,
+    /// Arguments passed to the code, as `sys.argv[1:]` or `$1`, `$2`, ...
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Input files uploaded with [`Request::UploadBegin`], as (path, upload id)
+    #[serde(default)]
+    pub uploads: Vec<(String, u64)>,
+    /// Answer at once with [`Response::Detached`] and keep the result on
+    /// the daemon until [`Request::FetchResult`] takes it, so the client
+    /// need not stay connected while the code runs
+    #[serde(default)]
+    pub detach: bool,
+    /// Strip a byte order mark, turn CRLF line endings into LF and blank
+    /// out a non-UTF-8 encoding declaration before running the code, each
+    /// listed in [`ExecuteResponse::adjustments`]; see [`crate::source`]
+    #[serde(default = "default_normalize_code")]
+    pub normalize_code: bool,
+    /// Let the daemon send the code `SIGUSR1` as it nears its memory
+    /// limit, when its sandbox config sets
+    /// [`pressure_signal_percent`](crate::SandboxConfig::pressure_signal_percent)
+    #[serde(default = "default_pressure_signal")]
+    pub pressure_signal: bool,
+}
+
+const fn default_normalize_code() -> bool {
+    true
+}
+
+const fn default_pressure_signal() -> bool {
+    true
+}
+
+impl ExecuteRequest {
+    /// Whether this is a small snippet under default limits, which the
+    /// daemon's fast path may run inline when a worker is idle
+    #[must_use]
+    pub fn fits_fast_path(&self) -> bool {
+        let size = self.code.as_ref().map_or(0, String::len)
+            + self.stdin.as_ref().map_or(0, Vec::len)
+            + self.args.iter().map(String::len).sum::<usize>()
+            + self
+                .files
+                .iter()
+                .map(|(name, bytes)| name.len() + bytes.len())
+                .sum::<usize>();
+
+        size < FAST_PATH_MAX_BYTES
+            && self.code.is_some()
+            && self.timeout.is_none()
+            && self.memory_limit.is_none()
+            && self.max_connections.is_none()
+            && self.uploads.is_empty()
+            && !self.profile_mode
+            && !self.debug_profile
+    }
+
+    /// Features the daemon must advertise to honour everything this
+    /// request asks for
+    ///
+    /// The interpreter is not among them; check it against
+    /// [`DaemonInfo::languages`].
+    #[must_use]
+    pub fn required_features(&self) -> Vec<&'static str> {
+        [
+            (!self.files.is_empty(), feature::EXEC_FILES),
+            (!self.uploads.is_empty(), feature::EXEC_UPLOADS),
+            (self.stdin.is_some(), feature::EXEC_STDIN),
+            (!self.env.is_empty(), feature::EXEC_ENV),
+            (!self.args.is_empty(), feature::EXEC_ARGS),
+            (self.timezone.is_some(), feature::EXEC_TIMEZONE),
+            (self.memory_limit.is_some(), feature::EXEC_MEMORY_LIMIT),
+            (
+                self.max_connections.is_some(),
+                feature::EXEC_MAX_CONNECTIONS,
+            ),
+            (self.soft_timeout_traceback, feature::EXEC_TRACEBACK),
+            (
+                self.priority != RequestPriority::Normal,
+                feature::EXEC_PRIORITY,
+            ),
+            (self.priority == RequestPriority::Batch, feature::EXEC_BATCH),
+            (self.profile_mode, feature::EXEC_PROFILE),
+            (self.debug_profile, feature::EXEC_DEBUG),
+            (self.detach, feature::EXEC_DETACH),
+            (self.shm_slot_id.is_some(), feature::EXEC_SHM),
+        ]
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect()
+    }
+}
+
+/// Scheduling priority of a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RequestPriority {
+    /// Long-running work, which a daemon with time slicing freezes while
+    /// other work runs
+    Batch,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Options a connection's executions get when they leave them unset,
+/// stored with [`Request::SetDefaults`]
+///
+/// Defaults only fill in a request; it is then held to the same limits as
+/// one that set them itself. Each field filled in is listed in
+/// [`ExecuteResponse::adjustments`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecuteDefaults {
+    /// Timeout of executions that set none
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Memory limit of executions that set none
+    #[serde(default)]
+    pub memory_limit: Option<ByteSize>,
+    /// Environment variables executions get unless they set the same name
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Priority of executions left at [`RequestPriority::Normal`]
+    #[serde(default)]
+    pub priority: Option<RequestPriority>,
+    /// IANA timezone of executions that set none
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl ExecuteDefaults {
+    /// Whether nothing is filled in
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fill in `timeout`
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fill in `limit` as the memory limit
+    #[must_use]
+    pub const fn memory_limit(mut self, limit: ByteSize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Add an environment variable
+    #[must_use]
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Fill in `priority`
+    #[must_use]
+    pub const fn priority(mut self, priority: RequestPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Fill in the timezone `name`
+    #[must_use]
+    pub fn timezone(mut self, name: impl Into<String>) -> Self {
+        self.timezone = Some(name.into());
+        self
+    }
+}
+
+/// Fluent builder for [`ExecuteRequest`]
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    request: ExecuteRequest,
+    max_code_size: usize,
+}
+
+impl RequestBuilder {
+    /// Start a request for `code`
+    #[must_use]
+    pub fn new(code: impl Into<String>) -> Self {
+        Self {
+            request: ExecuteRequest {
+                code: Some(code.into()),
+                shm_slot_id: None,
+                shm_generation: None,
+                shm_overflow: None,
+                timeout: None,
+                memory_limit: None,
+                files: Vec::new(),
+                max_connections: None,
+                soft_timeout_traceback: false,
+                stdin: None,
+                env: Vec::new(),
+                priority: RequestPriority::default(),
+                code_hash: None,
+                timezone: None,
+                profile_mode: false,
+                debug_profile: false,
+                interpreter: Interpreter::default(),
+                args: Vec::new(),
+                uploads: Vec::new(),
+                detach: false,
+                normalize_code: true,
+                pressure_signal: true,
+            },
+            max_code_size: MAX_CODE_SIZE,
+        }
+    }
+
+    /// Start a request for the code written to the shared memory slot of
+    /// `lease`
+    #[must_use]
+    pub fn shm(lease: &ShmLease) -> Self {
+        let mut builder = Self::new(String::new());
+        builder.request.code = None;
+        builder.request.shm_slot_id = Some(lease.slot_id);
+        builder.request.shm_generation = Some(lease.generation);
+        builder
+    }
+
+    /// Start a request for the script at `path`, recording its hash
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::InvalidRequest`] if `path` cannot be read as UTF-8 text.
+    pub fn from_file(path: &Path) -> crate::Result<Self> {
+        let code = std::fs::read_to_string(path).map_err(|e| {
+            LeewardError::InvalidRequest(format!("failed to read {}: {e}", path.display()))
+        })?;
+        Ok(Self::from_source(code))
+    }
+
+    /// Start a request for Python source read from a file or stdin,
+    /// normalized as the daemon would and with its hash recorded, so the
+    /// same script hashes alike whichever platform it was saved on
+    #[must_use]
+    pub fn from_source(mut code: String) -> Self {
+        crate::source::normalize(&mut code, Interpreter::Python);
+        let hash = sha256_hex(code.as_bytes());
+
+        let mut builder = Self::new(code);
+        builder.request.code_hash = Some(hash);
+        builder
+    }
+
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.request.timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    pub const fn memory_limit(mut self, limit: ByteSize) -> Self {
+        self.request.memory_limit = Some(limit);
+        self
+    }
+
+    #[must_use]
+    pub fn timezone(mut self, name: impl Into<String>) -> Self {
+        self.request.timezone = Some(name.into());
+        self
+    }
+
+    /// Ask for an accounting-only run, see [`ExecuteRequest::profile_mode`]
+    #[must_use]
+    pub const fn profile_mode(mut self, profile: bool) -> Self {
+        self.request.profile_mode = profile;
+        self
+    }
+
+    /// Ask for the debug profile, see [`ExecuteRequest::debug_profile`]
+    #[must_use]
+    pub const fn debug_profile(mut self, debug: bool) -> Self {
+        self.request.debug_profile = debug;
+        self
+    }
+
+    /// Whether to normalize the code first, see
+    /// [`ExecuteRequest::normalize_code`]
+    #[must_use]
+    pub const fn normalize_code(mut self, normalize: bool) -> Self {
+        self.request.normalize_code = normalize;
+        self
+    }
+
+    /// Whether the code may be warned of memory pressure, see
+    /// [`ExecuteRequest::pressure_signal`]
+    #[must_use]
+    pub const fn pressure_signal(mut self, signal: bool) -> Self {
+        self.request.pressure_signal = signal;
+        self
+    }
+
+    /// Detach from the execution, see [`ExecuteRequest::detach`]
+    #[must_use]
+    pub const fn detach(mut self, detach: bool) -> Self {
+        self.request.detach = detach;
+        self
+    }
+
+    /// What to do with output too big for the shared memory slot, see
+    /// [`ExecuteRequest::shm_overflow`]
+    #[must_use]
+    pub const fn shm_overflow(mut self, policy: ShmOverflowPolicy) -> Self {
+        self.request.shm_overflow = Some(policy);
+        self
+    }
+
+    #[must_use]
+    pub const fn interpreter(mut self, interpreter: Interpreter) -> Self {
+        self.request.interpreter = interpreter;
+        self
+    }
+
+    /// Append an argument passed to the code
+    #[must_use]
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.request.args.push(arg.into());
+        self
+    }
+
+    /// Add an input file
+    #[must_use]
+    pub fn with_file(mut self, name: impl Into<String>, bytes: impl Into<Vec<u8>>) -> Self {
+        self.request.files.push((name.into(), bytes.into()));
+        self
+    }
+
+    /// Add an input file uploaded as `id`, written to `name`
+    #[must_use]
+    pub fn with_upload(mut self, name: impl Into<String>, id: u64) -> Self {
+        self.request.uploads.push((name.into(), id));
+        self
+    }
+
+    #[must_use]
+    pub fn stdin(mut self, data: Vec<u8>) -> Self {
+        self.request.stdin = Some(data);
+        self
+    }
+
+    #[must_use]
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.request.env.push((key.into(), value.into()));
+        self
+    }
+
+    #[must_use]
+    pub const fn priority(mut self, priority: RequestPriority) -> Self {
+        self.request.priority = priority;
+        self
+    }
+
+    #[must_use]
+    pub const fn max_connections(mut self, max: u32) -> Self {
+        self.request.max_connections = Some(max);
+        self
+    }
+
+    #[must_use]
+    pub const fn soft_timeout_traceback(mut self, enabled: bool) -> Self {
+        self.request.soft_timeout_traceback = enabled;
+        self
+    }
+
+    /// Override the code size limit checked by [`build`](Self::build)
+    #[must_use]
+    pub const fn max_code_size(mut self, bytes: usize) -> Self {
+        self.max_code_size = bytes;
+        self
+    }
+
+    /// Finish the request, checking the code size and that it has no NUL
+    /// byte
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::InvalidRequest`] if the code is over the size limit or
+    /// has a NUL byte.
+    pub fn build(self) -> crate::Result<ExecuteRequest> {
+        let len = self.request.code.as_ref().map_or(0, String::len);
+        if len > self.max_code_size {
+            return Err(LeewardError::InvalidRequest(format!(
+                "code is {len} bytes, limit is {}",
+                self.max_code_size
+            )));
+        }
+        if let Some(at) = self
+            .request
+            .code
+            .as_deref()
+            .and_then(crate::source::find_nul)
+        {
+            return Err(LeewardError::InvalidRequest(format!(
+                "code has a NUL byte at offset {at}"
+            )));
+        }
+        Ok(self.request)
+    }
+}
+
+/// Hex-encoded SHA-256 of `bytes`, as used for code and upload hashes
+#[must_use]
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Hex-encoded SHA-256 of everything `reader` yields, read a buffer at a
+/// time
+///
+/// # Errors
+///
+/// The first error `reader` returns.
+pub fn sha256_hex_reader(mut reader: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
 }
 
 /// Communication mode for the request
@@ -26,7 +631,7 @@ pub struct ExecuteRequest {
 pub enum CommunicationMode {
     /// Traditional msgpack over socket
     Socket,
-    /// io_uring for control, shared memory for data
+    /// `io_uring` for control, shared memory for data
     IoUring,
     /// Shared memory for both control and data
     SharedMemory,
@@ -41,6 +646,134 @@ pub struct ExecuteResponse {
     pub result: Option<ExecutionResult>,
     /// Error message (if !success)
     pub error: Option<String>,
+    /// What went wrong (if !success)
+    #[serde(default)]
+    pub error_code: Option<OutcomeCode>,
+    /// What the code did, for a `profile_mode` request
+    #[serde(default)]
+    pub profile: Option<WorkloadProfile>,
+    /// Parts of the request the daemon did not honour as asked
+    #[serde(default)]
+    pub adjustments: Vec<Adjustment>,
+    /// Where the output of an execution with a shared memory slot went;
+    /// streams in the slot are left empty in `result`
+    #[serde(default)]
+    pub shm_payload: Option<ShmPayload>,
+}
+
+/// A part of an execution request the daemon changed or ignored, in
+/// [`ExecuteResponse::adjustments`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Adjustment {
+    /// Request field, as named on the wire
+    pub field: String,
+    /// What the request asked for
+    pub requested: String,
+    /// What the daemon used instead
+    pub applied: String,
+    /// Why, for the person who sent the request
+    pub reason: String,
+}
+
+impl Adjustment {
+    /// `field` was asked to be `requested` but is `applied`, because `reason`
+    #[must_use]
+    pub fn new(
+        field: impl Into<String>,
+        requested: impl Into<String>,
+        applied: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            requested: requested.into(),
+            applied: applied.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: requested {}, applied {} ({})",
+            self.field, self.requested, self.applied, self.reason
+        )
+    }
+}
+
+impl ExecuteResponse {
+    /// Response carrying a finished execution
+    #[must_use]
+    pub const fn ok(result: ExecutionResult) -> Self {
+        Self {
+            success: true,
+            result: Some(result),
+            error: None,
+            error_code: None,
+            profile: None,
+            adjustments: Vec::new(),
+            shm_payload: None,
+        }
+    }
+
+    /// Attach the profile of a `profile_mode` run
+    #[must_use]
+    pub fn with_profile(mut self, profile: WorkloadProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Note how the daemon changed the request before running it
+    #[must_use]
+    pub fn with_adjustments(mut self, adjustments: Vec<Adjustment>) -> Self {
+        self.adjustments = adjustments;
+        self
+    }
+
+    /// Note where the output went for an execution with a shared memory
+    /// slot
+    #[must_use]
+    pub const fn with_shm_payload(mut self, payload: ShmPayload) -> Self {
+        self.shm_payload = Some(payload);
+        self
+    }
+
+    /// Response for a request that did not run to completion
+    #[must_use]
+    pub fn failed(error_code: OutcomeCode, message: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            result: None,
+            error: Some(message.into()),
+            error_code: Some(error_code),
+            profile: None,
+            adjustments: Vec::new(),
+            shm_payload: None,
+        }
+    }
+
+    /// Exit code for this response under [`OutcomeCode`]
+    ///
+    /// The program's outcome on success; otherwise the error code, or
+    /// `Daemon` if an older daemon sent none.
+    #[must_use]
+    pub fn outcome(&self) -> OutcomeCode {
+        if self.success {
+            return self
+                .result
+                .as_ref()
+                .map_or(OutcomeCode::Protocol, ExecutionResult::outcome);
+        }
+        self.error_code.unwrap_or(OutcomeCode::Daemon)
+    }
+}
+
+impl From<&LeewardError> for ExecuteResponse {
+    fn from(error: &LeewardError) -> Self {
+        Self::failed(error.into(), error.to_string())
+    }
 }
 
 /// Request types
@@ -51,8 +784,994 @@ pub enum Request {
     Execute(ExecuteRequest),
     /// Get pool status
     Status,
+    /// Get pool status with executions in flight per client
+    ///
+    /// Answered without waiting on busy workers.
+    StatusDetailed,
+    /// List workers with their latest timing breakdown
+    ListWorkers,
+    /// Recycle idle workers still running an old config, a few at a time
+    RecycleStale,
+    /// Recycle every worker of `profile` once it finishes its current
+    /// execution, rebuilding the root template first, answered with
+    /// [`Response::Drain`]
+    ///
+    /// For picking up an upgraded interpreter or runtime that workers
+    /// spawned earlier still have mapped. This daemon has one profile,
+    /// [`DEFAULT_PROFILE`].
+    DrainProfile {
+        profile: String,
+        /// Why, for the daemon's log
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Replace the daemon's log filter, a `RUST_LOG` style directive such as
+    /// `leeward=debug`, answered with [`Response::LogSettings`]
+    ///
+    /// Only the daemon's own user or root may ask. A config reload restores
+    /// the filter the daemon started with, unless `persist` is set.
+    SetLogFilter {
+        directive: String,
+        #[serde(default)]
+        persist: bool,
+    },
+    /// Turn a [`DebugFlag`] on or off, answered with
+    /// [`Response::LogSettings`]
+    ///
+    /// Only the daemon's own user or root may ask. A config reload turns
+    /// the flag back off, unless `persist` is set.
+    SetDebug {
+        flag: DebugFlag,
+        enabled: bool,
+        #[serde(default)]
+        persist: bool,
+    },
+    /// Stream [`Response::Event`]s of the given kinds (all if empty) on
+    /// this connection until the client disconnects
+    Subscribe {
+        #[serde(default)]
+        kinds: Vec<EventKind>,
+    },
     /// Ping
     Ping,
+    /// Whether the daemon has room for more work, answered with
+    /// [`Response::Health`] from the pool's snapshot without waiting on a
+    /// lock
+    ///
+    /// Healthy means at least `min_idle` idle workers, the daemon's
+    /// `health_min_idle` if unset.
+    Health {
+        #[serde(default)]
+        min_idle: Option<usize>,
+    },
+    /// Ask who the daemon is and what it supports
+    Hello,
+    /// Start uploading an input file, answered with its id
+    ///
+    /// Beginning again with the same name, length and hash as an upload
+    /// this client left unfinished resumes that upload instead.
+    UploadBegin {
+        name: String,
+        total_len: u64,
+        /// Hex-encoded SHA-256 of the whole file, checked at commit
+        sha256: String,
+        /// Keep the upload for later executions instead of consuming it
+        /// with the first one
+        #[serde(default)]
+        reusable: bool,
+    },
+    /// Bytes of an upload starting at `offset`; chunks may come in any order
+    UploadChunk {
+        id: u64,
+        offset: u64,
+        #[serde(with = "binary::vec")]
+        data: Vec<u8>,
+    },
+    /// Check an upload against its hash, making it usable by executions
+    UploadCommit { id: u64 },
+    /// Lease a shared memory slot pair to this connection, answered with
+    /// [`Response::ShmSlot`]
+    ///
+    /// Only over msgpack, whose answer frame carries the region's memfd as
+    /// `SCM_RIGHTS`. Write the code to the request slot and execute it with
+    /// [`RequestBuilder::shm`]. The slot is this connection's until
+    /// [`Request::ShmFree`] gives it back, the connection closes, or it sits
+    /// unused past the daemon's `shm_slot_ttl`; after that its generation
+    /// changes and the old lease is refused.
+    ShmSetup,
+    /// Give back a slot leased with [`Request::ShmSetup`], answered with
+    /// [`Response::ShmFreed`]
+    ShmFree { slot_id: u32, generation: u32 },
+    /// Take the result of a detached execution, answered with
+    /// [`Response::Execute`] and forgotten by the daemon after
+    ///
+    /// Only the client uid that started the execution may take it. Until
+    /// the execution finishes the answer is an error of kind
+    /// [`ErrorKind::Pending`].
+    FetchResult { execution_id: u64 },
+    /// Replace this connection's [`ExecuteDefaults`], answered with
+    /// [`Response::Defaults`]
+    ///
+    /// The defaults are checked and brought within the daemon's limits as
+    /// they are set, and last until the connection closes, the next
+    /// `SetDefaults`, or a [`Request::Hello`], which clears them. Empty
+    /// defaults clear them too.
+    SetDefaults { defaults: ExecuteDefaults },
+    /// Resolve the settings `request` would run with on this connection,
+    /// answered with [`Response::Policy`] without running anything
+    ///
+    /// The same merges an execution goes through decide them, so this
+    /// connection's defaults and the daemon's limits apply.
+    ExplainPolicy { request: ExecuteRequest },
+    /// The latest points at which the daemon's effective policy changed,
+    /// oldest first, answered with [`Response::PolicyChanges`]
+    PolicyChanges,
+    /// Hand the listening socket and staged uploads to the daemon process
+    /// asking, then stop accepting, finish the requests in flight and exit
+    ///
+    /// Only the daemon's own user or root may ask, over msgpack. Answered
+    /// with [`Response::Handover`], whose frame carries the descriptors,
+    /// then [`Response::HandoverProgress`] until the old daemon's last
+    /// connection closes. Workers are not handed over: the new daemon
+    /// brings its own, and the old one's exit with it.
+    Handover,
+}
+
+/// What a daemon is and what it supports, sent in [`Response::Hello`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    /// Daemon release, such as `0.1.0`
+    pub version: String,
+    /// [`PROTOCOL_VERSION`] the daemon speaks
+    pub protocol_version: u32,
+    /// Supported [`feature`]s
+    pub features: BTreeSet<String>,
+    /// Interpreters installed for [`ExecuteRequest::interpreter`], by wire
+    /// name (`python`, `sh`, `bash`)
+    pub languages: Vec<String>,
+    /// Limits requests are held to
+    pub limits: Limits,
+    /// Changes every time the daemon starts, so a client can tell a
+    /// restart from a reconnect
+    pub boot_id: String,
+    /// Commit, toolchain and features the daemon was built with; `None`
+    /// from daemons that predate it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildInfo>,
+    /// Release of the kernel the daemon runs on, as in `uname -r`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+}
+
+impl DaemonInfo {
+    /// Whether the daemon advertises `feature`
+    #[must_use]
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Whether the daemon can run code in `interpreter`
+    #[must_use]
+    pub fn speaks(&self, interpreter: Interpreter) -> bool {
+        self.languages
+            .iter()
+            .any(|name| name == interpreter.wire_name())
+    }
+
+    /// Advertised names `request` needs but the daemon lacks: features, and
+    /// the interpreter if it is not installed
+    #[must_use]
+    pub fn unsupported(&self, request: &ExecuteRequest) -> Vec<&'static str> {
+        let mut missing: Vec<_> = request
+            .required_features()
+            .into_iter()
+            .filter(|name| !self.supports(name))
+            .collect();
+        if !self.speaks(request.interpreter) {
+            missing.push(request.interpreter.wire_name());
+        }
+        missing
+    }
+}
+
+/// Limits a daemon holds requests to, in [`DaemonInfo::limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Largest encoded message, in bytes
+    pub max_message_bytes: usize,
+    /// Largest code payload, in bytes
+    pub max_code_bytes: usize,
+    /// Largest request the fast path runs inline, if it is enabled
+    pub fast_path_max_bytes: Option<usize>,
+    /// Timeout of requests that set none, in milliseconds
+    pub default_timeout_ms: u64,
+    /// Longest timeout a request may set, in milliseconds; longer ones are
+    /// cut to it
+    #[serde(default)]
+    pub max_timeout_ms: Option<u64>,
+    /// Memory limit of requests that set none
+    pub default_memory_limit: Option<ByteSize>,
+    /// Size of the sandbox `/tmp`
+    #[serde(rename = "tmp_size_bytes")]
+    pub tmp_size: ByteSize,
+    /// How long before an unanswered request is given up on (0 = never)
+    #[serde(rename = "max_request_wall_secs")]
+    pub max_request_wall: DurationSecs,
+    /// Workers in the pool
+    pub workers: usize,
+    /// Executions one connection may have in flight (0 = no limit)
+    #[serde(default)]
+    pub max_inflight_per_connection: usize,
+    /// Executions one client uid may have in flight across all its
+    /// connections (0 = no limit)
+    #[serde(default)]
+    pub max_inflight_per_peer_uid: usize,
+    /// What one client uid may have staged in uploads (0 = no uploads)
+    #[serde(default, rename = "upload_quota_bytes")]
+    pub upload_quota: ByteSize,
+    /// How long an untouched upload is kept
+    #[serde(default, rename = "upload_ttl_secs")]
+    pub upload_ttl: DurationSecs,
+    /// Every size the daemon holds requests and output to; its
+    /// `max_request` and `max_code` are also in `max_message_bytes` and
+    /// `max_code_bytes`, for clients that predate it
+    #[serde(default)]
+    pub sizes: SizeLimits,
+}
+
+/// Sizes a daemon holds requests and their output to, in
+/// [`Limits::sizes`]
+///
+/// The daemon checks requests against the same struct it advertises, so a
+/// client can [`check`](Self::check) a request before sending it and be
+/// refused just as the daemon would refuse it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeLimits {
+    /// Largest encoded request
+    #[serde(rename = "max_request_bytes")]
+    pub max_request: ByteSize,
+    /// Largest code payload
+    #[serde(rename = "max_code_bytes")]
+    pub max_code: ByteSize,
+    /// Largest input file sent inline; uploads are held to the upload quota
+    /// instead
+    #[serde(rename = "max_file_bytes")]
+    pub max_file: ByteSize,
+    /// Largest the inline input files may be together
+    #[serde(rename = "max_files_bytes")]
+    pub max_files: ByteSize,
+    /// Largest stdin
+    #[serde(rename = "max_stdin_bytes")]
+    pub max_stdin: ByteSize,
+    /// Largest output, stdout and stderr together; anything past it is cut
+    /// off and the result's `stdout_truncated` or `stderr_truncated` set
+    #[serde(rename = "max_output_bytes")]
+    pub max_output: ByteSize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        let inline = ByteSize::from_bytes(MAX_CODE_SIZE as u64);
+        Self {
+            max_request: ByteSize::from_bytes(MAX_MESSAGE_SIZE as u64),
+            max_code: inline,
+            max_file: inline,
+            max_files: inline,
+            max_stdin: inline,
+            max_output: ByteSize::from_bytes(MAX_OUTPUT_SIZE as u64),
+        }
+    }
+}
+
+impl SizeLimits {
+    /// These limits, with the request and output sizes held to what the
+    /// protocol can carry
+    #[must_use]
+    pub fn within_protocol(self) -> Self {
+        Self {
+            max_request: self
+                .max_request
+                .min(ByteSize::from_bytes(MAX_MESSAGE_SIZE as u64)),
+            max_output: self
+                .max_output
+                .min(ByteSize::from_bytes(MAX_OUTPUT_SIZE as u64)),
+            ..self
+        }
+    }
+
+    /// Refuse `request` with [`LeewardError::Refused`] of kind
+    /// [`ErrorKind::TooLarge`] if its code, an inline input file, the
+    /// inline files together or its stdin is over its limit
+    ///
+    /// The message names the limit, so whoever sent the request knows how
+    /// much to trim.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Refused`] for the first limit exceeded.
+    pub fn check(&self, request: &ExecuteRequest) -> crate::Result<()> {
+        let code = request.code.as_ref().map_or(0, String::len);
+        check_size(SizeLimit::Code, "code", code, self.max_code)?;
+        let mut files = 0usize;
+        for (name, data) in &request.files {
+            check_size(
+                SizeLimit::File,
+                &format!("input file {name}"),
+                data.len(),
+                self.max_file,
+            )?;
+            files = files.saturating_add(data.len());
+        }
+        check_size(SizeLimit::Files, "input files", files, self.max_files)?;
+        let stdin = request.stdin.as_ref().map_or(0, Vec::len);
+        check_size(SizeLimit::Stdin, "stdin", stdin, self.max_stdin)
+    }
+
+    /// Refuse a request of `len` encoded bytes over
+    /// [`max_request`](Self::max_request), with [`ErrorKind::Malformed`]
+    /// as the daemon, which cannot read it, does
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Refused`] if `len` is over the limit.
+    pub fn check_request_len(&self, len: usize) -> crate::Result<()> {
+        if len as u64 <= self.max_request.bytes() {
+            return Ok(());
+        }
+        Err(LeewardError::Refused {
+            kind: ErrorKind::Malformed,
+            message: format!(
+                "request of {len} bytes exceeds the {} byte message size limit",
+                self.max_request.bytes()
+            ),
+        })
+    }
+}
+
+/// Refuse `len` bytes of `what` over `max`
+fn check_size(limit: SizeLimit, what: &str, len: usize, max: ByteSize) -> crate::Result<()> {
+    let bytes = len as u64;
+    if bytes <= max.bytes() {
+        return Ok(());
+    }
+    Err(LeewardError::Refused {
+        kind: ErrorKind::TooLarge {
+            limit,
+            bytes,
+            max_bytes: max.bytes(),
+        },
+        message: format!(
+            "{what} is {bytes} bytes, over the daemon's limit of {} bytes",
+            max.bytes()
+        ),
+    })
+}
+
+/// Which of [`SizeLimits`] refused a request, in [`ErrorKind::TooLarge`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeLimit {
+    Code,
+    /// One inline input file
+    File,
+    /// The inline input files together
+    Files,
+    Stdin,
+}
+
+impl std::fmt::Display for SizeLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Code => "code",
+            Self::File => "file",
+            Self::Files => "files",
+            Self::Stdin => "stdin",
+        })
+    }
+}
+
+/// A shared memory slot pair leased to a connection, in
+/// [`Response::ShmSlot`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmLease {
+    pub slot_id: u32,
+    /// Changes whenever the slot is given back; present it with the id
+    pub generation: u32,
+    /// Where in the region the code goes, after its length as a native
+    /// `u32`
+    pub request_offset: u64,
+    pub request_len: u64,
+    /// Where in the region the response slot starts
+    pub response_offset: u64,
+    pub response_len: u64,
+    /// Length of the whole region, for mapping it
+    pub region_len: u64,
+}
+
+/// What a daemon does with the output of an execution that does not fit
+/// its shared memory response slot
+///
+/// Whichever it does is listed in [`ExecuteResponse::adjustments`] as
+/// `shm_overflow`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShmOverflowPolicy {
+    /// Fail the execution
+    FailRequest,
+    /// Keep what fits, stdout first, and set the result's
+    /// `stdout_truncated` and `stderr_truncated`
+    TruncateWithFlag,
+    /// Send each stream that does not fit in the socket response instead,
+    /// as [`PayloadRef::Inline`]
+    #[default]
+    FallbackToSocket,
+}
+
+impl ShmOverflowPolicy {
+    /// Every policy
+    pub const ALL: [Self; 3] = [
+        Self::FailRequest,
+        Self::TruncateWithFlag,
+        Self::FallbackToSocket,
+    ];
+
+    /// Name on the wire and in the environment, such as `fail_request`
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::FailRequest => "fail_request",
+            Self::TruncateWithFlag => "truncate_with_flag",
+            Self::FallbackToSocket => "fallback_to_socket",
+        }
+    }
+}
+
+impl std::fmt::Display for ShmOverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for ShmOverflowPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name() == name)
+            .ok_or_else(|| {
+                let known: Vec<_> = Self::ALL.iter().map(|policy| policy.name()).collect();
+                format!(
+                    "unknown overflow policy '{name}'; known policies are {}",
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Where one output stream of a shared memory execution is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadRef {
+    /// `len` bytes at `offset` into what the response slot holds, after its
+    /// length
+    Slot { offset: u64, len: u64 },
+    /// In the socket response, where it would be without a slot
+    Inline,
+}
+
+/// Where stdout and stderr went, in [`ExecuteResponse::shm_payload`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmPayload {
+    pub stdout: PayloadRef,
+    pub stderr: PayloadRef,
+}
+
+impl ShmPayload {
+    /// Put the streams kept in the slot back into `result`, given what the
+    /// slot holds; `false`, leaving `result` alone, if a reference points
+    /// past it
+    pub fn restore(&self, slot: &[u8], result: &mut ExecutionResult) -> bool {
+        let part = |reference: PayloadRef| match reference {
+            PayloadRef::Slot { offset, len } => {
+                let start = usize::try_from(offset).ok()?;
+                let end = start.checked_add(usize::try_from(len).ok()?)?;
+                slot.get(start..end).map(|bytes| Some(bytes.to_vec()))
+            }
+            PayloadRef::Inline => Some(None),
+        };
+        let (Some(stdout), Some(stderr)) = (part(self.stdout), part(self.stderr)) else {
+            return false;
+        };
+        if let Some(stdout) = stdout {
+            result.stdout = stdout;
+        }
+        if let Some(stderr) = stderr {
+            result.stderr = stderr;
+        }
+        true
+    }
+}
+
+/// How many shared memory slots are leased, in
+/// [`Response::StatusDetailed`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmOccupancy {
+    pub leased: usize,
+    /// Slots in the region, 0 if the daemon has none
+    pub total: usize,
+    /// Leases taken back because their connection closed or they sat
+    /// unused, since the daemon started
+    pub reclaimed: u64,
+}
+
+/// Byte range `[start, end)` of an upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Where an upload stands, in [`Response::Upload`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadStatus {
+    /// Id to send chunks to and reference from `ExecuteRequest.uploads`
+    pub id: u64,
+    pub name: String,
+    pub total_len: u64,
+    pub received_bytes: u64,
+    /// Ranges not received yet, in order
+    pub missing: Vec<UploadRange>,
+    /// Whether the upload passed its hash check and can be used
+    pub committed: bool,
+}
+
+/// Executions one client uid has in flight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInflight {
+    pub uid: u32,
+    pub inflight: usize,
+}
+
+/// Which in-flight limit turned an execution away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InflightScope {
+    /// Executions on the connection the request came in on
+    Connection,
+    /// Executions from every connection of the same uid
+    PeerUid,
+}
+
+impl std::fmt::Display for InflightScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Connection => "connection",
+            Self::PeerUid => "peer_uid",
+        })
+    }
+}
+
+/// Snapshot of a single worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerInfo {
+    /// Worker index in the pool
+    pub id: u32,
+    /// Uid of the running process, or of the last one; the index is kept
+    /// across respawns, the uid never
+    #[serde(default)]
+    pub uid: Option<WorkerUid>,
+    /// Process ID, if running
+    pub pid: Option<i32>,
+    /// Current state
+    pub state: WorkerState,
+    /// Executions since the last (re)spawn
+    pub execution_count: u64,
+    /// Timing breakdown of the most recent execution
+    pub last_timing: Option<WorkerTiming>,
+    /// Fingerprint of the config the worker was spawned with
+    #[serde(default)]
+    pub config_fingerprint: String,
+    /// Interpreter binary the worker was spawned from, if it could be read
+    #[serde(default)]
+    pub interpreter: Option<InterpreterStamp>,
+    /// Marked by a drain and not yet recycled
+    #[serde(default)]
+    pub drain_pending: bool,
+    /// Syscalls refused to the worker's code, by name, since it was added
+    /// to the pool
+    #[serde(default)]
+    pub denied_syscalls: BTreeMap<String, u64>,
+}
+
+/// A worker's slot in the pool and the process in it, from
+/// [`Response::StatusDetailed`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerSlot {
+    /// Worker index in the pool
+    pub id: u32,
+    /// Uid of the process in the slot, if one was ever spawned
+    pub uid: Option<WorkerUid>,
+}
+
+/// Identity of an interpreter binary on disk, to spot it being replaced
+///
+/// Replacing the file, as package upgrades do, changes the inode; editing
+/// it in place changes the size or modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterpreterStamp {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    /// Modification time, in nanoseconds since the Unix epoch
+    pub mtime_ns: i64,
+}
+
+impl InterpreterStamp {
+    /// Stamp of the file `path` leads to, or `None` if it cannot be read
+    #[must_use]
+    pub fn of(path: &Path) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime_ns: metadata
+                .mtime()
+                .saturating_mul(1_000_000_000)
+                .saturating_add(metadata.mtime_nsec()),
+        })
+    }
+}
+
+/// Profile named by [`Request::DrainProfile`] for the daemon's only pool
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Answer to [`Request::DrainProfile`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainStatus {
+    pub profile: String,
+    /// Workers marked for recycling
+    pub scheduled: usize,
+    /// Of those, workers not yet recycled; [`Response::Status`] tracks the
+    /// rest of the drain
+    pub pending: usize,
+    /// Whether the root template was rebuilt for the new workers
+    pub template_rebuilt: bool,
+}
+
+/// State a daemon hands to its successor in [`Response::Handover`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandoverState {
+    /// Process id of the daemon handing over
+    pub pid: u32,
+    /// Staged uploads, in the order of their files among the descriptors
+    /// after the listener
+    pub uploads: Vec<HandedUpload>,
+}
+
+/// An upload handed over along with its staging file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandedUpload {
+    pub id: u64,
+    /// Client uid that began it
+    pub owner: Option<u32>,
+    pub name: String,
+    pub sha256: String,
+    pub total_len: u64,
+    pub reusable: bool,
+    /// Ranges received so far, in order
+    pub received: Vec<UploadRange>,
+    pub committed: bool,
+}
+
+/// A daemon's side of a handover in progress, in [`Response::Status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandoverStatus {
+    pub role: HandoverRole,
+    /// Process id of the other daemon, if known
+    pub peer_pid: Option<u32>,
+    /// Connections the old daemon still has open; it exits, along with its
+    /// workers, once the last one closes
+    pub predecessor_connections: usize,
+}
+
+/// Which side of a handover a daemon is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandoverRole {
+    /// Took over the socket; new connections come here
+    Successor,
+    /// Handed the socket over and is finishing its requests
+    Predecessor,
+}
+
+/// Category of a daemon [`Event`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A pool health threshold was crossed or cleared
+    Alert,
+    /// A worker process died while starting, serving an execution or idle
+    WorkerDied,
+    /// A worker process exited on its own after saying goodbye, and was
+    /// replaced
+    WorkerExited,
+    /// A batch execution was frozen to make room for other work
+    ExecutionFrozen,
+    /// A frozen batch execution was resumed
+    ExecutionThawed,
+    /// The daemon's effective policy changed
+    PolicyChange,
+}
+
+/// Pool condition watched by an alert threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Requests waiting for a worker
+    QueueDepth,
+    /// Time the oldest waiting request has been queued, in ms
+    QueueWait,
+    /// Workers in the `Dead` state
+    WorkerDead,
+    /// Workers still running an interpreter binary that has been replaced
+    /// on disk; draining the pool picks up the new one
+    InterpreterChanged,
+    /// A daemon task kept panicking and was left stopped, putting the
+    /// daemon in maintenance; the value is how many times in a row
+    TaskFailing,
+}
+
+impl AlertKind {
+    /// Every alert kind
+    pub const ALL: [Self; 5] = [
+        Self::QueueDepth,
+        Self::QueueWait,
+        Self::WorkerDead,
+        Self::InterpreterChanged,
+        Self::TaskFailing,
+    ];
+
+    /// Label used in logs and metrics
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::QueueDepth => "queue_depth",
+            Self::QueueWait => "queue_wait",
+            Self::WorkerDead => "worker_dead",
+            Self::InterpreterChanged => "interpreter_changed",
+            Self::TaskFailing => "task_failing",
+        }
+    }
+}
+
+impl std::fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether an alert started or ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    /// The value reached the threshold
+    Firing,
+    /// The value stayed below the threshold long enough to clear
+    Resolved,
+}
+
+/// Details of an [`EventKind::Alert`] event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub alert: AlertKind,
+    pub state: AlertState,
+    /// Sampled value that caused the transition
+    pub value: u64,
+    pub threshold: u64,
+}
+
+/// Details of an [`EventKind::WorkerDied`] event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerDiedEvent {
+    pub worker_id: u32,
+    /// What the worker reported before dying, if it got the chance
+    pub death: Option<WorkerDeath>,
+}
+
+/// Details of an [`EventKind::WorkerExited`] event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerExitedEvent {
+    pub worker_id: u32,
+    /// What the worker said it was leaving for
+    pub reason: GoodbyeReason,
+}
+
+/// Details of an [`EventKind::ExecutionFrozen`] or
+/// [`EventKind::ExecutionThawed`] event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreemptionEvent {
+    pub worker_id: u32,
+    /// Request id the daemon logs the execution under
+    pub execution_id: u64,
+    /// Times the execution has been frozen, this one included
+    pub preempted_count: u32,
+    /// Total time it has spent frozen so far, in ms
+    pub frozen_ms: u64,
+}
+
+/// Something that happened in the daemon, pushed to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// Human-readable summary
+    pub message: String,
+    /// Set for [`EventKind::Alert`]
+    #[serde(default)]
+    pub alert: Option<AlertEvent>,
+    /// Set for [`EventKind::WorkerDied`]
+    #[serde(default)]
+    pub worker_died: Option<WorkerDiedEvent>,
+    /// Set for [`EventKind::WorkerExited`]
+    #[serde(default)]
+    pub worker_exited: Option<WorkerExitedEvent>,
+    /// Set for [`EventKind::ExecutionFrozen`] and [`EventKind::ExecutionThawed`]
+    #[serde(default)]
+    pub preemption: Option<PreemptionEvent>,
+    /// Set for [`EventKind::PolicyChange`]
+    #[serde(default)]
+    pub policy_change: Option<PolicyChange>,
+}
+
+impl Event {
+    /// Event for an alert transition, stamped with the current time
+    #[must_use]
+    pub fn alert(alert: AlertEvent) -> Self {
+        let message = match alert.state {
+            AlertState::Firing if alert.alert == AlertKind::InterpreterChanged => format!(
+                "{} workers run an interpreter that has changed on disk; drain the pool to pick \
+                 up the new one",
+                alert.value
+            ),
+            AlertState::Firing => format!(
+                "{} at {}, threshold {}",
+                alert.alert, alert.value, alert.threshold
+            ),
+            AlertState::Resolved => format!(
+                "{} back below threshold {} (now {})",
+                alert.alert, alert.threshold, alert.value
+            ),
+        };
+
+        Self {
+            kind: EventKind::Alert,
+            timestamp_ms: now_ms(),
+            message,
+            alert: Some(alert),
+            worker_died: None,
+            worker_exited: None,
+            preemption: None,
+            policy_change: None,
+        }
+    }
+
+    /// Event for a worker that died with `error`, stamped with the current time
+    #[must_use]
+    pub fn worker_died(worker_id: u32, error: &LeewardError) -> Self {
+        let death = match error {
+            LeewardError::WorkerDied(death) => Some(death.clone()),
+            _ => None,
+        };
+
+        Self {
+            kind: EventKind::WorkerDied,
+            timestamp_ms: now_ms(),
+            message: format!("worker {worker_id}: {error}"),
+            alert: None,
+            worker_died: Some(WorkerDiedEvent { worker_id, death }),
+            worker_exited: None,
+            preemption: None,
+            policy_change: None,
+        }
+    }
+
+    /// Event for a worker that exited on its own for `reason`, stamped with
+    /// the current time
+    #[must_use]
+    pub fn worker_exited(worker_id: u32, reason: GoodbyeReason) -> Self {
+        Self {
+            kind: EventKind::WorkerExited,
+            timestamp_ms: now_ms(),
+            message: format!("worker {worker_id} exited: {reason}"),
+            alert: None,
+            worker_died: None,
+            worker_exited: Some(WorkerExitedEvent { worker_id, reason }),
+            preemption: None,
+            policy_change: None,
+        }
+    }
+
+    /// Event for a batch execution frozen (`frozen`) or thawed, stamped
+    /// with the current time
+    #[must_use]
+    pub fn preemption(preemption: PreemptionEvent, frozen: bool) -> Self {
+        let PreemptionEvent {
+            worker_id,
+            execution_id,
+            preempted_count,
+            frozen_ms,
+        } = preemption;
+        let (kind, message) = if frozen {
+            (
+                EventKind::ExecutionFrozen,
+                format!(
+                    "execution {execution_id} on worker {worker_id} \
+                     frozen ({preempted_count} so far)"
+                ),
+            )
+        } else {
+            (
+                EventKind::ExecutionThawed,
+                format!(
+                    "execution {execution_id} on worker {worker_id} \
+                     thawed after {frozen_ms} ms frozen in all"
+                ),
+            )
+        };
+
+        Self {
+            kind,
+            timestamp_ms: now_ms(),
+            message,
+            alert: None,
+            worker_died: None,
+            worker_exited: None,
+            preemption: Some(preemption),
+            policy_change: None,
+        }
+    }
+
+    /// Event for `change`, stamped with the time of the change
+    #[must_use]
+    pub fn policy_change(change: PolicyChange) -> Self {
+        let message = match &change.fingerprint_before {
+            Some(before) => format!(
+                "policy changed by {}: {} field(s), fingerprint {before} -> {}",
+                change.actor,
+                change.changes.len(),
+                change.fingerprint_after
+            ),
+            None => format!(
+                "policy at {}: fingerprint {}",
+                change.actor, change.fingerprint_after
+            ),
+        };
+
+        Self {
+            kind: EventKind::PolicyChange,
+            timestamp_ms: change.timestamp_ms,
+            message,
+            alert: None,
+            worker_died: None,
+            worker_exited: None,
+            preemption: None,
+            policy_change: Some(change),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch
+pub(crate) fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
 }
 
 /// Response types
@@ -61,24 +1780,394 @@ pub enum Request {
 pub enum Response {
     /// Execution result
     Execute(ExecuteResponse),
+    /// A detached execution was accepted; fetch its result with
+    /// [`Request::FetchResult`]
+    Detached { execution_id: u64 },
     /// Pool status
     Status {
         total: usize,
         idle: usize,
         busy: usize,
+        /// Workers whose config fingerprint differs from the current one
+        #[serde(default)]
+        stale: usize,
+        /// Workers still to be recycled by a drain
+        #[serde(default)]
+        draining: usize,
+        /// Workers recycled by drains since the daemon started
+        #[serde(default)]
+        drained: u64,
+        /// Set while an old and a new daemon overlap during a handover
+        #[serde(default)]
+        handover: Option<HandoverStatus>,
+        /// How old the oldest of these counts is; more than a few seconds
+        /// means a pool lock or the daemon's refresher is stuck
+        #[serde(default)]
+        snapshot_age_ms: u64,
+    },
+    /// Pool status with executions in flight per client
+    StatusDetailed {
+        total: usize,
+        /// Workers claimed for an execution
+        busy: usize,
+        dead: usize,
+        /// How old the oldest of the worker counts is
+        #[serde(default)]
+        snapshot_age_ms: u64,
+        /// Each worker's pool index and process uid
+        #[serde(default)]
+        workers: Vec<WorkerSlot>,
+        /// Clients with executions in flight, by uid
+        inflight: Vec<PeerInflight>,
+        max_inflight_per_connection: usize,
+        max_inflight_per_peer_uid: usize,
+        /// Log filter in effect, `None` if it cannot be changed
+        #[serde(default)]
+        log_filter: Option<String>,
+        /// Debug flags turned on
+        #[serde(default)]
+        debug_flags: Vec<DebugFlag>,
+        /// Shared memory slots leased to clients
+        #[serde(default)]
+        shm_slots: ShmOccupancy,
+        /// Workers held back for high-priority requests, which `total`,
+        /// `busy` and `dead` leave out
+        #[serde(default)]
+        standby: usize,
+    },
+    /// Per-worker details
+    WorkerList {
+        workers: Vec<WorkerInfo>,
+        /// Fingerprint of the pool's current config
+        #[serde(default)]
+        current_fingerprint: String,
+    },
+    /// Number of stale workers queued for recycling
+    RecycleStale { scheduled: usize },
+    /// Progress of the drain just requested
+    Drain(DrainStatus),
+    /// Log filter and debug flags after a change
+    LogSettings {
+        /// Log filter in effect, `None` if it cannot be changed
+        filter: Option<String>,
+        /// Debug flags turned on
+        debug_flags: Vec<DebugFlag>,
     },
+    /// Subscription accepted; [`Response::Event`]s follow
+    Subscribed,
+    /// Event pushed to a subscribed connection
+    Event(Event),
     /// Pong
     Pong,
+    /// Capacity of the pool, answering [`Request::Health`]
+    Health {
+        healthy: bool,
+        idle: usize,
+        queue_depth: usize,
+        /// How long a request sent now would likely wait for a worker,
+        /// from the waits of requests that recently queued
+        est_wait_ms: u64,
+    },
+    /// Who the daemon is and what it supports
+    Hello(DaemonInfo),
+    /// State of an upload after a begin, chunk or commit
+    Upload(UploadStatus),
+    /// This connection's defaults as stored, and how they differ from what
+    /// [`Request::SetDefaults`] asked for
+    Defaults {
+        defaults: ExecuteDefaults,
+        adjustments: Vec<Adjustment>,
+    },
+    /// Settings an execution would run with, each with where it came from,
+    /// answering [`Request::ExplainPolicy`]
+    Policy { fields: Vec<PolicyField> },
+    /// Points at which the effective policy changed, oldest first,
+    /// answering [`Request::PolicyChanges`]
+    PolicyChanges { changes: Vec<PolicyChange> },
+    /// State for the new daemon; the frame carries the listening socket
+    /// and then each upload's file as `SCM_RIGHTS`
+    Handover(HandoverState),
+    /// Connections the old daemon still has open, sent as they close
+    HandoverProgress { connections: usize },
+    /// A slot leased with [`Request::ShmSetup`]; the frame carries the
+    /// region's memfd as `SCM_RIGHTS`
+    ShmSlot(ShmLease),
+    /// The slot was given back
+    ShmFreed { slot_id: u32 },
     /// Error
-    Error { message: String },
+    Error {
+        message: String,
+        #[serde(default)]
+        kind: ErrorKind,
+    },
+}
+
+impl Response {
+    /// Error answering a single request; the connection stays usable
+    #[must_use]
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+            kind: ErrorKind::Request,
+        }
+    }
+}
+
+/// Why the daemon sent a [`Response::Error`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The request failed
+    #[default]
+    Request,
+    /// The request could not be decoded; the connection stays usable
+    /// unless its frame could not be read either
+    Malformed,
+    /// The connection sat idle too long and is being closed
+    IdleTimeout,
+    /// The daemon handed its socket to a new process and is closing this
+    /// connection; reconnect to reach the new one
+    HandedOver,
+    /// The client already has as many executions in flight as `scope`
+    /// allows; retry once one of them finishes
+    Busy {
+        scope: InflightScope,
+        current_inflight: usize,
+        limit: usize,
+    },
+    /// The upload would take the client's staged uploads past its quota,
+    /// or its kept detached results already fill theirs; use, finish,
+    /// abandon or fetch some first
+    QuotaExceeded { used_bytes: u64, quota_bytes: u64 },
+    /// The detached execution has not finished yet; fetch it again later
+    Pending,
+    /// The daemon's code screening refused the execution before it ran;
+    /// the message says which rule and why
+    PolicyRejected,
+    /// The daemon is in maintenance after one of its own tasks kept
+    /// failing and runs nothing until restarted; the message says why
+    Maintenance,
+    /// Part of the request is over one of the daemon's [`SizeLimits`]; the
+    /// message says which and by how much
+    TooLarge {
+        limit: SizeLimit,
+        bytes: u64,
+        max_bytes: u64,
+    },
+    /// The daemon failed to answer within the request deadline; a bug
+    Internal {
+        /// Last stage the request was seen in
+        stage: RequestStage,
+    },
+}
+
+/// How far the daemon got with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestStage {
+    /// Read, not yet handed to the pool
+    Received,
+    /// Waiting for a free worker
+    Queued,
+    /// Sent to a worker, waiting for its result
+    Running { worker_id: u32 },
+    /// Running in an observed worker of its own for a profile
+    Profiling,
+    /// Running in a worker of its own under the debug profile
+    Debugging,
+}
+
+impl std::fmt::Display for RequestStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Received => f.write_str("received"),
+            Self::Queued => f.write_str("queued"),
+            Self::Running { worker_id } => write!(f, "running on worker {worker_id}"),
+            Self::Profiling => f.write_str("profiling"),
+            Self::Debugging => f.write_str("debugging"),
+        }
+    }
 }
 
 /// Encode a message to msgpack
-pub fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
-    rmp_serde::to_vec(msg)
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `msg` cannot be serialized.
+pub fn encode<T: Serialize>(msg: &T) -> crate::Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec(msg)?)
+}
+
+/// Encode a message to msgpack at the end of `out`, reusing its capacity
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `msg` cannot be serialized.
+pub fn encode_into<T: Serialize>(msg: &T, out: &mut Vec<u8>) -> crate::Result<()> {
+    Ok(rmp_serde::encode::write(out, msg)?)
 }
 
 /// Decode a message from msgpack
-pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, rmp_serde::decode::Error> {
-    rmp_serde::from_slice(data)
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `data` is not a valid `T`.
+pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> crate::Result<T> {
+    Ok(rmp_serde::from_slice(data)?)
+}
+
+/// Encode a message as a single line of JSON, without the trailing newline
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `msg` cannot be serialized.
+pub fn encode_json<T: Serialize>(msg: &T) -> crate::Result<Vec<u8>> {
+    serde_json::to_vec(msg).map_err(|e| LeewardError::Protocol {
+        direction: Direction::Encode,
+        source: e.into(),
+    })
+}
+
+/// Encode a message as JSON at the end of `out`, reusing its capacity
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `msg` cannot be serialized.
+pub fn encode_json_into<T: Serialize>(msg: &T, out: &mut Vec<u8>) -> crate::Result<()> {
+    serde_json::to_writer(out, msg).map_err(|e| LeewardError::Protocol {
+        direction: Direction::Encode,
+        source: e.into(),
+    })
+}
+
+/// Decode a message from a line of JSON
+///
+/// # Errors
+///
+/// [`LeewardError::Protocol`] if `data` is not a valid `T`.
+pub fn decode_json<'a, T: Deserialize<'a>>(data: &'a [u8]) -> crate::Result<T> {
+    serde_json::from_slice(data).map_err(|e| LeewardError::Protocol {
+        direction: Direction::Decode,
+        source: e.into(),
+    })
+}
+
+/// Serde helpers for byte fields: base64 strings in human-readable formats
+/// such as JSON, plain byte sequences in msgpack so that wire stays unchanged
+pub(crate) mod binary {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Borrowed bytes serialized by format
+    struct Bytes<'a>(&'a [u8]);
+
+    impl Serialize for Bytes<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if serializer.is_human_readable() {
+                serializer.serialize_str(&STANDARD.encode(self.0))
+            } else {
+                self.0.serialize(serializer)
+            }
+        }
+    }
+
+    /// Owned bytes from either a base64 string or a byte sequence
+    ///
+    /// Decided by what the input holds rather than `is_human_readable()`,
+    /// which internally tagged enums report as `true` even for msgpack.
+    struct ByteBuf(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for ByteBuf {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(ByteBufVisitor)
+        }
+    }
+
+    struct ByteBufVisitor;
+
+    impl<'de> Visitor<'de> for ByteBufVisitor {
+        type Value = ByteBuf;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("a base64 string or a sequence of bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteBuf, E> {
+            STANDARD.decode(v).map(ByteBuf).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+            Ok(ByteBuf(v.to_vec()))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+            Ok(ByteBuf(v))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(ByteBuf(bytes))
+        }
+    }
+
+    /// `Vec<u8>`
+    pub mod vec {
+        use super::{ByteBuf, Bytes};
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            Bytes(bytes).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            ByteBuf::deserialize(deserializer).map(|buf| buf.0)
+        }
+    }
+
+    /// `Option<Vec<u8>>`
+    pub mod option {
+        use super::{ByteBuf, Bytes};
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        #[allow(clippy::ref_option)]
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            bytes.as_deref().map(Bytes).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            Option::<ByteBuf>::deserialize(deserializer).map(|buf| buf.map(|buf| buf.0))
+        }
+    }
+
+    /// `Vec<(String, Vec<u8>)>`, as used for input files
+    pub mod files {
+        use super::{ByteBuf, Bytes};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            files: &[(String, Vec<u8>)],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(files.iter().map(|(name, bytes)| (name, Bytes(bytes))))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<(String, Vec<u8>)>, D::Error> {
+            let files = Vec::<(String, ByteBuf)>::deserialize(deserializer)?;
+            Ok(files.into_iter().map(|(name, buf)| (name, buf.0)).collect())
+        }
+    }
 }
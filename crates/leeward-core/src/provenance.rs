@@ -0,0 +1,86 @@
+//! Where a binary came from: release, commit, build time, toolchain and
+//! features
+//!
+//! The binary crates embed it at build time from a build script that
+//! includes `build/provenance.rs` from this crate, and read it back with
+//! [`build_info!`](crate::build_info). Outside a git checkout the commit is
+//! `unknown` unless the packager sets `LEEWARD_GIT_DESCRIBE`.
+
+#[cfg(feature = "protocol")]
+use serde::{Deserialize, Serialize};
+
+/// Provenance of one binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+pub struct BuildInfo {
+    /// Package release, such as `0.1.0`
+    pub version: String,
+    /// `git describe --tags --always --dirty` of the tree it was built from
+    pub git: String,
+    /// Build time, as RFC 3339 UTC (`SOURCE_DATE_EPOCH` when set)
+    pub built_at: String,
+    /// `rustc --version` of the compiler
+    pub rustc: String,
+    /// Target triple
+    pub target: String,
+    /// Cargo profile, `debug` or `release`
+    pub profile: String,
+    /// Cargo features of the binary's own crate, then those of
+    /// `leeward-core` as `leeward-core/<name>`
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    /// Assemble from the values [`build_info!`](crate::build_info) reads at
+    /// the call site
+    #[doc(hidden)]
+    #[must_use]
+    pub fn from_parts(parts: [&str; 7]) -> Self {
+        let [version, git, built_at, rustc, target, profile, features] = parts.map(str::to_owned);
+        Self {
+            version,
+            git,
+            built_at,
+            rustc,
+            target,
+            profile,
+            features: features
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .chain(core_features().map(|name| format!("leeward-core/{name}")))
+                .collect(),
+        }
+    }
+}
+
+/// Features this build of `leeward-core` was compiled with
+pub fn core_features() -> impl Iterator<Item = &'static str> {
+    [
+        (cfg!(feature = "seccomp"), "seccomp"),
+        (cfg!(feature = "landlock"), "landlock"),
+        (cfg!(feature = "shm"), "shm"),
+        (cfg!(feature = "cgroups"), "cgroups"),
+        (cfg!(feature = "protocol"), "protocol"),
+        (cfg!(feature = "seccomp_validation"), "seccomp_validation"),
+    ]
+    .into_iter()
+    .filter_map(|(on, name)| on.then_some(name))
+}
+
+/// The [`BuildInfo`] of the calling crate, whose build script must emit
+/// the `LEEWARD_BUILD_*` variables
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::provenance::BuildInfo::from_parts([
+            env!("CARGO_PKG_VERSION"),
+            env!("LEEWARD_BUILD_GIT"),
+            env!("LEEWARD_BUILD_TIME"),
+            env!("LEEWARD_BUILD_RUSTC"),
+            env!("LEEWARD_BUILD_TARGET"),
+            env!("LEEWARD_BUILD_PROFILE"),
+            env!("LEEWARD_BUILD_FEATURES"),
+        ])
+    };
+}
@@ -1,34 +1,174 @@
 //! Execution result types
 
+use crate::LeewardError;
+use crate::isolation::fatal::Stage;
+#[cfg(feature = "protocol")]
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 /// Result of a sandboxed code execution
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ExecutionResult {
     /// Exit code of the process
     pub exit_code: i32,
 
     /// Standard output
+    #[cfg_attr(feature = "protocol", serde(with = "crate::protocol::binary::vec"))]
     pub stdout: Vec<u8>,
 
     /// Standard error
+    #[cfg_attr(feature = "protocol", serde(with = "crate::protocol::binary::vec"))]
     pub stderr: Vec<u8>,
 
+    /// Whether `stdout` was cut short to fit where it was sent
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub stdout_truncated: bool,
+
+    /// Whether `stderr` was cut short to fit where it was sent
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub stderr_truncated: bool,
+
     /// Execution duration
     pub duration: Duration,
 
-    /// Peak memory usage in bytes
+    /// Peak memory usage in bytes: of the worker's cgroup if it has one,
+    /// else the interpreter's resident set
     pub memory_peak: u64,
 
-    /// CPU time used in microseconds
+    /// CPU time used in microseconds: of the worker's cgroup if it has one,
+    /// else the interpreter's
     pub cpu_time_us: u64,
 
     /// Whether the process was killed due to timeout
     pub timed_out: bool,
 
-    /// Whether the process was killed due to memory limit
+    /// Which deadline killed it, when `timed_out`
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub killed_by: Option<KilledBy>,
+
+    /// Whether the kernel OOM-killed anything in the worker's cgroup; always
+    /// false for a worker without one
     pub oom_killed: bool,
+
+    /// Network usage (only when networking is enabled)
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub network: Option<NetworkUsage>,
+
+    /// Bytes in use on the workspace tmpfs when the code finished
+    ///
+    /// Measured by the worker, where the workspace and `/tmp` are tmpfs
+    /// mounts of its own; 0 when run outside one.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub workspace_bytes: u64,
+
+    /// Bytes in use on the `/tmp` tmpfs when the code finished, measured
+    /// like `workspace_bytes`
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub tmp_bytes: u64,
+
+    /// Times the program was frozen to make room for other work
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub preempted_count: u32,
+
+    /// Time spent frozen, which `duration` and the timeout leave out
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub frozen_duration: Duration,
+
+    /// Operations the sandbox refused the code, as seen by the supervisor
+    ///
+    /// Empty unless something was refused; see [`crate::denial`] for the
+    /// bounds.
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub denials: Vec<Denial>,
+
+    /// Whether the code ran under the debug profile, where its processes
+    /// could trace and read the memory of one another
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub debug: bool,
+
+    /// Whether the program was sent `SIGUSR1` to free memory before
+    /// reaching its limit; see
+    /// [`SandboxConfig::pressure_signal_percent`](crate::SandboxConfig::pressure_signal_percent)
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub pressure_signal_sent: bool,
+
+    /// Memory charged to the worker's cgroup when the signal was sent, in
+    /// bytes
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub pressure_signal_memory: Option<u64>,
+}
+
+/// Network usage of a single execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+pub struct NetworkUsage {
+    /// Sockets created or accepted
+    pub connections_opened: u32,
+    /// `connect` calls made on those sockets, not counted against
+    /// `max_connections`
+    #[cfg_attr(feature = "protocol", serde(default))]
+    pub connects: u32,
+    /// Bytes sent over non-loopback interfaces
+    pub bytes_sent: u64,
+    /// Bytes received over non-loopback interfaces
+    pub bytes_received: u64,
+}
+
+/// Operation the sandbox refused, with how often
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "protocol", derive(Serialize, Deserialize))]
+pub struct Denial {
+    /// Which layer refused it
+    pub layer: DenialLayer,
+    /// The syscall and its decisive argument, e.g. `connect(1.2.3.4:443)`,
+    /// or just the syscall (`connect(...)`) for denials past the cap
+    pub what: String,
+    /// Times it was refused
+    pub count: u64,
+}
+
+impl std::fmt::Display for Denial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.what)?;
+        if self.count > 1 {
+            write!(f, " x{}", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// What killed an execution that ran out of time
+///
+/// The worker enforces the timeout itself and, as a backstop, keeps a
+/// watchdog armed at the absolute deadline the daemon set plus a margin.
+/// Either way the result is `timed_out`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "protocol",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum KilledBy {
+    /// The worker's own timeout
+    Timeout,
+    /// The watchdog, because the timeout did not kill it in time
+    WorkerWatchdog,
+}
+
+/// Sandbox layer behind a [`Denial`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "protocol",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DenialLayer {
+    /// A seccomp notify supervisor failed the syscall
+    Seccomp,
+    /// Landlock refused a path, as reported by whoever brokered the access
+    Landlock,
 }
 
 impl ExecutionResult {
@@ -46,9 +186,34 @@ impl ExecutionResult {
 
     /// Check if execution was successful (exit code 0, no timeout, no OOM)
     #[must_use]
-    pub fn is_success(&self) -> bool {
+    pub const fn is_success(&self) -> bool {
         self.exit_code == 0 && !self.timed_out && !self.oom_killed
     }
+
+    /// Keep at most `max` bytes of output, stdout first, flagging each
+    /// stream that was cut; returns whether either was
+    pub fn truncate_output(&mut self, max: usize) -> bool {
+        let (stdout, stderr) = (self.stdout.len(), self.stderr.len());
+        let kept_stdout = stdout.min(max);
+        let kept_stderr = stderr.min(max - kept_stdout);
+        self.stdout.truncate(kept_stdout);
+        self.stderr.truncate(kept_stderr);
+        self.stdout_truncated |= kept_stdout < stdout;
+        self.stderr_truncated |= kept_stderr < stderr;
+        kept_stdout < stdout || kept_stderr < stderr
+    }
+
+    /// Exit code for this result under [`OutcomeCode`]
+    #[must_use]
+    pub const fn outcome(&self) -> OutcomeCode {
+        if self.timed_out {
+            OutcomeCode::Timeout
+        } else if self.oom_killed {
+            OutcomeCode::Killed
+        } else {
+            OutcomeCode::from_exit_status(self.exit_code)
+        }
+    }
 }
 
 impl Default for ExecutionResult {
@@ -57,11 +222,229 @@ impl Default for ExecutionResult {
             exit_code: -1,
             stdout: Vec::new(),
             stderr: Vec::new(),
+            stdout_truncated: false,
+            stderr_truncated: false,
             duration: Duration::ZERO,
             memory_peak: 0,
             cpu_time_us: 0,
             timed_out: false,
+            killed_by: None,
             oom_killed: false,
+            network: None,
+            workspace_bytes: 0,
+            tmp_bytes: 0,
+            preempted_count: 0,
+            frozen_duration: Duration::ZERO,
+            denials: Vec::new(),
+            debug: false,
+            pressure_signal_sent: false,
+            pressure_signal_memory: None,
+        }
+    }
+}
+
+/// Exit code for the outcome of a request
+///
+/// The one mapping from outcomes to numbers. `leeward exec` exits with it,
+/// and the C API's error codes use the same values:
+///
+/// | Code    | Outcome                                  |
+/// |---------|------------------------------------------|
+/// | 0–125   | the program's own exit status            |
+/// | 124     | timed out                                |
+/// | 125     | cancelled                                |
+/// | 126     | sandbox setup failed                     |
+/// | 127     | interpreter unavailable                  |
+/// | 137     | killed (out of memory or SIGKILL)        |
+/// | 200–255 | client or daemon infrastructure error    |
+///
+/// As with `timeout(1)`, a program that itself exits with 124 or 125 reads
+/// as a timeout or a cancellation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "protocol",
+    derive(Serialize, Deserialize),
+    serde(into = "u8", try_from = "u8")
+)]
+pub enum OutcomeCode {
+    /// The program exited with this status (0–125)
+    Exited(u8),
+    /// Killed at the timeout
+    Timeout,
+    /// Cancelled before it finished
+    Cancelled,
+    /// The sandbox could not be set up
+    SandboxSetup,
+    /// The interpreter is missing or cannot be executed
+    InterpreterUnavailable,
+    /// Killed by the memory limit or SIGKILL
+    Killed,
+    /// Invalid argument, such as a null pointer
+    InvalidArgument,
+    /// Text that is not valid UTF-8
+    InvalidEncoding,
+    /// Request rejected before it ran, e.g. oversized or unreadable code
+    InvalidRequest,
+    /// The daemon could not be reached
+    ConnectionFailed,
+    /// Malformed or unexpected response
+    Protocol,
+    /// The daemon failed the request
+    Daemon,
+    /// Anything else
+    Unknown,
+}
+
+impl OutcomeCode {
+    /// Every outcome with a fixed code, in code order
+    pub const RESERVED: [Self; 12] = [
+        Self::Timeout,
+        Self::Cancelled,
+        Self::SandboxSetup,
+        Self::InterpreterUnavailable,
+        Self::Killed,
+        Self::InvalidArgument,
+        Self::InvalidEncoding,
+        Self::InvalidRequest,
+        Self::ConnectionFailed,
+        Self::Protocol,
+        Self::Daemon,
+        Self::Unknown,
+    ];
+
+    /// Highest exit status passed through from the program
+    pub const MAX_EXIT_STATUS: u8 = 125;
+
+    /// The numeric code
+    #[must_use]
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::Exited(status) => status,
+            Self::Timeout => 124,
+            Self::Cancelled => 125,
+            Self::SandboxSetup => 126,
+            Self::InterpreterUnavailable => 127,
+            Self::Killed => 137,
+            Self::InvalidArgument => 200,
+            Self::InvalidEncoding => 201,
+            Self::InvalidRequest => 202,
+            Self::ConnectionFailed => 203,
+            Self::Protocol => 204,
+            Self::Daemon => 205,
+            Self::Unknown => 255,
+        }
+    }
+
+    /// Outcome for a code, or `None` if the code is unassigned
+    ///
+    /// 124 and 125 decode as `Timeout` and `Cancelled`, never as `Exited`.
+    #[must_use]
+    pub const fn from_code(code: u8) -> Option<Self> {
+        let mut i = 0;
+        while i < Self::RESERVED.len() {
+            if Self::RESERVED[i].code() == code {
+                return Some(Self::RESERVED[i]);
+            }
+            i += 1;
+        }
+        if code <= Self::MAX_EXIT_STATUS {
+            Some(Self::Exited(code))
+        } else {
+            None
+        }
+    }
+
+    /// Outcome for an exit status reported for the interpreter
+    ///
+    /// 0–125 pass through. 126 and 127 keep their shell meaning of "cannot
+    /// execute" and "not found". Anything else, including death by a
+    /// signal, is `Killed`.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub const fn from_exit_status(status: i32) -> Self {
+        match status {
+            0..=125 => Self::Exited(status as u8),
+            126 => Self::SandboxSetup,
+            127 => Self::InterpreterUnavailable,
+            _ => Self::Killed,
+        }
+    }
+
+    /// Whether the code reports a client or daemon failure (200 and up)
+    #[must_use]
+    pub const fn is_infrastructure(self) -> bool {
+        self.code() >= 200
+    }
+
+    /// Short description, as listed by `leeward exec --help`
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Exited(_) => "the program's own exit status",
+            Self::Timeout => "timed out",
+            Self::Cancelled => "cancelled",
+            Self::SandboxSetup => "sandbox setup failed",
+            Self::InterpreterUnavailable => "interpreter unavailable",
+            Self::Killed => "killed (out of memory or SIGKILL)",
+            Self::InvalidArgument => "invalid argument",
+            Self::InvalidEncoding => "invalid UTF-8",
+            Self::InvalidRequest => "request rejected",
+            Self::ConnectionFailed => "daemon unreachable",
+            Self::Protocol => "malformed or unexpected response",
+            Self::Daemon => "daemon error",
+            Self::Unknown => "unknown error",
+        }
+    }
+}
+
+impl std::fmt::Display for OutcomeCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exited(status) => write!(f, "exited with status {status}"),
+            other => write!(f, "{} ({})", other.description(), other.code()),
+        }
+    }
+}
+
+impl From<OutcomeCode> for u8 {
+    fn from(outcome: OutcomeCode) -> Self {
+        outcome.code()
+    }
+}
+
+impl TryFrom<u8> for OutcomeCode {
+    type Error = LeewardError;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        Self::from_code(code)
+            .ok_or_else(|| LeewardError::InvalidRequest(format!("unassigned outcome code {code}")))
+    }
+}
+
+impl From<&LeewardError> for OutcomeCode {
+    fn from(error: &LeewardError) -> Self {
+        match error {
+            LeewardError::Namespace(_) | LeewardError::Mount(_) | LeewardError::Config(_) => {
+                Self::SandboxSetup
+            }
+            #[cfg(feature = "seccomp")]
+            LeewardError::Seccomp(_) => Self::SandboxSetup,
+            #[cfg(feature = "landlock")]
+            LeewardError::Landlock(_) => Self::SandboxSetup,
+            #[cfg(feature = "cgroups")]
+            LeewardError::Cgroup(_) | LeewardError::CgroupUnavailable(_) => Self::SandboxSetup,
+            LeewardError::Timeout(_) => Self::Timeout,
+            LeewardError::MemoryLimitExceeded(_) => Self::Killed,
+            LeewardError::InvalidRequest(_) => Self::InvalidRequest,
+            LeewardError::Execution(_) | LeewardError::Io(_) | LeewardError::Nix(_) => Self::Daemon,
+            LeewardError::WorkerDied(death) => match death.stage {
+                Stage::Exec => Self::Daemon,
+                _ => Self::SandboxSetup,
+            },
+            #[cfg(feature = "protocol")]
+            LeewardError::Refused { .. } => Self::InvalidRequest,
+            #[cfg(feature = "protocol")]
+            LeewardError::Protocol { .. } => Self::Protocol,
         }
     }
 }
@@ -1,9 +1,32 @@
 //! Shared memory for zero-copy result passing
-
+//!
+//! # Slot leases
+//!
+//! Each slot pair is leased to one owner, the connection that asked for
+//! it, and carries a generation that changes every time the slot is given
+//! back. A client presents the slot id and generation it was leased with;
+//! once the slot has been freed, [released](SharedMemoryRegion::release_owner)
+//! because its connection went away, or [reclaimed](SharedMemoryRegion::reclaim_idle)
+//! after sitting untouched too long, that generation no longer matches,
+//! so a client still writing to an old [`SlotPair`] is told so instead of
+//! reaching whoever holds the slot now.
+//!
+//! # Output
+//!
+//! An execution's stdout and then its stderr go to the response slot,
+//! [packed](pack_output) under a [`ShmOverflowPolicy`] when together they
+//! are more than [`RESPONSE_CAPACITY`].
+
+#[cfg(feature = "protocol")]
+use crate::ExecutionResult;
+#[cfg(feature = "protocol")]
+use crate::protocol::{Adjustment, PayloadRef, ShmOverflowPolicy, ShmPayload};
 use crate::{LeewardError, Result};
 use memfd::{FileSeal, Memfd, MemfdOptions};
+use std::fmt;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
 /// Size of each request slot (64KB for code)
 pub const REQUEST_SLOT_SIZE: usize = 64 * 1024;
@@ -14,12 +37,74 @@ pub const RESPONSE_SLOT_SIZE: usize = 1024 * 1024;
 /// Maximum number of concurrent requests
 pub const MAX_SLOTS: usize = 64;
 
+/// Bytes of output a response slot holds, after their length
+pub const RESPONSE_CAPACITY: usize = RESPONSE_SLOT_SIZE - 4;
+
+/// Total size of a region: the request arena, then the response arena
+pub const REGION_SIZE: usize = (REQUEST_SLOT_SIZE + RESPONSE_SLOT_SIZE) * MAX_SLOTS;
+
+/// Who holds a slot, such as the id of the connection that leased it
+pub type Owner = u64;
+
+/// A leased slot's holder, and when it last used the slot
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    owner: Owner,
+    touched: Instant,
+}
+
+/// Which slots are leased, and the generation of each
+#[derive(Debug)]
+struct Slots {
+    leases: [Option<Lease>; MAX_SLOTS],
+    generations: [u32; MAX_SLOTS],
+}
+
+impl Slots {
+    /// Give slot `index` back, so its current lease no longer matches
+    const fn free(&mut self, index: usize) {
+        self.leases[index] = None;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+    }
+}
+
+/// Why a slot could not be used or freed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseError {
+    /// The region has no slot with that id
+    NoSuchSlot,
+    /// Nobody holds the slot
+    NotLeased,
+    /// The slot was freed or reclaimed since this lease was handed out
+    GenerationMismatch { presented: u32, current: u32 },
+    /// Another owner holds the slot
+    NotOwner,
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchSlot => f.write_str("no such slot"),
+            Self::NotLeased => f.write_str("slot is not leased"),
+            Self::GenerationMismatch { presented, current } => {
+                write!(
+                    f,
+                    "slot was reclaimed (generation {presented}, now {current})"
+                )
+            }
+            Self::NotOwner => f.write_str("slot is leased to another connection"),
+        }
+    }
+}
+
+impl std::error::Error for LeaseError {}
+
 /// Shared memory region for request/response communication
 pub struct SharedMemoryRegion {
     /// The memfd backing the shared memory
     memfd: Memfd,
-    /// Number of active requests
-    active_requests: AtomicU32,
+    /// Leases and generations, by slot
+    slots: Mutex<Slots>,
     /// Request arena offset
     request_arena_offset: usize,
     /// Response arena offset
@@ -28,6 +113,11 @@ pub struct SharedMemoryRegion {
 
 impl SharedMemoryRegion {
     /// Create a new shared memory region
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the memfd cannot be created, and
+    /// [`LeewardError::Io`] if it cannot be sized.
     pub fn new() -> Result<Self> {
         let opts = MemfdOptions::default()
             .allow_sealing(true)
@@ -37,21 +127,17 @@ impl SharedMemoryRegion {
             .create("leeward_shm")
             .map_err(|e| LeewardError::Execution(format!("failed to create memfd: {e}")))?;
 
-        // Calculate total size: request arena + response arena
-        let request_arena_size = REQUEST_SLOT_SIZE * MAX_SLOTS;
-        let response_arena_size = RESPONSE_SLOT_SIZE * MAX_SLOTS;
-        let total_size = request_arena_size + response_arena_size;
-
         // Resize the memfd
-        memfd
-            .as_file()
-            .set_len(total_size as u64)?;
+        memfd.as_file().set_len(REGION_SIZE as u64)?;
 
         Ok(Self {
             memfd,
-            active_requests: AtomicU32::new(0),
+            slots: Mutex::new(Slots {
+                leases: [None; MAX_SLOTS],
+                generations: [0; MAX_SLOTS],
+            }),
             request_arena_offset: 0,
-            response_arena_offset: request_arena_size,
+            response_arena_offset: REQUEST_SLOT_SIZE * MAX_SLOTS,
         })
     }
 
@@ -60,31 +146,144 @@ impl SharedMemoryRegion {
         self.memfd.as_raw_fd()
     }
 
-    /// Allocate a request/response slot pair
-    pub fn allocate_slot(&self) -> Result<SlotPair> {
-        let slot_id = self.active_requests.fetch_add(1, Ordering::SeqCst);
-
-        if slot_id >= MAX_SLOTS as u32 {
-            self.active_requests.fetch_sub(1, Ordering::SeqCst);
+    /// Lease a free request/response slot pair to `owner`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if every slot is leased.
+    pub fn allocate_slot(&self, owner: Owner) -> Result<SlotPair> {
+        let mut slots = self.lock();
+        let Some(index) = slots.leases.iter().position(Option::is_none) else {
             return Err(LeewardError::Execution(
                 "no available slots in shared memory".into(),
             ));
+        };
+        slots.leases[index] = Some(Lease {
+            owner,
+            touched: Instant::now(),
+        });
+        Ok(self.pair(index, slots.generations[index]))
+    }
+
+    /// The slot pair `slot_id` would be in its `generation`, `None` if
+    /// there is no such slot
+    ///
+    /// For rebuilding a lease from the ids a client sent; whether it is
+    /// still good is up to [`check`](Self::check).
+    #[must_use]
+    pub fn slot_pair(&self, slot_id: u32, generation: u32) -> Option<SlotPair> {
+        let index = usize::try_from(slot_id)
+            .ok()
+            .filter(|&index| index < MAX_SLOTS)?;
+        Some(self.pair(index, generation))
+    }
+
+    /// Check that `owner` still holds `slot` in its generation, counting
+    /// it as used now
+    ///
+    /// # Errors
+    ///
+    /// The [`LeaseError`] saying why `owner` does not hold `slot`.
+    pub fn check(&self, owner: Owner, slot: &SlotPair) -> std::result::Result<(), LeaseError> {
+        let mut slots = self.lock();
+        let index = Self::held(&slots, owner, slot)?;
+        if let Some(lease) = &mut slots.leases[index] {
+            lease.touched = Instant::now();
+        }
+        drop(slots);
+        Ok(())
+    }
+
+    /// Give back a slot `owner` holds, bumping its generation
+    ///
+    /// # Errors
+    ///
+    /// As [`SharedMemoryRegion::check`].
+    pub fn free_slot(&self, owner: Owner, slot: &SlotPair) -> std::result::Result<(), LeaseError> {
+        let mut slots = self.lock();
+        let index = Self::held(&slots, owner, slot)?;
+        slots.free(index);
+        drop(slots);
+        Ok(())
+    }
+
+    /// Give back every slot `owner` holds, returning how many it had
+    pub fn release_owner(&self, owner: Owner) -> usize {
+        self.free_where(|lease| lease.owner == owner)
+    }
+
+    /// Give back every slot untouched for `ttl`, returning how many
+    pub fn reclaim_idle(&self, ttl: Duration) -> usize {
+        self.free_where(|lease| lease.touched.elapsed() >= ttl)
+    }
+
+    /// Slots leased right now
+    #[must_use]
+    pub fn leased(&self) -> usize {
+        self.lock()
+            .leases
+            .iter()
+            .filter(|lease| lease.is_some())
+            .count()
+    }
+
+    fn free_where(&self, mut stale: impl FnMut(&Lease) -> bool) -> usize {
+        let mut slots = self.lock();
+        let mut freed = 0;
+        for index in 0..MAX_SLOTS {
+            if slots.leases[index].as_ref().is_some_and(&mut stale) {
+                slots.free(index);
+                freed += 1;
+            }
+        }
+        drop(slots);
+        freed
+    }
+
+    /// Index of `slot` if `owner` holds it in its generation
+    fn held(
+        slots: &Slots,
+        owner: Owner,
+        slot: &SlotPair,
+    ) -> std::result::Result<usize, LeaseError> {
+        let index = usize::try_from(slot.slot_id)
+            .ok()
+            .filter(|&index| index < MAX_SLOTS)
+            .ok_or(LeaseError::NoSuchSlot)?;
+        let current = slots.generations[index];
+        if slot.generation != current {
+            return Err(LeaseError::GenerationMismatch {
+                presented: slot.generation,
+                current,
+            });
         }
+        match slots.leases[index] {
+            None => Err(LeaseError::NotLeased),
+            Some(lease) if lease.owner != owner => Err(LeaseError::NotOwner),
+            Some(_) => Ok(index),
+        }
+    }
 
-        Ok(SlotPair {
-            slot_id,
-            request_offset: self.request_arena_offset + (slot_id as usize * REQUEST_SLOT_SIZE),
-            response_offset: self.response_arena_offset + (slot_id as usize * RESPONSE_SLOT_SIZE),
+    #[allow(clippy::cast_possible_truncation)]
+    fn pair(&self, index: usize, generation: u32) -> SlotPair {
+        SlotPair {
+            slot_id: index as u32,
+            generation,
+            request_offset: self.request_arena_offset + index * REQUEST_SLOT_SIZE,
+            response_offset: self.response_arena_offset + index * RESPONSE_SLOT_SIZE,
             memfd_fd: self.as_raw_fd(),
-        })
+        }
     }
 
-    /// Free a slot
-    pub fn free_slot(&self, _slot: SlotPair) {
-        self.active_requests.fetch_sub(1, Ordering::SeqCst);
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Seal the memfd to prevent further modifications
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the seals cannot be added.
     pub fn seal(&mut self) -> Result<()> {
         self.memfd
             .add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow])
@@ -98,6 +297,8 @@ impl SharedMemoryRegion {
 pub struct SlotPair {
     /// Unique slot ID
     pub slot_id: u32,
+    /// Generation of the slot this pair was leased in
+    pub generation: u32,
     /// Offset into shared memory for request data
     pub request_offset: usize,
     /// Offset into shared memory for response data
@@ -111,7 +312,8 @@ impl SlotPair {
     ///
     /// # Safety
     /// The caller must ensure the shared memory is properly mapped
-    pub unsafe fn request_ptr(&self) -> *mut u8 {
+    #[must_use]
+    pub const unsafe fn request_ptr(&self) -> *mut u8 {
         // This would be used after mmap() to get actual pointer
         // For now, just return the offset as a pointer (will be fixed in actual mmap usage)
         self.request_offset as *mut u8
@@ -121,7 +323,8 @@ impl SlotPair {
     ///
     /// # Safety
     /// The caller must ensure the shared memory is properly mapped
-    pub unsafe fn response_ptr(&self) -> *mut u8 {
+    #[must_use]
+    pub const unsafe fn response_ptr(&self) -> *mut u8 {
         self.response_offset as *mut u8
     }
 }
@@ -136,10 +339,12 @@ pub struct MappedSharedMemory {
 
 impl MappedSharedMemory {
     /// Map a shared memory file descriptor into this process
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Io`] if `fd` cannot be mapped.
     pub fn new(fd: RawFd, read_only: bool) -> Result<Self> {
-        let request_arena_size = REQUEST_SLOT_SIZE * MAX_SLOTS;
-        let response_arena_size = RESPONSE_SLOT_SIZE * MAX_SLOTS;
-        let total_size = request_arena_size + response_arena_size;
+        let total_size = REGION_SIZE;
 
         let prot = if read_only {
             libc::PROT_READ
@@ -169,40 +374,96 @@ impl MappedSharedMemory {
         })
     }
 
-    /// Write code to a request slot
+    /// Write code to a request slot, after its length
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if `code` does not fit the slot.
     pub fn write_request(&self, slot: &SlotPair, code: &[u8]) -> Result<()> {
-        if code.len() > REQUEST_SLOT_SIZE {
+        if code.len() > REQUEST_SLOT_SIZE - 4 {
             return Err(LeewardError::Execution(format!(
                 "code too large: {} bytes (max {})",
                 code.len(),
-                REQUEST_SLOT_SIZE
+                REQUEST_SLOT_SIZE - 4
             )));
         }
 
-        // SAFETY: Writing to mapped shared memory
+        #[allow(clippy::cast_possible_truncation)]
+        let len = code.len() as u32;
+        // SAFETY: Writing to mapped shared memory, within the slot
         unsafe {
-            let dest = (self.base_ptr as *mut u8).add(slot.request_offset);
-            std::ptr::copy_nonoverlapping(code.as_ptr(), dest, code.len());
+            let dest = self.base_ptr.cast::<u8>().add(slot.request_offset);
             // Write length prefix
-            let len_ptr = dest.cast::<u32>();
-            *len_ptr = code.len() as u32;
+            dest.cast::<u32>().write_unaligned(len);
+            std::ptr::copy_nonoverlapping(code.as_ptr(), dest.add(4), code.len());
+        }
+
+        Ok(())
+    }
+
+    /// Read the code written to a request slot
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the length written does not fit the slot.
+    pub fn read_request(&self, slot: &SlotPair) -> Result<Vec<u8>> {
+        // SAFETY: Reading from mapped shared memory, within the slot
+        unsafe {
+            let src = (self.base_ptr as *const u8).add(slot.request_offset);
+            let len = src.cast::<u32>().read_unaligned() as usize;
+
+            if len > REQUEST_SLOT_SIZE - 4 {
+                return Err(LeewardError::Execution(format!(
+                    "request too large: {len} bytes"
+                )));
+            }
+
+            let mut buffer = vec![0u8; len];
+            std::ptr::copy_nonoverlapping(src.add(4), buffer.as_mut_ptr(), len);
+            Ok(buffer)
+        }
+    }
+
+    /// Write output to a response slot, after its length
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if `output` does not fit the slot.
+    pub fn write_response(&self, slot: &SlotPair, output: &[u8]) -> Result<()> {
+        if output.len() > RESPONSE_CAPACITY {
+            return Err(LeewardError::Execution(format!(
+                "response too large: {} bytes (max {RESPONSE_CAPACITY})",
+                output.len()
+            )));
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let len = output.len() as u32;
+        // SAFETY: Writing to mapped shared memory, within the slot
+        unsafe {
+            let dest = self.base_ptr.cast::<u8>().add(slot.response_offset);
+            dest.cast::<u32>().write_unaligned(len);
+            std::ptr::copy_nonoverlapping(output.as_ptr(), dest.add(4), output.len());
         }
 
         Ok(())
     }
 
     /// Read response from a response slot
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the length written does not fit the slot.
     pub fn read_response(&self, slot: &SlotPair) -> Result<Vec<u8>> {
         // SAFETY: Reading from mapped shared memory
         unsafe {
             let src = (self.base_ptr as *const u8).add(slot.response_offset);
             // Read length prefix
-            let len = *(src.cast::<u32>());
+            let len = src.cast::<u32>().read_unaligned();
 
-            if len as usize > RESPONSE_SLOT_SIZE {
+            if len as usize > RESPONSE_CAPACITY {
                 return Err(LeewardError::Execution(format!(
-                    "response too large: {} bytes",
-                    len
+                    "response too large: {len} bytes"
                 )));
             }
 
@@ -225,3 +486,148 @@ impl Drop for MappedSharedMemory {
 // SAFETY: Shared memory can be safely sent between threads
 unsafe impl Send for MappedSharedMemory {}
 unsafe impl Sync for MappedSharedMemory {}
+
+/// An execution's output laid out for its response slot by [`pack_output`]
+#[cfg(feature = "protocol")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedOutput {
+    /// What goes in the slot: stdout's part, then stderr's
+    pub slot: Vec<u8>,
+    /// Where each stream went
+    pub payload: ShmPayload,
+    /// How the overflow policy was applied, if the output did not fit
+    pub adjustment: Option<Adjustment>,
+}
+
+/// Output too big for its response slot, under
+/// [`ShmOverflowPolicy::FailRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputOverflow {
+    /// Bytes of stdout and stderr together
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for OutputOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "output of {} bytes does not fit the {} byte response slot",
+            self.len, self.capacity
+        )
+    }
+}
+
+impl std::error::Error for OutputOverflow {}
+
+#[cfg(feature = "protocol")]
+impl OutputOverflow {
+    /// The adjustment saying the execution failed for it
+    #[must_use]
+    pub fn adjustment(&self) -> Adjustment {
+        overflow_adjustment(ShmOverflowPolicy::FailRequest, "failed", *self)
+    }
+}
+
+/// Move the output of `result` into a response slot of `capacity` bytes,
+/// applying `policy` if it does not all fit
+///
+/// Streams moved to the slot are left empty in `result`; the others stay
+/// there to go in the socket response.
+///
+/// # Errors
+///
+/// [`OutputOverflow`] if the output does not all fit under
+/// [`ShmOverflowPolicy::FailRequest`], the only policy that fails.
+#[cfg(feature = "protocol")]
+pub fn pack_output(
+    result: &mut ExecutionResult,
+    capacity: usize,
+    policy: ShmOverflowPolicy,
+) -> std::result::Result<PackedOutput, OutputOverflow> {
+    let (stdout, stderr) = (result.stdout.len(), result.stderr.len());
+    let overflow = OutputOverflow {
+        len: stdout.saturating_add(stderr),
+        capacity,
+    };
+    if overflow.len <= capacity {
+        return Ok(pack(result, true, true, None));
+    }
+
+    match policy {
+        ShmOverflowPolicy::FailRequest => Err(overflow),
+        ShmOverflowPolicy::TruncateWithFlag => {
+            result.truncate_output(capacity);
+            let (kept_stdout, kept_stderr) = (result.stdout.len(), result.stderr.len());
+            let applied = format!(
+                "kept {kept_stdout} of {stdout} stdout and {kept_stderr} of {stderr} stderr bytes"
+            );
+            Ok(pack(
+                result,
+                true,
+                true,
+                Some(overflow_adjustment(policy, &applied, overflow)),
+            ))
+        }
+        ShmOverflowPolicy::FallbackToSocket => {
+            let stdout_fits = stdout <= capacity;
+            let room = if stdout_fits {
+                capacity - stdout
+            } else {
+                capacity
+            };
+            let stderr_fits = stderr <= room;
+            let applied = match (stdout_fits, stderr_fits) {
+                (true, _) => "stderr via socket",
+                (false, true) => "stdout via socket",
+                (false, false) => "stdout and stderr via socket",
+            };
+            Ok(pack(
+                result,
+                stdout_fits,
+                stderr_fits,
+                Some(overflow_adjustment(policy, applied, overflow)),
+            ))
+        }
+    }
+}
+
+/// Move the chosen streams of `result` into a slot, in order
+#[cfg(feature = "protocol")]
+fn pack(
+    result: &mut ExecutionResult,
+    stdout: bool,
+    stderr: bool,
+    adjustment: Option<Adjustment>,
+) -> PackedOutput {
+    let mut slot = Vec::new();
+    let mut place = |stream: &mut Vec<u8>, in_slot: bool| {
+        if !in_slot {
+            return PayloadRef::Inline;
+        }
+        let offset = slot.len() as u64;
+        slot.append(stream);
+        PayloadRef::Slot {
+            offset,
+            len: slot.len() as u64 - offset,
+        }
+    };
+    let payload = ShmPayload {
+        stdout: place(&mut result.stdout, stdout),
+        stderr: place(&mut result.stderr, stderr),
+    };
+    PackedOutput {
+        slot,
+        payload,
+        adjustment,
+    }
+}
+
+#[cfg(feature = "protocol")]
+fn overflow_adjustment(
+    policy: ShmOverflowPolicy,
+    applied: &str,
+    overflow: OutputOverflow,
+) -> Adjustment {
+    Adjustment::new("shm_overflow", policy.name(), applied, overflow.to_string())
+}
@@ -0,0 +1,298 @@
+//! Daemon socket addresses
+//!
+//! A socket path is either a filesystem path or, starting with `@`, a name
+//! in Linux's abstract socket namespace, which has no file and no directory
+//! to worry about. A Unix socket address holds at most [`SUN_PATH_MAX`]
+//! bytes; longer filesystem paths are reached through `/proc/self/fd` and a
+//! handle on their directory, so only the file name has to fit.
+//!
+//! [`send_with_fds`] and [`recv_with_fds`] pass descriptors along with
+//! bytes, which is how a daemon hands its listening socket to a successor.
+
+use crate::{LeewardError, Result};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::Path;
+
+/// Bytes of a path or abstract name that fit in `sockaddr_un.sun_path`,
+/// leaving room for the terminating (or, for abstract names, leading) NUL
+pub const SUN_PATH_MAX: usize = 107;
+
+/// Longest `/proc/self/fd/<fd>/` a file name is appended to
+const PROC_FD_PREFIX_MAX: usize = "/proc/self/fd/2147483647/".len();
+
+/// Filesystems Unix sockets are known to misbehave on
+const UNRELIABLE_FILESYSTEMS: [(nix::sys::statfs::FsType, &str); 7] = {
+    use nix::sys::statfs::{
+        AFS_SUPER_MAGIC, CODA_SUPER_MAGIC, FUSE_SUPER_MAGIC, NCP_SUPER_MAGIC, NFS_SUPER_MAGIC,
+        OVERLAYFS_SUPER_MAGIC, SMB_SUPER_MAGIC,
+    };
+    [
+        (NFS_SUPER_MAGIC, "NFS"),
+        (SMB_SUPER_MAGIC, "SMB"),
+        (AFS_SUPER_MAGIC, "AFS"),
+        (CODA_SUPER_MAGIC, "Coda"),
+        (NCP_SUPER_MAGIC, "NCP"),
+        (FUSE_SUPER_MAGIC, "FUSE"),
+        (OVERLAYFS_SUPER_MAGIC, "overlayfs"),
+    ]
+};
+
+/// A socket path, resolved to something `bind` and `connect` accept
+enum Address<'a> {
+    Abstract(&'a [u8]),
+    Path(&'a Path),
+    /// Too long to use directly: a file name in a directory
+    InDirectory(&'a Path, &'a OsStr),
+}
+
+impl<'a> Address<'a> {
+    fn parse(path: &'a Path) -> Result<Self> {
+        let bytes = path.as_os_str().as_bytes();
+        if let Some(name) = bytes.strip_prefix(b"@") {
+            if name.is_empty() || name.len() > SUN_PATH_MAX {
+                return Err(LeewardError::Config(format!(
+                    "abstract socket name {} must be 1 to {SUN_PATH_MAX} bytes",
+                    path.display()
+                )));
+            }
+            return Ok(Self::Abstract(name));
+        }
+        if bytes.len() <= SUN_PATH_MAX {
+            return Ok(Self::Path(path));
+        }
+
+        match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) if PROC_FD_PREFIX_MAX + name.len() <= SUN_PATH_MAX => {
+                Ok(Self::InDirectory(dir, name))
+            }
+            _ => Err(too_long(path)),
+        }
+    }
+
+    /// Run `f` on the address, holding the directory open while it runs
+    fn with<T>(&self, path: &Path, f: impl FnOnce(&SocketAddr) -> std::io::Result<T>) -> Result<T> {
+        match self {
+            Self::Abstract(name) => Ok(f(&SocketAddr::from_abstract_name(name)?)?),
+            Self::Path(path) => Ok(f(&SocketAddr::from_pathname(path)?)?),
+            Self::InDirectory(dir, name) => {
+                let dir = File::options()
+                    .read(true)
+                    .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+                    .open(dir)
+                    .map_err(|e| {
+                        LeewardError::Config(format!(
+                            "{}, and its directory cannot be opened to bind in place: {e}",
+                            too_long_message(path)
+                        ))
+                    })?;
+                let short = proc_fd_path(dir.as_raw_fd(), name);
+                let addr = SocketAddr::from_pathname(Path::new(OsStr::from_bytes(&short)))
+                    .map_err(|_| too_long(path))?;
+                Ok(f(&addr)?)
+            }
+        }
+    }
+}
+
+/// `/proc/self/fd/<fd>/<name>`, which resolves through an open directory
+fn proc_fd_path(fd: i32, name: &OsStr) -> Vec<u8> {
+    let mut path = format!("/proc/self/fd/{fd}/").into_bytes();
+    path.extend_from_slice(name.as_bytes());
+    path
+}
+
+fn too_long(path: &Path) -> LeewardError {
+    LeewardError::Config(too_long_message(path))
+}
+
+fn too_long_message(path: &Path) -> String {
+    format!(
+        "socket path {} is {} bytes, more than the {SUN_PATH_MAX} a Unix socket address holds; \
+         use a shorter path, or an abstract socket name such as @leeward",
+        path.display(),
+        path.as_os_str().len()
+    )
+}
+
+/// Check that `path` can be bound or connected to
+///
+/// # Errors
+///
+/// [`LeewardError::Config`] for over-length paths whose directory cannot be
+/// opened, file names too long on their own, and abstract names that are empty
+/// or too long.
+pub fn check_socket_path(path: &Path) -> Result<()> {
+    let address = Address::parse(path)?;
+    if let Address::InDirectory(..) = address {
+        address.with(path, |_| Ok(()))?;
+    }
+    Ok(())
+}
+
+/// Listen on `path`
+///
+/// # Errors
+///
+/// As [`check_socket_path`], and [`LeewardError::Io`] if `path` cannot be
+/// bound.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    Address::parse(path)?.with(path, UnixListener::bind_addr)
+}
+
+/// Connect to a daemon listening on `path`
+///
+/// # Errors
+///
+/// As [`check_socket_path`], and [`LeewardError::Io`] if nothing is listening
+/// on `path`.
+pub fn connect(path: &Path) -> Result<UnixStream> {
+    Address::parse(path)?.with(path, UnixStream::connect_addr)
+}
+
+/// Whether `path` names an abstract socket rather than a file
+#[must_use]
+pub fn is_abstract(path: &Path) -> bool {
+    path.as_os_str().as_bytes().starts_with(b"@")
+}
+
+/// Name of the filesystem holding a socket at `path`, if sockets are
+/// known to misbehave there; `None` for abstract names
+#[must_use]
+pub fn unreliable_filesystem(path: &Path) -> Option<&'static str> {
+    if is_abstract(path) {
+        return None;
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty())?;
+    let fs_type = nix::sys::statfs::statfs(dir).ok()?.filesystem_type();
+    UNRELIABLE_FILESYSTEMS
+        .iter()
+        .find(|(magic, _)| *magic == fs_type)
+        .map(|(_, name)| *name)
+}
+
+/// Most descriptors one message can carry, the kernel's `SCM_MAX_FD`
+pub const MAX_FDS: usize = 253;
+
+/// Control buffer for `count` `SCM_RIGHTS` fds, aligned for a `cmsghdr`
+fn fd_control(count: usize) -> Vec<u64> {
+    let fds = u32::try_from(count * std::mem::size_of::<RawFd>()).unwrap_or(u32::MAX);
+    // SAFETY: CMSG_SPACE only does arithmetic
+    let space = unsafe { libc::CMSG_SPACE(fds) } as usize;
+    vec![0; space.div_ceil(std::mem::size_of::<u64>())]
+}
+
+/// Send `bytes` over a unix socket with `fds` attached as `SCM_RIGHTS`,
+/// returning how many bytes went out
+///
+/// The descriptors arrive with the first byte; send whatever is left of
+/// `bytes` with plain writes. At most [`MAX_FDS`] fit.
+///
+/// # Errors
+///
+/// `InvalidInput` for more than [`MAX_FDS`] descriptors, and the error of
+/// `sendmsg`.
+pub fn send_with_fds(
+    socket: BorrowedFd<'_>,
+    bytes: &[u8],
+    fds: &[BorrowedFd<'_>],
+) -> std::io::Result<usize> {
+    if fds.len() > MAX_FDS {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} descriptors do not fit in one message", fds.len()),
+        ));
+    }
+    let mut iov = libc::iovec {
+        iov_base: bytes.as_ptr().cast_mut().cast(),
+        iov_len: bytes.len(),
+    };
+    let mut control = fd_control(fds.len());
+
+    // SAFETY: msghdr is plain data; every pointer in it outlives the
+    // sendmsg call, and the control buffer has room for every fd
+    let ret = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            let len = u32::try_from(std::mem::size_of_val(fds)).unwrap_or(u32::MAX);
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(len) as usize;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(len) as usize;
+            let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+            for (i, fd) in fds.iter().enumerate() {
+                std::ptr::write_unaligned(data.add(i), fd.as_raw_fd());
+            }
+        }
+
+        libc::sendmsg(socket.as_raw_fd(), &raw const msg, libc::MSG_NOSIGNAL)
+    };
+
+    usize::try_from(ret).map_err(|_| std::io::Error::last_os_error())
+}
+
+/// Receive into `buf` along with the descriptors sent with
+/// [`send_with_fds`], returning how many bytes arrived
+///
+/// # Errors
+///
+/// The error of `recvmsg`, and an error if more than [`MAX_FDS`] descriptors
+/// were attached, which are then dropped.
+pub fn recv_with_fds(
+    socket: BorrowedFd<'_>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, Vec<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut control = fd_control(MAX_FDS);
+
+    // SAFETY: msghdr is plain data; every pointer in it outlives the recvmsg call
+    let (ret, msg) = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(control.as_slice());
+
+        let ret = libc::recvmsg(socket.as_raw_fd(), &raw mut msg, libc::MSG_CMSG_CLOEXEC);
+        (ret, msg)
+    };
+    let received = usize::try_from(ret).map_err(|_| std::io::Error::last_os_error())?;
+
+    let mut fds = Vec::new();
+    // SAFETY: The kernel filled in the control buffer msg points to, and
+    // every fd in an SCM_RIGHTS message is new and ours
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let header = libc::CMSG_LEN(0) as usize;
+                let count = ((*cmsg).cmsg_len as usize - header) / std::mem::size_of::<RawFd>();
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&raw const msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(std::io::Error::other(
+            "descriptors were dropped: more than fit in one message",
+        ));
+    }
+    Ok((received, fds))
+}
@@ -0,0 +1,117 @@
+//! Normalizing code before an interpreter sees it
+//!
+//! Code pasted from Windows editors or notebooks can start with a UTF-8
+//! byte order mark, end its lines with CRLF, or carry a PEP 263 encoding
+//! declaration. Python reports the mark as an invalid character at line 1,
+//! column 1, and a shell runs each `\r` as part of a command. A declaration
+//! is wrong by the time the code is a `String`: it was decoded as UTF-8,
+//! and reaches the interpreter as text rather than bytes, so one naming any
+//! other encoding is blanked out rather than honoured. [`normalize`] undoes
+//! all three without moving any line.
+//!
+//! NUL bytes cannot be handed to an interpreter at all; see [`find_nul`].
+
+use crate::config::Interpreter;
+use std::ops::Range;
+
+/// A UTF-8 byte order mark, as decoded
+const BOM: char = '\u{feff}';
+
+/// What [`normalize`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Normalization {
+    /// A leading byte order mark was removed
+    pub bom: bool,
+    /// CRLF line endings turned into LF
+    pub crlf_lines: usize,
+    /// Encoding named by a declaration that was blanked out
+    pub encoding: Option<String>,
+}
+
+impl Normalization {
+    /// Whether the code was left as it was
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        !self.bom && self.crlf_lines == 0 && self.encoding.is_none()
+    }
+}
+
+/// Strip a byte order mark, turn CRLF into LF and, for Python, blank out a
+/// declaration of any encoding but UTF-8, returning what was changed
+///
+/// Code that needs none of it is not copied.
+pub fn normalize(code: &mut String, interpreter: Interpreter) -> Normalization {
+    let mut normalized = Normalization::default();
+
+    if code.starts_with(BOM) {
+        code.drain(..BOM.len_utf8());
+        normalized.bom = true;
+    }
+
+    normalized.crlf_lines = code.matches("\r\n").count();
+    if normalized.crlf_lines > 0 {
+        *code = code.replace("\r\n", "\n");
+    }
+
+    if interpreter == Interpreter::Python {
+        if let Some((line, encoding)) =
+            encoding_declaration(code).filter(|(_, encoding)| !is_utf8(encoding))
+        {
+            normalized.encoding = Some(encoding.to_owned());
+            code.replace_range(line, "");
+        }
+    }
+
+    normalized
+}
+
+/// Byte offset of the first NUL in `code`, which no interpreter can be
+/// given since code travels as a command-line argument
+#[must_use]
+pub fn find_nul(code: &str) -> Option<usize> {
+    code.bytes().position(|b| b == 0)
+}
+
+/// The line holding a PEP 263 declaration and the encoding it names
+///
+/// Python looks at the first line, and at the second if the first is
+/// blank or a comment.
+fn encoding_declaration(code: &str) -> Option<(Range<usize>, &str)> {
+    let mut start = 0;
+    for _ in 0..2 {
+        let end = code[start..].find('\n').map_or(code.len(), |at| start + at);
+        let line = code[start..end].trim_start_matches([' ', '\t', '\x0c']);
+        match line.strip_prefix('#') {
+            Some(comment) => {
+                if let Some(encoding) = declared_encoding(comment) {
+                    return Some((start..end, encoding));
+                }
+            }
+            None if line.is_empty() => {}
+            None => return None,
+        }
+        start = end + 1;
+        if start >= code.len() {
+            return None;
+        }
+    }
+    None
+}
+
+/// The name after the first `coding:` or `coding=` in `comment`
+fn declared_encoding(comment: &str) -> Option<&str> {
+    comment.match_indices("coding").find_map(|(at, word)| {
+        let rest = comment[at + word.len()..].strip_prefix([':', '='])?;
+        let rest = rest.trim_start_matches([' ', '\t']);
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .unwrap_or(rest.len());
+        (len > 0).then(|| &rest[..len])
+    })
+}
+
+/// Whether Python reads `encoding` as UTF-8
+fn is_utf8(encoding: &str) -> bool {
+    let name = encoding.to_ascii_lowercase().replace('_', "-");
+    name == "utf-8" || name == "utf8" || name.starts_with("utf-8-")
+}
@@ -0,0 +1,402 @@
+//! Sizes and durations that carry their unit
+//!
+//! Config values used to be bare integers in whatever unit the field name
+//! said, bytes here and MiB or seconds there, and one size divided down to
+//! MiB on its way to a mount cost a sub-MiB tmpfs its limit. [`ByteSize`]
+//! and [`DurationSecs`] parse human strings such as `256MiB` or `1500ms`,
+//! print in the largest unit that is exact, so they parse back to the same
+//! value, and convert only through named constructors and accessors.
+//!
+//! Both serialize the way the bare integers they replace did: a size as its
+//! byte count, a duration as whole seconds, or as a string when it is not
+//! whole. Either form deserializes.
+//!
+//! Bare integers still parse, as bytes and seconds, for one more release.
+//! Config read from people rather than from the wire warns about them; see
+//! [`is_bare`].
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Binary size units, largest first, as printed
+const SIZE_UNITS: [(&str, u64); 5] = [
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("B", 1),
+];
+
+/// Duration units in nanoseconds, largest first, as printed
+const DURATION_UNITS: [(&str, u64); 6] = [
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+/// Why a size or duration could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UnitError {
+    #[error("{0:?} is not a number followed by a unit")]
+    Malformed(String),
+    #[error("unknown unit {unit:?} in {input:?}")]
+    UnknownUnit { input: String, unit: String },
+    #[error("{0:?} is not a whole number of {1}")]
+    Fractional(String, &'static str),
+    #[error("{0:?} is too large")]
+    Overflow(String),
+}
+
+/// Whether `value` is a bare integer, which parses as bytes or seconds but
+/// is deprecated in config in favour of a number with a unit
+#[must_use]
+pub fn is_bare(value: &str) -> bool {
+    let value = value.trim();
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `input` as a number times the multiplier `unit` maps its unit to, in
+/// whole `what`s
+fn parse_scaled(
+    input: &str,
+    what: &'static str,
+    unit: impl Fn(&str) -> Option<u64>,
+) -> Result<u64, UnitError> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+    let suffix = suffix.trim_start();
+    let multiplier = unit(suffix).ok_or_else(|| UnitError::UnknownUnit {
+        input: input.to_owned(),
+        unit: suffix.to_owned(),
+    })?;
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return Err(UnitError::Malformed(input.to_owned()));
+    }
+    let overflow = || UnitError::Overflow(input.to_owned());
+    let digits = |digits: &str| -> Result<u128, UnitError> {
+        if digits.is_empty() {
+            Ok(0)
+        } else {
+            digits.parse().map_err(|_| overflow())
+        }
+    };
+    let scale = u32::try_from(fraction.len())
+        .ok()
+        .and_then(|len| 10u128.checked_pow(len))
+        .ok_or_else(overflow)?;
+    let numerator = digits(whole)?
+        .checked_mul(scale)
+        .and_then(|whole| whole.checked_add(digits(fraction).ok()?))
+        .and_then(|value| value.checked_mul(u128::from(multiplier)))
+        .ok_or_else(overflow)?;
+    if numerator % scale != 0 {
+        return Err(UnitError::Fractional(input.to_owned(), what));
+    }
+    u64::try_from(numerator / scale).map_err(|_| overflow())
+}
+
+/// `value` in the largest of `units` that divides it exactly, or as zero
+/// of `zero`
+fn write_scaled(
+    f: &mut fmt::Formatter<'_>,
+    value: u64,
+    units: &[(&str, u64)],
+    zero: &str,
+) -> fmt::Result {
+    if value == 0 {
+        return write!(f, "0{zero}");
+    }
+    let (name, size) = units
+        .iter()
+        .find(|(_, size)| value % size == 0)
+        .copied()
+        .unwrap_or(units[units.len() - 1]);
+    write!(f, "{}{name}", value / size)
+}
+
+/// A number of bytes
+///
+/// Parses from `512KiB`, `1.5GiB`, `64M` (binary), `100MB` (decimal) or,
+/// deprecated, a bare byte count; prints as `512KiB`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const ZERO: Self = Self(0);
+
+    #[must_use]
+    pub const fn from_bytes(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    /// `n` KiB, saturating
+    #[must_use]
+    pub const fn kib(n: u64) -> Self {
+        Self(n.saturating_mul(1 << 10))
+    }
+
+    /// `n` MiB, saturating
+    #[must_use]
+    pub const fn mib(n: u64) -> Self {
+        Self(n.saturating_mul(1 << 20))
+    }
+
+    /// `n` GiB, saturating
+    #[must_use]
+    pub const fn gib(n: u64) -> Self {
+        Self(n.saturating_mul(1 << 30))
+    }
+
+    /// The size in bytes
+    #[must_use]
+    pub const fn bytes(self) -> u64 {
+        self.0
+    }
+
+    /// The size in whole MiB, rounded down
+    #[must_use]
+    pub const fn whole_mib(self) -> u64 {
+        self.0 >> 20
+    }
+
+    #[must_use]
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    #[must_use]
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    #[must_use]
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    #[must_use]
+    pub const fn saturating_mul(self, factor: u64) -> Self {
+        Self(self.0.saturating_mul(factor))
+    }
+
+    #[must_use]
+    pub const fn checked_add(self, other: Self) -> Option<Self> {
+        match self.0.checked_add(other.0) {
+            Some(sum) => Some(Self(sum)),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_scaled(f, self.0, &SIZE_UNITS, "B")
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = UnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_scaled(s, "bytes", |unit| {
+            Some(match unit.to_ascii_lowercase().as_str() {
+                "" | "b" => 1,
+                "k" | "kib" => 1 << 10,
+                "m" | "mib" => 1 << 20,
+                "g" | "gib" => 1 << 30,
+                "t" | "tib" => 1 << 40,
+                "kb" => 1_000,
+                "mb" => 1_000_000,
+                "gb" => 1_000_000_000,
+                "tb" => 1_000_000_000_000,
+                _ => return None,
+            })
+        })
+        .map(Self)
+    }
+}
+
+/// A duration whose bare form is in seconds
+///
+/// Parses from `1500ms`, `30s`, `5m`, `1h`, `0.5s` or, deprecated, a bare
+/// number of seconds; prints as `1500ms`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DurationSecs(Duration);
+
+impl DurationSecs {
+    pub const ZERO: Self = Self(Duration::ZERO);
+
+    #[must_use]
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    #[must_use]
+    pub const fn from_millis(millis: u64) -> Self {
+        Self(Duration::from_millis(millis))
+    }
+
+    /// The duration
+    #[must_use]
+    pub const fn get(self) -> Duration {
+        self.0
+    }
+
+    /// `None` when zero, which config uses to mean off
+    #[must_use]
+    pub const fn non_zero(self) -> Option<Duration> {
+        if self.0.is_zero() { None } else { Some(self.0) }
+    }
+
+    #[must_use]
+    pub const fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Whole milliseconds, saturating
+    #[must_use]
+    pub fn as_millis_u64(self) -> u64 {
+        u64::try_from(self.0.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    #[must_use]
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    #[must_use]
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    #[must_use]
+    pub const fn saturating_mul(self, factor: u32) -> Self {
+        Self(self.0.saturating_mul(factor))
+    }
+}
+
+impl From<Duration> for DurationSecs {
+    fn from(duration: Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl From<DurationSecs> for Duration {
+    fn from(duration: DurationSecs) -> Self {
+        duration.0
+    }
+}
+
+impl fmt::Display for DurationSecs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match u64::try_from(self.0.as_nanos()) {
+            Ok(nanos) => write_scaled(f, nanos, &DURATION_UNITS, "s"),
+            // Over five centuries; seconds are exact enough
+            Err(_) => write!(f, "{}s", self.0.as_secs()),
+        }
+    }
+}
+
+impl FromStr for DurationSecs {
+    type Err = UnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_scaled(s, "nanoseconds", |unit| {
+            Some(match unit {
+                "" | "s" | "sec" | "secs" => 1_000_000_000,
+                "ms" => 1_000_000,
+                "us" | "µs" => 1_000,
+                "ns" => 1,
+                "m" | "min" | "mins" => 60_000_000_000,
+                "h" => 3_600_000_000_000,
+                _ => return None,
+            })
+        })
+        .map(|nanos| Self(Duration::from_nanos(nanos)))
+    }
+}
+
+#[cfg(feature = "protocol")]
+mod serde_impls {
+    use super::{ByteSize, DurationSecs};
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::{Deserialize, Serialize, Serializer};
+    use std::fmt;
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    /// Takes an integer through `from_int` or a string through `FromStr`
+    struct IntOrStr<T> {
+        expecting: &'static str,
+        from_int: fn(u64) -> T,
+        kind: PhantomData<T>,
+    }
+
+    impl<T: FromStr<Err: fmt::Display>> Visitor<'_> for IntOrStr<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.expecting)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+            Ok((self.from_int)(value))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+            u64::try_from(value)
+                .map(self.from_int)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+            value.parse().map_err(E::custom)
+        }
+    }
+
+    impl Serialize for ByteSize {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_u64(self.0)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ByteSize {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(IntOrStr {
+                expecting: "a byte count or a size such as \"256MiB\"",
+                from_int: Self,
+                kind: PhantomData,
+            })
+        }
+    }
+
+    impl Serialize for DurationSecs {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.0.subsec_nanos() == 0 {
+                serializer.serialize_u64(self.0.as_secs())
+            } else {
+                serializer.collect_str(self)
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for DurationSecs {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(IntOrStr {
+                expecting: "a number of seconds or a duration such as \"1500ms\"",
+                from_int: Self::from_secs,
+                kind: PhantomData,
+            })
+        }
+    }
+}
@@ -1,6 +1,29 @@
-use crate::{pipe::ParentPipe, ExecutionResult, LeewardError, Result, SandboxConfig};
+use crate::config::{Interpreter, NetworkConfig, SchedPolicy, TMP_DIR};
+use crate::denial::DenialLog;
+use crate::isolation::fatal::{self, Stage, WorkerDeath};
+use crate::isolation::template::WORKSPACE_TMPFS_SIZE;
+use crate::isolation::{
+    ControlledNetwork, IsolationLayer, MountConfig, NamespaceConfig, NetworkNamespaceSetup,
+    NetworkSlot, NetworkSlots, RootTemplate,
+};
+use crate::network::{ConnectionTracker, InterfaceCounters};
+use crate::pipe::{ParentPipe, PipeGauge, PipePeaks, PipeWatch};
+use crate::policy::PolicyTrace;
+use crate::preempt::Preemption;
+use crate::protocol::InterpreterStamp;
+use crate::result::KilledBy;
+use crate::units::ByteSize;
+use crate::workspace::Workspace;
+use crate::{ExecutionResult, LeewardError, OutcomeCode, Result, SandboxConfig};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WorkerState {
     /// Ready to accept work
     Idle,
@@ -12,52 +35,1038 @@ pub enum WorkerState {
     Dead,
 }
 
+/// Internal timing breakdown reported by a worker, in microseconds
+///
+/// Setup fields are measured once at spawn and repeated with every
+/// execution; the rest describe the most recent execution. The pipe peaks
+/// are in bytes, sampled by the daemon rather than reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerTiming {
+    /// Entering namespaces
+    pub namespace_setup_us: u64,
+    /// Mount setup and `pivot_root`
+    pub mount_setup_us: u64,
+    /// Installing the seccomp filter
+    pub seccomp_setup_us: u64,
+    /// Applying Landlock rules
+    pub landlock_setup_us: u64,
+    /// Starting the interpreter process
+    pub python_import_us: u64,
+    /// Reading the code off the pipe, excluding idle wait
+    pub code_recv_us: u64,
+    /// Running the code
+    pub execution_us: u64,
+    /// Writing the result back to the daemon
+    pub result_send_us: u64,
+    /// Most bytes seen waiting in the code pipe
+    #[serde(default)]
+    pub code_pipe_peak: u64,
+    /// Most bytes seen waiting in the result pipe
+    #[serde(default)]
+    pub result_pipe_peak: u64,
+}
+
+impl WorkerTiming {
+    /// Record the setup time of an isolation layer by name
+    fn record_layer(&mut self, layer: &str, elapsed: Duration) {
+        let us = duration_us(elapsed);
+        match layer {
+            "namespaces" => self.namespace_setup_us = us,
+            "mounts" => self.mount_setup_us = us,
+            "seccomp" => self.seccomp_setup_us = us,
+            "landlock" => self.landlock_setup_us = us,
+            _ => {}
+        }
+    }
+
+    /// The timing with the peaks the daemon saw in the pipes added, if it
+    /// sampled them
+    fn with_pipe_peaks(self, peaks: Option<PipePeaks>) -> Self {
+        let peaks = peaks.unwrap_or_default();
+        Self {
+            code_pipe_peak: peaks.code as u64,
+            result_pipe_peak: peaks.result as u64,
+            ..self
+        }
+    }
+}
+
+/// Per-execution settings
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ExecuteOptions {
+    /// Maximum sockets the code may open when networking is enabled
+    pub max_connections: Option<u32>,
+    /// Overrides the config's timeout
+    pub timeout: Option<Duration>,
+    /// Dump a Python traceback to stderr shortly before the timeout kills the code
+    pub soft_timeout_traceback: bool,
+    /// Data fed to the code's stdin
+    pub stdin: Option<Vec<u8>>,
+    /// Environment variables added on top of the config's
+    pub env: Vec<(String, String)>,
+    /// Overrides the config's memory limit
+    pub memory_limit: Option<ByteSize>,
+    /// Overrides the config's nice value
+    pub nice: Option<i8>,
+    /// Overrides the config's scheduling policy
+    pub sched_policy: Option<SchedPolicy>,
+    /// Input files written under the config's workdir, by relative name
+    pub files: Vec<(String, Vec<u8>)>,
+    /// Overrides the config's timezone; must be a name accepted by
+    /// [`zoneinfo_path`](crate::config::zoneinfo_path)
+    pub timezone: Option<String>,
+    /// Program that runs the code
+    pub interpreter: Interpreter,
+    /// Arguments passed to the code after it
+    pub args: Vec<String>,
+    /// Files streamed into the workdir after the job, by relative name, in
+    /// full as they are when the execution starts
+    ///
+    /// Only [`Worker::execute`] sends them; running the interpreter
+    /// directly ignores them.
+    pub uploads: Vec<(String, Arc<std::fs::File>)>,
+    /// Let the program be frozen through [`Worker::preemption`] while it
+    /// runs; time spent frozen counts against neither its timeout nor its
+    /// duration
+    pub preemptible: bool,
+    /// Send no `SIGUSR1` for memory pressure, whatever the config's
+    /// [`pressure_signal_percent`](SandboxConfig::pressure_signal_percent)
+    pub suppress_pressure_signal: bool,
+    /// Id the daemon logs the execution under, for pipe stall logs
+    pub execution_id: Option<u64>,
+    /// Test hook: leave the program to the watchdog alone, as if the
+    /// worker's own timeout had failed
+    #[doc(hidden)]
+    pub skip_timeout: bool,
+}
+
+/// Work sent from the daemon to a worker over the code pipe
+///
+/// The daemon's side borrows the request's inputs to encode them; the
+/// worker decodes its own copy.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct WorkerJob<'a> {
+    code: Cow<'a, str>,
+    pub(crate) timeout: Duration,
+    /// When to dump a traceback, if requested
+    traceback_after: Option<Duration>,
+    pub(crate) stdin: Option<Cow<'a, [u8]>>,
+    env: Cow<'a, [(String, String)]>,
+    /// Address-space limit for the interpreter
+    memory_limit: Option<ByteSize>,
+    nice: Option<i8>,
+    sched_policy: Option<SchedPolicy>,
+    pub(crate) files: Cow<'a, [(String, Vec<u8>)]>,
+    /// Value for `TZ`
+    timezone: Cow<'a, str>,
+    interpreter: Interpreter,
+    args: Cow<'a, [String]>,
+    /// Names and lengths of the files streamed after the job, in order
+    pub(crate) uploads: Vec<(String, u64)>,
+    /// Watch the code pipe for freeze and thaw requests while running
+    preemptible: bool,
+    /// Absolute deadline the daemon set at dispatch, in nanoseconds on
+    /// `CLOCK_MONOTONIC`, which the worker shares with it
+    pub(crate) deadline_ns: Option<u64>,
+    /// How long past the deadline the watchdog fires
+    watchdog_margin: Duration,
+    skip_timeout: bool,
+    /// Watch the code pipe for memory pressure warnings while running
+    pressure_signal: bool,
+}
+
+impl<'a> WorkerJob<'a> {
+    pub(crate) fn new(
+        code: &'a str,
+        config: &'a SandboxConfig,
+        options: &'a ExecuteOptions,
+    ) -> Self {
+        Self::resolve(code, config, options, &mut PolicyTrace::off())
+    }
+
+    /// Merge `options` over `config`, noting in `trace` where each setting
+    /// came from
+    fn resolve(
+        code: &'a str,
+        config: &'a SandboxConfig,
+        options: &'a ExecuteOptions,
+        trace: &mut PolicyTrace,
+    ) -> Self {
+        let timeout = trace.pick(
+            "timeout",
+            options.timeout,
+            config.timeout,
+            |timeout| format!("{timeout:?}"),
+            |d| d.timeout == config.timeout,
+        );
+        let memory_limit = trace.pick(
+            "memory_limit",
+            options.memory_limit.map(Some),
+            config.memory_limit,
+            |limit| limit.map_or_else(|| "unlimited".into(), |limit| limit.to_string()),
+            |d| d.memory_limit == config.memory_limit,
+        );
+        let nice = trace.pick(
+            "nice",
+            options.nice.map(Some),
+            config.nice,
+            |nice| nice.map_or_else(|| "inherited".into(), |nice| nice.to_string()),
+            |d| d.nice == config.nice,
+        );
+        let sched_policy = trace.pick(
+            "sched_policy",
+            options.sched_policy.map(Some),
+            config.sched_policy,
+            |policy| policy.map_or_else(|| "inherited".into(), |policy| format!("{policy:?}")),
+            |d| d.sched_policy == config.sched_policy,
+        );
+        let timezone = trace.pick(
+            "timezone",
+            options.timezone.as_deref(),
+            config.timezone_name(),
+            |&zone| zone.to_owned(),
+            |d| d.timezone_name() == config.timezone_name(),
+        );
+        Self {
+            code: Cow::Borrowed(code),
+            timeout,
+            traceback_after: options
+                .soft_timeout_traceback
+                .then(|| timeout.saturating_sub(config.traceback_margin)),
+            stdin: options.stdin.as_deref().map(Cow::Borrowed),
+            env: Cow::Borrowed(&options.env),
+            memory_limit,
+            nice,
+            sched_policy,
+            files: Cow::Borrowed(&options.files),
+            timezone: Cow::Borrowed(timezone),
+            interpreter: options.interpreter,
+            args: Cow::Borrowed(&options.args),
+            uploads: Vec::new(),
+            preemptible: options.preemptible,
+            deadline_ns: None,
+            watchdog_margin: config.watchdog_margin,
+            skip_timeout: options.skip_timeout,
+            pressure_signal: config.pressure_signal_percent.is_some()
+                && memory_limit.is_some()
+                && !options.suppress_pressure_signal,
+        }
+    }
+}
+
+/// Note in `trace` where each setting an execution with `options` would
+/// run with under `config` came from, without running anything
+///
+/// Settings only the config decides, such as networking, are noted too.
+pub fn explain(config: &SandboxConfig, options: &ExecuteOptions, trace: &mut PolicyTrace) {
+    trace.configured(
+        "allow_network",
+        || config.allow_network.to_string(),
+        |d| d.allow_network == config.allow_network,
+    );
+    WorkerJob::resolve("", config, options, trace);
+}
+
+/// Messages sent from a worker to the daemon over the result pipe
+///
+/// After isolation setup the worker sends `Ready`. For every execution it
+/// then sends `Result` (or `StartupFailed`) followed by `Timing`, so the
+/// timing can include how long the result took to send. A worker about to
+/// exit on its own sends `Goodbye` between the two, then exits after
+/// `Timing`, so its pipe closing is expected. A worker that dies instead
+/// sends a [`fatal`] frame, which [`recv_control`] turns into
+/// [`LeewardError::WorkerDied`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ControlMessage {
+    /// Isolation is in place and the worker is waiting for code
+    Ready,
+    /// Outcome of an execution
+    Result(ExecutionResult),
+    /// The interpreter died before reaching the user code
+    StartupFailed(String),
+    /// Timing breakdown for the execution just reported
+    Timing(WorkerTiming),
+    /// The worker exits once this execution is reported
+    Goodbye(GoodbyeReason),
+}
+
+/// Why a worker exited on its own, sent in [`ControlMessage::Goodbye`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GoodbyeReason {
+    /// Its resident memory grew past [`SandboxConfig::worker_max_rss`]
+    MemoryGrown { rss: ByteSize, limit: ByteSize },
+}
+
+impl GoodbyeReason {
+    /// Label of every reason, as [`GoodbyeReason::kind`] gives it
+    pub const KINDS: [&'static str; 1] = ["memory_grown"];
+
+    /// Label used in metrics and logs
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::MemoryGrown { .. } => "memory_grown",
+        }
+    }
+}
+
+impl std::fmt::Display for GoodbyeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MemoryGrown { rss, limit } => {
+                write!(f, "resident memory grew to {rss}, past {limit}")
+            }
+        }
+    }
+}
+
+/// Prefix of the cgroup each worker process is accounted in, followed by
+/// its [`WorkerUid`]
+pub const CGROUP_PREFIX: &str = "worker-";
+
+/// Names one worker process for good, unlike [`Worker::id`], the pool
+/// slot its replacements reuse
+///
+/// `seq` counts up from 1 for each process spawned under `boot_id` and is
+/// never handed out twice, so a resource named after a uid belongs to that
+/// one process, and one named under another boot id to none still running.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct WorkerUid {
+    pub boot_id: String,
+    pub seq: u64,
+}
+
+impl WorkerUid {
+    /// Name of the cgroup this process is accounted in
+    #[must_use]
+    pub fn cgroup_name(&self) -> String {
+        format!("{CGROUP_PREFIX}{self}")
+    }
+
+    /// The uid a cgroup named by [`Self::cgroup_name`] belongs to
+    #[must_use]
+    pub fn from_cgroup_name(name: &str) -> Option<Self> {
+        name.strip_prefix(CGROUP_PREFIX)?.parse().ok()
+    }
+}
+
+impl std::fmt::Display for WorkerUid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.boot_id, self.seq)
+    }
+}
+
+impl std::str::FromStr for WorkerUid {
+    type Err = LeewardError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.rsplit_once('-')
+            .filter(|(boot_id, _)| !boot_id.is_empty())
+            .and_then(|(boot_id, seq)| {
+                Some(Self {
+                    boot_id: boot_id.to_owned(),
+                    seq: seq.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| LeewardError::Config(format!("not a worker uid: {s:?}")))
+    }
+}
+
+/// Hands out the [`WorkerUid`]s of one daemon boot
+#[derive(Debug)]
+pub struct WorkerUids {
+    boot_id: String,
+    next: AtomicU64,
+}
+
+impl WorkerUids {
+    /// Uids under `boot_id`, which no other boot may share
+    #[must_use]
+    pub fn new(boot_id: impl Into<String>) -> Self {
+        Self {
+            boot_id: boot_id.into(),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    /// Uids under a random boot id
+    #[must_use]
+    pub fn random() -> Self {
+        let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/uuid").map_or_else(
+            |_| {
+                let nanos = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                format!("{:x}-{nanos:x}", std::process::id())
+            },
+            |uuid| uuid.trim().to_owned(),
+        );
+        Self::new(boot_id)
+    }
+
+    /// The ones workers not given any draw from, shared by the process
+    #[must_use]
+    pub fn process() -> Arc<Self> {
+        static PROCESS: OnceLock<Arc<WorkerUids>> = OnceLock::new();
+        Arc::clone(PROCESS.get_or_init(|| Arc::new(Self::random())))
+    }
+
+    #[must_use]
+    pub fn boot_id(&self) -> &str {
+        &self.boot_id
+    }
+
+    /// A uid never handed out before
+    #[must_use]
+    pub fn next(&self) -> WorkerUid {
+        WorkerUid {
+            boot_id: self.boot_id.clone(),
+            seq: self.next.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The `seq` the next uid will have; every uid handed out so far is
+    /// below it
+    #[must_use]
+    pub fn peek(&self) -> u64 {
+        self.next.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Debug)]
 pub struct Worker {
+    /// Slot in the pool, kept by each process that replaces this one
     pub id: u32,
+    /// The running process, or the last one
+    pub uid: Option<WorkerUid>,
     pub state: WorkerState,
     pub pid: Option<i32>,
     pub execution_count: u64,
+    /// Timing breakdown of the most recent execution
+    pub last_timing: Option<WorkerTiming>,
+    /// Why the process is exiting after the most recent execution, if it
+    /// said goodbye
+    goodbye: Option<GoodbyeReason>,
+    /// Fingerprint of the config the worker is spawned with
+    pub config_fingerprint: String,
+    /// Interpreter binary the running process was spawned from
+    pub interpreter: Option<InterpreterStamp>,
     config: SandboxConfig,
+    template: Option<Arc<RootTemplate>>,
     pipe: Option<ParentPipe>,
+    /// Buffer jobs are encoded into, kept between executions unless a big
+    /// one grew it past [`MAX_RETAINED_FRAME`]
+    frame: Vec<u8>,
+    connections: Arc<ConnectionTracker>,
+    denials: Arc<DenialLog>,
+    preemption: Arc<Preemption>,
+    /// Bytes waiting in the pipes, sampled while executing
+    pipes: Arc<PipeGauge>,
+    /// Syscalls an embedder asked to decide on themselves
+    notify_syscalls: Vec<i64>,
+    /// Listener stream waiting for the embedder to take it
+    #[cfg(feature = "seccomp")]
+    notifications: Option<crate::isolation::seccomp::NotificationStream>,
+    /// Answers the listener of each process unless the embedder takes it
+    #[cfg(feature = "seccomp")]
+    supervisor: crate::isolation::seccomp::Supervisor,
+    /// Whether every syscall is counted instead of filtered, see
+    /// [`Worker::observed`]
+    observe: bool,
+    /// Counts of the syscalls made under the running process, once its
+    /// listener is handed over
+    #[cfg(feature = "seccomp")]
+    observer: Option<std::thread::JoinHandle<crate::profile::WorkloadProfile>>,
+    /// Where the uid of each process of this worker comes from
+    uids: Arc<WorkerUids>,
+    /// Where the slot of a controlled network's link comes from
+    network_slots: Arc<NetworkSlots>,
+    /// The running process's slot of the egress network
+    network_slot: Option<NetworkSlot>,
+    /// Where each process of this worker gets a cgroup of its own
+    #[cfg(feature = "cgroups")]
+    cgroup_root: Option<std::path::PathBuf>,
+    /// The running process's cgroup
+    #[cfg(feature = "cgroups")]
+    cgroup: Option<crate::isolation::CgroupHandle>,
 }
 
 impl Worker {
+    #[must_use]
     pub fn new(id: u32, config: SandboxConfig) -> Self {
+        let connections = Arc::new(ConnectionTracker::default());
         Self {
             id,
+            uid: None,
             state: WorkerState::Dead,
             pid: None,
             execution_count: 0,
+            last_timing: None,
+            goodbye: None,
+            config_fingerprint: config.fingerprint(),
+            interpreter: None,
             config,
+            template: None,
             pipe: None,
+            frame: Vec::new(),
+            #[cfg(feature = "seccomp")]
+            supervisor: crate::isolation::seccomp::Supervisor::new(Arc::clone(&connections)),
+            observe: false,
+            #[cfg(feature = "seccomp")]
+            observer: None,
+            connections,
+            denials: Arc::new(DenialLog::default()),
+            preemption: Arc::new(Preemption::default()),
+            pipes: Arc::new(PipeGauge::default()),
+            notify_syscalls: Vec::new(),
+            #[cfg(feature = "seccomp")]
+            notifications: None,
+            uids: WorkerUids::process(),
+            network_slots: NetworkSlots::process(),
+            network_slot: None,
+            #[cfg(feature = "cgroups")]
+            cgroup_root: None,
+            #[cfg(feature = "cgroups")]
+            cgroup: None,
+        }
+    }
+
+    /// Draw the uid of each process of this worker from `uids`
+    #[must_use]
+    pub fn with_uids(mut self, uids: Arc<WorkerUids>) -> Self {
+        self.uids = uids;
+        self
+    }
+
+    /// Lease each process of this worker its slot of a controlled network
+    /// from `slots`, which should be shared by every worker on the same
+    /// egress network
+    #[must_use]
+    pub fn with_network_slots(mut self, slots: Arc<NetworkSlots>) -> Self {
+        self.network_slots = slots;
+        self
+    }
+
+    /// Have the built-in supervisor ask `policy` about the syscalls it
+    /// answers, see [`Supervisor::with_policy`](crate::isolation::seccomp::Supervisor::with_policy)
+    ///
+    /// Takes effect from the next spawn or recycle.
+    #[cfg(feature = "seccomp")]
+    #[must_use]
+    pub fn with_seccomp_policy(mut self, policy: crate::isolation::seccomp::Policy) -> Self {
+        self.supervisor = self.supervisor.with_policy(policy);
+        self
+    }
+
+    /// Use `config` from the next spawn or recycle on
+    pub fn reconfigure(&mut self, config: SandboxConfig) {
+        self.config_fingerprint = config.fingerprint();
+        self.config = config;
+    }
+
+    /// Root workers in a shared template instead of the host filesystem
+    #[must_use]
+    pub fn with_root_template(mut self, template: Arc<RootTemplate>) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Root workers in `template`, or the host filesystem, from the next
+    /// spawn or recycle on
+    pub fn set_root_template(&mut self, template: Option<Arc<RootTemplate>>) {
+        self.template = template;
+    }
+
+    /// Account each process of this worker in a cgroup of its own under
+    /// `root`, named after its uid, starting with the running one
+    ///
+    /// Results then carry the cgroup's peak memory, CPU time and OOM kills,
+    /// and the cgroup holds the sandbox's CPU share and process limit; see
+    /// [`crate::isolation::cgroups`]. Each cgroup is removed once its
+    /// process is gone.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Cgroup`] or [`LeewardError::CgroupUnavailable`] if the
+    /// running process's cgroup cannot be created or joined.
+    #[cfg(feature = "cgroups")]
+    pub fn set_cgroup_root(&mut self, root: impl Into<std::path::PathBuf>) -> Result<()> {
+        self.leave_cgroup();
+        let root = self.cgroup_root.insert(root.into());
+        if let (Some(pid), Some(uid)) = (self.pid, &self.uid) {
+            let cgroup = crate::isolation::CgroupHandle::create(root, &uid.cgroup_name())?;
+            self.limit_new_cgroup(&cgroup);
+            cgroup.add_process(pid)?;
+            self.cgroup = Some(cgroup);
+        }
+        Ok(())
+    }
+
+    /// The running process's cgroup, if it has one
+    #[cfg(feature = "cgroups")]
+    #[must_use]
+    pub const fn cgroup(&self) -> Option<&crate::isolation::CgroupHandle> {
+        self.cgroup.as_ref()
+    }
+
+    /// Create the cgroup of the process about to be spawned, if the worker
+    /// has a cgroup root, returning its directory to clone into
+    #[cfg(feature = "cgroups")]
+    fn open_cgroup(&mut self) -> Option<std::os::fd::OwnedFd> {
+        let (Some(root), Some(uid)) = (&self.cgroup_root, &self.uid) else {
+            return None;
+        };
+        let opened = crate::isolation::CgroupHandle::create(root, &uid.cgroup_name())
+            .and_then(|cgroup| cgroup.directory().map(|dir| (cgroup, dir)));
+        match opened {
+            Ok((cgroup, dir)) => {
+                self.limit_new_cgroup(&cgroup);
+                self.cgroup = Some(cgroup);
+                Some(dir)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = %uid,
+                    error = %e,
+                    "worker left out of its cgroup"
+                );
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "cgroups"))]
+    #[allow(clippy::unused_self)]
+    const fn open_cgroup(&self) -> Option<std::os::fd::OwnedFd> {
+        None
+    }
+
+    /// Give a new cgroup no swap and the sandbox's CPU share and process
+    /// limit
+    #[cfg(feature = "cgroups")]
+    fn limit_new_cgroup(&self, cgroup: &crate::isolation::CgroupHandle) {
+        if let Err(e) = cgroup.set_swap_max(Some(ByteSize::from_bytes(0))) {
+            tracing::debug!(
+                worker_id = self.id,
+                worker_uid = self.uid_field(),
+                error = %e,
+                "worker cgroup may swap"
+            );
+        }
+        if let Some((quota, period)) = self.config.cpu_quota() {
+            if let Err(e) = cgroup.set_cpu_quota(period, quota) {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    error = %e,
+                    "CPU limit not applied to the cgroup"
+                );
+            }
+        }
+        if let Some(limit) = self.config.max_pids {
+            if let Err(e) = cgroup.set_pids_max(Some(limit)) {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    error = %e,
+                    "process limit not applied to the cgroup"
+                );
+            }
+        }
+    }
+
+    /// Move a newly spawned process into its cgroup, unless it was cloned
+    /// into it
+    #[cfg(feature = "cgroups")]
+    fn join_cgroup(&mut self, pid: i32, cloned_into: bool) {
+        let Some(cgroup) = self.cgroup.as_ref().filter(|_| !cloned_into) else {
+            return;
+        };
+        if let Err(e) = cgroup.add_process(pid) {
+            tracing::warn!(
+                worker_id = self.id,
+                worker_uid = self.uid_field(),
+                error = %e,
+                "worker left out of its cgroup"
+            );
+            self.leave_cgroup();
+        }
+    }
+
+    #[cfg(not(feature = "cgroups"))]
+    #[allow(clippy::unused_self)]
+    const fn join_cgroup(&self, _pid: i32, _cloned_into: bool) {}
+
+    /// Remove the cgroup of a process that is gone
+    ///
+    /// One the kernel still counts as populated is left to
+    /// [`crate::isolation::cgroups::remove_stale`].
+    #[cfg(feature = "cgroups")]
+    fn leave_cgroup(&mut self) {
+        if let Some(cgroup) = self.cgroup.take() {
+            if let Err(e) = cgroup.remove() {
+                tracing::debug!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    error = %e,
+                    "cgroup left for reconciliation"
+                );
+            }
+        }
+    }
+
+    #[cfg(not(feature = "cgroups"))]
+    #[allow(clippy::unused_self)]
+    const fn leave_cgroup(&self) {}
+
+    /// Start measuring an execution in the worker's cgroup, if it has one
+    #[cfg(feature = "cgroups")]
+    fn watch_cgroup(&self) -> Option<crate::isolation::cgroups::PeakWatch> {
+        let cgroup = self.cgroup.as_ref()?;
+        cgroup
+            .watch()
+            .map_err(|e| {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    error = %e,
+                    "execution not measured in its cgroup"
+                );
+            })
+            .ok()
+    }
+
+    /// Fill in what the cgroup saw of an execution, failing it if it hit
+    /// the process limit
+    #[cfg(feature = "cgroups")]
+    fn finish_watch(
+        &self,
+        watch: crate::isolation::cgroups::PeakWatch,
+        result: &mut ExecutionResult,
+    ) -> Result<()> {
+        let usage = match watch.finish() {
+            Ok(usage) => usage,
+            Err(e) => {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    error = %e,
+                    "execution not measured in its cgroup"
+                );
+                return Ok(());
+            }
+        };
+        result.memory_peak = usage.memory_peak;
+        result.oom_killed = usage.oom_killed;
+        if let Some(cpu_time_us) = usage.cpu_time_us {
+            result.cpu_time_us = cpu_time_us;
         }
+        if usage.pids_limited {
+            return Err(LeewardError::Execution(self.config.max_pids.map_or_else(
+                || "execution hit its cgroup's process limit (pids.max)".into(),
+                |limit| format!("execution hit the limit of {limit} processes (pids.max)"),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Warn the program `job` runs through `pipe` when the worker's cgroup
+    /// nears the job's memory limit, if the config asks for that
+    ///
+    /// A watch that cannot start is logged and skipped; the limit still
+    /// holds without it.
+    #[cfg(feature = "cgroups")]
+    fn watch_pressure(
+        id: u32,
+        cgroup: Option<&crate::isolation::CgroupHandle>,
+        config: &SandboxConfig,
+        job: &WorkerJob,
+        pipe: &ParentPipe,
+    ) -> Option<crate::isolation::PressureWatch> {
+        use std::io::Write;
+
+        let (cgroup, percent, limit) =
+            match (cgroup, config.pressure_signal_percent, job.memory_limit) {
+                (Some(cgroup), Some(percent), Some(limit)) if job.pressure_signal => {
+                    (cgroup, percent, limit)
+                }
+                _ => return None,
+            };
+        let threshold = limit.bytes().saturating_mul(u64::from(percent)) / 100;
+        let watch = pipe.control().and_then(|mut control| {
+            cgroup.watch_pressure(threshold, move |current| {
+                tracing::debug!(
+                    worker_id = id,
+                    current,
+                    threshold,
+                    "warning execution of memory pressure"
+                );
+                // A worker that has gone away has nothing to warn
+                let _ = control.write_all(&[crate::pipe::PRESSURE]);
+            })
+        });
+        watch
+            .map_err(|e| {
+                tracing::warn!(
+                    worker_id = id,
+                    error = %e,
+                    "execution not watched for memory pressure"
+                );
+            })
+            .ok()
+    }
+
+    /// Start sampling the worker's pipes for an execution, if they can be
+    fn watch_pipes(&self, execution_id: Option<u64>) -> Option<PipeWatch> {
+        let pipe = self.pipe.as_ref()?;
+        pipe.watch(
+            Arc::clone(&self.pipes),
+            self.config.pipe_stall_threshold,
+            self.id,
+            execution_id,
+        )
+        .map_err(|e| {
+            tracing::warn!(
+                worker_id = self.id,
+                worker_uid = self.uid_field(),
+                error = %e,
+                "worker pipes not sampled"
+            );
+        })
+        .ok()
+    }
+
+    /// Hold the worker's cgroup, if it has one, to an execution's memory
+    /// limit
+    #[cfg(feature = "cgroups")]
+    fn limit_cgroup(&self, limit: Option<ByteSize>) {
+        let Some(cgroup) = &self.cgroup else {
+            return;
+        };
+        if let Err(e) = cgroup.set_memory_max(limit) {
+            tracing::warn!(
+                worker_id = self.id,
+                worker_uid = self.uid_field(),
+                error = %e,
+                "memory limit not applied to the cgroup"
+            );
+        }
+    }
+
+    /// The uid as a log field
+    fn uid_field(&self) -> Option<tracing::field::DisplayValue<&WorkerUid>> {
+        self.uid.as_ref().map(tracing::field::display)
+    }
+
+    /// Connection accounting shared with whoever observes socket creation
+    #[must_use]
+    pub fn connections(&self) -> Arc<ConnectionTracker> {
+        Arc::clone(&self.connections)
+    }
+
+    /// Syscalls refused during the current execution, recorded by whoever
+    /// answers the worker's notifications
+    #[must_use]
+    pub fn denials(&self) -> Arc<DenialLog> {
+        Arc::clone(&self.denials)
+    }
+
+    /// Freezes and thaws this worker's preemptible executions
+    #[must_use]
+    pub fn preemption(&self) -> Arc<Preemption> {
+        Arc::clone(&self.preemption)
+    }
+
+    /// Bytes waiting in this worker's pipes, as last sampled during an
+    /// execution
+    #[must_use]
+    pub fn pipe_gauge(&self) -> Arc<PipeGauge> {
+        Arc::clone(&self.pipes)
+    }
+
+    /// Route `syscalls` to a stream the caller drives instead of the built-in supervisor
+    ///
+    /// After each spawn or recycle, [`Worker::take_notifications`] returns
+    /// the stream. It also carries the connection syscalls when networking
+    /// is enabled; pass those to
+    /// [`Supervisor::handle`](crate::isolation::seccomp::Supervisor::handle)
+    /// with [`Worker::connections`] to keep connection limits working.
+    /// Syscalls denied through the stream end up in the result's
+    /// [`denials`](crate::ExecutionResult::denials).
+    #[cfg(feature = "seccomp")]
+    #[must_use]
+    pub fn with_notifications(mut self, syscalls: Vec<i64>) -> Self {
+        self.notify_syscalls = syscalls;
+        self
+    }
+
+    /// Count every syscall the code run makes instead of filtering them,
+    /// for [`profile_worker`](crate::profile::profile_worker)
+    ///
+    /// The worker keeps its namespaces, mounts and cgroup, but applies
+    /// neither Landlock nor a syscall filter that denies anything, so it is
+    /// only for code trusted that far. Takes effect from the next spawn.
+    #[cfg(feature = "seccomp")]
+    #[must_use]
+    pub const fn observed(mut self) -> Self {
+        self.observe = true;
+        self
+    }
+
+    /// Take what was counted of the current worker process, see
+    /// [`Worker::observed`]
+    ///
+    /// The thread returns the counts once the process and everything it
+    /// started are gone.
+    #[cfg(feature = "seccomp")]
+    pub(crate) const fn take_observer(
+        &mut self,
+    ) -> Option<std::thread::JoinHandle<crate::profile::WorkloadProfile>> {
+        self.observer.take()
+    }
+
+    /// Take the notification stream of the current worker process
+    ///
+    /// Only set when [`Worker::with_notifications`] was used. Routed
+    /// syscalls block until answered, so drive the stream before executing
+    /// code; dropping it fails them with `ENOSYS`.
+    #[cfg(feature = "seccomp")]
+    pub const fn take_notifications(
+        &mut self,
+    ) -> Option<crate::isolation::seccomp::NotificationStream> {
+        self.notifications.take()
+    }
+
+    /// Config the worker is spawned with
+    #[must_use]
+    pub const fn config(&self) -> &SandboxConfig {
+        &self.config
     }
 
+    /// Start the worker process and wait until it is isolated and ready
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the worker dies or says anything but that
+    /// it is ready while setting up, and [`LeewardError::Io`] if its pipes
+    /// cannot be created.
     pub fn spawn(&mut self) -> Result<()> {
         use crate::isolation::clone3;
-        use crate::pipe::WorkerPipe;
+        use std::os::fd::{AsFd, AsRawFd};
 
-        tracing::info!(worker_id = self.id, "spawning pre-forked worker");
+        let uid = self.uids.next();
+        tracing::info!(worker_id = self.id, worker_uid = %uid, "spawning pre-forked worker");
+        self.uid = Some(uid);
+        self.interpreter = None;
+        let interpreter = InterpreterStamp::of(&self.config.python_path);
 
-        // Create pipes for communication
-        let worker_pipe = WorkerPipe::new()?;
-        let (parent_pipe, child_pipe) = worker_pipe.split();
+        let (parent_pipe, child_pipe) = self.open_pipes()?.split();
 
         // Get namespace flags (but don't include them in clone3, we'll set them inside)
         let namespace_flags = 0; // We'll enter namespaces from inside the worker
         let config = self.config.clone();
+        let template = self.template.clone();
 
-        let pid = clone3::clone_worker(namespace_flags, move || {
-            worker_main(child_pipe, &config)
-        })?;
+        // The worker hands its seccomp listener back over this socket
+        let (listener_rx, channel) = std::os::unix::net::UnixStream::pair()?;
+        let listener = ListenerRequest {
+            syscalls: self.routed_syscalls(),
+            channel,
+            observe: self.observe,
+        };
+        #[cfg(feature = "seccomp")]
+        let observe = self.observe;
+
+        let cgroup = self.open_cgroup();
+        // A controlled network's link takes a slot of the egress network
+        // for as long as this process lives
+        self.network_slot = None;
+        let network = ControlledNetwork::for_worker(&self.config, 0).map(|network| {
+            let slot = self.network_slots.lease();
+            let network = network.in_slot(slot.get());
+            self.network_slot = Some(slot);
+            network
+        });
+        let worker_network = network.clone();
+        let child = move || {
+            // Nothing of the daemon's, its sockets and listeners or the cgroup
+            // directory on the host, has a place in the sandbox
+            let mut keep = vec![
+                child_pipe.control().as_raw_fd(),
+                child_pipe.result_tx_fd(),
+                listener.channel.as_raw_fd(),
+            ];
+            keep.extend(template.as_deref().map(RootTemplate::namespace_fd));
+            clone3::close_inherited(&keep);
+            worker_main(child_pipe, &config, template, listener, worker_network)
+        };
+        // Except a controlled network's namespace, which the worker must
+        // already be in for its link to be created from here
+        let cloned = match network {
+            Some(network) => clone3::clone_worker_with(
+                namespace_flags | libc::CLONE_NEWNET as u64,
+                cgroup.as_ref().map(AsFd::as_fd),
+                |pid| network.setup_host_side(pid),
+                child,
+            ),
+            None => {
+                clone3::clone_worker_into(namespace_flags, cgroup.as_ref().map(AsFd::as_fd), child)
+            }
+        };
+        drop(cgroup);
+        let (pid, in_cgroup) = match cloned {
+            Ok(cloned) => cloned,
+            Err(e) => {
+                self.leave_cgroup();
+                self.network_slot = None;
+                return Err(e);
+            }
+        };
 
         self.pid = Some(pid);
-        self.pipe = Some(parent_pipe);
+        self.join_cgroup(pid, in_cgroup);
+        self.preemption.attach(parent_pipe.control()?);
+        let pipe = self.pipe.insert(parent_pipe);
+
+        // Every syscall of an observed worker blocks from its seccomp layer
+        // on, so the listener is answered before it says it is ready
+        #[cfg(feature = "seccomp")]
+        let observer = match observe
+            .then(|| crate::profile::observe_worker(&listener_rx, pid))
+            .transpose()
+        {
+            Ok(observer) => observer,
+            Err(e) => {
+                let e = self.reap(e);
+                return Err(self.abandon(pid, e));
+            }
+        };
+        // Wait for isolation setup so a broken worker never looks idle
+        let setup = match recv_control(pipe) {
+            Ok(ControlMessage::Ready) => Ok(()),
+            Ok(_) => Err(LeewardError::Execution(
+                "unexpected message during setup".into(),
+            )),
+            Err(e) => Err(self.reap(e)),
+        };
+        if let Err(e) = setup {
+            return Err(self.abandon(pid, e));
+        }
+        #[cfg(feature = "seccomp")]
+        {
+            self.observer = observer;
+        }
+        self.attach_listener(&listener_rx)?;
         self.state = WorkerState::Idle;
+        self.interpreter = interpreter;
 
         tracing::info!(
             worker_id = self.id,
+            worker_uid = self.uid_field(),
             pid = pid,
             "worker spawned and ready"
         );
@@ -65,7 +1074,30 @@ impl Worker {
         Ok(())
     }
 
-    pub fn execute(&mut self, code: &str) -> Result<ExecutionResult> {
+    /// Create the pipes to a new worker process, sized as the config says
+    fn open_pipes(&self) -> Result<crate::pipe::WorkerPipe> {
+        let pipes = crate::pipe::WorkerPipe::new()?;
+        if let Some(size) = self.config.pipe_buffer_size {
+            if let Err(e) = pipes.resize(size) {
+                tracing::warn!(
+                    worker_id = self.id,
+                    %size,
+                    error = %e,
+                    "worker pipes left at the kernel's size"
+                );
+            }
+        }
+        Ok(pipes)
+    }
+
+    /// Execute code in the worker
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Execution`] if the worker is not idle or dies
+    /// mid-execution, and [`LeewardError::Config`] if the interpreter cannot
+    /// start under the memory limit.
+    pub fn execute(&mut self, code: &str, options: &ExecuteOptions) -> Result<ExecutionResult> {
         if self.state != WorkerState::Idle {
             return Err(LeewardError::Execution(format!(
                 "worker {} is not idle (state: {:?})",
@@ -73,26 +1105,110 @@ impl Worker {
             )));
         }
 
+        // Interface counters are read from the host through the worker's
+        // /proc entry, which reflects the network namespace it lives in
+        let counters_before = self.network_counters();
+        #[cfg(feature = "cgroups")]
+        let watch = self.watch_cgroup();
+
+        let mut job = WorkerJob::new(code, &self.config, options);
+        job.deadline_ns = Some(monotonic_ns().saturating_add(duration_ns(job.timeout)));
+        #[cfg(feature = "cgroups")]
+        self.limit_cgroup(job.memory_limit);
+        for (name, file) in &options.uploads {
+            job.uploads.push((name.clone(), file.metadata()?.len()));
+        }
+
+        let occupancy = self.watch_pipes(options.execution_id);
         let pipe = self
             .pipe
             .as_mut()
             .ok_or_else(|| LeewardError::Execution("worker pipe not initialized".into()))?;
 
         self.state = WorkerState::Busy;
-        tracing::debug!(worker_id = self.id, code_len = code.len(), "sending code to worker");
+        tracing::debug!(
+            worker_id = self.id,
+            worker_uid = self.uid.as_ref().map(tracing::field::display),
+            code_len = code.len(),
+            "sending code to worker"
+        );
+
+        self.connections.begin(options.max_connections);
+        self.denials.begin();
 
-        pipe.send_code(code.as_bytes())?;
-        let result_bytes = pipe.recv_result()?;
+        send_job(pipe, &mut self.frame, &job)?;
+        let streamed = options
+            .uploads
+            .iter()
+            .zip(&job.uploads)
+            .try_for_each(|((_, file), (_, len))| pipe.send_stream(file, *len));
+        if let Err(e) = streamed {
+            // Left partway through a stream, the worker can never get back
+            // in step; the failure may be ours, reading the file
+            if let Some(pid) = self.pid.take() {
+                // SAFETY: Killing and reaping our own child
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, std::ptr::null_mut(), 0);
+                }
+                self.network_slot = None;
+            }
+            return Err(e);
+        }
 
-        // MessagePack deserialization
-        let result: ExecutionResult = rmp_serde::from_slice(&result_bytes)
-            .map_err(|e| LeewardError::Execution(format!("failed to deserialize result: {}", e)))?;
+        // Only now is the worker past the streams, reading control bytes
+        if options.preemptible {
+            let connections = self
+                .config
+                .allow_network
+                .then(|| Arc::clone(&self.connections));
+            self.preemption.arm(connections);
+        }
+        #[cfg(feature = "cgroups")]
+        let pressure =
+            Self::watch_pressure(self.id, self.cgroup.as_ref(), &self.config, &job, pipe);
+        let received = recv_outcome(pipe);
+        self.preemption.disarm();
+        #[cfg(feature = "cgroups")]
+        let pressure_memory = pressure.and_then(crate::isolation::PressureWatch::stop);
+        let (result, goodbye, timing) = match received {
+            Ok(messages) => messages,
+            Err(e) => return Err(self.reap(e)),
+        };
+        self.goodbye = goodbye;
+        let outcome = match result {
+            ControlMessage::Result(result) => Ok(result),
+            ControlMessage::StartupFailed(message) => Err(LeewardError::Config(message)),
+            _ => return Err(LeewardError::Execution("worker sent no result".into())),
+        };
+        match timing {
+            ControlMessage::Timing(timing) => {
+                self.last_timing = Some(timing.with_pipe_peaks(occupancy.map(PipeWatch::stop)));
+            }
+            _ => return Err(LeewardError::Execution("worker sent no timing".into())),
+        }
 
         self.execution_count += 1;
         self.state = WorkerState::Idle;
 
+        // The worker itself survives a failed interpreter start
+        let mut result = outcome?;
+        if let (Some(before), Some(after)) = (counters_before, self.network_counters()) {
+            result.network = Some(after.usage_since(&before, &self.connections));
+        }
+        result.denials = self.denials.take();
+        result.debug = self.config.debug;
+        #[cfg(feature = "cgroups")]
+        {
+            result.pressure_signal_memory = pressure_memory.filter(|_| result.pressure_signal_sent);
+            if let Some(watch) = watch {
+                self.finish_watch(watch, &mut result)?;
+            }
+        }
+
         tracing::debug!(
             worker_id = self.id,
+            worker_uid = self.uid_field(),
             execution_count = self.execution_count,
             "execution completed"
         );
@@ -100,169 +1216,1703 @@ impl Worker {
         Ok(result)
     }
 
+    /// Why the process is exiting, if it said goodbye after the execution
+    /// just run, so it can be replaced
+    pub const fn take_goodbye(&mut self) -> Option<GoodbyeReason> {
+        self.goodbye.take()
+    }
+
+    /// Whether an idle worker's process has died, found without blocking
+    ///
+    /// A worker only exits on its own after saying goodbye with an
+    /// execution's result, so an idle one whose pipe closes, or that sends
+    /// anything at all, crashed. Returns why, with the stage it died in if
+    /// its last words or exit status tell, and leaves the worker `Dead`.
+    pub fn check_idle(&mut self) -> Option<LeewardError> {
+        if self.state != WorkerState::Idle {
+            return None;
+        }
+        let pipe = self.pipe.as_mut()?;
+        let mut poll = libc::pollfd {
+            fd: pipe.result_rx_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: Polling one fd we own, without waiting
+        if unsafe { libc::poll(&raw mut poll, 1, 0) } <= 0 {
+            return None;
+        }
+
+        let error = match recv_control(pipe) {
+            Ok(message) => LeewardError::Execution(format!("idle worker sent {message:?}")),
+            Err(e) => self.reap(e),
+        };
+        self.stop();
+        Some(error)
+    }
+
+    /// Kill the worker process and start a new one in its place
+    ///
+    /// # Errors
+    ///
+    /// As [`Worker::spawn`].
     pub fn recycle(&mut self) -> Result<()> {
-        tracing::info!(worker_id = self.id, "recycling worker");
+        tracing::info!(
+            worker_id = self.id,
+            worker_uid = self.uid_field(),
+            "recycling worker"
+        );
         self.state = WorkerState::Recycling;
 
         if let Some(pid) = self.pid {
-            unsafe {
-                libc::kill(pid, libc::SIGKILL);
-            }
+            self.kill(pid);
         }
 
         self.pipe = None;
+        self.preemption.detach();
         self.pid = None;
+        self.leave_cgroup();
+        self.network_slot = None;
         self.execution_count = 0;
+        self.last_timing = None;
+        self.goodbye = None;
 
         self.spawn()
     }
 
-    #[must_use]
-    pub fn should_recycle(&self, max_executions: u64) -> bool {
-        self.execution_count >= max_executions
+    /// Kill and reap the worker process, for a worker that is not reused
+    pub fn stop(&mut self) {
+        if let Some(pid) = self.pid.take() {
+            self.kill(pid);
+        }
+        self.pipe = None;
+        self.preemption.detach();
+        self.leave_cgroup();
+        self.network_slot = None;
+        self.state = WorkerState::Dead;
     }
 
-    fn config_to_namespace_flags(&self) -> u64 {
-        use nix::sched::CloneFlags;
-
-        let mut flags = CloneFlags::CLONE_NEWUSER
-            | CloneFlags::CLONE_NEWPID
-            | CloneFlags::CLONE_NEWNS
-            | CloneFlags::CLONE_NEWIPC
-            | CloneFlags::CLONE_NEWUTS;
-
-        if !self.config.allow_network {
-            flags |= CloneFlags::CLONE_NEWNET;
+    /// Kill and reap the worker process `pid`
+    ///
+    /// With a cgroup, the process is frozen first and whatever it had
+    /// written to the result pipe is thrown away, so it dies at a point
+    /// where it writes nothing more, to the pipe or to a shared memory slot.
+    fn kill(&mut self, pid: i32) {
+        #[cfg(feature = "cgroups")]
+        if let Some(cgroup) = &self.cgroup {
+            match cgroup.freeze() {
+                Ok(()) => {
+                    let discarded = self.pipe.as_mut().map_or(0, ParentPipe::discard_results);
+                    if discarded > 0 {
+                        tracing::debug!(
+                            worker_id = self.id,
+                            worker_uid = self.uid_field(),
+                            discarded,
+                            "discarded output of a killed worker"
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        worker_id = self.id,
+                        worker_uid = self.uid_field(),
+                        error = %e,
+                        "worker killed without freezing it"
+                    );
+                }
+            }
+        }
+        // SAFETY: Killing and reaping our own child, so its cgroup is
+        // empty to remove; SIGKILL reaches frozen processes too
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
         }
-
-        flags.bits() as u64
     }
-}
-
-fn worker_main(mut pipe: crate::pipe::ChildPipe, config: &SandboxConfig) -> Result<()> {
-    use crate::isolation::{LandlockConfig, SeccompConfig, NamespaceConfig};
-
-    tracing::debug!("worker process starting isolation setup");
-
-    // Step 1: Setup namespaces (critical for security)
-    let namespace_config = NamespaceConfig {
-        user: false,  // User namespace needs UID mapping setup
-        pid: true,    // Isolate process tree
-        mount: true,  // Isolate filesystem
-        net: !config.allow_network,  // Network isolation
-        ipc: true,    // IPC isolation
-        uts: true,    // Hostname isolation
-    };
-
-    namespace_config.enter()?;
-    tracing::info!("namespaces configured");
 
-    // Step 2: Apply Landlock filesystem restrictions (if available)
-    // Landlock requires Linux 5.13+, but that's okay - we try it
-    let mut landlock = LandlockConfig::default();
-
-    // Add Python path and libraries as executable
-    if let Some(python_dir) = config.python_path.parent() {
-        landlock = landlock.exec(python_dir).ro(python_dir);
+    /// Stop the worker process, and whatever it is running, without
+    /// killing it, e.g. while the host is short of memory
+    ///
+    /// Needs the worker to have a cgroup ([`Worker::set_cgroup_root`]). An
+    /// execution's timeout keeps running while it is paused. To pause a
+    /// worker busy with an execution, freeze a clone of its
+    /// [`Worker::cgroup`] taken beforehand.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::CgroupUnavailable`] without a cgroup, and
+    /// [`LeewardError::Cgroup`] if it cannot be frozen.
+    #[cfg(feature = "cgroups")]
+    pub fn pause(&self) -> Result<()> {
+        self.own_cgroup()?.freeze()
     }
 
-    // Add read-only paths
-    for path in &config.ro_binds {
-        landlock = landlock.ro(path);
+    /// Let a worker stopped by [`Worker::pause`] run again
+    ///
+    /// # Errors
+    ///
+    /// As [`Worker::pause`].
+    #[cfg(feature = "cgroups")]
+    pub fn resume(&self) -> Result<()> {
+        self.own_cgroup()?.thaw()
     }
 
-    // Add read-write paths
-    for path in &config.rw_binds {
-        landlock = landlock.rw(path);
+    #[cfg(feature = "cgroups")]
+    fn own_cgroup(&self) -> Result<&crate::isolation::CgroupHandle> {
+        self.cgroup.as_ref().ok_or_else(|| {
+            LeewardError::CgroupUnavailable(format!("worker {} has no cgroup", self.id))
+        })
     }
 
-    // Add /tmp as read-write
-    landlock = landlock.rw("/tmp");
+    /// Give up on process `pid`, which failed with `error` before it was
+    /// ready, returning the error
+    fn abandon(&mut self, pid: libc::pid_t, error: LeewardError) -> LeewardError {
+        tracing::error!(
+            worker_id = self.id,
+            worker_uid = self.uid_field(),
+            pid,
+            "worker setup failed: {}",
+            error
+        );
+        self.leave_cgroup();
+        self.network_slot = None;
+        self.state = WorkerState::Dead;
+        error
+    }
 
-    match landlock.apply() {
-        Ok(_) => tracing::info!("landlock restrictions applied"),
-        Err(e) => {
-            // Landlock is nice to have but not critical if we have seccomp + namespaces
-            tracing::warn!("landlock not available (kernel < 5.13?): {}", e);
+    /// Wait for a worker whose pipe failed with `error`, adding how it died
+    ///
+    /// The worker has exited, or is about to, once its end of the pipe is
+    /// gone or it sent its last words. Other errors are returned as they
+    /// are, since the worker may still be running.
+    fn reap(&mut self, error: LeewardError) -> LeewardError {
+        if !matches!(error, LeewardError::Io(_) | LeewardError::WorkerDied(_)) {
+            return error;
+        }
+        let Some(pid) = self.pid.take() else {
+            return error;
+        };
+        let mut status = 0;
+        // SAFETY: Waiting on our own child, which no one else reaps
+        if unsafe { libc::waitpid(pid, &raw mut status, 0) } != pid {
+            return error;
+        }
+        self.network_slot = None;
+
+        match error {
+            LeewardError::WorkerDied(_) => error,
+            _ if libc::WIFSIGNALED(status) => LeewardError::Execution(format!(
+                "worker killed by signal {}",
+                libc::WTERMSIG(status)
+            )),
+            _ => WorkerDeath::from_wait_status(status).map_or(error, LeewardError::WorkerDied),
+        }
+    }
+
+    /// Syscalls the worker's listener covers
+    fn routed_syscalls(&self) -> Vec<i64> {
+        let mut syscalls = self.notify_syscalls.clone();
+        if self.config.allow_network {
+            syscalls.extend([
+                libc::SYS_socket,
+                libc::SYS_connect,
+                libc::SYS_accept,
+                libc::SYS_accept4,
+            ]);
+        }
+        syscalls.sort_unstable();
+        syscalls.dedup();
+        syscalls
+    }
+
+    /// Collect the listener the worker sent during setup, if any
+    ///
+    /// Kept for the embedder if they asked for it, otherwise answered by
+    /// the built-in supervisor until the worker process exits.
+    #[cfg(feature = "seccomp")]
+    fn attach_listener(&mut self, channel: &std::os::unix::net::UnixStream) -> Result<()> {
+        use crate::isolation::seccomp::{NotificationStream, SeccompNotifyFd};
+        use std::os::fd::AsRawFd;
+
+        self.notifications = None;
+        let Some(fd) = crate::pipe::recv_fd(channel.as_raw_fd(), libc::MSG_DONTWAIT)? else {
+            return Ok(());
+        };
+        let stream = NotificationStream::new(SeccompNotifyFd::from(fd)).recording(self.denials());
+
+        if self.notify_syscalls.is_empty() {
+            self.supervisor.clone().spawn(stream)?;
+        } else {
+            self.notifications = Some(stream);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "seccomp"))]
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    fn attach_listener(&mut self, _channel: &std::os::unix::net::UnixStream) -> Result<()> {
+        Ok(())
+    }
+
+    /// Snapshot the worker's interface counters when networking is enabled
+    fn network_counters(&self) -> Option<InterfaceCounters> {
+        if !self.config.allow_network && self.network_slot.is_none() {
+            return None;
+        }
+
+        let pid = self.pid?;
+        match InterfaceCounters::read_for_pid(pid) {
+            Ok(counters) => Some(counters),
+            Err(e) => {
+                tracing::warn!(
+                    worker_id = self.id,
+                    worker_uid = self.uid_field(),
+                    "failed to read network counters: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Snapshot of this worker for status reporting
+    #[must_use]
+    pub fn info(&self) -> crate::protocol::WorkerInfo {
+        crate::protocol::WorkerInfo {
+            id: self.id,
+            uid: self.uid.clone(),
+            pid: self.pid,
+            state: self.state,
+            execution_count: self.execution_count,
+            last_timing: self.last_timing,
+            config_fingerprint: self.config_fingerprint.clone(),
+            interpreter: self.interpreter,
+            drain_pending: false,
+            denied_syscalls: self.denials.totals(),
+        }
+    }
+
+    #[must_use]
+    pub const fn should_recycle(&self, max_executions: u64) -> bool {
+        self.execution_count >= max_executions
+    }
+}
+
+/// Stops sending work to a config whose interpreter keeps failing to start
+///
+/// Counts consecutive [`LeewardError::Config`] failures per config
+/// fingerprint. Once `limit` is reached the breaker stays open for that
+/// fingerprint until an execution under it succeeds, which in practice means
+/// until the config is changed. A limit of zero disables it.
+#[derive(Debug, Clone)]
+pub struct StartupBreaker {
+    limit: u32,
+    fingerprint: String,
+    consecutive: u32,
+}
+
+impl Default for StartupBreaker {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl StartupBreaker {
+    #[must_use]
+    pub const fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            fingerprint: String::new(),
+            consecutive: 0,
+        }
+    }
+
+    /// Fail fast if the breaker is open for `fingerprint`
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] while the breaker is open.
+    pub fn check(&self, fingerprint: &str) -> Result<()> {
+        if self.limit > 0 && self.consecutive >= self.limit && self.fingerprint == fingerprint {
+            return Err(LeewardError::Config(format!(
+                "interpreter failed to start {} times in a row under this config; \
+                 not dispatching until the config changes",
+                self.consecutive
+            )));
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of an execution under `fingerprint`
+    pub fn record(&mut self, fingerprint: &str, outcome: &Result<ExecutionResult>) {
+        match outcome {
+            Err(LeewardError::Config(_)) if self.fingerprint == fingerprint => {
+                self.consecutive += 1;
+            }
+            Err(LeewardError::Config(_)) => {
+                fingerprint.clone_into(&mut self.fingerprint);
+                self.consecutive = 1;
+            }
+            Ok(_) if self.fingerprint == fingerprint => self.consecutive = 0,
+            _ => {}
+        }
+    }
+
+    /// Whether work under `fingerprint` is being refused
+    #[must_use]
+    pub fn is_open(&self, fingerprint: &str) -> bool {
+        self.check(fingerprint).is_err()
+    }
+}
+
+/// What the worker's seccomp listener covers and where to send it
+#[cfg_attr(not(feature = "seccomp"), allow(dead_code))]
+struct ListenerRequest {
+    syscalls: Vec<i64>,
+    channel: std::os::unix::net::UnixStream,
+    /// Send every syscall instead, allowing and denying none
+    observe: bool,
+}
+
+/// Mounts `/proc` for the worker's pid namespace from the namespace's init
+struct ProcLayer {
+    hidepid: u8,
+}
+
+impl IsolationLayer for ProcLayer {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        let hidepid = self.hidepid;
+        crate::isolation::namespace::spawn_init(|| {
+            MountConfig::proc(Path::new("/proc"), hidepid).apply()
+        })
+        .map(drop)
+    }
+}
+
+/// Seccomp layer of an observed worker, handing the daemon a listener
+/// that is sent every syscall
+#[cfg(feature = "seccomp")]
+struct ObservedSeccomp {
+    channel: std::os::unix::net::UnixStream,
+}
+
+#[cfg(feature = "seccomp")]
+impl IsolationLayer for ObservedSeccomp {
+    fn name(&self) -> &'static str {
+        "seccomp"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let listener = crate::isolation::seccomp::install_observer(self.channel.as_raw_fd())?;
+        crate::pipe::send_fd(self.channel.as_raw_fd(), listener.as_raw_fd())?;
+        Ok(())
+    }
+}
+
+/// Seccomp layer that hands its listener, if any, to the daemon
+#[cfg(feature = "seccomp")]
+struct SupervisedSeccomp {
+    config: crate::isolation::SeccompConfig,
+    channel: std::os::unix::net::UnixStream,
+}
+
+#[cfg(feature = "seccomp")]
+impl IsolationLayer for SupervisedSeccomp {
+    fn name(&self) -> &'static str {
+        "seccomp"
+    }
+
+    fn apply_layer(&self) -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        if let Some(listener) = self.config.apply()? {
+            crate::pipe::send_fd(self.channel.as_raw_fd(), listener.as_raw_fd())?;
+        }
+        Ok(())
+    }
+}
+
+fn worker_main(
+    mut pipe: crate::pipe::ChildPipe,
+    config: &SandboxConfig,
+    template: Option<Arc<RootTemplate>>,
+    listener: ListenerRequest,
+    network: Option<ControlledNetwork>,
+) -> Result<()> {
+    tracing::debug!("worker process starting isolation setup");
+    fatal::report_to(pipe.result_tx_fd())?;
+
+    // Not dumpable, nothing it starts can read its memory through /proc or
+    // attach to it without CAP_SYS_PTRACE. The interpreter becomes dumpable
+    // again on exec, so tracing between the code's own processes is left
+    // to the seccomp filter.
+    if !config.debug {
+        // SAFETY: prctl with constant arguments
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    let mut timing = WorkerTiming::default();
+    // Opened before isolation hides /proc; read again at each check
+    let mut statm = config
+        .worker_max_rss
+        .and_then(|_| std::fs::File::open("/proc/self/statm").ok());
+    if let Ok(proc) = std::fs::File::open("/proc") {
+        let _ = HOST_PROC.set(proc);
+    }
+
+    for layer in isolation_layers(config, template, listener, network) {
+        let started = Instant::now();
+        fatal::enter(Stage::from_layer(layer.name()));
+        match layer.apply_layer() {
+            Ok(()) => tracing::info!(layer = layer.name(), "isolation layer applied"),
+            Err(e) if !layer.required() => {
+                tracing::warn!(
+                    layer = layer.name(),
+                    "optional isolation layer not applied: {}",
+                    e
+                );
+            }
+            Err(e) => return Err(e),
         }
+        timing.record_layer(layer.name(), started.elapsed());
     }
 
-    // Step 3: Apply seccomp filter (critical for security)
-    let seccomp = SeccompConfig::default();
-    seccomp.apply()?;
-    tracing::info!("seccomp filter applied");
+    fatal::enter(Stage::Exec);
+    send_control(&mut pipe, &ControlMessage::Ready)?;
 
     tracing::info!("worker fully isolated, entering main loop");
 
     // Main worker loop
     loop {
-        let code = match pipe.recv_code() {
-            Ok(code) => code,
+        let (job, recv_time) = match pipe.recv_code_timed() {
+            Ok(received) => received,
             Err(e) => {
                 tracing::error!("failed to receive code: {}", e);
                 break;
             }
         };
+        timing.code_recv_us = duration_us(recv_time);
 
-        let exec_result = execute_python(&code, config);
+        scrub(config);
 
-        let result_bytes = match rmp_serde::to_vec(&exec_result) {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                tracing::error!("failed to serialize result: {}", e);
-                break;
+        let job = rmp_serde::from_slice::<WorkerJob>(&job)
+            .map_err(|e| LeewardError::Execution(format!("failed to decode job: {e}")))?;
+        // Uploads are user data, so staging failures are reported like user errors
+        let exec_result = match receive_uploads(&mut pipe, &job, config)? {
+            Ok(()) => {
+                execute_python(&job, config, &mut timing, Some(pipe.control())).map(|mut result| {
+                    result.workspace_bytes = mount_usage(&config.workdir);
+                    result.tmp_bytes = mount_usage(Path::new(TMP_DIR));
+                    result
+                })
             }
+            Err(e) => Ok(launch_failure(
+                &job,
+                Duration::ZERO,
+                &e,
+                OutcomeCode::from(&e),
+            )),
         };
 
-        if let Err(e) = pipe.send_result(&result_bytes) {
+        let message = match exec_result {
+            Ok(result) => ControlMessage::Result(result),
+            Err(LeewardError::Config(message)) => ControlMessage::StartupFailed(message),
+            Err(e) => ControlMessage::StartupFailed(e.to_string()),
+        };
+
+        let send_started = Instant::now();
+        if let Err(e) = send_control(&mut pipe, &message) {
             tracing::error!("failed to send result: {}", e);
             break;
         }
+        timing.result_send_us = duration_us(send_started.elapsed());
+
+        let goodbye = statm.as_mut().and_then(|statm| outgrown(statm, config));
+        if let Some(reason) = goodbye {
+            if let Err(e) = send_control(&mut pipe, &ControlMessage::Goodbye(reason)) {
+                tracing::error!("failed to say goodbye: {}", e);
+                break;
+            }
+        }
+        if let Err(e) = send_control(&mut pipe, &ControlMessage::Timing(timing)) {
+            tracing::error!("failed to send timing: {}", e);
+            break;
+        }
+        if goodbye.is_some() {
+            break;
+        }
     }
 
     Ok(())
 }
 
-fn execute_python(code: &[u8], config: &SandboxConfig) -> ExecutionResult {
-    use std::process::{Command, Stdio};
-    use std::time::Instant;
+/// Why this worker should make way for a fresh one, if it should, reading
+/// its resident memory from `statm`, its `/proc/self/statm`
+fn outgrown(statm: &mut std::fs::File, config: &SandboxConfig) -> Option<GoodbyeReason> {
+    use std::io::{Read, Seek};
+
+    let limit = config.worker_max_rss?;
+    let mut contents = String::new();
+    statm.rewind().ok()?;
+    statm.read_to_string(&mut contents).ok()?;
+    let pages: u64 = contents.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf with a constant argument
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+    let rss = ByteSize::from_bytes(pages.saturating_mul(page_size));
+    (rss > limit).then_some(GoodbyeReason::MemoryGrown { rss, limit })
+}
+
+/// Write the files streamed after `job` into the workspace
+///
+/// Every stream is read off the pipe even after one fails to stage, so the
+/// next job starts where it should. Only a broken pipe is returned as the
+/// outer error.
+fn receive_uploads(
+    pipe: &mut crate::pipe::ChildPipe,
+    job: &WorkerJob,
+    config: &SandboxConfig,
+) -> Result<Result<()>> {
+    if job.uploads.is_empty() {
+        return Ok(Ok(()));
+    }
+
+    let mut staged = Workspace::open(&config.workdir).map(Some);
+    for (name, len) in &job.uploads {
+        let file = match &staged {
+            Ok(Some(workspace)) => workspace.create(name).map(Some),
+            _ => Ok(None),
+        };
+        let written = match file {
+            Ok(Some(mut file)) => pipe
+                .recv_stream(*len, &mut file)?
+                .map_err(|e| crate::workspace::staging_error(name, &e)),
+            // Read anyway, so the pipe stays in step
+            skipped => {
+                let _ = pipe.recv_stream(*len, &mut std::io::sink())?;
+                skipped.map(drop)
+            }
+        };
+        if let Err(e) = written {
+            if staged.is_ok() {
+                staged = Err(e);
+            }
+        }
+    }
+    Ok(staged.map(drop))
+}
+
+/// Clear whatever the last execution left for the next one to find
+///
+/// The workspace and scratch mounts are tmpfs mounts of the worker's own,
+/// though a template has no `/dev/shm`. Processes were killed with their
+/// group, and System V IPC objects live in the worker's IPC namespace.
+fn scrub(config: &SandboxConfig) {
+    let shm = Path::new(SHM_DIR);
+    let dirs = [Path::new(TMP_DIR), config.workdir.as_path()];
+    for dir in dirs.into_iter().chain(shm.exists().then_some(shm)) {
+        if let Err(e) = empty_dir(dir) {
+            tracing::warn!("failed to empty {}: {}", dir.display(), e);
+        }
+    }
+    remove_ipc_objects();
+}
+
+/// Remove every System V shared memory segment, semaphore set and message
+/// queue in this IPC namespace
+fn remove_ipc_objects() {
+    // Not in libc: the `*_STAT` commands take an index and return an id
+    const SHM_STAT: libc::c_int = 13;
+    const SHM_INFO: libc::c_int = 14;
+
+    // Big enough for any of the `*_ds` and `*info` structs
+    let mut buf = [0u64; 32];
+    let buf = buf.as_mut_ptr();
+    // SAFETY: Each call writes at most one struct into buf; RMID takes no buffer
+    unsafe {
+        for index in 0..=libc::shmctl(0, SHM_INFO, buf.cast()) {
+            let id = libc::shmctl(index, SHM_STAT, buf.cast());
+            if id >= 0 {
+                libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut());
+            }
+        }
+        for index in 0..=libc::semctl(0, 0, libc::SEM_INFO, buf) {
+            let id = libc::semctl(index, 0, libc::SEM_STAT, buf);
+            if id >= 0 {
+                libc::semctl(id, 0, libc::IPC_RMID);
+            }
+        }
+        for index in 0..=libc::msgctl(0, libc::MSG_INFO, buf.cast()) {
+            let id = libc::msgctl(index, libc::MSG_STAT, buf.cast());
+            if id >= 0 {
+                libc::msgctl(id, libc::IPC_RMID, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Remove everything in `dir`, so each execution gets all of its quota
+fn empty_dir(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Bytes in use on the filesystem mounted at `path`, or 0 if unknown
+fn mount_usage(path: &Path) -> u64 {
+    nix::sys::statvfs::statvfs(path).map_or(0, |stat| {
+        (stat.blocks() - stat.blocks_free()) * stat.fragment_size()
+    })
+}
+
+/// Encode and send a control message to the daemon
+fn send_control(pipe: &mut crate::pipe::ChildPipe, msg: &ControlMessage) -> Result<()> {
+    let bytes = rmp_serde::to_vec(msg).map_err(|e| {
+        LeewardError::Execution(format!("failed to serialize control message: {e}"))
+    })?;
+    pipe.send_result(&bytes)
+}
+
+/// Receive and decode a control message from a worker
+///
+/// Fails with [`LeewardError::WorkerDied`] if the worker sent its last words.
+fn recv_control(pipe: &mut ParentPipe) -> Result<ControlMessage> {
+    let bytes = pipe.recv_result()?;
+    if let Some(death) = WorkerDeath::decode(&bytes) {
+        return Err(LeewardError::WorkerDied(death));
+    }
+    rmp_serde::from_slice(&bytes)
+        .map_err(|e| LeewardError::Execution(format!("failed to deserialize control message: {e}")))
+}
+
+/// Encode `job` into `frame` and send it to the worker
+fn send_job(pipe: &mut ParentPipe, frame: &mut Vec<u8>, job: &WorkerJob<'_>) -> Result<()> {
+    frame.clear();
+    rmp_serde::encode::write(frame, job)
+        .map_err(|e| LeewardError::Execution(format!("failed to serialize job: {e}")))?;
+    pipe.send_code(frame)?;
+    if frame.capacity() > MAX_RETAINED_FRAME {
+        *frame = Vec::new();
+    }
+    Ok(())
+}
+
+/// Receive what a worker sends after a job: its result, a goodbye if the
+/// worker is exiting, then its timing
+fn recv_outcome(
+    pipe: &mut ParentPipe,
+) -> Result<(ControlMessage, Option<GoodbyeReason>, ControlMessage)> {
+    let result = recv_control(pipe)?;
+    let mut next = recv_control(pipe)?;
+    let mut goodbye = None;
+    if let ControlMessage::Goodbye(reason) = next {
+        goodbye = Some(reason);
+        next = recv_control(pipe)?;
+    }
+    Ok((result, goodbye, next))
+}
+
+/// Convert a duration to whole microseconds, saturating
+fn duration_us(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn duration_ns(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Build the isolation layers for a worker, in the order they must be applied
+///
+/// Namespaces come first, then the shared root or private mounts, then Landlock, and
+/// seccomp last since it restricts the syscalls the other layers need.
+fn isolation_layers(
+    config: &SandboxConfig,
+    template: Option<Arc<RootTemplate>>,
+    listener: ListenerRequest,
+    network: Option<ControlledNetwork>,
+) -> Vec<Box<dyn IsolationLayer>> {
+    let mut layers: Vec<Box<dyn IsolationLayer>> = Vec::new();
+
+    // Setup namespaces (critical for security)
+    layers.push(Box::new(NamespaceConfig {
+        user: false, // Workers set up mounts and cgroups as the host user
+        pid: true,   // Isolate process tree
+        mount: true, // Isolate filesystem
+        net: !config.allow_network && network.is_none(), // Network isolation, unless cloned into it
+        ipc: true,   // IPC isolation
+        uts: true,   // Hostname isolation
+        time: config.freeze_clock, // Clocks from 0 for the interpreter
+        ..NamespaceConfig::default()
+    }));
+
+    #[cfg(feature = "landlock")]
+    let controlled = network.is_some();
+    let resolv_conf = network.as_ref().map(ControlledNetwork::resolv_conf);
+
+    // The new network namespace's loopback starts down; a controlled
+    // network brings it up along with the link the parent created
+    match network {
+        Some(network) => layers.push(Box::new(network)),
+        None if !config.allow_network && config.network == NetworkConfig::LoopbackOnly => {
+            layers.push(Box::new(NetworkNamespaceSetup));
+        }
+        None => {}
+    }
+
+    // Attach the shared root and pivot into it, or else keep the host's but
+    // mount a workspace and scratch space of the worker's own over it, since
+    // the host's are shared; /tmp first, so a workdir under it goes on top
+    #[cfg(feature = "landlock")]
+    let rooted = template.is_some();
+    match template {
+        Some(template) => layers.push(Box::new(template)),
+        None => layers.push(Box::new(
+            MountConfig::default()
+                .strict(config.strict_paths)
+                .tmpfs(TMP_DIR, config.tmp_size)
+                .tmpfs(&config.workdir, WORKSPACE_TMPFS_SIZE)
+                .tmpfs(SHM_DIR, config.tmp_size),
+        )),
+    }
+
+    // Staged in the worker's own /tmp, so only once that is mounted
+    if let Some(resolv_conf) = resolv_conf {
+        layers.push(Box::new(resolv_conf));
+    }
+
+    if config.mount_proc {
+        layers.push(Box::new(ProcLayer {
+            hidepid: config.proc_hidepid,
+        }));
+    }
+
+    // Apply Landlock filesystem restrictions (requires Linux 5.13+, best
+    // effort), except in an observed worker, whose opens are only recorded
+    #[cfg(feature = "landlock")]
+    if !listener.observe {
+        use crate::isolation::LandlockConfig;
+
+        let mut landlock = LandlockConfig::default().strict(config.strict_paths);
+
+        // Add Python path and libraries as executable
+        if let Some(python_dir) = config.python_path.parent() {
+            landlock = landlock.exec(python_dir).ro(python_dir);
+        }
+
+        // Add read-only paths
+        for path in &config.ro_binds {
+            landlock = landlock.ro(path);
+        }
+
+        // Add read-write paths
+        for path in &config.rw_binds {
+            landlock = landlock.rw(path);
+        }
+
+        // A template's /etc holds only what it was given, such as /etc/localtime
+        if rooted {
+            landlock = landlock.ro("/etc");
+        } else if controlled {
+            landlock = landlock.ro(crate::isolation::netns::RESOLV_CONF);
+        }
+
+        let landlock = config
+            .allowed_tcp_connect_ports
+            .iter()
+            .fold(landlock, |landlock, &port| landlock.connect_tcp(port));
+        let landlock = config
+            .allowed_tcp_bind_ports
+            .iter()
+            .fold(landlock, |landlock, &port| landlock.bind_tcp(port));
+
+        let landlock = if config.mount_proc {
+            landlock.ro("/proc")
+        } else {
+            landlock
+        };
+
+        // Add /tmp as read-write
+        layers.push(Box::new(landlock.rw("/tmp")));
+    }
+
+    // Apply seccomp filter (critical for security)
+    #[cfg(feature = "seccomp")]
+    if listener.observe {
+        layers.push(Box::new(ObservedSeccomp {
+            channel: listener.channel,
+        }));
+    } else {
+        layers.push(Box::new(SupervisedSeccomp {
+            config: crate::isolation::SeccompConfig {
+                notify_syscalls: listener.syscalls,
+                allow_debugging: config.debug,
+                ..crate::isolation::SeccompConfig::default()
+            },
+            channel: listener.channel,
+        }));
+    }
+    #[cfg(not(feature = "seccomp"))]
+    drop(listener);
+
+    layers
+}
+
+/// Run code in the interpreter, enforcing the timeout and memory limit
+///
+/// This is what a worker runs once isolated; it adds no isolation of its own.
+///
+/// # Errors
+///
+/// [`LeewardError::Config`] if the interpreter cannot start under the memory
+/// limit; anything the user code does is reported in the result.
+pub fn run_python(
+    code: &str,
+    config: &SandboxConfig,
+    options: &ExecuteOptions,
+) -> Result<ExecutionResult> {
+    execute_python(
+        &WorkerJob::new(code, config, options),
+        config,
+        &mut WorkerTiming::default(),
+        None,
+    )
+}
+
+/// Run `job`, reading freeze and thaw requests from `control` if it is
+/// preemptible, and memory pressure warnings if it takes them
+fn execute_python(
+    job: &WorkerJob,
+    config: &SandboxConfig,
+    timing: &mut WorkerTiming,
+    control: Option<&std::fs::File>,
+) -> Result<ExecutionResult> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
 
-    let code_str = String::from_utf8_lossy(code);
     let start = Instant::now();
+    let failed =
+        |e: &dyn std::fmt::Display, outcome| launch_failure(job, start.elapsed(), e, outcome);
 
-    let output = match Command::new(&config.python_path)
-        .arg("-c")
-        .arg(code_str.as_ref())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-    {
-        Ok(output) => output,
+    // Input files are user data, so staging failures are reported like user errors
+    if !job.files.is_empty() {
+        if let Err(e) =
+            Workspace::open(&config.workdir).and_then(|workspace| workspace.materialize(&job.files))
+        {
+            return Ok(failed(&e, OutcomeCode::from(&e)));
+        }
+    }
+
+    // Under a memory limit, a stage marker tells a startup OOM from a user-code one
+    let marker = match (job.memory_limit, job.interpreter) {
+        (Some(_), Interpreter::Python) => match crate::pipe::create_pipe() {
+            Ok(pipe) => Some(pipe),
+            Err(e) => return Ok(failed(&e, OutcomeCode::SandboxSetup)),
+        },
+        _ => None,
+    };
+
+    let marker_fd = marker.as_ref().map(|(_, tx)| tx.as_raw_fd());
+    let mut command = interpreter_command(job, config, marker_fd);
+    // Its own process group, so a freeze stops and the watchdog kills
+    // everything it started
+    command.process_group(0);
+    let control = control.filter(|_| job.preemptible || job.pressure_signal);
+    let watchdog = match Watchdog::arm(watchdog_deadline(job)) {
+        Ok(watchdog) => Some(watchdog),
         Err(e) => {
-            return ExecutionResult {
-                exit_code: -1,
-                stdout: Vec::new(),
-                stderr: format!("Failed to execute Python: {}", e).into_bytes(),
-                duration: start.elapsed(),
-                memory_peak: 0,
-                cpu_time_us: 0,
-                timed_out: false,
-                oom_killed: false,
-            };
+            tracing::warn!(error = %e, "no watchdog; the timeout alone bounds this execution");
+            None
         }
     };
+    let child = command.spawn();
 
-    let duration = start.elapsed();
+    // Only the interpreter may hold the write end, so EOF means it exited
+    let marker = marker.map(|(rx, tx)| {
+        drop(tx);
+        rx
+    });
 
-    ExecutionResult {
+    let output = match child.and_then(|child| {
+        timing.python_import_us = duration_us(start.elapsed());
+        let timeout = (!job.skip_timeout).then_some(job.timeout);
+        wait_with_deadline(
+            child,
+            job.stdin.as_deref(),
+            timeout,
+            control,
+            watchdog.as_ref(),
+        )
+    }) {
+        Ok(output) => output,
+        // exec itself can run out of address space under a tight limit
+        Err(e) if e.raw_os_error() == Some(libc::ENOMEM) && job.memory_limit.is_some() => {
+            return Err(startup_error(job.memory_limit.unwrap_or_default(), config));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(failed(&e, OutcomeCode::InterpreterUnavailable));
+        }
+        Err(e) => return Ok(failed(&e, OutcomeCode::SandboxSetup)),
+    };
+
+    let duration = start.elapsed().saturating_sub(output.frozen);
+    timing.execution_us = duration_us(duration).saturating_sub(timing.python_import_us);
+
+    if let (Some(marker), Some(limit)) = (marker, job.memory_limit) {
+        if !output.timed_out && read_stage_marker(marker).is_none() {
+            return Err(startup_error(limit, config));
+        }
+    }
+
+    Ok(ExecutionResult {
         exit_code: output.status.code().unwrap_or(-1),
         stdout: output.stdout,
         stderr: output.stderr,
+        stdout_truncated: false,
+        stderr_truncated: false,
+        duration,
+        memory_peak: output.memory_peak,
+        cpu_time_us: output.cpu_time_us,
+        timed_out: output.timed_out,
+        killed_by: output.killed_by,
+        oom_killed: false,  // Filled in by the parent from the worker's cgroup
+        network: None,      // Filled in by the parent from the worker's netns
+        workspace_bytes: 0, // Measured by the worker, which knows its mounts
+        tmp_bytes: 0,
+        preempted_count: output.preempted_count,
+        frozen_duration: output.frozen,
+        denials: Vec::new(),
+        debug: false, // Marked by the parent, which knows the profile
+        pressure_signal_sent: output.pressure_signaled,
+        pressure_signal_memory: None, // Filled in by the parent from the worker's cgroup
+    })
+}
+
+/// When the watchdog of `job` fires, in nanoseconds on `CLOCK_MONOTONIC`
+///
+/// Input files and uploads are staged after the daemon sets its deadline
+/// and count against neither timeout, so the watchdog never fires before
+/// the timeout would have from now. Without a daemon there is only the
+/// latter.
+fn watchdog_deadline(job: &WorkerJob) -> u64 {
+    let local = monotonic_ns().saturating_add(duration_ns(job.timeout));
+    job.deadline_ns
+        .map_or(local, |deadline| deadline.max(local))
+        .saturating_add(duration_ns(job.watchdog_margin))
+}
+
+/// Result for a job whose program never ran, with `error` as its stderr
+///
+/// Launch failures use the shell's 126 ("cannot execute") and 127 ("not found").
+fn launch_failure(
+    job: &WorkerJob,
+    duration: Duration,
+    error: &dyn std::fmt::Display,
+    outcome: OutcomeCode,
+) -> ExecutionResult {
+    ExecutionResult {
+        exit_code: i32::from(outcome.code()),
+        stdout: Vec::new(),
+        stderr: format!("Failed to execute {}: {}", job.interpreter.name(), error).into_bytes(),
+        stdout_truncated: false,
+        stderr_truncated: false,
         duration,
-        memory_peak: 0,  // TODO: Get from cgroup memory.peak
-        cpu_time_us: 0,  // TODO: Get from /proc/[pid]/stat
-        timed_out: false, // TODO: Implement timeout handling
-        oom_killed: false, // TODO: Detect from cgroup events
+        memory_peak: 0,
+        cpu_time_us: 0,
+        timed_out: false,
+        killed_by: None,
+        oom_killed: false,
+        network: None,
+        workspace_bytes: 0,
+        tmp_bytes: 0,
+        preempted_count: 0,
+        frozen_duration: Duration::ZERO,
+        denials: Vec::new(),
+        debug: false,
+        pressure_signal_sent: false,
+        pressure_signal_memory: None,
+    }
+}
+
+/// Interpreter command for `job`, with its stdio piped
+///
+/// `marker_fd` is the stage marker's write end, used under a memory limit
+/// by Python only.
+pub(crate) fn interpreter_command(
+    job: &WorkerJob,
+    config: &SandboxConfig,
+    marker_fd: Option<std::os::fd::RawFd>,
+) -> std::process::Command {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let program = job.interpreter.program(config);
+    let mut command = Command::new(program);
+    match job.interpreter {
+        Interpreter::Python => match prologue(job.traceback_after, marker_fd.is_some()) {
+            Some(prologue) => command.arg("-c").arg(prologue).arg(&*job.code),
+            None => command.arg("-c").arg(&*job.code),
+        },
+        // The shell's own name becomes $0, so the arguments start at $1
+        Interpreter::Sh | Interpreter::Bash => command.arg("-c").arg(&*job.code).arg(program),
+    };
+    command.args(job.args.iter());
+
+    if let Some(limit) = job.memory_limit {
+        // SAFETY: The hook only makes async-signal-safe syscalls
+        unsafe { command.pre_exec(move || limit_interpreter(limit.bytes(), marker_fd)) };
+    }
+
+    if job.nice.is_some() || job.sched_policy.is_some() {
+        let (nice, policy) = (job.nice, job.sched_policy);
+        // SAFETY: The hook only makes async-signal-safe syscalls
+        unsafe { command.pre_exec(move || set_scheduling(nice, policy)) };
+    }
+
+    if !job.files.is_empty() || !job.uploads.is_empty() {
+        command.current_dir(&config.workdir);
+    }
+
+    let stdin = if job.stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+
+    command
+        .envs(config.env.iter().map(|(k, v)| (k, v)))
+        .envs(job.env.iter().map(|(k, v)| (k, v)))
+        .env("TZ", &*job.timezone)
+        .env("TMPDIR", TMP_DIR)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
+
+/// File descriptor the interpreter writes the stage marker to
+const STAGE_MARKER_FD: i32 = 3;
+
+/// Stage marker written just before the user code runs
+const EXEC_STARTED: &str = "exec:started";
+
+const MIB: u64 = 1024 * 1024;
+
+/// Where POSIX shared memory lives; a worker on the host root gets its own
+const SHM_DIR: &str = "/dev/shm";
+
+/// Largest job buffer a worker keeps for the next execution
+const MAX_RETAINED_FRAME: usize = 1024 * 1024;
+
+/// Python run ahead of user code, if any is needed
+///
+/// With `traceback_after`, registers faulthandler on SIGALRM and arms a
+/// one-shot timer. With `stage_marker`, writes [`EXEC_STARTED`] and the
+/// interpreter's peak size in kB to [`STAGE_MARKER_FD`], then closes it so
+/// the user code cannot see it. The user code (passed as `argv[1]`) then
+/// runs as its own code object so its line numbers are unchanged.
+fn prologue(traceback_after: Option<Duration>, stage_marker: bool) -> Option<String> {
+    if traceback_after.is_none() && !stage_marker {
+        return None;
+    }
+
+    let mut prologue = String::from("import sys as _sys\n");
+    if let Some(after) = traceback_after {
+        // A zero timer would disarm rather than fire immediately
+        let after = after.max(Duration::from_millis(1));
+        let _ = write!(
+            prologue,
+            "import faulthandler as _fh, signal as _sig\n\
+             _fh.register(_sig.SIGALRM, all_threads=True)\n\
+             _sig.setitimer(_sig.ITIMER_REAL, {})\n\
+             del _fh, _sig\n",
+            after.as_secs_f64()
+        );
+    }
+    prologue.push_str("_code = _sys.argv.pop(1)\ndel _sys\n");
+    if stage_marker {
+        let _ = write!(
+            prologue,
+            "def _started():\n\
+             \x20   import os\n\
+             \x20   try:\n\
+             \x20       with open('/proc/self/status') as f:\n\
+             \x20           peak = next((l.split()[1] for l in f if l.startswith('VmPeak:')), '')\n\
+             \x20   except OSError:\n\
+             \x20       peak = ''\n\
+             \x20   os.write({STAGE_MARKER_FD}, ('{EXEC_STARTED} ' + peak).encode())\n\
+             \x20   os.close({STAGE_MARKER_FD})\n\
+             _started()\n\
+             del _started\n"
+        );
+    }
+    prologue.push_str("exec(compile(_code, '<string>', 'exec'))\n");
+    Some(prologue)
+}
+
+/// Runs in the forked interpreter before exec: cap the address space and
+/// move the marker pipe to [`STAGE_MARKER_FD`]
+fn limit_interpreter(limit: u64, marker_fd: Option<i32>) -> std::io::Result<()> {
+    let rlimit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    // SAFETY: setrlimit with a valid struct
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &raw const rlimit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    if let Some(fd) = marker_fd {
+        // dup2 onto itself would keep O_CLOEXEC, so clear it instead
+        // SAFETY: fd is the marker pipe's write end, open in this process
+        let ret = unsafe {
+            if fd == STAGE_MARKER_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, STAGE_MARKER_FD)
+            }
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the scheduling policy and nice value to the calling process
+///
+/// Runs between fork and exec, so only the interpreter is affected.
+fn set_scheduling(nice: Option<i8>, policy: Option<SchedPolicy>) -> std::io::Result<()> {
+    if let Some(policy) = policy {
+        let param = libc::sched_param { sched_priority: 0 };
+        // SAFETY: sched_setscheduler on ourselves with a valid param
+        if unsafe { libc::sched_setscheduler(0, policy.as_raw(), &raw const param) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    if let Some(nice) = nice {
+        // SAFETY: setpriority on ourselves
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice.into()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the stage marker, returning the interpreter's peak size in bytes
+/// (zero if unknown), or `None` if the user code was never reached
+fn read_stage_marker(mut marker: std::fs::File) -> Option<u64> {
+    use std::io::Read;
+
+    let mut stage = String::new();
+    marker.read_to_string(&mut stage).ok()?;
+    let peak_kb = stage.strip_prefix(EXEC_STARTED)?.trim();
+    Some(peak_kb.parse::<u64>().map_or(0, |kb| kb * 1024))
+}
+
+/// Error for an interpreter that died before reaching the user code
+fn startup_error(limit: ByteSize, config: &SandboxConfig) -> LeewardError {
+    let mut message = format!(
+        "memory limit too low for interpreter startup ({} MB)",
+        limit.whole_mib()
+    );
+    if let Some(peak) = startup_footprint(config) {
+        let _ = write!(message, "; minimum observed {} MB", peak.div_ceil(MIB));
+    }
+    LeewardError::Config(message)
+}
+
+/// Peak size of an unlimited interpreter when it reaches user code
+///
+/// Measured once per process, the first time a startup fails.
+fn startup_footprint(config: &SandboxConfig) -> Option<u64> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    static FOOTPRINT: OnceLock<Option<u64>> = OnceLock::new();
+
+    *FOOTPRINT.get_or_init(|| {
+        let (rx, tx) = crate::pipe::create_pipe().ok()?;
+        let marker_fd = tx.as_raw_fd();
+        let mut command = Command::new(&config.python_path);
+        command
+            .arg("-c")
+            .arg(prologue(None, true)?)
+            .arg("pass")
+            .envs(config.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // SAFETY: The hook only makes async-signal-safe syscalls
+        unsafe {
+            command.pre_exec(move || limit_interpreter(libc::RLIM_INFINITY, Some(marker_fd)))
+        };
+
+        let status = command.status();
+        drop(tx);
+        status.ok()?;
+        read_stage_marker(rx).filter(|&peak| peak > 0)
+    })
+}
+
+/// Output of an interpreter run under a deadline
+pub(crate) struct DeadlineOutput {
+    pub(crate) status: std::process::ExitStatus,
+    pub(crate) stdout: Vec<u8>,
+    pub(crate) stderr: Vec<u8>,
+    pub(crate) timed_out: bool,
+    /// Which deadline killed it, when `timed_out`
+    pub(crate) killed_by: Option<KilledBy>,
+    /// Peak resident set size, in bytes
+    pub(crate) memory_peak: u64,
+    /// User and system CPU time, in microseconds
+    pub(crate) cpu_time_us: u64,
+    /// Time spent frozen
+    pub(crate) frozen: Duration,
+    /// Times it was frozen
+    pub(crate) preempted_count: u32,
+    /// Whether it was sent `SIGUSR1` for memory pressure
+    pub(crate) pressure_signaled: bool,
+}
+
+/// Stops and resumes a child's process group as the daemon asks, and
+/// passes on its memory pressure warnings
+struct Freezer<'a> {
+    /// Code pipe the requests arrive on, until the daemon goes away
+    control: Option<&'a std::fs::File>,
+    pgid: libc::pid_t,
+    since: Option<Instant>,
+    total: Duration,
+    count: u32,
+    /// Whether the child was warned of memory pressure
+    warned: bool,
+}
+
+impl Freezer<'_> {
+    /// Time spent frozen so far
+    fn frozen(&self) -> Duration {
+        self.total + self.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Act on the control bytes waiting on the pipe
+    fn read(&mut self) {
+        use std::io::Read;
+
+        let Some(mut control) = self.control else {
+            return;
+        };
+        let mut bytes = [0u8; 16];
+        match control.read(&mut bytes) {
+            Ok(n) if n > 0 => {
+                for byte in &bytes[..n] {
+                    match *byte {
+                        crate::pipe::FREEZE => self.set(true),
+                        crate::pipe::THAW => self.set(false),
+                        crate::pipe::PRESSURE => self.warn(),
+                        _ => {}
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            // Nothing will ever thaw it now
+            _ => {
+                self.control = None;
+                self.set(false);
+            }
+        }
+    }
+
+    /// Send the child `SIGUSR1`, once, if it handles the signal
+    ///
+    /// Left to its default action the signal would kill the child, so a
+    /// child that has not installed a handler, or whose handlers cannot be
+    /// read, is not sent it.
+    fn warn(&mut self) {
+        if self.warned || !handles_sigusr1(self.pgid) {
+            return;
+        }
+        self.warned = true;
+        // SAFETY: Signalling our own child, not its group
+        unsafe {
+            libc::kill(self.pgid, libc::SIGUSR1);
+        }
+    }
+
+    fn set(&mut self, frozen: bool) {
+        let signal = match (frozen, self.since) {
+            (true, None) => {
+                self.since = Some(Instant::now());
+                self.count += 1;
+                libc::SIGSTOP
+            }
+            (false, Some(since)) => {
+                self.since = None;
+                self.total += since.elapsed();
+                libc::SIGCONT
+            }
+            _ => return,
+        };
+        // SAFETY: Signalling the process group our own child leads
+        unsafe {
+            libc::kill(-self.pgid, signal);
+        }
+    }
+}
+
+/// The `/proc` the worker started with, numbered by the pids it sees,
+/// opened before isolation hides it or mounts the sandbox's over it
+static HOST_PROC: OnceLock<std::fs::File> = OnceLock::new();
+
+/// Whether process `pid` has a handler for `SIGUSR1`, from the `SigCgt`
+/// mask in its `/proc` status
+fn handles_sigusr1(pid: libc::pid_t) -> bool {
+    use nix::fcntl::{OFlag, openat};
+    use std::io::Read;
+
+    let path = format!("{pid}/status");
+    let opened = HOST_PROC.get().map_or_else(
+        || std::fs::File::open(Path::new("/proc").join(&path)),
+        |proc| {
+            openat(
+                proc,
+                path.as_str(),
+                OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+                nix::sys::stat::Mode::empty(),
+            )
+            .map(std::fs::File::from)
+            .map_err(std::io::Error::from)
+        },
+    );
+    let mut status = String::new();
+    if opened
+        .and_then(|mut file| file.read_to_string(&mut status))
+        .is_err()
+    {
+        return false;
+    }
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("SigCgt:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .is_some_and(|mask| mask & (1 << (libc::SIGUSR1 - 1)) != 0)
+}
+
+/// Backstop for the timeout: a timerfd armed at an absolute deadline on
+/// `CLOCK_MONOTONIC`, polled alongside the program's output
+///
+/// It fires whatever the timeout did, so code the timeout failed to kill
+/// still dies, at its deadline plus the margin. Time spent frozen pushes it
+/// back as it does the timeout.
+pub(crate) struct Watchdog {
+    timer: std::os::fd::OwnedFd,
+    /// When it fires, before any time frozen
+    at: u64,
+}
+
+impl Watchdog {
+    /// A watchdog firing at `at` nanoseconds on `CLOCK_MONOTONIC`
+    fn arm(at: u64) -> std::io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: timerfd_create with constant arguments
+        let fd = unsafe {
+            libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_CLOEXEC | libc::TFD_NONBLOCK,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: A new descriptor nothing else owns
+        let timer = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+        let watchdog = Self { timer, at };
+        watchdog.set(at)?;
+        Ok(watchdog)
+    }
+
+    fn set(&self, at: u64) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let nanos_per_sec = 1_000_000_000;
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: libc::time_t::try_from(at / nanos_per_sec).unwrap_or(libc::time_t::MAX),
+                // Under a second, so it fits
+                tv_nsec: (at % nanos_per_sec) as libc::c_long,
+            },
+        };
+        // SAFETY: Arming our own timerfd with a valid spec
+        let ret = unsafe {
+            libc::timerfd_settime(
+                self.timer.as_raw_fd(),
+                libc::TFD_TIMER_ABSTIME,
+                &raw const spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Whether it is past the deadline pushed back by `frozen`, re-arming
+    /// for what is left if not
+    fn expired(&self, frozen: Duration) -> bool {
+        use std::os::fd::AsRawFd;
+
+        let mut expirations = [0u8; 8];
+        // SAFETY: Reading the expiration count into a buffer of its size
+        unsafe {
+            libc::read(
+                self.timer.as_raw_fd(),
+                expirations.as_mut_ptr().cast(),
+                expirations.len(),
+            )
+        };
+        let due = self.at.saturating_add(duration_ns(frozen));
+        // A watchdog that cannot be re-armed would never fire again
+        monotonic_ns() >= due || self.set(due).is_err()
+    }
+}
+
+/// Now, in nanoseconds on `CLOCK_MONOTONIC`
+fn monotonic_ns() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime fills in the timespec we own
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &raw mut now) };
+    u64::try_from(now.tv_sec)
+        .unwrap_or_default()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::try_from(now.tv_nsec).unwrap_or_default())
+}
+
+/// Feed `input` to a child and collect its output, killing it if it outlives `timeout`
+///
+/// The child must lead its own process group, and whatever is left in the
+/// group once it exits is killed. With `control`, the group is frozen and
+/// thawed on request; time spent frozen extends the deadline. With a
+/// `watchdog`, the whole group is killed if the watchdog fires first.
+/// Without a `timeout`, only the watchdog kills it.
+pub(crate) fn wait_with_deadline(
+    mut child: std::process::Child,
+    input: Option<&[u8]>,
+    timeout: Option<Duration>,
+    control: Option<&std::fs::File>,
+    watchdog: Option<&Watchdog>,
+) -> std::io::Result<DeadlineOutput> {
+    use std::os::fd::OwnedFd;
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut freezer = Freezer {
+        control,
+        pgid: libc::pid_t::try_from(child.id()).unwrap_or_default(),
+        since: None,
+        total: Duration::ZERO,
+        count: 0,
+        warned: false,
+    };
+    let mut stdin: Option<std::fs::File> = child.stdin.take().map(|s| OwnedFd::from(s).into());
+    let mut input = input.unwrap_or_default();
+    let mut streams: [Option<std::fs::File>; 2] = [
+        child.stdout.take().map(|s| OwnedFd::from(s).into()),
+        child.stderr.take().map(|s| OwnedFd::from(s).into()),
+    ];
+    let mut output = [Vec::new(), Vec::new()];
+    let mut killed_by = None;
+    let mut buf = [0u8; 8192];
+
+    if input.is_empty() {
+        stdin = None;
+    }
+
+    while streams.iter().any(Option::is_some) {
+        let remaining = deadline.map(|deadline| {
+            (deadline + freezer.frozen()).saturating_duration_since(Instant::now())
+        });
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            killed_by = Some(KilledBy::Timeout);
+            break;
+        }
+
+        // poll ignores negative fds, so closed streams keep their slot
+        let [out, err] = streams
+            .each_ref()
+            .map(|stream| pollfd(stream.as_ref(), libc::POLLIN));
+        let mut fds = [
+            out,
+            err,
+            pollfd(stdin.as_ref(), libc::POLLOUT),
+            pollfd(freezer.control, libc::POLLIN),
+            pollfd(watchdog.map(|watchdog| &watchdog.timer), libc::POLLIN),
+        ];
+        let timeout_ms = remaining.map_or(-1, |remaining| {
+            i32::try_from(remaining.as_millis())
+                .unwrap_or(i32::MAX)
+                .max(1)
+        });
+
+        // SAFETY: poll on a valid array of pollfds
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        for ((fd, stream), output) in fds[..2].iter().zip(&mut streams).zip(&mut output) {
+            if fd.revents != 0 {
+                read_ready(stream, output, &mut buf);
+            }
+        }
+        if fds[2].revents != 0 {
+            feed(&mut stdin, &mut input);
+        }
+
+        if fds[3].revents != 0 {
+            freezer.read();
+        }
+
+        if fds[4].revents != 0
+            && watchdog.is_some_and(|watchdog| watchdog.expired(freezer.frozen()))
+        {
+            tracing::warn!(
+                pid = freezer.pgid,
+                "watchdog killed an execution its timeout did not"
+            );
+            // SAFETY: Killing the process group our own child leads
+            unsafe {
+                libc::kill(-freezer.pgid, libc::SIGKILL);
+            }
+            killed_by = Some(KilledBy::WorkerWatchdog);
+            break;
+        }
+    }
+
+    // A child that closed its output while frozen would never be reaped
+    freezer.set(false);
+    if killed_by == Some(KilledBy::Timeout) {
+        child.kill()?;
+    }
+    let (status, usage) = wait_with_usage(&child)?;
+    // Whatever it left running in its group would see the next execution
+    // SAFETY: Killing the process group our own child led
+    unsafe {
+        libc::kill(-freezer.pgid, libc::SIGKILL);
+    }
+    let [stdout, stderr] = output;
+
+    Ok(DeadlineOutput {
+        status,
+        stdout,
+        stderr,
+        timed_out: killed_by.is_some(),
+        killed_by,
+        memory_peak: u64::try_from(usage.ru_maxrss).unwrap_or_default() * 1024,
+        cpu_time_us: cpu_time_us(&usage),
+        frozen: freezer.total,
+        preempted_count: freezer.count,
+        pressure_signaled: freezer.warned,
+    })
+}
+
+/// Read what `stream` has ready into `output`, closing it at its end
+fn read_ready(stream: &mut Option<std::fs::File>, output: &mut Vec<u8>, buf: &mut [u8]) {
+    use std::io::Read;
+
+    if let Some(file) = stream.as_mut() {
+        match file.read(buf) {
+            Ok(0) | Err(_) => *stream = None,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+/// Write the next chunk of `input` to `stdin`, closing it once all of
+/// `input` is written or the child stops reading
+fn feed(stdin: &mut Option<std::fs::File>, input: &mut &[u8]) {
+    use std::io::Write;
+
+    if let Some(pipe) = stdin.as_mut() {
+        // Writes up to PIPE_BUF never block once POLLOUT is set
+        let chunk = &input[..input.len().min(libc::PIPE_BUF)];
+        match pipe.write(chunk) {
+            Ok(n) => *input = &input[n..],
+            Err(_) => *input = &[],
+        }
+        if input.is_empty() {
+            *stdin = None;
+        }
+    }
+}
+
+/// Entry polling `fd` for `events`, or one poll skips
+fn pollfd(fd: Option<&impl std::os::fd::AsRawFd>, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd: fd.map_or(-1, std::os::fd::AsRawFd::as_raw_fd),
+        events,
+        revents: 0,
+    }
+}
+
+/// User and system CPU time in `usage`, in microseconds
+fn cpu_time_us(usage: &libc::rusage) -> u64 {
+    [usage.ru_utime, usage.ru_stime]
+        .iter()
+        .map(|time| {
+            u64::try_from(time.tv_sec).unwrap_or_default() * 1_000_000
+                + u64::try_from(time.tv_usec).unwrap_or_default()
+        })
+        .sum()
+}
+
+/// Reap `child` along with its resource usage
+///
+/// Usage covers the child and any descendants it waited for.
+fn wait_with_usage(
+    child: &std::process::Child,
+) -> std::io::Result<(std::process::ExitStatus, libc::rusage)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = libc::pid_t::try_from(child.id())
+        .map_err(|_| std::io::Error::from_raw_os_error(libc::ESRCH))?;
+    let mut status = 0;
+    // SAFETY: rusage is plain data the kernel fills in
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: wait4 on our own unreaped child with valid out pointers
+        if unsafe { libc::wait4(pid, &raw mut status, 0, &raw mut usage) } >= 0 {
+            return Ok((std::process::ExitStatus::from_raw(status), usage));
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
     }
 }
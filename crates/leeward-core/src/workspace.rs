@@ -0,0 +1,344 @@
+//! Writing a request's input files into the sandbox workspace
+//!
+//! Input file names come from the client. Unless the workspace is the
+//! worker's own tmpfs, emptied before each execution, it may hold anything,
+//! including symlinks pointing out of it. Names are checked up front
+//! (relative, no `.` or `..`, no control characters, bounded depth and
+//! length, no two that collide when case is ignored), then every path is
+//! resolved one component at a time from a directory fd on the workspace.
+//! Each step uses `openat2` with `RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS`,
+//! or `openat` with `O_NOFOLLOW` on kernels without it, so nothing is ever
+//! written through a symlink, and parent directories are created through
+//! the same resolver.
+//!
+//! Nothing an execution leaves in the workspace reaches the next one, and
+//! the daemon has no sessions to keep it for. Workspaces are therefore
+//! never snapshotted or restored, and code that needs files across
+//! executions must send them as input.
+
+use crate::config::paths::{CanonicalPath, PathErrorKind, PathPolicy};
+use crate::{LeewardError, Result};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+
+/// Most path components an input file name may have
+pub const MAX_DEPTH: usize = 16;
+
+/// Longest single component, in bytes (`NAME_MAX`)
+pub const MAX_COMPONENT_LEN: usize = 255;
+
+/// Longest whole name, in bytes (`PATH_MAX` less the terminator)
+pub const MAX_PATH_LEN: usize = 4095;
+
+/// `struct open_how` from `linux/openat2.h`; libc's is not constructible
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// How path components are opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolver {
+    /// `openat2` confined beneath the directory, refusing symlinks
+    Openat2,
+    /// `openat` with `O_NOFOLLOW`, for kernels before 5.6
+    NoFollow,
+}
+
+/// An open workspace directory that input files are written into
+#[derive(Debug)]
+pub struct Workspace {
+    dir: OwnedFd,
+    resolver: Resolver,
+}
+
+impl Workspace {
+    /// Open the workspace at `path`, creating it if needed
+    ///
+    /// `path` itself comes from the config and is trusted, so symlinks
+    /// leading to it are followed; only what is inside it is not.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] if `path` cannot be resolved, and
+    /// [`LeewardError::Io`] if it cannot be created or opened.
+    pub fn open(path: &Path) -> Result<Self> {
+        let path = CanonicalPath::resolve(path, &PathPolicy::host())
+            .map_err(|e| LeewardError::Config(format!("workdir: {e}")))?;
+        std::fs::create_dir_all(&path)?;
+        let dir = open_at(
+            libc::AT_FDCWD,
+            &path_cstring(path.as_path())?,
+            libc::O_DIRECTORY | libc::O_RDONLY,
+            Resolver::NoFollow,
+        )?;
+        let resolver = if openat2_available(&dir) {
+            Resolver::Openat2
+        } else {
+            Resolver::NoFollow
+        };
+        Ok(Self { dir, resolver })
+    }
+
+    /// Use `resolver` instead of the one picked for this kernel
+    #[must_use]
+    pub const fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    #[must_use]
+    pub const fn resolver(&self) -> Resolver {
+        self.resolver
+    }
+
+    /// Check every name, then write every file
+    ///
+    /// # Errors
+    ///
+    /// As [`check_names`], also [`LeewardError::InvalidRequest`] if a path
+    /// crosses a symlink, special file or hard link in the workspace, and
+    /// [`LeewardError::Io`] if a file cannot be written.
+    pub fn materialize(&self, files: &[(String, Vec<u8>)]) -> Result<()> {
+        check_names(files)?;
+        for (name, contents) in files {
+            self.write(name, contents)
+                .map_err(|e| staging_error(name, &e))?;
+        }
+        Ok(())
+    }
+
+    /// Create (or empty) the file `name`, for contents written separately
+    ///
+    /// Use [`check_paths`] first on every name of the request; this checks
+    /// `name` alone, not how it collides with the others.
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::InvalidRequest`] if `name` is not allowed or crosses a
+    /// symlink, special file or hard link in the workspace, and
+    /// [`LeewardError::Io`] if the file cannot be created.
+    pub fn create(&self, name: &str) -> Result<std::fs::File> {
+        check_name(name).map_err(|reason| {
+            LeewardError::InvalidRequest(format!("input file {name:?}: {reason}"))
+        })?;
+        self.open_file(name).map_err(|e| staging_error(name, &e))
+    }
+
+    /// Write one file whose name already passed [`check_names`]
+    fn write(&self, name: &str, contents: &[u8]) -> std::io::Result<()> {
+        use std::io::Write;
+
+        self.open_file(name)?.write_all(contents)
+    }
+
+    /// Open one file whose name already passed [`check_names`], emptied
+    fn open_file(&self, name: &str) -> std::io::Result<std::fs::File> {
+        let mut components: Vec<&str> = name.split('/').collect();
+        let file_name = components.pop().unwrap_or_default();
+
+        let mut parent: Option<OwnedFd> = None;
+        for component in components {
+            let dir = parent.as_ref().unwrap_or(&self.dir).as_raw_fd();
+            let component = CString::new(component)?;
+
+            // mkdirat never follows a symlink in the last component; one
+            // already there shows up as EEXIST and is refused by the open
+            // SAFETY: mkdirat relative to a directory fd we hold
+            if unsafe { libc::mkdirat(dir, component.as_ptr(), 0o755) } != 0 {
+                let error = std::io::Error::last_os_error();
+                if error.raw_os_error() != Some(libc::EEXIST) {
+                    return Err(error);
+                }
+            }
+            parent = Some(open_at(
+                dir,
+                &component,
+                libc::O_DIRECTORY | libc::O_RDONLY,
+                self.resolver,
+            )?);
+        }
+
+        // O_NONBLOCK keeps a planted FIFO from blocking the open
+        let dir = parent.as_ref().unwrap_or(&self.dir).as_raw_fd();
+        let fd = open_at(
+            dir,
+            &CString::new(file_name)?,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_NONBLOCK,
+            self.resolver,
+        )?;
+        let file = std::fs::File::from(fd);
+
+        // A hard link would let the write land in a file outside the workspace
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || std::os::unix::fs::MetadataExt::nlink(&metadata) > 1 {
+            return Err(std::io::Error::from_raw_os_error(libc::EPERM));
+        }
+        file.set_len(0)?;
+        Ok(file)
+    }
+}
+
+/// Check input file names before anything is written
+///
+/// # Errors
+///
+/// [`LeewardError::InvalidRequest`] naming the first bad file.
+pub fn check_names(files: &[(String, Vec<u8>)]) -> Result<()> {
+    check_paths(files.iter().map(|(name, _)| name.as_str()))
+}
+
+/// Check the names of every input file of a request, however each one's
+/// contents arrive, as [`check_names`] does
+///
+/// # Errors
+///
+/// As [`check_names`].
+pub fn check_paths<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    // Lowercased path -> (original, is a directory)
+    let mut seen: HashMap<String, (&str, bool)> = HashMap::new();
+
+    for name in names {
+        check_name(name).map_err(|reason| {
+            LeewardError::InvalidRequest(format!("input file {name:?}: {reason}"))
+        })?;
+
+        let mut end = 0;
+        for component in name.split('/') {
+            end += component.len();
+            let path = &name[..end];
+            let is_dir = end < name.len();
+            end += 1;
+
+            match seen.insert(path.to_lowercase(), (path, is_dir)) {
+                None => {}
+                Some((other, other_is_dir)) if other == path && is_dir && other_is_dir => {}
+                Some((other, _)) => {
+                    return Err(LeewardError::InvalidRequest(format!(
+                        "input file {name:?}: {path:?} collides with {other:?}"
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Why `name` is not an acceptable input file name, if it is not
+fn check_name(name: &str) -> std::result::Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("empty name");
+    }
+    if name.len() > MAX_PATH_LEN {
+        return Err("name too long");
+    }
+    if let Err(e) = CanonicalPath::resolve(name, &PathPolicy::relative_name()) {
+        return Err(match e.kind {
+            PathErrorKind::NotRelative => "absolute path",
+            PathErrorKind::ParentDir => "relative path component",
+            _ => "control character in name",
+        });
+    }
+    if name.chars().any(char::is_control) {
+        return Err("control character in name");
+    }
+
+    let components: Vec<&str> = name.split('/').collect();
+    if components.len() > MAX_DEPTH {
+        return Err("too deeply nested");
+    }
+    for component in components {
+        match component {
+            "" => return Err("empty path component"),
+            "." => return Err("relative path component"),
+            _ if component.len() > MAX_COMPONENT_LEN => return Err("path component too long"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Error for a file that could not be staged
+pub(crate) fn staging_error(name: &str, error: &std::io::Error) -> LeewardError {
+    match error.raw_os_error() {
+        Some(libc::ELOOP | libc::EXDEV | libc::ENOTDIR | libc::EPERM | libc::ENXIO) => {
+            LeewardError::InvalidRequest(format!(
+                "input file {name:?}: path crosses a symlink, special file or hard link in the \
+                 workspace"
+            ))
+        }
+        _ => LeewardError::Io(std::io::Error::new(
+            error.kind(),
+            format!("input file {name:?}: {error}"),
+        )),
+    }
+}
+
+/// Open one component relative to `dir` without following symlinks
+fn open_at(
+    dir: libc::c_int,
+    path: &CString,
+    flags: libc::c_int,
+    resolver: Resolver,
+) -> std::io::Result<OwnedFd> {
+    let flags = flags | libc::O_CLOEXEC | libc::O_NOFOLLOW;
+
+    let ret = match resolver {
+        Resolver::Openat2 => {
+            let how = OpenHow {
+                flags: u64::try_from(flags).unwrap_or_default(),
+                mode: if flags & libc::O_CREAT == 0 { 0 } else { 0o644 },
+                resolve: libc::RESOLVE_BENEATH
+                    | libc::RESOLVE_NO_SYMLINKS
+                    | libc::RESOLVE_NO_MAGICLINKS,
+            };
+            // SAFETY: openat2 with a valid path and a correctly sized open_how
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_openat2,
+                    dir,
+                    path.as_ptr(),
+                    &raw const how,
+                    std::mem::size_of::<OpenHow>(),
+                )
+            };
+            libc::c_int::try_from(ret).unwrap_or(-1)
+        }
+        // SAFETY: openat with a valid path
+        Resolver::NoFollow => unsafe { libc::openat(dir, path.as_ptr(), flags, 0o644) },
+    };
+
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: The open succeeded and returned a new fd we now own
+    Ok(unsafe { OwnedFd::from_raw_fd(ret) })
+}
+
+/// Whether this kernel (and any seccomp filter) lets `openat2` through
+fn openat2_available(dir: &OwnedFd) -> bool {
+    let Ok(here) = CString::new(".") else {
+        return false;
+    };
+    match open_at(
+        dir.as_raw_fd(),
+        &here,
+        libc::O_DIRECTORY | libc::O_RDONLY,
+        Resolver::Openat2,
+    ) {
+        Ok(_) => true,
+        Err(e) => !matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)),
+    }
+}
+
+fn path_cstring(path: &Path) -> Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        LeewardError::InvalidRequest(format!("path contains a NUL byte: {}", path.display()))
+    })
+}
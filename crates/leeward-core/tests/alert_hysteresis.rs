@@ -0,0 +1,73 @@
+//! A saturation episode that flaps around the threshold fires and resolves once
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::alert::{AlertMonitor, AlertThresholds, PoolSample};
+use leeward_core::protocol::{AlertEvent, AlertKind, AlertState};
+
+fn depth(queue_depth: u64) -> PoolSample {
+    PoolSample {
+        queue_depth,
+        ..PoolSample::default()
+    }
+}
+
+#[test]
+fn flapping_episode_fires_and_resolves_once() {
+    let mut monitor = AlertMonitor::new(AlertThresholds {
+        queue_depth: Some(8),
+        ..AlertThresholds::default()
+    })
+    .with_clear_samples(3);
+
+    let events: Vec<AlertEvent> = [0, 5, 9, 7, 12, 3, 8, 2, 1, 0, 0, 0, 4]
+        .into_iter()
+        .flat_map(|value| monitor.evaluate(&depth(value)))
+        .collect();
+
+    assert_eq!(
+        events,
+        [
+            AlertEvent {
+                alert: AlertKind::QueueDepth,
+                state: AlertState::Firing,
+                value: 9,
+                threshold: 8,
+            },
+            AlertEvent {
+                alert: AlertKind::QueueDepth,
+                state: AlertState::Resolved,
+                value: 0,
+                threshold: 8,
+            },
+        ]
+    );
+    assert_eq!(
+        monitor.firing(),
+        [] as [leeward_core::protocol::AlertKind; 0]
+    );
+}
+
+#[test]
+fn disabled_thresholds_never_fire() {
+    let mut monitor = AlertMonitor::new(AlertThresholds {
+        worker_dead_count: Some(1),
+        ..AlertThresholds::default()
+    });
+
+    let sample = PoolSample {
+        queue_depth: 1000,
+        queue_wait_ms: 60_000,
+        dead_workers: 1,
+        outdated_interpreters: 1,
+    };
+    let events = monitor.evaluate(&sample);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].alert, AlertKind::WorkerDead);
+    assert_eq!(monitor.firing(), [AlertKind::WorkerDead]);
+    assert!(
+        monitor.evaluate(&sample).is_empty(),
+        "a firing alert fired again"
+    );
+}
@@ -0,0 +1,122 @@
+//! Each bind mount gets its own `nosuid`, `nodev` and `noexec` flags, kept
+//! when a read-only bind is remounted
+
+mod common;
+
+use leeward_core::LeewardError;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::{BindOptions, MountConfig};
+use nix::sched::CloneFlags;
+use nix::sys::statvfs::{FsFlags, statvfs};
+use std::path::Path;
+
+fn expect(holds: bool, what: &str) -> leeward_core::Result<()> {
+    if holds {
+        Ok(())
+    } else {
+        Err(LeewardError::Mount(what.to_owned()))
+    }
+}
+
+/// Apply `mounts` in a mount namespace of its own and run `check` there
+fn in_mount_namespace(
+    mounts: &MountConfig,
+    check: impl FnOnce() -> leeward_core::Result<()>,
+) -> bool {
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        mounts.apply()?;
+        check()
+    })
+    .unwrap();
+    common::succeeded(pid)
+}
+
+fn flags(path: &Path) -> leeward_core::Result<FsFlags> {
+    statvfs(path)
+        .map(|stat| stat.flags())
+        .map_err(|e| LeewardError::Mount(format!("statvfs: {e}")))
+}
+
+#[test]
+fn binds_default_to_nosuid_and_nodev_but_executable() {
+    let options = BindOptions::default();
+    assert!(options.nosuid && options.nodev && options.rec);
+    assert!(!options.noexec);
+
+    let dir = common::Scratch::new("bind-default");
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::create_dir_all(&dst).unwrap();
+    let mounts = MountConfig::default().rw_bind(&src, &dst);
+    assert!(in_mount_namespace(&mounts, || {
+        let flags = flags(&dst)?;
+        expect(
+            flags.contains(FsFlags::ST_NOSUID | FsFlags::ST_NODEV),
+            "nosuid and nodev",
+        )?;
+        expect(!flags.contains(FsFlags::ST_NOEXEC), "noexec")?;
+        expect(!flags.contains(FsFlags::ST_RDONLY), "read-only")
+    }));
+}
+
+#[test]
+fn scratch_binds_refuse_to_execute() {
+    let dir = common::Scratch::new("bind-noexec");
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::create_dir_all(&dst).unwrap();
+    std::fs::copy("/bin/true", src.join("true")).unwrap();
+    let mounts = MountConfig::default().rw_bind_with_opts(&src, &dst, BindOptions::SCRATCH);
+    assert!(in_mount_namespace(&mounts, || {
+        expect(flags(&dst)?.contains(FsFlags::ST_NOEXEC), "noexec")?;
+        let ran = std::process::Command::new(dst.join("true")).status();
+        expect(
+            ran.is_err_and(|e| e.raw_os_error() == Some(libc::EACCES)),
+            "ran from a noexec bind",
+        )
+    }));
+}
+
+#[test]
+fn read_only_binds_keep_their_flags() {
+    let dir = common::Scratch::new("bind-ro");
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::create_dir_all(&dst).unwrap();
+    let options = BindOptions {
+        nosuid: false,
+        noexec: true,
+        ..BindOptions::default()
+    };
+    let mounts = MountConfig::default().ro_bind_with_opts(&src, &dst, options);
+    assert!(in_mount_namespace(&mounts, || {
+        let flags = flags(&dst)?;
+        expect(
+            flags.contains(FsFlags::ST_RDONLY | FsFlags::ST_NOEXEC | FsFlags::ST_NODEV),
+            "ro, noexec and nodev",
+        )?;
+        expect(!flags.contains(FsFlags::ST_NOSUID), "nosuid")
+    }));
+}
+
+#[test]
+fn devices_need_binds_without_nodev() {
+    let dir = common::Scratch::new("bind-dev");
+    let (blocked, usable) = (dir.join("blocked"), dir.join("usable"));
+    std::fs::File::create(&blocked).unwrap();
+    std::fs::File::create(&usable).unwrap();
+    let mounts = MountConfig::default()
+        .rw_bind("/dev/null", &blocked)
+        .rw_bind_with_opts("/dev/null", &usable, BindOptions::DEVICE);
+    assert!(in_mount_namespace(&mounts, || {
+        let opened = std::fs::OpenOptions::new().write(true).open(&blocked);
+        expect(
+            opened.is_err_and(|e| e.raw_os_error() == Some(libc::EACCES)),
+            "opened a device on a nodev bind",
+        )?;
+        std::fs::write(&usable, b"discarded")?;
+        Ok(())
+    }));
+}
@@ -0,0 +1,263 @@
+//! Worker cgroups hold the sandbox's CPU share in `cpu.max` and its process
+//! limit in `pids.max`, and executions report the CPU time they used and
+//! fail when they hit the limit
+//!
+//! The fake cgroups are plain directories, which read the same. Real
+//! workers need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::isolation::{CgroupHandle, CpuStat};
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A directory laid out like a cgroup, removed on drop
+struct FakeCgroup(PathBuf);
+
+impl Drop for FakeCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FakeCgroup {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-cgroup-cpu-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cgroup = Self(dir);
+        cgroup.set("memory.current", "4096\n");
+        cgroup.set("memory.events", "oom_kill 0\n");
+        cgroup.set(
+            "cpu.stat",
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 4\nthrottled_usec 20\n",
+        );
+        cgroup.set("pids.current", "3\n");
+        cgroup.set("pids.events", "max 0\n");
+        cgroup
+    }
+
+    fn set(&self, file: &str, contents: &str) {
+        std::fs::write(self.0.join(file), contents).unwrap();
+    }
+
+    fn read(&self, file: &str) -> String {
+        std::fs::read_to_string(self.0.join(file)).unwrap()
+    }
+}
+
+#[test]
+fn cpu_and_pid_files_are_read_and_written() {
+    let fake = FakeCgroup::new("files");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+
+    assert_eq!(
+        cgroup.cpu_stat().unwrap(),
+        CpuStat {
+            usage_usec: 1500,
+            user_usec: 1000,
+            system_usec: 500,
+            throttled_usec: 20,
+        }
+    );
+    assert_eq!(cgroup.pids_current().unwrap(), 3);
+    assert_eq!(cgroup.pids_limit_hits().unwrap(), 0);
+
+    cgroup.set_cpu_quota(100_000, 50_000).unwrap();
+    assert_eq!(fake.read("cpu.max"), "50000 100000");
+    cgroup.set_pids_max(Some(32)).unwrap();
+    assert_eq!(fake.read("pids.max"), "32");
+    cgroup.set_pids_max(None).unwrap();
+    assert_eq!(fake.read("pids.max"), "max");
+
+    // Without the cpu controller there is no throttling to report, and
+    // without the pids controller nothing was held back
+    fake.set("cpu.stat", "usage_usec 7\nuser_usec 4\nsystem_usec 3\n");
+    assert_eq!(cgroup.cpu_stat().unwrap().throttled_usec, 0);
+    std::fs::remove_file(fake.0.join("pids.events")).unwrap();
+    assert_eq!(cgroup.pids_limit_hits().unwrap(), 0);
+    std::fs::remove_file(fake.0.join("pids.current")).unwrap();
+    assert!(matches!(
+        cgroup.pids_current(),
+        Err(LeewardError::Cgroup(_))
+    ));
+
+    fake.set("cpu.stat", "usage_usec lots\n");
+    assert!(matches!(cgroup.cpu_stat(), Err(LeewardError::Cgroup(_))));
+}
+
+#[test]
+fn watches_report_cpu_time_and_pid_limit_hits() {
+    let fake = FakeCgroup::new("watch");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+
+    let watch = cgroup.watch().unwrap();
+    fake.set(
+        "cpu.stat",
+        "usage_usec 4000\nuser_usec 3000\nsystem_usec 1000\n",
+    );
+    fake.set("pids.events", "max 2\n");
+    let usage = watch.finish().unwrap();
+    assert_eq!(usage.cpu_time_us, Some(2500));
+    assert!(usage.pids_limited);
+
+    let watch = cgroup.watch().unwrap();
+    let usage = watch.finish().unwrap();
+    assert_eq!(usage.cpu_time_us, Some(0));
+    assert!(!usage.pids_limited);
+
+    // Unreadable CPU time is left out rather than failing the watch
+    std::fs::remove_file(fake.0.join("cpu.stat")).unwrap();
+    let usage = cgroup.watch().unwrap().finish().unwrap();
+    assert_eq!(usage.cpu_time_us, None);
+}
+
+#[test]
+fn cpu_quota_follows_the_percent_and_period() {
+    let config = SandboxConfig::default();
+    assert_eq!(config.cpu_period, Duration::from_millis(100));
+    assert_eq!(config.cpu_quota(), None);
+
+    let half = SandboxConfig::builder().cpu_percent(50).build();
+    assert_eq!(half.cpu_quota(), Some((50_000, 100_000)));
+    let two_and_a_half = SandboxConfig::builder()
+        .cpu_percent(250)
+        .cpu_period(Duration::from_millis(10))
+        .build();
+    assert_eq!(two_and_a_half.cpu_quota(), Some((25_000, 10_000)));
+    // The kernel takes no quota under 1ms
+    let sliver = SandboxConfig::builder()
+        .cpu_percent(1)
+        .cpu_period(Duration::from_millis(1))
+        .build();
+    assert_eq!(sliver.cpu_quota(), Some((1000, 1000)));
+
+    for config in [
+        SandboxConfig::builder().cpu_percent(0).build(),
+        SandboxConfig::builder()
+            .cpu_period(Duration::from_micros(999))
+            .build(),
+        SandboxConfig::builder()
+            .cpu_period(Duration::from_secs(2))
+            .build(),
+        SandboxConfig::builder().max_pids(0).build(),
+    ] {
+        assert!(
+            matches!(config.validate(), Err(LeewardError::Config(_))),
+            "{config:?}"
+        );
+    }
+    assert!(
+        SandboxConfig::builder()
+            .cpu_percent(50)
+            .max_pids(64)
+            .build()
+            .validate()
+            .is_ok()
+    );
+}
+
+/// A worker with `config` accounted under a fresh cgroup root in
+/// `LEEWARD_TEST_CGROUP_ROOT`, or `None` if there is none or code cannot
+/// run here
+fn accounted_worker(name: &str, config: SandboxConfig) -> Option<(Worker, CgroupHandle)> {
+    let Ok(root) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return None;
+    };
+    let cgroup =
+        CgroupHandle::create(Path::new(&root), &format!("{name}-{}", std::process::id())).unwrap();
+    let mut worker = Worker::new(0, config);
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return None;
+    }
+    worker.set_cgroup_root(cgroup.path()).unwrap();
+    let probe = worker.execute("pass", &ExecuteOptions::default()).unwrap();
+    if probe.stderr_str().starts_with("Failed to execute") {
+        eprintln!("skipping, execution fails here: {}", probe.stderr_str());
+        worker.stop();
+        return None;
+    }
+    Some((worker, cgroup))
+}
+
+#[test]
+fn executions_are_held_to_the_cpu_share_and_report_cpu_time() {
+    let config = SandboxConfig::builder().cpu_percent(50).build();
+    let Some((mut worker, cgroup)) = accounted_worker("cpu", config) else {
+        return;
+    };
+    let own = worker.cgroup().unwrap().clone();
+    if !own.path().join("cpu.max").exists() {
+        eprintln!("skipping: no cpu controller delegated");
+        worker.stop();
+        return;
+    }
+    assert_eq!(
+        std::fs::read_to_string(own.path().join("cpu.max"))
+            .unwrap()
+            .trim(),
+        "50000 100000"
+    );
+
+    let result = worker
+        .execute(
+            "import time\nend = time.monotonic() + 0.5\nwhile time.monotonic() < end: pass",
+            &ExecuteOptions::default(),
+        )
+        .unwrap();
+    assert!(result.is_success(), "{result:?}");
+    assert!(result.cpu_time_us > 100_000, "{result:?}");
+    assert!(own.cpu_stat().unwrap().throttled_usec > 0);
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
+
+#[test]
+fn executions_past_the_process_limit_fail_naming_it() {
+    let config = SandboxConfig::builder().max_pids(16).build();
+    let Some((mut worker, cgroup)) = accounted_worker("pids", config) else {
+        return;
+    };
+    if !worker.cgroup().unwrap().path().join("pids.max").exists() {
+        eprintln!("skipping: no pids controller delegated");
+        worker.stop();
+        return;
+    }
+    assert!(worker.cgroup().unwrap().pids_current().unwrap() <= 16);
+
+    let forks = concat!(
+        "import os\n",
+        "for _ in range(64):\n",
+        "    try:\n",
+        "        if os.fork() == 0:\n",
+        "            os._exit(0)\n",
+        "    except OSError:\n",
+        "        pass",
+    );
+    let error = worker
+        .execute(forks, &ExecuteOptions::default())
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            LeewardError::Execution(message) if message.contains("limit of 16 processes")
+        ),
+        "{error}"
+    );
+
+    // The worker carries on below the limit
+    assert!(
+        worker
+            .execute("pass", &ExecuteOptions::default())
+            .unwrap()
+            .is_success()
+    );
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
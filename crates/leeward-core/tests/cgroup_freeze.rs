@@ -0,0 +1,131 @@
+//! Cgroups freeze and thaw through `cgroup.freeze`, workers pause and
+//! resume that way, and recycling freezes a worker before killing it
+//!
+//! The fake cgroups are plain directories, which read the same. Real
+//! workers need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::isolation::CgroupHandle;
+use leeward_core::isolation::cgroups::FREEZE_TIMEOUT;
+use leeward_core::pipe::WorkerPipe;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A directory laid out like a cgroup, removed on drop
+struct FakeCgroup(PathBuf);
+
+impl Drop for FakeCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FakeCgroup {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "leeward-cgroup-freeze-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cgroup = Self(dir);
+        cgroup.set("memory.current", "4096\n");
+        cgroup.set("cgroup.events", "populated 1\nfrozen 0\n");
+        cgroup
+    }
+
+    fn set(&self, file: &str, contents: &str) {
+        std::fs::write(self.0.join(file), contents).unwrap();
+    }
+
+    fn read(&self, file: &str) -> String {
+        std::fs::read_to_string(self.0.join(file)).unwrap()
+    }
+}
+
+#[test]
+fn freezing_waits_for_the_kernel_to_say_frozen() {
+    let fake = FakeCgroup::new("files");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    assert!(!cgroup.is_frozen().unwrap());
+
+    // Nothing freezes a plain directory, so the freeze gives up
+    let started = Instant::now();
+    assert!(matches!(cgroup.freeze(), Err(LeewardError::Cgroup(_))));
+    assert!(started.elapsed() >= FREEZE_TIMEOUT);
+    assert_eq!(fake.read("cgroup.freeze"), "1");
+
+    fake.set("cgroup.events", "populated 1\nfrozen 1\n");
+    cgroup.freeze().unwrap();
+    assert!(cgroup.is_frozen().unwrap());
+    cgroup.thaw().unwrap();
+    assert_eq!(fake.read("cgroup.freeze"), "0");
+}
+
+#[test]
+fn pending_results_are_discarded_without_waiting() {
+    let pipe = WorkerPipe::new().unwrap();
+    let mut result_tx = pipe.result_tx.try_clone().unwrap();
+    let (mut parent, _child) = pipe.split();
+    assert_eq!(parent.discard_results(), 0);
+    result_tx.write_all(&[7; 10_000]).unwrap();
+    assert_eq!(parent.discard_results(), 10_000);
+    assert_eq!(parent.discard_results(), 0);
+}
+
+#[test]
+fn workers_without_a_cgroup_cannot_pause() {
+    let worker = Worker::new(0, SandboxConfig::default());
+    assert!(matches!(
+        worker.pause(),
+        Err(LeewardError::CgroupUnavailable(_))
+    ));
+    assert!(matches!(
+        worker.resume(),
+        Err(LeewardError::CgroupUnavailable(_))
+    ));
+}
+
+#[test]
+fn paused_workers_resume_and_recycling_removes_their_cgroup() {
+    let Ok(root) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return;
+    };
+    let root =
+        CgroupHandle::create(Path::new(&root), &format!("freeze-{}", std::process::id())).unwrap();
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    worker.set_cgroup_root(root.path()).unwrap();
+    let cgroup = worker.cgroup().unwrap().clone();
+
+    worker.pause().unwrap();
+    assert!(cgroup.is_frozen().unwrap());
+    worker.resume().unwrap();
+    let result = worker
+        .execute("print('ok')", &ExecuteOptions::default())
+        .unwrap();
+    assert_eq!(result.stdout_str().trim(), "ok", "{result:?}");
+
+    // Recycled while frozen, the old process is killed and its cgroup goes
+    worker.pause().unwrap();
+    worker.recycle().unwrap();
+    assert!(!cgroup.path().exists());
+    assert_ne!(worker.cgroup().unwrap().path(), cgroup.path());
+    let result = worker
+        .execute("print('again')", &ExecuteOptions::default())
+        .unwrap();
+    assert_eq!(result.stdout_str().trim(), "again", "{result:?}");
+
+    worker.stop();
+    std::thread::sleep(Duration::from_millis(10));
+    let _ = std::fs::remove_dir(root.path());
+}
@@ -0,0 +1,253 @@
+//! Executions report the peak memory and OOM kills of their worker's
+//! cgroup, sampling `memory.current` on kernels without `memory.peak`, and
+//! are held to their memory limit by it
+//!
+//! The fake cgroups are plain directories, which read the same. Real
+//! workers need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::isolation::CgroupHandle;
+use leeward_core::isolation::cgroups::SAMPLE_INTERVAL;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{ByteSize, LeewardError, SandboxConfig};
+use std::path::{Path, PathBuf};
+
+/// A directory laid out like a cgroup, removed on drop
+struct FakeCgroup(PathBuf);
+
+impl Drop for FakeCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FakeCgroup {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-cgroup-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cgroup = Self(dir);
+        cgroup.set("memory.current", "4096\n");
+        cgroup.set(
+            "memory.events",
+            "low 0\nhigh 0\nmax 3\noom 1\noom_kill 0\noom_group_kill 0\n",
+        );
+        cgroup
+    }
+
+    fn set(&self, file: &str, contents: &str) {
+        std::fs::write(self.0.join(file), contents).unwrap();
+    }
+
+    fn oom_kills(&self, count: u64) {
+        self.set(
+            "memory.events",
+            &format!("max 3\noom 2\noom_kill {count}\n"),
+        );
+    }
+}
+
+#[test]
+fn kernel_files_are_read() {
+    let fake = FakeCgroup::new("read");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    assert_eq!(cgroup.memory_current().unwrap(), 4096);
+    assert_eq!(cgroup.memory_peak().unwrap(), None);
+    assert_eq!(cgroup.oom_kills().unwrap(), 0);
+    assert!(!cgroup.was_oom_killed(0).unwrap());
+
+    fake.set("memory.peak", "1048576\n");
+    fake.oom_kills(2);
+    assert_eq!(cgroup.memory_peak().unwrap(), Some(1_048_576));
+    assert_eq!(cgroup.oom_kills().unwrap(), 2);
+    assert!(cgroup.was_oom_killed(1).unwrap());
+    assert!(!cgroup.was_oom_killed(2).unwrap());
+
+    // Kernels before the counter existed have no oom_kill line
+    fake.set("memory.events", "low 0\nhigh 0\n");
+    assert_eq!(cgroup.oom_kills().unwrap(), 0);
+
+    fake.set("memory.current", "lots\n");
+    assert!(matches!(
+        cgroup.memory_current(),
+        Err(LeewardError::Cgroup(_))
+    ));
+}
+
+#[test]
+fn directories_without_the_memory_controller_are_refused() {
+    let fake = FakeCgroup::new("bare");
+    std::fs::remove_file(fake.0.join("memory.current")).unwrap();
+    let error = CgroupHandle::open(&fake.0).unwrap_err();
+    assert!(
+        error.to_string().contains("no memory controller"),
+        "{error}"
+    );
+}
+
+#[test]
+fn cgroups_are_only_created_on_cgroup2() {
+    let fake = FakeCgroup::new("plain");
+    let error = CgroupHandle::create(&fake.0, "worker").unwrap_err();
+    assert!(
+        matches!(error, LeewardError::CgroupUnavailable(_)),
+        "{error}"
+    );
+    assert!(!fake.0.join("worker").exists());
+}
+
+#[test]
+fn limits_are_written_in_bytes_or_as_max() {
+    let fake = FakeCgroup::new("limits");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    let read = |file: &str| std::fs::read_to_string(fake.0.join(file)).unwrap();
+
+    cgroup.set_memory_max(Some(ByteSize::mib(32))).unwrap();
+    assert_eq!(read("memory.max"), "33554432");
+    cgroup.set_memory_max(None).unwrap();
+    assert_eq!(read("memory.max"), "max");
+    cgroup.set_swap_max(Some(ByteSize::from_bytes(0))).unwrap();
+    assert_eq!(read("memory.swap.max"), "0");
+
+    // Outside cgroup v2 a failed write says so
+    std::fs::remove_file(fake.0.join("memory.max")).unwrap();
+    std::fs::create_dir(fake.0.join("memory.max")).unwrap();
+    let error = cgroup.set_memory_max(None).unwrap_err();
+    assert!(
+        matches!(error, LeewardError::CgroupUnavailable(_)),
+        "{error}"
+    );
+}
+
+#[test]
+fn without_memory_peak_the_largest_sample_is_the_peak() {
+    let fake = FakeCgroup::new("sampled");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+
+    let watch = cgroup.watch().unwrap();
+    fake.set("memory.current", "65536\n");
+    std::thread::sleep(SAMPLE_INTERVAL * 10);
+    fake.set("memory.current", "8192\n");
+    fake.oom_kills(1);
+    let usage = watch.finish().unwrap();
+    assert_eq!(usage.memory_peak, 65536);
+    assert!(usage.oom_killed);
+
+    // A rise still there when the watch ends counts without a sample
+    let watch = cgroup.watch().unwrap();
+    fake.set("memory.current", "131072\n");
+    let usage = watch.finish().unwrap();
+    assert_eq!(usage.memory_peak, 131_072);
+    assert!(!usage.oom_killed);
+}
+
+/// Allocate and touch `sys.argv[1]` MiB
+const ALLOCATE: &str = "import sys; block = bytearray(int(sys.argv[1]) << 20); print(len(block))";
+
+/// A worker accounted under a fresh cgroup root in
+/// `LEEWARD_TEST_CGROUP_ROOT` with `memory.max` set to `max`, or `None` if
+/// there is none or code cannot run here
+fn accounted_worker(name: &str, max: Option<ByteSize>) -> Option<(Worker, CgroupHandle)> {
+    let Ok(root) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return None;
+    };
+    let cgroup =
+        CgroupHandle::create(Path::new(&root), &format!("{name}-{}", std::process::id())).unwrap();
+    if let Some(max) = max {
+        std::fs::write(cgroup.path().join("memory.max"), max.bytes().to_string()).unwrap();
+    }
+
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return None;
+    }
+    worker.set_cgroup_root(cgroup.path()).unwrap();
+    let probe = worker.execute("pass", &ExecuteOptions::default()).unwrap();
+    if probe.stderr_str().starts_with("Failed to execute") {
+        eprintln!("skipping, execution fails here: {}", probe.stderr_str());
+        worker.stop();
+        return None;
+    }
+    Some((worker, cgroup))
+}
+
+fn allocate(worker: &mut Worker, mib: u64) -> leeward_core::ExecutionResult {
+    let options = ExecuteOptions {
+        args: vec![mib.to_string()],
+        ..ExecuteOptions::default()
+    };
+    worker.execute(ALLOCATE, &options).unwrap()
+}
+
+#[test]
+fn executions_report_their_own_cgroup_peak() {
+    let Some((mut worker, cgroup)) = accounted_worker("peak", None) else {
+        return;
+    };
+    let big = allocate(&mut worker, 64);
+    assert_eq!(big.exit_code, 0, "{}", big.stderr_str());
+    assert!(big.memory_peak >= 64 << 20, "{big:?}");
+    assert!(!big.oom_killed);
+
+    // The next execution's peak does not include the last one's
+    let small = allocate(&mut worker, 1);
+    assert!(small.memory_peak < big.memory_peak, "{small:?}");
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
+
+#[test]
+fn executions_killed_at_memory_max_are_oom_killed() {
+    let Some((mut worker, cgroup)) = accounted_worker("oom", Some(ByteSize::mib(128))) else {
+        return;
+    };
+    let result = allocate(&mut worker, 512);
+    assert!(result.oom_killed, "{result:?}");
+    assert!(!result.is_success());
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
+
+#[test]
+fn executions_are_held_to_their_memory_limit() {
+    let Some((mut worker, cgroup)) = accounted_worker("limit", None) else {
+        return;
+    };
+    let limited = ExecuteOptions {
+        memory_limit: Some(ByteSize::mib(32)),
+        ..ExecuteOptions::default()
+    };
+
+    let mapped = worker
+        .execute(
+            "import mmap\ntry:\n    mmap.mmap(-1, 2 << 30)\nexcept OSError:\n    print('refused')",
+            &limited,
+        )
+        .unwrap();
+    assert_eq!(mapped.stdout_str().trim(), "refused", "{mapped:?}");
+    assert_eq!(cgroup_file(&worker, "memory.max"), "33554432");
+    assert_eq!(cgroup_file(&worker, "memory.swap.max"), "0");
+
+    let touched = worker
+        .execute(&ALLOCATE.replace("sys.argv[1]", "'64'"), &limited)
+        .unwrap();
+    assert!(!touched.is_success(), "{touched:?}");
+
+    // Without a limit the cgroup is left to its parent's
+    let result = allocate(&mut worker, 64);
+    assert!(result.is_success(), "{result:?}");
+    assert_eq!(cgroup_file(&worker, "memory.max"), "max");
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
+
+/// What `file` in the worker's own cgroup holds
+fn cgroup_file(worker: &Worker, file: &str) -> String {
+    let path = worker.cgroup().unwrap().path().join(file);
+    std::fs::read_to_string(path).unwrap().trim().to_owned()
+}
@@ -0,0 +1,112 @@
+//! Subscribers to a cgroup's OOM events are woken when its `oom_kill`
+//! counter moves past where it was when they subscribed
+//!
+//! The fake cgroups are plain directories, which inotify watches the same.
+
+#![cfg(feature = "cgroups")]
+
+use leeward_core::isolation::{CgroupHandle, OomEventReceiver};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+/// A directory laid out like a cgroup, removed on drop
+struct FakeCgroup(PathBuf);
+
+impl Drop for FakeCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FakeCgroup {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-cgroup-oom-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cgroup = Self(dir);
+        std::fs::write(cgroup.0.join("memory.current"), "4096\n").unwrap();
+        cgroup.set_kills(2);
+        cgroup
+    }
+
+    fn set_kills(&self, kills: u64) {
+        std::fs::write(
+            self.0.join("memory.events"),
+            format!("oom 0\noom_kill {kills}\n"),
+        )
+        .unwrap();
+    }
+}
+
+/// Wakes by sending on a channel
+struct Signal(mpsc::Sender<()>);
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        let _ = self.0.send(());
+    }
+}
+
+/// Poll `receiver` until it resolves, only when woken, or `None` after
+/// `timeout`
+fn wait(receiver: &mut OomEventReceiver, timeout: Duration) -> Option<leeward_core::Result<u64>> {
+    let (tx, rx) = mpsc::channel();
+    let waker = Waker::from(Arc::new(Signal(tx)));
+    let mut cx = Context::from_waker(&waker);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Poll::Ready(kills) = Pin::new(&mut *receiver).poll(&mut cx) {
+            return Some(kills);
+        }
+        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .ok()?;
+    }
+}
+
+#[test]
+fn subscribers_wake_on_new_kills_only() {
+    let fake = FakeCgroup::new("wake");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    let mut receiver = cgroup.subscribe_oom_events().unwrap();
+    assert_eq!(receiver.kills().unwrap(), 0);
+
+    // Changes that kill nothing wake it without resolving it
+    std::fs::write(fake.0.join("memory.events"), "oom 1\noom_kill 2\n").unwrap();
+    assert!(wait(&mut receiver, Duration::from_millis(200)).is_none());
+
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        fake.set_kills(3);
+        fake
+    });
+    assert_eq!(
+        wait(&mut receiver, Duration::from_secs(5))
+            .unwrap()
+            .unwrap(),
+        1
+    );
+    assert_eq!(receiver.kills().unwrap(), 1);
+    let fake = writer.join().unwrap();
+
+    // A later subscriber counts from where it subscribed
+    let receiver = cgroup.subscribe_oom_events().unwrap();
+    assert_eq!(receiver.kills().unwrap(), 0);
+    drop(fake);
+}
+
+#[test]
+fn subscribing_needs_memory_events() {
+    let fake = FakeCgroup::new("missing");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    std::fs::remove_file(fake.0.join("memory.events")).unwrap();
+    assert!(matches!(
+        cgroup.subscribe_oom_events(),
+        Err(leeward_core::LeewardError::Cgroup(_))
+    ));
+}
@@ -0,0 +1,114 @@
+//! Workers start in their cgroup rather than being moved there, falling
+//! back to moving them where the kernel cannot clone into it
+//!
+//! Real cgroups need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::CgroupHandle;
+use leeward_core::isolation::clone3::clone_worker_into;
+use leeward_core::worker::{Worker, WorkerUids};
+use std::os::fd::AsFd;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Exit status of `pid`, once it exits
+fn exit_status(pid: i32) -> i32 {
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "status {status}");
+    libc::WEXITSTATUS(status)
+}
+
+/// The cgroup `pid` is in, relative to the cgroup v2 root
+fn cgroup_of(pid: i32) -> String {
+    let cgroups = std::fs::read_to_string(format!("/proc/{pid}/cgroup")).unwrap();
+    cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .unwrap_or_else(|| panic!("no cgroup v2 entry in {cgroups}"))
+        .to_owned()
+}
+
+/// A fresh cgroup under `LEEWARD_TEST_CGROUP_ROOT`, if it is set
+fn test_cgroup(name: &str) -> Option<CgroupHandle> {
+    let Ok(parent) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return None;
+    };
+    Some(
+        CgroupHandle::create(
+            Path::new(&parent),
+            &format!("{name}-{}", std::process::id()),
+        )
+        .unwrap(),
+    )
+}
+
+#[test]
+fn children_start_where_the_caller_is_when_given_no_cgroup() {
+    // A plain directory is not a cgroup the kernel can clone into
+    let dir = std::fs::File::open(std::env::temp_dir()).unwrap();
+    let (pid, in_cgroup) = clone_worker_into(0, Some(dir.as_fd()), || Ok(())).unwrap();
+    assert!(!in_cgroup);
+    assert_eq!(exit_status(pid), 0);
+}
+
+#[test]
+fn children_start_in_the_cgroup() {
+    let Some(cgroup) = test_cgroup("clone-into") else {
+        return;
+    };
+    let name = cgroup
+        .path()
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let dir = cgroup.directory().unwrap();
+    let (pid, in_cgroup) = clone_worker_into(0, Some(dir.as_fd()), move || {
+        let own = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        // SAFETY: Exiting the child with whether it started in the cgroup
+        unsafe { libc::_exit(i32::from(!own.trim_end().ends_with(&format!("/{name}")))) }
+    })
+    .unwrap();
+    assert!(in_cgroup);
+    assert_eq!(exit_status(pid), 0);
+    cgroup.remove().unwrap();
+}
+
+#[test]
+fn workers_are_spawned_into_their_own_cgroup_and_it_goes_with_them() {
+    let Some(root) = test_cgroup("spawn-into") else {
+        return;
+    };
+    let mut worker =
+        Worker::new(0, SandboxConfig::default()).with_uids(Arc::new(WorkerUids::new("spawn")));
+    worker.set_cgroup_root(root.path()).unwrap();
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+
+    let mut paths = Vec::new();
+    for _ in 0..3 {
+        let cgroup = worker.cgroup().unwrap().path().to_path_buf();
+        let name = worker.uid.as_ref().unwrap().cgroup_name();
+        assert!(cgroup.ends_with(&name), "{}", cgroup.display());
+        assert!(cgroup_of(worker.pid.unwrap()).ends_with(&format!("/{name}")));
+        paths.push(cgroup);
+        worker.recycle().unwrap();
+        assert!(!paths.last().unwrap().exists(), "old cgroup left behind");
+    }
+    worker.stop();
+    let left: Vec<_> = std::fs::read_dir(root.path())
+        .unwrap()
+        .filter_map(|entry| entry.ok().filter(|entry| entry.path().is_dir()))
+        .collect();
+    assert!(left.is_empty(), "{left:?} left behind");
+    root.remove().unwrap();
+}
@@ -0,0 +1,52 @@
+//! Scratch directories and child processes for the tests in `tests/`
+
+// Each test binary uses only some of these
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty directory of its own for a test, removed with everything in it
+/// on drop
+pub struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    /// Make `leeward-<name>-<pid>` in the temporary directory, emptying
+    /// whatever an earlier run left there
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("leeward-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Wait for `pid` and say whether it exited 0
+pub fn succeeded(pid: libc::pid_t) -> bool {
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+}
@@ -0,0 +1,65 @@
+//! Config fingerprints must be stable across restarts and field reordering
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::fingerprint::fingerprint;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Serialize)]
+struct Ordered {
+    timeout: u64,
+    network: bool,
+    binds: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Reordered {
+    binds: Vec<&'static str>,
+    network: bool,
+    timeout: u64,
+}
+
+#[test]
+fn field_order_does_not_change_fingerprint() {
+    let a = Ordered {
+        timeout: 30,
+        network: false,
+        binds: vec!["/usr", "/lib"],
+    };
+    let b = Reordered {
+        binds: vec!["/usr", "/lib"],
+        network: false,
+        timeout: 30,
+    };
+
+    assert_eq!(fingerprint(&a), fingerprint(&b));
+}
+
+#[test]
+fn fingerprint_is_pinned() {
+    let value = Ordered {
+        timeout: 30,
+        network: false,
+        binds: vec!["/usr", "/lib"],
+    };
+
+    // sha256 of {"binds":["/usr","/lib"],"network":false,"timeout":30}
+    assert_eq!(
+        fingerprint(&value),
+        "5a316e9118682778b269c2cc57df3ac46603acc5042d7e0df5f90332edf09fff"
+    );
+}
+
+#[test]
+fn any_policy_change_changes_fingerprint() {
+    let base = SandboxConfig::default();
+    assert_eq!(base.fingerprint(), SandboxConfig::default().fingerprint());
+
+    let changed = SandboxConfig {
+        timeout: base.timeout + Duration::from_secs(1),
+        ..base.clone()
+    };
+    assert_ne!(base.fingerprint(), changed.fingerprint());
+}
@@ -0,0 +1,90 @@
+//! With networking on, the sockets an execution opens count against its
+//! `max_connections`: the one past the limit fails with `EMFILE`, and the
+//! result reports the sockets opened and the connects made on them
+
+#![cfg(all(feature = "protocol", feature = "seccomp"))]
+
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::seccomp;
+use leeward_core::network::ConnectionTracker;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use std::process::Command;
+
+const LIMIT: u32 = 3;
+
+/// Whether seccomp user notifications work here
+fn notify_available() -> bool {
+    match seccomp::spawn_supervised(&mut Command::new("true"), &[libc::SYS_socket]) {
+        Ok((mut child, _)) => {
+            let _ = child.wait();
+            true
+        }
+        Err(e) => {
+            eprintln!("skipping: no seccomp user notifications here: {e}");
+            false
+        }
+    }
+}
+
+#[test]
+fn connect_is_counted_apart_from_the_limit() {
+    let connections = ConnectionTracker::default();
+    connections.begin(Some(1));
+    assert_eq!(connections.on_syscall(libc::SYS_socket), None);
+    assert_eq!(connections.on_syscall(libc::SYS_connect), None);
+    assert_eq!(connections.on_syscall(libc::SYS_connect), None);
+    assert_eq!(connections.on_syscall(libc::SYS_socket), Some(libc::EMFILE));
+    assert_eq!((connections.opened(), connections.connects()), (1, 2));
+
+    connections.begin(None);
+    assert_eq!((connections.opened(), connections.connects()), (0, 0));
+}
+
+#[test]
+fn sockets_past_the_limit_fail_with_emfile() {
+    if !notify_available() {
+        return;
+    }
+    let config = SandboxConfig {
+        allow_network: true,
+        ..SandboxConfig::minimal_for_tests()
+    };
+    let mut worker = Worker::new(0, config);
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    // Every socket but the last stays open; the first is also connected,
+    // to a port nothing listens on
+    let code = format!(
+        "import errno, socket\n\
+         held = []\n\
+         for _ in range({}):\n\
+         \x20   try:\n\
+         \x20       held.append(socket.socket())\n\
+         \x20   except OSError as e:\n\
+         \x20       print(errno.errorcode[e.errno])\n\
+         try:\n\
+         \x20   held[0].connect(('127.0.0.1', 65535))\n\
+         except OSError:\n\
+         \x20   pass\n\
+         print(len(held))\n",
+        LIMIT + 1
+    );
+    let options = ExecuteOptions {
+        max_connections: Some(LIMIT),
+        ..ExecuteOptions::default()
+    };
+    let result = worker.execute(&code, &options);
+    worker.stop();
+
+    let result = result.unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping, execution fails here: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(result.stdout_str(), format!("EMFILE\n{LIMIT}\n"));
+    let network = result.network.expect("no network usage reported");
+    assert_eq!(network.connections_opened, LIMIT);
+    assert_eq!(network.connects, 1);
+}
@@ -0,0 +1,368 @@
+//! A controlled network gives a worker a veth link to the host: the parent
+//! creates it into the worker's namespace, the worker addresses its end
+//! and routes through the host's, and its resolv.conf names the configured
+//! nameserver
+
+mod common;
+
+use leeward_core::config::{Ipv4Net, NetworkConfig};
+use leeward_core::isolation::clone3::{clone_worker, clone_worker_with};
+use leeward_core::isolation::netns::{ControlledNetwork, NetworkSlot, NetworkSlots, RESOLV_CONF};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Out of the way of anything the host is likely to use
+const EGRESS: &str = "10.253.0.0/16";
+
+const DNS: Ipv4Addr = Ipv4Addr::new(10, 253, 0, 53);
+
+/// For links made without the host side's table, whose filter would keep
+/// the worker from the host
+const UNFILTERED: &str = "10.252.0.0/16";
+
+/// For workers trying to reach each other, apart from the other tests'
+const APART: &str = "10.251.0.0/16";
+
+fn controlled(slot: u32) -> ControlledNetwork {
+    ControlledNetwork::new(EGRESS.parse().unwrap(), DNS, slot)
+}
+
+#[test]
+fn networks_parse_and_print_like_ip_takes_them() {
+    let net: Ipv4Net = "10.253.7.9/16".parse().unwrap();
+    assert_eq!(net.to_string(), "10.253.0.0/16");
+    assert_eq!(net.size(), 65536);
+    assert!(net.contains(Ipv4Addr::new(10, 253, 255, 255)));
+    assert!(!net.contains(Ipv4Addr::new(10, 254, 0, 0)));
+    assert_eq!(net.nth(65535), Some(Ipv4Addr::new(10, 253, 255, 255)));
+    assert_eq!(net.nth(65536), None);
+    assert_eq!("0.0.0.0/0".parse::<Ipv4Net>().unwrap().size(), 1 << 32);
+
+    for bad in [
+        "10.253.0.0",
+        "10.253.0.0/33",
+        "10.253.0.0/+8",
+        "10.253.0/16",
+        "/16",
+    ] {
+        assert!(bad.parse::<Ipv4Net>().is_err(), "{bad}");
+    }
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn controlled_networks_serialize_by_name() {
+    let network = NetworkConfig::Controlled {
+        egress_cidr: EGRESS.parse().unwrap(),
+        dns: DNS,
+    };
+    let json = serde_json::to_string(&network).unwrap();
+    assert_eq!(
+        json,
+        r#"{"controlled":{"egress_cidr":"10.253.0.0/16","dns":"10.253.0.53"}}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<NetworkConfig>(&json).unwrap(),
+        network
+    );
+    assert_eq!(
+        serde_json::to_string(&NetworkConfig::LoopbackOnly).unwrap(),
+        r#""loopback_only""#
+    );
+}
+
+#[test]
+fn each_worker_gets_its_own_link_addresses() {
+    assert_eq!(
+        controlled(0).addresses().unwrap(),
+        (Ipv4Addr::new(10, 253, 0, 1), Ipv4Addr::new(10, 253, 0, 2))
+    );
+    assert_eq!(
+        controlled(70).addresses().unwrap(),
+        (Ipv4Addr::new(10, 253, 1, 25), Ipv4Addr::new(10, 253, 1, 26))
+    );
+    // A /16 has room for 16384 links
+    assert!(controlled(16383).addresses().is_ok());
+    assert!(controlled(16384).addresses().is_err());
+}
+
+#[test]
+fn slots_are_leased_lowest_first_and_given_back() {
+    let slots = Arc::new(NetworkSlots::default());
+    let (a, b, c) = (slots.lease(), slots.lease(), slots.lease());
+    assert_eq!([a.get(), b.get(), c.get()], [0, 1, 2]);
+    drop(b);
+    drop(a);
+    let again: Vec<NetworkSlot> = (0..3).map(|_| slots.lease()).collect();
+    assert_eq!(
+        again.iter().map(NetworkSlot::get).collect::<Vec<_>>(),
+        [0, 1, 3]
+    );
+    drop(c);
+    assert_eq!(slots.lease().get(), 2);
+}
+
+#[test]
+fn the_egress_network_needs_room_only_for_live_links() {
+    let network = NetworkConfig::Controlled {
+        egress_cidr: "10.253.0.0/29".parse().unwrap(),
+        dns: DNS,
+    };
+    let config = SandboxConfig::builder().network(network).build();
+    let slots = Arc::new(NetworkSlots::default());
+    // Room for two links, whichever workers they are for
+    let links: Vec<NetworkSlot> = (0..2).map(|_| slots.lease()).collect();
+    for slot in &links {
+        let network = ControlledNetwork::for_worker(&config, 0).unwrap();
+        assert!(network.in_slot(slot.get()).addresses().is_ok());
+    }
+    let third = slots.lease();
+    let network = ControlledNetwork::for_worker(&config, 0).unwrap();
+    assert!(network.in_slot(third.get()).addresses().is_err());
+    drop(third);
+    drop(links);
+    assert!(
+        ControlledNetwork::for_worker(&config, 0)
+            .unwrap()
+            .in_slot(slots.lease().get())
+            .addresses()
+            .is_ok()
+    );
+}
+
+#[test]
+fn a_controlled_network_needs_its_own_namespace_and_room() {
+    let network = NetworkConfig::Controlled {
+        egress_cidr: EGRESS.parse().unwrap(),
+        dns: DNS,
+    };
+    let config = SandboxConfig::builder().network(network).build();
+    config.validate().unwrap();
+    assert!(ControlledNetwork::for_worker(&config, 3).is_some());
+
+    let config = SandboxConfig::builder()
+        .network(network)
+        .allow_network(true)
+        .build();
+    assert!(matches!(config.validate(), Err(LeewardError::Config(_))));
+    assert!(ControlledNetwork::for_worker(&config, 3).is_none());
+
+    let network = NetworkConfig::Controlled {
+        egress_cidr: "10.253.0.0/31".parse().unwrap(),
+        dns: DNS,
+    };
+    let config = SandboxConfig::builder().network(network).build();
+    assert!(matches!(config.validate(), Err(LeewardError::Config(_))));
+}
+
+#[test]
+fn the_worker_reaches_the_host_through_its_link() {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let network =
+        ControlledNetwork::new(UNFILTERED.parse().unwrap(), DNS, std::process::id() % 8192);
+    let (gateway, _) = network.addresses().unwrap();
+
+    let cloned = clone_worker_with(
+        libc::CLONE_NEWNET as u64,
+        None,
+        |pid| network.create_link(pid),
+        || {
+            network.setup_worker_side()?;
+            let mut stream = TcpStream::connect((gateway, port))?;
+            let mut greeting = String::new();
+            stream.read_to_string(&mut greeting)?;
+            if greeting == "hello" {
+                Ok(())
+            } else {
+                Err(LeewardError::Namespace(format!("got {greeting:?}")))
+            }
+        },
+    );
+    let pid = match cloned {
+        Ok((pid, _)) => pid,
+        Err(e) => {
+            eprintln!("skipping: no veth links here: {e}");
+            return;
+        }
+    };
+
+    let (mut stream, peer) = listener.accept().unwrap();
+    assert_eq!(peer.ip(), network.addresses().unwrap().1);
+    std::io::Write::write_all(&mut stream, b"hello").unwrap();
+    drop(stream);
+    assert!(common::succeeded(pid));
+    // Gone with the worker's namespace, which the kernel tears down in the background
+    let link = std::ffi::CString::new(ControlledNetwork::host_link(pid)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    // SAFETY: if_nametoindex reads a NUL-terminated name
+    while unsafe { libc::if_nametoindex(link.as_ptr()) } != 0 {
+        assert!(Instant::now() < deadline, "{link:?} outlived its namespace");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn the_host_side_needs_nft_to_masquerade() {
+    let network = controlled(8192 + std::process::id() % 8192);
+    let nft = std::process::Command::new("nft")
+        .arg("--version")
+        .output()
+        .is_ok();
+    let cloned = clone_worker_with(
+        libc::CLONE_NEWNET as u64,
+        None,
+        |pid| network.setup_host_side(pid),
+        || Ok(()),
+    );
+    match cloned {
+        Ok((pid, _)) => {
+            assert!(nft);
+            assert!(common::succeeded(pid));
+        }
+        Err(e) if !nft => assert!(e.to_string().contains("nft"), "{e}"),
+        Err(e) => eprintln!("skipping: nft cannot masquerade here: {e}"),
+    }
+}
+
+#[test]
+fn the_ruleset_keeps_workers_apart_and_off_private_networks() {
+    let allowed: Ipv4Net = "192.168.7.0/24".parse().unwrap();
+    let ruleset = controlled(0).allow_private([allowed]).ruleset();
+    for rule in [
+        "ip saddr 10.253.0.0/16 masquerade",
+        "iifname \"lw*\" oifname \"lw*\" drop",
+        "ip saddr 10.253.0.0/16 ip daddr 10.253.0.53 meta l4proto { tcp, udp } th dport 53 accept",
+        "ip saddr 10.253.0.0/16 ip daddr { 192.168.7.0/24 } accept",
+        "ip saddr 10.253.0.0/16 ip daddr { 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, \
+         100.64.0.0/10, 127.0.0.0/8, 169.254.0.0/16 } drop",
+        "iifname \"lw*\" ip saddr 10.253.0.0/16 drop",
+    ] {
+        assert!(ruleset.contains(rule), "{rule} missing from\n{ruleset}");
+    }
+    // Allowed networks are let through before the private ones are dropped
+    assert!(ruleset.find("192.168.7.0/24").unwrap() < ruleset.find("192.168.0.0/16").unwrap());
+    assert!(!controlled(0).ruleset().contains("192.168.7.0/24"));
+    // Workers have no IPv6 address, so none of their IPv6 gets anywhere
+    let ip6 = &ruleset[ruleset.find("table ip6 leeward_10_253_0_0_16 {").unwrap()..];
+    assert_eq!(ip6.matches("iifname \"lw*\" drop").count(), 2, "{ip6}");
+
+    let config = SandboxConfig::builder()
+        .network(NetworkConfig::Controlled {
+            egress_cidr: EGRESS.parse().unwrap(),
+            dns: DNS,
+        })
+        .egress_allow_private([allowed])
+        .build();
+    assert_eq!(
+        ControlledNetwork::for_worker(&config, 0),
+        Some(controlled(0).allow_private([allowed]))
+    );
+}
+
+#[test]
+fn a_worker_reaches_neither_another_worker_nor_the_host() {
+    let host = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let host_port = host.local_addr().unwrap().port();
+    let (a, b) = (
+        ControlledNetwork::new(APART.parse().unwrap(), DNS, 0),
+        ControlledNetwork::new(APART.parse().unwrap(), DNS, 1),
+    );
+    let (gateway, _) = a.addresses().unwrap();
+    let (_, b_address) = b.addresses().unwrap();
+    let (mut ready, mut b_end) = UnixStream::pair().unwrap();
+
+    // Worker B listens until worker A is done
+    let cloned = clone_worker_with(
+        libc::CLONE_NEWNET as u64,
+        None,
+        |pid| b.setup_host_side(pid),
+        || {
+            b.setup_worker_side()?;
+            let _listener = TcpListener::bind((b_address, host_port))?;
+            // Its copy of the other end keeps EOF away if the test fails
+            b_end.set_read_timeout(Some(Duration::from_secs(60)))?;
+            b_end.write_all(b"r")?;
+            b_end.read_exact(&mut [0])?;
+            Ok(())
+        },
+    );
+    let b_pid = match cloned {
+        Ok((pid, _)) => pid,
+        Err(e) => {
+            eprintln!("skipping: nft cannot filter here: {e}");
+            return;
+        }
+    };
+    ready.read_exact(&mut [0]).unwrap();
+
+    let unreachable = |address: Ipv4Addr| {
+        TcpStream::connect_timeout(&(address, host_port).into(), Duration::from_secs(1)).is_err()
+    };
+    let a_pid = clone_worker_with(
+        libc::CLONE_NEWNET as u64,
+        None,
+        |pid| a.setup_host_side(pid),
+        || {
+            a.setup_worker_side()?;
+            if !unreachable(b_address) {
+                return Err(LeewardError::Namespace(format!(
+                    "reached worker B at {b_address}"
+                )));
+            }
+            if !unreachable(gateway) {
+                return Err(LeewardError::Namespace(format!(
+                    "reached the host at {gateway}"
+                )));
+            }
+            Ok(())
+        },
+    )
+    .unwrap()
+    .0;
+    let contained = common::succeeded(a_pid);
+    ready.write_all(b"d").unwrap();
+    assert!(common::succeeded(b_pid));
+    assert!(contained);
+}
+
+#[test]
+fn the_worker_gets_a_resolv_conf_naming_the_nameserver() {
+    if !std::path::Path::new(RESOLV_CONF).exists() {
+        eprintln!("skipping: no {RESOLV_CONF} to bind over");
+        return;
+    }
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Mount(e.to_string()))?;
+        // Keep the bind from reaching the host
+        nix::mount::mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            nix::mount::MsFlags::MS_REC | nix::mount::MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .map_err(|e| LeewardError::Mount(e.to_string()))?;
+        controlled(0).resolv_conf().bind()?;
+
+        let contents = std::fs::read_to_string(RESOLV_CONF)?;
+        if contents != "nameserver 10.253.0.53\n" {
+            return Err(LeewardError::Mount(format!(
+                "resolv.conf says {contents:?}"
+            )));
+        }
+        match std::fs::write(RESOLV_CONF, "nameserver 192.0.2.1\n") {
+            Err(e) if e.raw_os_error() == Some(libc::EROFS) => Ok(()),
+            other => Err(LeewardError::Mount(format!(
+                "writing resolv.conf: {other:?}"
+            ))),
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
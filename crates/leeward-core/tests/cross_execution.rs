@@ -0,0 +1,336 @@
+//! No execution sees what an earlier one left, whichever worker either ran
+//! on: random interleavings of writers planting secrets everywhere they can
+//! and probers looking for them, on reused workers with and without a root
+//! template
+//!
+//! The run is seeded from `LEEWARD_PROPERTY_SEED` if set, so a failure can
+//! be replayed; the seed is printed either way.
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::config::Interpreter;
+use leeward_core::isolation::RootTemplate;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{ExecutionResult, SandboxConfig};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STEPS: usize = 24;
+const WORKERS: usize = 2;
+
+/// First System V key a writer uses; each adds its step
+const SHM_KEY: u32 = 0x1ee0_0000;
+
+/// Plant `sys.argv[1]` in files, System V shared memory and processes that
+/// outlive the execution, one of them in a session of its own
+const PYTHON_WRITER: &str = r#"
+import ctypes, os, socket, sys, time
+secret, key = sys.argv[1], int(sys.argv[2])
+for d in {os.environ["HOME"], os.getcwd(), "/tmp", "/dev/shm"}:
+    try:
+        with open(os.path.join(d, secret), "w") as f:
+            f.write(secret)
+    except OSError:
+        pass
+try:
+    libc = ctypes.CDLL(None, use_errno=True)
+    libc.shmget(key, 4096, 0o1666)
+except OSError:
+    pass
+for detach in (False, True):
+    if os.fork() == 0:
+        if detach:
+            os.setsid()
+        try:
+            listener = socket.socket(socket.AF_UNIX)
+            listener.bind("\0" + secret + str(detach))
+            listener.listen(1)
+        except OSError:
+            pass
+        os.closerange(0, 3)
+        time.sleep(30)
+        os._exit(0)
+"#;
+
+/// The same from a shell, where `$1` is the secret
+const SHELL_WRITER: &str = r#"
+for d in "$HOME" "$PWD" /tmp /dev/shm; do echo "$1" > "$d/$1" 2>/dev/null; done
+(setsid sleep 30 "$1" >/dev/null 2>&1 &)
+(sleep 30 "$1" >/dev/null 2>&1 &)
+"#;
+
+/// Print `LEAK <secret> <where>` for each of `sys.argv[1:]` found anywhere
+/// a writer may have left it
+const PROBER: &str = r#"
+import ctypes, os, socket, sys
+base = int(sys.argv[1])
+secrets = sys.argv[2:]
+def leak(secret, where):
+    print("LEAK", secret, where)
+for d in {os.environ["HOME"], os.getcwd(), "/tmp", "/dev/shm"}:
+    for root, _, names in os.walk(d):
+        for name in names:
+            path = os.path.join(root, name)
+            try:
+                contents = open(path, errors="replace").read(4096)
+            except OSError:
+                contents = ""
+            for secret in secrets:
+                if secret in name or secret in contents:
+                    leak(secret, path)
+try:
+    libc = ctypes.CDLL(None, use_errno=True)
+    for i, secret in enumerate(secrets):
+        if libc.shmget(base + i, 0, 0) >= 0:
+            leak(secret, "sysv-shm")
+except OSError:
+    pass
+for secret in secrets:
+    for detach in (False, True):
+        try:
+            socket.socket(socket.AF_UNIX).connect("\0" + secret + str(detach))
+            leak(secret, "process")
+        except OSError:
+            pass
+    for name, value in os.environ.items():
+        if secret in value:
+            leak(secret, "env " + name)
+for pid in os.listdir("/proc") if os.path.isdir("/proc") else []:
+    for part in ("cmdline", "environ"):
+        try:
+            data = open("/proc/%s/%s" % (pid, part), "rb").read().decode(errors="replace")
+        except OSError:
+            continue
+        for secret in secrets:
+            if secret in data:
+                leak(secret, "process %s %s" % (pid, part))
+"#;
+
+/// xorshift64*, enough to pick interleavings
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let value = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32;
+        usize::try_from(value).unwrap() % n
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Write(Interpreter),
+    Probe,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Planned {
+    index: usize,
+    worker: usize,
+    step: Step,
+}
+
+fn seed() -> u64 {
+    std::env::var("LEEWARD_PROPERTY_SEED").map_or_else(
+        |_| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            u64::try_from(now.as_nanos() % u128::from(u64::MAX)).unwrap() | 1
+        },
+        |seed| seed.parse().expect("LEEWARD_PROPERTY_SEED is a number"),
+    )
+}
+
+fn plan(seed: u64) -> Vec<Planned> {
+    let mut rng = Rng(seed);
+    let steps = [
+        Step::Write(Interpreter::Python),
+        Step::Write(Interpreter::Sh),
+        Step::Probe,
+    ];
+    (0..STEPS)
+        .map(|index| Planned {
+            index,
+            worker: rng.below(WORKERS),
+            step: steps[rng.below(steps.len())],
+        })
+        .collect()
+}
+
+fn secret(seed: u64, index: usize) -> String {
+    format!("leeward-secret-{seed:x}-{index}")
+}
+
+fn key(index: usize) -> u32 {
+    SHM_KEY + u32::try_from(index).unwrap()
+}
+
+/// Workers for one mode, or `None` if they cannot run code here
+fn workers(rooted: bool) -> Option<Vec<Worker>> {
+    let config = SandboxConfig::default();
+    let template = if rooted {
+        match RootTemplate::build(&config) {
+            Ok(template) => Some(Arc::new(template)),
+            Err(e) => {
+                eprintln!("skipping: no root template here: {e}");
+                return None;
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut workers = Vec::new();
+    for id in 0..WORKERS {
+        let mut worker = Worker::new(u32::try_from(id).unwrap(), config.clone());
+        if let Some(template) = &template {
+            worker = worker.with_root_template(Arc::clone(template));
+        }
+        if let Err(e) = worker.spawn() {
+            eprintln!("skipping: no sandbox here: {e}");
+            stop(workers);
+            return None;
+        }
+        workers.push(worker);
+    }
+    Some(workers)
+}
+
+fn stop(workers: Vec<Worker>) {
+    for worker in workers {
+        if let Some(pid) = worker.info().pid {
+            // SAFETY: Killing and reaping our own worker
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+                libc::waitpid(pid, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
+
+/// Run `steps` in order, returning what each prober found, as
+/// (prober index, secret, where), or `None` if code cannot run here
+fn run(seed: u64, rooted: bool, steps: &[Planned]) -> Option<Vec<(usize, String, String)>> {
+    let mut workers = workers(rooted)?;
+    let mut leaks = Vec::new();
+
+    for planned in steps {
+        let (code, options) = match planned.step {
+            Step::Write(interpreter) => {
+                let secret = secret(seed, planned.index);
+                let code = if interpreter == Interpreter::Python {
+                    PYTHON_WRITER
+                } else {
+                    SHELL_WRITER
+                };
+                let options = ExecuteOptions {
+                    interpreter,
+                    args: vec![secret.clone(), key(planned.index).to_string()],
+                    env: vec![("LEEWARD_SECRET".into(), secret.clone())],
+                    files: vec![(format!("input-{secret}"), secret.into_bytes())],
+                    ..ExecuteOptions::default()
+                };
+                (code, options)
+            }
+            Step::Probe => {
+                let mut args = vec![SHM_KEY.to_string()];
+                // Keys are probed by position, so every step gets one
+                args.extend((0..=planned.index).map(|index| secret(seed, index)));
+                (
+                    PROBER,
+                    ExecuteOptions {
+                        args,
+                        ..ExecuteOptions::default()
+                    },
+                )
+            }
+        };
+        let result: ExecutionResult = workers[planned.worker].execute(code, &options).unwrap();
+        if result.stderr_str().starts_with("Failed to execute") {
+            eprintln!("skipping, execution fails here: {}", result.stderr_str());
+            stop(workers);
+            return None;
+        }
+        if planned.step == Step::Probe {
+            assert_eq!(
+                result.exit_code,
+                0,
+                "prober failed: {}",
+                result.stderr_str()
+            );
+            for line in result.stdout_str().lines() {
+                let mut parts = line.splitn(3, ' ').skip(1);
+                let (Some(secret), Some(place)) = (parts.next(), parts.next()) else {
+                    continue;
+                };
+                leaks.push((planned.index, secret.to_owned(), place.to_owned()));
+            }
+        }
+    }
+    stop(workers);
+    Some(leaks)
+}
+
+/// The shortest run found that still leaks: the writer and the prober
+/// alone if that is enough, else everything up to the prober
+fn minimize(
+    seed: u64,
+    rooted: bool,
+    steps: &[Planned],
+    prober: usize,
+    secret: &str,
+) -> Vec<Planned> {
+    let writer = steps
+        .iter()
+        .find(|planned| self::secret(seed, planned.index) == secret)
+        .copied()
+        .unwrap();
+    let pair = [writer, steps[prober]];
+    let reproduces = run(seed, rooted, &pair)
+        .is_some_and(|leaks| leaks.iter().any(|(_, found, _)| found == secret));
+    if reproduces {
+        pair.to_vec()
+    } else {
+        steps[..=prober].to_vec()
+    }
+}
+
+fn check(rooted: bool) {
+    let seed = seed();
+    let mode = if rooted { "template root" } else { "host root" };
+    eprintln!("{mode}: LEEWARD_PROPERTY_SEED={seed}");
+    let steps = plan(seed);
+    let Some(leaks) = run(seed, rooted, &steps) else {
+        return;
+    };
+
+    if let Some((prober, secret, place)) = leaks.first() {
+        let reproduction = minimize(seed, rooted, &steps, *prober, secret);
+        let sequence = reproduction
+            .iter()
+            .map(|planned| {
+                format!(
+                    "#{} {:?} on worker {}",
+                    planned.index, planned.step, planned.worker
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        panic!(
+            "{mode}: step #{prober} found {secret} in {place} ({} leak(s) in all); \
+             reproduce with LEEWARD_PROPERTY_SEED={seed}: {sequence}",
+            leaks.len()
+        );
+    }
+}
+
+#[test]
+fn nothing_leaks_between_executions_under_a_template_root() {
+    check(true);
+}
+
+#[test]
+fn nothing_leaks_between_executions_on_the_host_root() {
+    check(false);
+}
@@ -0,0 +1,108 @@
+//! Processes of one execution cannot trace each other, nor read each
+//! other's memory, unless it runs under the debug profile
+
+#![cfg(all(feature = "seccomp", feature = "protocol"))]
+
+use leeward_core::escape::{self, SandboxProbe, Verdict};
+use leeward_core::isolation::SeccompConfig;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{ExecutionResult, SandboxConfig};
+
+/// Errno of a `PTRACE_ATTACH` from a process under the worker's filter to
+/// a child it forked, or 0 if it attached
+fn attach_errno(allow_debugging: bool) -> i32 {
+    let pid = clone_worker(0, move || {
+        let config = SeccompConfig {
+            allow_debugging,
+            ..SeccompConfig::default()
+        };
+        config.apply()?;
+
+        // SAFETY: The child only waits to be killed
+        let child = unsafe { libc::fork() };
+        if child == 0 {
+            loop {
+                // SAFETY: pause has no failure modes that matter here
+                unsafe { libc::pause() };
+            }
+        }
+        // SAFETY: Attaching to, then killing and reaping, our own child
+        unsafe {
+            let ret = libc::ptrace(
+                libc::PTRACE_ATTACH,
+                libc::c_long::from(child),
+                std::ptr::null_mut::<libc::c_void>(),
+                std::ptr::null_mut::<libc::c_void>(),
+            );
+            let errno = if ret == 0 {
+                0
+            } else {
+                std::io::Error::last_os_error().raw_os_error().unwrap_or(-1)
+            };
+            libc::kill(child, libc::SIGKILL);
+            libc::waitpid(child, std::ptr::null_mut(), 0);
+            libc::_exit(errno)
+        }
+    })
+    .unwrap();
+
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "status {status}");
+    libc::WEXITSTATUS(status)
+}
+
+#[test]
+fn filter_denies_attaching_by_default() {
+    assert_eq!(attach_errno(false), libc::EPERM);
+}
+
+#[test]
+fn filter_allows_attaching_under_the_debug_profile() {
+    match attach_errno(true) {
+        0 => {}
+        libc::EPERM => eprintln!("skipping: the host forbids ptrace (Yama or an outer sandbox)"),
+        errno => panic!("attach failed with errno {errno}"),
+    }
+}
+
+/// Run `code` in a new worker under `config`, which is killed afterwards
+fn run_in_worker(code: &str, config: &SandboxConfig) -> leeward_core::Result<ExecutionResult> {
+    let mut worker = Worker::new(0, config.clone());
+    worker.spawn()?;
+    let result = worker.execute(code, &ExecuteOptions::default());
+    worker.stop();
+    result
+}
+
+#[test]
+fn siblings_attach_only_under_the_debug_profile() {
+    let probe = escape::probe("ptrace_sibling").unwrap();
+    for debug in [false, true] {
+        let config = SandboxConfig {
+            debug,
+            ..SandboxConfig::default()
+        };
+        match run_in_worker("pass", &config) {
+            Ok(result) if result.exit_code == 0 => assert_eq!(result.debug, debug),
+            Ok(result) => {
+                eprintln!("skipping, execution fails here: {}", result.stderr_str());
+                return;
+            }
+            Err(e) => {
+                eprintln!("skipping, no sandbox here: {e}");
+                return;
+            }
+        }
+
+        let report = SandboxProbe::new(|code: &str| run_in_worker(code, &config)).run(probe);
+        match (debug, report.verdict) {
+            (false, Verdict::Denied(denial)) => assert_eq!(denial, probe.expected),
+            (true, Verdict::Escaped(_)) => {}
+            (_, Verdict::Skipped(reason)) => eprintln!("skipping: {reason}"),
+            (debug, verdict) => panic!("debug profile {debug}: {verdict}"),
+        }
+    }
+}
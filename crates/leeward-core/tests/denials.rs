@@ -0,0 +1,267 @@
+//! Syscalls refused through a notification stream are summarized per
+//! execution, within bounds however many the code provokes, including
+//! those the allowlist sends to the listener instead of killing the caller
+
+#![cfg(feature = "seccomp")]
+
+use leeward_core::denial::{self, DenialLog, MAX_DENIALS, MAX_DESCRIBED};
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::seccomp::{
+    self, NotificationStream, SeccompNotification, SeccompNotifyFd, SeccompResponse, Supervisor,
+};
+use leeward_core::isolation::{SeccompConfig, SyscallRule};
+use leeward_core::network::ConnectionTracker;
+use leeward_core::result::{Denial, DenialLayer};
+use std::cell::Cell;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+/// Run `script` under python3 with `syscalls` routed, or None to skip
+fn python(script: &str, syscalls: &[i64]) -> Option<(Child, NotificationStream)> {
+    let mut command = Command::new("python3");
+    command
+        .args(["-c", script])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    match seccomp::spawn_supervised(&mut command, syscalls) {
+        Ok(spawned) => Some(spawned),
+        Err(e) => {
+            eprintln!("skipping: {e}");
+            None
+        }
+    }
+}
+
+#[test]
+fn supervisor_records_refused_sockets() {
+    let script = concat!(
+        "import socket\n",
+        "for _ in range(3):\n",
+        "    try:\n",
+        "        socket.socket()\n",
+        "    except OSError:\n",
+        "        pass\n",
+    );
+    let Some((mut child, stream)) = python(script, &[libc::SYS_socket]) else {
+        return;
+    };
+    let connections = Arc::new(ConnectionTracker::default());
+    connections.begin(Some(0));
+    let denials = Arc::new(DenialLog::default());
+    denials.begin();
+
+    Supervisor::new(connections).run(stream.recording(Arc::clone(&denials)));
+    child.wait().unwrap();
+
+    let denials = denials.take();
+    assert_eq!(
+        denials,
+        [Denial {
+            layer: DenialLayer::Seccomp,
+            what: "socket(AF_INET, SOCK_STREAM)".into(),
+            count: 3,
+        }]
+    );
+    assert_eq!(denial::summary(&denials), "socket(AF_INET, SOCK_STREAM) x3");
+}
+
+#[test]
+fn embedder_denials_name_the_address() {
+    let script = concat!(
+        "import socket\n",
+        "for _ in range(3):\n",
+        "    try:\n",
+        "        socket.create_connection(('1.2.3.4', 443))\n",
+        "    except OSError:\n",
+        "        pass\n",
+    );
+    let Some((mut child, stream)) = python(script, &[libc::SYS_connect]) else {
+        return;
+    };
+    let denials = Arc::new(DenialLog::default());
+    denials.begin();
+
+    for pending in stream.recording(Arc::clone(&denials)) {
+        pending
+            .unwrap()
+            .respond(SeccompResponse::DenyWithError(libc::ENETUNREACH))
+            .unwrap();
+    }
+    child.wait().unwrap();
+
+    assert_eq!(denial::summary(&denials.take()), "connect(1.2.3.4:443) x3");
+    assert_eq!(denials.take(), [], "a second take found denials again");
+}
+
+#[test]
+fn floods_are_counted_per_syscall_past_the_bounds() {
+    let log = DenialLog::default();
+    log.begin();
+    let described = Cell::new(0);
+    for i in 0..5000 {
+        log.record(DenialLayer::Seccomp, "openat", || {
+            described.set(described.get() + 1);
+            format!("openat(/nonexistent/{i})")
+        });
+    }
+    assert_eq!(described.get(), MAX_DESCRIBED);
+
+    let denials = log.take();
+    assert_eq!(denials.len(), MAX_DENIALS + 1);
+    assert_eq!(denials[0].what, "openat(/nonexistent/0)");
+    let overflow = denials.last().unwrap();
+    assert_eq!(
+        (overflow.what.as_str(), overflow.count),
+        ("openat(...)", 5000 - MAX_DENIALS as u64)
+    );
+
+    // A new execution starts from nothing
+    log.record(DenialLayer::Landlock, "openat", || {
+        "openat(/etc/shadow)".into()
+    });
+    log.begin();
+    assert_eq!(log.take(), []);
+}
+
+#[test]
+fn totals_count_per_syscall_across_executions() {
+    let log = DenialLog::default();
+    for _ in 0..2 {
+        log.begin();
+        log.record(DenialLayer::Seccomp, "socket", || {
+            "socket(AF_INET, SOCK_STREAM)".into()
+        });
+        log.record(DenialLayer::Seccomp, "connect", || {
+            "connect(1.2.3.4:443)".into()
+        });
+        let _ = log.take();
+    }
+    log.record(DenialLayer::Landlock, "openat", || {
+        "openat(/etc/shadow)".into()
+    });
+    let totals: Vec<_> = log.totals().into_iter().collect();
+    assert_eq!(
+        totals,
+        [
+            ("connect".to_string(), 2),
+            ("openat".to_string(), 1),
+            ("socket".to_string(), 2)
+        ]
+    );
+}
+
+#[test]
+fn policies_decide_what_the_supervisor_would_let_through() {
+    let notification = |syscall| SeccompNotification {
+        id: 1,
+        pid: 1,
+        syscall,
+        args: [0; 6],
+    };
+    let connections = Arc::new(ConnectionTracker::default());
+    connections.begin(Some(1));
+
+    // Denied by the policy, a socket is not accounted
+    let denying =
+        Supervisor::new(Arc::clone(&connections)).with_policy(Arc::new(seccomp::deny_all));
+    assert!(matches!(
+        denying.decide(&notification(libc::SYS_socket)),
+        SeccompResponse::DenyWithEacces
+    ));
+    assert_eq!(connections.opened(), 0);
+
+    // Let through, it still counts against the limit
+    let sockets_only = |n: &SeccompNotification| match n.syscall {
+        libc::SYS_socket => SeccompResponse::Allow,
+        _ => SeccompResponse::DenyWithError(libc::EPERM),
+    };
+    let allowing = Supervisor::new(Arc::clone(&connections)).with_policy(Arc::new(sockets_only));
+    assert!(matches!(
+        allowing.decide(&notification(libc::SYS_socket)),
+        SeccompResponse::Allow
+    ));
+    assert!(matches!(
+        allowing.decide(&notification(libc::SYS_socket)),
+        SeccompResponse::DenyWithError(libc::EMFILE)
+    ));
+    assert!(matches!(
+        allowing.decide(&notification(libc::SYS_connect)),
+        SeccompResponse::DenyWithError(libc::EPERM)
+    ));
+    assert_eq!(connections.opened(), 1);
+}
+
+#[test]
+fn a_denying_policy_fails_the_syscall_with_eacces() {
+    let script = concat!(
+        "import socket, sys\n",
+        "for _ in range(3):\n",
+        "    try:\n",
+        "        socket.socket()\n",
+        "    except OSError as e:\n",
+        "        code = e.errno\n",
+        "sys.exit(code)\n",
+    );
+    let Some((mut child, stream)) = python(script, &[libc::SYS_socket]) else {
+        return;
+    };
+    let denials = Arc::new(DenialLog::default());
+    denials.begin();
+
+    Supervisor::new(Arc::new(ConnectionTracker::default()))
+        .with_policy(Arc::new(seccomp::deny_all))
+        .run(stream.recording(Arc::clone(&denials)));
+    assert_eq!(child.wait().unwrap().code(), Some(libc::EACCES));
+    assert_eq!(denials.totals().get("socket"), Some(&3));
+}
+
+#[test]
+fn syscalls_the_allowlist_refuses_reach_the_listener_instead_of_killing() {
+    let mut config = SeccompConfig {
+        log_denials: false,
+        notify_denials: true,
+        ..SeccompConfig::default()
+    };
+    // The listener is handed over under the filter
+    config
+        .allowed_syscalls
+        .push(SyscallRule::allow(libc::SYS_sendmsg));
+    assert!(!config.syscalls().contains(&libc::SYS_socket));
+
+    let (parent, child) = UnixStream::pair().unwrap();
+    let pid = clone_worker(0, move || {
+        let listener = config.apply()?.expect("no listener");
+        leeward_core::socket::send_with_fds(child.as_fd(), b"l", &[listener.as_fd()])?;
+        drop(listener);
+        // SAFETY: Probing a syscall, then exiting with whether it was refused
+        unsafe {
+            let ret = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+            let refused = ret == -1 && *libc::__errno_location() == libc::EPERM;
+            libc::_exit(i32::from(!refused))
+        }
+    })
+    .unwrap();
+
+    let (_, mut fds) = leeward_core::socket::recv_with_fds(parent.as_fd(), &mut [0u8; 1]).unwrap();
+    let stream =
+        NotificationStream::new(SeccompNotifyFd::from(fds.pop().expect("no listener sent")));
+    let denials = Arc::new(DenialLog::default());
+    denials.begin();
+    Supervisor::new(Arc::new(ConnectionTracker::default()))
+        .denying_unrouted(Vec::new())
+        .run(stream.recording(Arc::clone(&denials)));
+
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "status {status}"
+    );
+    assert_eq!(
+        denial::summary(&denials.take()),
+        "socket(AF_INET, SOCK_STREAM)"
+    );
+}
@@ -0,0 +1,194 @@
+//! Known ways out of the sandbox stay shut, each by the layer meant to stop it
+//!
+//! Every test runs one probe from `leeward_core::escape` in a fresh worker
+//! and checks the exact denial it got, so a layer that silently stops
+//! enforcing shows up as a named failure. Probes skip when the kernel lacks
+//! what they exercise, and everything skips when the sandbox cannot run
+//! Python on this host.
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::escape::{self, SandboxProbe, Verdict};
+use leeward_core::isolation::RootTemplate;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{ExecutionResult, SandboxConfig};
+use std::os::unix::net::UnixListener;
+use std::sync::Arc;
+
+/// Run `code` in a new worker, which is killed afterwards
+fn run_in_worker(
+    code: &str,
+    template: Option<&Arc<RootTemplate>>,
+) -> leeward_core::Result<ExecutionResult> {
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Some(template) = template {
+        worker = worker.with_root_template(Arc::clone(template));
+    }
+    worker.spawn()?;
+    let result = worker.execute(code, &ExecuteOptions::default());
+
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+    result
+}
+
+/// Whether the sandbox can run Python here at all
+fn sandbox_runs(template: Option<&Arc<RootTemplate>>) -> bool {
+    match run_in_worker("pass", template) {
+        Ok(result) if result.exit_code == 0 => true,
+        Ok(result) => {
+            eprintln!("skipping, execution fails here: {}", result.stderr_str());
+            false
+        }
+        Err(e) => {
+            eprintln!("skipping, no sandbox here: {e}");
+            false
+        }
+    }
+}
+
+/// Run the probe `name` and require its expected denial
+fn expect_denied(
+    name: &str,
+    template: Option<&Arc<RootTemplate>>,
+    socket: Option<&std::path::Path>,
+) {
+    let probe = escape::probe(name).unwrap();
+    if let Some(feature) = probe.missing_feature() {
+        eprintln!("skipping: {} unavailable", feature.name());
+        return;
+    }
+    if !sandbox_runs(template) {
+        return;
+    }
+
+    let mut sandbox = SandboxProbe::new(|code: &str| run_in_worker(code, template));
+    if let Some(socket) = socket {
+        sandbox = sandbox.socket(socket);
+    }
+    let report = sandbox.run(probe);
+
+    match report.verdict {
+        Verdict::Skipped(reason) => eprintln!("skipping: {reason}"),
+        Verdict::Denied(denial) => assert_eq!(
+            denial, probe.expected,
+            "{name} was stopped, but not by the {}",
+            probe.layer
+        ),
+        verdict => panic!("{name}: {verdict}"),
+    }
+}
+
+#[test]
+fn proc_self_mem() {
+    expect_denied("proc_self_mem", None, None);
+}
+
+#[test]
+fn chroot_escape() {
+    expect_denied("chroot_escape", None, None);
+}
+
+#[test]
+fn daemon_socket() {
+    // The socket is only out of reach when workers run in a root template
+    let template = match RootTemplate::build(&SandboxConfig::default()) {
+        Ok(template) => Arc::new(template),
+        Err(e) => {
+            eprintln!("skipping: no root template here: {e}");
+            return;
+        }
+    };
+
+    let socket = std::env::temp_dir().join(format!("leeward-escape-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    expect_denied("daemon_socket", Some(&template), Some(&socket));
+    drop(listener);
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn sysrq_trigger() {
+    expect_denied("sysrq_trigger", None, None);
+}
+
+#[test]
+fn userns_mount() {
+    expect_denied("userns_mount", None, None);
+}
+
+#[test]
+fn setns_host() {
+    expect_denied("setns_host", None, None);
+}
+
+#[test]
+fn memfd_exec() {
+    expect_denied("memfd_exec", None, None);
+}
+
+#[test]
+fn ptrace_worker() {
+    expect_denied("ptrace_worker", None, None);
+}
+
+#[test]
+fn ptrace_sibling() {
+    expect_denied("ptrace_sibling", None, None);
+}
+
+#[test]
+fn every_probe_is_tested() {
+    let tested = [
+        "proc_self_mem",
+        "chroot_escape",
+        "daemon_socket",
+        "sysrq_trigger",
+        "userns_mount",
+        "setns_host",
+        "memfd_exec",
+        "ptrace_worker",
+        "ptrace_sibling",
+    ];
+    let names: Vec<_> = escape::PROBES.iter().map(|probe| probe.name).collect();
+    assert_eq!(names, tested);
+}
+
+#[test]
+fn verdicts() {
+    use leeward_core::escape::Denial;
+    use nix::errno::Errno;
+
+    let result = |stdout: &str, exit_code| ExecutionResult {
+        exit_code,
+        stdout: stdout.as_bytes().to_vec(),
+        ..ExecutionResult::default()
+    };
+
+    assert_eq!(
+        Verdict::from_result(&result("noise\nLEEWARD-PROBE denied 13\n", 0)),
+        Verdict::Denied(Denial::Errno(Errno::EACCES))
+    );
+    assert_eq!(
+        Verdict::from_result(&result("LEEWARD-PROBE escaped got out\n", 0)),
+        Verdict::Escaped("got out".into())
+    );
+    assert_eq!(
+        Verdict::from_result(&result("LEEWARD-PROBE unsupported no memfd\n", 0)),
+        Verdict::Skipped("no memfd".into())
+    );
+    assert_eq!(
+        Verdict::from_result(&result("", -1)),
+        Verdict::Denied(Denial::Killed)
+    );
+    assert!(matches!(
+        Verdict::from_result(&result("", 1)),
+        Verdict::Inconclusive(_)
+    ));
+}
@@ -0,0 +1,80 @@
+//! Every example builds and runs to success, or says why this host cannot
+//! run it, so they keep up with the APIs they show
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Exit code of an example when this host lacks what it needs
+const EXIT_UNSUPPORTED: i32 = 77;
+
+/// Longest an example may run
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Examples run as they are, without arguments
+const EXAMPLES: &[&str] = &[
+    "minimal_sandbox",
+    "landlock_only",
+    "seccomp_notify_supervisor",
+    "shm_roundtrip",
+    "namespace_probe",
+];
+
+/// Build every example in the profile of this test, and return where
+/// they are
+fn build_examples() -> PathBuf {
+    // Test binaries live in target/<profile>/deps, examples in target/<profile>/examples
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+    let profile = if profile_dir.ends_with("release") {
+        "release"
+    } else {
+        "dev"
+    };
+
+    let built = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "-p",
+            "leeward-core",
+            "--examples",
+            "--profile",
+            profile,
+        ])
+        .status()
+        .unwrap();
+    assert!(built.success(), "failed to build the examples");
+    profile_dir.join("examples")
+}
+
+#[test]
+fn examples_run_to_success() {
+    let dir = build_examples();
+    for name in EXAMPLES {
+        let mut child = Command::new(dir.join(name))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while child.try_wait().unwrap().is_none() {
+            if started.elapsed() > TIMEOUT {
+                child.kill().unwrap();
+                panic!("{name} ran for over {TIMEOUT:?}");
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if output.status.code() == Some(EXIT_UNSUPPORTED) {
+            eprintln!("{name}: {stderr}");
+            continue;
+        }
+        assert!(
+            output.status.success(),
+            "{name} failed with {}: {stderr}",
+            output.status
+        );
+    }
+}
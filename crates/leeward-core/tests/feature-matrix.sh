@@ -0,0 +1,35 @@
+#!/usr/bin/env bash
+# Check that leeward-core builds with every combination of its optional features.
+#
+# Usage: crates/leeward-core/tests/feature-matrix.sh [cargo subcommand, default: check]
+
+set -euo pipefail
+
+cmd="${1:-check}"
+features=(seccomp landlock shm cgroups protocol)
+count=${#features[@]}
+failed=()
+
+for ((mask = 0; mask < (1 << count); mask++)); do
+    selected=()
+    for ((i = 0; i < count; i++)); do
+        if ((mask & (1 << i))); then
+            selected+=("${features[i]}")
+        fi
+    done
+
+    list=$(IFS=,; echo "${selected[*]:-}")
+    echo "==> leeward-core features: [${list}]"
+
+    if ! cargo "$cmd" --quiet -p leeward-core --no-default-features --features "$list" --all-targets; then
+        failed+=("[${list}]")
+    fi
+done
+
+if ((${#failed[@]} > 0)); then
+    echo "feature combinations failed:" >&2
+    printf '  %s\n' "${failed[@]}" >&2
+    exit 1
+fi
+
+echo "all $((1 << count)) feature combinations passed"
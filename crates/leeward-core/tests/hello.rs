@@ -0,0 +1,230 @@
+//! The daemon's advertisement keeps a stable shape, and feature names stay
+//! the plain strings third-party clients check for
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::{
+    self, BuildInfo, DaemonInfo, Limits, Request, RequestBuilder, RequestPriority, Response,
+    SizeLimits, feature,
+};
+use leeward_core::{ByteSize, DurationSecs};
+
+fn sample() -> DaemonInfo {
+    DaemonInfo {
+        version: "0.1.0".into(),
+        protocol_version: 1,
+        features: [feature::EXEC_FILES, feature::WIRE_JSON]
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+        languages: vec!["python".into(), "sh".into()],
+        limits: Limits {
+            max_message_bytes: 16_777_216,
+            max_code_bytes: 1_024_000,
+            fast_path_max_bytes: None,
+            default_timeout_ms: 30_000,
+            max_timeout_ms: Some(300_000),
+            default_memory_limit: Some(ByteSize::mib(256)),
+            tmp_size: ByteSize::mib(64),
+            max_request_wall: DurationSecs::from_secs(120),
+            workers: 4,
+            max_inflight_per_connection: 0,
+            max_inflight_per_peer_uid: 8,
+            upload_quota: ByteSize::gib(1),
+            upload_ttl: DurationSecs::from_secs(600),
+            sizes: SizeLimits::default(),
+        },
+        boot_id: "2f1c0e7a-5b9d-4c38-9e51-0a6f3d2b8c47".into(),
+        build: None,
+        kernel: None,
+    }
+}
+
+#[test]
+fn advertisement_json_is_stable() {
+    let golden = concat!(
+        r#"{"type":"Hello","version":"0.1.0","protocol_version":1,"#,
+        r#""features":["exec.files","wire.json"],"languages":["python","sh"],"#,
+        r#""limits":{"max_message_bytes":16777216,"max_code_bytes":1024000,"#,
+        r#""fast_path_max_bytes":null,"default_timeout_ms":30000,"max_timeout_ms":300000,"#,
+        r#""default_memory_limit":268435456,"tmp_size_bytes":67108864,"#,
+        r#""max_request_wall_secs":120,"workers":4,"#,
+        r#""max_inflight_per_connection":0,"max_inflight_per_peer_uid":8,"#,
+        r#""upload_quota_bytes":1073741824,"upload_ttl_secs":600,"#,
+        r#""sizes":{"max_request_bytes":16777216,"max_code_bytes":1024000,"#,
+        r#""max_file_bytes":1024000,"max_files_bytes":1024000,"max_stdin_bytes":1024000,"#,
+        r#""max_output_bytes":8388608}},"#,
+        r#""boot_id":"2f1c0e7a-5b9d-4c38-9e51-0a6f3d2b8c47"}"#
+    );
+    let response = Response::Hello(sample());
+    assert_eq!(
+        String::from_utf8(protocol::encode_json(&response).unwrap()).unwrap(),
+        golden
+    );
+    assert_eq!(
+        protocol::encode_json(&Request::Hello).unwrap(),
+        br#"{"type":"Hello"}"#
+    );
+
+    let decoded: Response = protocol::decode(&protocol::encode(&response).unwrap()).unwrap();
+    assert!(matches!(decoded, Response::Hello(info) if info == sample()));
+}
+
+#[test]
+fn build_info_is_optional() {
+    let with_build = DaemonInfo {
+        build: Some(BuildInfo {
+            version: "0.1.0".into(),
+            git: "v0.1.0-3-g48397b7".into(),
+            built_at: "2026-10-16T17:29:13Z".into(),
+            rustc: "rustc 1.85.0 (4d91de4e4 2025-02-17)".into(),
+            target: "x86_64-unknown-linux-gnu".into(),
+            profile: "release".into(),
+            features: vec!["leeward-core/protocol".into()],
+        }),
+        kernel: Some("6.8.0".into()),
+        ..sample()
+    };
+    // Daemons from before build info send the same as `sample()`, which
+    // `advertisement_json_is_stable` pins
+    for info in [sample(), with_build] {
+        let response = Response::Hello(info.clone());
+        let decoded: Response = protocol::decode(&protocol::encode(&response).unwrap()).unwrap();
+        assert!(matches!(decoded, Response::Hello(ref decoded) if *decoded == info));
+        let decoded: Response =
+            protocol::decode_json(&protocol::encode_json(&response).unwrap()).unwrap();
+        assert!(matches!(decoded, Response::Hello(ref decoded) if *decoded == info));
+    }
+}
+
+#[test]
+fn limits_from_daemons_without_sizes_are_the_old_fixed_ones() {
+    let json =
+        String::from_utf8(protocol::encode_json(&Response::Hello(sample())).unwrap()).unwrap();
+    let start = json.find(r#","sizes":"#).unwrap();
+    let end = start + json[start..].find('}').unwrap() + 1;
+    let older = format!("{}{}", &json[..start], &json[end..]);
+
+    let Response::Hello(info) = protocol::decode_json(older.as_bytes()).unwrap() else {
+        panic!("not a hello");
+    };
+    assert_eq!(info.limits.sizes, SizeLimits::default());
+    assert_eq!(
+        info.limits.sizes.max_request.bytes(),
+        protocol::MAX_MESSAGE_SIZE as u64
+    );
+    assert_eq!(
+        info.limits.sizes.max_code.bytes(),
+        protocol::MAX_CODE_SIZE as u64
+    );
+}
+
+#[test]
+fn feature_names_are_stable() {
+    let names = [
+        feature::EXEC_FILES,
+        feature::EXEC_STDIN,
+        feature::EXEC_ENV,
+        feature::EXEC_ARGS,
+        feature::EXEC_TIMEZONE,
+        feature::EXEC_MEMORY_LIMIT,
+        feature::EXEC_MAX_CONNECTIONS,
+        feature::EXEC_TRACEBACK,
+        feature::EXEC_PRIORITY,
+        feature::EXEC_BATCH,
+        feature::EXEC_PROFILE,
+        feature::EXEC_DEBUG,
+        feature::EXEC_UPLOADS,
+        feature::EXEC_DETACH,
+        feature::EXEC_DEFAULTS,
+        feature::EXEC_EXPLAIN,
+        feature::EXEC_FAST_PATH,
+        feature::WIRE_JSON,
+        feature::EVENTS_ALERTS,
+        feature::EVENTS_WORKER_DIED,
+        feature::POOL_RECYCLE_STALE,
+        feature::POOL_DRAIN,
+        feature::DAEMON_HANDOVER,
+        feature::DAEMON_LOG_CONTROL,
+        feature::POOL_ROOT_TEMPLATE,
+        feature::POOL_TIME_SLICING,
+        feature::SANDBOX_SECCOMP,
+        feature::SANDBOX_LANDLOCK,
+        feature::SANDBOX_NETWORK,
+    ];
+    assert_eq!(
+        names,
+        [
+            "exec.files",
+            "exec.stdin",
+            "exec.env",
+            "exec.args",
+            "exec.timezone",
+            "exec.memory_limit",
+            "exec.max_connections",
+            "exec.soft_timeout_traceback",
+            "exec.priority",
+            "exec.batch",
+            "exec.profile",
+            "exec.debug",
+            "exec.uploads",
+            "exec.detach",
+            "exec.defaults",
+            "exec.explain",
+            "exec.fast_path",
+            "wire.json",
+            "events.alerts",
+            "events.worker_died",
+            "pool.recycle_stale",
+            "pool.drain",
+            "daemon.handover",
+            "daemon.log_control",
+            "pool.root_template",
+            "pool.time_slicing",
+            "sandbox.seccomp",
+            "sandbox.landlock",
+            "sandbox.network",
+        ]
+    );
+    let languages = Interpreter::ALL.map(Interpreter::wire_name);
+    assert_eq!(languages, ["python", "sh", "bash"]);
+}
+
+#[test]
+fn unsupported_names_what_a_request_needs() {
+    let plain = RequestBuilder::new("print(1)").build().unwrap();
+    assert_eq!(plain.required_features(), Vec::<&str>::new());
+    assert_eq!(sample().unsupported(&plain), Vec::<&str>::new());
+
+    let request = RequestBuilder::new("echo $1")
+        .interpreter(Interpreter::Bash)
+        .arg("x")
+        .with_file("input.txt", b"data".to_vec())
+        .priority(RequestPriority::High)
+        .build()
+        .unwrap();
+    assert_eq!(
+        request.required_features(),
+        [
+            feature::EXEC_FILES,
+            feature::EXEC_ARGS,
+            feature::EXEC_PRIORITY
+        ]
+    );
+    assert_eq!(
+        sample().unsupported(&request),
+        [feature::EXEC_ARGS, feature::EXEC_PRIORITY, "bash"]
+    );
+    let batch = RequestBuilder::new("print(1)")
+        .priority(RequestPriority::Batch)
+        .build()
+        .unwrap();
+    assert_eq!(
+        batch.required_features(),
+        [feature::EXEC_PRIORITY, feature::EXEC_BATCH]
+    );
+    assert!(sample().speaks(Interpreter::Sh));
+    assert!(sample().supports("exec.files"));
+    assert!(!sample().supports("exec.stream"));
+}
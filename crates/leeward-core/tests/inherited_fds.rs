@@ -0,0 +1,71 @@
+//! A worker keeps none of the descriptors its parent had open, only the
+//! pipes and sockets it was handed
+
+#![cfg(feature = "protocol")]
+
+mod common;
+
+use leeward_core::isolation::clone3::{clone_worker, close_inherited};
+use leeward_core::worker::Worker;
+use leeward_core::{LeewardError, SandboxConfig};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// The files `pid` has open, by where their descriptors lead
+fn open_files(pid: libc::pid_t) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{pid}/fd"))? {
+        if let Ok(target) = std::fs::read_link(entry?.path()) {
+            files.push(target);
+        }
+    }
+    Ok(files)
+}
+
+#[test]
+fn only_the_kept_descriptors_survive() {
+    let dir = common::Scratch::new("fds-kept");
+    let (kept_path, closed_path) = (dir.join("kept"), dir.join("closed"));
+    let kept = std::fs::File::create(&kept_path).unwrap();
+    let closed = std::fs::File::create(&closed_path).unwrap();
+    let (kept_fd, closed_fd) = (kept.as_raw_fd(), closed.as_raw_fd());
+
+    let pid = clone_worker(0, move || {
+        close_inherited(&[kept_fd]);
+        let open = |fd: i32| Path::new(&format!("/proc/self/fd/{fd}")).exists();
+        if open(kept_fd) && !open(closed_fd) && open(2) {
+            Ok(())
+        } else {
+            Err(LeewardError::Execution(
+                "the wrong descriptors were closed".into(),
+            ))
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn workers_hold_none_of_the_daemons_descriptors() {
+    let dir = common::Scratch::new("fds-daemon");
+    let path = dir.join("held");
+    let daemon_file = std::fs::File::create(&path).unwrap();
+    let mut worker = Worker::new(0, SandboxConfig::minimal_for_tests());
+    let spawned = worker.spawn();
+    let files = worker.info().pid.map(open_files);
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+    drop(daemon_file);
+
+    if let Err(e) = spawned {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let files = files.unwrap().unwrap();
+    assert!(!files.contains(&path), "{files:?}");
+}
@@ -0,0 +1,299 @@
+//! Input files land inside the workspace and nowhere else
+//!
+//! Each attack shape runs under both resolvers: `openat2` where the kernel
+//! has it, and the `O_NOFOLLOW` walk used on older kernels.
+
+use leeward_core::LeewardError;
+use leeward_core::workspace::{self, Resolver, Workspace};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory holding `workspace/` and `outside/`
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("leeward-input-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("workspace")).unwrap();
+        std::fs::create_dir_all(root.join("outside")).unwrap();
+        std::fs::write(root.join("outside/secret"), "untouched").unwrap();
+        Self(root)
+    }
+
+    fn workspace(&self) -> PathBuf {
+        self.0.join("workspace")
+    }
+
+    fn outside(&self) -> PathBuf {
+        self.0.join("outside")
+    }
+
+    fn secret(&self) -> String {
+        std::fs::read_to_string(self.outside().join("secret")).unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The resolvers this kernel can run
+fn resolvers(path: &Path) -> Vec<Resolver> {
+    let workspace = Workspace::open(path).unwrap();
+    if workspace.resolver() == Resolver::Openat2 {
+        vec![Resolver::Openat2, Resolver::NoFollow]
+    } else {
+        eprintln!("openat2 unavailable, testing the fallback only");
+        vec![Resolver::NoFollow]
+    }
+}
+
+fn materialize(
+    path: &Path,
+    resolver: Resolver,
+    files: &[(&str, &str)],
+) -> leeward_core::Result<()> {
+    let files: Vec<_> = files
+        .iter()
+        .map(|(name, contents)| ((*name).to_owned(), contents.as_bytes().to_vec()))
+        .collect();
+    Workspace::open(path)
+        .unwrap()
+        .with_resolver(resolver)
+        .materialize(&files)
+}
+
+fn assert_rejected(result: &leeward_core::Result<()>) {
+    assert!(
+        matches!(result, Err(LeewardError::InvalidRequest(_))),
+        "expected a rejection, got {result:?}"
+    );
+}
+
+#[test]
+fn nested_files_are_written() {
+    let scratch = Scratch::new("nested");
+    for resolver in resolvers(&scratch.workspace()) {
+        materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("main.py", "print(1)"), ("pkg/sub/data.txt", "hello")],
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(scratch.workspace().join("pkg/sub/data.txt")).unwrap(),
+            "hello"
+        );
+
+        // Rewriting a shorter file leaves no tail of the old one
+        materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("pkg/sub/data.txt", "hi")],
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(scratch.workspace().join("pkg/sub/data.txt")).unwrap(),
+            "hi"
+        );
+    }
+}
+
+#[test]
+fn planted_directory_symlink_is_not_followed() {
+    let scratch = Scratch::new("dirlink");
+    // Left behind by an earlier execution in the same workspace
+    symlink(scratch.outside(), scratch.workspace().join("pkg")).unwrap();
+
+    for resolver in resolvers(&scratch.workspace()) {
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("pkg/secret", "owned")],
+        ));
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("pkg/new", "owned")],
+        ));
+    }
+    assert_eq!(scratch.secret(), "untouched");
+    assert!(!scratch.outside().join("new").exists());
+}
+
+#[test]
+fn planted_file_symlink_is_not_followed() {
+    let scratch = Scratch::new("filelink");
+    symlink(
+        scratch.outside().join("secret"),
+        scratch.workspace().join("main.py"),
+    )
+    .unwrap();
+    // Dangling, so following it would create the target
+    symlink(
+        scratch.outside().join("created"),
+        scratch.workspace().join("data.txt"),
+    )
+    .unwrap();
+
+    for resolver in resolvers(&scratch.workspace()) {
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("main.py", "owned")],
+        ));
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("data.txt", "owned")],
+        ));
+    }
+    assert_eq!(scratch.secret(), "untouched");
+    assert!(!scratch.outside().join("created").exists());
+}
+
+#[test]
+fn planted_hard_link_is_not_written_through() {
+    let scratch = Scratch::new("hardlink");
+    if std::fs::hard_link(
+        scratch.outside().join("secret"),
+        scratch.workspace().join("main.py"),
+    )
+    .is_err()
+    {
+        eprintln!("skipping: cannot hard link here");
+        return;
+    }
+
+    for resolver in resolvers(&scratch.workspace()) {
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("main.py", "owned")],
+        ));
+    }
+    assert_eq!(scratch.secret(), "untouched");
+}
+
+#[test]
+fn planted_fifo_does_not_block() {
+    let scratch = Scratch::new("fifo");
+    nix::unistd::mkfifo(
+        &scratch.workspace().join("main.py"),
+        nix::sys::stat::Mode::S_IRWXU,
+    )
+    .unwrap();
+
+    for resolver in resolvers(&scratch.workspace()) {
+        assert_rejected(&materialize(
+            &scratch.workspace(),
+            resolver,
+            &[("main.py", "print(1)")],
+        ));
+    }
+}
+
+#[test]
+fn bad_names_are_rejected() {
+    let long = "a".repeat(workspace::MAX_COMPONENT_LEN + 1);
+    let deep = vec!["d"; workspace::MAX_DEPTH + 1].join("/");
+    let bad = [
+        "",
+        "/etc/passwd",
+        "../escape",
+        "pkg/../../escape",
+        "./main.py",
+        "pkg//main.py",
+        "pkg/",
+        "line\nbreak",
+        "tab\there",
+        "nul\0byte",
+        "escape\u{1b}[2J",
+        long.as_str(),
+        deep.as_str(),
+    ];
+    for name in bad {
+        let files = vec![(name.to_owned(), Vec::new())];
+        assert!(
+            matches!(
+                workspace::check_names(&files),
+                Err(LeewardError::InvalidRequest(_))
+            ),
+            "{name:?} was accepted"
+        );
+    }
+
+    let at_limits = vec!["d"; workspace::MAX_DEPTH].join("/");
+    let files = vec![
+        (at_limits, Vec::new()),
+        ("a".repeat(workspace::MAX_COMPONENT_LEN), Vec::new()),
+        ("données/naïve.py".to_owned(), Vec::new()),
+    ];
+    workspace::check_names(&files).unwrap();
+}
+
+#[test]
+fn case_collisions_are_rejected() {
+    let collide = |names: &[&str]| {
+        let files: Vec<_> = names
+            .iter()
+            .map(|name| ((*name).to_owned(), Vec::new()))
+            .collect();
+        workspace::check_names(&files)
+    };
+
+    assert!(collide(&["Main.py", "main.py"]).is_err());
+    assert!(collide(&["main.py", "main.py"]).is_err());
+    assert!(collide(&["Pkg/a.py", "pkg/b.py"]).is_err());
+    // A file and a directory of the same name cannot both exist
+    assert!(collide(&["pkg", "pkg/a.py"]).is_err());
+    assert!(collide(&["pkg/a.py", "PKG"]).is_err());
+
+    collide(&["pkg/a.py", "pkg/b.py", "pkg/sub/c.py"]).unwrap();
+
+    // Nothing is written when any name is bad
+    let scratch = Scratch::new("collide");
+    assert_rejected(&materialize(
+        &scratch.workspace(),
+        Resolver::NoFollow,
+        &[("first.py", ""), ("A.py", ""), ("a.py", "")],
+    ));
+    assert!(!scratch.workspace().join("first.py").exists());
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn code_runs_beside_its_files() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::worker::{ExecuteOptions, run_python};
+
+    let scratch = Scratch::new("run");
+    let config = SandboxConfig::builder()
+        .workdir(scratch.workspace())
+        .build();
+    let options = ExecuteOptions {
+        files: vec![("pkg/data.txt".to_owned(), b"hello".to_vec())],
+        ..ExecuteOptions::default()
+    };
+    let result = run_python("print(open('pkg/data.txt').read())", &config, &options).unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(result.stdout_str().trim(), "hello");
+
+    // A rejected file fails the execution before the interpreter starts
+    symlink(scratch.outside(), scratch.workspace().join("link")).unwrap();
+    let options = ExecuteOptions {
+        files: vec![("link/secret".to_owned(), b"owned".to_vec())],
+        ..ExecuteOptions::default()
+    };
+    let result = run_python("print('ran')", &config, &options).unwrap();
+    assert_ne!(result.exit_code, 0);
+    assert_eq!(result.stdout_str(), "");
+    assert_eq!(scratch.secret(), "untouched");
+}
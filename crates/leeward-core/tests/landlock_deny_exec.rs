@@ -0,0 +1,52 @@
+//! `LandlockConfig::deny_exec` must block every `execve`
+
+#![cfg(feature = "landlock")]
+
+use leeward_core::isolation::LandlockConfig;
+use std::ffi::CString;
+
+/// Child exit code when execve failed with EACCES
+const EXIT_DENIED: i32 = 0;
+/// Child exit code when Landlock could not be applied on this kernel
+const EXIT_UNSUPPORTED: i32 = 2;
+
+#[test]
+fn deny_exec_blocks_execve_even_on_readable_paths() {
+    let id = CString::new("/usr/bin/id").unwrap();
+    let argv = [id.as_ptr(), std::ptr::null()];
+
+    // SAFETY: fork in a test; the child only applies Landlock and execs
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+
+    if pid == 0 {
+        let config = LandlockConfig::default().ro("/").deny_exec();
+        if config.apply().is_err() {
+            // SAFETY: Exiting child process
+            unsafe { libc::_exit(EXIT_UNSUPPORTED) };
+        }
+
+        // SAFETY: execv with a valid NULL-terminated argv
+        unsafe { libc::execv(id.as_ptr(), argv.as_ptr()) };
+        // SAFETY: Reading errno and exiting child process
+        unsafe {
+            let errno = *libc::__errno_location();
+            libc::_exit(if errno == libc::EACCES {
+                EXIT_DENIED
+            } else {
+                1
+            });
+        }
+    }
+
+    let mut status = 0;
+    // SAFETY: Waiting on our own child
+    unsafe { libc::waitpid(pid, &raw mut status, 0) };
+    assert!(libc::WIFEXITED(status), "child did not exit normally");
+
+    match libc::WEXITSTATUS(status) {
+        EXIT_DENIED => {}
+        EXIT_UNSUPPORTED => eprintln!("skipping: Landlock not supported by this kernel"),
+        code => panic!("execve was not denied with EACCES (child exit {code})"),
+    }
+}
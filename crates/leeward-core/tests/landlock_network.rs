@@ -0,0 +1,134 @@
+//! Landlock holds TCP `connect` and `bind` to the ports allowed, on kernels
+//! with ABI 4 or newer, and reports partial enforcement on older ones
+
+#![cfg(feature = "landlock")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::landlock::NET_ABI;
+use leeward_core::isolation::{Enforcement, LandlockConfig, LandlockStatus};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+/// Child exit codes
+const EXIT_OK: i32 = 0;
+const EXIT_APPLY_FAILED: i32 = 2;
+const EXIT_WRONG_STATUS: i32 = 3;
+const EXIT_ALLOWED_REFUSED: i32 = 4;
+const EXIT_OTHER_ALLOWED: i32 = 5;
+
+/// Run `child` in a forked process and return its exit code
+fn in_child(child: impl FnOnce() -> i32) -> i32 {
+    // SAFETY: fork in a test; the child only makes sockets and exits
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = child();
+        // SAFETY: Exiting child process
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    // SAFETY: Waiting on our own child
+    unsafe { libc::waitpid(pid, &raw mut status, 0) };
+    assert!(libc::WIFEXITED(status), "child did not exit normally");
+    libc::WEXITSTATUS(status)
+}
+
+/// Whether this kernel enforces TCP port rules
+fn net_rules() -> bool {
+    LandlockStatus::probe()
+        .abi
+        .is_some_and(|abi| abi >= NET_ABI)
+}
+
+/// Whether `result` failed as Landlock refuses an operation
+fn refused<T>(result: std::io::Result<T>) -> bool {
+    result.is_err_and(|e| e.raw_os_error() == Some(libc::EACCES))
+}
+
+/// Whether a status is what port rules get on this kernel
+fn expected(status: LandlockStatus) -> bool {
+    match LandlockStatus::probe().abi {
+        None => status.enforcement == Enforcement::NotEnforced,
+        Some(abi) if abi < NET_ABI => status.enforcement == Enforcement::Partial,
+        Some(_) => status.enforced(),
+    }
+}
+
+#[test]
+fn connecting_is_held_to_the_allowed_ports() {
+    let allowed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let other = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let allowed_port = allowed.local_addr().unwrap().port();
+    let other_port = other.local_addr().unwrap().port();
+    let enforced = net_rules();
+
+    let code = in_child(|| {
+        let config = LandlockConfig::default().ro("/").connect_tcp(allowed_port);
+        let Ok(status) = config.apply() else {
+            return EXIT_APPLY_FAILED;
+        };
+        if !expected(status) {
+            return EXIT_WRONG_STATUS;
+        }
+        if TcpStream::connect((Ipv4Addr::LOCALHOST, allowed_port)).is_err() {
+            return EXIT_ALLOWED_REFUSED;
+        }
+        if enforced != refused(TcpStream::connect((Ipv4Addr::LOCALHOST, other_port))) {
+            return EXIT_OTHER_ALLOWED;
+        }
+        EXIT_OK
+    });
+    assert_eq!(code, EXIT_OK);
+    if !enforced {
+        eprintln!("skipping enforcement: this kernel has no Landlock ABI {NET_ABI}");
+    }
+}
+
+#[test]
+fn binding_is_held_to_the_allowed_ports() {
+    // Free a port to ask for by name, and find another the same way
+    let allowed_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let other_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let enforced = net_rules();
+
+    let code = in_child(|| {
+        let config = LandlockConfig::default().ro("/").bind_tcp(allowed_port);
+        let Ok(status) = config.apply() else {
+            return EXIT_APPLY_FAILED;
+        };
+        if !expected(status) {
+            return EXIT_WRONG_STATUS;
+        }
+        if TcpListener::bind((Ipv4Addr::LOCALHOST, allowed_port)).is_err() {
+            return EXIT_ALLOWED_REFUSED;
+        }
+        if enforced != refused(TcpListener::bind((Ipv4Addr::LOCALHOST, other_port))) {
+            return EXIT_OTHER_ALLOWED;
+        }
+        EXIT_OK
+    });
+    assert_eq!(code, EXIT_OK);
+}
+
+#[test]
+fn the_sandbox_config_carries_the_ports_to_landlock() {
+    let config = SandboxConfig::builder()
+        .allow_network(true)
+        .allowed_tcp_connect_ports([443])
+        .allowed_tcp_bind_ports([8080, 8081])
+        .build();
+    config.validate().unwrap();
+    assert_eq!(config.allowed_tcp_connect_ports, [443]);
+    assert_eq!(config.allowed_tcp_bind_ports, [8080, 8081]);
+    assert_eq!(
+        SandboxConfig::default().allowed_tcp_connect_ports,
+        Vec::<u16>::new()
+    );
+}
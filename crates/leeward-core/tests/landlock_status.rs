@@ -0,0 +1,100 @@
+//! Landlock is built for the ABI the kernel has, not a fixed one, and says
+//! how much it enforced: rights newer ABIs add, such as truncation, work on
+//! read-write paths, and a kernel without Landlock is reported rather than
+//! failed on
+
+#![cfg(feature = "landlock")]
+
+mod common;
+
+use leeward_core::escape::KernelFeature;
+use leeward_core::isolation::{Enforcement, LandlockConfig, LandlockStatus, landlock::LATEST_ABI};
+
+/// Child exit codes
+const EXIT_OK: i32 = 0;
+const EXIT_APPLY_FAILED: i32 = 2;
+const EXIT_WRONG_STATUS: i32 = 3;
+const EXIT_TRUNCATE_DENIED: i32 = 4;
+const EXIT_OUTSIDE_ALLOWED: i32 = 5;
+
+/// Run `child` in a forked process and return its exit code
+fn in_child(child: impl FnOnce() -> i32) -> i32 {
+    // SAFETY: fork in a test; the child only touches the filesystem and exits
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = child();
+        // SAFETY: Exiting child process
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    // SAFETY: Waiting on our own child
+    unsafe { libc::waitpid(pid, &raw mut status, 0) };
+    assert!(libc::WIFEXITED(status), "child did not exit normally");
+    libc::WEXITSTATUS(status)
+}
+
+#[test]
+fn the_probe_agrees_with_the_kernel_feature_check() {
+    let status = LandlockStatus::probe();
+    assert_eq!(status.enforced(), KernelFeature::Landlock.available());
+    match status.abi {
+        None => assert_eq!(status.enforcement, Enforcement::NotEnforced),
+        Some(LATEST_ABI) => assert_eq!(status.enforcement, Enforcement::Full),
+        Some(abi) => {
+            assert!((1..LATEST_ABI).contains(&abi));
+            assert_eq!(status.enforcement, Enforcement::Partial);
+        }
+    }
+}
+
+#[test]
+fn read_write_paths_get_the_rights_of_the_probed_abi() {
+    let probed = LandlockStatus::probe();
+    let rw = common::Scratch::new("landlock-rw");
+    let outside = common::Scratch::new("landlock-outside");
+    let file = rw.join("data");
+    std::fs::write(&file, b"some contents").unwrap();
+
+    let code = in_child(|| {
+        let config = LandlockConfig::default().ro("/").rw(&*rw);
+        let Ok(status) = config.apply() else {
+            return EXIT_APPLY_FAILED;
+        };
+        if status.abi != probed.abi || status.enforced() != probed.enforced() {
+            return EXIT_WRONG_STATUS;
+        }
+        // Truncation is its own right from ABI 3 on, handled like writing
+        let truncated = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&file);
+        if truncated.is_err() {
+            return EXIT_TRUNCATE_DENIED;
+        }
+        if status.enforced() && std::fs::write(outside.join("data"), b"x").is_ok() {
+            return EXIT_OUTSIDE_ALLOWED;
+        }
+        EXIT_OK
+    });
+
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(std::fs::metadata(&file).unwrap().len(), 0);
+}
+
+#[test]
+fn without_landlock_only_deny_exec_fails() {
+    if KernelFeature::Landlock.available() {
+        eprintln!("skipping: this kernel has Landlock");
+        return;
+    }
+    let code = in_child(|| match LandlockConfig::default().ro("/").apply() {
+        Ok(status) if status.enforcement == Enforcement::NotEnforced && status.abi.is_none() => {
+            EXIT_OK
+        }
+        Ok(_) => EXIT_WRONG_STATUS,
+        Err(_) => EXIT_APPLY_FAILED,
+    });
+    assert_eq!(code, EXIT_OK);
+    assert!(LandlockConfig::default().deny_exec().apply().is_err());
+}
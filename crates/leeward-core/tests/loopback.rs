@@ -0,0 +1,84 @@
+//! A worker without network access still has a working loopback: `lo` is
+//! up in its network namespace, so connecting to a closed port on it is
+//! refused rather than unreachable
+
+#![cfg(feature = "protocol")]
+
+mod common;
+
+use leeward_core::config::NetworkConfig;
+use leeward_core::isolation::NetworkNamespaceSetup;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::net::{SocketAddr, TcpStream};
+
+/// Nothing listens here
+const CLOSED_PORT: u16 = 65535;
+
+/// Fail unless connecting to the closed port on `ip` fails with `expected`
+fn connect_fails_with(ip: &str, expected: i32) -> leeward_core::Result<()> {
+    let address: SocketAddr = format!("{ip}:{CLOSED_PORT}").parse().unwrap();
+    match TcpStream::connect(address) {
+        Err(e) if e.raw_os_error() == Some(expected) => Ok(()),
+        other => Err(LeewardError::Namespace(format!("{address}: {other:?}"))),
+    }
+}
+
+#[test]
+fn configuring_loopback_makes_localhost_reachable() {
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNET)
+            .map_err(|e| LeewardError::Namespace(e.to_string()))?;
+        connect_fails_with("127.0.0.1", libc::ENETUNREACH)?;
+
+        NetworkNamespaceSetup::configure_loopback()?;
+        connect_fails_with("127.0.0.1", libc::ECONNREFUSED)?;
+        connect_fails_with("[::1]", libc::ECONNREFUSED)?;
+        // Done twice, the addresses already there are no error
+        NetworkNamespaceSetup::configure_loopback()
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn loopback_is_on_by_default() {
+    assert_eq!(
+        SandboxConfig::default().network,
+        NetworkConfig::LoopbackOnly
+    );
+    let config = SandboxConfig::builder()
+        .network(NetworkConfig::None)
+        .build();
+    assert_eq!(config.network, NetworkConfig::None);
+}
+
+#[test]
+fn sandboxed_connect_is_refused() {
+    let mut worker = Worker::new(0, SandboxConfig::minimal_for_tests());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let code = format!(
+        "import errno, socket\n\
+         try:\n    socket.create_connection(('127.0.0.1', {CLOSED_PORT}))\n\
+         except OSError as e:\n    print(errno.errorcode[e.errno])\n"
+    );
+    let result = worker.execute(&code, &ExecuteOptions::default());
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let result = result.unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping, execution fails here: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(result.stdout_str().trim(), "ECONNREFUSED");
+}
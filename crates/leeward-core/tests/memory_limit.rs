@@ -0,0 +1,113 @@
+//! An interpreter that cannot start under the memory limit is a config
+//! error, not a user-code failure, and repeated ones trip the breaker
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::worker::{ExecuteOptions, StartupBreaker, run_python};
+use leeward_core::{ByteSize, LeewardError, SandboxConfig};
+
+/// Far below what any Python interpreter needs to start
+const TINY_LIMIT: ByteSize = ByteSize::mib(8);
+
+fn python_runs() -> bool {
+    let result = run_python(
+        "pass",
+        &SandboxConfig::default(),
+        &ExecuteOptions::default(),
+    )
+    .unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", String::from_utf8_lossy(&result.stderr));
+        return false;
+    }
+    true
+}
+
+#[test]
+fn tiny_limit_is_a_startup_error() {
+    if !python_runs() {
+        return;
+    }
+
+    let config = SandboxConfig::builder().memory_limit(TINY_LIMIT).build();
+    match run_python("print('unreachable')", &config, &ExecuteOptions::default()) {
+        Err(LeewardError::Config(message)) => {
+            assert!(
+                message.contains("interpreter startup"),
+                "unexpected message: {message}"
+            );
+            assert!(
+                message.contains("minimum observed"),
+                "no footprint in: {message}"
+            );
+        }
+        other => panic!("expected a startup config error, got {other:?}"),
+    }
+}
+
+#[test]
+fn user_code_oom_is_a_result() {
+    if !python_runs() {
+        return;
+    }
+
+    let config = SandboxConfig::builder()
+        .memory_limit(ByteSize::mib(512))
+        .build();
+    let result = run_python(
+        "x = bytearray(2 * 1024 ** 3)",
+        &config,
+        &ExecuteOptions::default(),
+    )
+    .expect("interpreter should start under 512 MB");
+
+    assert_ne!(result.exit_code, 0);
+    assert!(String::from_utf8_lossy(&result.stderr).contains("MemoryError"));
+}
+
+#[test]
+fn request_limit_overrides_config() {
+    if !python_runs() {
+        return;
+    }
+
+    let options = ExecuteOptions {
+        memory_limit: Some(TINY_LIMIT),
+        ..ExecuteOptions::default()
+    };
+    let outcome = run_python("pass", &SandboxConfig::default(), &options);
+    assert!(
+        matches!(outcome, Err(LeewardError::Config(_))),
+        "got {outcome:?}"
+    );
+}
+
+#[test]
+fn breaker_opens_after_consecutive_startup_failures() {
+    if !python_runs() {
+        return;
+    }
+
+    let tiny = SandboxConfig::builder().memory_limit(TINY_LIMIT).build();
+    let tiny_fingerprint = tiny.fingerprint();
+    let mut breaker = StartupBreaker::new(2);
+
+    for _ in 0..2 {
+        breaker.check(&tiny_fingerprint).unwrap();
+        let outcome = run_python("pass", &tiny, &ExecuteOptions::default());
+        breaker.record(&tiny_fingerprint, &outcome);
+    }
+
+    assert!(matches!(
+        breaker.check(&tiny_fingerprint),
+        Err(LeewardError::Config(_))
+    ));
+
+    // A fixed config is not affected
+    let fixed = SandboxConfig::default();
+    breaker.check(&fixed.fingerprint()).unwrap();
+    let outcome = run_python("pass", &fixed, &ExecuteOptions::default());
+    breaker.record(&fixed.fingerprint(), &outcome);
+    assert!(breaker.is_open(&tiny_fingerprint));
+    assert!(!breaker.is_open(&fixed.fingerprint()));
+}
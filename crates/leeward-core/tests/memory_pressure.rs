@@ -0,0 +1,199 @@
+//! Code nearing its memory limit is sent `SIGUSR1` once, if it handles the
+//! signal, and the limit holds whether or not it frees anything
+//!
+//! The fake cgroups are plain directories, which read the same. Real
+//! workers need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::isolation::CgroupHandle;
+use leeward_core::protocol::RequestBuilder;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{ByteSize, LeewardError, SandboxConfig};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// A directory laid out like a cgroup, removed on drop
+struct FakeCgroup(PathBuf);
+
+impl Drop for FakeCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FakeCgroup {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "leeward-cgroup-pressure-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cgroup = Self(dir);
+        cgroup.set_current(4096);
+        cgroup
+    }
+
+    fn set_current(&self, bytes: u64) {
+        std::fs::write(self.0.join("memory.current"), format!("{bytes}\n")).unwrap();
+    }
+}
+
+#[test]
+fn crossing_the_threshold_warns_once() {
+    let fake = FakeCgroup::new("cross");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let watch = cgroup
+        .watch_pressure(8192, move |current| tx.send(current).unwrap())
+        .unwrap();
+
+    assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    fake.set_current(9000);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(9000));
+    fake.set_current(20_000);
+    assert_eq!(watch.stop(), Some(9000));
+    // The warning was given once, and the watch is gone
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(50)),
+        Err(mpsc::RecvTimeoutError::Disconnected)
+    );
+}
+
+#[test]
+fn a_watch_stopped_under_the_threshold_never_warns() {
+    let fake = FakeCgroup::new("under");
+    let cgroup = CgroupHandle::open(&fake.0).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let watch = cgroup
+        .watch_pressure(8192, move |current| tx.send(current).unwrap())
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(watch.stop(), None);
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn pressure_percent_is_validated_and_requests_may_opt_out() {
+    assert_eq!(SandboxConfig::default().pressure_signal_percent, None);
+    for percent in [0, 100] {
+        let config = SandboxConfig::builder()
+            .pressure_signal_percent(percent)
+            .build();
+        assert!(
+            matches!(config.validate(), Err(LeewardError::Config(_))),
+            "{percent}"
+        );
+    }
+    assert!(
+        SandboxConfig::builder()
+            .pressure_signal_percent(80)
+            .build()
+            .validate()
+            .is_ok()
+    );
+
+    assert!(RequestBuilder::new("pass").build().unwrap().pressure_signal);
+    assert!(
+        !RequestBuilder::new("pass")
+            .pressure_signal(false)
+            .build()
+            .unwrap()
+            .pressure_signal
+    );
+}
+
+/// A worker with `config` accounted under a fresh cgroup root in
+/// `LEEWARD_TEST_CGROUP_ROOT`, or `None` if there is none or code cannot
+/// run here
+fn accounted_worker(name: &str, config: SandboxConfig) -> Option<(Worker, CgroupHandle)> {
+    let Ok(root) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return None;
+    };
+    let cgroup =
+        CgroupHandle::create(Path::new(&root), &format!("{name}-{}", std::process::id())).unwrap();
+    let mut worker = Worker::new(0, config);
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return None;
+    }
+    worker.set_cgroup_root(cgroup.path()).unwrap();
+    let probe = worker.execute("pass", &ExecuteOptions::default()).unwrap();
+    if probe.stderr_str().starts_with("Failed to execute") {
+        eprintln!("skipping, execution fails here: {}", probe.stderr_str());
+        worker.stop();
+        return None;
+    }
+    Some((worker, cgroup))
+}
+
+/// Grows a list in 4 MiB steps up to 512 MiB, or if `handled` until
+/// `SIGUSR1` arrives, when it drops the list and stops
+fn grow(handled: bool) -> String {
+    let shed = if handled {
+        "def shed(signum, frame):\n    global warned\n    warned = True\n    del chunks[:]\n\
+         signal.signal(signal.SIGUSR1, shed)\n"
+    } else {
+        ""
+    };
+    format!(
+        "import signal, time\nchunks = []\nwarned = False\n{shed}\
+         while not warned and len(chunks) < 128:\n    chunks.append(bytearray(4 << 20))\
+         \n    time.sleep(0.02)\nprint('done')"
+    )
+}
+
+fn limited() -> ExecuteOptions {
+    ExecuteOptions {
+        memory_limit: Some(ByteSize::mib(96)),
+        ..ExecuteOptions::default()
+    }
+}
+
+#[test]
+fn a_handler_freeing_memory_survives_the_limit() {
+    let config = SandboxConfig::builder().pressure_signal_percent(50).build();
+    let Some((mut worker, cgroup)) = accounted_worker("handled", config) else {
+        return;
+    };
+    let result = worker.execute(&grow(true), &limited()).unwrap();
+    assert!(result.pressure_signal_sent, "{result:?}");
+    assert!(
+        result
+            .pressure_signal_memory
+            .is_some_and(|bytes| bytes >= ByteSize::mib(48).bytes()),
+        "{result:?}"
+    );
+    assert!(result.is_success() && !result.oom_killed, "{result:?}");
+    assert_eq!(result.stdout_str().trim(), "done");
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
+
+#[test]
+fn without_a_handler_the_limit_kills_unwarned() {
+    let config = SandboxConfig::builder().pressure_signal_percent(50).build();
+    let Some((mut worker, cgroup)) = accounted_worker("unhandled", config) else {
+        return;
+    };
+    let result = worker.execute(&grow(false), &limited()).unwrap();
+    assert!(!result.pressure_signal_sent, "{result:?}");
+    assert_eq!(result.pressure_signal_memory, None);
+    // Killed by the cgroup, or failing a MemoryError under the address
+    // space limit first
+    assert!(!result.is_success(), "{result:?}");
+
+    // Opted out, a handler is not warned either
+    let options = ExecuteOptions {
+        suppress_pressure_signal: true,
+        ..limited()
+    };
+    let result = worker.execute(&grow(true), &options).unwrap();
+    assert!(!result.pressure_signal_sent, "{result:?}");
+    worker.stop();
+    let _ = std::fs::remove_dir(cgroup.path());
+}
@@ -0,0 +1,274 @@
+//! A minimal `/dev` holds the devices code expects and links to the
+//! standard streams, made with `mknod` or bound from the host's `/dev`
+
+mod common;
+
+use leeward_core::LeewardError;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::mounts::{DEV_LINKS, DEV_NODES, HIDEPID_INVISIBLE};
+use leeward_core::isolation::{MountConfig, NamespaceConfig};
+use leeward_core::units::ByteSize;
+use nix::sched::CloneFlags;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+fn expect(holds: bool, what: &str) -> leeward_core::Result<()> {
+    if holds {
+        Ok(())
+    } else {
+        Err(LeewardError::Mount(what.to_owned()))
+    }
+}
+
+/// Check the devices and links in `dev` work
+fn check_dev(dev: &Path) -> leeward_core::Result<()> {
+    for (name, _, _) in DEV_NODES {
+        let kind = std::fs::metadata(dev.join(name))?.file_type();
+        expect(kind.is_char_device(), name)?;
+    }
+    for (name, target) in DEV_LINKS {
+        expect(
+            std::fs::read_link(dev.join(name))? == Path::new(target),
+            name,
+        )?;
+    }
+
+    let mut random = [0u8; 16];
+    std::fs::File::open(dev.join("urandom"))?.read_exact(&mut random)?;
+    expect(random != [0; 16], "urandom read zeros")?;
+    std::fs::write(dev.join("null"), b"discarded")?;
+    expect(
+        std::fs::read(dev.join("null"))?.is_empty(),
+        "null had contents",
+    )?;
+    let full = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dev.join("full"))?
+        .write_all(b"x");
+    expect(
+        full.is_err_and(|e| e.raw_os_error() == Some(libc::ENOSPC)),
+        "full took a write",
+    )
+}
+
+/// Check the mount at `dev` is `nosuid` and `noexec` but not `nodev`, which
+/// would make its devices unusable
+fn check_dev_mount(dev: &Path) -> leeward_core::Result<()> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    let options = mountinfo
+        .lines()
+        .map(|line| line.split(' ').collect::<Vec<_>>())
+        .rfind(|fields| fields.len() > 5 && Path::new(fields[4]) == dev)
+        .map(|fields| fields[5].split(',').map(str::to_owned).collect::<Vec<_>>())
+        .ok_or_else(|| LeewardError::Mount(format!("{} is not mounted", dev.display())))?;
+    for (option, wanted) in [("nosuid", true), ("noexec", true), ("nodev", false)] {
+        expect(
+            options.iter().any(|o| o == option) == wanted,
+            &format!("{option} in {options:?}"),
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn devices_and_stream_links_work() {
+    let dir = common::Scratch::new("dev-root");
+    let dev = dir.join("dev");
+    let mounts = MountConfig::minimal_dev(&dev).allow_pts(true);
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        mounts.apply()?;
+        check_dev(&dev)?;
+        check_dev_mount(&dev)?;
+        expect(
+            std::fs::read_link(dev.join("ptmx"))? == Path::new("pts/ptmx"),
+            "ptmx",
+        )?;
+        // A pseudo-terminal pair opens on the new devpts instance
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dev.join("ptmx"))?;
+        Ok(())
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+    // Nothing was made on the host
+    assert_eq!(std::fs::read_dir(&dev).unwrap().count(), 0);
+}
+
+#[test]
+fn in_a_user_namespace_the_host_devices_are_bound() {
+    let dir = common::Scratch::new("dev-userns");
+    let dev = dir.join("dev");
+    let mounts = MountConfig::minimal_dev(&dev);
+    let namespaces = NamespaceConfig {
+        pid: false,
+        net: false,
+        ipc: false,
+        uts: false,
+        ..NamespaceConfig::default()
+    };
+    let pid = clone_worker(0, || {
+        if namespaces.enter().is_err() {
+            eprintln!("skipping: no user namespaces here");
+            return Ok(());
+        }
+        mounts.apply()?;
+        check_dev(&dev)?;
+        expect(!dev.join("pts").exists(), "devpts without allow_pts")
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+/// Mount a tmpfs at `root` and pivot into it with `proc` and a `/dev` of
+/// `null` and `urandom` only, then check them from inside
+fn pivot_with_proc_and_dev(root: &Path, proc: bool) -> leeward_core::Result<()> {
+    MountConfig::default()
+        .tmpfs(root, ByteSize::mib(1))
+        .apply()?;
+    MountConfig {
+        new_root: root.to_path_buf(),
+        dev: Some((root.join("dev"), vec!["null".into(), "urandom".into()])),
+        proc: proc.then(|| (root.join("proc"), HIDEPID_INVISIBLE)),
+        ..MountConfig::default()
+    }
+    .apply()?;
+
+    let dev = Path::new("/dev");
+    expect(
+        std::fs::metadata(dev.join("null"))?
+            .file_type()
+            .is_char_device(),
+        "null",
+    )?;
+    std::fs::write(dev.join("null"), b"discarded")?;
+    std::fs::File::open(dev.join("urandom"))?.read_exact(&mut [0u8; 4])?;
+    expect(!dev.join("zero").exists(), "zero was not asked for")?;
+    expect(
+        std::fs::read_link(dev.join("stdout"))? == Path::new("/proc/self/fd/1"),
+        "stdout",
+    )?;
+    expect(Path::new("/proc/self/status").exists() == proc, "proc")
+}
+
+#[test]
+fn a_new_root_gets_proc_and_the_named_devices() {
+    let root = common::Scratch::new("dev-new-root");
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        pivot_with_proc_and_dev(&root, true)
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+    assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+}
+
+#[test]
+fn a_new_root_in_a_user_namespace_gets_the_host_devices() {
+    let root = common::Scratch::new("dev-new-root-userns");
+    let namespaces = NamespaceConfig {
+        pid: false,
+        net: false,
+        ipc: false,
+        uts: false,
+        ..NamespaceConfig::default()
+    };
+    let pid = clone_worker(0, || {
+        if namespaces.enter().is_err() {
+            eprintln!("skipping: no user namespaces here");
+            return Ok(());
+        }
+        // proc needs a pid namespace the user namespace owns
+        pivot_with_proc_and_dev(&root, false)
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn unknown_device_names_are_rejected() {
+    let dir = common::Scratch::new("dev-unknown");
+    let dev = dir.join("dev");
+    let mounts = MountConfig {
+        dev: Some((dev, vec!["null".into(), "sda".into()])),
+        ..MountConfig::default()
+    };
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        match mounts.apply() {
+            Err(e) if e.to_string().contains("sda") => Ok(()),
+            other => Err(LeewardError::Mount(format!("unexpected: {other:?}"))),
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn sandboxed_code_reads_urandom() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::worker::{ExecuteOptions, Worker};
+
+    let mut worker = Worker::new(0, SandboxConfig::minimal_for_tests());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let result = worker.execute(
+        "import os; print(os.urandom(4).hex())",
+        &ExecuteOptions::default(),
+    );
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let result = result.unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return;
+    }
+    let hex = result.stdout_str();
+    let hex = hex.trim();
+    assert_eq!(hex.len(), 8, "{hex:?}");
+    assert!(hex.bytes().all(|b| b.is_ascii_hexdigit()), "{hex:?}");
+}
+
+#[test]
+fn an_empty_device_list_makes_a_dev_without_devices() {
+    let root = common::Scratch::new("dev-no-devices");
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        MountConfig::default()
+            .tmpfs(&*root, ByteSize::mib(1))
+            .apply()?;
+        MountConfig {
+            new_root: root.to_path_buf(),
+            dev: Some((root.join("dev"), Vec::new())),
+            ..MountConfig::default()
+        }
+        .apply()?;
+        let dev = Path::new("/dev");
+        expect(
+            dev.join("stdout").is_symlink(),
+            "the stream links are missing",
+        )?;
+        for (name, _, _) in DEV_NODES {
+            expect(!dev.join(name).exists(), name)?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
@@ -0,0 +1,227 @@
+//! Mounts are made private before anything is mounted, so none travel
+//! between the sandbox and the host, unless another [`MountPropagation`]
+//! is asked for, and a plain directory can be the new root, bound onto
+//! itself for `pivot_root`, which mounts shared with the host never are
+
+mod common;
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::mounts::HIDEPID_INVISIBLE;
+use leeward_core::isolation::{MountConfig, MountPropagation};
+use leeward_core::{ByteSize, LeewardError};
+use nix::mount::{MsFlags, mount};
+use nix::sched::CloneFlags;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+fn expect(holds: bool, what: &str) -> leeward_core::Result<()> {
+    if holds {
+        Ok(())
+    } else {
+        Err(LeewardError::Mount(what.to_owned()))
+    }
+}
+
+fn unshare_mounts() -> leeward_core::Result<()> {
+    nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+        .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))
+}
+
+#[test]
+fn a_plain_directory_becomes_the_root() {
+    let dir = common::Scratch::new("propagation-plain");
+    let (root, data) = (dir.join("root"), dir.join("data"));
+    std::fs::create_dir_all(&data).unwrap();
+    std::fs::create_dir_all(root.join("data")).unwrap();
+    std::fs::write(data.join("file"), b"bound").unwrap();
+    let mounts = MountConfig {
+        new_root: root.clone(),
+        proc: Some((root.join("proc"), HIDEPID_INVISIBLE)),
+        ..MountConfig::default()
+    }
+    .ro_bind(&data, root.join("data"));
+
+    let pid = clone_worker(0, || {
+        unshare_mounts()?;
+        mounts.apply()?;
+        expect(
+            std::fs::read("/data/file")? == b"bound",
+            "the bind is missing",
+        )?;
+        expect(!Path::new("/put_old").exists(), "the old root is left")?;
+        // Nothing the sandbox sees propagates anywhere
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        expect(!mountinfo.contains("shared:"), &mountinfo)
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+    // The bind was made in the sandbox only
+    assert!(!root.join("data").join("file").exists());
+}
+
+#[test]
+fn a_tmpfs_mounted_in_the_sandbox_is_not_seen_outside() {
+    let dir = common::Scratch::new("propagation-outside");
+    let mounts = MountConfig::default().tmpfs(&*dir, ByteSize::mib(1));
+    let pid = clone_worker(0, || {
+        unshare_mounts()?;
+        mounts.apply()?;
+        std::fs::write(dir.join("in-sandbox"), b"x")?;
+        Ok(())
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+    assert!(!dir.join("in-sandbox").exists());
+}
+
+/// Exit status of a stand-in host that found out, with bit 0 set if mounts
+/// came in and bit 1 if they went out
+const FINDINGS: i32 = 16;
+
+/// Whether mounts travel (into, out of) a sandbox with `propagation` whose
+/// mount namespace was copied from one where they are shared, as systemd
+/// leaves them
+fn traffic(name: &str, propagation: MountPropagation) -> (bool, bool) {
+    let dir = common::Scratch::new(&format!("propagation-{name}"));
+    let (inward, outward) = (dir.join("in"), dir.join("out"));
+    std::fs::create_dir_all(&inward).unwrap();
+    std::fs::create_dir_all(&outward).unwrap();
+    let mounts = MountConfig::default()
+        .propagation(propagation)
+        .tmpfs(&outward, ByteSize::mib(1));
+    let (mut outer_end, mut sandbox_end) = UnixStream::pair().unwrap();
+
+    let pid = clone_worker(0, || {
+        // A namespace standing in for the host, private to the real one so
+        // nothing here reaches it, with a shared mount of its own
+        unshare_mounts()?;
+        let mounted = |result: nix::Result<()>| {
+            result.map_err(|e| LeewardError::Mount(format!("mount: {e}")))
+        };
+        mounted(mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        ))?;
+        mounted(mount(
+            Some(&*dir),
+            &*dir,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        ))?;
+        mounted(mount(
+            None::<&str>,
+            &*dir,
+            None::<&str>,
+            MsFlags::MS_SHARED,
+            None::<&str>,
+        ))?;
+
+        let sandbox = clone_worker(0, || {
+            unshare_mounts()?;
+            mounts.apply()?;
+            std::fs::write(outward.join("marker"), b"x")?;
+            sandbox_end.write_all(b"m")?;
+            // The host mounts while the sandbox waits
+            sandbox_end.read_exact(&mut [0])?;
+            let came_in = inward.join("marker").exists();
+            sandbox_end.write_all(&[u8::from(came_in)])?;
+            Ok(())
+        })?;
+
+        let mut byte = [0];
+        outer_end.read_exact(&mut byte)?;
+        let went_out = outward.join("marker").exists();
+        mounted(mount(
+            Some("tmpfs"),
+            &inward,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            None::<&str>,
+        ))?;
+        std::fs::write(inward.join("marker"), b"x")?;
+        outer_end.write_all(b"h")?;
+        outer_end.read_exact(&mut byte)?;
+        expect(common::succeeded(sandbox), "the sandbox failed")?;
+        // Report both in the exit status, apart from any a failure gets
+        let code = FINDINGS | i32::from(byte[0]) | i32::from(went_out) << 1;
+        // SAFETY: Exiting the stand-in host with its findings
+        unsafe { libc::_exit(code) }
+    })
+    .unwrap();
+
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status));
+    let code = libc::WEXITSTATUS(status);
+    assert_eq!(code & !3, FINDINGS, "the stand-in host failed with {code}");
+    (code & 1 != 0, code & 2 != 0)
+}
+
+#[test]
+fn private_sandboxes_neither_see_nor_leak_mounts() {
+    assert_eq!(
+        MountConfig::default().propagation,
+        MountPropagation::Private
+    );
+    assert_eq!(
+        traffic("private", MountPropagation::Private),
+        (false, false)
+    );
+}
+
+#[test]
+fn slave_sandboxes_see_mounts_but_do_not_leak_them() {
+    assert_eq!(traffic("slave", MountPropagation::Slave), (true, false));
+}
+
+#[test]
+fn shared_and_unchanged_sandboxes_pass_mounts_both_ways() {
+    assert_eq!(traffic("shared", MountPropagation::Shared), (true, true));
+    assert_eq!(
+        traffic("unchanged", MountPropagation::Unchanged),
+        (true, true)
+    );
+}
+
+#[test]
+fn a_new_root_refuses_mounts_shared_with_the_host() {
+    let dir = common::Scratch::new("propagation-shared-root");
+    let root = dir.join("root");
+    for propagation in [MountPropagation::Shared, MountPropagation::Unchanged] {
+        let mounts = MountConfig {
+            new_root: root.clone(),
+            ..MountConfig::default()
+        }
+        .propagation(propagation);
+        let pid = clone_worker(0, || {
+            unshare_mounts()?;
+            match mounts.apply() {
+                Err(LeewardError::Mount(message)) if message.contains("private or slave") => Ok(()),
+                other => Err(LeewardError::Mount(format!("unexpected: {other:?}"))),
+            }
+        })
+        .unwrap();
+        assert!(common::succeeded(pid), "{propagation:?}");
+    }
+    // Refused before anything was made
+    assert!(!root.exists());
+
+    let mounts = MountConfig {
+        new_root: root,
+        ..MountConfig::default()
+    }
+    .propagation(MountPropagation::Slave);
+    let pid = clone_worker(0, || {
+        unshare_mounts()?;
+        mounts.apply()?;
+        expect(!Path::new("/put_old").exists(), "the old root is left")
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
@@ -0,0 +1,146 @@
+//! Every outcome, error and exit status maps to one documented code
+
+use leeward_core::isolation::fatal::{Stage, WorkerDeath};
+use leeward_core::{ExecutionResult, LeewardError, OutcomeCode};
+
+#[test]
+fn codes_round_trip() {
+    for code in 0..=u8::MAX {
+        let Some(outcome) = OutcomeCode::from_code(code) else {
+            assert!(
+                (126..200).contains(&code) || code > 205,
+                "{code} should be assigned"
+            );
+            continue;
+        };
+        assert_eq!(outcome.code(), code);
+        assert_eq!(outcome.is_infrastructure(), code >= 200, "{outcome:?}");
+    }
+
+    assert_eq!(OutcomeCode::from_code(124), Some(OutcomeCode::Timeout));
+    assert_eq!(OutcomeCode::from_code(125), Some(OutcomeCode::Cancelled));
+    assert_eq!(OutcomeCode::from_code(123), Some(OutcomeCode::Exited(123)));
+
+    let reserved: Vec<u8> = OutcomeCode::RESERVED.iter().map(|o| o.code()).collect();
+    assert_eq!(
+        reserved,
+        [124, 125, 126, 127, 137, 200, 201, 202, 203, 204, 205, 255]
+    );
+}
+
+#[test]
+fn exit_statuses() {
+    for status in 0..=125 {
+        assert_eq!(
+            i32::from(OutcomeCode::from_exit_status(status).code()),
+            status
+        );
+    }
+    assert_eq!(
+        OutcomeCode::from_exit_status(126),
+        OutcomeCode::SandboxSetup
+    );
+    assert_eq!(
+        OutcomeCode::from_exit_status(127),
+        OutcomeCode::InterpreterUnavailable
+    );
+    for status in [-1, 128, 137, 255, 256] {
+        assert_eq!(
+            OutcomeCode::from_exit_status(status),
+            OutcomeCode::Killed,
+            "{status}"
+        );
+    }
+
+    let result = |exit_code, timed_out, oom_killed| ExecutionResult {
+        exit_code,
+        timed_out,
+        oom_killed,
+        ..ExecutionResult::default()
+    };
+    assert_eq!(result(3, false, false).outcome(), OutcomeCode::Exited(3));
+    assert_eq!(result(-1, true, false).outcome(), OutcomeCode::Timeout);
+    assert_eq!(result(-1, false, true).outcome(), OutcomeCode::Killed);
+}
+
+#[test]
+fn errors() {
+    let io = std::io::Error::other("broken");
+    let died = |stage| {
+        LeewardError::WorkerDied(WorkerDeath {
+            stage,
+            errno: 0,
+            message: String::new(),
+        })
+    };
+    let cases = [
+        (
+            LeewardError::Namespace(String::new()),
+            OutcomeCode::SandboxSetup,
+        ),
+        #[cfg(feature = "seccomp")]
+        (
+            LeewardError::Seccomp(String::new()),
+            OutcomeCode::SandboxSetup,
+        ),
+        #[cfg(feature = "landlock")]
+        (
+            LeewardError::Landlock(String::new()),
+            OutcomeCode::SandboxSetup,
+        ),
+        (
+            LeewardError::Mount(String::new()),
+            OutcomeCode::SandboxSetup,
+        ),
+        (LeewardError::Execution(String::new()), OutcomeCode::Daemon),
+        (LeewardError::Timeout(30), OutcomeCode::Timeout),
+        (LeewardError::MemoryLimitExceeded(1), OutcomeCode::Killed),
+        (LeewardError::Io(io), OutcomeCode::Daemon),
+        (LeewardError::Nix(nix::Error::EPERM), OutcomeCode::Daemon),
+        (
+            LeewardError::Config(String::new()),
+            OutcomeCode::SandboxSetup,
+        ),
+        (
+            LeewardError::InvalidRequest(String::new()),
+            OutcomeCode::InvalidRequest,
+        ),
+        (died(Stage::Seccomp), OutcomeCode::SandboxSetup),
+        (died(Stage::Exec), OutcomeCode::Daemon),
+    ];
+
+    for (error, outcome) in &cases {
+        assert_eq!(OutcomeCode::from(error), *outcome, "{error}");
+    }
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn execute_responses() {
+    use leeward_core::protocol::{self, ExecuteResponse};
+
+    let ok = ExecuteResponse::ok(ExecutionResult {
+        exit_code: 7,
+        ..ExecutionResult::default()
+    });
+    assert_eq!(ok.outcome(), OutcomeCode::Exited(7));
+
+    let failed = ExecuteResponse::from(&LeewardError::InvalidRequest("too big".into()));
+    assert_eq!(failed.outcome(), OutcomeCode::InvalidRequest);
+
+    let decoded: ExecuteResponse = protocol::decode(&protocol::encode(&failed).unwrap()).unwrap();
+    assert_eq!(decoded.error_code, Some(OutcomeCode::InvalidRequest));
+    let json = protocol::encode_json(&failed).unwrap();
+    assert!(
+        String::from_utf8(json)
+            .unwrap()
+            .contains(r#""error_code":202"#)
+    );
+
+    // Responses from before the field existed
+    let old: ExecuteResponse =
+        protocol::decode_json(br#"{"success": false, "result": null, "error": "boom"}"#).unwrap();
+    assert_eq!(old.outcome(), OutcomeCode::Daemon);
+    let unassigned = br#"{"success": false, "result": null, "error": null, "error_code": 150}"#;
+    assert!(protocol::decode_json::<ExecuteResponse>(unassigned).is_err());
+}
@@ -0,0 +1,141 @@
+//! An overlay takes writes into its upper directory, leaving the lower one,
+//! which other workers share, as it was
+
+mod common;
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::mounts::{OVERLAY_SCRATCH, overlay_options};
+use leeward_core::isolation::{MountConfig, OverlayMount};
+use leeward_core::{ByteSize, LeewardError};
+use nix::sched::CloneFlags;
+use std::path::Path;
+
+/// Whether this kernel has overlayfs
+fn have_overlayfs() -> bool {
+    let listed = std::fs::read_to_string("/proc/filesystems")
+        .is_ok_and(|filesystems| filesystems.lines().any(|line| line.ends_with("\toverlay")));
+    if !listed {
+        eprintln!("skipping: no overlayfs here");
+    }
+    listed
+}
+
+/// Run `mounts` in a new mount namespace, then `check` there
+fn in_mount_namespace(mounts: &MountConfig, check: impl Fn() -> leeward_core::Result<()>) -> bool {
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        mounts.apply()?;
+        check()
+    })
+    .unwrap();
+    common::succeeded(pid)
+}
+
+fn expect(holds: bool, what: &str) -> leeward_core::Result<()> {
+    if holds {
+        Ok(())
+    } else {
+        Err(LeewardError::Mount(what.to_owned()))
+    }
+}
+
+#[test]
+fn writes_through_the_merged_directory_land_in_the_upper_one() {
+    if !have_overlayfs() {
+        return;
+    }
+    let dir = common::Scratch::new("overlay-merged");
+    let (lower, merged, layers) = (dir.join("lower"), dir.join("merged"), dir.join("layers"));
+    std::fs::create_dir_all(&lower).unwrap();
+    std::fs::write(lower.join("shared.txt"), b"from lower").unwrap();
+    let (upper, work) = (layers.join("upper"), layers.join("work"));
+    let mounts = MountConfig::default()
+        .tmpfs(&layers, ByteSize::mib(4))
+        .overlay(&lower, &upper, &work, &merged);
+
+    let lower_in_child = lower.clone();
+    assert!(in_mount_namespace(&mounts, || {
+        expect(
+            std::fs::read(merged.join("shared.txt"))? == b"from lower",
+            "lower not visible",
+        )?;
+        std::fs::write(merged.join("written.txt"), b"through merged")?;
+        expect(
+            std::fs::read(upper.join("written.txt"))? == b"through merged",
+            "write not in upper",
+        )?;
+        expect(
+            !lower_in_child.join("written.txt").exists(),
+            "write reached lower",
+        )?;
+        std::fs::write(upper.join("direct.txt"), b"into upper")?;
+        expect(
+            std::fs::read(merged.join("direct.txt"))? == b"into upper",
+            "upper not visible",
+        )?;
+        expect(
+            !lower_in_child.join("direct.txt").exists(),
+            "upper reached lower",
+        )
+    }));
+    assert_eq!(std::fs::read_dir(&lower).unwrap().count(), 1);
+}
+
+#[test]
+fn the_python_overlay_makes_the_tree_writable_where_it_is() {
+    let root = Path::new("/usr");
+    let mounts = MountConfig::python_overlay(root, ByteSize::mib(16));
+    let scratch_dir = Path::new(OVERLAY_SCRATCH);
+    assert_eq!(
+        mounts.tmpfs,
+        [(scratch_dir.to_path_buf(), ByteSize::mib(16))]
+    );
+    assert_eq!(
+        mounts.overlays,
+        [OverlayMount {
+            lower: root.to_path_buf(),
+            upper: scratch_dir.join("upper"),
+            work: scratch_dir.join("work"),
+            merged: root.to_path_buf(),
+        }]
+    );
+
+    if !have_overlayfs() {
+        return;
+    }
+    let python_root = common::Scratch::new("overlay-python");
+    std::fs::create_dir_all(python_root.join("lib")).unwrap();
+    std::fs::write(python_root.join("lib/os.py"), b"# stdlib").unwrap();
+    let mounts = MountConfig::python_overlay(&python_root, ByteSize::mib(4));
+    assert!(in_mount_namespace(&mounts, || {
+        std::fs::write(python_root.join("lib/os.cpython.pyc"), b"cache")?;
+        expect(
+            std::fs::read(python_root.join("lib/os.py"))? == b"# stdlib",
+            "stdlib hidden",
+        )
+    }));
+    assert!(!python_root.join("lib/os.cpython.pyc").exists());
+}
+
+#[test]
+fn separators_in_overlay_paths_are_refused() {
+    let overlay = OverlayMount {
+        lower: "/srv/lower".into(),
+        upper: "/tmp/upper".into(),
+        work: "/tmp/work".into(),
+        merged: "/srv/merged".into(),
+    };
+    assert_eq!(
+        overlay_options(&overlay).unwrap(),
+        "lowerdir=/srv/lower,upperdir=/tmp/upper,workdir=/tmp/work"
+    );
+    let overlay = OverlayMount {
+        lower: "/srv/a,b".into(),
+        ..overlay
+    };
+    assert!(matches!(
+        overlay_options(&overlay),
+        Err(LeewardError::Mount(_))
+    ));
+}
@@ -0,0 +1,310 @@
+//! Every user-supplied path is resolved by `config::paths`, so tricky
+//! inputs behave the same under each policy wherever they come from
+
+use leeward_core::config::paths::{CanonicalPath, PathErrorKind, PathPolicy};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Directory of tricky entries, removed on drop
+struct Fixture {
+    root: PathBuf,
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("leeward-paths-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("dir/sub")).unwrap();
+        let root = root.canonicalize().unwrap();
+        std::fs::write(root.join("dir/file"), b"").unwrap();
+        std::fs::create_dir(root.join(OsString::from_vec(b"caf\xff".to_vec()))).unwrap();
+
+        symlink("dir", root.join("link_dir")).unwrap();
+        symlink("dir/sub", root.join("deep")).unwrap();
+        symlink("dir/file", root.join("file_link")).unwrap();
+        symlink("nowhere", root.join("dangling")).unwrap();
+        symlink("dangling", root.join("to_dangling")).unwrap();
+        symlink("/", root.join("escape")).unwrap();
+        symlink("loop_b", root.join("loop_a")).unwrap();
+        symlink("loop_a", root.join("loop_b")).unwrap();
+        Self { root }
+    }
+
+    /// `raw` with `{root}` replaced by the fixture directory
+    fn path(&self, raw: &[u8]) -> PathBuf {
+        let root = self.root.as_os_str().as_encoded_bytes();
+        let mut bytes = Vec::new();
+        let mut rest = raw;
+        while let Some(at) = rest.windows(6).position(|window| window == b"{root}") {
+            bytes.extend_from_slice(&rest[..at]);
+            bytes.extend_from_slice(root);
+            rest = &rest[at + 6..];
+        }
+        bytes.extend_from_slice(rest);
+        PathBuf::from(OsString::from_vec(bytes))
+    }
+
+    /// Resolve each case's path (with `{root}` replaced) under its policy
+    fn check(&self, cases: Vec<(&[u8], PathPolicy, Expect)>) {
+        for (raw, policy, expect) in cases {
+            let raw = self.path(raw);
+            let resolved = CanonicalPath::resolve(&raw, &policy);
+            match (expect, resolved) {
+                (Expect::Resolves(path, exists), Ok(resolved)) => {
+                    assert_eq!(resolved.as_path(), self.path(path), "{}", raw.display());
+                    assert_eq!(resolved.exists(), exists, "{}", raw.display());
+                    assert_eq!(resolved.raw(), raw);
+                }
+                (Expect::Fails(kind), Err(e)) => {
+                    assert!(kind(&e.kind), "{}: unexpected {e}", raw.display());
+                    assert_eq!(e.raw, raw);
+                }
+                (Expect::Resolves(..), Err(e)) => panic!("{}: {e}", raw.display()),
+                (Expect::Fails(_), Ok(resolved)) => {
+                    panic!("{}: resolved to {:?}", raw.display(), resolved)
+                }
+            }
+        }
+    }
+}
+
+/// Fails with this kind of [`PathErrorKind`]
+macro_rules! fails {
+    ($kind:ident) => {
+        Expect::Fails(|kind| matches!(kind, PathErrorKind::$kind { .. }))
+    };
+}
+
+enum Expect {
+    /// Resolves to this path (with `{root}` replaced), found or not
+    Resolves(&'static [u8], bool),
+    Fails(fn(&PathErrorKind) -> bool),
+}
+
+#[test]
+fn symlinks_are_followed_or_refused() {
+    let fixture = Fixture::new("symlinks");
+    let host = PathPolicy::host;
+    fixture.check(vec![
+        // Plain paths and symlinks that lead somewhere real
+        (
+            b"{root}/dir/file",
+            host(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/link_dir/file",
+            host(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/./dir//file",
+            host(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/link_dir/file",
+            host().no_symlinks(),
+            fails!(Symlink),
+        ),
+        (
+            b"{root}/dir/file",
+            host().no_symlinks(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        // Dangling symlinks fail even where a missing path would not
+        (b"{root}/dangling", host(), fails!(Dangling)),
+        (b"{root}/dangling/more", host(), fails!(Dangling)),
+        (b"{root}/to_dangling", host(), fails!(Dangling)),
+        (
+            b"{root}/missing/more",
+            host(),
+            Expect::Resolves(b"{root}/missing/more", false),
+        ),
+        (
+            b"{root}/link_dir/missing",
+            host(),
+            Expect::Resolves(b"{root}/dir/missing", false),
+        ),
+        (b"{root}/missing", host().existing(), fails!(Missing)),
+        (b"{root}/missing/../dir", host(), fails!(Missing)),
+        (b"{root}/loop_a", host(), fails!(TooManySymlinks)),
+    ]);
+}
+
+#[test]
+fn parent_dirs_roots_and_kinds() {
+    let fixture = Fixture::new("parents");
+    let root = &fixture.root;
+    let host = PathPolicy::host;
+    fixture.check(vec![
+        // `..` mid-path goes up from where a symlink led, as the kernel does
+        (
+            b"{root}/dir/../dir/file",
+            host(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/deep/../file",
+            host(),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/dir/../dir/file",
+            PathPolicy::sandbox(),
+            fails!(ParentDir),
+        ),
+        (b"{root}/dir/file/more", host(), fails!(NotADirectory)),
+        // Allowed roots are checked against where the path leads
+        (
+            b"{root}/escape/etc",
+            host().within(root),
+            fails!(OutsideRoots),
+        ),
+        (b"{root}/escape", host(), Expect::Resolves(b"/", true)),
+        (
+            b"{root}/file_link",
+            host().within(root.join("dir")),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (
+            b"{root}/deep/../../escape",
+            host().within(root),
+            fails!(OutsideRoots),
+        ),
+        // Kinds are only checked for paths that exist
+        (b"{root}/dir", host().file(), fails!(WrongKind)),
+        (b"{root}/file_link", host().dir(), fails!(WrongKind)),
+        (
+            b"{root}/missing",
+            host().dir(),
+            Expect::Resolves(b"{root}/missing", false),
+        ),
+    ]);
+}
+
+#[test]
+fn anchors_and_odd_bytes() {
+    let fixture = Fixture::new("anchors");
+    let root = &fixture.root;
+    let host = PathPolicy::host;
+    fixture.check(vec![
+        // Non-UTF-8 bytes are just bytes
+        (
+            b"{root}/caf\xff",
+            host().dir().within(root),
+            Expect::Resolves(b"{root}/caf\xff", true),
+        ),
+        (
+            b"{root}/caf\xff/new",
+            host(),
+            Expect::Resolves(b"{root}/caf\xff/new", false),
+        ),
+        (
+            b"/srv/caf\xff",
+            PathPolicy::sandbox(),
+            Expect::Resolves(b"/srv/caf\xff", false),
+        ),
+        // Where a path may be anchored
+        (b"", host(), fails!(Empty)),
+        (b"{root}/dir\0file", host(), fails!(Nul)),
+        (b"dir/file", host(), fails!(NotAbsolute)),
+        (
+            b"link_dir/file",
+            host().relative_to(root),
+            Expect::Resolves(b"{root}/dir/file", true),
+        ),
+        (b"home/sandbox", PathPolicy::sandbox(), fails!(NotAbsolute)),
+        (
+            b"/home/./sandbox",
+            PathPolicy::sandbox(),
+            Expect::Resolves(b"/home/sandbox", false),
+        ),
+        (
+            b"data/./in.csv",
+            PathPolicy::relative_name(),
+            Expect::Resolves(b"data/in.csv", false),
+        ),
+        (
+            b"/etc/passwd",
+            PathPolicy::relative_name(),
+            fails!(NotRelative),
+        ),
+        (
+            b"data/../../etc",
+            PathPolicy::relative_name(),
+            fails!(ParentDir),
+        ),
+        (
+            b"data/in.csv",
+            PathPolicy::relative_name().within("data"),
+            Expect::Resolves(b"data/in.csv", false),
+        ),
+        (
+            b"in.csv",
+            PathPolicy::relative_name().within("data"),
+            fails!(OutsideRoots),
+        ),
+    ]);
+}
+
+#[test]
+fn errors_name_the_component_and_where_it_led() {
+    let fixture = Fixture::new("errors");
+    let raw = fixture.root.join("dangling/more");
+
+    let e = CanonicalPath::resolve(&raw, &PathPolicy::host()).unwrap_err();
+    assert_eq!(
+        e.kind,
+        PathErrorKind::Dangling {
+            link: fixture.root.join("dangling"),
+            target: PathBuf::from("nowhere"),
+        }
+    );
+    let message = e.to_string();
+    assert!(message.contains(&*raw.to_string_lossy()), "{message}");
+    assert!(message.contains("dangling symlink to nowhere"), "{message}");
+}
+
+#[test]
+fn config_validation_resolves_binds_and_workdir() {
+    let fixture = Fixture::new("config");
+    let validate = |configure: &dyn Fn(&mut SandboxConfig)| {
+        let mut config = SandboxConfig::default();
+        configure(&mut config);
+        config.validate()
+    };
+
+    // Binds that do not exist are skipped later, not refused
+    validate(&|config| config.ro_binds.push(fixture.root.join("missing"))).unwrap();
+    validate(&|config| config.rw_binds.push(fixture.root.join("link_dir"))).unwrap();
+
+    for bad in [
+        PathBuf::from("relative/bind"),
+        fixture.root.join("dangling"),
+        fixture.root.join("dir/../dir"),
+    ] {
+        let outcome = validate(&|config| config.ro_binds.push(bad.clone()));
+        match outcome {
+            Err(LeewardError::Config(message)) => {
+                assert!(message.starts_with("ro_binds: "), "{message}");
+            }
+            other => panic!("{}: {other:?}", bad.display()),
+        }
+    }
+
+    let outcome = validate(&|config| config.workdir = Path::new("/home/sandbox/../etc").into());
+    assert!(
+        matches!(outcome, Err(LeewardError::Config(message)) if message.starts_with("workdir: "))
+    );
+}
@@ -0,0 +1,172 @@
+//! A policy change names each leaf field that moved, keeps secrets to set
+//! or unset, and tells configured values from built-in ones
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::policy::{
+    FieldChange, PolicyActor, PolicyChange, Provenance, REDACTED, SECRET_FIELDS, diff,
+};
+use std::time::Duration;
+
+fn change(
+    field: &str,
+    before: Option<&str>,
+    after: Option<&str>,
+    provenance: Provenance,
+) -> FieldChange {
+    FieldChange {
+        field: field.into(),
+        before: before.map(Into::into),
+        after: after.map(Into::into),
+        provenance,
+    }
+}
+
+#[test]
+fn identical_configs_have_no_changes() {
+    let config = SandboxConfig::default();
+    assert_eq!(diff(&config, &config.clone(), &SECRET_FIELDS), Vec::new());
+}
+
+#[test]
+fn nested_fields_are_named_by_path() {
+    let before = SandboxConfig::default();
+    let mut after = before.clone();
+    after.timeout = Duration::from_secs(5);
+    after.ro_binds.push("/opt/data".into());
+    after.memory_limit = Some(leeward_core::ByteSize::mib(256));
+
+    let changes = diff(&before, &after, &SECRET_FIELDS);
+    assert_eq!(
+        changes,
+        [
+            change(
+                "memory_limit",
+                None,
+                Some("268435456"),
+                Provenance::DaemonConfig
+            ),
+            change(
+                "ro_binds.3",
+                None,
+                Some("/opt/data"),
+                Provenance::DaemonConfig
+            ),
+            change(
+                "timeout.secs",
+                Some("30"),
+                Some("5"),
+                Provenance::DaemonConfig
+            ),
+        ]
+    );
+
+    // Going back reports the built-in values as such
+    let changes = diff(&after, &before, &SECRET_FIELDS);
+    assert_eq!(
+        changes[2],
+        change("timeout.secs", Some("5"), Some("30"), Provenance::Default)
+    );
+    assert_eq!(changes[0].after, None);
+}
+
+#[test]
+fn secret_fields_show_only_whether_they_are_set() {
+    let before = SandboxConfig::default();
+    let after = SandboxConfig::builder().env("API_TOKEN", "hunter2").build();
+
+    let changes = diff(&before, &after, &SECRET_FIELDS);
+    assert_ne!(changes.len(), 0);
+    for change in &changes {
+        assert!(change.field.starts_with("env."), "{change:?}");
+        assert_eq!(change.after.as_deref(), Some(REDACTED), "{change:?}");
+    }
+    let json = serde_json::to_string(&changes).unwrap();
+    assert!(!json.contains("hunter2"), "{json}");
+    assert!(!json.contains("API_TOKEN"), "{json}");
+
+    // Only whole path segments match: `env` does not hide `envelope`
+    let changes = diff(&before, &after, &["en"]);
+    assert!(
+        changes
+            .iter()
+            .any(|change| change.after.as_deref() == Some("hunter2"))
+    );
+
+    // Other fields can be made secret instead
+    let mut moved = before.clone();
+    moved.workdir = "/srv/secret-project".into();
+    let changes = diff(&before, &moved, &["workdir"]);
+    assert_eq!(
+        changes,
+        [change(
+            "workdir",
+            Some(REDACTED),
+            Some(REDACTED),
+            Provenance::DaemonConfig
+        )]
+    );
+}
+
+#[test]
+fn startup_is_diffed_against_the_built_in_config() {
+    let config = SandboxConfig::builder()
+        .timeout(Duration::from_secs(7))
+        .build();
+    let startup = PolicyChange::new(PolicyActor::Startup, None, &config, &SECRET_FIELDS);
+    assert_eq!(startup.fingerprint_before, None);
+    assert_eq!(startup.fingerprint_after, config.fingerprint());
+    assert_eq!(
+        startup.changes,
+        [change(
+            "timeout.secs",
+            Some("30"),
+            Some("7"),
+            Provenance::DaemonConfig
+        )]
+    );
+    assert!(startup.timestamp_ms > 0);
+
+    let unchanged = PolicyChange::new(
+        PolicyActor::Startup,
+        None,
+        &SandboxConfig::default(),
+        &SECRET_FIELDS,
+    );
+    assert_eq!(unchanged.changes, Vec::new());
+}
+
+#[test]
+fn records_round_trip_with_the_actor_inline() {
+    let before = SandboxConfig::default();
+    let after = SandboxConfig::builder()
+        .timeout(Duration::from_secs(9))
+        .build();
+    let actor = PolicyActor::Request {
+        request: "drain_profile".into(),
+        peer_uid: Some(1000),
+    };
+    let record = PolicyChange::new(actor, Some(&before), &after, &SECRET_FIELDS)
+        .with_reason(Some("interpreter upgraded".into()));
+    assert_eq!(record.fingerprint_before, Some(before.fingerprint()));
+    assert_eq!(record.actor.to_string(), "drain_profile from uid 1000");
+
+    let json = serde_json::to_value(&record).unwrap();
+    assert_eq!(json["actor"], "request");
+    assert_eq!(json["request"], "drain_profile");
+    assert_eq!(json["peer_uid"], 1000);
+    assert_eq!(json["reason"], "interpreter upgraded");
+    assert_eq!(
+        json["changes"][0]["provenance"],
+        serde_json::to_value(Provenance::DaemonConfig).unwrap()
+    );
+    let back: PolicyChange = serde_json::from_value(json).unwrap();
+    assert_eq!(back, record);
+
+    let signal = PolicyActor::Signal {
+        signal: "SIGHUP".into(),
+    };
+    assert_eq!(signal.to_string(), "SIGHUP");
+    assert_eq!(PolicyActor::Startup.to_string(), "startup");
+}
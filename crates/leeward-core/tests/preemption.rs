@@ -0,0 +1,126 @@
+//! A preemptible execution can be frozen and thawed while it runs, and
+//! neither its timeout nor its duration counts the time it spent frozen
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::config::Interpreter;
+use leeward_core::pipe::{FREEZE, THAW, WorkerPipe};
+use leeward_core::preempt::Freeze;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Work that takes well under a second, and the same however long it is
+/// stopped for
+const COUNTING_LOOP: &str = "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done; echo $i";
+
+/// How long the loop is kept frozen, longer than its timeout
+const FROZEN_FOR: Duration = Duration::from_millis(2500);
+
+fn counting(preemptible: bool) -> ExecuteOptions {
+    ExecuteOptions {
+        interpreter: Interpreter::Sh,
+        timeout: Some(Duration::from_secs(2)),
+        preemptible,
+        ..ExecuteOptions::default()
+    }
+}
+
+fn kill(worker: &Worker) {
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+#[test]
+fn frozen_time_is_left_out_of_timeout_and_duration() {
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let preemption = worker.preemption();
+    assert_eq!(preemption.freeze().unwrap(), Freeze::Idle);
+
+    let started = Instant::now();
+    let running = std::thread::spawn(move || {
+        let result = worker.execute(COUNTING_LOOP, &counting(true));
+        (worker, result)
+    });
+
+    std::thread::sleep(Duration::from_millis(100));
+    let froze = preemption.freeze().unwrap();
+    if froze == Freeze::Frozen {
+        assert!(preemption.is_frozen());
+        assert_eq!(preemption.freeze().unwrap(), Freeze::AlreadyFrozen);
+        std::thread::sleep(FROZEN_FOR);
+        assert!(preemption.thaw().unwrap());
+    }
+    let (worker, result) = running.join().unwrap();
+    let wall = started.elapsed();
+    kill(&worker);
+
+    let result = result.unwrap();
+    if result.stderr_str().starts_with("Failed to execute") {
+        eprintln!("skipping, execution fails here: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(froze, Freeze::Frozen, "finished within 100ms: {result:?}");
+    assert!(!result.timed_out, "{result:?}");
+    assert_eq!(result.stdout_str(), "100000\n");
+    assert_eq!(result.preempted_count, 1);
+
+    // Stopped for the whole freeze, so done only after it
+    assert!(wall >= FROZEN_FOR, "finished after {wall:?}");
+    assert!(
+        result.frozen_duration + Duration::from_millis(100) >= FROZEN_FOR,
+        "{:?}",
+        result.frozen_duration
+    );
+    assert!(result.duration + result.frozen_duration <= wall);
+    assert!(result.duration + FROZEN_FOR < wall + Duration::from_millis(100));
+
+    // Disarmed with the execution
+    assert!(!preemption.thaw().unwrap());
+    assert_eq!(preemption.freeze().unwrap(), Freeze::Idle);
+}
+
+#[test]
+fn only_preemptible_executions_freeze() {
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let preemption = worker.preemption();
+
+    let running = std::thread::spawn(move || {
+        let result = worker.execute("sleep 0.5; echo done", &counting(false));
+        (worker, result)
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(preemption.freeze().unwrap(), Freeze::Idle);
+    let (worker, result) = running.join().unwrap();
+    kill(&worker);
+
+    let result = result.unwrap();
+    assert_eq!(result.preempted_count, 0);
+    assert_eq!(result.frozen_duration, Duration::ZERO);
+}
+
+#[test]
+fn late_control_bytes_are_skipped() {
+    let (mut parent, mut child) = WorkerPipe::new().unwrap().split();
+
+    // A freeze and thaw sent just as the last execution finished
+    let mut control = parent.control().unwrap();
+    control.write_all(&[FREEZE, THAW]).unwrap();
+    parent.send_code(b"next job").unwrap();
+
+    assert_eq!(child.recv_code().unwrap(), b"next job");
+}
@@ -0,0 +1,160 @@
+//! Each worker mounts `/proc` for its own pid namespace with `hidepid=2`,
+//! from an init process kept in the namespace, so sandboxed code lists only
+//! the processes of its sandbox
+
+#![cfg(feature = "protocol")]
+
+mod common;
+
+use leeward_core::isolation::MountConfig;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::mounts::HIDEPID_INVISIBLE;
+use leeward_core::isolation::namespace::spawn_init;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{LeewardError, SandboxConfig};
+use nix::sched::{CloneFlags, unshare};
+use std::path::Path;
+
+/// The pids a `proc` mounted at `dir` lists
+fn listed_pids(dir: &Path) -> std::io::Result<Vec<i32>> {
+    let mut pids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        if let Some(pid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            pids.push(pid);
+        }
+    }
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// Fork, run `child` in the fork and say whether it returned true
+fn forked(child: impl FnOnce() -> bool) -> std::io::Result<bool> {
+    // SAFETY: fork in a single-threaded child; the fork only reads files and exits
+    match unsafe { libc::fork() } {
+        0 => {
+            let ok = child();
+            // SAFETY: Exiting the fork
+            unsafe { libc::_exit(i32::from(!ok)) };
+        }
+        -1 => Err(std::io::Error::last_os_error()),
+        pid => Ok(common::succeeded(pid)),
+    }
+}
+
+#[test]
+fn proc_lists_only_the_pid_namespace() {
+    let dir = common::Scratch::new("proc-listing");
+    let target = dir.to_path_buf();
+    let pid = clone_worker(0, move || {
+        unshare(CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        let init = spawn_init(|| MountConfig::proc(&target, HIDEPID_INVISIBLE).apply())?;
+        if init <= 0 {
+            return Err(LeewardError::Namespace(format!("init has pid {init}")));
+        }
+        // The init is pid 1 and the fork the next one in the namespace
+        let listed = forked(|| listed_pids(&target).is_ok_and(|pids| pids == [1, 2]))?;
+        if listed {
+            Ok(())
+        } else {
+            Err(LeewardError::Namespace(
+                "proc lists processes outside the namespace".into(),
+            ))
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn the_namespace_outlives_its_first_child() {
+    let pid = clone_worker(0, || {
+        unshare(CloneFlags::CLONE_NEWPID)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        spawn_init(|| Ok(()))?;
+        // Without a lasting init the second fork would fail
+        for _ in 0..2 {
+            if !forked(|| std::process::id() > 1)? {
+                return Err(LeewardError::Namespace(
+                    "a fork into the namespace failed".into(),
+                ));
+            }
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn a_failed_init_setup_is_reported() {
+    let pid = clone_worker(0, || {
+        unshare(CloneFlags::CLONE_NEWPID)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        match spawn_init(|| Err(LeewardError::Namespace("no proc here".into()))) {
+            Err(e) if e.to_string().contains("no proc here") => Ok(()),
+            other => Err(LeewardError::Namespace(format!("unexpected: {other:?}"))),
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn hidepid_above_two_is_rejected() {
+    let dir = common::Scratch::new("proc-hidepid");
+    assert!(MountConfig::proc(&dir, 3).apply().is_err());
+
+    let config = SandboxConfig::default();
+    assert!(config.mount_proc);
+    assert_eq!(config.proc_hidepid, HIDEPID_INVISIBLE);
+    assert!(
+        SandboxConfig::builder()
+            .proc_hidepid(3)
+            .build()
+            .validate()
+            .is_err()
+    );
+    assert!(
+        !SandboxConfig::builder()
+            .mount_proc(false)
+            .build()
+            .mount_proc
+    );
+}
+
+#[test]
+fn sandboxed_code_lists_only_its_own_processes() {
+    let mut worker = Worker::new(0, SandboxConfig::minimal_for_tests());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let code = "import os; print(os.getpid(), \
+                *sorted(int(p) for p in os.listdir('/proc') if p.isdigit()))";
+    let result = worker.execute(code, &ExecuteOptions::default());
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let result = result.unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return;
+    }
+    let output = result.stdout_str();
+    let mut fields = output.split_whitespace();
+    let own = fields.next().unwrap();
+    // The worker's own pid, and those of the processes above it in the sandbox
+    let listed: Vec<&str> = fields.collect();
+    assert!(listed.contains(&own), "{output}");
+    assert!(listed.len() <= 3, "{output}");
+}
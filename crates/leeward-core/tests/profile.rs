@@ -0,0 +1,212 @@
+//! Profiling runs count what a workload does without denying any of it,
+//! and suggestions derived from a profile cover exactly what was refused
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::profile::{FileAccess, Suggestion, WorkloadProfile, suggest, syscall_name};
+use std::path::PathBuf;
+
+/// A profile whose refused paths were opened with the given access
+fn denied(paths: &[(&str, FileAccess)]) -> WorkloadProfile {
+    WorkloadProfile {
+        files: paths
+            .iter()
+            .map(|(path, access)| (PathBuf::from(path), *access))
+            .collect(),
+        landlock_denied: paths.iter().map(|(path, _)| PathBuf::from(path)).collect(),
+        ..WorkloadProfile::default()
+    }
+}
+
+fn paths(binds: &[PathBuf]) -> Vec<&str> {
+    binds.iter().map(|path| path.to_str().unwrap()).collect()
+}
+
+#[test]
+fn binds_group_a_package_under_one_directory() {
+    let suggestion = suggest(&denied(&[
+        ("/usr/lib/python3.12/json/__init__.py", FileAccess::Read),
+        ("/usr/lib/python3.12/json/decoder.py", FileAccess::Read),
+        ("/usr/lib/python3.12/os.py", FileAccess::Read),
+        ("/opt/data/input.csv", FileAccess::Read),
+        ("/etc/hosts", FileAccess::Read),
+    ]));
+    assert_eq!(
+        paths(&suggestion.ro_binds),
+        ["/etc", "/opt/data", "/usr/lib/python3.12"]
+    );
+    assert_eq!(paths(&suggestion.rw_binds), Vec::<&str>::new());
+}
+
+#[test]
+fn writes_win_over_reads() {
+    let suggestion = suggest(&denied(&[
+        ("/srv/cache/index.json", FileAccess::Read),
+        ("/srv/cache/blob", FileAccess::Write),
+        ("/srv/cache/nested/deeper/file", FileAccess::Read),
+        ("/srv/config.toml", FileAccess::Read),
+    ]));
+    assert_eq!(paths(&suggestion.rw_binds), ["/srv/cache"]);
+    assert_eq!(paths(&suggestion.ro_binds), ["/srv"]);
+}
+
+#[test]
+fn nested_binds_are_left_out() {
+    let suggestion = suggest(&denied(&[
+        ("/data/a.txt", FileAccess::Read),
+        ("/data/sets/b.txt", FileAccess::Read),
+        ("/out/run/log.txt", FileAccess::Write),
+        ("/out/run/x/y/z.txt", FileAccess::Write),
+    ]));
+    assert_eq!(paths(&suggestion.ro_binds), ["/data"]);
+    assert_eq!(paths(&suggestion.rw_binds), ["/out/run"]);
+}
+
+#[test]
+fn pseudo_filesystem_entries_are_bound_exactly() {
+    let suggestion = suggest(&denied(&[
+        ("/dev/urandom", FileAccess::Read),
+        ("/dev/null", FileAccess::Write),
+        ("/proc/self/status", FileAccess::Read),
+    ]));
+    assert_eq!(
+        paths(&suggestion.ro_binds),
+        ["/dev/urandom", "/proc/self/status"]
+    );
+    assert_eq!(paths(&suggestion.rw_binds), ["/dev/null"]);
+}
+
+#[test]
+fn allowed_paths_get_no_bind() {
+    let mut profile = denied(&[("/opt/data/x.csv", FileAccess::Read)]);
+    profile
+        .files
+        .insert("/tmp/scratch".into(), FileAccess::Write);
+    let suggestion = suggest(&profile);
+    assert_eq!(paths(&suggestion.ro_binds), ["/opt/data"]);
+    assert_eq!(paths(&suggestion.rw_binds), Vec::<&str>::new());
+
+    assert_eq!(suggest(&WorkloadProfile::default()), Suggestion::default());
+}
+
+#[test]
+fn syscalls_and_network_are_suggested() {
+    let profile = WorkloadProfile {
+        syscalls: [(libc::SYS_read, 10), (libc::SYS_openat, 2), (100_000, 1)]
+            .into_iter()
+            .collect(),
+        inet_sockets: 1,
+        ..WorkloadProfile::default()
+    };
+    let suggestion = suggest(&profile);
+    assert!(suggestion.allow_network);
+    for name in ["read", "openat", "100000"] {
+        assert!(
+            suggestion.syscalls.iter().any(|syscall| syscall == name),
+            "{name} missing from {:?}",
+            suggestion.syscalls
+        );
+    }
+    assert!(!suggest(&WorkloadProfile::default()).allow_network);
+
+    assert_eq!(syscall_name(libc::SYS_getrandom), Some("getrandom"));
+    assert_eq!(syscall_name(-1), None);
+}
+
+#[cfg(feature = "landlock")]
+#[test]
+fn landlock_dry_run_mirrors_worker_rules() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::profile::landlock_allows;
+    use std::path::Path;
+
+    let config = SandboxConfig::builder()
+        .python_path("/usr/bin/python3")
+        .ro_bind("/opt/data")
+        .rw_bind("/srv/out")
+        .build();
+    let allows = |path: &str, access| landlock_allows(&config, Path::new(path), access);
+
+    assert!(allows("/usr/bin/python3", FileAccess::Read));
+    assert!(allows("/opt/data/x.csv", FileAccess::Read));
+    assert!(!allows("/opt/data/x.csv", FileAccess::Write));
+    assert!(allows("/srv/out/result", FileAccess::Write));
+    assert!(allows("/tmp/scratch", FileAccess::Write));
+    assert!(!allows("/etc/passwd", FileAccess::Read));
+    assert!(!allows("/opt/database", FileAccess::Read));
+}
+
+#[cfg(feature = "seccomp")]
+#[test]
+fn profiling_counts_without_denying() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::profile::profile_worker;
+    use leeward_core::worker::{ExecuteOptions, Worker};
+
+    let mut worker = Worker::new(0, SandboxConfig::minimal_for_tests()).observed();
+    // Resource use comes from the worker's cgroup, where there is one
+    #[cfg(feature = "cgroups")]
+    let accounted = std::env::var("LEEWARD_TEST_CGROUP_ROOT").ok().map(|root| {
+        let name = format!("profile-{}", std::process::id());
+        let cgroup =
+            leeward_core::isolation::CgroupHandle::create(std::path::Path::new(&root), &name)
+                .unwrap();
+        worker.set_cgroup_root(cgroup.path()).unwrap();
+        cgroup
+    });
+    let options = ExecuteOptions {
+        files: vec![("input.txt".to_owned(), b"hello".to_vec())],
+        ..ExecuteOptions::default()
+    };
+    let code = r"
+import socket, subprocess
+data = open('input.txt').read()
+open('output.txt', 'w').write(data * 2)
+socket.socket(socket.AF_INET, socket.SOCK_STREAM).close()
+subprocess.run(['true'])
+print(data)
+";
+
+    let (result, profile) = match profile_worker(&mut worker, code, &options) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            eprintln!("skipping: cannot profile here: {e}");
+            return;
+        }
+    };
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(result.stdout_str().trim(), "hello");
+    #[cfg(feature = "cgroups")]
+    if let Some(cgroup) = accounted {
+        cgroup.remove().unwrap();
+        assert!(profile.memory_peak > 0);
+        assert_eq!(profile.memory_peak, result.memory_peak);
+        assert_eq!(profile.cpu_time_us, result.cpu_time_us);
+    } else {
+        assert_eq!(profile.memory_peak, 0);
+    }
+
+    assert!(profile.syscalls[&libc::SYS_openat] > 0);
+    assert!(profile.syscalls[&libc::SYS_write] > 0);
+    assert!(profile.inet_sockets >= 1);
+    assert!(profile.processes >= 1);
+
+    let workdir = &worker.config().workdir;
+    assert_eq!(
+        profile.files.get(&workdir.join("input.txt")),
+        Some(&FileAccess::Read)
+    );
+    assert_eq!(
+        profile.files.get(&workdir.join("output.txt")),
+        Some(&FileAccess::Write)
+    );
+    assert_eq!(profile.workspace.get("output.txt"), Some(&10));
+
+    assert_ne!(profile.unlisted_syscalls.len(), 0);
+    let suggestion = suggest(&profile);
+    assert!(suggestion.allow_network);
+    assert!(suggestion.syscalls.iter().any(|name| name == "openat"));
+}
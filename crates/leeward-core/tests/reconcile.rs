@@ -0,0 +1,119 @@
+//! Reconciliation reaps template roots nobody owns, and their mounts, but
+//! never a root in use, another daemon's, or anything it would have to
+//! remove recursively
+
+use leeward_core::isolation::registry::{self, ROOT_PREFIX, Reaped, RootClaim};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Scratch roots dir, removed on drop
+struct RootsDir(PathBuf);
+
+impl RootsDir {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-reconcile-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir.canonicalize().unwrap())
+    }
+
+    /// Create the root a process `pid` would have made as its `n`th
+    fn root(&self, pid: u32, n: u32) -> PathBuf {
+        let path = self.0.join(format!("{ROOT_PREFIX}{pid}-{n}"));
+        std::fs::create_dir(&path).unwrap();
+        path
+    }
+
+    /// Reconcile, with the roots reaped sorted by path
+    fn reconcile(&self, grace: Duration) -> Vec<Reaped> {
+        let mut reaped = registry::reconcile(&self.0, grace);
+        reaped.sort_by(|a, b| a.path().cmp(b.path()));
+        reaped
+    }
+}
+
+impl Drop for RootsDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Pid of a process that has exited and been reaped
+fn dead_pid() -> u32 {
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let pid = child.id();
+    child.wait().unwrap();
+    pid
+}
+
+#[test]
+fn only_orphans_are_reaped() {
+    let dir = RootsDir::new("orphans");
+    let own = std::process::id();
+    let dead = dead_pid();
+
+    let claim = RootClaim::register(dir.0.join(format!("{ROOT_PREFIX}{own}-0")));
+    let in_use = dir.root(own, 0);
+    let unregistered = dir.root(own, 1);
+    let abandoned = dir.root(dead, 0);
+    let other_daemon = dir.root(1, 0);
+    let not_empty = dir.root(dead, 1);
+    std::fs::write(not_empty.join("file"), b"").unwrap();
+    let unrelated = dir.0.join(format!("{ROOT_PREFIX}{dead}"));
+    std::fs::create_dir(&unrelated).unwrap();
+
+    // Too young to be reaped yet
+    assert_eq!(dir.reconcile(Duration::from_secs(3600)), []);
+
+    let mut orphans = vec![
+        Reaped::Root(unregistered.clone()),
+        Reaped::Root(abandoned.clone()),
+    ];
+    orphans.sort_by(|a, b| a.path().cmp(b.path()));
+    assert_eq!(dir.reconcile(Duration::ZERO), orphans);
+    assert!(!abandoned.exists() && !unregistered.exists());
+    for kept in [&in_use, &other_daemon, &not_empty, &unrelated] {
+        assert!(kept.exists(), "{} was reaped", kept.display());
+    }
+
+    // Released roots are removed by their claim, not found as orphans
+    drop(claim);
+    assert!(!in_use.exists());
+    assert_eq!(dir.reconcile(Duration::ZERO), []);
+}
+
+#[test]
+fn leaked_mounts_are_detached() {
+    let dir = RootsDir::new("mounts");
+    let root = dir.root(dead_pid(), 0);
+    let nested = root.join("nested");
+
+    if let Err(e) = mount_tmpfs(&root) {
+        eprintln!("skipping: cannot mount here: {e}");
+        return;
+    }
+    std::fs::create_dir(&nested).unwrap();
+    mount_tmpfs(&nested).unwrap();
+
+    // Deepest mount first, then the root once nothing is mounted on it
+    assert_eq!(
+        registry::reconcile(&dir.0, Duration::ZERO),
+        [
+            Reaped::Mount(nested),
+            Reaped::Mount(root.clone()),
+            Reaped::Root(root.clone()),
+        ]
+    );
+    assert!(!root.exists());
+}
+
+fn mount_tmpfs(path: &Path) -> nix::Result<()> {
+    nix::mount::mount(
+        Some("tmpfs"),
+        path,
+        Some("tmpfs"),
+        nix::mount::MsFlags::empty(),
+        Some("size=64k"),
+    )
+}
@@ -0,0 +1,141 @@
+//! Template roots are only made in a directory no other user can enter,
+//! and a root swapped for something else as it is created is never used
+
+use leeward_core::isolation::registry;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Scratch dir in the temp dir, removed on drop
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-root-dirs-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn euid() -> u32 {
+    // SAFETY: geteuid has no failure modes
+    unsafe { libc::geteuid() }
+}
+
+#[test]
+fn the_roots_dir_is_private_to_this_user() {
+    registry::open_roots_dir().unwrap();
+    let metadata = std::fs::symlink_metadata(registry::roots_dir()).unwrap();
+    assert!(metadata.is_dir());
+    assert_eq!(metadata.uid(), euid());
+    assert_eq!(metadata.mode() & 0o077, 0, "mode {:o}", metadata.mode());
+}
+
+#[test]
+fn missing_dirs_are_created_private() {
+    let scratch = Scratch::new("missing");
+    let dir = scratch.0.join("roots");
+    registry::open_private_dir(&dir).unwrap();
+    assert_eq!(std::fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+    // Opening it again finds the same
+    registry::open_private_dir(&dir).unwrap();
+}
+
+#[test]
+fn planted_dirs_are_refused() {
+    let scratch = Scratch::new("planted");
+    let real = scratch.0.join("real");
+    std::fs::create_dir(&real).unwrap();
+    std::fs::set_permissions(&real, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+    let link = scratch.0.join("link");
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+    assert!(
+        registry::open_private_dir(&link).is_err(),
+        "followed a symlink"
+    );
+
+    let open = scratch.0.join("open");
+    std::fs::create_dir(&open).unwrap();
+    std::fs::set_permissions(&open, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let error = registry::open_private_dir(&open).unwrap_err();
+    assert!(error.to_string().contains("open to other users"), "{error}");
+}
+
+#[test]
+fn roots_are_created_once() {
+    let scratch = Scratch::new("once");
+    let dir = registry::open_private_dir(&scratch.0.join("roots")).unwrap();
+    registry::create_root(&dir, "root").unwrap();
+    assert!(
+        registry::create_root(&dir, "root").is_err(),
+        "reused an existing root"
+    );
+}
+
+#[test]
+fn a_root_swapped_for_a_symlink_is_never_used() {
+    let scratch = Scratch::new("swap");
+    let roots = scratch.0.join("roots");
+    let dir = registry::open_private_dir(&roots).unwrap();
+    let decoy = scratch.0.join("decoy");
+    std::fs::create_dir(&decoy).unwrap();
+    let decoy_ino = std::fs::metadata(&decoy).unwrap().ino();
+    let root = roots.join("root");
+
+    // Put a symlink at the root's name, removing a root made there first,
+    // as fast as it goes
+    let stop = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (stop, root, decoy) = (Arc::clone(&stop), root.clone(), decoy);
+        std::thread::spawn(move || {
+            let mut planted = 0u32;
+            while !stop.load(Ordering::Relaxed) {
+                let _ = std::fs::remove_dir(&root);
+                if std::os::unix::fs::symlink(&decoy, &root).is_ok() {
+                    planted += 1;
+                }
+                std::thread::yield_now();
+            }
+            planted
+        })
+    };
+
+    let (mut created, mut refused) = (0u32, 0u32);
+    for _ in 0..2000 {
+        clear(&root);
+        match registry::create_root(&dir, "root") {
+            Ok(fd) => {
+                created += 1;
+                std::thread::yield_now();
+                let stat = nix::sys::stat::fstat(&fd).unwrap();
+                assert_ne!(
+                    stat.st_ino, decoy_ino,
+                    "opened the decoy through a planted symlink"
+                );
+            }
+            Err(_) => refused += 1,
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    let planted = swapper.join().unwrap();
+    clear(&root);
+    // Best effort: how often the swapper got in depends on scheduling
+    eprintln!("{created} roots created, {refused} refused, {planted} symlinks planted");
+    assert!(created > 0, "no root was ever created");
+}
+
+/// Remove the root or the symlink in its place
+fn clear(root: &Path) {
+    let _ = std::fs::remove_file(root);
+    let _ = std::fs::remove_dir(root);
+}
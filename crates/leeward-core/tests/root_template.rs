@@ -0,0 +1,177 @@
+//! Workers attached to a `RootTemplate` see the mount layout as it was when
+//! the template was built. File contents are not snapshotted: edits to host
+//! files made afterwards are visible through the template's bind mounts.
+//! Entering the template leaves `/` on a filesystem of its own.
+
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::{IsolationLayer, RootTemplate};
+use nix::sched::CloneFlags;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Child exit code when every check passed
+const EXIT_OK: i32 = 0;
+/// Child exit code when the template could not be built or attached here
+const EXIT_UNSUPPORTED: i32 = 2;
+/// Child exit code when a host mount added later leaked into the template
+const EXIT_MOUNT_LEAKED: i32 = 3;
+/// Child exit code when the host file edit was not visible
+const EXIT_STALE_CONTENTS: i32 = 4;
+/// Child exit code when the template root was writable
+const EXIT_WRITABLE: i32 = 5;
+/// Child exit code when `/` was still the host's after entering
+const EXIT_HOST_ROOT: i32 = 6;
+/// Child exit code when entering the template failed
+const EXIT_NOT_ENTERED: i32 = 7;
+
+/// Run `check` in a forked child, returning its exit code
+fn in_child(check: impl FnOnce() -> i32) -> i32 {
+    // SAFETY: fork in a test; the child only does mount setup and file I/O
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+
+    if pid == 0 {
+        let code = check();
+        // SAFETY: Exiting child process
+        unsafe { libc::_exit(code) };
+    }
+
+    let mut status = 0;
+    // SAFETY: Waiting on our own child
+    unsafe { libc::waitpid(pid, &raw mut status, 0) };
+    assert!(libc::WIFEXITED(status), "child did not exit normally");
+    libc::WEXITSTATUS(status)
+}
+
+#[test]
+fn template_snapshots_mounts_but_not_file_contents() {
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: building a root template needs root");
+        return;
+    }
+
+    let base = std::env::temp_dir().join(format!("leeward-template-test-{}", std::process::id()));
+    let host = base.join("host");
+    std::fs::create_dir_all(host.join("sub")).unwrap();
+    std::fs::write(host.join("data"), "before").unwrap();
+
+    let code = in_child(|| check_snapshot(&base, &host));
+    let _ = std::fs::remove_dir_all(&base);
+
+    match code {
+        EXIT_OK => {}
+        EXIT_UNSUPPORTED => eprintln!("skipping: mount namespaces or open_tree unavailable"),
+        EXIT_MOUNT_LEAKED => panic!("mount added after template creation is visible"),
+        EXIT_STALE_CONTENTS => panic!("host file edit is not visible through the template"),
+        EXIT_WRITABLE => panic!("template root is writable"),
+        code => panic!("unexpected child exit {code}"),
+    }
+}
+
+#[test]
+fn entering_leaves_the_host_root_filesystem() {
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: building a root template needs root");
+        return;
+    }
+
+    match in_child(check_entered) {
+        EXIT_OK => {}
+        EXIT_UNSUPPORTED => eprintln!("skipping: mount namespaces or open_tree unavailable"),
+        EXIT_HOST_ROOT => panic!("/ is still on the host root's filesystem after entering"),
+        EXIT_NOT_ENTERED => panic!("entering the template failed"),
+        code => panic!("unexpected child exit {code}"),
+    }
+}
+
+/// Runs in the forked child, which holds the template while a grandchild
+/// enters it as a worker would
+fn check_entered() -> i32 {
+    let Ok(host) = std::fs::metadata("/").map(|metadata| metadata.dev()) else {
+        return EXIT_UNSUPPORTED;
+    };
+    if nix::sched::unshare(CloneFlags::CLONE_NEWNS).is_err() || make_private().is_err() {
+        return EXIT_UNSUPPORTED;
+    }
+    let Ok(template) = RootTemplate::build(&SandboxConfig::default()) else {
+        return EXIT_UNSUPPORTED;
+    };
+
+    in_child(|| {
+        if nix::sched::unshare(CloneFlags::CLONE_NEWNS).is_err() {
+            return EXIT_UNSUPPORTED;
+        }
+        if template.apply_layer().is_err() {
+            return EXIT_NOT_ENTERED;
+        }
+        match std::fs::metadata("/") {
+            Ok(metadata) if metadata.dev() != host => EXIT_OK,
+            _ => EXIT_HOST_ROOT,
+        }
+    })
+}
+
+/// Runs in the forked child, which plays the role of the host
+fn check_snapshot(base: &Path, host: &Path) -> i32 {
+    // Keep the mounts made below out of the real host
+    if nix::sched::unshare(CloneFlags::CLONE_NEWNS).is_err() || make_private().is_err() {
+        return EXIT_UNSUPPORTED;
+    }
+
+    let config = SandboxConfig {
+        ro_binds: vec![host.to_path_buf()],
+        rw_binds: vec![],
+        ..SandboxConfig::default()
+    };
+    let Ok(template) = RootTemplate::build(&config) else {
+        return EXIT_UNSUPPORTED;
+    };
+
+    // Change the host after the template exists: a new mount and a file edit
+    let sub = host.join("sub");
+    if nix::mount::mount(
+        Some("tmpfs"),
+        &sub,
+        Some("tmpfs"),
+        nix::mount::MsFlags::empty(),
+        None::<&str>,
+    )
+    .is_err()
+    {
+        return EXIT_UNSUPPORTED;
+    }
+    std::fs::write(sub.join("marker"), "new mount").unwrap();
+    std::fs::write(host.join("data"), "after").unwrap();
+
+    let target = base.join("attached");
+    if template.attach(&target).is_err() {
+        return EXIT_UNSUPPORTED;
+    }
+
+    let seen = target.join(host.strip_prefix("/").unwrap());
+    if seen.join("sub/marker").exists() {
+        return EXIT_MOUNT_LEAKED;
+    }
+    if std::fs::read_to_string(seen.join("data")).ok().as_deref() != Some("after") {
+        return EXIT_STALE_CONTENTS;
+    }
+    if std::fs::write(seen.join("new"), "x").is_ok()
+        || std::fs::create_dir(target.join("new")).is_ok()
+    {
+        return EXIT_WRITABLE;
+    }
+
+    EXIT_OK
+}
+
+fn make_private() -> nix::Result<()> {
+    nix::mount::mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        nix::mount::MsFlags::MS_REC | nix::mount::MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+}
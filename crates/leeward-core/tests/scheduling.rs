@@ -0,0 +1,130 @@
+//! The interpreter runs under the configured nice value and scheduling
+//! policy, and batch work yields the CPU to interactive work
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::config::SchedPolicy;
+use leeward_core::worker::{ExecuteOptions, run_python};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Prints the nice value and policy from `/proc/self/stat`
+///
+/// Fields are counted after the command name, which may contain spaces.
+const READ_STAT: &str = r#"
+fields = open("/proc/self/stat").read().rsplit(")", 1)[1].split()
+print(fields[16], fields[38])
+"#;
+
+/// Spins on one shared CPU until a deadline, printing its iteration count
+const SPIN: &str = r"
+import os, time
+os.sched_setaffinity(0, {min(os.sched_getaffinity(0))})
+start, end = START, START + DURATION
+while time.time() < start:
+    pass
+count = 0
+while time.time() < end:
+    count += 1
+print(count)
+";
+
+/// `(nice, policy)` the interpreter ran with
+fn stat(config: &SandboxConfig, options: &ExecuteOptions) -> Option<(i32, i32)> {
+    let result = run_python(READ_STAT, config, options).unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return None;
+    }
+    let stdout = result.stdout_str();
+    let mut fields = stdout
+        .split_whitespace()
+        .map(|field| field.parse().unwrap());
+    Some((fields.next().unwrap(), fields.next().unwrap()))
+}
+
+#[test]
+fn config_sets_nice_and_policy() {
+    let config = SandboxConfig::builder()
+        .nice(10)
+        .sched_policy(SchedPolicy::Batch)
+        .build();
+    let Some(batch) = stat(&config, &ExecuteOptions::default()) else {
+        return;
+    };
+    assert_eq!(batch, (10, libc::SCHED_BATCH));
+
+    let config = SandboxConfig::builder()
+        .sched_policy(SchedPolicy::Idle)
+        .build();
+    assert_eq!(
+        stat(&config, &ExecuteOptions::default()).unwrap().1,
+        libc::SCHED_IDLE
+    );
+}
+
+#[test]
+fn options_override_config() {
+    let config = SandboxConfig::builder()
+        .nice(5)
+        .sched_policy(SchedPolicy::Idle)
+        .build();
+    let options = ExecuteOptions {
+        nice: Some(15),
+        sched_policy: Some(SchedPolicy::Batch),
+        ..ExecuteOptions::default()
+    };
+    let Some(overridden) = stat(&config, &options) else {
+        return;
+    };
+    assert_eq!(overridden, (15, libc::SCHED_BATCH));
+}
+
+#[test]
+fn unset_fields_are_inherited() {
+    let Some((nice, policy)) = stat(&SandboxConfig::default(), &ExecuteOptions::default()) else {
+        return;
+    };
+    // SAFETY: getpriority on ourselves
+    let own_nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    assert_eq!(nice, own_nice);
+    // SAFETY: sched_getscheduler on ourselves
+    assert_eq!(policy, unsafe { libc::sched_getscheduler(0) });
+}
+
+#[test]
+fn batch_yields_to_interactive() {
+    let config = SandboxConfig::default();
+    if stat(&config, &ExecuteOptions::default()).is_none() {
+        return;
+    }
+
+    // Both spin on the same CPU over the same window, once the interpreters are up
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(1);
+    let code = SPIN
+        .replace("START", &start.as_secs_f64().to_string())
+        .replace("DURATION", "1.0");
+
+    let spin = |options: ExecuteOptions| {
+        let config = config.clone();
+        let code = code.clone();
+        std::thread::spawn(move || {
+            let result = run_python(&code, &config, &options).unwrap();
+            assert_eq!(result.exit_code, 0, "{}", result.stderr_str());
+            result.stdout_str().trim().parse::<u64>().unwrap()
+        })
+    };
+    let batch = spin(ExecuteOptions {
+        nice: Some(10),
+        sched_policy: Some(SchedPolicy::Batch),
+        ..ExecuteOptions::default()
+    });
+    let interactive = spin(ExecuteOptions::default());
+
+    let (batch, interactive) = (batch.join().unwrap(), interactive.join().unwrap());
+    // Nice 10 against nice 0 is roughly a 1:10 CPU share
+    assert!(
+        interactive > batch * 3,
+        "interactive managed {interactive} iterations, batch {batch}"
+    );
+}
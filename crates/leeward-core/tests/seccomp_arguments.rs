@@ -0,0 +1,324 @@
+//! Syscall rules with argument constraints let the permitted form of a
+//! syscall through and kill the caller for any other, and denial rules
+//! fail the forms they match with `EPERM` whatever the allowlist says
+
+#![cfg(feature = "seccomp")]
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::{ArgCmp, ArgConstraint, SeccompConfig, SyscallProfile, SyscallRule};
+
+/// How a syscall made under a filter ended
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Reached,
+    Killed,
+    /// Returned the errno
+    Failed(libc::c_int),
+}
+
+/// Apply the default allowlist with `rules` in place of any others for
+/// their syscalls, then make `syscall` in a child
+fn under(rules: &[SyscallRule], syscall: impl FnOnce() + Send) -> Outcome {
+    let mut config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::default()
+    };
+    config
+        .allowed_syscalls
+        .retain(|allowed| rules.iter().all(|rule| rule.syscall != allowed.syscall));
+    config.allowed_syscalls.extend_from_slice(rules);
+    applied(config, || {
+        syscall();
+        0
+    })
+}
+
+/// Apply `config`, then make `syscall` in a child, which returns the errno
+/// it failed with or 0
+fn applied(config: SeccompConfig, syscall: impl FnOnce() -> libc::c_int + Send) -> Outcome {
+    let pid = clone_worker(0, move || {
+        config.apply()?;
+        let errno = syscall();
+        // SAFETY: Exiting the child without running anything else
+        unsafe { libc::_exit(errno) }
+    })
+    .unwrap();
+
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS {
+        Outcome::Killed
+    } else {
+        assert!(libc::WIFEXITED(status), "status {status}");
+        match libc::WEXITSTATUS(status) {
+            0 => Outcome::Reached,
+            errno => Outcome::Failed(errno),
+        }
+    }
+}
+
+fn openat(flags: libc::c_int) {
+    // SAFETY: Opening a path we own the string of; the fd dies with the child
+    unsafe {
+        libc::syscall(
+            libc::SYS_openat,
+            libc::AT_FDCWD,
+            c"/dev/null".as_ptr(),
+            flags,
+            0o600,
+        )
+    };
+}
+
+fn mmap(prot: libc::c_int, flags: libc::c_int) {
+    // SAFETY: A fresh mapping that dies with the child
+    unsafe { libc::syscall(libc::SYS_mmap, 0, 4096, prot, flags, -1, 0) };
+}
+
+fn socket(domain: libc::c_long) {
+    // SAFETY: The socket dies with the child
+    unsafe { libc::syscall(libc::SYS_socket, domain, libc::SOCK_STREAM, 0) };
+}
+
+#[test]
+fn openat_is_allowed_for_reading_only() {
+    let rule = [SyscallRule::allow_no_new_fds_openat()];
+    assert_eq!(
+        under(&rule, || openat(libc::O_RDONLY | libc::O_CLOEXEC)),
+        Outcome::Reached
+    );
+    assert_eq!(under(&rule, || openat(libc::O_WRONLY)), Outcome::Killed);
+    assert_eq!(under(&rule, || openat(libc::O_RDWR)), Outcome::Killed);
+    assert_eq!(
+        under(&rule, || openat(libc::O_RDONLY | libc::O_CREAT)),
+        Outcome::Killed
+    );
+    assert_eq!(
+        under(&rule, || openat(libc::O_RDONLY | libc::O_TRUNC)),
+        Outcome::Killed
+    );
+}
+
+#[test]
+fn mmap_is_allowed_for_anonymous_memory_only() {
+    let rule = [SyscallRule::allow_mmap_anon_only()];
+    let anonymous = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+    let rw = libc::PROT_READ | libc::PROT_WRITE;
+    assert_eq!(under(&rule, || mmap(rw, anonymous)), Outcome::Reached);
+    assert_eq!(
+        under(&rule, || mmap(rw | libc::PROT_EXEC, anonymous)),
+        Outcome::Killed
+    );
+    assert_eq!(
+        under(&rule, || mmap(rw, libc::MAP_PRIVATE)),
+        Outcome::Killed
+    );
+
+    let rule = [SyscallRule::allow_mmap_no_exec()];
+    assert_eq!(
+        under(&rule, || mmap(rw, libc::MAP_PRIVATE)),
+        Outcome::Reached
+    );
+    assert_eq!(
+        under(&rule, || mmap(libc::PROT_EXEC, anonymous)),
+        Outcome::Killed
+    );
+}
+
+#[test]
+fn socket_is_allowed_for_unix_domain_only() {
+    let rule = [SyscallRule::allow_unix_sockets()];
+    assert_eq!(
+        under(&rule, || socket(libc::AF_UNIX.into())),
+        Outcome::Reached
+    );
+    assert_eq!(
+        under(&rule, || socket(libc::AF_INET.into())),
+        Outcome::Killed
+    );
+    assert_eq!(
+        under(&rule, || socket(libc::AF_INET6.into())),
+        Outcome::Killed
+    );
+    // The kernel reads an int, so bits above it change nothing
+    assert_eq!(
+        under(&rule, || socket(
+            libc::c_long::from(libc::AF_UNIX) | (1 << 32)
+        )),
+        Outcome::Reached
+    );
+}
+
+#[test]
+fn any_matching_rule_allows_the_syscall() {
+    let unix = SyscallRule::allow_unix_sockets();
+    let netlink = SyscallRule::when(
+        libc::SYS_socket,
+        vec![ArgConstraint::int(
+            0,
+            ArgCmp::Eq(libc::AF_NETLINK.try_into().unwrap()),
+        )],
+    );
+    let rules = [unix.clone(), netlink];
+    assert_eq!(
+        under(&rules, || socket(libc::AF_UNIX.into())),
+        Outcome::Reached
+    );
+    assert_eq!(
+        under(&rules, || socket(libc::AF_NETLINK.into())),
+        Outcome::Reached
+    );
+    assert_eq!(
+        under(&rules, || socket(libc::AF_INET.into())),
+        Outcome::Killed
+    );
+
+    // An unconditional rule allows every form
+    let rules = [unix, SyscallRule::allow(libc::SYS_socket)];
+    assert_eq!(
+        under(&rules, || socket(libc::AF_INET.into())),
+        Outcome::Reached
+    );
+}
+
+#[test]
+fn range_comparisons_cover_all_64_bits() {
+    // mmap lengths past 4 GiB get past a 32 bit comparison
+    let small = [SyscallRule::when(
+        libc::SYS_mmap,
+        vec![ArgConstraint::new(1, ArgCmp::Lt(1 << 20))],
+    )];
+    let map = |len: u64| {
+        move || {
+            // SAFETY: A fresh mapping, if any, that dies with the child
+            unsafe {
+                libc::syscall(
+                    libc::SYS_mmap,
+                    0,
+                    len,
+                    libc::PROT_NONE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+        }
+    };
+    assert_eq!(under(&small, map(4096)), Outcome::Reached);
+    assert_eq!(under(&small, map(1 << 20)), Outcome::Killed);
+    assert_eq!(under(&small, map((1 << 32) + 4096)), Outcome::Killed);
+
+    let large = [SyscallRule::when(
+        libc::SYS_mmap,
+        vec![ArgConstraint::new(1, ArgCmp::Gt(1 << 20))],
+    )];
+    assert_eq!(under(&large, map(4096)), Outcome::Killed);
+    assert_eq!(under(&large, map(1 << 21)), Outcome::Reached);
+}
+
+#[test]
+fn rules_on_missing_arguments_are_rejected() {
+    let config = SeccompConfig {
+        allowed_syscalls: vec![SyscallRule::when(
+            libc::SYS_socket,
+            vec![ArgConstraint::int(6, ArgCmp::Eq(0))],
+        )],
+        ..SeccompConfig::default()
+    };
+    let error = config.run_validation(&[], &[]).unwrap_err();
+    assert!(error.to_string().contains("syscall"), "{error}");
+    assert_eq!(config.syscalls(), [libc::SYS_socket]);
+}
+
+/// `ioctl` with `request` on `/dev/null`, returning the errno it failed with
+fn ioctl(request: u64) -> libc::c_int {
+    let byte = 0u8;
+    // SAFETY: Reads one byte we own; the fd dies with the child
+    let ret = unsafe {
+        let fd = libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY);
+        libc::syscall(libc::SYS_ioctl, fd, request, &raw const byte)
+    };
+    if ret < 0 {
+        std::io::Error::last_os_error().raw_os_error().unwrap_or(-1)
+    } else {
+        0
+    }
+}
+
+#[test]
+fn the_builder_allows_socket_for_unix_domain_only() {
+    let config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::default()
+    }
+    .allow_with_args(
+        libc::SYS_socket,
+        ArgConstraint::int(0, ArgCmp::Eq(libc::AF_UNIX.try_into().unwrap())),
+    );
+    let socket = |domain: libc::c_int| {
+        move || {
+            socket(domain.into());
+            0
+        }
+    };
+    assert_eq!(
+        applied(config.clone(), socket(libc::AF_UNIX)),
+        Outcome::Reached
+    );
+    assert_eq!(applied(config, socket(libc::AF_INET)), Outcome::Killed);
+}
+
+#[test]
+fn tiocsti_is_denied_however_ioctl_is_allowed() {
+    let tiocsti = u64::from(u32::try_from(libc::TIOCSTI).unwrap());
+    let config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::default()
+    };
+    // The Python profile allows ioctl outright; the denial still wins
+    assert!(
+        config
+            .allowed_syscalls
+            .contains(&SyscallRule::allow(libc::SYS_ioctl))
+    );
+    assert_eq!(config.denied_syscalls, [SyscallRule::tiocsti()]);
+    assert_eq!(
+        applied(config.clone(), || ioctl(tiocsti)),
+        Outcome::Failed(libc::EPERM)
+    );
+    // The kernel reads an unsigned int, so bits above it change nothing
+    assert_eq!(
+        applied(config.clone(), || ioctl(tiocsti | (1 << 32))),
+        Outcome::Failed(libc::EPERM)
+    );
+    // Other requests reach the kernel, which finds /dev/null is no terminal
+    let tiocgwinsz = u64::from(u32::try_from(libc::TIOCGWINSZ).unwrap());
+    assert_eq!(
+        applied(config, || ioctl(tiocgwinsz)),
+        Outcome::Failed(libc::ENOTTY)
+    );
+
+    let unrestricted = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::with_profile(SyscallProfile::Unrestricted)
+    };
+    assert_eq!(unrestricted.denied_syscalls, []);
+    assert_eq!(
+        applied(unrestricted.clone(), || ioctl(tiocsti)),
+        Outcome::Failed(libc::ENOTTY)
+    );
+
+    // Denials added through the builder stack on the profile's
+    let fionread = u64::from(u32::try_from(libc::FIONREAD).unwrap());
+    let config =
+        unrestricted.deny_with_args(libc::SYS_ioctl, ArgConstraint::int(1, ArgCmp::Eq(fionread)));
+    assert_eq!(
+        applied(config.clone(), || ioctl(fionread)),
+        Outcome::Failed(libc::EPERM)
+    );
+    assert_eq!(
+        applied(config, || ioctl(tiocsti)),
+        Outcome::Failed(libc::ENOTTY)
+    );
+}
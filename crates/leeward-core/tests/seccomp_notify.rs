@@ -0,0 +1,101 @@
+//! A listener receives the real syscall a process is blocked in and its
+//! answer is what the process sees, unless the process is gone by then
+
+#![cfg(feature = "seccomp")]
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::seccomp::{SeccompNotifyFd, SeccompResponse};
+use leeward_core::isolation::{SeccompConfig, SyscallRule};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+
+/// Clone a child that calls `socket(AF_INET, SOCK_STREAM)` under a filter
+/// sending it to the returned listener, and exits with 0 if the call
+/// failed with `EACCES`
+fn blocked_socket() -> (libc::pid_t, SeccompNotifyFd) {
+    let mut config = SeccompConfig {
+        log_denials: false,
+        notify_denials: true,
+        ..SeccompConfig::default()
+    };
+    // The listener is handed over under the filter
+    config
+        .allowed_syscalls
+        .push(SyscallRule::allow(libc::SYS_sendmsg));
+
+    let (parent, child) = UnixStream::pair().unwrap();
+    let pid = clone_worker(0, move || {
+        let listener = config.apply()?.expect("no listener");
+        leeward_core::socket::send_with_fds(child.as_fd(), b"l", &[listener.as_fd()])?;
+        drop(listener);
+        // SAFETY: Probing a syscall, then exiting with whether it was refused
+        unsafe {
+            let ret = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+            let refused = ret == -1 && *libc::__errno_location() == libc::EACCES;
+            libc::_exit(i32::from(!refused))
+        }
+    })
+    .unwrap();
+
+    let (_, mut fds) = leeward_core::socket::recv_with_fds(parent.as_fd(), &mut [0u8; 1]).unwrap();
+    (
+        pid,
+        SeccompNotifyFd::from(fds.pop().expect("no listener sent")),
+    )
+}
+
+fn wait(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    status
+}
+
+#[test]
+fn a_socket_denied_from_the_parent_fails_with_eacces() {
+    let (pid, listener) = blocked_socket();
+
+    let notification = listener
+        .wait_notification()
+        .unwrap()
+        .expect("no notification");
+    assert_eq!(notification.pid, u32::try_from(pid).unwrap());
+    assert_eq!(notification.syscall, libc::SYS_socket);
+    assert_eq!(notification.args[0], libc::AF_INET as u64);
+    assert_eq!(notification.args[1] & 0xff, libc::SOCK_STREAM as u64);
+    assert!(listener.id_valid(notification.id));
+
+    assert!(
+        listener
+            .send_response(&notification, SeccompResponse::DenyWithEacces)
+            .unwrap()
+    );
+    let status = wait(pid);
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "status {status}"
+    );
+    // No process is left under the filter
+    assert!(listener.wait_notification().unwrap().is_none());
+}
+
+#[test]
+fn answering_a_process_that_died_is_skipped_not_failed() {
+    let (pid, listener) = blocked_socket();
+    let notification = listener
+        .wait_notification()
+        .unwrap()
+        .expect("no notification");
+
+    // SAFETY: Killing our own child
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGKILL) }, 0);
+    let status = wait(pid);
+    assert!(libc::WIFSIGNALED(status), "status {status}");
+
+    assert!(!listener.id_valid(notification.id));
+    assert!(
+        !listener
+            .send_response(&notification, SeccompResponse::DenyWithEacces)
+            .unwrap()
+    );
+}
@@ -0,0 +1,222 @@
+//! Syscall profiles each allow what their workloads need, checked against
+//! a real interpreter under the filter
+
+#![cfg(feature = "seccomp")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::seccomp::DEBUGGING_SYSCALLS;
+use leeward_core::isolation::{SeccompConfig, SyscallProfile};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+
+/// How a process under a filter ended
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Exited(i32),
+    /// Killed for a syscall the filter does not allow
+    Killed,
+}
+
+/// Run the interpreter on `code` under `profile`, or `None` if there is no
+/// interpreter here
+fn python_under(profile: SyscallProfile, code: &str) -> Option<Outcome> {
+    let python = SandboxConfig::default().python_path;
+    if !python.exists() {
+        eprintln!("skipping: no interpreter at {}", python.display());
+        return None;
+    }
+    let config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::with_profile(profile)
+    };
+    let path = CString::new(python.as_os_str().as_bytes()).unwrap();
+    let args = [
+        path.clone(),
+        CString::new("-c").unwrap(),
+        CString::new(code).unwrap(),
+    ];
+
+    let pid = clone_worker(0, move || {
+        config.apply()?;
+        let pointers: Vec<_> = args
+            .iter()
+            .map(|arg| arg.as_ptr())
+            .chain([std::ptr::null()])
+            .collect();
+        // SAFETY: The pointers are null-terminated and outlives the call, which only
+        // returns on failure
+        unsafe {
+            libc::execv(path.as_ptr(), pointers.as_ptr());
+            libc::_exit(127)
+        }
+    })
+    .unwrap();
+
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    if libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSYS {
+        Some(Outcome::Killed)
+    } else {
+        assert!(libc::WIFEXITED(status), "status {status}");
+        Some(Outcome::Exited(libc::WEXITSTATUS(status)))
+    }
+}
+
+/// Whether the interpreter here can import `module` at all
+fn importable(module: &str) -> bool {
+    std::process::Command::new(SandboxConfig::default().python_path)
+        .args(["-c", &format!("import {module}")])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn syscalls(profile: SyscallProfile) -> Vec<i64> {
+    SeccompConfig::with_profile(profile).syscalls()
+}
+
+#[test]
+fn profiles_are_named_and_versioned() {
+    let names: Vec<_> = SyscallProfile::ALL
+        .iter()
+        .map(|profile| profile.name())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "minimal-io",
+            "python",
+            "data-science",
+            "networking",
+            "unrestricted"
+        ]
+    );
+    assert_eq!(SyscallProfile::Python.to_string(), "python/2");
+    assert!(
+        SyscallProfile::ALL
+            .iter()
+            .all(|profile| !profile.version().is_empty())
+    );
+}
+
+#[test]
+fn profiles_build_on_python() {
+    let python = syscalls(SyscallProfile::Python);
+    for wider in [
+        SyscallProfile::DataScience,
+        SyscallProfile::Networking,
+        SyscallProfile::Unrestricted,
+    ] {
+        let wider = syscalls(wider);
+        assert!(python.iter().all(|syscall| wider.contains(syscall)));
+    }
+    for syscall in [
+        libc::SYS_getrandom,
+        libc::SYS_clone,
+        libc::SYS_set_robust_list,
+        libc::SYS_sigaltstack,
+    ] {
+        assert!(python.contains(&syscall), "{syscall}");
+    }
+    assert!(!python.contains(&libc::SYS_socket));
+    assert!(syscalls(SyscallProfile::Networking).contains(&libc::SYS_socket));
+
+    let data = syscalls(SyscallProfile::DataScience);
+    assert!(data.contains(&libc::SYS_memfd_create));
+    assert!(data.contains(&libc::SYS_fadvise64));
+
+    let minimal = syscalls(SyscallProfile::MinimalIO);
+    assert!(minimal.len() < python.len());
+    assert!(!minimal.contains(&libc::SYS_execve));
+
+    // The default config is the Python profile
+    assert_eq!(SeccompConfig::default().syscalls(), python);
+    assert_eq!(SeccompConfig::default().enosys_syscalls, [libc::SYS_clone3]);
+}
+
+#[test]
+fn unrestricted_still_refuses_debugging() {
+    let config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::with_profile(SyscallProfile::Unrestricted)
+    };
+    let unrestricted = config.syscalls();
+    assert!(
+        [libc::SYS_mount, libc::SYS_bpf, libc::SYS_socket]
+            .iter()
+            .all(|syscall| unrestricted.contains(syscall))
+    );
+
+    let report = config
+        .run_validation(&[libc::SYS_mount, libc::SYS_socket], &[])
+        .unwrap();
+    assert!(report.is_clean(), "{report:?}");
+
+    // The stacked debugging filter still fails them, with EPERM
+    let pid = clone_worker(0, move || {
+        config.apply()?;
+        // SAFETY: Probing a syscall with zeroed arguments, then exiting
+        unsafe {
+            let ret = libc::syscall(DEBUGGING_SYSCALLS[0], 0, 0, 0, 0);
+            let refused = ret == -1 && *libc::__errno_location() == libc::EPERM;
+            libc::_exit(i32::from(!refused))
+        }
+    })
+    .unwrap();
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "status {status}"
+    );
+}
+
+#[test]
+fn python_runs_threads_and_subprocesses_under_its_profile() {
+    let code = "\
+import json, os, subprocess, sys, tempfile, threading, time
+t = threading.Thread(target=time.sleep, args=(0.01,))
+t.start()
+t.join()
+with tempfile.TemporaryDirectory() as d:
+    with open(os.path.join(d, 'x'), 'w') as f:
+        json.dump({'cwd': os.getcwd()}, f)
+    os.rename(os.path.join(d, 'x'), os.path.join(d, 'y'))
+subprocess.run([sys.executable, '-c', 'pass'], check=True)
+";
+    let Some(outcome) = python_under(SyscallProfile::Python, code) else {
+        return;
+    };
+    assert_eq!(outcome, Outcome::Exited(0));
+}
+
+#[test]
+fn python_is_killed_for_a_socket_under_its_profile() {
+    let Some(outcome) = python_under(SyscallProfile::Python, "import socket; socket.socket()")
+    else {
+        return;
+    };
+    assert_eq!(outcome, Outcome::Killed);
+
+    let networking = python_under(
+        SyscallProfile::Networking,
+        "import socket; socket.socket().close()",
+    );
+    assert_eq!(networking, Some(Outcome::Exited(0)));
+}
+
+#[test]
+fn numpy_imports_under_the_data_science_profile() {
+    if !importable("numpy") {
+        eprintln!("skipping: numpy is not installed");
+        return;
+    }
+    let code = "import numpy; numpy.memmap; numpy.arange(1 << 20).sum()";
+    assert_eq!(
+        python_under(SyscallProfile::DataScience, code),
+        Some(Outcome::Exited(0))
+    );
+}
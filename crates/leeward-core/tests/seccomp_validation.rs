@@ -0,0 +1,51 @@
+//! Validate that seccomp filters allow and block the syscalls we expect
+//!
+//! Run with `cargo test -p leeward-core --features seccomp_validation`.
+
+#![cfg(feature = "seccomp_validation")]
+
+use leeward_core::isolation::SeccompConfig;
+
+/// Syscalls that must never reach the kernel from a sandboxed worker
+const BLOCKED: &[i64] = &[
+    libc::SYS_socket,
+    libc::SYS_ptrace,
+    libc::SYS_mount,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_bpf,
+];
+
+#[test]
+fn default_python_allowlist_is_enforced() {
+    let config = SeccompConfig {
+        log_denials: false,
+        ..SeccompConfig::default()
+    };
+
+    let report = config
+        .run_validation(&config.syscalls(), BLOCKED)
+        .expect("validation should run");
+
+    assert!(
+        report.unexpected_blocks.is_empty(),
+        "allowlisted syscalls were blocked: {:?}",
+        report.unexpected_blocks
+    );
+    assert!(
+        report.failed.is_empty(),
+        "blocked syscalls got through: {:?}",
+        report.failed
+    );
+    assert_eq!(report.passed.len(), config.syscalls().len() + BLOCKED.len());
+}
+
+#[test]
+fn empty_expectations_produce_clean_report() {
+    let report = SeccompConfig::default()
+        .run_validation(&[], &[])
+        .expect("validation should run");
+
+    assert!(report.is_clean());
+    assert_eq!(report.passed, [] as [i64; 0]);
+}
@@ -0,0 +1,104 @@
+//! The embedder-driven notification stream, through the `secret_guard`
+//! example and directly
+
+#![cfg(feature = "seccomp")]
+
+mod common;
+
+use leeward_core::isolation::seccomp::{self, SeccompResponse};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Exit code of the example when user notifications are unavailable
+const EXIT_UNSUPPORTED: i32 = 77;
+
+#[test]
+fn example_blocks_secret_writes() {
+    // Test binaries live in target/<profile>/deps, examples in target/<profile>/examples
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+    let profile = if profile_dir.ends_with("release") {
+        "release"
+    } else {
+        "dev"
+    };
+
+    let built = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "-p",
+            "leeward-core",
+            "--example",
+            "secret_guard",
+            "--profile",
+            profile,
+        ])
+        .status()
+        .unwrap();
+    assert!(built.success(), "failed to build the secret_guard example");
+
+    let dir = common::Scratch::new("secret-guard");
+    let output = Command::new(profile_dir.join("examples/secret_guard"))
+        .arg(&*dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    let notes = std::fs::read_to_string(dir.join("notes.txt"));
+    let secret_exists = dir.join("secret.txt").exists();
+
+    if output.status.code() == Some(EXIT_UNSUPPORTED) {
+        eprintln!("skipping: {stderr}");
+        return;
+    }
+
+    assert_eq!(
+        notes.unwrap(),
+        "ok\n",
+        "ordinary write was blocked: {stderr}"
+    );
+    assert!(!secret_exists, "secret write got through: {stderr}");
+    assert!(
+        stderr.contains("denied write to secret.txt"),
+        "unexpected stderr: {stderr}"
+    );
+    assert!(
+        !output.status.success(),
+        "the shell should report the failed redirect"
+    );
+}
+
+#[test]
+fn process_dying_mid_decision_is_reported() {
+    let dir = common::Scratch::new("notify-lifetime");
+    let mut command = Command::new("/bin/sh");
+    command
+        .args(["-c", "echo x > file"])
+        .current_dir(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    let Ok((mut child, mut stream)) = seccomp::spawn_supervised(&mut command, &[libc::SYS_openat])
+    else {
+        eprintln!("skipping: seccomp user notifications unavailable");
+        return;
+    };
+
+    let pending = stream.next().expect("no notification").unwrap();
+    assert!(pending.is_valid());
+    assert_eq!(pending.pid, child.id());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(!pending.is_valid());
+    assert!(
+        pending.read_mem(pending.args[1], 16).is_err(),
+        "read memory of a dead process"
+    );
+    assert!(
+        !pending.respond(SeccompResponse::Allow).unwrap(),
+        "answered a dead process"
+    );
+    assert!(stream.next().is_none(), "stream outlived the process");
+}
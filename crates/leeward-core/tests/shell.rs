@@ -0,0 +1,130 @@
+//! Shell interpreters run code with `-c`, arguments as `$1`, `$2`, ...,
+//! and are confined by the same sandbox as Python
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::{self, Request, RequestBuilder};
+use leeward_core::worker::{ExecuteOptions, Worker, run_python};
+use std::path::Path;
+
+fn shell(interpreter: Interpreter) -> ExecuteOptions {
+    ExecuteOptions {
+        interpreter,
+        ..ExecuteOptions::default()
+    }
+}
+
+#[test]
+fn exit_status_and_output_pass_through() {
+    let result = run_python(
+        "echo hi; exit 3",
+        &SandboxConfig::default(),
+        &shell(Interpreter::Sh),
+    )
+    .unwrap();
+    assert_eq!(result.stdout_str(), "hi\n");
+    assert_eq!(result.exit_code, 3);
+}
+
+#[test]
+fn arguments_and_stdin_reach_the_script() {
+    let options = ExecuteOptions {
+        args: vec!["one".into(), "two words".into()],
+        stdin: Some(b"piped\n".to_vec()),
+        ..shell(Interpreter::Sh)
+    };
+    let result = run_python(
+        r#"echo "$#:$1:$2"; read line; echo "$line""#,
+        &SandboxConfig::default(),
+        &options,
+    )
+    .unwrap();
+    assert_eq!(result.exit_code, 0, "{}", result.stderr_str());
+    assert_eq!(result.stdout_str(), "2:one:two words\npiped\n");
+
+    // Python sees the same arguments in sys.argv
+    let options = ExecuteOptions {
+        args: vec!["one".into()],
+        ..ExecuteOptions::default()
+    };
+    let result = run_python(
+        "import sys; print(sys.argv[1:])",
+        &SandboxConfig::default(),
+        &options,
+    )
+    .unwrap();
+    assert_eq!(
+        result.stdout_str().trim(),
+        "['one']",
+        "{}",
+        result.stderr_str()
+    );
+}
+
+#[test]
+fn bash_is_selectable() {
+    if !Path::new("/bin/bash").exists() {
+        eprintln!("skipping: no /bin/bash");
+        return;
+    }
+    let result = run_python(
+        r#"echo "${BASH_VERSION:+bash}""#,
+        &SandboxConfig::default(),
+        &shell(Interpreter::Bash),
+    )
+    .unwrap();
+    assert_eq!(result.stdout_str(), "bash\n");
+}
+
+#[test]
+fn requests_default_to_python() {
+    let request = RequestBuilder::new("echo $1")
+        .interpreter(Interpreter::Sh)
+        .arg("x")
+        .build()
+        .unwrap();
+    assert_eq!(request.interpreter, Interpreter::Sh);
+    assert_eq!(request.args, ["x"]);
+
+    // Clients that predate interpreters send neither field
+    let request: Request =
+        protocol::decode_json(br#"{"type": "Execute", "code": "print(1)", "files": []}"#).unwrap();
+    let Request::Execute(request) = request else {
+        panic!("decoded {request:?}");
+    };
+    assert_eq!(request.interpreter, Interpreter::Python);
+    assert_eq!(request.args, Vec::<String>::new());
+}
+
+#[test]
+fn network_is_denied_by_default() {
+    if !Path::new("/usr/bin/curl").exists() {
+        eprintln!("skipping: no curl");
+        return;
+    }
+    let mut worker = Worker::new(0, SandboxConfig::default());
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let result = worker.execute(
+        "curl -sS --max-time 5 http://1.1.1.1/",
+        &shell(Interpreter::Sh),
+    );
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let result = result.unwrap();
+    if result.stderr_str().starts_with("Failed to execute") {
+        eprintln!("skipping, execution fails here: {}", result.stderr_str());
+        return;
+    }
+    assert_ne!(result.exit_code, 0, "{}", result.stdout_str());
+}
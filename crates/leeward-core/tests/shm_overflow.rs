@@ -0,0 +1,189 @@
+//! Output that does not fit its response slot fails, is truncated or goes
+//! through the socket, byte for byte, as the overflow policy says
+
+#![cfg(all(feature = "shm", feature = "protocol"))]
+
+use leeward_core::ExecutionResult;
+use leeward_core::protocol::{PayloadRef, ShmOverflowPolicy, ShmPayload};
+use leeward_core::shm::{
+    MappedSharedMemory, OutputOverflow, RESPONSE_CAPACITY, SharedMemoryRegion, pack_output,
+};
+
+const CAPACITY: usize = 100;
+
+fn output(stdout: usize, stderr: usize) -> ExecutionResult {
+    ExecutionResult {
+        stdout: vec![b'o'; stdout],
+        stderr: vec![b'e'; stderr],
+        ..ExecutionResult::default()
+    }
+}
+
+#[test]
+fn output_exactly_filling_the_slot_fits_under_every_policy() {
+    for policy in ShmOverflowPolicy::ALL {
+        let mut result = output(60, 40);
+        let packed = pack_output(&mut result, CAPACITY, policy).unwrap();
+        assert_eq!(
+            packed.slot,
+            [vec![b'o'; 60], vec![b'e'; 40]].concat(),
+            "{policy}"
+        );
+        assert_eq!(
+            packed.payload,
+            ShmPayload {
+                stdout: PayloadRef::Slot { offset: 0, len: 60 },
+                stderr: PayloadRef::Slot {
+                    offset: 60,
+                    len: 40
+                },
+            }
+        );
+        assert_eq!(packed.adjustment, None);
+        assert!(result.stdout.is_empty() && result.stderr.is_empty());
+        assert!(!result.stdout_truncated && !result.stderr_truncated);
+    }
+}
+
+#[test]
+fn one_byte_over_fails_the_request() {
+    let mut result = output(60, 41);
+    let overflow = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::FailRequest).unwrap_err();
+    assert_eq!(
+        overflow,
+        OutputOverflow {
+            len: 101,
+            capacity: 100
+        }
+    );
+    assert_eq!(
+        overflow.to_string(),
+        "output of 101 bytes does not fit the 100 byte response slot"
+    );
+
+    let adjustment = overflow.adjustment();
+    assert_eq!(adjustment.field, "shm_overflow");
+    assert_eq!(adjustment.requested, "fail_request");
+    assert_eq!(adjustment.applied, "failed");
+    // Nothing was moved or cut
+    assert_eq!((result.stdout.len(), result.stderr.len()), (60, 41));
+}
+
+#[test]
+fn one_byte_over_is_truncated_from_stderr_and_flagged() {
+    let mut result = output(60, 41);
+    let packed = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::TruncateWithFlag).unwrap();
+    assert_eq!(packed.slot, [vec![b'o'; 60], vec![b'e'; 40]].concat());
+    assert_eq!(
+        packed.payload.stderr,
+        PayloadRef::Slot {
+            offset: 60,
+            len: 40
+        }
+    );
+    assert!(!result.stdout_truncated);
+    assert!(result.stderr_truncated);
+
+    let adjustment = packed.adjustment.unwrap();
+    assert_eq!(adjustment.requested, "truncate_with_flag");
+    assert_eq!(
+        adjustment.applied,
+        "kept 60 of 60 stdout and 40 of 41 stderr bytes"
+    );
+    assert_eq!(
+        adjustment.reason,
+        "output of 101 bytes does not fit the 100 byte response slot"
+    );
+
+    let mut result = output(101, 5);
+    let packed = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::TruncateWithFlag).unwrap();
+    assert_eq!(packed.slot, vec![b'o'; 100]);
+    assert_eq!(
+        packed.payload,
+        ShmPayload {
+            stdout: PayloadRef::Slot {
+                offset: 0,
+                len: 100
+            },
+            stderr: PayloadRef::Slot {
+                offset: 100,
+                len: 0
+            },
+        }
+    );
+    assert!(result.stdout_truncated && result.stderr_truncated);
+}
+
+#[test]
+fn one_byte_over_falls_back_to_the_socket() {
+    let mut result = output(60, 41);
+    let packed = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::FallbackToSocket).unwrap();
+    assert_eq!(packed.slot, vec![b'o'; 60]);
+    assert_eq!(
+        packed.payload,
+        ShmPayload {
+            stdout: PayloadRef::Slot { offset: 0, len: 60 },
+            stderr: PayloadRef::Inline,
+        }
+    );
+    assert_eq!(result.stdout, [] as [u8; 0]);
+    assert_eq!(result.stderr, vec![b'e'; 41]);
+    assert!(!result.stdout_truncated && !result.stderr_truncated);
+    assert_eq!(packed.adjustment.unwrap().applied, "stderr via socket");
+
+    let mut result = output(101, 100);
+    let packed = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::FallbackToSocket).unwrap();
+    assert_eq!(packed.slot, vec![b'e'; 100]);
+    assert_eq!(packed.payload.stdout, PayloadRef::Inline);
+    assert_eq!(
+        packed.payload.stderr,
+        PayloadRef::Slot {
+            offset: 0,
+            len: 100
+        }
+    );
+    assert_eq!(result.stdout.len(), 101);
+    assert_eq!(packed.adjustment.unwrap().applied, "stdout via socket");
+
+    let mut result = output(101, 101);
+    let packed = pack_output(&mut result, CAPACITY, ShmOverflowPolicy::FallbackToSocket).unwrap();
+    assert_eq!(packed.slot, [] as [u8; 0]);
+    assert_eq!((result.stdout.len(), result.stderr.len()), (101, 101));
+    assert_eq!(
+        packed.adjustment.unwrap().applied,
+        "stdout and stderr via socket"
+    );
+}
+
+#[test]
+fn output_comes_back_whole_through_the_slot() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let mapped = MappedSharedMemory::new(region.as_raw_fd(), false).unwrap();
+    let slot = region.allocate_slot(1).unwrap();
+
+    let mut result = ExecutionResult {
+        stdout: b"hello\n".to_vec(),
+        stderr: vec![b'e'; RESPONSE_CAPACITY],
+        ..ExecutionResult::default()
+    };
+    let packed = pack_output(
+        &mut result,
+        RESPONSE_CAPACITY,
+        ShmOverflowPolicy::FallbackToSocket,
+    )
+    .unwrap();
+    mapped.write_response(&slot, &packed.slot).unwrap();
+
+    let read = mapped.read_response(&slot).unwrap();
+    assert_eq!(read, b"hello\n");
+    assert!(packed.payload.restore(&read, &mut result));
+    assert_eq!(result.stdout, b"hello\n");
+    assert_eq!(result.stderr.len(), RESPONSE_CAPACITY);
+
+    let past = ShmPayload {
+        stdout: PayloadRef::Slot { offset: 4, len: 3 },
+        stderr: PayloadRef::Inline,
+    };
+    assert!(!past.restore(&read, &mut result));
+    assert_eq!(result.stdout, b"hello\n");
+}
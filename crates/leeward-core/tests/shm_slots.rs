@@ -0,0 +1,88 @@
+//! Shared memory slots are leased to one owner at a time, and a lease that
+//! was freed, released or reclaimed is refused by its generation
+
+#![cfg(feature = "shm")]
+
+use leeward_core::shm::{LeaseError, MAX_SLOTS, MappedSharedMemory, SharedMemoryRegion};
+use std::time::Duration;
+
+#[test]
+fn slots_run_out_and_come_back_with_a_new_generation() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let leases: Vec<_> = (0..MAX_SLOTS)
+        .map(|_| region.allocate_slot(1).unwrap())
+        .collect();
+    assert_eq!(region.leased(), MAX_SLOTS);
+    assert!(region.allocate_slot(2).is_err());
+
+    let old = &leases[5];
+    region.free_slot(1, old).unwrap();
+    let new = region.allocate_slot(2).unwrap();
+    assert_eq!(new.slot_id, old.slot_id);
+    assert_ne!(new.generation, old.generation);
+
+    assert_eq!(
+        region.check(1, old),
+        Err(LeaseError::GenerationMismatch {
+            presented: old.generation,
+            current: new.generation,
+        })
+    );
+    assert_eq!(region.free_slot(1, old), region.check(1, old));
+    assert_eq!(region.check(1, &new), Err(LeaseError::NotOwner));
+    assert_eq!(region.check(2, &new), Ok(()));
+    assert!(
+        region
+            .slot_pair(u32::try_from(MAX_SLOTS).unwrap(), 0)
+            .is_none()
+    );
+}
+
+#[test]
+fn an_owner_gone_gives_back_only_its_own_slots() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let mine = [
+        region.allocate_slot(1).unwrap(),
+        region.allocate_slot(1).unwrap(),
+    ];
+    let theirs = region.allocate_slot(2).unwrap();
+
+    assert_eq!(region.release_owner(1), 2);
+    assert_eq!(region.release_owner(1), 0);
+    assert_eq!(region.leased(), 1);
+    for slot in &mine {
+        assert!(matches!(
+            region.check(1, slot),
+            Err(LeaseError::GenerationMismatch { .. })
+        ));
+    }
+    assert_eq!(region.check(2, &theirs), Ok(()));
+}
+
+#[test]
+fn idle_slots_are_reclaimed_and_used_ones_kept() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let idle = region.allocate_slot(1).unwrap();
+    let used = region.allocate_slot(1).unwrap();
+
+    std::thread::sleep(Duration::from_millis(60));
+    region.check(1, &used).unwrap();
+    assert_eq!(region.reclaim_idle(Duration::from_millis(50)), 1);
+    assert!(matches!(
+        region.check(1, &idle),
+        Err(LeaseError::GenerationMismatch { .. })
+    ));
+    assert_eq!(region.check(1, &used), Ok(()));
+}
+
+#[test]
+fn code_written_to_a_slot_reads_back_whole() {
+    let region = SharedMemoryRegion::new().unwrap();
+    let slot = region.allocate_slot(1).unwrap();
+    let client = MappedSharedMemory::new(region.as_raw_fd(), false).unwrap();
+    let daemon = MappedSharedMemory::new(region.as_raw_fd(), true).unwrap();
+
+    client.write_request(&slot, b"print('hi')").unwrap();
+    assert_eq!(daemon.read_request(&slot).unwrap(), b"print('hi')");
+    assert!(client.write_request(&slot, &vec![b'x'; 64 * 1024]).is_err());
+}
@@ -0,0 +1,83 @@
+//! Socket paths too long for a Unix socket address are bound through their
+//! directory, or refused with a clear error, and `@` names are abstract
+
+use leeward_core::LeewardError;
+use leeward_core::socket::{self, SUN_PATH_MAX, check_socket_path, is_abstract};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A fresh directory whose path alone is longer than a socket address
+fn deep_dir(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("leeward-socket-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let dir = root.join("d".repeat(60)).join("e".repeat(60));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(dir.as_os_str().len() > SUN_PATH_MAX);
+    dir
+}
+
+fn config_error(path: &Path) -> String {
+    match check_socket_path(path) {
+        Err(LeewardError::Config(message)) => message,
+        other => panic!("{} was accepted: {other:?}", path.display()),
+    }
+}
+
+#[test]
+fn over_length_path_suggests_an_abstract_name() {
+    let path = Path::new("/nonexistent")
+        .join("x".repeat(SUN_PATH_MAX))
+        .join("leeward.sock");
+    let message = config_error(&path);
+    assert!(
+        message.contains(&format!("is {} bytes", path.as_os_str().len())),
+        "{message}"
+    );
+    assert!(
+        message.contains("abstract socket name such as @leeward"),
+        "{message}"
+    );
+    assert!(message.contains("cannot be opened"), "{message}");
+    assert!(socket::bind(&path).is_err());
+
+    // Even through its directory, a name this long cannot fit
+    let dir = deep_dir("name");
+    let message = config_error(&dir.join("n".repeat(SUN_PATH_MAX)));
+    assert!(message.contains("@leeward"), "{message}");
+    let _ = std::fs::remove_dir_all(dir.parent().unwrap().parent().unwrap());
+}
+
+#[test]
+fn long_path_binds_through_its_directory() {
+    let dir = deep_dir("bind");
+    let path = dir.join("leeward.sock");
+    check_socket_path(&path).unwrap();
+
+    let listener = socket::bind(&path).unwrap();
+    assert!(path.exists(), "socket file was not created in place");
+    let mut client = socket::connect(&path).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    client.write_all(b"x").unwrap();
+    let mut byte = [0u8; 1];
+    server.read_exact(&mut byte).unwrap();
+    assert_eq!(&byte, b"x");
+
+    let _ = std::fs::remove_dir_all(dir.parent().unwrap().parent().unwrap());
+}
+
+#[test]
+fn abstract_names_need_no_file() {
+    let path = PathBuf::from(format!("@leeward-socket-test-{}", std::process::id()));
+    assert!(is_abstract(&path));
+    assert!(!is_abstract(Path::new("/run/leeward/leeward.sock")));
+    assert_eq!(socket::unreliable_filesystem(&path), None);
+
+    let listener = socket::bind(&path).unwrap();
+    let _client = socket::connect(&path).unwrap();
+    listener.accept().unwrap();
+    assert!(!Path::new(&path).exists());
+
+    assert!(config_error(Path::new("@")).contains("1 to"));
+    let long = PathBuf::from(format!("@{}", "a".repeat(SUN_PATH_MAX + 1)));
+    assert!(config_error(&long).contains("1 to"));
+}
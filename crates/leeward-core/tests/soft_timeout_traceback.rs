@@ -0,0 +1,54 @@
+//! A soft-timeout traceback shows where the code was stuck before the hard kill
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::SandboxConfig;
+use leeward_core::worker::{ExecuteOptions, run_python};
+use std::time::Duration;
+
+const LOOPING_CODE: &str = "ready = True\nwhile ready: pass\n";
+
+#[test]
+fn traceback_names_the_looping_line() {
+    let config = SandboxConfig::builder()
+        .timeout(Duration::from_secs(3))
+        .traceback_margin(Duration::from_millis(1500))
+        .build();
+    let options = ExecuteOptions {
+        soft_timeout_traceback: true,
+        ..ExecuteOptions::default()
+    };
+
+    let result = run_python(LOOPING_CODE, &config, &options).unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if stderr.starts_with("Failed to execute Python") {
+        eprintln!("skipping: {stderr}");
+        return;
+    }
+
+    assert!(result.timed_out, "loop was not killed at the timeout");
+    assert!(
+        stderr.contains("File \"<string>\", line 2"),
+        "traceback does not name the looping line:\n{stderr}"
+    );
+}
+
+#[test]
+fn no_traceback_unless_requested() {
+    let config = SandboxConfig::builder()
+        .timeout(Duration::from_secs(1))
+        .build();
+
+    let result = run_python(LOOPING_CODE, &config, &ExecuteOptions::default()).unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if stderr.starts_with("Failed to execute Python") {
+        eprintln!("skipping: {stderr}");
+        return;
+    }
+
+    assert!(result.timed_out, "loop was not killed at the timeout");
+    assert!(
+        !stderr.contains("<string>"),
+        "unexpected traceback:\n{stderr}"
+    );
+}
@@ -0,0 +1,115 @@
+//! Code saved on any platform normalizes to the same source and hash
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::RequestBuilder;
+use leeward_core::source::{self, Normalization};
+
+const LF: &str = "import sys\nprint('hi')\n";
+
+fn normalized(code: &str, interpreter: Interpreter) -> (String, Normalization) {
+    let mut code = code.to_owned();
+    let normalization = source::normalize(&mut code, interpreter);
+    (code, normalization)
+}
+
+#[test]
+fn clean_code_is_left_alone() {
+    let (code, normalization) = normalized(LF, Interpreter::Python);
+    assert_eq!(code, LF);
+    assert!(normalization.is_empty());
+}
+
+#[test]
+fn byte_order_marks_and_crlf_are_removed() {
+    let (code, normalization) =
+        normalized("\u{feff}import sys\r\nprint('hi')\r\n", Interpreter::Python);
+    assert_eq!(code, LF);
+    assert_eq!(
+        normalization,
+        Normalization {
+            bom: true,
+            crlf_lines: 2,
+            encoding: None,
+        }
+    );
+
+    // A lone carriage return is not a line ending
+    let (code, _) = normalized("print('a\rb')\r\n", Interpreter::Sh);
+    assert_eq!(code, "print('a\rb')\n");
+}
+
+#[test]
+fn foreign_encoding_declarations_are_blanked_keeping_line_numbers() {
+    for (declared, expected) in [
+        ("# -*- coding: latin-1 -*-\nprint(1)\n", "\nprint(1)\n"),
+        (
+            "#!/usr/bin/env python\n# vim: set fileencoding=cp1252 :\nprint(1)\n",
+            "#!/usr/bin/env python\n\nprint(1)\n",
+        ),
+        ("\n#coding=iso-8859-15\nprint(1)", "\n\nprint(1)"),
+    ] {
+        let (code, normalization) = normalized(declared, Interpreter::Python);
+        assert_eq!(code, expected, "{declared:?}");
+        assert!(normalization.encoding.is_some(), "{declared:?}");
+    }
+    assert_eq!(
+        normalized("# coding: latin-1\n", Interpreter::Python)
+            .1
+            .encoding
+            .as_deref(),
+        Some("latin-1")
+    );
+}
+
+#[test]
+fn declarations_python_would_not_read_are_kept() {
+    for kept in [
+        // UTF-8 under any spelling is what the code already is
+        "# -*- coding: utf-8 -*-\nprint(1)\n",
+        "# coding=UTF_8\nprint(1)\n",
+        "# coding: utf-8-sig\nprint(1)\n",
+        // Only the first two lines count, and the second only after a comment
+        "import sys\n# coding: latin-1\n",
+        "print(1)\n\n# coding: latin-1\n",
+        // Not a comment
+        "s = '# coding: latin-1'\n",
+    ] {
+        let (code, normalization) = normalized(kept, Interpreter::Python);
+        assert_eq!(code, kept);
+        assert!(normalization.is_empty(), "{kept:?}");
+    }
+
+    // Shells have no such declaration
+    let shell = "# coding: latin-1\necho hi\n";
+    assert_eq!(normalized(shell, Interpreter::Sh).0, shell);
+}
+
+#[test]
+fn sources_hash_alike_across_platforms() {
+    let unix = RequestBuilder::from_source(LF.to_owned()).build().unwrap();
+    for variant in [
+        "import sys\r\nprint('hi')\r\n",
+        "\u{feff}import sys\nprint('hi')\n",
+        "\u{feff}import sys\r\nprint('hi')\r\n",
+    ] {
+        let request = RequestBuilder::from_source(variant.to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(request.code, unix.code, "{variant:?}");
+        assert_eq!(request.code_hash, unix.code_hash, "{variant:?}");
+    }
+}
+
+#[test]
+fn nul_bytes_are_refused() {
+    assert_eq!(source::find_nul("print(1)\0"), Some(8));
+    assert_eq!(source::find_nul(LF), None);
+
+    let error = RequestBuilder::new("print(1)\0").build().unwrap_err();
+    assert!(
+        error.to_string().contains("NUL byte at offset 8"),
+        "{error}"
+    );
+}
@@ -0,0 +1,141 @@
+//! A missing bind or Landlock path is skipped by default, and fails the
+//! mounts, the ruleset, the template and the worker when strict
+
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::{MountConfig, RootTemplate};
+use leeward_core::{LeewardError, SandboxConfig};
+use nix::sched::CloneFlags;
+use std::path::PathBuf;
+
+/// A path nothing is at, named for this process, so not for a forked child
+fn missing() -> PathBuf {
+    std::env::temp_dir().join(format!("leeward-strict-missing-{}", std::process::id()))
+}
+
+/// Whether `result` failed naming the missing path
+fn names_missing<T>(result: &leeward_core::Result<T>) -> bool {
+    result
+        .as_ref()
+        .is_err_and(|e| e.to_string().contains(&*missing().to_string_lossy()))
+}
+
+/// Apply `mounts` in a new mount namespace, and say whether that worked
+fn mounts_apply(mounts: &MountConfig) -> bool {
+    let pid = clone_worker(0, || {
+        nix::sched::unshare(CloneFlags::CLONE_NEWNS)
+            .map_err(|e| LeewardError::Namespace(format!("unshare: {e}")))?;
+        mounts.apply()
+    })
+    .unwrap();
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+    libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+}
+
+#[test]
+fn a_missing_bind_source_fails_the_mounts_only_when_strict() {
+    let target = std::env::temp_dir().join(format!("leeward-strict-target-{}", std::process::id()));
+    let mounts = MountConfig::default().ro_bind(missing(), &target);
+    assert!(mounts_apply(&mounts));
+    assert!(!mounts_apply(&mounts.strict(true)));
+}
+
+#[cfg(feature = "landlock")]
+#[test]
+fn a_missing_landlock_path_fails_the_ruleset_only_when_strict() {
+    use leeward_core::isolation::{IsolationLayer, LandlockConfig};
+
+    let lenient = LandlockConfig::default().ro("/").ro(missing());
+    let named_in_child = missing();
+    assert!(!lenient.required());
+    let strict = lenient.clone().strict(true);
+    assert!(strict.required());
+
+    // SAFETY: fork in a test; the child only applies Landlock and exits
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        // The strict ruleset fails before anything is enforced
+        let strict_failed = matches!(strict.apply(), Err(LeewardError::Landlock(_)));
+        let named = strict
+            .apply()
+            .is_err_and(|e| e.to_string().contains(&*named_in_child.to_string_lossy()));
+        let lenient_applied = lenient.apply().is_ok();
+        // SAFETY: Exiting child process
+        unsafe { libc::_exit(i32::from(!(strict_failed && named && lenient_applied))) };
+    }
+    let mut status = 0;
+    // SAFETY: Waiting on our own child
+    unsafe { libc::waitpid(pid, &raw mut status, 0) };
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "status {status}"
+    );
+}
+
+#[test]
+fn a_strict_template_names_the_missing_bind() {
+    let config = SandboxConfig::builder()
+        .ro_bind(missing())
+        .strict_paths(true)
+        .build();
+    assert!(config.strict_paths);
+    let built = RootTemplate::build(&config);
+    assert!(names_missing(&built), "{built:?}");
+
+    let lenient = SandboxConfig::builder().ro_bind(missing()).build();
+    assert!(!lenient.strict_paths);
+    match RootTemplate::build(&lenient) {
+        Ok(_) => {}
+        Err(e) => assert!(
+            !e.to_string().contains(&*missing().to_string_lossy()),
+            "{e}"
+        ),
+    }
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn a_strict_worker_does_not_spawn_without_its_binds() {
+    use leeward_core::worker::Worker;
+
+    let config = SandboxConfig {
+        ro_binds: vec![missing()],
+        strict_paths: true,
+        ..SandboxConfig::minimal_for_tests()
+    };
+    let mut worker = Worker::new(0, config.clone());
+    let spawned = worker.spawn();
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let mut lenient = Worker::new(
+        0,
+        SandboxConfig {
+            strict_paths: false,
+            ..config
+        },
+    );
+    if let Err(e) = lenient.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    if let Some(pid) = lenient.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+    assert!(
+        spawned.is_err(),
+        "a strict worker spawned without {}",
+        missing().display()
+    );
+}
@@ -0,0 +1,136 @@
+//! A new time namespace starts its children's monotonic and boot clocks
+//! from 0, so sandboxed code cannot tell how long the host has been up
+
+#![cfg(feature = "protocol")]
+
+mod common;
+
+use leeward_core::isolation::NamespaceConfig;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::worker::{ExecuteOptions, Worker};
+use leeward_core::{LeewardError, SandboxConfig};
+use nix::sched::CloneFlags;
+use std::path::Path;
+
+/// Whether this kernel has time namespaces
+fn have_time_namespaces() -> bool {
+    if Path::new("/proc/self/ns/time").exists() {
+        true
+    } else {
+        eprintln!("skipping: no time namespaces here");
+        false
+    }
+}
+
+/// Seconds `clock` reads
+fn seconds(clock: libc::clockid_t) -> f64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime writes only the timespec it is given
+    assert_eq!(unsafe { libc::clock_gettime(clock, &raw mut now) }, 0);
+    #[allow(clippy::cast_precision_loss)]
+    let seconds = now.tv_sec as f64 + now.tv_nsec as f64 / 1e9;
+    seconds
+}
+
+#[test]
+fn time_adds_the_clone_flag() {
+    let time = CloneFlags::from_bits_retain(libc::CLONE_NEWTIME);
+    assert!(!NamespaceConfig::default().to_clone_flags().contains(time));
+    let config = NamespaceConfig {
+        time: true,
+        ..NamespaceConfig::default()
+    };
+    assert!(config.to_clone_flags().contains(time));
+}
+
+#[test]
+fn children_of_a_new_time_namespace_start_their_clocks_from_zero() {
+    if !have_time_namespaces() {
+        return;
+    }
+    let config = NamespaceConfig {
+        user: false,
+        pid: false,
+        mount: false,
+        net: false,
+        ipc: false,
+        uts: false,
+        time: true,
+        ..NamespaceConfig::default()
+    };
+    let pid = clone_worker(0, || {
+        config.enter()?;
+        // Only children are in the namespace
+        let host = seconds(libc::CLOCK_MONOTONIC);
+        if host < 1.0 {
+            return Err(LeewardError::Namespace(format!(
+                "the caller's clock moved to {host}"
+            )));
+        }
+        // SAFETY: fork in a single-threaded child; the grandchild only reads clocks and exits
+        match unsafe { libc::fork() } {
+            0 => {
+                let zeroed =
+                    seconds(libc::CLOCK_MONOTONIC) < 1.0 && seconds(libc::CLOCK_BOOTTIME) < 1.0;
+                // SAFETY: Exiting the grandchild
+                unsafe { libc::_exit(i32::from(!zeroed)) };
+            }
+            -1 => Err(std::io::Error::last_os_error().into()),
+            child if common::succeeded(child) => Ok(()),
+            _ => Err(LeewardError::Namespace(
+                "the child's clocks did not start from 0".into(),
+            )),
+        }
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+/// Run `code` once in a fresh worker spawned with `config`
+fn run_in_worker(
+    config: SandboxConfig,
+    code: &str,
+) -> leeward_core::Result<leeward_core::ExecutionResult> {
+    let mut worker = Worker::new(0, config);
+    worker.spawn()?;
+    let result = worker.execute(code, &ExecuteOptions::default());
+    worker.stop();
+    result
+}
+
+#[test]
+fn the_sandboxed_monotonic_clock_starts_from_zero() {
+    if !have_time_namespaces() {
+        return;
+    }
+    assert!(
+        SandboxConfig::builder()
+            .freeze_clock(true)
+            .build()
+            .freeze_clock
+    );
+    // Only workers that cannot run code at all are a reason to skip
+    match run_in_worker(SandboxConfig::minimal_for_tests(), "pass") {
+        Ok(result) if result.exit_code == 0 => {}
+        Ok(result) => {
+            eprintln!("skipping: {}", result.stderr_str());
+            return;
+        }
+        Err(e) => {
+            eprintln!("skipping: no sandbox here: {e}");
+            return;
+        }
+    }
+
+    let config = SandboxConfig {
+        freeze_clock: true,
+        ..SandboxConfig::minimal_for_tests()
+    };
+    let result = run_in_worker(config, "import time; print(time.monotonic())").unwrap();
+    assert_eq!(result.exit_code, 0, "{}", result.stderr_str());
+    let monotonic: f64 = result.stdout_str().trim().parse().unwrap();
+    assert!(monotonic < 1.0, "monotonic clock at {monotonic}");
+}
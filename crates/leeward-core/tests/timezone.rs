@@ -0,0 +1,178 @@
+//! The interpreter runs in the configured timezone, UTC by default, and
+//! zone names cannot reach files outside the host's zoneinfo
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::config::{ZONEINFO_DIR, zoneinfo_path};
+use leeward_core::isolation::RootTemplate;
+use leeward_core::worker::{ExecuteOptions, Worker, run_python};
+use leeward_core::{LeewardError, SandboxConfig};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A zone without daylight saving, so its abbreviation never changes
+const ZONE: &str = "Asia/Tokyo";
+
+const TZNAME: &str = "from datetime import datetime; print(datetime.now().astimezone().tzname())";
+
+/// Whether the host has tzdata to test against
+fn have_tzdata() -> bool {
+    if Path::new(ZONEINFO_DIR).join(ZONE).is_file() {
+        true
+    } else {
+        eprintln!("skipping: no {ZONE} under {ZONEINFO_DIR}");
+        false
+    }
+}
+
+/// Zone abbreviation the interpreter reports
+fn tzname(config: &SandboxConfig, options: &ExecuteOptions) -> Option<String> {
+    let result = run_python(TZNAME, config, options).unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping: {}", result.stderr_str());
+        return None;
+    }
+    Some(result.stdout_str().trim().to_owned())
+}
+
+#[test]
+fn default_is_utc() {
+    assert_eq!(SandboxConfig::default().timezone_name(), "UTC");
+    SandboxConfig::default().validate().unwrap();
+
+    if let Some(name) = tzname(&SandboxConfig::default(), &ExecuteOptions::default()) {
+        assert_eq!(name, "UTC");
+    }
+}
+
+#[test]
+fn configured_zone_is_used() {
+    if !have_tzdata() {
+        return;
+    }
+    let config = SandboxConfig::builder().timezone(ZONE).build();
+    config.validate().unwrap();
+    if let Some(name) = tzname(&config, &ExecuteOptions::default()) {
+        assert_eq!(name, "JST");
+    }
+
+    // The config's zone wins over a TZ in its environment
+    let config = SandboxConfig::builder()
+        .env("TZ", "Europe/Paris")
+        .timezone(ZONE)
+        .build();
+    if let Some(name) = tzname(&config, &ExecuteOptions::default()) {
+        assert_eq!(name, "JST");
+    }
+}
+
+#[test]
+fn request_overrides_config() {
+    if !have_tzdata() {
+        return;
+    }
+    let options = ExecuteOptions {
+        timezone: Some(ZONE.into()),
+        ..ExecuteOptions::default()
+    };
+    if let Some(name) = tzname(&SandboxConfig::default(), &options) {
+        assert_eq!(name, "JST");
+    }
+}
+
+#[test]
+fn zone_names_stay_inside_zoneinfo() {
+    if !have_tzdata() {
+        return;
+    }
+    assert!(
+        zoneinfo_path(ZONE)
+            .unwrap()
+            .starts_with(Path::new(ZONEINFO_DIR).canonicalize().unwrap())
+    );
+    zoneinfo_path("UTC").unwrap();
+    zoneinfo_path("Etc/GMT+5").unwrap();
+
+    for name in [
+        "",
+        "/etc/passwd",
+        "../../../etc/passwd",
+        "Asia/../../../etc/passwd",
+        "Asia/./Tokyo",
+        "Asia//Tokyo",
+        "Asia",
+        "zone.tab",
+        "Nowhere/Atlantis",
+        "Asia/Tokyo\n",
+        "-Asia",
+    ] {
+        assert!(
+            matches!(zoneinfo_path(name), Err(LeewardError::InvalidRequest(_))),
+            "{name:?} was accepted"
+        );
+    }
+}
+
+#[test]
+fn invalid_zone_fails_validation() {
+    let config = SandboxConfig::builder()
+        .timezone("Mars/Olympus_Mons")
+        .build();
+    assert!(matches!(config.validate(), Err(LeewardError::Config(_))));
+
+    let config = SandboxConfig::builder()
+        .timezone("../../etc/shadow")
+        .build();
+    assert!(matches!(config.validate(), Err(LeewardError::Config(_))));
+    assert!(matches!(
+        RootTemplate::build(&config),
+        Err(LeewardError::Config(_))
+    ));
+}
+
+#[test]
+fn template_provides_localtime() {
+    if !have_tzdata() {
+        return;
+    }
+    let config = SandboxConfig::builder().timezone(ZONE).build();
+    let template = match RootTemplate::build(&config) {
+        Ok(template) => Arc::new(template),
+        Err(e) => {
+            eprintln!("skipping: no root template here: {e}");
+            return;
+        }
+    };
+
+    let mut worker = Worker::new(0, config).with_root_template(template);
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return;
+    }
+    let result = worker.execute(
+        "print(open('/etc/localtime', 'rb').read().hex())",
+        &ExecuteOptions::default(),
+    );
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let result = result.unwrap();
+    if result.exit_code != 0 {
+        eprintln!("skipping, execution fails here: {}", result.stderr_str());
+        return;
+    }
+    let expected = std::fs::read(zoneinfo_path(ZONE).unwrap())
+        .unwrap()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+    assert_eq!(result.stdout_str().trim(), expected);
+}
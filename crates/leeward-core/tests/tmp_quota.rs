@@ -0,0 +1,130 @@
+//! `/tmp` has a quota of its own, so filling it leaves the workspace
+//! writable, and `TMPDIR` always points at it
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::config::{DEFAULT_TMP_SIZE, TMP_DIR};
+use leeward_core::isolation::RootTemplate;
+use leeward_core::worker::{ExecuteOptions, Worker, run_python};
+use leeward_core::{ByteSize, ExecutionResult, LeewardError, SandboxConfig};
+use std::sync::Arc;
+
+const TMP_SIZE: ByteSize = ByteSize::mib(1);
+
+/// Fill TMPDIR until it is out of space, then write and read back a file
+/// in the workspace, which is also `HOME`
+const FILL_THEN_WRITE: &str = r#"
+import errno, os, tempfile
+written = 0
+try:
+    with tempfile.NamedTemporaryFile(delete=False) as f:
+        while True:
+            f.write(b"x" * 65536)
+            f.flush()
+            written += 65536
+except OSError as e:
+    assert e.errno == errno.ENOSPC, e
+artifact = os.path.join(os.environ["HOME"], "artifact.txt")
+with open(artifact, "w") as f:
+    f.write("result")
+print(written, open(artifact).read())
+"#;
+
+/// List what is left in TMPDIR and in the workspace
+const LIST_LEFTOVERS: &str = r#"
+import os
+print(os.listdir(os.environ["TMPDIR"]), os.listdir(os.environ["HOME"]))
+"#;
+
+#[test]
+fn config_defaults_and_validation() {
+    let config = SandboxConfig::default();
+    assert_eq!(config.tmp_size, DEFAULT_TMP_SIZE);
+    config.validate().unwrap();
+
+    let config = SandboxConfig::builder().tmp_size(ByteSize::ZERO).build();
+    assert!(matches!(config.validate(), Err(LeewardError::Config(_))));
+    assert!(matches!(
+        RootTemplate::build(&config),
+        Err(LeewardError::Config(_))
+    ));
+}
+
+#[test]
+fn tmpdir_cannot_be_redirected() {
+    let config = SandboxConfig::builder()
+        .env("TMPDIR", "/home/sandbox/tmp")
+        .build();
+    let result = run_python(
+        "import os; print(os.environ['TMPDIR'])",
+        &config,
+        &ExecuteOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        result.stdout_str().trim(),
+        TMP_DIR,
+        "{}",
+        result.stderr_str()
+    );
+}
+
+/// Run each snippet in turn on one worker with a small `/tmp`
+fn run_rooted(snippets: &[&str]) -> Option<Vec<ExecutionResult>> {
+    let config = SandboxConfig::builder().tmp_size(TMP_SIZE).build();
+    let template = match RootTemplate::build(&config) {
+        Ok(template) => Arc::new(template),
+        Err(e) => {
+            eprintln!("skipping: no root template here: {e}");
+            return None;
+        }
+    };
+    let mut worker = Worker::new(0, config).with_root_template(template);
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return None;
+    }
+    let results = snippets
+        .iter()
+        .map(|code| worker.execute(code, &ExecuteOptions::default()))
+        .collect::<Vec<_>>();
+    if let Some(pid) = worker.info().pid {
+        // SAFETY: Killing and reaping our own worker
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+    if let Some(failed) = results
+        .iter()
+        .find(|result| result.stderr_str().starts_with("Failed to execute"))
+    {
+        eprintln!("skipping, execution fails here: {}", failed.stderr_str());
+        return None;
+    }
+    Some(results)
+}
+
+#[test]
+fn full_tmp_leaves_the_workspace_writable() {
+    let Some(results) = run_rooted(&[FILL_THEN_WRITE, LIST_LEFTOVERS]) else {
+        return;
+    };
+
+    let filled = &results[0];
+    assert_eq!(filled.exit_code, 0, "{}", filled.stderr_str());
+    let stdout = filled.stdout_str();
+    let (written, artifact) = stdout.trim().split_once(' ').unwrap();
+    assert!(written.parse::<u64>().unwrap() <= TMP_SIZE.bytes());
+    assert_eq!(artifact, "result");
+    assert!(filled.tmp_bytes > TMP_SIZE.bytes() / 2, "{filled:?}");
+    assert!(filled.workspace_bytes > 0, "{filled:?}");
+    assert!(filled.workspace_bytes < filled.tmp_bytes, "{filled:?}");
+
+    // The next execution starts with an empty /tmp and workspace
+    let next = &results[1];
+    assert_eq!(next.stdout_str().trim(), "[] []", "{}", next.stderr_str());
+    assert!(next.tmp_bytes < TMP_SIZE.bytes() / 2, "{next:?}");
+}
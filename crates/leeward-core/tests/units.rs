@@ -0,0 +1,190 @@
+//! Sizes and durations parse from what people write, print back to the
+//! same value, and reach the kernel exact
+
+use leeward_core::isolation::mounts::tmpfs_options;
+use leeward_core::units::{self, UnitError};
+use leeward_core::{ByteSize, DurationSecs};
+use std::time::Duration;
+
+#[test]
+fn sizes_parse_in_binary_and_decimal_units() {
+    for (input, bytes) in [
+        ("512KiB", 512 * 1024),
+        ("64M", 64 << 20),
+        ("64 MiB", 64 << 20),
+        ("1.5GiB", 3 << 29),
+        ("2tib", 2 << 40),
+        ("100MB", 100_000_000),
+        ("4kb", 4000),
+        ("0", 0),
+        ("1048576", 1 << 20),
+    ] {
+        assert_eq!(
+            input.parse::<ByteSize>(),
+            Ok(ByteSize::from_bytes(bytes)),
+            "{input}"
+        );
+    }
+}
+
+#[test]
+fn sizes_print_in_the_largest_exact_unit() {
+    for (size, printed) in [
+        (ByteSize::kib(512), "512KiB"),
+        (ByteSize::mib(1024), "1GiB"),
+        (ByteSize::mib(1536), "1536MiB"),
+        (ByteSize::from_bytes(1000), "1000B"),
+        (ByteSize::ZERO, "0B"),
+    ] {
+        assert_eq!(size.to_string(), printed);
+        assert_eq!(printed.parse(), Ok(size));
+    }
+}
+
+#[test]
+fn durations_parse_and_print() {
+    for (input, duration) in [
+        ("1500ms", Duration::from_millis(1500)),
+        ("30s", Duration::from_secs(30)),
+        ("0.5s", Duration::from_millis(500)),
+        ("5m", Duration::from_secs(300)),
+        ("1h", Duration::from_secs(3600)),
+        ("250us", Duration::from_micros(250)),
+        ("90", Duration::from_secs(90)),
+    ] {
+        assert_eq!(
+            input.parse::<DurationSecs>(),
+            Ok(duration.into()),
+            "{input}"
+        );
+    }
+    assert_eq!(DurationSecs::from_millis(1500).to_string(), "1500ms");
+    assert_eq!(DurationSecs::from_secs(90).to_string(), "90s");
+    assert_eq!(DurationSecs::from_secs(7200).to_string(), "2h");
+    assert_eq!(DurationSecs::ZERO.to_string(), "0s");
+}
+
+#[test]
+fn nonsense_is_refused() {
+    assert!(matches!(
+        "".parse::<ByteSize>(),
+        Err(UnitError::Malformed(_))
+    ));
+    assert!(matches!(
+        "MiB".parse::<ByteSize>(),
+        Err(UnitError::Malformed(_))
+    ));
+    assert!(matches!(
+        "1.2.3MiB".parse::<ByteSize>(),
+        Err(UnitError::Malformed(_))
+    ));
+    assert!(matches!(
+        "64 parsecs".parse::<ByteSize>(),
+        Err(UnitError::UnknownUnit { .. })
+    ));
+    assert!(matches!(
+        "-1s".parse::<DurationSecs>(),
+        Err(UnitError::UnknownUnit { .. })
+    ));
+    assert!(matches!(
+        "1.5B".parse::<ByteSize>(),
+        Err(UnitError::Fractional(..))
+    ));
+    assert!(matches!(
+        "0.5ns".parse::<DurationSecs>(),
+        Err(UnitError::Fractional(..))
+    ));
+    assert!(matches!(
+        "20000000TiB".parse::<ByteSize>(),
+        Err(UnitError::Overflow(_))
+    ));
+}
+
+#[test]
+fn only_numbers_without_units_are_bare() {
+    assert!(units::is_bare("1024"));
+    assert!(units::is_bare(" 30 "));
+    assert!(!units::is_bare("1024B"));
+    assert!(!units::is_bare("1.5"));
+    assert!(!units::is_bare(""));
+}
+
+#[test]
+fn arithmetic_saturates() {
+    assert_eq!(
+        ByteSize::mib(1).saturating_add(ByteSize::kib(512)),
+        ByteSize::kib(1536)
+    );
+    assert_eq!(
+        ByteSize::kib(1).saturating_sub(ByteSize::mib(1)),
+        ByteSize::ZERO
+    );
+    assert_eq!(ByteSize::gib(u64::MAX), ByteSize::from_bytes(u64::MAX));
+    assert_eq!(
+        ByteSize::from_bytes(u64::MAX).checked_add(ByteSize::from_bytes(1)),
+        None
+    );
+    assert_eq!(ByteSize::from_bytes((3 << 20) - 1).whole_mib(), 2);
+    assert_eq!(
+        DurationSecs::from_secs(2).saturating_mul(3),
+        DurationSecs::from_secs(6)
+    );
+    assert_eq!(DurationSecs::ZERO.non_zero(), None);
+    assert_eq!(
+        DurationSecs::from_millis(20).non_zero(),
+        Some(Duration::from_millis(20))
+    );
+}
+
+/// A tmpfs under a MiB used to be mounted as `size=0M`, which tmpfs takes
+/// as no limit at all
+#[test]
+fn tmpfs_sizes_are_exact() {
+    assert_eq!(tmpfs_options(ByteSize::kib(512)), "size=524288");
+    assert_eq!(
+        tmpfs_options(ByteSize::from_bytes(1_500_000)),
+        "size=1500000"
+    );
+    assert_eq!(tmpfs_options(ByteSize::mib(64)), "size=67108864");
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn serialized_as_the_integers_they_replace() {
+    assert_eq!(serde_json::to_string(&ByteSize::mib(1)).unwrap(), "1048576");
+    assert_eq!(
+        serde_json::to_string(&DurationSecs::from_secs(600)).unwrap(),
+        "600"
+    );
+    assert_eq!(
+        serde_json::to_string(&DurationSecs::from_millis(1500)).unwrap(),
+        r#""1500ms""#
+    );
+
+    assert_eq!(
+        serde_json::from_str::<ByteSize>("1048576").unwrap(),
+        ByteSize::mib(1)
+    );
+    assert_eq!(
+        serde_json::from_str::<ByteSize>(r#""1MiB""#).unwrap(),
+        ByteSize::mib(1)
+    );
+    assert_eq!(
+        serde_json::from_str::<DurationSecs>("600").unwrap(),
+        DurationSecs::from_secs(600)
+    );
+    assert_eq!(
+        serde_json::from_str::<DurationSecs>(r#""1500ms""#).unwrap(),
+        DurationSecs::from_millis(1500)
+    );
+    assert!(serde_json::from_str::<ByteSize>("-1").is_err());
+    assert!(serde_json::from_str::<ByteSize>(r#""lots""#).is_err());
+
+    for duration in [DurationSecs::from_secs(3), DurationSecs::from_millis(20)] {
+        let packed = rmp_serde::to_vec(&duration).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<DurationSecs>(&packed).unwrap(),
+            duration
+        );
+    }
+}
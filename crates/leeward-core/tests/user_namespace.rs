@@ -0,0 +1,98 @@
+//! A new user namespace gets its id maps, from the process itself after
+//! `unshare` or from its parent before a cloned child goes on, so the
+//! sandbox user can do what needs a real uid
+
+mod common;
+
+use leeward_core::LeewardError;
+use leeward_core::isolation::NamespaceConfig;
+use leeward_core::isolation::clone3::{clone_worker, clone_worker_with};
+use leeward_core::isolation::namespace::SANDBOX_ID;
+use nix::unistd::{getgid, getuid};
+
+/// Fail unless this process runs as `SANDBOX_ID` and can chown a file of
+/// its own in `dir`
+fn check_sandbox_user(dir: &std::path::Path) -> leeward_core::Result<()> {
+    if getuid().as_raw() != SANDBOX_ID || getgid().as_raw() != SANDBOX_ID {
+        return Err(LeewardError::Namespace(format!(
+            "running as {}:{}",
+            getuid(),
+            getgid()
+        )));
+    }
+    let file = dir.join(format!("owned-{}", std::process::id()));
+    std::fs::write(&file, "")?;
+    std::os::unix::fs::chown(&file, Some(SANDBOX_ID), Some(SANDBOX_ID))?;
+    std::fs::remove_file(&file)?;
+    Ok(())
+}
+
+/// A scratch directory whoever the sandbox user maps to can write in
+fn writable_scratch(name: &str) -> common::Scratch {
+    let dir = common::Scratch::new(name);
+    std::fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o777)).unwrap();
+    dir
+}
+
+#[test]
+fn the_default_maps_put_the_sandbox_user_on_the_caller() {
+    let config = NamespaceConfig::default();
+    assert_eq!(config.uid_map, [(SANDBOX_ID, getuid().as_raw(), 1)]);
+    assert_eq!(config.gid_map, [(SANDBOX_ID, getgid().as_raw(), 1)]);
+
+    let config = NamespaceConfig::single_user_map(4242, 7);
+    assert_eq!(config.uid_map, [(7, 4242, 1)]);
+    assert_eq!(config.gid_map, [(7, getgid().as_raw(), 1)]);
+}
+
+#[test]
+fn entering_a_user_namespace_maps_the_sandbox_user() {
+    let dir = writable_scratch("userns-enter");
+    let config = NamespaceConfig {
+        user: true,
+        pid: false,
+        mount: false,
+        net: false,
+        ipc: false,
+        uts: false,
+        ..NamespaceConfig::default()
+    };
+    let pid = clone_worker(0, || {
+        config.enter()?;
+        check_sandbox_user(&dir)
+    })
+    .unwrap();
+    assert!(common::succeeded(pid));
+    assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+}
+
+#[test]
+fn the_parent_maps_a_child_cloned_into_a_user_namespace() {
+    let dir = writable_scratch("userns-clone");
+    let config = NamespaceConfig::single_user_map(getuid().as_raw(), SANDBOX_ID);
+    let (pid, _) = clone_worker_with(
+        libc::CLONE_NEWUSER as u64,
+        None,
+        |pid| config.write_id_maps(Some(pid)),
+        || check_sandbox_user(&dir),
+    )
+    .unwrap();
+    assert!(common::succeeded(pid));
+}
+
+#[test]
+fn a_child_does_not_go_on_when_its_parent_fails_to_set_it_up() {
+    let dir = writable_scratch("userns-refused");
+    let ran = dir.join("ran");
+    let refused = clone_worker_with(
+        libc::CLONE_NEWUSER as u64,
+        None,
+        |_| Err(LeewardError::Namespace("no maps for you".into())),
+        || Ok(std::fs::write(&ran, "")?),
+    );
+    assert!(
+        matches!(refused, Err(LeewardError::Namespace(message)) if message == "no maps for you")
+    );
+    // Reaped already, so the child is gone
+    assert!(!ran.exists());
+}
@@ -0,0 +1,209 @@
+//! A worker that fails or panics says which stage it died in, through its
+//! last-words frame or, failing that, its exit status
+
+use leeward_core::LeewardError;
+use leeward_core::isolation::clone3::clone_worker;
+use leeward_core::isolation::fatal::{self, FRAME_LEN, Stage, WorkerDeath};
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+/// Run `child` in a cloned process reporting to a socket, returning the
+/// frame it sent, if any, and its wait status
+fn run_child(child: impl FnOnce() -> leeward_core::Result<()>) -> (Option<WorkerDeath>, i32) {
+    let (mut rx, tx) = UnixStream::pair().unwrap();
+    let pid = clone_worker(0, move || {
+        fatal::report_to(tx.as_raw_fd())?;
+        child()
+    })
+    .unwrap();
+
+    let mut frame = Vec::new();
+    rx.read_to_end(&mut frame).unwrap();
+    let mut status = 0;
+    // SAFETY: Reaping our own child
+    assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+
+    let death = (!frame.is_empty()).then(|| {
+        assert_eq!(frame.len(), 4 + FRAME_LEN);
+        assert_eq!(frame[..4], u32::try_from(FRAME_LEN).unwrap().to_be_bytes());
+        WorkerDeath::decode(&frame[4..]).unwrap()
+    });
+    (death, status)
+}
+
+#[test]
+fn each_stage_reaches_the_parent() {
+    for stage in Stage::ALL {
+        let (death, status) = run_child(move || {
+            fatal::enter(stage);
+            Err(LeewardError::Io(std::io::Error::from_raw_os_error(
+                libc::ENOENT,
+            )))
+        });
+
+        let death = death.unwrap();
+        assert_eq!(death.stage, stage);
+        assert_eq!(death.errno, libc::ENOENT);
+        assert!(death.message.starts_with("io error:"), "{death}");
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), stage.exit_code());
+        assert_eq!(WorkerDeath::from_wait_status(status).unwrap().stage, stage);
+
+        let error = LeewardError::WorkerDied(death);
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("worker died during {stage}: ")),
+            "{error}"
+        );
+    }
+}
+
+#[test]
+fn panics_are_reported_with_their_message() {
+    let (death, status) = run_child(|| {
+        fatal::enter(Stage::Landlock);
+        panic!("ruleset went missing");
+    });
+
+    let death = death.unwrap();
+    assert_eq!(death.stage, Stage::Landlock);
+    assert_eq!(death.errno, 0);
+    assert!(death.message.contains("ruleset went missing"), "{death}");
+    assert_eq!(libc::WEXITSTATUS(status), Stage::Landlock.exit_code());
+}
+
+#[test]
+fn long_messages_are_cut_at_a_char_boundary() {
+    let (death, _) = run_child(|| {
+        fatal::enter(Stage::Exec);
+        Err(LeewardError::Execution("é".repeat(FRAME_LEN)))
+    });
+
+    let message = death.unwrap().message;
+    assert!(message.len() < FRAME_LEN);
+    assert!(message.ends_with('é'), "{message}");
+    assert!(!message.contains(char::REPLACEMENT_CHARACTER));
+}
+
+#[test]
+fn exit_status_names_the_stage_without_a_frame() {
+    let reap = |pid| {
+        let mut status = 0;
+        // SAFETY: Reaping our own child
+        assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+        status
+    };
+
+    let failed = clone_worker(0, || {
+        fatal::enter(Stage::Mounts);
+        Err(LeewardError::Mount("no root".into()))
+    })
+    .unwrap();
+    let death = WorkerDeath::from_wait_status(reap(failed)).unwrap();
+    assert_eq!(death.stage, Stage::Mounts);
+    assert_eq!(
+        death.message,
+        format!("exited with status {}", Stage::Mounts.exit_code())
+    );
+
+    let clean = clone_worker(0, || Ok(())).unwrap();
+    assert_eq!(WorkerDeath::from_wait_status(reap(clean)), None);
+}
+
+/// Spawn a worker expected to die during setup, or `None` where workers
+/// cannot get that far
+#[cfg(feature = "protocol")]
+fn spawn_broken(worker: &mut leeward_core::worker::Worker, stage: Stage) -> Option<WorkerDeath> {
+    match worker.spawn() {
+        Ok(()) => panic!("worker started despite a broken {stage} stage"),
+        Err(LeewardError::WorkerDied(death)) if death.stage == Stage::Namespaces => {
+            eprintln!("skipping: no namespaces here: {death}");
+            None
+        }
+        Err(LeewardError::WorkerDied(death)) => Some(death),
+        Err(e) => panic!("worker death not reported: {e}"),
+    }
+}
+
+#[cfg(all(feature = "protocol", feature = "seccomp"))]
+#[test]
+fn broken_seccomp_fails_spawn_naming_the_stage() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::worker::{Worker, WorkerState};
+
+    let mut worker = Worker::new(0, SandboxConfig::default()).with_notifications(vec![-1]);
+    let Some(death) = spawn_broken(&mut worker, Stage::Seccomp) else {
+        return;
+    };
+    assert_eq!(death.stage, Stage::Seccomp);
+    assert!(
+        death.message.contains("invalid syscall number -1"),
+        "{death}"
+    );
+    assert_eq!(worker.state, WorkerState::Dead);
+    assert_eq!(worker.pid, None);
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn broken_mounts_fail_spawn_naming_the_stage() {
+    use leeward_core::SandboxConfig;
+    use leeward_core::isolation::RootTemplate;
+    use leeward_core::worker::Worker;
+    use std::sync::Arc;
+
+    let config = SandboxConfig::default();
+    let template = match RootTemplate::build(&config) {
+        Ok(template) => Arc::new(template),
+        Err(e) => {
+            eprintln!("skipping: no root template here: {e}");
+            return;
+        }
+    };
+    // Workers attach the template at its root path, which is now a file
+    let root = template.root().to_path_buf();
+    std::fs::remove_dir(&root).unwrap();
+    std::fs::write(&root, b"").unwrap();
+
+    let mut worker = Worker::new(0, config).with_root_template(template);
+    let death = spawn_broken(&mut worker, Stage::Mounts);
+    std::fs::remove_file(&root).unwrap();
+    let Some(death) = death else {
+        return;
+    };
+    assert_eq!(death.stage, Stage::Mounts);
+    assert!(death.message.starts_with("mount error:"), "{death}");
+}
+
+#[cfg(feature = "protocol")]
+#[test]
+fn died_event_carries_the_report() {
+    use leeward_core::protocol::{self, Event, EventKind};
+
+    let death = WorkerDeath {
+        stage: Stage::Mounts,
+        errno: libc::ENOENT,
+        message: "mount error: no root".into(),
+    };
+    let event = Event::worker_died(3, &LeewardError::WorkerDied(death.clone()));
+    assert_eq!(event.kind, EventKind::WorkerDied);
+    assert_eq!(
+        event.message,
+        "worker 3: worker died during mounts: mount error: no root (os error 2)"
+    );
+    let details = event.worker_died.as_ref().unwrap();
+    assert_eq!(details.worker_id, 3);
+    assert_eq!(details.death.as_ref(), Some(&death));
+
+    let json = String::from_utf8(protocol::encode_json(&event).unwrap()).unwrap();
+    assert!(json.contains(r#""kind":"worker_died""#), "{json}");
+    assert!(json.contains(r#""stage":"mounts","errno":2"#), "{json}");
+
+    let killed = Event::worker_died(
+        1,
+        &LeewardError::Execution("worker killed by signal 9".into()),
+    );
+    assert_eq!(killed.worker_died.unwrap().death, None);
+}
@@ -0,0 +1,142 @@
+//! Each worker process gets a uid of its own, never handed out again, and
+//! the resources named after it go with the process
+//!
+//! Real cgroups need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+#![cfg(all(feature = "cgroups", feature = "protocol"))]
+
+use leeward_core::SandboxConfig;
+use leeward_core::worker::{CGROUP_PREFIX, Worker, WorkerUid, WorkerUids};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+#[test]
+fn uids_count_up_within_a_boot() {
+    let uids = WorkerUids::new("boot");
+    assert_eq!(uids.peek(), 1);
+    let first = uids.next();
+    let second = uids.next();
+    assert_eq!(first.boot_id, "boot");
+    assert_eq!((first.seq, second.seq), (1, 2));
+    assert_eq!(uids.peek(), 3);
+
+    assert_ne!(
+        WorkerUids::random().boot_id(),
+        WorkerUids::random().boot_id()
+    );
+}
+
+#[test]
+fn uids_round_trip_through_their_names() {
+    // Boot ids are uuids, dashes and all
+    let uid = WorkerUid {
+        boot_id: "0f3e9c2a-7d41-4b8e-9a65-1c2d3e4f5a6b".into(),
+        seq: 42,
+    };
+    assert_eq!(uid.to_string(), "0f3e9c2a-7d41-4b8e-9a65-1c2d3e4f5a6b-42");
+    assert_eq!(uid.to_string().parse::<WorkerUid>().unwrap(), uid);
+    assert_eq!(uid.cgroup_name(), format!("{CGROUP_PREFIX}{uid}"));
+    assert_eq!(WorkerUid::from_cgroup_name(&uid.cgroup_name()), Some(uid));
+
+    for name in ["42", "-42", "boot-", "boot-x", "boot"] {
+        assert!(name.parse::<WorkerUid>().is_err(), "{name}");
+    }
+    assert_eq!(WorkerUid::from_cgroup_name("worker-7"), None);
+    assert_eq!(WorkerUid::from_cgroup_name("other-boot-7"), None);
+}
+
+/// A worker that spawned, or `None` if there is no sandbox here
+fn spawned(uids: &Arc<WorkerUids>) -> Option<Worker> {
+    let mut worker = Worker::new(3, SandboxConfig::default()).with_uids(Arc::clone(uids));
+    if let Err(e) = worker.spawn() {
+        eprintln!("skipping: no sandbox here: {e}");
+        return None;
+    }
+    Some(worker)
+}
+
+#[test]
+fn recycling_gives_each_process_a_new_uid_in_the_same_slot() {
+    let uids = Arc::new(WorkerUids::new("recycle"));
+    let Some(mut worker) = spawned(&uids) else {
+        return;
+    };
+
+    let mut seen = BTreeSet::new();
+    for _ in 0..5 {
+        let info = worker.info();
+        assert_eq!(info.id, 3);
+        let uid = info.uid.unwrap();
+        assert_eq!(uid.boot_id, "recycle");
+        assert!(seen.insert(uid.seq), "{uid} reused");
+        worker.recycle().unwrap();
+    }
+    assert!(seen.iter().is_sorted());
+    worker.stop();
+    // The last process keeps naming the worker once it is gone
+    assert_eq!(worker.uid.unwrap().seq, 6);
+}
+
+#[test]
+fn recycled_workers_never_reuse_a_cgroup_and_remove_the_old_one() {
+    let Ok(parent) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return;
+    };
+    let root = leeward_core::isolation::CgroupHandle::create(
+        std::path::Path::new(&parent),
+        &format!("uids-{}", std::process::id()),
+    )
+    .unwrap();
+    let uids = Arc::new(WorkerUids::new("cgroups"));
+    let Some(mut worker) = spawned(&uids) else {
+        return;
+    };
+    worker.set_cgroup_root(root.path()).unwrap();
+
+    let mut paths = Vec::new();
+    for _ in 0..5 {
+        let path = worker.cgroup().unwrap().path().to_path_buf();
+        assert!(path.exists(), "{}", path.display());
+        assert!(!paths.contains(&path), "{} reused", path.display());
+        paths.push(path);
+        worker.recycle().unwrap();
+        assert!(!paths.last().unwrap().exists(), "old cgroup left behind");
+    }
+    worker.stop();
+    assert!(worker.cgroup().is_none());
+    assert!(!paths.iter().any(|path| path.exists()));
+    root.remove().unwrap();
+}
+
+#[test]
+fn stale_cgroups_are_removed_and_the_rest_left() {
+    let root = std::env::temp_dir().join(format!("leeward-cgroup-stale-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let uid = |boot: &str, seq| WorkerUid {
+        boot_id: boot.into(),
+        seq,
+    };
+    let live = uid("now", 2);
+    let names = [
+        uid("before", 2).cgroup_name(),
+        uid("now", 1).cgroup_name(),
+        live.cgroup_name(),
+        "unrelated".to_owned(),
+    ];
+    for name in &names {
+        std::fs::create_dir_all(root.join(name)).unwrap();
+    }
+
+    let mut removed = leeward_core::isolation::cgroups::remove_stale(&root, |name| {
+        WorkerUid::from_cgroup_name(name).is_some_and(|uid| uid != live)
+    });
+    removed.sort();
+    let mut expected = vec![root.join(&names[0]), root.join(&names[1])];
+    expected.sort();
+    assert_eq!(removed, expected);
+    assert!(root.join(live.cgroup_name()).exists());
+    assert!(root.join("unrelated").exists());
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,75 @@
+//! The worker's watchdog kills code its timeout did not, and either way the
+//! result says it timed out
+
+#![cfg(feature = "protocol")]
+
+use leeward_core::result::KilledBy;
+use leeward_core::worker::{ExecuteOptions, run_python};
+use leeward_core::{ExecutionResult, OutcomeCode, SandboxConfig};
+use std::time::{Duration, Instant};
+
+const LOOPING_CODE: &str = "while True: pass\n";
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+const MARGIN: Duration = Duration::from_millis(700);
+
+fn run(code: &str, skip_timeout: bool) -> Option<(ExecutionResult, Duration)> {
+    let config = SandboxConfig::builder()
+        .timeout(TIMEOUT)
+        .watchdog_margin(MARGIN)
+        .build();
+    let options = ExecuteOptions {
+        skip_timeout,
+        ..ExecuteOptions::default()
+    };
+    let started = Instant::now();
+    let result = run_python(code, &config, &options).unwrap();
+    let elapsed = started.elapsed();
+    if result.stderr_str().starts_with("Failed to execute Python") {
+        eprintln!("skipping: {}", result.stderr_str());
+        return None;
+    }
+    Some((result, elapsed))
+}
+
+#[test]
+fn watchdog_kills_what_the_timeout_left_running() {
+    let Some((result, elapsed)) = run(LOOPING_CODE, true) else {
+        return;
+    };
+    assert!(result.timed_out, "{result:?}");
+    assert_eq!(result.killed_by, Some(KilledBy::WorkerWatchdog));
+    assert_eq!(result.outcome(), OutcomeCode::Timeout);
+    assert!(
+        elapsed >= TIMEOUT + MARGIN,
+        "watchdog fired early, after {elapsed:?}"
+    );
+    assert!(
+        elapsed < TIMEOUT + MARGIN + Duration::from_secs(1),
+        "watchdog fired late, after {elapsed:?}"
+    );
+}
+
+#[test]
+fn the_timeout_kills_first_when_it_works() {
+    let Some((result, elapsed)) = run(LOOPING_CODE, false) else {
+        return;
+    };
+    assert!(result.timed_out, "{result:?}");
+    assert_eq!(result.killed_by, Some(KilledBy::Timeout));
+    assert_eq!(result.outcome(), OutcomeCode::Timeout);
+    assert!(
+        elapsed < TIMEOUT + MARGIN,
+        "timeout fired late, after {elapsed:?}"
+    );
+}
+
+#[test]
+fn code_that_finishes_is_killed_by_nothing() {
+    let Some((result, _)) = run("print('done')", true) else {
+        return;
+    };
+    assert!(!result.timed_out);
+    assert_eq!(result.killed_by, None);
+    assert_eq!(result.stdout, b"done\n");
+}
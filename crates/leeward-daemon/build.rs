@@ -0,0 +1,7 @@
+//! Build script embedding build provenance for `leeward_core::build_info!`
+
+include!("../leeward-core/build/provenance.rs");
+
+fn main() {
+    emit_provenance();
+}
@@ -0,0 +1,54 @@
+//! Pool saturation alerts, evaluated on a timer off the request path
+
+use crate::metrics::Metrics;
+use crate::pool::WorkerPool;
+use crate::server::EventBus;
+use leeward_core::alert::AlertMonitor;
+use leeward_core::protocol::{AlertState, Event};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Sample the pool every `interval` and publish alert transitions
+///
+/// Each transition is logged, counted in `alerts_total` when firing, and
+/// sent to event subscribers.
+pub async fn run(
+    pool: Arc<WorkerPool>,
+    mut monitor: AlertMonitor,
+    interval: Duration,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+
+        for alert in monitor.evaluate(&pool.sample()) {
+            match alert.state {
+                AlertState::Firing => {
+                    tracing::warn!(
+                        alert = %alert.alert,
+                        value = alert.value,
+                        threshold = alert.threshold,
+                        "pool alert firing"
+                    );
+                    metrics.alert_fired(alert.alert);
+                }
+                AlertState::Resolved => {
+                    tracing::info!(
+                        alert = %alert.alert,
+                        value = alert.value,
+                        threshold = alert.threshold,
+                        "pool alert resolved"
+                    );
+                }
+            }
+
+            // Fails only when nobody is subscribed
+            let _ = events.send(Event::alert(alert));
+        }
+    }
+}
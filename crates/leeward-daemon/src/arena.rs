@@ -0,0 +1,121 @@
+//! Buffers reused across requests on the socket path
+//!
+//! Reading a frame and encoding its answer each want a buffer about the
+//! size of a typical message. Rather than allocating both per request, a
+//! connection borrows them from the [`RequestArena`] for as long as it
+//! handles one message and hands them back after, so a quiet connection
+//! still holds no buffer.
+//!
+//! Buffers come back only if they are no bigger than most recent messages
+//! needed (the 90th percentile, rounded up to a power of two), so one huge
+//! request does not pin its memory for good.
+
+use parking_lot::Mutex;
+use std::ops::{Deref, DerefMut};
+
+/// Buffers kept for reuse at most
+const MAX_FREE: usize = 64;
+
+/// Message sizes the retention limit is worked out from
+const WINDOW: usize = 256;
+
+/// Messages between recomputing the retention limit
+const RECOMPUTE_EVERY: usize = 64;
+
+/// Retention limit bounds, in bytes
+const MIN_RETAINED: usize = 4 * 1024;
+const MAX_RETAINED: usize = 1024 * 1024;
+
+/// Pool of buffers shared by every connection
+#[derive(Debug)]
+pub struct RequestArena {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    free: Vec<Vec<u8>>,
+    /// Lengths of recent messages, as a ring
+    recent: [u32; WINDOW],
+    /// Messages seen, whose remainder by [`WINDOW`] is the next slot
+    seen: usize,
+    /// Capacity above which buffers are dropped instead of kept
+    retain: usize,
+}
+
+impl Default for RequestArena {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                free: Vec::with_capacity(MAX_FREE),
+                recent: [0; WINDOW],
+                seen: 0,
+                retain: MIN_RETAINED,
+            }),
+        }
+    }
+}
+
+impl RequestArena {
+    /// Borrow an empty buffer, given back when dropped
+    pub fn take(&self) -> Buffer<'_> {
+        let buf = self.inner.lock().free.pop().unwrap_or_default();
+        Buffer { arena: self, buf }
+    }
+
+    fn give_back(&self, mut buf: Vec<u8>) {
+        let mut inner = self.inner.lock();
+        let slot = inner.seen % WINDOW;
+        inner.recent[slot] = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        inner.seen += 1;
+        if inner.seen % RECOMPUTE_EVERY == 0 {
+            inner.retain = retention_limit(&inner.recent[..inner.seen.min(WINDOW)]);
+        }
+
+        if buf.capacity() > 0 && buf.capacity() <= inner.retain && inner.free.len() < MAX_FREE {
+            buf.clear();
+            inner.free.push(buf);
+        }
+    }
+}
+
+/// The 90th percentile of `sizes`, rounded up to a power of two and held
+/// within the retention bounds
+fn retention_limit(sizes: &[u32]) -> usize {
+    let mut sorted = [0; WINDOW];
+    let sorted = &mut sorted[..sizes.len()];
+    sorted.copy_from_slice(sizes);
+    let at = sorted.len() * 9 / 10;
+    let (_, p90, _) = sorted.select_nth_unstable(at.min(sorted.len() - 1));
+    (*p90 as usize)
+        .checked_next_power_of_two()
+        .unwrap_or(MAX_RETAINED)
+        .clamp(MIN_RETAINED, MAX_RETAINED)
+}
+
+/// A buffer borrowed from a [`RequestArena`]
+#[derive(Debug)]
+pub struct Buffer<'a> {
+    arena: &'a RequestArena,
+    buf: Vec<u8>,
+}
+
+impl Deref for Buffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for Buffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        self.arena.give_back(std::mem::take(&mut self.buf));
+    }
+}
@@ -0,0 +1,132 @@
+//! The audit log: each point at which the effective policy changed
+//!
+//! Startup, a config reload and a profile drain each leave a
+//! [`PolicyChange`] with the config fingerprint before and after and a diff
+//! of the fields that changed, secret-bearing ones redacted. Records are
+//! appended to `audit_log` as JSON lines, tagged with their `type`, by a
+//! thread of their own through a [`JsonlWriter`], so each is synced whole:
+//! whoever records never waits on the disk, and a
+//! record that finds the channel full is dropped with a warning. The
+//! latest [`RECENT`] are also kept for [`Request::PolicyChanges`] and
+//! published to subscribers as they happen.
+//!
+//! [`Request::PolicyChanges`]: leeward_core::protocol::Request::PolicyChanges
+
+use crate::server::EventBus;
+use crate::storage::JsonlWriter;
+use leeward_core::SandboxConfig;
+use leeward_core::policy::{PolicyActor, PolicyChange};
+use leeward_core::protocol::Event;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+
+/// Policy changes kept in memory
+pub const RECENT: usize = 256;
+
+/// Records waiting for the writer before new ones are dropped
+const BUFFER: usize = 1024;
+
+/// One line of the audit log
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    PolicyChange(&'a PolicyChange),
+}
+
+/// Where audit records go
+pub struct Audit {
+    /// Lines for the writer thread, if there is a log file
+    lines: Option<SyncSender<String>>,
+    recent: Mutex<VecDeque<PolicyChange>>,
+    events: EventBus,
+    /// Config fields shown only as set or unset
+    secret_fields: Vec<String>,
+}
+
+impl Audit {
+    /// Append records to `path`, if set, and publish them on `events`
+    ///
+    /// A log that cannot be opened is warned about and left out; records
+    /// are still kept and published.
+    pub fn open(path: Option<&Path>, secret_fields: Vec<String>, events: EventBus) -> Self {
+        let lines = path.and_then(|path| match spawn_writer(path) {
+            Ok(lines) => Some(lines),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "audit log disabled");
+                None
+            }
+        });
+        Self {
+            lines,
+            recent: Mutex::new(VecDeque::new()),
+            events,
+            secret_fields,
+        }
+    }
+
+    /// Record that `actor` moved the policy from `before` (`None` at
+    /// startup) to `after`, for `reason` if given
+    pub fn policy_change(
+        &self,
+        actor: PolicyActor,
+        before: Option<&SandboxConfig>,
+        after: &SandboxConfig,
+        reason: Option<String>,
+    ) {
+        let change =
+            PolicyChange::new(actor, before, after, &self.secret_fields).with_reason(reason);
+        tracing::info!(
+            actor = %change.actor,
+            fingerprint = %change.fingerprint_after,
+            fields = change.changes.len(),
+            "policy changed"
+        );
+
+        if let Some(lines) = &self.lines {
+            match serde_json::to_string(&Record::PolicyChange(&change)) {
+                Ok(line) => match lines.try_send(line) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        tracing::warn!("audit log behind; policy change record dropped");
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        tracing::warn!("audit log writer gone; record dropped");
+                    }
+                },
+                Err(e) => tracing::warn!(error = %e, "policy change record not serializable"),
+            }
+        }
+
+        let mut recent = self.recent.lock();
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(change.clone());
+        drop(recent);
+        let _ = self.events.send(Event::policy_change(change));
+    }
+
+    /// The latest policy changes, oldest first
+    pub fn policy_changes(&self) -> Vec<PolicyChange> {
+        self.recent.lock().iter().cloned().collect()
+    }
+}
+
+/// Open `path` for appending and start the thread writing to it
+fn spawn_writer(path: &Path) -> std::io::Result<SyncSender<String>> {
+    let mut file = JsonlWriter::open(path)?;
+    let (lines, received) = mpsc::sync_channel::<String>(BUFFER);
+    std::thread::Builder::new()
+        .name("leeward-audit".into())
+        .spawn(move || {
+            for line in received {
+                if let Err(e) = file.append(&line) {
+                    tracing::warn!(error = %e, "failed to write the audit log");
+                }
+            }
+        })?;
+    Ok(lines)
+}
@@ -1,11 +1,20 @@
 //! Daemon configuration
 
 use leeward_core::SandboxConfig;
+use leeward_core::alert::AlertThresholds;
+use leeward_core::config::SchedPolicy;
+use leeward_core::isolation::{Enforcement, LandlockStatus};
+use leeward_core::policy::SECRET_FIELDS;
+use leeward_core::protocol::{RequestPriority, ShmOverflowPolicy, SizeLimits};
+use leeward_core::units::{self, ByteSize, DurationSecs};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 /// Configuration for the daemon
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct DaemonConfig {
     /// Path to Unix socket
     pub socket_path: PathBuf,
@@ -13,12 +22,169 @@ pub struct DaemonConfig {
     /// Number of workers in the pool
     pub num_workers: usize,
 
+    /// Warm workers held back from normal scheduling, on top of
+    /// `num_workers`, for high-priority requests that would otherwise queue
+    pub standby_workers: usize,
+
     /// Recycle workers after this many executions
     pub recycle_after: u64,
 
     /// Sandbox configuration for workers
     pub sandbox_config: SandboxConfig,
 
+    /// Nice value and scheduling policy per request priority, applied on
+    /// top of the sandbox config
+    pub priority_scheduling: PriorityScheduling,
+
+    /// Build the sandbox root once and share it read-only across workers
+    pub root_template: bool,
+
+    /// Delegated cgroup v2 directory each worker process gets a cgroup of its own
+    /// under, for the peak memory and OOM kills of its executions; it must
+    /// not hold the daemon itself
+    pub cgroup_root: Option<PathBuf>,
+
+    /// Warn when the sandbox memory limit is below this
+    pub memory_limit_floor: ByteSize,
+
+    /// Stop dispatching after this many consecutive interpreter startup
+    /// failures under the same config (0 = never)
+    pub startup_failure_limit: u32,
+
+    /// Wait this long before restarting a daemon task that panicked,
+    /// doubling for each panic in a row
+    pub task_restart_backoff: DurationSecs,
+
+    /// Put the daemon in maintenance once a task panics this many times in
+    /// a row, each within `task_failure_window` of starting (0 = never)
+    pub task_failure_limit: u32,
+
+    /// A task that runs this long before panicking starts its count of
+    /// panics in a row again
+    pub task_failure_window: DurationSecs,
+
+    /// Alert when this many requests are waiting for a worker (0 = off)
+    pub alert_queue_depth: u64,
+
+    /// Alert when the oldest queued request has waited this long (0 = off)
+    pub alert_queue_wait: DurationSecs,
+
+    /// Alert when this many workers are dead (0 = off)
+    pub alert_worker_dead_count: u64,
+
+    /// Alert when this many workers run an interpreter binary that has
+    /// since changed on disk, suggesting a drain (0 = off)
+    pub alert_interpreter_changed: u64,
+
+    /// How often alert thresholds are evaluated
+    pub alert_sample_interval: DurationSecs,
+
+    /// Consecutive samples below a threshold before its alert resolves
+    pub alert_clear_samples: u32,
+
+    /// How often the pool snapshot status requests read is refreshed, on
+    /// top of every worker state change
+    pub status_refresh_interval: DurationSecs,
+
+    /// How often idle workers are checked for a process that died, on top
+    /// of the check before each dispatch (0 = only then)
+    pub worker_check_interval: DurationSecs,
+
+    /// Idle workers a health probe needs to see to answer healthy, unless
+    /// it asks for another number
+    pub health_min_idle: usize,
+
+    /// Close connections with no request in flight and no subscription
+    /// after this long without a request (zero = never)
+    pub idle_connection_timeout: DurationSecs,
+
+    /// Run small requests under default limits inline on the connection's
+    /// task when a worker is idle, instead of through the queue
+    pub fast_path: bool,
+
+    /// Answer a request with an internal error if it has not completed
+    /// after this long, plus however far its own timeout exceeds the
+    /// sandbox's (0 = never). A backstop against daemon bugs, so well
+    /// above the sandbox timeout and any expected queueing.
+    pub max_request_wall: DurationSecs,
+
+    /// Cut request timeouts longer than this down to it, noting it in the
+    /// response (0 = no cap)
+    pub max_timeout: DurationSecs,
+
+    /// Executions one connection may have in flight (0 = no limit). A
+    /// connection sends one request at a time, so this only bites on
+    /// executions still running after their request overran its deadline.
+    pub max_inflight_per_connection: usize,
+
+    /// Executions one client uid may have in flight across all of its
+    /// connections (0 = no limit), so a single client cannot take every
+    /// worker
+    pub max_inflight_per_peer_uid: usize,
+
+    /// Unfinished and committed uploads one client uid may hold at once
+    /// (0 = uploads disabled)
+    pub upload_quota: ByteSize,
+
+    /// Drop an upload this long after it was last touched
+    pub upload_ttl: DurationSecs,
+
+    /// Sizes requests and their output are held to, advertised as they
+    /// are in `Hello`; the request and output sizes cannot be raised past
+    /// the protocol's
+    pub sizes: SizeLimits,
+
+    /// Directory keeping the results of detached executions until they
+    /// are fetched, across restarts of the daemon
+    pub spool_dir: PathBuf,
+
+    /// Detached results one client uid may have waiting at once
+    /// (0 = detaching disabled)
+    pub spool_quota: ByteSize,
+
+    /// Delete a detached result this long after it was written, if it has
+    /// not been fetched
+    pub spool_ttl: DurationSecs,
+
+    /// Take back a shared memory slot no execution has used for this long
+    /// (0 = only when its connection closes)
+    pub shm_slot_ttl: DurationSecs,
+
+    /// What to do with output too big for a shared memory response slot,
+    /// for executions that do not say
+    pub shm_overflow: ShmOverflowPolicy,
+
+    /// Freeze a batch execution that has run this long since it started
+    /// or was last thawed, while other executions run, and thaw it once
+    /// they are done (0 = never freeze)
+    pub batch_slice: DurationSecs,
+
+    /// Longest a frozen batch execution may take in all, running and
+    /// frozen; it is thawed for good once it would need more to finish
+    /// within its timeout
+    pub batch_max_wall: DurationSecs,
+
+    /// Look for leaked template roots, mounts and worker cgroups this often (0 = never)
+    pub reconcile_interval: DurationSecs,
+
+    /// Leave a leaked root alone until it is this old
+    pub reconcile_grace: DurationSecs,
+
+    /// TOML file of rules code is screened against before it is
+    /// dispatched, read again on `SIGHUP`; see [`crate::screening`]
+    pub screening_rules: Option<PathBuf>,
+
+    /// File policy changes are appended to as JSON lines, if any; they are
+    /// kept in memory and published to subscribers either way
+    pub audit_log: Option<PathBuf>,
+
+    /// Sandbox config fields a policy change shows only as set or unset,
+    /// along with everything under them, such as `env`
+    pub audit_secret_fields: Vec<String>,
+
+    /// Refuse to start on a kernel whose Landlock would restrict nothing
+    pub require_landlock: bool,
+
     /// Enable metrics endpoint
     pub metrics_enabled: bool,
 
@@ -31,10 +197,468 @@ impl Default for DaemonConfig {
         Self {
             socket_path: leeward_core::config::default_socket_path(),
             num_workers: 4,
+            standby_workers: 0,
             recycle_after: 100,
             sandbox_config: SandboxConfig::default(),
+            priority_scheduling: PriorityScheduling::default(),
+            root_template: false,
+            cgroup_root: None,
+            memory_limit_floor: ByteSize::mib(32),
+            startup_failure_limit: 3,
+            task_restart_backoff: DurationSecs::from_millis(100),
+            task_failure_limit: 5,
+            task_failure_window: DurationSecs::from_secs(60),
+            alert_queue_depth: 8,
+            alert_queue_wait: DurationSecs::from_secs(5),
+            alert_worker_dead_count: 1,
+            alert_interpreter_changed: 1,
+            alert_sample_interval: DurationSecs::from_secs(1),
+            alert_clear_samples: 3,
+            status_refresh_interval: DurationSecs::from_secs(1),
+            worker_check_interval: DurationSecs::from_secs(1),
+            health_min_idle: 1,
+            idle_connection_timeout: DurationSecs::from_secs(300),
+            fast_path: false,
+            // The default sandbox timeout, a minute of queueing and 30s to spare
+            max_request_wall: DurationSecs::from_secs(120),
+            max_timeout: DurationSecs::ZERO,
+            max_inflight_per_connection: 0,
+            max_inflight_per_peer_uid: 0,
+            upload_quota: ByteSize::gib(1),
+            upload_ttl: DurationSecs::from_secs(600),
+            sizes: SizeLimits::default(),
+            spool_dir: PathBuf::from("/var/lib/leeward/spool"),
+            spool_quota: ByteSize::mib(64),
+            spool_ttl: DurationSecs::from_secs(3600),
+            shm_slot_ttl: DurationSecs::from_secs(300),
+            shm_overflow: ShmOverflowPolicy::default(),
+            batch_slice: DurationSecs::ZERO,
+            batch_max_wall: DurationSecs::from_secs(3600),
+            reconcile_interval: DurationSecs::from_secs(300),
+            reconcile_grace: DurationSecs::from_secs(600),
+            screening_rules: None,
+            audit_log: None,
+            audit_secret_fields: SECRET_FIELDS.map(String::from).to_vec(),
+            require_landlock: false,
             metrics_enabled: true,
             metrics_port: 9090,
         }
     }
 }
+
+impl DaemonConfig {
+    /// Defaults, with overrides from the environment
+    ///
+    /// `LEEWARD_SOCKET` picks the socket path, `LEEWARD_WORKERS` the pool
+    /// size, `LEEWARD_STANDBY_WORKERS` the standby workers on top of it,
+    /// `LEEWARD_IDLE_CONNECTION_TIMEOUT` the idle timeout,
+    /// `LEEWARD_FAST_PATH` turns the fast path on or off,
+    /// `LEEWARD_MAX_REQUEST_WALL` the request deadline,
+    /// `LEEWARD_MAX_TIMEOUT` the cap on request timeouts,
+    /// `LEEWARD_MAX_INFLIGHT_PER_CONNECTION` and
+    /// `LEEWARD_MAX_INFLIGHT_PER_PEER_UID` the in-flight limits,
+    /// `LEEWARD_UPLOAD_QUOTA` and `LEEWARD_UPLOAD_TTL` the upload quota and
+    /// lifetime, `LEEWARD_MAX_REQUEST`, `LEEWARD_MAX_CODE`,
+    /// `LEEWARD_MAX_FILE`, `LEEWARD_MAX_FILES`, `LEEWARD_MAX_STDIN` and
+    /// `LEEWARD_MAX_OUTPUT` the size limits, `LEEWARD_SPOOL_DIR`,
+    /// `LEEWARD_SPOOL_QUOTA` and `LEEWARD_SPOOL_TTL` where detached results
+    /// are kept, how many and how long, `LEEWARD_SHM_SLOT_TTL` how long a
+    /// shared memory slot may sit unused, `LEEWARD_SHM_OVERFLOW` what to do
+    /// with output too big for it, `LEEWARD_BATCH_SLICE` and `LEEWARD_BATCH_MAX_WALL` batch
+    /// time slicing,
+    /// `LEEWARD_RECONCILE_INTERVAL` and `LEEWARD_RECONCILE_GRACE` the
+    /// reaping of leaked roots, `LEEWARD_SCREENING_RULES` the code
+    /// screening rules file, `LEEWARD_AUDIT_LOG` the audit log and
+    /// `LEEWARD_AUDIT_SECRET_FIELDS` the comma-separated fields it redacts,
+    /// `LEEWARD_REQUIRE_LANDLOCK` whether Landlock must be enforced,
+    /// `LEEWARD_CGROUP_ROOT` where workers get cgroups of their own,
+    /// `LEEWARD_STATUS_REFRESH_INTERVAL` how often status is refreshed,
+    /// `LEEWARD_WORKER_CHECK_INTERVAL` how often idle workers are checked,
+    /// `LEEWARD_HEALTH_MIN_IDLE` the idle workers a health probe needs,
+    /// `LEEWARD_TASK_RESTART_BACKOFF`, `LEEWARD_TASK_FAILURE_LIMIT` and
+    /// `LEEWARD_TASK_FAILURE_WINDOW` how panicking daemon tasks are
+    /// restarted,
+    /// `LEEWARD_TIMEZONE` sets the sandbox timezone,
+    /// `LEEWARD_TMP_SIZE` the size of the sandbox `/tmp`,
+    /// `LEEWARD_WORKER_MAX_RSS` the memory a worker process may grow to,
+    /// `LEEWARD_CPU_PERCENT` and `LEEWARD_MAX_PIDS` the CPU share and
+    /// processes a worker's cgroup is held to,
+    /// `LEEWARD_PRESSURE_SIGNAL_PERCENT` the share of the memory limit at
+    /// which code is sent `SIGUSR1`, `LEEWARD_STRICT_PATHS` whether a
+    /// missing bind or Landlock path stops workers spawning,
+    /// `LEEWARD_METRICS_PORT` the metrics port, and `LEEWARD_ALERT_*` the
+    /// alert fields of the same name.
+    ///
+    /// Sizes and durations take a unit, as in `64MiB` or `1500ms`. The
+    /// variables they replace, named for their unit such as
+    /// `LEEWARD_UPLOAD_QUOTA_BYTES` or `LEEWARD_BATCH_SLICE_MS`, are still
+    /// read in that unit for one more release, with a warning, as are bare
+    /// numbers, which mean bytes or seconds.
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        env_override("LEEWARD_WORKERS", &mut config.num_workers);
+        env_override("LEEWARD_STANDBY_WORKERS", &mut config.standby_workers);
+        env_override("LEEWARD_ALERT_QUEUE_DEPTH", &mut config.alert_queue_depth);
+        env_unit(
+            "LEEWARD_ALERT_QUEUE_WAIT",
+            MILLIS,
+            &mut config.alert_queue_wait,
+        );
+        env_override(
+            "LEEWARD_ALERT_WORKER_DEAD_COUNT",
+            &mut config.alert_worker_dead_count,
+        );
+        env_override(
+            "LEEWARD_ALERT_INTERPRETER_CHANGED",
+            &mut config.alert_interpreter_changed,
+        );
+        env_unit(
+            "LEEWARD_ALERT_SAMPLE_INTERVAL",
+            MILLIS,
+            &mut config.alert_sample_interval,
+        );
+        env_override(
+            "LEEWARD_ALERT_CLEAR_SAMPLES",
+            &mut config.alert_clear_samples,
+        );
+        env_unit(
+            "LEEWARD_STATUS_REFRESH_INTERVAL",
+            MILLIS,
+            &mut config.status_refresh_interval,
+        );
+        env_unit(
+            "LEEWARD_WORKER_CHECK_INTERVAL",
+            MILLIS,
+            &mut config.worker_check_interval,
+        );
+        env_override("LEEWARD_HEALTH_MIN_IDLE", &mut config.health_min_idle);
+        env_unit(
+            "LEEWARD_TASK_RESTART_BACKOFF",
+            MILLIS,
+            &mut config.task_restart_backoff,
+        );
+        env_override("LEEWARD_TASK_FAILURE_LIMIT", &mut config.task_failure_limit);
+        env_unit(
+            "LEEWARD_TASK_FAILURE_WINDOW",
+            SECS,
+            &mut config.task_failure_window,
+        );
+        env_unit(
+            "LEEWARD_IDLE_CONNECTION_TIMEOUT",
+            MILLIS,
+            &mut config.idle_connection_timeout,
+        );
+        env_override("LEEWARD_FAST_PATH", &mut config.fast_path);
+        env_override("LEEWARD_REQUIRE_LANDLOCK", &mut config.require_landlock);
+        env_unit(
+            "LEEWARD_MAX_REQUEST_WALL",
+            SECS,
+            &mut config.max_request_wall,
+        );
+        env_unit("LEEWARD_MAX_TIMEOUT", SECS, &mut config.max_timeout);
+        env_override(
+            "LEEWARD_MAX_INFLIGHT_PER_CONNECTION",
+            &mut config.max_inflight_per_connection,
+        );
+        env_override(
+            "LEEWARD_MAX_INFLIGHT_PER_PEER_UID",
+            &mut config.max_inflight_per_peer_uid,
+        );
+        env_unit("LEEWARD_UPLOAD_QUOTA", BYTES, &mut config.upload_quota);
+        env_unit("LEEWARD_UPLOAD_TTL", SECS, &mut config.upload_ttl);
+        sizes_from_env(&mut config.sizes);
+        env_override("LEEWARD_SPOOL_DIR", &mut config.spool_dir);
+        env_unit("LEEWARD_SPOOL_QUOTA", BYTES, &mut config.spool_quota);
+        env_unit("LEEWARD_SPOOL_TTL", SECS, &mut config.spool_ttl);
+        env_unit("LEEWARD_SHM_SLOT_TTL", SECS, &mut config.shm_slot_ttl);
+        env_override("LEEWARD_SHM_OVERFLOW", &mut config.shm_overflow);
+        env_unit("LEEWARD_BATCH_SLICE", MILLIS, &mut config.batch_slice);
+        env_unit("LEEWARD_BATCH_MAX_WALL", SECS, &mut config.batch_max_wall);
+        env_unit(
+            "LEEWARD_RECONCILE_INTERVAL",
+            SECS,
+            &mut config.reconcile_interval,
+        );
+        env_unit("LEEWARD_RECONCILE_GRACE", SECS, &mut config.reconcile_grace);
+        env_some("LEEWARD_SCREENING_RULES", &mut config.screening_rules);
+        env_some("LEEWARD_AUDIT_LOG", &mut config.audit_log);
+        if let Ok(fields) = std::env::var("LEEWARD_AUDIT_SECRET_FIELDS") {
+            config.audit_secret_fields = fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        env_some("LEEWARD_CGROUP_ROOT", &mut config.cgroup_root);
+        sandbox_from_env(&mut config.sandbox_config);
+        env_override("LEEWARD_METRICS_PORT", &mut config.metrics_port);
+        config
+    }
+
+    /// Longest timeout a request may set, if capped
+    #[must_use]
+    pub const fn max_timeout(&self) -> Option<Duration> {
+        self.max_timeout.non_zero()
+    }
+
+    /// Batch time slicing, if enabled
+    #[must_use]
+    pub fn time_slicing(&self) -> Option<TimeSlicing> {
+        self.batch_slice.non_zero().map(|slice| TimeSlicing {
+            slice,
+            max_wall: self.batch_max_wall.get(),
+        })
+    }
+
+    /// How often to check idle workers on a timer, if at all
+    #[must_use]
+    pub const fn worker_check_interval(&self) -> Option<Duration> {
+        self.worker_check_interval.non_zero()
+    }
+
+    /// How often to reap leaked roots, if at all
+    #[must_use]
+    pub const fn reconcile_interval(&self) -> Option<Duration> {
+        self.reconcile_interval.non_zero()
+    }
+
+    /// How daemon tasks that panic are restarted
+    #[must_use]
+    pub const fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            backoff: self.task_restart_backoff.get(),
+            failure_limit: self.task_failure_limit,
+            failure_window: self.task_failure_window.get(),
+        }
+    }
+
+    /// Alert thresholds, with 0 meaning disabled
+    #[must_use]
+    pub fn alert_thresholds(&self) -> AlertThresholds {
+        let enabled = |threshold: u64| (threshold > 0).then_some(threshold);
+        AlertThresholds {
+            queue_depth: enabled(self.alert_queue_depth),
+            queue_wait_ms: enabled(self.alert_queue_wait.as_millis_u64()),
+            worker_dead_count: enabled(self.alert_worker_dead_count),
+            interpreter_changed: enabled(self.alert_interpreter_changed),
+        }
+    }
+
+    /// Log the Landlock workers get on this kernel, failing if none is
+    /// enforced and `require_landlock` is set
+    ///
+    /// # Errors
+    ///
+    /// Why, if no Landlock is enforced and `require_landlock` is set.
+    pub fn check_landlock(&self) -> Result<LandlockStatus, String> {
+        let status = LandlockStatus::probe();
+        match status.enforcement {
+            Enforcement::Full => tracing::info!(abi = status.abi, "Landlock {status}"),
+            Enforcement::Partial => tracing::warn!(
+                abi = status.abi,
+                "Landlock {status}, older than this build knows"
+            ),
+            Enforcement::NotEnforced if self.require_landlock => {
+                return Err("require_landlock is set but this kernel has no Landlock".into());
+            }
+            Enforcement::NotEnforced => {
+                tracing::warn!("Landlock not enforced, this kernel has none");
+            }
+        }
+        Ok(status)
+    }
+
+    /// Warn about a memory limit the interpreter is unlikely to start under
+    pub fn warn_if_below_floor(&self) {
+        let floor = self.memory_limit_floor;
+        if let Some(limit) = self
+            .sandbox_config
+            .memory_limit
+            .filter(|&limit| limit < floor)
+        {
+            tracing::warn!(
+                memory_limit = %limit,
+                %floor,
+                "memory limit is below the configured floor; the interpreter may fail to start"
+            );
+        }
+    }
+}
+
+/// How daemon tasks that panic are restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Wait before the first restart, doubled for each panic in a row
+    pub backoff: Duration,
+    /// Panics in a row that put the daemon in maintenance (0 = never)
+    pub failure_limit: u32,
+    /// A run at least this long starts the count of panics in a row again
+    pub failure_window: Duration,
+}
+
+/// How batch executions share the pool with other work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSlicing {
+    /// Running time a batch execution gets before it may be frozen
+    pub slice: Duration,
+    /// Longest a batch execution may take, running and frozen
+    pub max_wall: Duration,
+}
+
+impl TimeSlicing {
+    /// Longest an execution with `timeout` may spend frozen
+    #[must_use]
+    pub const fn frozen_budget(&self, timeout: Duration) -> Duration {
+        self.max_wall.saturating_sub(timeout)
+    }
+
+    /// How often to look for executions to freeze or thaw: a few times a
+    /// slice
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        (self.slice / 4).clamp(Duration::from_millis(10), Duration::from_secs(1))
+    }
+}
+
+/// Scheduling overrides for one request priority (unset fields are inherited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scheduling {
+    pub nice: Option<i8>,
+    pub sched_policy: Option<SchedPolicy>,
+}
+
+/// Scheduling overrides for each request priority
+///
+/// By default batch and low-priority requests run as `SCHED_BATCH` at
+/// nice 10 and the others inherit the sandbox config. Setting `high` to nice 0 and
+/// `SchedPolicy::Other` pins interactive requests to the usual class even
+/// when the sandbox config lowers everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriorityScheduling {
+    pub batch: Scheduling,
+    pub low: Scheduling,
+    pub normal: Scheduling,
+    pub high: Scheduling,
+}
+
+impl Default for PriorityScheduling {
+    fn default() -> Self {
+        let background = Scheduling {
+            nice: Some(10),
+            sched_policy: Some(SchedPolicy::Batch),
+        };
+        Self {
+            batch: background,
+            low: background,
+            normal: Scheduling::default(),
+            high: Scheduling::default(),
+        }
+    }
+}
+
+impl PriorityScheduling {
+    /// Overrides for requests of `priority`
+    #[must_use]
+    pub const fn get(&self, priority: RequestPriority) -> Scheduling {
+        match priority {
+            RequestPriority::Batch => self.batch,
+            RequestPriority::Low => self.low,
+            RequestPriority::Normal => self.normal,
+            RequestPriority::High => self.high,
+        }
+    }
+}
+
+/// Apply the `LEEWARD_MAX_*` overrides of the size limits
+fn sizes_from_env(sizes: &mut SizeLimits) {
+    env_override("LEEWARD_MAX_REQUEST", &mut sizes.max_request);
+    env_override("LEEWARD_MAX_CODE", &mut sizes.max_code);
+    env_override("LEEWARD_MAX_FILE", &mut sizes.max_file);
+    env_override("LEEWARD_MAX_FILES", &mut sizes.max_files);
+    env_override("LEEWARD_MAX_STDIN", &mut sizes.max_stdin);
+    env_override("LEEWARD_MAX_OUTPUT", &mut sizes.max_output);
+}
+
+/// Apply the `LEEWARD_*` overrides of the sandbox's settings
+fn sandbox_from_env(sandbox: &mut SandboxConfig) {
+    env_some("LEEWARD_TIMEZONE", &mut sandbox.timezone);
+    env_unit("LEEWARD_TMP_SIZE", BYTES, &mut sandbox.tmp_size);
+    env_some("LEEWARD_WORKER_MAX_RSS", &mut sandbox.worker_max_rss);
+    env_some("LEEWARD_CPU_PERCENT", &mut sandbox.cpu_percent);
+    env_some("LEEWARD_MAX_PIDS", &mut sandbox.max_pids);
+    env_some(
+        "LEEWARD_PRESSURE_SIGNAL_PERCENT",
+        &mut sandbox.pressure_signal_percent,
+    );
+    env_some("LEEWARD_PIPE_BUFFER_SIZE", &mut sandbox.pipe_buffer_size);
+    env_override("LEEWARD_STRICT_PATHS", &mut sandbox.strict_paths);
+}
+
+/// Replace `field` with the value of `var`, if set and valid
+fn env_override<T: FromStr>(var: &str, field: &mut T) {
+    let Ok(value) = std::env::var(var) else {
+        return;
+    };
+    if let Ok(parsed) = value.parse() {
+        *field = parsed;
+    } else {
+        tracing::warn!(var, value, "ignoring invalid config override");
+    }
+}
+
+/// Like [`env_override`], for a field that is unset by default
+fn env_some<T: FromStr>(var: &str, field: &mut Option<T>) {
+    let Ok(value) = std::env::var(var) else {
+        return;
+    };
+    if let Ok(parsed) = value.parse() {
+        *field = Some(parsed);
+    } else {
+        tracing::warn!(var, value, "ignoring invalid config override");
+    }
+}
+
+/// How the variable an [`env_unit`] variable replaces counted its value
+type Legacy<T> = (&'static str, fn(u64) -> T);
+
+const BYTES: Legacy<ByteSize> = ("_BYTES", ByteSize::from_bytes);
+const SECS: Legacy<DurationSecs> = ("_SECS", DurationSecs::from_secs);
+const MILLIS: Legacy<DurationSecs> = ("_MS", DurationSecs::from_millis);
+
+/// Replace `field` with the size or duration in `var`, if set and valid,
+/// or else with the count in the deprecated variable named `var` plus the
+/// suffix of `legacy`, in that variable's unit
+fn env_unit<T: FromStr>(var: &str, legacy: Legacy<T>, field: &mut T) {
+    if let Ok(value) = std::env::var(var) {
+        if units::is_bare(&value) {
+            tracing::warn!(
+                var,
+                value,
+                "a number without a unit is deprecated; add one, such as MiB or s"
+            );
+        }
+        if let Ok(parsed) = value.parse() {
+            *field = parsed;
+        } else {
+            tracing::warn!(var, value, "ignoring invalid config override");
+        }
+        return;
+    }
+    let (suffix, from_count) = legacy;
+    let old = format!("{var}{suffix}");
+    let Ok(value) = std::env::var(&old) else {
+        return;
+    };
+    tracing::warn!(
+        var = old,
+        replacement = var,
+        "config variable is deprecated"
+    );
+    if let Ok(count) = value.parse() {
+        *field = from_count(count);
+    } else {
+        tracing::warn!(var = old, value, "ignoring invalid config override");
+    }
+}
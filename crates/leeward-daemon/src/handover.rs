@@ -0,0 +1,242 @@
+//! Zero-downtime handover of the socket to a new daemon process
+//!
+//! A daemon started with `--takeover` spawns its workers, then connects to
+//! the running daemon's socket and sends [`Request::Handover`]. The old
+//! daemon answers with its listening socket and the staging file of each
+//! upload attached, and stops accepting. Both processes hold the same
+//! listening socket throughout, so a client connecting mid-handover is
+//! accepted by one of them and never refused.
+//!
+//! The old daemon then closes each connection once it has nothing in
+//! flight (after [`HANDOVER_LINGER`], so a request already on its way is
+//! still served), ends subscriptions, and exits with its workers when the
+//! last connection is gone. Until then it reports its open connections to
+//! the new daemon, and both show the overlap in [`Response::Status`].
+//!
+//! Uploads keep their ids. At most [`socket::MAX_FDS`] - 1 go over, most
+//! recently touched first. Chunks the old daemon takes after handing over
+//! reach the same file but not the new daemon's record of it, so they are
+//! reported missing again and resent by clients that resume.
+
+use crate::uploads::Uploads;
+use leeward_core::protocol::{
+    self, HandoverRole, HandoverState, HandoverStatus, Request, Response,
+};
+use leeward_core::socket;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::watch;
+
+/// How long a connection of the old daemon may stay quiet after the
+/// handover before it is closed
+pub const HANDOVER_LINGER: Duration = Duration::from_secs(1);
+
+/// How often the old daemon reports and checks its open connections
+pub const HANDOVER_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// This daemon's part in a handover, shared by its connections
+#[derive(Debug)]
+pub struct Handover {
+    /// Flips to true once the listener has gone to a successor
+    handed_over: watch::Sender<bool>,
+    /// The other daemon while the two overlap
+    status: Mutex<Option<HandoverStatus>>,
+}
+
+impl Default for Handover {
+    fn default() -> Self {
+        Self {
+            handed_over: watch::Sender::new(false),
+            status: Mutex::new(None),
+        }
+    }
+}
+
+impl Handover {
+    /// The overlap with the other daemon, if one is under way
+    pub fn status(&self) -> Option<HandoverStatus> {
+        *self.status.lock()
+    }
+
+    /// Wait until the listener has gone to a successor
+    pub async fn wait(&self) {
+        let mut handed_over = self.handed_over.subscribe();
+        // The sender lives as long as `self`
+        let _ = handed_over.wait_for(|handed_over| *handed_over).await;
+    }
+
+    /// Claim the listener for the successor `pid`; false if another
+    /// handover already has
+    pub fn begin(&self, pid: Option<u32>) -> bool {
+        let mut status = self.status.lock();
+        if status.is_some_and(|status| status.role == HandoverRole::Predecessor) {
+            return false;
+        }
+        *status = Some(HandoverStatus {
+            role: HandoverRole::Predecessor,
+            peer_pid: pid,
+            predecessor_connections: 0,
+        });
+        true
+    }
+
+    /// Give the listener back after a handover failed part way
+    pub fn abort(&self) {
+        *self.status.lock() = None;
+    }
+
+    /// The successor has the listener: stop accepting
+    pub fn finish(&self) {
+        self.handed_over.send_replace(true);
+    }
+
+    /// Note how many connections the old daemon still has open
+    pub fn set_predecessor_connections(&self, connections: usize) {
+        if let Some(status) = self.status.lock().as_mut() {
+            status.predecessor_connections = connections;
+        }
+    }
+
+    /// Follow the old daemon on `progress` until it has closed its last
+    /// connection
+    pub async fn follow(&self, pid: u32, progress: UnixStream) {
+        *self.status.lock() = Some(HandoverStatus {
+            role: HandoverRole::Successor,
+            peer_pid: Some(pid),
+            predecessor_connections: 0,
+        });
+
+        let progress = progress
+            .set_nonblocking(true)
+            .and_then(|()| tokio::net::UnixStream::from_std(progress));
+        if let Ok(mut progress) = progress {
+            while let Ok(Response::HandoverProgress { connections }) =
+                read_response(&mut progress).await
+            {
+                tracing::debug!(connections, "old daemon still finishing connections");
+                self.set_predecessor_connections(connections);
+                if connections == 0 {
+                    break;
+                }
+            }
+        }
+
+        tracing::info!(pid, "old daemon finished its connections");
+        *self.status.lock() = None;
+    }
+}
+
+/// The socket and state taken over from the old daemon
+pub struct Inherited {
+    pub listener: UnixListener,
+    pub state: HandoverState,
+    /// Staging file of each of `state.uploads`
+    pub files: Vec<File>,
+    /// Where the old daemon reports its progress
+    pub progress: UnixStream,
+}
+
+/// Ask the daemon serving `path` to hand over its socket and uploads
+pub fn take_over(path: &Path) -> io::Result<Inherited> {
+    let mut stream = socket::connect(path).map_err(io::Error::other)?;
+    let body = protocol::encode(&Request::Handover).map_err(io::Error::other)?;
+    let len = u32::try_from(body.len()).map_err(io::Error::other)?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(&body)?;
+
+    // The descriptors come with the first bytes of the answer
+    let mut len = [0u8; 4];
+    let (read, mut fds) = socket::recv_with_fds(stream.as_fd(), &mut len)?;
+    if read == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the running daemon closed the connection",
+        ));
+    }
+    stream.read_exact(&mut len[read..])?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > protocol::MAX_MESSAGE_SIZE {
+        return Err(io::Error::other(format!(
+            "answer of {len} bytes exceeds the message size limit"
+        )));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+
+    let state = match protocol::decode(&body).map_err(io::Error::other)? {
+        Response::Handover(state) => state,
+        Response::Error { message, .. } => return Err(io::Error::other(message)),
+        other => {
+            return Err(io::Error::other(format!(
+                "unexpected answer to a handover: {other:?}"
+            )));
+        }
+    };
+    if fds.len() != state.uploads.len() + 1 {
+        return Err(io::Error::other(format!(
+            "handover sent {} descriptors for {} uploads",
+            fds.len(),
+            state.uploads.len()
+        )));
+    }
+
+    let listener = UnixListener::from(fds.remove(0));
+    let files = fds.into_iter().map(File::from).collect();
+    Ok(Inherited {
+        listener,
+        state,
+        files,
+        progress: stream,
+    })
+}
+
+/// Adopt what `inherited` carries and follow the old daemon to its exit,
+/// returning the listener to serve
+///
+/// Must be called within a Tokio runtime.
+pub fn adopt(
+    inherited: Inherited,
+    uploads: &Uploads,
+    handover: &Arc<Handover>,
+) -> io::Result<tokio::net::UnixListener> {
+    let Inherited {
+        listener,
+        state,
+        files,
+        progress,
+    } = inherited;
+    tracing::info!(
+        pid = state.pid,
+        uploads = state.uploads.len(),
+        "took over the socket"
+    );
+    uploads.adopt(state.uploads, files);
+
+    let handover = Arc::clone(handover);
+    tokio::spawn(async move { handover.follow(state.pid, progress).await });
+
+    listener.set_nonblocking(true)?;
+    tokio::net::UnixListener::from_std(listener)
+}
+
+/// Read one length-prefixed msgpack response
+async fn read_response(stream: &mut tokio::net::UnixStream) -> io::Result<Response> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > protocol::MAX_MESSAGE_SIZE {
+        return Err(io::Error::other(format!(
+            "answer of {len} bytes exceeds the message size limit"
+        )));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+    protocol::decode(&body).map_err(io::Error::other)
+}
@@ -0,0 +1,154 @@
+//! The daemon's answer to a `Hello`
+//!
+//! The release, build provenance, kernel, boot id and daemon settings are
+//! fixed at startup. The sandbox config is read again for each hello, so a
+//! reload shows up in the next one.
+
+use crate::config::DaemonConfig;
+use leeward_core::SandboxConfig;
+use leeward_core::config::Interpreter;
+use leeward_core::escape::KernelFeature;
+use leeward_core::protocol::{self, BuildInfo, DaemonInfo, Limits, SizeLimits, feature};
+use leeward_core::units::{ByteSize, DurationSecs};
+use std::collections::BTreeSet;
+
+/// Features every daemon of this release supports
+const ALWAYS: [&str; 26] = [
+    feature::EXEC_FILES,
+    feature::EXEC_STDIN,
+    feature::EXEC_ENV,
+    feature::EXEC_ARGS,
+    feature::EXEC_TIMEZONE,
+    feature::EXEC_MEMORY_LIMIT,
+    feature::EXEC_MAX_CONNECTIONS,
+    feature::EXEC_TRACEBACK,
+    feature::EXEC_PRIORITY,
+    feature::EXEC_BATCH,
+    feature::EXEC_PROFILE,
+    feature::EXEC_DEBUG,
+    feature::EXEC_DEFAULTS,
+    feature::EXEC_EXPLAIN,
+    feature::WIRE_JSON,
+    feature::EVENTS_ALERTS,
+    feature::EVENTS_WORKER_DIED,
+    feature::EVENTS_WORKER_EXITED,
+    feature::EVENTS_POLICY_CHANGE,
+    feature::HISTORY_POLICY_CHANGES,
+    feature::POOL_RECYCLE_STALE,
+    feature::POOL_DRAIN,
+    feature::POOL_HEALTH,
+    feature::DAEMON_HANDOVER,
+    feature::DAEMON_LOG_CONTROL,
+    // Workers cannot start without their filter
+    feature::SANDBOX_SECCOMP,
+];
+
+/// What a hello says that does not change while the daemon runs
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Identity {
+    build: BuildInfo,
+    kernel: Option<String>,
+    boot_id: String,
+    fast_path: bool,
+    root_template: bool,
+    max_request_wall: DurationSecs,
+    max_timeout: Option<std::time::Duration>,
+    workers: usize,
+    max_inflight_per_connection: usize,
+    max_inflight_per_peer_uid: usize,
+    upload_quota: ByteSize,
+    upload_ttl: DurationSecs,
+    sizes: SizeLimits,
+    detach: bool,
+    shm: bool,
+    time_slicing: bool,
+}
+
+impl Identity {
+    /// What `config` says, under the pool's `boot_id`, with `detach` set if
+    /// the result spool opened and `shm` if the shared memory region did
+    pub fn new(config: &DaemonConfig, boot_id: String, detach: bool, shm: bool) -> Self {
+        Self {
+            build: leeward_core::build_info!(),
+            kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|release| release.trim().to_owned()),
+            boot_id,
+            fast_path: config.fast_path,
+            root_template: config.root_template,
+            max_request_wall: config.max_request_wall,
+            max_timeout: config.max_timeout(),
+            workers: config.num_workers,
+            max_inflight_per_connection: config.max_inflight_per_connection,
+            max_inflight_per_peer_uid: config.max_inflight_per_peer_uid,
+            upload_quota: config.upload_quota,
+            upload_ttl: config.upload_ttl,
+            sizes: config.sizes.within_protocol(),
+            detach,
+            shm,
+            time_slicing: config.time_slicing().is_some(),
+        }
+    }
+
+    /// The full advertisement, given the pool's current sandbox config
+    pub fn daemon_info(&self, sandbox: &SandboxConfig) -> DaemonInfo {
+        DaemonInfo {
+            version: self.build.version.clone(),
+            protocol_version: protocol::PROTOCOL_VERSION,
+            features: self.features(sandbox),
+            languages: Interpreter::ALL
+                .into_iter()
+                .filter(|interpreter| interpreter.program(sandbox).exists())
+                .map(|interpreter| interpreter.wire_name().to_owned())
+                .collect(),
+            limits: Limits {
+                max_message_bytes: usize::try_from(self.sizes.max_request.bytes())
+                    .unwrap_or(usize::MAX),
+                max_code_bytes: usize::try_from(self.sizes.max_code.bytes()).unwrap_or(usize::MAX),
+                fast_path_max_bytes: self.fast_path.then_some(protocol::FAST_PATH_MAX_BYTES),
+                default_timeout_ms: u64::try_from(sandbox.timeout.as_millis()).unwrap_or(u64::MAX),
+                max_timeout_ms: self
+                    .max_timeout
+                    .map(|max| u64::try_from(max.as_millis()).unwrap_or(u64::MAX)),
+                default_memory_limit: sandbox.memory_limit,
+                tmp_size: sandbox.tmp_size,
+                max_request_wall: self.max_request_wall,
+                workers: self.workers,
+                max_inflight_per_connection: self.max_inflight_per_connection,
+                max_inflight_per_peer_uid: self.max_inflight_per_peer_uid,
+                upload_quota: self.upload_quota,
+                upload_ttl: self.upload_ttl,
+                sizes: self.sizes,
+            },
+            boot_id: self.boot_id.clone(),
+            build: Some(self.build.clone()),
+            kernel: self.kernel.clone(),
+        }
+    }
+
+    fn features(&self, sandbox: &SandboxConfig) -> BTreeSet<String> {
+        let probed = [
+            (self.fast_path, feature::EXEC_FAST_PATH),
+            (self.root_template, feature::POOL_ROOT_TEMPLATE),
+            (!self.upload_quota.is_zero(), feature::EXEC_UPLOADS),
+            (self.detach, feature::EXEC_DETACH),
+            (self.shm, feature::EXEC_SHM),
+            (self.time_slicing, feature::POOL_TIME_SLICING),
+            (
+                KernelFeature::Landlock.available(),
+                feature::SANDBOX_LANDLOCK,
+            ),
+            (sandbox.allow_network, feature::SANDBOX_NETWORK),
+        ];
+        ALWAYS
+            .into_iter()
+            .chain(
+                probed
+                    .into_iter()
+                    .filter_map(|(on, name)| on.then_some(name)),
+            )
+            .map(str::to_owned)
+            .collect()
+    }
+}
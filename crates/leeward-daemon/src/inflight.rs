@@ -0,0 +1,163 @@
+//! Limits on executions in flight per connection and per client uid
+//!
+//! An execution counts from when it is admitted until its handler finishes,
+//! even if the client was already answered because the request overran its
+//! deadline. The count is held by an [`Admitted`] guard, so it is given back
+//! on every path out of the handler, errors and panics included.
+
+use crate::metrics::Metrics;
+use leeward_core::protocol::{ErrorKind, InflightScope, PeerInflight, Response};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Executions in flight on one connection
+#[derive(Debug, Default)]
+pub struct ConnectionInflight {
+    /// Only changed with the uid table locked
+    count: Mutex<usize>,
+}
+
+/// Shared accounting of executions in flight
+#[derive(Debug)]
+pub struct InflightLimits {
+    per_connection: usize,
+    per_peer_uid: usize,
+    by_uid: Mutex<BTreeMap<u32, usize>>,
+    metrics: Arc<Metrics>,
+}
+
+/// An execution turned away by a limit
+#[derive(Debug, Clone, Copy)]
+pub struct Rejected {
+    pub scope: InflightScope,
+    pub current_inflight: usize,
+    pub limit: usize,
+}
+
+impl Rejected {
+    /// Answer telling the client to retry later
+    pub fn response(self) -> Response {
+        Response::Error {
+            message: format!(
+                "{} executions already in flight for this {}, the limit is {}",
+                self.current_inflight,
+                match self.scope {
+                    InflightScope::Connection => "connection",
+                    InflightScope::PeerUid => "client",
+                },
+                self.limit
+            ),
+            kind: ErrorKind::Busy {
+                scope: self.scope,
+                current_inflight: self.current_inflight,
+                limit: self.limit,
+            },
+        }
+    }
+}
+
+/// An admitted execution, counted until dropped
+#[derive(Debug)]
+pub struct Admitted {
+    limits: Arc<InflightLimits>,
+    connection: Arc<ConnectionInflight>,
+    uid: Option<u32>,
+}
+
+impl InflightLimits {
+    /// Limits of `per_connection` and `per_peer_uid` executions (0 = none)
+    pub const fn new(per_connection: usize, per_peer_uid: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            per_connection,
+            per_peer_uid,
+            by_uid: Mutex::new(BTreeMap::new()),
+            metrics,
+        }
+    }
+
+    pub const fn per_connection(&self) -> usize {
+        self.per_connection
+    }
+
+    pub const fn per_peer_uid(&self) -> usize {
+        self.per_peer_uid
+    }
+
+    /// Count one more execution on `connection` from `uid`, unless that
+    /// would go over a limit
+    ///
+    /// Peers whose uid is unknown are only held to the connection limit.
+    pub fn admit(
+        self: &Arc<Self>,
+        connection: &Arc<ConnectionInflight>,
+        uid: Option<u32>,
+    ) -> Result<Admitted, Rejected> {
+        let mut by_uid = self.by_uid.lock();
+        let on_uid = uid.map_or(0, |uid| by_uid.get(&uid).copied().unwrap_or(0));
+        let rejected = {
+            let mut on_connection = connection.count.lock();
+            if self.per_connection > 0 && *on_connection >= self.per_connection {
+                Some(Rejected {
+                    scope: InflightScope::Connection,
+                    current_inflight: *on_connection,
+                    limit: self.per_connection,
+                })
+            } else if uid.is_some() && self.per_peer_uid > 0 && on_uid >= self.per_peer_uid {
+                Some(Rejected {
+                    scope: InflightScope::PeerUid,
+                    current_inflight: on_uid,
+                    limit: self.per_peer_uid,
+                })
+            } else {
+                *on_connection += 1;
+                None
+            }
+        };
+        if let Some(rejected) = rejected {
+            drop(by_uid);
+            self.metrics.inflight_rejected(rejected.scope);
+            return Err(rejected);
+        }
+
+        if let Some(uid) = uid {
+            by_uid.insert(uid, on_uid + 1);
+            // Still under the lock, so the gauge never goes back in time
+            self.metrics.inflight(uid, on_uid + 1);
+        }
+        drop(by_uid);
+        Ok(Admitted {
+            limits: Arc::clone(self),
+            connection: Arc::clone(connection),
+            uid,
+        })
+    }
+
+    /// Clients with executions in flight
+    pub fn by_uid(&self) -> Vec<PeerInflight> {
+        self.by_uid
+            .lock()
+            .iter()
+            .map(|(&uid, &inflight)| PeerInflight { uid, inflight })
+            .collect()
+    }
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        let mut by_uid = self.limits.by_uid.lock();
+        *self.connection.count.lock() -= 1;
+
+        let Some(uid) = self.uid else {
+            return;
+        };
+        let remaining = by_uid.get(&uid).map_or(0, |count| count.saturating_sub(1));
+        if remaining == 0 {
+            by_uid.remove(&uid);
+        } else {
+            by_uid.insert(uid, remaining);
+        }
+        self.limits.metrics.inflight(uid, remaining);
+        drop(by_uid);
+    }
+}
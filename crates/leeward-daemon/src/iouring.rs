@@ -1,6 +1,6 @@
 //! io_uring integration for zero-copy IPC
 
-use io_uring::{opcode, types, IoUring};
+use io_uring::{IoUring, opcode, types};
 use leeward_core::{LeewardError, Result};
 use std::collections::HashMap;
 use std::os::unix::io::RawFd;
@@ -222,5 +222,8 @@ pub enum Completion {
     /// Write completed successfully
     Write { id: RequestId, bytes_written: usize },
     /// Operation failed
-    Error { id: RequestId, error: std::io::Error },
+    Error {
+        id: RequestId,
+        error: std::io::Error,
+    },
 }
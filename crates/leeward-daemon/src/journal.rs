@@ -0,0 +1,72 @@
+//! Where each request is in its handling
+//!
+//! Updated as a request moves through the server and pool, and read when
+//! one overruns its deadline, so the error says where it got stuck.
+
+use leeward_core::protocol::RequestStage;
+use leeward_core::worker::Worker;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// Progress of one request
+#[derive(Debug)]
+pub struct Journal {
+    /// Identifies the request in logs
+    pub id: u64,
+    last: Mutex<Entry>,
+}
+
+/// The last stage a request reached
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub stage: RequestStage,
+    /// When the request reached it
+    pub since: Instant,
+    /// Process of the worker it runs on, while running
+    pub worker_pid: Option<i32>,
+}
+
+impl Entry {
+    /// How long the request has been in this stage
+    pub fn elapsed(&self) -> Duration {
+        self.since.elapsed()
+    }
+}
+
+impl Journal {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            last: Mutex::new(Entry {
+                stage: RequestStage::Received,
+                since: Instant::now(),
+                worker_pid: None,
+            }),
+        }
+    }
+
+    /// Note that the request reached `stage`
+    pub fn record(&self, stage: RequestStage) {
+        *self.last.lock() = Entry {
+            stage,
+            since: Instant::now(),
+            worker_pid: None,
+        };
+    }
+
+    /// Note that the request was sent to `worker`
+    pub fn running(&self, worker: &Worker) {
+        *self.last.lock() = Entry {
+            stage: RequestStage::Running {
+                worker_id: worker.id,
+            },
+            since: Instant::now(),
+            worker_pid: worker.pid,
+        };
+    }
+
+    /// The last stage recorded
+    pub fn last(&self) -> Entry {
+        *self.last.lock()
+    }
+}
@@ -0,0 +1,413 @@
+//! leeward-daemon - Persistent sandbox daemon with pre-forked worker pool
+//!
+//! Performance optimizations:
+//! - Pre-forked workers with clone3 + `CLONE_INTO_CGROUP`
+//! - `io_uring` for zero-copy IPC
+//! - Shared memory (memfd) for results
+//! - `SECCOMP_USER_NOTIF` for non-fatal syscall filtering
+//!
+//! The `leeward-daemon` binary is a thin wrapper around [`Daemon`]. With
+//! the `testing` feature, [`testing::TestDaemon`] runs one in-process for
+//! integration tests.
+//!
+//! A new daemon can take over the socket of a running one without
+//! refusing a single connection; see [`Daemon::take_over`].
+//!
+//! Code is screened before it reaches a worker; see [`screening`].
+//!
+//! Background tasks are restarted when they panic, and put the daemon in
+//! maintenance if they keep panicking; see [`DaemonConfig::task_failure_limit`].
+//!
+//! The audit log and result spool survive a crash whole; see [`storage`].
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::net::UnixListener;
+use tokio::signal::unix::{SignalKind, signal};
+
+mod alerts;
+mod arena;
+mod audit;
+pub mod config;
+mod handover;
+mod hello;
+mod inflight;
+mod iouring;
+mod journal;
+mod liveness;
+pub mod logging;
+mod metrics;
+mod pool;
+mod reconcile;
+pub mod screening;
+mod server;
+mod shm;
+mod snapshot;
+mod spool;
+mod standby;
+pub mod storage;
+mod supervisor;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timeslice;
+mod uploads;
+
+pub use config::DaemonConfig;
+
+use audit::Audit;
+use handover::Handover;
+use leeward_core::isolation::RootTemplate;
+use leeward_core::policy::PolicyActor;
+use logging::LogControl;
+use metrics::Metrics;
+use pool::WorkerPool;
+use screening::{RuleFile, Screening};
+use server::EventBus;
+use shm::ShmSlots;
+use spool::Spool;
+use std::path::Path;
+use std::time::Duration;
+use supervisor::Supervisor;
+use uploads::Uploads;
+
+/// A configured worker pool, ready to serve a socket
+pub struct Daemon {
+    config: DaemonConfig,
+    pool: Arc<WorkerPool>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    uploads: Arc<Uploads>,
+    spool: Arc<Spool>,
+    shm: Arc<ShmSlots>,
+    handover: Arc<Handover>,
+    log: LogControl,
+    /// Hook executions are screened with, if any
+    screening: Option<Arc<dyn Screening>>,
+    /// Rules from `screening_rules`, read again on `SIGHUP`
+    rules: Option<Arc<RuleFile>>,
+    audit: Arc<Audit>,
+    /// Restarts background tasks that panic
+    supervisor: Supervisor,
+}
+
+impl Daemon {
+    /// Spawn the worker pool for `config`, optionally rooting workers in a
+    /// shared template
+    ///
+    /// Must be called within a Tokio runtime, whose thread count bounds the
+    /// fast path.
+    #[must_use]
+    pub fn new(config: DaemonConfig, template: Option<RootTemplate>) -> Self {
+        let workers = config.num_workers + config.standby_workers;
+        let pool = WorkerPool::new(workers, config.sandbox_config.clone(), template);
+        Self::with_pool(config, pool)
+    }
+
+    fn with_pool(config: DaemonConfig, pool: WorkerPool) -> Self {
+        // Inline executions hold a runtime thread, so leave at least one free
+        let inline_limit = if config.fast_path {
+            tokio::runtime::Handle::current()
+                .metrics()
+                .num_workers()
+                .saturating_sub(1)
+        } else {
+            0
+        };
+
+        let (events, _) = tokio::sync::broadcast::channel(server::EVENT_BUFFER);
+        let mut pool = pool
+            .with_startup_failure_limit(config.startup_failure_limit)
+            .with_inline_limit(inline_limit)
+            .with_events(events.clone())
+            .with_standby(config.standby_workers);
+        if let Some(slicing) = config.time_slicing() {
+            pool = pool.with_time_slicing(slicing);
+        }
+        if let Some(root) = &config.cgroup_root {
+            pool = pool.with_cgroups(root);
+        }
+        tracing::info!(
+            workers = config.num_workers,
+            standby = config.standby_workers,
+            "worker pool initialized"
+        );
+
+        let uploads = Uploads::new(config.upload_quota.bytes(), config.upload_ttl.get());
+        let spool = Spool::open(
+            &config.spool_dir,
+            config.spool_quota.bytes(),
+            config.spool_ttl.get(),
+        );
+        let metrics = Arc::new(Metrics::default());
+        let shm = ShmSlots::open(config.shm_slot_ttl.non_zero(), Arc::clone(&metrics));
+        let rules = config
+            .screening_rules
+            .as_deref()
+            .map(|path| Arc::new(RuleFile::open(path, config.sandbox_config.allow_network)));
+        let audit = Audit::open(
+            config.audit_log.as_deref(),
+            config.audit_secret_fields.clone(),
+            events.clone(),
+        );
+        audit.policy_change(PolicyActor::Startup, None, &config.sandbox_config, None);
+        let pool = Arc::new(pool);
+        let supervisor = Supervisor::new(
+            config.restart_policy(),
+            Arc::clone(&pool),
+            events.clone(),
+            Arc::clone(&metrics),
+        );
+        Self {
+            config,
+            pool,
+            events,
+            metrics,
+            uploads: Arc::new(uploads),
+            spool: Arc::new(spool),
+            shm: Arc::new(shm),
+            handover: Arc::new(Handover::default()),
+            log: LogControl::default(),
+            screening: rules.clone().map(|rules| rules as Arc<dyn Screening>),
+            rules,
+            audit: Arc::new(audit),
+            supervisor,
+        }
+    }
+
+    /// Screen executions with `screening` instead of the rules in
+    /// `screening_rules`
+    #[must_use]
+    pub fn with_screening(mut self, screening: Arc<dyn Screening>) -> Self {
+        self.screening = Some(screening);
+        self.rules = None;
+        self
+    }
+
+    /// Change the log filter through `log`, as installed by
+    /// [`logging::init`], when asked over the socket
+    #[must_use]
+    pub fn with_log_control(mut self, log: LogControl) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Take over the listening socket and uploads of the daemon serving
+    /// `path`, which stops accepting, finishes its requests and exits
+    ///
+    /// Connections arrive as soon as this returns, so call it once the
+    /// pool is up, and serve the listener it returns. Must be called within
+    /// a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error reaching the old daemon or adopting what it hands over.
+    pub fn take_over(&self, path: &Path) -> std::io::Result<UnixListener> {
+        let inherited = handover::take_over(path)?;
+        handover::adopt(inherited, &self.uploads, &self.handover)
+    }
+
+    /// Reload the sandbox config from the environment on every `SIGHUP`;
+    /// workers pick it up as they recycle
+    ///
+    /// A config that differs from the one in effect is recorded in the
+    /// audit log.
+    ///
+    /// The log filter and debug flags go back to how the daemon started,
+    /// except those changed with `persist`, and the screening rules are
+    /// read again, kept as they were if the file no longer parses.
+    ///
+    /// Must be called within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error setting up the `SIGHUP` handler.
+    pub fn reload_on_hangup(&self) -> std::io::Result<()> {
+        // Fails here rather than in the task if the signal cannot be caught
+        let _ = signal(SignalKind::hangup())?;
+        let pool = Arc::clone(&self.pool);
+        let log = self.log.clone();
+        let rules = self.rules.clone();
+        let audit = Arc::clone(&self.audit);
+        self.supervisor.spawn("reload", move || {
+            let hangup = signal(SignalKind::hangup());
+            let (pool, log, rules, audit) = (
+                Arc::clone(&pool),
+                log.clone(),
+                rules.clone(),
+                Arc::clone(&audit),
+            );
+            async move {
+                let Ok(mut hangup) = hangup else {
+                    return;
+                };
+                while hangup.recv().await.is_some() {
+                    reload(&pool, &log, rules.as_deref(), &audit);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Serve requests on `listener` until accepting fails or, after handing
+    /// the socket to a new daemon, until the last connection closes, along
+    /// with the metrics endpoint, alerting, time slicing, idle worker
+    /// checks, standby replacement and reaping of leaked roots the config
+    /// asks for
+    ///
+    /// # Errors
+    ///
+    /// The I/O error that stopped accepting.
+    pub async fn serve(self, listener: UnixListener) -> Result<()> {
+        let Self {
+            config,
+            pool,
+            events,
+            metrics,
+            uploads,
+            spool,
+            shm,
+            handover,
+            log,
+            screening,
+            rules: _,
+            audit,
+            supervisor,
+        } = self;
+
+        if config.metrics_enabled {
+            serve_metrics(&config, &supervisor, &metrics, &pool).await;
+        }
+
+        // Sample the pool for saturation alerts, off the request path
+        let monitor = leeward_core::alert::AlertMonitor::new(config.alert_thresholds())
+            .with_clear_samples(config.alert_clear_samples);
+        let interval = config
+            .alert_sample_interval
+            .get()
+            .max(Duration::from_millis(1));
+        let (sampled, alerted, counted) = (Arc::clone(&pool), events.clone(), Arc::clone(&metrics));
+        supervisor.spawn("alerts", move || {
+            alerts::run(
+                Arc::clone(&sampled),
+                monitor.clone(),
+                interval,
+                alerted.clone(),
+                Arc::clone(&counted),
+            )
+        });
+
+        // Keep the snapshot status requests read fresh while nothing changes
+        let interval = config
+            .status_refresh_interval
+            .get()
+            .max(Duration::from_millis(1));
+        let refreshed = Arc::clone(&pool);
+        supervisor.spawn("snapshot", move || {
+            snapshot::run(Arc::clone(&refreshed), interval)
+        });
+
+        // Replace idle workers that died between executions
+        if let Some(interval) = config.worker_check_interval() {
+            let checked = Arc::clone(&pool);
+            supervisor.spawn("liveness", move || {
+                liveness::run(Arc::clone(&checked), interval)
+            });
+        }
+
+        // Replace standby workers promoted for high-priority requests
+        if config.standby_workers > 0 {
+            let refilled = Arc::clone(&pool);
+            supervisor.spawn("standby", move || standby::run(Arc::clone(&refilled)));
+        }
+
+        // Reap template roots, mounts and cgroups that outlived their owner
+        if let Some(interval) = config.reconcile_interval() {
+            let grace = config.reconcile_grace.get();
+            let (reaped, counted) = (Arc::clone(&pool), Arc::clone(&metrics));
+            supervisor.spawn("reconcile", move || {
+                reconcile::run(interval, grace, Arc::clone(&reaped), Arc::clone(&counted))
+            });
+        }
+
+        // Freeze batch executions while others run
+        if let Some(slicing) = config.time_slicing() {
+            let (sliced, announced) = (Arc::clone(&pool), events.clone());
+            supervisor.spawn("timeslice", move || {
+                timeslice::run(
+                    Arc::clone(&sliced),
+                    slicing.check_interval(),
+                    announced.clone(),
+                )
+            });
+        }
+
+        let shared = server::Shared {
+            pool,
+            events,
+            metrics,
+            uploads,
+            spool,
+            shm,
+            handover,
+            log,
+            screening,
+            audit,
+            supervisor,
+        };
+        Ok(server::run(listener, shared, config).await?)
+    }
+}
+
+/// Serve metrics on the configured port, or carry on without them if it
+/// cannot be bound
+async fn serve_metrics(
+    config: &DaemonConfig,
+    supervisor: &Supervisor,
+    metrics: &Arc<Metrics>,
+    pool: &Arc<WorkerPool>,
+) {
+    match tokio::net::TcpListener::bind(("127.0.0.1", config.metrics_port)).await {
+        Ok(listener) => {
+            tracing::info!(port = config.metrics_port, "serving metrics");
+            let listener = Arc::new(listener);
+            let (metrics, pool) = (Arc::clone(metrics), Arc::clone(pool));
+            let min_idle = config.health_min_idle;
+            supervisor.spawn("metrics", move || {
+                metrics::serve(
+                    Arc::clone(&listener),
+                    Arc::clone(&metrics),
+                    Arc::clone(&pool),
+                    min_idle,
+                )
+            });
+        }
+        Err(e) => {
+            tracing::warn!(port = config.metrics_port, error = %e, "metrics endpoint disabled");
+        }
+    }
+}
+
+/// Reload the sandbox config from the environment, the log filter and the
+/// screening `rules`, for `SIGHUP`
+fn reload(pool: &WorkerPool, log: &LogControl, rules: Option<&RuleFile>, audit: &Audit) {
+    let config = DaemonConfig::from_env();
+    config.warn_if_below_floor();
+    if let Err(e) = config.sandbox_config.validate() {
+        tracing::error!(error = %e, "keeping the current sandbox config");
+    } else {
+        let before = pool.config();
+        if before.fingerprint() != config.sandbox_config.fingerprint() {
+            let actor = PolicyActor::Signal {
+                signal: "SIGHUP".into(),
+            };
+            audit.policy_change(actor, Some(&before), &config.sandbox_config, None);
+        }
+        pool.reload_config(config.sandbox_config);
+        log.reset();
+    }
+    // Whichever sandbox config is now in use decides the rules kept for
+    // sandboxes without networking
+    if let Some(rules) = rules {
+        rules.reload(pool.config().allow_network);
+    }
+}
@@ -0,0 +1,25 @@
+//! Checking idle workers for a process that died, on a timer off the
+//! request path
+
+use crate::pool::WorkerPool;
+use crate::supervisor;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Replace idle workers whose process died every `interval`
+///
+/// Dispatch checks the worker it claims too; this finds deaths while
+/// nothing is dispatched, so they are reported when they happen and the
+/// pool is back to full strength before the next burst.
+pub async fn run(pool: Arc<WorkerPool>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        // Respawning forks and waits for isolation setup
+        let checking = Arc::clone(&pool);
+        supervisor::blocking(move || checking.check_idle()).await;
+    }
+}
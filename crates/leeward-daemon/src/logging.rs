@@ -0,0 +1,140 @@
+//! Log filter and debug flags that can be changed while the daemon runs
+//!
+//! Restarting the daemon to change `RUST_LOG` throws away its warm workers,
+//! so the filter sits behind a reload handle that
+//! [`Request::SetLogFilter`](leeward_core::protocol::Request::SetLogFilter)
+//! swaps. [`DebugFlag`]s turn on the log lines of one hot point without
+//! raising the filter for everything else.
+//!
+//! Both are for chasing a problem, so a config reload puts them back:
+//! the filter to the one the daemon started with and every flag off,
+//! except what was changed with `persist`.
+
+use leeward_core::debug_flags::{self, DebugFlag};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+/// Install the global subscriber, writing to `writer` what `filter` lets
+/// through, and return the control that changes it
+///
+/// # Errors
+///
+/// [`TryInitError`] if this process already has a global subscriber.
+pub fn init<W>(filter: EnvFilter, writer: W) -> Result<LogControl, TryInitError>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let initial = filter.to_string();
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .try_init()?;
+    Ok(LogControl {
+        filter: Some(handle),
+        state: Arc::new(Mutex::new(State {
+            initial,
+            persisted_filter: None,
+            persisted_flags: BTreeMap::new(),
+        })),
+    })
+}
+
+/// Changes the log filter installed by [`init`] and the debug flags
+///
+/// Clones share their state. The default control has no filter to change,
+/// for daemons whose subscriber was installed some other way; debug flags
+/// still work.
+#[derive(Debug, Clone, Default)]
+pub struct LogControl {
+    filter: Option<reload::Handle<EnvFilter, Registry>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Filter the daemon started with
+    initial: String,
+    /// Filter a reload goes back to instead, if one was persisted
+    persisted_filter: Option<String>,
+    /// Flags a reload leaves as they are; the others go back off
+    persisted_flags: BTreeMap<DebugFlag, bool>,
+}
+
+impl LogControl {
+    /// Log filter in effect, `None` if it cannot be changed
+    #[must_use]
+    pub fn filter(&self) -> Option<String> {
+        self.filter.as_ref()?.with_current(ToString::to_string).ok()
+    }
+
+    /// Debug flags turned on
+    #[must_use]
+    pub fn debug_flags(&self) -> Vec<DebugFlag> {
+        debug_flags::enabled()
+    }
+
+    /// Replace the log filter with `directive`, keeping it across reloads
+    /// if `persist` is set
+    ///
+    /// # Errors
+    ///
+    /// Why `directive` was refused, or that this daemon's filter cannot be
+    /// changed.
+    pub fn set_filter(&self, directive: &str, persist: bool) -> Result<(), String> {
+        let Some(handle) = &self.filter else {
+            return Err("this daemon's log filter cannot be changed".into());
+        };
+        let filter = EnvFilter::try_new(directive)
+            .map_err(|e| format!("invalid log filter '{directive}': {e}"))?;
+        handle
+            .reload(filter)
+            .map_err(|e| format!("failed to change the log filter: {e}"))?;
+        if persist {
+            self.state.lock().persisted_filter = Some(directive.to_owned());
+        }
+        tracing::info!(filter = directive, persist, "log filter changed");
+        Ok(())
+    }
+
+    /// Turn `flag` on or off, keeping it so across reloads if `persist` is
+    /// set
+    pub fn set_debug(&self, flag: DebugFlag, enabled: bool, persist: bool) {
+        flag.set(enabled);
+        if persist {
+            self.state.lock().persisted_flags.insert(flag, enabled);
+        }
+        tracing::info!(%flag, enabled, persist, "debug flag changed");
+    }
+
+    /// Put back the filter and flags that were not persisted, as a config
+    /// reload does
+    pub fn reset(&self) {
+        let state = self.state.lock();
+        if let Some(handle) = &self.filter {
+            let directive = state.persisted_filter.as_deref().unwrap_or(&state.initial);
+            match EnvFilter::try_new(directive) {
+                Ok(filter) => {
+                    if let Err(e) = handle.reload(filter) {
+                        tracing::warn!(error = %e, "failed to reset the log filter");
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        filter = directive,
+                        error = %e,
+                        "failed to reset the log filter"
+                    );
+                }
+            }
+        }
+        for flag in DebugFlag::ALL {
+            flag.set(state.persisted_flags.get(&flag).copied().unwrap_or(false));
+        }
+    }
+}
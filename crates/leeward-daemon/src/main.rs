@@ -1,47 +1,40 @@
-//! leeward-daemon - Persistent sandbox daemon with pre-forked worker pool
+//! leeward-daemon binary: configures the daemon from the environment and
+//! serves its socket
 //!
-//! Performance optimizations:
-//! - Pre-forked workers with clone3 + CLONE_INTO_CGROUP
-//! - io_uring for zero-copy IPC
-//! - Shared memory (memfd) for results
-//! - SECCOMP_USER_NOTIF for non-fatal syscall filtering
+//! With `--takeover`, it takes the socket over from the daemon already
+//! serving it instead of binding a new one, so an upgrade refuses no
+//! connections; the old daemon exits once its requests are finished.
 
 use anyhow::Result;
+use leeward_daemon::{Daemon, DaemonConfig, logging};
 use tokio::net::UnixListener;
 use tracing_subscriber::EnvFilter;
 
-mod config;
-mod iouring;
-mod pool;
-mod server;
-
-use config::DaemonConfig;
-
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("leeward=info".parse()?))
-        .init();
+    // Initialize logging, behind a filter clients can change
+    let log = logging::init(
+        EnvFilter::from_default_env().add_directive("leeward=info".parse()?),
+        std::io::stdout,
+    )?;
 
-    tracing::info!("leeward-daemon starting");
+    let takeover = std::env::args().skip(1).any(|arg| arg == "--takeover");
+    let build = leeward_core::build_info!();
+    tracing::info!(takeover, version = %build.version, git = %build.git, "leeward-daemon starting");
 
     // Load config
-    let config = DaemonConfig::default();
+    let config = DaemonConfig::from_env();
     tracing::info!(
         workers = config.num_workers,
         socket = ?config.socket_path,
         "configuration loaded"
     );
 
-    // Create socket directory if needed
-    if let Some(parent) = config.socket_path.parent() {
-        std::fs::create_dir_all(parent)?;
+    // Taking over needs the old daemon's socket left in place
+    if !takeover {
+        prepare_socket(&config.socket_path)?;
     }
 
-    // Remove existing socket
-    let _ = std::fs::remove_file(&config.socket_path);
-
     // Validate Python
     let python_path = &config.sandbox_config.python_path;
 
@@ -50,9 +43,7 @@ async fn main() -> Result<()> {
         .output()
     {
         Ok(output) => {
-            let version = String::from_utf8_lossy(&output.stdout)
-                .trim()
-                .to_string();
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
             let version = if version.is_empty() {
                 String::from_utf8_lossy(&output.stderr).trim().to_string()
             } else {
@@ -61,20 +52,75 @@ async fn main() -> Result<()> {
             tracing::info!(python = ?python_path, version = %version, "Python ready");
         }
         Err(e) => {
-            anyhow::bail!("Python not found or not executable: {}", e);
+            anyhow::bail!("Python not found or not executable: {e}");
         }
     }
 
-    // Bind socket
-    let listener = UnixListener::bind(&config.socket_path)?;
-    tracing::info!(socket = ?config.socket_path, "listening");
+    config.warn_if_below_floor();
+    config
+        .sandbox_config
+        .validate()
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    config
+        .check_landlock()
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
 
-    // Initialize worker pool
-    let pool = pool::WorkerPool::new(config.num_workers, config.sandbox_config.clone())?;
-    tracing::info!(workers = config.num_workers, "worker pool initialized");
+    // Refuse to start on rules that would refuse every execution
+    if let Some(path) = &config.screening_rules {
+        leeward_daemon::screening::Rules::load(path, config.sandbox_config.allow_network)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+    }
 
-    // Run server
-    server::run(listener, pool, config).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    // Build the shared sandbox root before anything else is forked
+    let template = if config.root_template {
+        let template = leeward_core::isolation::RootTemplate::build(&config.sandbox_config)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        tracing::info!(root = ?template.root(), "root template built");
+        Some(template)
+    } else {
+        None
+    };
 
+    let socket = config.socket_path.clone();
+    let daemon = Daemon::new(config, template).with_log_control(log);
+    daemon.reload_on_hangup()?;
+
+    // With the pool up, take over or bind the socket
+    let listener = if takeover {
+        daemon
+            .take_over(&socket)
+            .map_err(|e| anyhow::anyhow!("failed to take over {}: {e}", socket.display()))?
+    } else {
+        let listener = leeward_core::socket::bind(&socket).map_err(|e| anyhow::anyhow!("{e}"))?;
+        listener.set_nonblocking(true)?;
+        UnixListener::from_std(listener)?
+    };
+    tracing::info!(socket = ?socket, "listening");
+
+    daemon.serve(listener).await
+}
+
+/// Make way for the socket, failing early on a path it cannot be bound at
+fn prepare_socket(path: &std::path::Path) -> Result<()> {
+    // Abstract socket names have no file or directory
+    if !leeward_core::socket::is_abstract(path) {
+        // Create socket directory if needed
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Remove existing socket
+        let _ = std::fs::remove_file(path);
+    }
+
+    leeward_core::socket::check_socket_path(path).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if let Some(filesystem) = leeward_core::socket::unreliable_filesystem(path) {
+        tracing::warn!(
+            socket = ?path,
+            filesystem,
+            "socket directory is on a filesystem where Unix sockets may not work; prefer a local \
+             one such as /run"
+        );
+    }
     Ok(())
 }
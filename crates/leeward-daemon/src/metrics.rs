@@ -0,0 +1,766 @@
+//! Daemon counters, exported in the Prometheus text format
+//!
+//! Served over plain HTTP on `metrics_port`; every request gets the full
+//! exposition regardless of path, except `/healthz`, which answers a health
+//! probe for balancers that only speak HTTP.
+
+use crate::pool::{DIED, PoolSnapshot, RESULT_TO_IDLE_BUCKETS, WorkerPool};
+use crate::supervisor::TASKS;
+use leeward_core::isolation::registry::Reaped;
+use leeward_core::protocol::{AlertKind, InflightScope};
+use leeward_core::worker::{GoodbyeReason, WorkerState};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Upper bounds of the connection age histogram, in seconds
+const AGE_BUCKETS: [u64; 7] = [1, 10, 60, 300, 1800, 3600, 86400];
+
+/// Counters shared across the daemon
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Alerts fired, by kind
+    alerts_total: Mutex<BTreeMap<AlertKind, u64>>,
+    /// Open client connections, by id, with when each was accepted
+    connections: Mutex<BTreeMap<u64, Instant>>,
+    /// Client connections accepted; also the source of connection ids
+    connections_total: AtomicU64,
+    /// Connections closed for sitting idle
+    connections_idle_closed: AtomicU64,
+    /// Failed accepts on the daemon socket that were retried
+    accept_errors: AtomicU64,
+    /// Executions run inline by the fast path
+    executions_inline: AtomicU64,
+    /// Executions dispatched through the queue
+    executions_queued: AtomicU64,
+    /// Requests answered with an error for overrunning their deadline
+    request_deadline_exceeded: AtomicU64,
+    /// Requests that could not be decoded
+    protocol_errors: AtomicU64,
+    /// Executions in flight, by client uid; uids stay once seen
+    inflight: Mutex<BTreeMap<u32, usize>>,
+    /// Executions turned away by the per-connection limit
+    inflight_rejected_connection: AtomicU64,
+    /// Executions turned away by the per-uid limit
+    inflight_rejected_peer_uid: AtomicU64,
+    /// Leaked template roots removed
+    leaked_roots_reaped: AtomicU64,
+    /// Leaked mounts detached
+    leaked_mounts_reaped: AtomicU64,
+    /// Leaked worker cgroups removed
+    leaked_cgroups_reaped: AtomicU64,
+    /// Shared memory slots leased to clients
+    shm_slots_leased: AtomicU64,
+    /// Shared memory slots in the region, 0 without one
+    shm_slots_total: AtomicU64,
+    /// Shared memory slots taken back from closed connections
+    shm_reclaimed_disconnect: AtomicU64,
+    /// Shared memory slots taken back after sitting unused
+    shm_reclaimed_idle: AtomicU64,
+    /// Executions code screening refused
+    screening_denied: AtomicU64,
+    /// Executions code screening let run with a note
+    screening_flagged: AtomicU64,
+    /// Daemon tasks restarted after panicking, by task
+    task_restarts: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+/// Why a shared memory slot was taken back from its client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reclaimed {
+    /// Its connection closed
+    Disconnect,
+    /// It went unused past the TTL
+    Idle,
+}
+
+/// How an execution reached its worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Run on the connection's task by the fast path
+    Inline,
+    /// Through the queue
+    Queued,
+}
+
+impl Metrics {
+    /// Count a firing alert
+    pub fn alert_fired(&self, kind: AlertKind) {
+        *self.alerts_total.lock().entry(kind).or_default() += 1;
+    }
+
+    /// Track an accepted connection until the returned guard is dropped
+    pub fn connection_opened(self: &Arc<Self>) -> OpenConnection {
+        let id = self.connections_total.fetch_add(1, Ordering::Relaxed);
+        self.connections.lock().insert(id, Instant::now());
+        OpenConnection {
+            metrics: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// Client connections currently open
+    pub fn open_connections(&self) -> usize {
+        self.connections.lock().len()
+    }
+
+    /// Count a connection closed by the idle timeout
+    pub fn connection_idle_closed(&self) {
+        self.connections_idle_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a failed accept that will be retried
+    pub fn accept_error(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a finished execution
+    pub fn execution(&self, dispatch: Dispatch) {
+        let counter = match dispatch {
+            Dispatch::Inline => &self.executions_inline,
+            Dispatch::Queued => &self.executions_queued,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request that overran its deadline
+    pub fn request_deadline_exceeded(&self) {
+        self.request_deadline_exceeded
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request that could not be decoded
+    pub fn protocol_error(&self) {
+        self.protocol_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set how many executions `uid` has in flight
+    pub fn inflight(&self, uid: u32, count: usize) {
+        self.inflight.lock().insert(uid, count);
+    }
+
+    /// Count an execution turned away by an in-flight limit
+    pub fn inflight_rejected(&self, scope: InflightScope) {
+        let counter = match scope {
+            InflightScope::Connection => &self.inflight_rejected_connection,
+            InflightScope::PeerUid => &self.inflight_rejected_peer_uid,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a leaked root, mount or cgroup removed by reconciliation
+    pub fn leak_reaped(&self, reaped: &Reaped) {
+        let counter = match reaped {
+            Reaped::Root(_) => &self.leaked_roots_reaped,
+            Reaped::Mount(_) => &self.leaked_mounts_reaped,
+            Reaped::Cgroup(_) => &self.leaked_cgroups_reaped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set how many shared memory slots are leased, of `total`
+    pub fn shm_slots(&self, leased: usize, total: usize) {
+        self.shm_slots_leased
+            .store(leased as u64, Ordering::Relaxed);
+        self.shm_slots_total.store(total as u64, Ordering::Relaxed);
+    }
+
+    /// Count shared memory slots taken back from their clients
+    pub fn shm_reclaimed(&self, reason: Reclaimed, count: usize) {
+        let counter = match reason {
+            Reclaimed::Disconnect => &self.shm_reclaimed_disconnect,
+            Reclaimed::Idle => &self.shm_reclaimed_idle,
+        };
+        counter.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Count an execution code screening refused, or flagged if not `denied`
+    pub fn screened(&self, denied: bool) {
+        let counter = if denied {
+            &self.screening_denied
+        } else {
+            &self.screening_flagged
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a restart of `task` after it panicked
+    pub fn task_restarted(&self, task: &'static str) {
+        *self.task_restarts.lock().entry(task).or_default() += 1;
+    }
+
+    /// Render every counter in the Prometheus text format, along with the
+    /// pool gauges of `pool`
+    pub fn render(&self, pool: &PoolSnapshot) -> String {
+        let mut out = String::new();
+        render_build(&mut out);
+        render_pool(&mut out, pool);
+        render_workers(&mut out, pool);
+        self.render_alerts(&mut out);
+        self.render_connections(&mut out);
+        self.render_executions(&mut out);
+        self.render_requests(&mut out);
+        self.render_inflight(&mut out);
+        self.render_leaks(&mut out);
+        self.render_shm(&mut out);
+        self.render_screening(&mut out);
+        self.render_tasks(&mut out);
+        out
+    }
+
+    fn render_alerts(&self, out: &mut String) {
+        let alerts = self.alerts_total.lock();
+        header(
+            out,
+            "leeward_alerts_total",
+            "counter",
+            "Pool alerts fired, by kind.",
+        );
+        for kind in AlertKind::ALL {
+            let count = alerts.get(&kind).copied().unwrap_or(0);
+            let _ = writeln!(out, "leeward_alerts_total{{kind=\"{kind}\"}} {count}");
+        }
+        drop(alerts);
+    }
+
+    fn render_connections(&self, out: &mut String) {
+        let now = Instant::now();
+        let ages: Vec<Duration> = self
+            .connections
+            .lock()
+            .values()
+            .map(|opened| now.duration_since(*opened))
+            .collect();
+
+        header(
+            out,
+            "leeward_connections_open",
+            "gauge",
+            "Client connections currently open.",
+        );
+        let _ = writeln!(out, "leeward_connections_open {}", ages.len());
+        header(
+            out,
+            "leeward_connections_total",
+            "counter",
+            "Client connections accepted.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_connections_total {}",
+            self.connections_total.load(Ordering::Relaxed)
+        );
+        header(
+            out,
+            "leeward_connections_idle_closed_total",
+            "counter",
+            "Connections closed after sitting idle.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_connections_idle_closed_total {}",
+            self.connections_idle_closed.load(Ordering::Relaxed)
+        );
+        header(
+            out,
+            "leeward_accept_errors_total",
+            "counter",
+            "Failed accepts on the daemon socket, retried after a pause.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_accept_errors_total {}",
+            self.accept_errors.load(Ordering::Relaxed)
+        );
+
+        header(
+            out,
+            "leeward_connection_age_seconds",
+            "histogram",
+            "Age of each open connection.",
+        );
+        for bound in AGE_BUCKETS {
+            let count = ages
+                .iter()
+                .filter(|&&age| age <= Duration::from_secs(bound))
+                .count();
+            let _ = writeln!(
+                out,
+                "leeward_connection_age_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "leeward_connection_age_seconds_bucket{{le=\"+Inf\"}} {}",
+            ages.len()
+        );
+        let _ = writeln!(
+            out,
+            "leeward_connection_age_seconds_sum {:.3}",
+            ages.iter().map(Duration::as_secs_f64).sum::<f64>()
+        );
+        let _ = writeln!(out, "leeward_connection_age_seconds_count {}", ages.len());
+    }
+
+    fn render_executions(&self, out: &mut String) {
+        header(
+            out,
+            "leeward_executions_total",
+            "counter",
+            "Executions dispatched, by path to the worker.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_executions_total{{path=\"inline\"}} {}",
+            self.executions_inline.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "leeward_executions_total{{path=\"queued\"}} {}",
+            self.executions_queued.load(Ordering::Relaxed)
+        );
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        header(
+            out,
+            "leeward_request_deadline_exceeded_total",
+            "counter",
+            "Requests the daemon failed to answer in time; nonzero means a bug.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_request_deadline_exceeded_total {}",
+            self.request_deadline_exceeded.load(Ordering::Relaxed)
+        );
+        header(
+            out,
+            "leeward_protocol_errors_total",
+            "counter",
+            "Requests that could not be decoded, including oversized ones.",
+        );
+        let _ = writeln!(
+            out,
+            "leeward_protocol_errors_total {}",
+            self.protocol_errors.load(Ordering::Relaxed)
+        );
+    }
+
+    fn render_inflight(&self, out: &mut String) {
+        header(
+            out,
+            "leeward_inflight_executions",
+            "gauge",
+            "Executions in flight, by client uid.",
+        );
+        for (uid, count) in self.inflight.lock().iter() {
+            let _ = writeln!(out, "leeward_inflight_executions{{uid=\"{uid}\"}} {count}");
+        }
+        header(
+            out,
+            "leeward_inflight_rejected_total",
+            "counter",
+            "Executions turned away by an in-flight limit, by limit.",
+        );
+        for (scope, counter) in [
+            (
+                InflightScope::Connection,
+                &self.inflight_rejected_connection,
+            ),
+            (InflightScope::PeerUid, &self.inflight_rejected_peer_uid),
+        ] {
+            let _ = writeln!(
+                out,
+                "leeward_inflight_rejected_total{{limit=\"{scope}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    fn render_leaks(&self, out: &mut String) {
+        header(
+            out,
+            "leeward_leaked_reaped_total",
+            "counter",
+            "Leaked template roots, mounts and worker cgroups removed by reconciliation; \
+             nonzero means a cleanup bug or a crash.",
+        );
+        for (kind, counter) in [
+            ("root", &self.leaked_roots_reaped),
+            ("mount", &self.leaked_mounts_reaped),
+            ("cgroup", &self.leaked_cgroups_reaped),
+        ] {
+            let _ = writeln!(
+                out,
+                "leeward_leaked_reaped_total{{kind=\"{kind}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    fn render_shm(&self, out: &mut String) {
+        let leased = self.shm_slots_leased.load(Ordering::Relaxed);
+        let free = self
+            .shm_slots_total
+            .load(Ordering::Relaxed)
+            .saturating_sub(leased);
+        header(
+            out,
+            "leeward_shm_slots",
+            "gauge",
+            "Shared memory slots, by state.",
+        );
+        let _ = writeln!(out, "leeward_shm_slots{{state=\"leased\"}} {leased}");
+        let _ = writeln!(out, "leeward_shm_slots{{state=\"free\"}} {free}");
+        header(
+            out,
+            "leeward_shm_slots_reclaimed_total",
+            "counter",
+            "Shared memory slots taken back from clients that did not free them, by reason.",
+        );
+        for (reason, counter) in [
+            ("disconnect", &self.shm_reclaimed_disconnect),
+            ("idle", &self.shm_reclaimed_idle),
+        ] {
+            let _ = writeln!(
+                out,
+                "leeward_shm_slots_reclaimed_total{{reason=\"{reason}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    fn render_screening(&self, out: &mut String) {
+        header(
+            out,
+            "leeward_screening_total",
+            "counter",
+            "Executions code screening refused or flagged, by verdict.",
+        );
+        for (verdict, counter) in [
+            ("deny", &self.screening_denied),
+            ("flag", &self.screening_flagged),
+        ] {
+            let _ = writeln!(
+                out,
+                "leeward_screening_total{{verdict=\"{verdict}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    fn render_tasks(&self, out: &mut String) {
+        let restarts = self.task_restarts.lock().clone();
+        header(
+            out,
+            "leeward_task_restarts_total",
+            "counter",
+            "Daemon tasks restarted after panicking, by task.",
+        );
+        for task in TASKS {
+            let count = restarts.get(task).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "leeward_task_restarts_total{{task=\"{task}\"}} {count}"
+            );
+        }
+    }
+}
+
+/// The `# HELP` and `# TYPE` lines of the metric `name`
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Pool gauges, from its snapshot so a wedged pool cannot hold up a scrape
+fn render_pool(out: &mut String, pool: &PoolSnapshot) {
+    header(
+        out,
+        "leeward_pool_workers",
+        "gauge",
+        "Workers in the pool, by state, standbys aside.",
+    );
+    for (label, state) in [
+        ("idle", WorkerState::Idle),
+        ("busy", WorkerState::Busy),
+        ("recycling", WorkerState::Recycling),
+        ("dead", WorkerState::Dead),
+    ] {
+        let _ = writeln!(
+            out,
+            "leeward_pool_workers{{state=\"{label}\"}} {}",
+            pool.count(state)
+        );
+    }
+    header(
+        out,
+        "leeward_pool_standby_workers",
+        "gauge",
+        "Workers held back for high-priority requests, which leeward_pool_workers leaves out.",
+    );
+    let _ = writeln!(out, "leeward_pool_standby_workers {}", pool.standby());
+    header(
+        out,
+        "leeward_pool_standby_promotions_total",
+        "counter",
+        "Standby workers taken by high-priority requests that would have queued.",
+    );
+    let _ = writeln!(
+        out,
+        "leeward_pool_standby_promotions_total {}",
+        pool.promotions
+    );
+    header(
+        out,
+        "leeward_pool_queue_depth",
+        "gauge",
+        "Requests waiting for a worker.",
+    );
+    let _ = writeln!(out, "leeward_pool_queue_depth {}", pool.queue_depth);
+    header(
+        out,
+        "leeward_pool_snapshot_age_seconds",
+        "gauge",
+        "Age of the oldest part of the pool gauges; growing means a pool lock is wedged.",
+    );
+    let _ = writeln!(
+        out,
+        "leeward_pool_snapshot_age_seconds {:.3}",
+        pool.age().as_secs_f64()
+    );
+}
+
+/// Per-worker gauges and worker lifecycle counters, from the pool's snapshot
+fn render_workers(out: &mut String, pool: &PoolSnapshot) {
+    header(
+        out,
+        "leeward_worker_pipe_queued_bytes",
+        "gauge",
+        "Bytes waiting in each worker's pipes as last sampled, by pipe: \
+         code for the worker to read, result for the daemon.",
+    );
+    for (id, worker) in pool.workers.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "leeward_worker_pipe_queued_bytes{{worker=\"{id}\",pipe=\"code\"}} {}",
+            worker.code_queued
+        );
+        let _ = writeln!(
+            out,
+            "leeward_worker_pipe_queued_bytes{{worker=\"{id}\",pipe=\"result\"}} {}",
+            worker.result_queued
+        );
+    }
+    header(
+        out,
+        "leeward_worker_exits_total",
+        "counter",
+        "Worker processes gone, by cause: died, or the reason they gave for exiting on their own.",
+    );
+    for cause in std::iter::once(DIED).chain(GoodbyeReason::KINDS) {
+        let count = pool.exits.get(cause).copied().unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "leeward_worker_exits_total{{cause=\"{cause}\"}} {count}"
+        );
+    }
+
+    let released = &pool.result_to_idle;
+    header(
+        out,
+        "leeward_result_to_idle_seconds",
+        "histogram",
+        "Time from reading a worker's result to releasing the worker, recycling included.",
+    );
+    for (bound, count) in RESULT_TO_IDLE_BUCKETS.into_iter().zip(released.buckets) {
+        let bound = Duration::from_micros(bound).as_secs_f64();
+        let _ = writeln!(
+            out,
+            "leeward_result_to_idle_seconds_bucket{{le=\"{bound}\"}} {count}"
+        );
+    }
+    let _ = writeln!(
+        out,
+        "leeward_result_to_idle_seconds_bucket{{le=\"+Inf\"}} {}",
+        released.count
+    );
+    let sum = Duration::from_micros(released.sum_micros).as_secs_f64();
+    let _ = writeln!(out, "leeward_result_to_idle_seconds_sum {sum:.6}");
+    let _ = writeln!(
+        out,
+        "leeward_result_to_idle_seconds_count {}",
+        released.count
+    );
+}
+
+/// `leeward_build_info`, always 1, labelled with the daemon's provenance
+fn render_build(out: &mut String) {
+    let build = leeward_core::build_info!();
+    let label = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    header(
+        out,
+        "leeward_build_info",
+        "gauge",
+        "Release, commit, toolchain and features the daemon was built with.",
+    );
+    let _ = writeln!(
+        out,
+        "leeward_build_info{{version=\"{}\",git=\"{}\",built_at=\"{}\",\
+         rustc=\"{}\",target=\"{}\",profile=\"{}\",features=\"{}\"}} 1",
+        label(&build.version),
+        label(&build.git),
+        label(&build.built_at),
+        label(&build.rustc),
+        label(&build.target),
+        label(&build.profile),
+        label(&build.features.join(",")),
+    );
+}
+
+/// An answer of the metrics endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpAnswer {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpAnswer {
+    const fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    /// The whole HTTP response
+    fn encode(&self) -> String {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+        format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// What the endpoint answers a request that starts with `head`: the
+/// health of `pool` for `/healthz`, every metric for anything else
+///
+/// `/healthz` answers 200 when healthy and 503 when not, with the health
+/// as a JSON [`Response::Health`](leeward_core::protocol::Response::Health).
+/// `?min_idle=N` overrides `min_idle`.
+pub fn answer_http(
+    metrics: &Metrics,
+    head: &str,
+    pool: &PoolSnapshot,
+    min_idle: usize,
+) -> HttpAnswer {
+    let target = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/healthz" {
+        return HttpAnswer::new(200, "text/plain; version=0.0.4", metrics.render(pool));
+    }
+
+    let requested = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|&(key, _)| key == "min_idle")
+        .map(|(_, value)| value.parse::<usize>());
+    let min_idle = match requested {
+        None => min_idle,
+        Some(Ok(min_idle)) => min_idle,
+        Some(Err(_)) => {
+            return HttpAnswer::new(
+                400,
+                "text/plain",
+                "min_idle must be a number of workers\n".to_owned(),
+            );
+        }
+    };
+    let health = pool.health(min_idle);
+    let body = serde_json::to_string(&health.response()).unwrap_or_default() + "\n";
+    HttpAnswer::new(
+        if health.healthy { 200 } else { 503 },
+        "application/json",
+        body,
+    )
+}
+
+/// An open connection, counted in the metrics until dropped
+#[derive(Debug)]
+pub struct OpenConnection {
+    metrics: Arc<Metrics>,
+    id: u64,
+}
+
+impl OpenConnection {
+    /// The connection's id, unique for the life of the daemon
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.metrics.connections.lock().remove(&self.id);
+    }
+}
+
+/// Answer every connection on `listener` with the current metrics and the
+/// gauges of `pool`, or its health, needing `min_idle` idle workers unless
+/// the probe says
+pub async fn serve(
+    listener: Arc<TcpListener>,
+    metrics: Arc<Metrics>,
+    pool: Arc<WorkerPool>,
+    min_idle: usize,
+) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(error = %e, "metrics accept failed");
+                continue;
+            }
+        };
+        let metrics = Arc::clone(&metrics);
+        let pool = Arc::clone(&pool);
+
+        tokio::spawn(async move {
+            // Only the request line matters, in what the client sent first
+            let mut request = [0u8; 1024];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let head = String::from_utf8_lossy(&request[..read]);
+
+            let response = answer_http(&metrics, &head, &pool.snapshot(), min_idle).encode();
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                tracing::debug!(error = %e, "metrics client went away");
+            }
+        });
+    }
+}
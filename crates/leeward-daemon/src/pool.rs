@@ -1,97 +1,1700 @@
 //! Worker pool management
+//!
+//! A drain marks every worker for recycling once it is free, so workers
+//! spawned before an interpreter or runtime upgrade pick it up without
+//! interrupting executions; the root template is rebuilt first, so the
+//! new workers see any new mounts too.
+//!
+//! With time slicing on, batch executions past their slice are frozen
+//! while other executions run, so they get the CPU, and thawed once those
+//! are done or the batch execution has used up its frozen budget; see
+//! [`leeward_core::preempt`].
+//!
+//! Executions under the debug profile never use a pooled worker: each gets
+//! a worker of its own, killed once it is done.
+//!
+//! Status requests and metrics read a [`PoolSnapshot`] instead of the pool
+//! itself, so they answer even while a worker or queue lock is wedged. The
+//! snapshot is refreshed on every state change and on a timer, only ever
+//! trying the locks; what it could not see is kept from before and shows in
+//! its [age](PoolSnapshot::age).
+//!
+//! The snapshot also answers health probes, with an estimate of how long a
+//! new request would wait: the 90th percentile of the waits of requests
+//! that recently queued, or the wait of the oldest one still queued if
+//! that is longer.
+//!
+//! A worker is released as soon as its result has been read off its pipe,
+//! and recycled first if due; writing the response is left to the
+//! connection, so a slow client holds up only itself. How long that
+//! release takes is kept as the result-to-idle histogram.
+//!
+//! A worker may exit on its own after an execution, saying goodbye with
+//! its result; it is then replaced or left dead depending on why, and
+//! reported as exited rather than dead. Any other worker whose pipe
+//! closes has crashed: idle workers are checked before each dispatch and
+//! by [`WorkerPool::check_idle`], and replaced like workers that die mid
+//! execution.
+//!
+//! With standby workers, the last few workers are held back from normal
+//! scheduling. A high-priority request that finds no other worker idle
+//! takes one at once instead of queueing, and it then serves like any
+//! other; the next worker to be free is respawned in the background to
+//! stand by in its place. Standbys are recycled by drains and config
+//! reloads like any idle worker, and counted apart in status and metrics.
 
-use leeward_core::{ExecutionResult, LeewardError, Result, SandboxConfig, worker::{Worker, WorkerState}};
-use parking_lot::Mutex;
+use crate::config::TimeSlicing;
+use crate::journal::Journal;
+use crate::server::EventBus;
+use arc_swap::ArcSwap;
+use leeward_core::alert::PoolSample;
+use leeward_core::debug_flags::DebugFlag;
+use leeward_core::isolation::{NetworkSlots, OomEventReceiver, RootTemplate, cgroups};
+use leeward_core::pipe::PipeGauge;
+use leeward_core::preempt::{Freeze, Preemption};
+use leeward_core::profile::WorkloadProfile;
+use leeward_core::protocol::{
+    Event, InterpreterStamp, PreemptionEvent, RequestPriority, RequestStage, Response, WorkerInfo,
+    WorkerSlot,
+};
+use leeward_core::{
+    ExecutionResult, LeewardError, Result, SandboxConfig,
+    worker::{
+        ExecuteOptions, GoodbyeReason, StartupBreaker, Worker, WorkerState, WorkerUid, WorkerUids,
+    },
+};
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Id of the one-off workers debug-profile executions run in, outside the
+/// range pooled workers use
+const DEBUG_WORKER_ID: u32 = u32::MAX;
+
+/// Id of the one-off workers profiling executions run in
+const PROFILE_WORKER_ID: u32 = u32::MAX - 1;
+
+/// Queue waits kept for the estimate, latest first
+const RECENT_WAITS: usize = 128;
+
+/// Queue waits older than this say nothing about the next one
+const RECENT_WAIT_AGE: Duration = Duration::from_secs(60);
+
+/// Percentile of recent queue waits a health probe reports
+const WAIT_PERCENTILE: usize = 90;
+
+/// Cause a worker exit is counted under when it did not say goodbye
+pub const DIED: &str = "died";
+
+/// Upper bounds of the result-to-idle histogram, in microseconds
+pub const RESULT_TO_IDLE_BUCKETS: [u64; 6] = [100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
 
 /// Pool of sandbox workers
 pub struct WorkerPool {
     workers: Vec<Arc<Mutex<Worker>>>,
-    config: SandboxConfig,
+    /// Config new and recycled workers are spawned with
+    config: RwLock<SandboxConfig>,
+    /// Fingerprint of `config`, computed once per reload rather than per
+    /// execution
+    fingerprint: RwLock<Arc<str>>,
+    /// Shared root new and recycled workers are spawned in, if any
+    template: RwLock<Option<Arc<RootTemplate>>>,
+    /// Interpreter binary each worker was last spawned from, by worker id,
+    /// readable without waiting on busy workers
+    interpreters: Mutex<Vec<Option<InterpreterStamp>>>,
+    /// Workers marked by a drain
+    drain: Mutex<Drain>,
+    /// Refuses work while the config's interpreter keeps failing to start
+    breaker: Mutex<StartupBreaker>,
+    /// Requests waiting for a worker
+    queue: Queue,
+    /// Signalled whenever a worker finishes, so a queued request can claim it
+    idle: Notify,
+    /// Executions allowed to run inline on a connection's task at once
+    inline: InlineSlots,
+    /// Where worker deaths are published
+    events: Option<EventBus>,
+    /// Freezes each worker's batch executions, by worker id
+    preemptions: Vec<Arc<Preemption>>,
+    /// Bytes waiting in each worker's pipes, by worker id
+    pipes: Vec<Arc<PipeGauge>>,
+    /// How batch executions are time sliced, if they are
+    slicing: Option<TimeSlicing>,
+    /// Batch executions running, by worker id
+    batch: Mutex<BTreeMap<u32, BatchRun>>,
+    /// Echo code back after this long instead of running it, for tests
+    mock: Option<Duration>,
+    /// Where the uid of each worker process comes from
+    uids: Arc<WorkerUids>,
+    /// Where each worker process with a controlled network gets its slot
+    /// of the egress network, one-off workers included
+    network_slots: Arc<NetworkSlots>,
+    /// Where each worker process gets a cgroup of its own, if anywhere
+    cgroup_root: Option<PathBuf>,
+    /// Pool state as last seen, for what must not wait on a lock here
+    snapshot: ArcSwap<PoolSnapshot>,
+    /// Whether each worker is claimed for an execution, by worker id, so
+    /// a refresh can tell a busy worker's lock from a wedged one
+    dispatched: Vec<AtomicBool>,
+    /// Time from reading each result to releasing its worker
+    result_to_idle: ReleaseTimes,
+    /// Worker processes gone so far, by [`DIED`] or goodbye reason
+    exits: Mutex<BTreeMap<&'static str, u64>>,
+    /// Why the daemon stopped running executions, once one of its tasks
+    /// kept failing
+    maintenance: Mutex<Option<Arc<str>>>,
+    /// Whether each worker is held back for high-priority requests, by
+    /// worker id; only changed under the worker's lock
+    standby: Vec<AtomicBool>,
+    /// Standby workers promoted and not yet replaced
+    standby_owed: AtomicUsize,
+    /// Signalled when a standby is owed and a worker may be free to
+    /// replace it
+    standby_wanted: Notify,
+    /// Standby workers promoted so far
+    promotions: AtomicU64,
+}
+
+/// Time slicing state of one batch execution
+#[derive(Debug)]
+struct BatchRun {
+    execution_id: u64,
+    /// When it last started running, from the start or a thaw
+    resumed: Instant,
+    frozen_since: Option<Instant>,
+    /// Time frozen before `frozen_since`
+    frozen_total: Duration,
+    /// Longest it may spend frozen and still finish in time
+    budget: Duration,
+    preempted_count: u32,
+}
+
+/// Progress of drains
+#[derive(Debug, Default)]
+struct Drain {
+    /// Workers still to be recycled, by id
+    pending: BTreeSet<u32>,
+    /// Workers recycled by drains so far
+    drained: u64,
+}
+
+impl BatchRun {
+    fn event(&self, worker_id: u32) -> PreemptionEvent {
+        let frozen = self.frozen_total
+            + self
+                .frozen_since
+                .map_or(Duration::ZERO, |since| since.elapsed());
+        PreemptionEvent {
+            worker_id,
+            execution_id: self.execution_id,
+            preempted_count: self.preempted_count,
+            frozen_ms: u64::try_from(frozen.as_millis()).unwrap_or(u64::MAX),
+        }
+    }
 }
 
 impl WorkerPool {
-    /// Create a new worker pool
-    pub fn new(num_workers: usize, config: SandboxConfig) -> Result<Self> {
-        let mut workers = Vec::with_capacity(num_workers);
+    /// Create a new worker pool, optionally rooting workers in a shared template
+    ///
+    /// Workers whose isolation setup fails are kept in the pool as `Dead`.
+    pub fn new(num_workers: usize, config: SandboxConfig, template: Option<RootTemplate>) -> Self {
+        let template = template.map(Arc::new);
+        let uids = Arc::new(WorkerUids::random());
+        let network_slots = Arc::new(NetworkSlots::default());
+        let workers: Vec<Worker> = (0..u32::try_from(num_workers).unwrap_or(u32::MAX))
+            .map(|id| {
+                let mut worker = Worker::new(id, config.clone())
+                    .with_uids(Arc::clone(&uids))
+                    .with_network_slots(Arc::clone(&network_slots));
+                if let Some(template) = &template {
+                    worker = worker.with_root_template(Arc::clone(template));
+                }
+                if let Err(e) = worker.spawn() {
+                    tracing::error!(worker_id = id, "worker failed to start: {}", e);
+                }
+                worker
+            })
+            .collect();
+        Self::with_workers(workers, config, template, uids, network_slots)
+    }
+
+    /// Create a pool of workers that never spawn a process and answer every
+    /// execution by echoing its code on stdout after `latency`
+    ///
+    /// See [`crate::testing`] for what this does and does not exercise.
+    #[cfg(feature = "testing")]
+    pub fn mock(num_workers: usize, config: SandboxConfig, latency: Duration) -> Self {
+        let uids = Arc::new(WorkerUids::random());
+        let workers = (0..u32::try_from(num_workers).unwrap_or(u32::MAX))
+            .map(|id| {
+                let mut worker = Worker::new(id, config.clone()).with_uids(Arc::clone(&uids));
+                worker.uid = Some(uids.next());
+                worker.state = WorkerState::Idle;
+                worker.interpreter = InterpreterStamp::of(&config.python_path);
+                worker
+            })
+            .collect();
+        let mut pool = Self::with_workers(workers, config, None, uids, Arc::default());
+        pool.mock = Some(latency);
+        pool
+    }
 
-        for id in 0..num_workers {
-            let mut worker = Worker::new(id as u32, config.clone());
-            worker.spawn()?;
-            workers.push(Arc::new(Mutex::new(worker)));
+    fn with_workers(
+        workers: Vec<Worker>,
+        config: SandboxConfig,
+        template: Option<Arc<RootTemplate>>,
+        uids: Arc<WorkerUids>,
+        network_slots: Arc<NetworkSlots>,
+    ) -> Self {
+        let preemptions = workers.iter().map(Worker::preemption).collect();
+        let pipes: Vec<_> = workers.iter().map(Worker::pipe_gauge).collect();
+        let interpreters = workers.iter().map(|worker| worker.interpreter).collect();
+        let fingerprint = config.fingerprint();
+        let now = Instant::now();
+        let snapshot = PoolSnapshot {
+            workers: workers
+                .iter()
+                .map(|worker| WorkerSnapshot {
+                    state: worker.state,
+                    standby: false,
+                    stale: worker.config_fingerprint != fingerprint,
+                    seq: worker.uid.as_ref().map(|uid| uid.seq),
+                    seen_at: now,
+                    code_queued: 0,
+                    result_queued: 0,
+                })
+                .collect(),
+            boot_id: uids.boot_id().into(),
+            queue_depth: 0,
+            oldest_queued: None,
+            recent_wait: Duration::ZERO,
+            result_to_idle: ReleaseHistogram::default(),
+            exits: BTreeMap::new(),
+            draining: 0,
+            drained: 0,
+            maintenance: None,
+            promotions: 0,
+            counted_at: now,
+            refreshed_at: now,
+        };
+        let dispatched = workers.iter().map(|_| AtomicBool::new(false)).collect();
+        let standby = workers.iter().map(|_| AtomicBool::new(false)).collect();
+        Self {
+            workers: workers
+                .into_iter()
+                .map(|worker| Arc::new(Mutex::new(worker)))
+                .collect(),
+            fingerprint: RwLock::new(fingerprint.into()),
+            config: RwLock::new(config),
+            template: RwLock::new(template),
+            interpreters: Mutex::new(interpreters),
+            drain: Mutex::new(Drain::default()),
+            breaker: Mutex::new(StartupBreaker::default()),
+            queue: Queue::default(),
+            idle: Notify::new(),
+            inline: InlineSlots::default(),
+            events: None,
+            preemptions,
+            pipes,
+            slicing: None,
+            batch: Mutex::new(BTreeMap::new()),
+            mock: None,
+            uids,
+            network_slots,
+            cgroup_root: None,
+            snapshot: ArcSwap::from_pointee(snapshot),
+            dispatched,
+            result_to_idle: ReleaseTimes::default(),
+            exits: Mutex::new(BTreeMap::new()),
+            maintenance: Mutex::new(None),
+            standby,
+            standby_owed: AtomicUsize::new(0),
+            standby_wanted: Notify::new(),
+            promotions: AtomicU64::new(0),
         }
+    }
+
+    /// Id of this pool's boot, which every worker uid it hands out carries
+    #[must_use]
+    pub fn boot_id(&self) -> &str {
+        self.uids.boot_id()
+    }
+
+    /// Refuse work after `limit` consecutive interpreter startup failures
+    #[must_use]
+    pub fn with_startup_failure_limit(mut self, limit: u32) -> Self {
+        self.breaker = Mutex::new(StartupBreaker::new(limit));
+        self
+    }
+
+    /// Hold the last `count` workers back as standbys for high-priority
+    /// requests
+    #[must_use]
+    pub fn with_standby(self, count: usize) -> Self {
+        for standby in self.standby.iter().rev().take(count) {
+            standby.store(true, Ordering::Release);
+        }
+        self.refresh_snapshot();
+        self
+    }
+
+    /// Allow up to `limit` executions at once to skip the queue (0 = none)
+    ///
+    /// Inline executions hold a runtime thread for their whole run, so this
+    /// should stay below the runtime's worker thread count.
+    #[must_use]
+    pub const fn with_inline_limit(mut self, limit: usize) -> Self {
+        self.inline = InlineSlots {
+            in_use: AtomicUsize::new(0),
+            limit,
+        };
+        self
+    }
 
-        Ok(Self { workers, config })
+    /// Publish a `WorkerDied` event whenever a worker dies or fails to
+    /// respawn, and a `WorkerExited` one whenever it exits on its own
+    #[must_use]
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
     }
 
-    /// Get an idle worker from the pool
-    pub fn get_idle(&self) -> Option<Arc<Mutex<Worker>>> {
+    /// Account each worker process in a cgroup of its own under `root`,
+    /// named after its uid, for the peak memory and OOM kills of its
+    /// executions
+    ///
+    /// `root` must be a delegated cgroup v2 directory with no processes of
+    /// its own. A worker whose cgroup cannot be set up is warned about and
+    /// runs unaccounted, reporting its interpreter's peak RSS.
+    #[must_use]
+    pub fn with_cgroups(mut self, root: &Path) -> Self {
+        if self.mock.is_some() {
+            return self;
+        }
         for worker in &self.workers {
-            let guard = worker.lock();
-            if guard.state == WorkerState::Idle {
+            let mut worker = worker.lock();
+            let placed = worker.set_cgroup_root(root);
+            let (id, uid) = (worker.id, worker.uid.clone());
+            drop(worker);
+            if let Err(e) = placed {
+                tracing::warn!(
+                    worker_id = id,
+                    worker_uid = ?uid,
+                    error = %e,
+                    "worker not accounted in a cgroup"
+                );
+            }
+        }
+        self.cgroup_root = Some(root.to_path_buf());
+        self
+    }
+
+    /// Remove the worker cgroups no running process of this pool owns:
+    /// those of other boots, and those of this one's processes that are gone
+    ///
+    /// Returns the paths removed; cgroups still holding a process stay.
+    pub fn remove_stale_cgroups(&self) -> Vec<PathBuf> {
+        let Some(root) = &self.cgroup_root else {
+            return Vec::new();
+        };
+        // Uids handed out from here on belong to processes still starting
+        let next = self.uids.peek();
+        let live: BTreeSet<u64> = self
+            .workers
+            .iter()
+            .filter_map(|worker| {
+                let worker = worker.lock();
+                worker
+                    .uid
+                    .as_ref()
+                    .filter(|_| worker.pid.is_some())
+                    .map(|uid| uid.seq)
+            })
+            .collect();
+        cgroups::remove_stale(root, |name| {
+            WorkerUid::from_cgroup_name(name).is_some_and(|uid| {
+                uid.boot_id != self.uids.boot_id() || (uid.seq < next && !live.contains(&uid.seq))
+            })
+        })
+    }
+
+    /// Time slice preemptible executions as `slicing` says, through
+    /// [`Self::time_slice`]
+    #[must_use]
+    pub const fn with_time_slicing(mut self, slicing: TimeSlicing) -> Self {
+        self.slicing = Some(slicing);
+        self
+    }
+
+    /// How batch executions are time sliced, if they are
+    pub const fn time_slicing(&self) -> Option<TimeSlicing> {
+        self.slicing
+    }
+
+    /// Log a worker's death and tell event subscribers
+    fn report_death(&self, worker_id: u32, error: &LeewardError) {
+        tracing::error!(worker_id, "worker died: {}", error);
+        *self.exits.lock().entry(DIED).or_default() += 1;
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::worker_died(worker_id, error));
+        }
+    }
+
+    /// Log a worker exiting on its own and tell event subscribers
+    fn report_goodbye(&self, worker_id: u32, reason: GoodbyeReason) {
+        tracing::info!(
+            worker_id,
+            reason = reason.kind(),
+            "worker exited: {}",
+            reason
+        );
+        *self.exits.lock().entry(reason.kind()).or_default() += 1;
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::worker_exited(worker_id, reason));
+        }
+    }
+
+    /// Replace workers whose process died while idle, returning how many
+    /// died
+    ///
+    /// Skips workers locked by someone else; a worker is checked again
+    /// before each dispatch anyway.
+    pub fn check_idle(&self) -> usize {
+        let mut died = 0;
+        for worker in &self.workers {
+            let Some(mut guard) = worker.try_lock() else {
+                continue;
+            };
+            if self.replace_if_died(&mut guard) {
+                died += 1;
                 drop(guard);
-                return Some(Arc::clone(worker));
+                self.idle.notify_one();
             }
         }
-        None
+        if died > 0 {
+            self.refresh_snapshot();
+        }
+        died
     }
 
-    /// Execute code using an available worker
-    pub async fn execute(&self, code: &str) -> Result<ExecutionResult> {
-        // Get idle worker
-        let worker = self.get_idle().ok_or_else(|| {
-            LeewardError::Execution("no idle workers available".into())
-        })?;
+    /// Report and respawn an idle worker whose process has died, returning
+    /// whether it had
+    fn replace_if_died(&self, worker: &mut Worker) -> bool {
+        if self.mock.is_some() {
+            return false;
+        }
+        let Some(error) = worker.check_idle() else {
+            return false;
+        };
+        self.report_death(worker.id, &error);
+        if let Err(e) = self.respawn(worker) {
+            self.report_death(worker.id, &e);
+        }
+        true
+    }
 
-        // Execute
-        let result = {
-            let mut guard = worker.lock();
-            guard.execute(code)?
+    /// Claim an idle worker that is not a standby, locked for the caller's
+    /// exclusive use
+    ///
+    /// Workers locked by someone else are busy and skipped.
+    fn claim_idle(&self) -> Option<MutexGuard<'_, Worker>> {
+        self.workers
+            .iter()
+            .zip(&self.standby)
+            .find_map(|(worker, standby)| {
+                worker.try_lock().filter(|guard| {
+                    guard.state == WorkerState::Idle && !standby.load(Ordering::Acquire)
+                })
+            })
+    }
+
+    /// Claim an idle worker, or failing that promote an idle standby if
+    /// `standby` allows
+    fn claim(&self, standby: bool) -> Option<MutexGuard<'_, Worker>> {
+        self.claim_idle()
+            .or_else(|| standby.then(|| self.promote_standby()).flatten())
+    }
+
+    /// Take an idle standby out of reserve, owing the pool a replacement
+    fn promote_standby(&self) -> Option<MutexGuard<'_, Worker>> {
+        let (guard, standby) =
+            self.workers
+                .iter()
+                .zip(&self.standby)
+                .find_map(|(worker, standby)| {
+                    if !standby.load(Ordering::Acquire) {
+                        return None;
+                    }
+                    let guard = worker
+                        .try_lock()
+                        .filter(|guard| guard.state == WorkerState::Idle)?;
+                    Some((guard, standby))
+                })?;
+        standby.store(false, Ordering::Release);
+        self.promotions.fetch_add(1, Ordering::Relaxed);
+        self.standby_owed.fetch_add(1, Ordering::AcqRel);
+        self.standby_wanted.notify_one();
+        if DebugFlag::Scheduler.enabled() {
+            tracing::info!(worker_id = guard.id, "standby worker promoted");
+        }
+        Some(guard)
+    }
+
+    /// Respawn idle workers to stand by in place of promoted ones, as many
+    /// as are owed and idle, returning how many
+    ///
+    /// The replacement is a fresh process under the current config. Queued
+    /// requests come first, so nothing is taken while any wait.
+    pub fn refill_standby(&self) -> usize {
+        if self.queue.depth() > 0 {
+            return 0;
+        }
+        let mut refilled = 0;
+        for (worker, standby) in self.workers.iter().zip(&self.standby) {
+            if self.standby_owed.load(Ordering::Acquire) == 0 {
+                break;
+            }
+            if standby.load(Ordering::Acquire) {
+                continue;
+            }
+            let Some(mut guard) = worker
+                .try_lock()
+                .filter(|guard| guard.state == WorkerState::Idle)
+            else {
+                continue;
+            };
+            if let Err(e) = self.respawn(&mut guard) {
+                self.report_death(guard.id, &e);
+                continue;
+            }
+            standby.store(true, Ordering::Release);
+            self.standby_owed.fetch_sub(1, Ordering::AcqRel);
+            refilled += 1;
+        }
+        if refilled > 0 {
+            self.refresh_snapshot();
+        }
+        refilled
+    }
+
+    /// Wait until a promoted standby is owed a replacement and a worker may
+    /// be free to become it
+    pub async fn standby_wanted(&self) {
+        self.standby_wanted.notified().await;
+    }
+
+    /// Whether any worker may still become idle, counting standbys if
+    /// `standby` allows
+    fn can_serve(&self, standby: bool) -> bool {
+        self.workers
+            .iter()
+            .zip(&self.standby)
+            .filter(|(_, held)| standby || !held.load(Ordering::Acquire))
+            .any(|(worker, _)| {
+                worker
+                    .try_lock()
+                    .is_none_or(|guard| guard.state != WorkerState::Dead)
+            })
+    }
+
+    /// Wait in the queue until a worker is free; a high-priority request
+    /// takes a standby rather than wait
+    async fn acquire(
+        &self,
+        journal: &Journal,
+        priority: RequestPriority,
+    ) -> Result<MutexGuard<'_, Worker>> {
+        let standby = priority == RequestPriority::High;
+        if let Some(worker) = self.claim(standby) {
+            return Ok(worker);
+        }
+
+        journal.record(RequestStage::Queued);
+        let _ticket = self.queue.join();
+        if DebugFlag::Scheduler.enabled() {
+            tracing::info!(
+                execution_id = journal.id,
+                depth = self.queue.depth(),
+                "queued for a worker"
+            );
+        }
+        loop {
+            // Register before checking, so a worker freed in between still wakes us
+            let freed = self.idle.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+
+            if let Some(worker) = self.claim(standby) {
+                return Ok(worker);
+            }
+            if !self.can_serve(standby) {
+                return Err(LeewardError::Execution("no idle workers available".into()));
+            }
+            freed.await;
+        }
+    }
+
+    /// Execute code on the next free worker, queueing while all are busy
+    /// unless `priority` lets it take a standby
+    pub async fn execute(
+        &self,
+        code: &str,
+        options: &ExecuteOptions,
+        journal: &Journal,
+        priority: RequestPriority,
+    ) -> Result<ExecutionResult> {
+        // Requests that override the memory limit say nothing about the config
+        let breaker_fingerprint = options
+            .memory_limit
+            .is_none()
+            .then(|| self.fingerprint.read().clone());
+        if let Some(fingerprint) = &breaker_fingerprint {
+            self.breaker.lock().check(fingerprint)?;
+        }
+
+        let worker = self.acquire(journal, priority).await?;
+
+        // Execution blocks on the worker pipe; keep it off the async workers
+        tokio::task::block_in_place(|| {
+            self.dispatch(
+                worker,
+                code,
+                options,
+                breaker_fingerprint.is_some(),
+                journal,
+            )
+        })
+    }
+
+    /// Execute code right here if a worker is idle and an inline slot is free
+    ///
+    /// Skips the queue and the hand-off to a blocking thread, which is most
+    /// of the latency of a tiny snippet. Returns `None` without side effects
+    /// when either is busy, or when requests are already queued so they are
+    /// not overtaken; the caller then falls back to [`Self::execute`].
+    pub fn try_execute_inline(
+        &self,
+        code: &str,
+        options: &ExecuteOptions,
+        journal: &Journal,
+    ) -> Option<Result<ExecutionResult>> {
+        let _slot = self.inline.try_acquire()?;
+        if self.queue.depth() > 0 {
+            return None;
+        }
+
+        let breaker_fingerprint = options
+            .memory_limit
+            .is_none()
+            .then(|| self.fingerprint.read().clone());
+        if let Some(fingerprint) = &breaker_fingerprint {
+            let allowed = self.breaker.lock().check(fingerprint);
+            if let Err(e) = allowed {
+                return Some(Err(e));
+            }
+        }
+
+        let worker = self.claim_idle()?;
+        Some(self.dispatch(
+            worker,
+            code,
+            options,
+            breaker_fingerprint.is_some(),
+            journal,
+        ))
+    }
+
+    /// Run code on a claimed worker, then recycle it if due and release it
+    fn dispatch(
+        &self,
+        mut worker: MutexGuard<'_, Worker>,
+        code: &str,
+        options: &ExecuteOptions,
+        record_startup: bool,
+        journal: &Journal,
+    ) -> Result<ExecutionResult> {
+        journal.running(&worker);
+        let worker_id = worker.id;
+        if DebugFlag::Scheduler.enabled() {
+            tracing::info!(
+                execution_id = journal.id,
+                worker_id,
+                preemptible = options.preemptible,
+                "dispatched to a worker"
+            );
+        }
+        if let (true, Some(slicing)) = (options.preemptible, self.slicing) {
+            let timeout = options.timeout.unwrap_or_else(|| self.timeout());
+            let run = BatchRun {
+                execution_id: journal.id,
+                resumed: Instant::now(),
+                frozen_since: None,
+                frozen_total: Duration::ZERO,
+                budget: slicing.frozen_budget(timeout),
+                preempted_count: 0,
+            };
+            self.batch.lock().insert(worker_id, run);
+        }
+        let dispatched = self.dispatched.get(worker_id as usize);
+        if let Some(dispatched) = dispatched {
+            dispatched.store(true, Ordering::Release);
+        }
+        let (outcome, ready) = self.run_on(&mut worker, code, options, record_startup);
+        self.batch.lock().remove(&worker_id);
+        if let Some(dispatched) = dispatched {
+            dispatched.store(false, Ordering::Release);
+        }
+        drop(worker);
+        self.idle.notify_one();
+        if self.standby_owed.load(Ordering::Acquire) > 0 {
+            self.standby_wanted.notify_one();
+        }
+        self.result_to_idle.observe(ready.elapsed());
+        self.refresh_snapshot();
+        outcome
+    }
+
+    /// Run code on a worker, recycling it if due, returning the outcome and
+    /// when the worker's result was read
+    fn run_on(
+        &self,
+        worker: &mut Worker,
+        code: &str,
+        options: &ExecuteOptions,
+        record_startup: bool,
+    ) -> (Result<ExecutionResult>, Instant) {
+        self.replace_if_died(worker);
+        let oom = self.subscribe_oom(worker);
+        let outcome = match self.mock {
+            Some(latency) => Ok(echo(worker, code, latency)),
+            None => worker.execute(code, options),
+        };
+        let ready = Instant::now();
+        let oom_peak = oom.and_then(|oom| oom_peak(&oom, &outcome));
+        let outcome = self.finish_run(worker, outcome, record_startup);
+        let Some(peak) = oom_peak else {
+            return (outcome, ready);
         };
+        // A worker that died was respawned already; one that answered may
+        // have been left short of memory it could not reclaim
+        tracing::warn!(
+            worker_id = worker.id,
+            peak,
+            "execution OOM-killed; recycling its worker"
+        );
+        if outcome.is_ok() {
+            if let Err(e) = self.respawn(worker) {
+                self.report_death(worker.id, &e);
+            }
+        }
+        (Err(LeewardError::MemoryLimitExceeded(peak)), ready)
+    }
 
-        // Check if worker needs recycling
-        {
-            let mut guard = worker.lock();
-            if guard.should_recycle(100) {
-                guard.recycle()?;
+    /// Watch the cgroup of `worker`, if it has one, for the kernel
+    /// OOM-killing an execution
+    fn subscribe_oom(&self, worker: &Worker) -> Option<OomEventReceiver> {
+        if self.mock.is_some() {
+            return None;
+        }
+        worker
+            .cgroup()?
+            .subscribe_oom_events()
+            .map_err(
+                |e| tracing::warn!(worker_id = worker.id, error = %e, "OOM kills not watched for"),
+            )
+            .ok()
+    }
+
+    /// Tidy up after a worker's run: respawn it if the run broke it,
+    /// recycle it if due
+    fn finish_run(
+        &self,
+        worker: &mut Worker,
+        outcome: Result<ExecutionResult>,
+        record_startup: bool,
+    ) -> Result<ExecutionResult> {
+        if let Err(e) = &outcome {
+            if worker.state == WorkerState::Busy {
+                // Its pipe broke mid-execution, usually because it was reclaimed
+                self.report_death(worker.id, e);
+                if let Err(e) = self.respawn(worker) {
+                    self.report_death(worker.id, &e);
+                }
             }
         }
+        if record_startup {
+            self.breaker
+                .lock()
+                .record(&worker.config_fingerprint, &outcome);
+        }
+        let goodbye = worker.take_goodbye();
+        if let Some(reason) = goodbye {
+            self.report_goodbye(worker.id, reason);
+            if !replaced_after(reason) {
+                worker.stop();
+            }
+        }
+        let result = outcome?;
 
+        let leaving = goodbye.is_some_and(replaced_after);
+        if leaving || self.take_drain(worker.id) || worker.should_recycle(100) {
+            if let Err(e) = self.respawn(worker) {
+                self.report_death(worker.id, &e);
+                return Err(e);
+            }
+        }
         Ok(result)
     }
 
-    /// Get pool status
-    pub fn status(&self) -> PoolStatus {
-        let mut idle = 0;
-        let mut busy = 0;
-        let mut recycling = 0;
-        let mut dead = 0;
+    /// Replace a worker's process with one spawned under the current config
+    /// and root template
+    fn respawn(&self, worker: &mut Worker) -> Result<()> {
+        self.refresh_config(worker);
+        let outcome = if self.mock.is_some() {
+            worker.uid = Some(self.uids.next());
+            worker.execution_count = 0;
+            worker.interpreter = InterpreterStamp::of(&self.config.read().python_path);
+            Ok(())
+        } else {
+            worker.set_root_template(self.template.read().clone());
+            worker.recycle()
+        };
+        if let Some(stamp) = self.interpreters.lock().get_mut(worker.id as usize) {
+            *stamp = worker.interpreter;
+        }
+        outcome
+    }
+
+    /// Mark every worker for recycling once it is free, rebuilding the root
+    /// template first if workers use one
+    ///
+    /// Returns whether the template was rebuilt. Fails, marking nothing,
+    /// if the template cannot be rebuilt; workers then keep the old one.
+    pub fn drain(&self, reason: Option<&str>) -> Result<bool> {
+        tracing::info!(
+            reason = reason.unwrap_or("none given"),
+            "draining worker pool"
+        );
+        let rebuilt = if self.template.read().is_some() {
+            let template = RootTemplate::build(&self.config.read())?;
+            tracing::info!(root = ?template.root(), "root template rebuilt");
+            *self.template.write() = Some(Arc::new(template));
+            true
+        } else {
+            false
+        };
+
+        // Worker ids are their indices
+        let ids = 0..u32::try_from(self.workers.len()).unwrap_or(u32::MAX);
+        self.drain.lock().pending.extend(ids);
+        self.refresh_snapshot();
+        Ok(rebuilt)
+    }
+
+    /// Recycle up to `max` drain-pending workers that are not busy
+    ///
+    /// Returns how many were recycled; busy workers are recycled as they
+    /// finish instead.
+    pub fn drain_idle(&self, max: usize) -> usize {
+        let pending: Vec<u32> = self.drain.lock().pending.iter().copied().collect();
+        let mut recycled = 0;
+
+        for worker_id in pending {
+            if recycled == max {
+                break;
+            }
+            let Some(mut guard) = self
+                .workers
+                .get(worker_id as usize)
+                .and_then(|worker| worker.try_lock())
+            else {
+                continue;
+            };
+            if guard.state == WorkerState::Busy || !self.take_drain(worker_id) {
+                continue;
+            }
+            if let Err(e) = self.respawn(&mut guard) {
+                self.report_death(worker_id, &e);
+            }
+            drop(guard);
+            self.idle.notify_one();
+            recycled += 1;
+        }
+
+        if recycled > 0 {
+            self.refresh_snapshot();
+        }
+        recycled
+    }
+
+    /// Unmark a worker as drain-pending, returning whether it was
+    fn take_drain(&self, worker_id: u32) -> bool {
+        let mut drain = self.drain.lock();
+        let pending = drain.pending.remove(&worker_id);
+        if pending {
+            drain.drained += 1;
+        }
+        drop(drain);
+        pending
+    }
+
+    /// Workers still to be recycled by a drain
+    pub fn draining(&self) -> usize {
+        self.drain.lock().pending.len()
+    }
+
+    /// Stop running executions for `reason` and report unhealthy, until
+    /// the daemon restarts
+    pub fn enter_maintenance(&self, reason: &str) {
+        self.maintenance.lock().get_or_insert_with(|| reason.into());
+        self.refresh_snapshot();
+    }
+
+    /// Why the daemon is in maintenance, if it is
+    pub fn maintenance(&self) -> Option<Arc<str>> {
+        self.maintenance.lock().clone()
+    }
+
+    /// Freeze batch executions that have run for a slice while other
+    /// executions run, and thaw frozen ones once none do or their frozen
+    /// budget is spent
+    ///
+    /// Returns each execution frozen (`true`) or thawed. Queued requests
+    /// do not count until they run: freezing frees the CPU, not the worker.
+    pub fn time_slice(&self) -> Vec<(PreemptionEvent, bool)> {
+        let Some(slicing) = self.slicing else {
+            return Vec::new();
+        };
+        let busy = self.busy_now();
+        let mut batch = self.batch.lock();
+        let contended = busy > batch.len();
+        let mut changes = Vec::new();
+
+        for (&worker_id, run) in batch.iter_mut() {
+            let Some(preemption) = self.preemptions.get(worker_id as usize) else {
+                continue;
+            };
+            match run.frozen_since {
+                None if contended
+                    && run.resumed.elapsed() >= slicing.slice
+                    && run.frozen_total < run.budget =>
+                {
+                    match preemption.freeze() {
+                        Ok(Freeze::Frozen) => {
+                            run.frozen_since = Some(Instant::now());
+                            run.preempted_count += 1;
+                            changes.push((run.event(worker_id), true));
+                        }
+                        Ok(Freeze::HoldsNetwork) => {
+                            tracing::debug!(
+                                worker_id,
+                                "not freezing a batch execution that uses the network"
+                            );
+                        }
+                        Ok(Freeze::AlreadyFrozen | Freeze::Idle) => {}
+                        Err(e) => {
+                            tracing::warn!(
+                                worker_id,
+                                error = %e,
+                                "failed to freeze batch execution"
+                            );
+                        }
+                    }
+                }
+                Some(since) if !contended || run.frozen_total + since.elapsed() >= run.budget => {
+                    if let Err(e) = preemption.thaw() {
+                        tracing::warn!(worker_id, error = %e, "failed to thaw batch execution");
+                    }
+                    run.frozen_total += since.elapsed();
+                    run.frozen_since = None;
+                    run.resumed = Instant::now();
+                    changes.push((run.event(worker_id), false));
+                }
+                _ => {}
+            }
+        }
+        drop(batch);
+        changes
+    }
+
+    /// Kill a worker that is stuck on an execution
+    ///
+    /// The execution waiting on it then fails and the worker is replaced.
+    /// A worker that is no longer busy has moved on and is left alone.
+    pub fn reclaim(&self, worker_id: u32, pid: i32) {
+        let Some(worker) = self.workers.get(worker_id as usize) else {
+            return;
+        };
+        if worker.try_lock().is_some() {
+            return;
+        }
+        tracing::warn!(worker_id, pid, "reclaiming stuck worker");
+        // SAFETY: Signalling a worker process we spawned and have not reaped
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+
+    /// Workers claimed for an execution right now, without waiting on any
+    pub fn busy_now(&self) -> usize {
+        self.workers
+            .iter()
+            .filter(|worker| {
+                worker
+                    .try_lock()
+                    .is_none_or(|guard| guard.state == WorkerState::Busy)
+            })
+            .count()
+    }
+
+    /// Current queue and worker health, for alerting
+    ///
+    /// Never waits on a busy worker, so it stays cheap under saturation.
+    pub fn sample(&self) -> PoolSample {
+        let dead = self
+            .workers
+            .iter()
+            .filter(|worker| {
+                worker
+                    .try_lock()
+                    .is_some_and(|guard| guard.state == WorkerState::Dead)
+            })
+            .count();
+
+        // Workers that failed to spawn have no stamp and are not counted
+        let current = InterpreterStamp::of(&self.config.read().python_path);
+        let outdated = current.map_or(0, |current| {
+            self.interpreters
+                .lock()
+                .iter()
+                .filter(|stamp| stamp.is_some_and(|stamp| stamp != current))
+                .count()
+        });
+
+        PoolSample {
+            queue_depth: self.queue.depth() as u64,
+            queue_wait_ms: u64::try_from(self.queue.oldest_wait().as_millis()).unwrap_or(u64::MAX),
+            dead_workers: dead as u64,
+            outdated_interpreters: outdated as u64,
+        }
+    }
+
+    /// Switch to a new config; running workers keep theirs until recycled
+    pub fn reload_config(&self, config: SandboxConfig) {
+        let fingerprint = config.fingerprint();
+        tracing::info!(%fingerprint, "sandbox config reloaded");
+        let mut current = self.config.write();
+        *current = config;
+        *self.fingerprint.write() = fingerprint.into();
+        drop(current);
+        self.refresh_snapshot();
+    }
+
+    /// Run code in a worker of its own under the debug profile, killed
+    /// afterwards, so no pooled worker ever runs without the debugging
+    /// restrictions
+    ///
+    /// The worker's pid is logged once it is ready, for a sampler such as
+    /// `py-spy` to attach to.
+    pub fn execute_debug(&self, code: &str, options: &ExecuteOptions) -> Result<ExecutionResult> {
+        let config = SandboxConfig {
+            debug: true,
+            ..self.config()
+        };
+        let mut worker = Worker::new(DEBUG_WORKER_ID, config)
+            .with_uids(Arc::clone(&self.uids))
+            .with_network_slots(Arc::clone(&self.network_slots));
+        if let Some(latency) = self.mock {
+            return Ok(ExecutionResult {
+                debug: true,
+                ..echo(&mut worker, code, latency)
+            });
+        }
+
+        worker.set_root_template(self.template.read().clone());
+        worker.spawn()?;
+        if let Some(pid) = worker.pid {
+            tracing::info!(
+                pid,
+                "debug worker ready; sample it with `py-spy dump --subprocesses --pid {pid}`"
+            );
+        }
+        let outcome = worker.execute(code, options);
+        worker.stop();
+        outcome
+    }
+
+    /// Run code in an observed worker of its own, with every syscall
+    /// counted and nothing denied, killed afterwards
+    ///
+    /// The worker is rooted and accounted like pooled ones, so the profile
+    /// holds what its cgroup saw; see [`leeward_core::profile::profile_worker`].
+    pub fn execute_profiled(
+        &self,
+        code: &str,
+        options: &ExecuteOptions,
+    ) -> Result<(ExecutionResult, WorkloadProfile)> {
+        let mut worker = Worker::new(PROFILE_WORKER_ID, self.config())
+            .with_uids(Arc::clone(&self.uids))
+            .with_network_slots(Arc::clone(&self.network_slots))
+            .observed();
+        worker.set_root_template(self.template.read().clone());
+        if let Some(root) = &self.cgroup_root {
+            if let Err(e) = worker.set_cgroup_root(root) {
+                tracing::warn!(error = %e, "profiling worker not accounted in a cgroup");
+            }
+        }
+        leeward_core::profile::profile_worker(&mut worker, code, options)
+    }
+
+    /// Config new workers are spawned with
+    pub fn config(&self) -> SandboxConfig {
+        self.config.read().clone()
+    }
+
+    /// Timeout of the config new workers are spawned with, without cloning
+    /// the rest of it
+    pub fn timeout(&self) -> Duration {
+        self.config.read().timeout
+    }
+
+    /// Fingerprint of the config new workers are spawned with
+    pub fn current_fingerprint(&self) -> String {
+        self.fingerprint.read().to_string()
+    }
+
+    /// Recycle up to `max` idle workers running an old config
+    ///
+    /// Returns how many were recycled. Busy stale workers are left alone.
+    pub fn recycle_stale(&self, max: usize) -> usize {
+        let current = self.current_fingerprint();
+        let mut recycled = 0;
 
         for worker in &self.workers {
-            match worker.lock().state {
-                WorkerState::Idle => idle += 1,
-                WorkerState::Busy => busy += 1,
-                WorkerState::Recycling => recycling += 1,
-                WorkerState::Dead => dead += 1,
+            if recycled == max {
+                break;
+            }
+
+            let mut guard = worker.lock();
+            if guard.state != WorkerState::Idle || guard.config_fingerprint == current {
+                continue;
+            }
+
+            if let Err(e) = self.respawn(&mut guard) {
+                self.report_death(guard.id, &e);
             }
+            drop(guard);
+            self.idle.notify_one();
+            recycled += 1;
         }
 
-        PoolStatus {
-            total: self.workers.len(),
-            idle,
-            busy,
-            recycling,
-            dead,
+        if recycled > 0 {
+            self.refresh_snapshot();
+        }
+        recycled
+    }
+
+    /// Point a worker at the current config before it is respawned
+    fn refresh_config(&self, worker: &mut Worker) {
+        let config = self.config.read();
+        if *worker.config_fingerprint != **self.fingerprint.read() {
+            worker.reconfigure(config.clone());
         }
     }
+
+    /// Get details for every worker
+    pub fn worker_info(&self) -> Vec<WorkerInfo> {
+        self.workers
+            .iter()
+            .map(|worker| {
+                let mut info = worker.lock().info();
+                info.drain_pending = self.drain.lock().pending.contains(&info.id);
+                info
+            })
+            .collect()
+    }
+
+    /// Hold every worker's lock and the drain and queue locks for
+    /// `duration`, as a wedged pool would, calling `held` once they are all
+    /// held
+    #[cfg(feature = "testing")]
+    pub fn wedge(&self, duration: Duration, held: impl FnOnce()) {
+        let workers: Vec<_> = self.workers.iter().map(|worker| worker.lock()).collect();
+        let drain = self.drain.lock();
+        let queue = self.queue.waiting.lock();
+        held();
+        std::thread::sleep(duration);
+        drop((workers, drain, queue));
+    }
+
+    /// Pool status, as of the last snapshot
+    pub fn status(&self) -> PoolStatus {
+        self.snapshot.load().status()
+    }
+
+    /// Pool state as last seen, without taking any lock of the pool's
+    pub fn snapshot(&self) -> Arc<PoolSnapshot> {
+        self.snapshot.load_full()
+    }
+
+    /// Look at the pool again and publish what it saw as the snapshot
+    ///
+    /// Never waits on a lock. A worker locked for an execution is busy;
+    /// one locked for anything else, and counts whose lock is held, keep
+    /// what was seen before, along with when it was seen.
+    pub fn refresh_snapshot(&self) {
+        let previous = self.snapshot.load();
+        let now = Instant::now();
+        let current = self.fingerprint.try_read();
+        let workers = self
+            .workers
+            .iter()
+            .zip(&self.dispatched)
+            .zip(&previous.workers)
+            .zip(self.pipes.iter().zip(&self.standby))
+            .map(|(((worker, dispatched), seen), (pipes, standby))| {
+                // Sampled off the worker's lock, so fresh even while it is held
+                let (code_queued, result_queued) = (pipes.code(), pipes.result());
+                let standby = standby.load(Ordering::Acquire);
+                match worker.try_lock() {
+                    Some(guard) => WorkerSnapshot {
+                        state: guard.state,
+                        standby,
+                        seq: guard.uid.as_ref().map(|uid| uid.seq),
+                        stale: current.as_ref().map_or(seen.stale, |current| {
+                            *guard.config_fingerprint != ***current
+                        }),
+                        seen_at: now,
+                        code_queued,
+                        result_queued,
+                    },
+                    None if dispatched.load(Ordering::Acquire) => WorkerSnapshot {
+                        state: WorkerState::Busy,
+                        standby,
+                        stale: seen.stale,
+                        seq: seen.seq,
+                        seen_at: now,
+                        code_queued,
+                        result_queued,
+                    },
+                    None => WorkerSnapshot {
+                        standby,
+                        code_queued,
+                        result_queued,
+                        ..*seen
+                    },
+                }
+            })
+            .collect();
+        drop(current);
+
+        let counts = self.drain.try_lock().and_then(|drain| {
+            let waiting = self.queue.waiting.try_lock()?;
+            let oldest = waiting.first_key_value().map(|(_, &joined)| joined);
+            Some((waiting.len(), oldest, drain.pending.len(), drain.drained))
+        });
+        let (queue_depth, oldest_queued, draining, drained, counted_at) = match counts {
+            Some((depth, oldest, draining, drained)) => (depth, oldest, draining, drained, now),
+            None => (
+                previous.queue_depth,
+                previous.oldest_queued,
+                previous.draining,
+                previous.drained,
+                previous.counted_at,
+            ),
+        };
+        let recent_wait = self
+            .queue
+            .recent
+            .try_lock()
+            .map_or(previous.recent_wait, |recent| {
+                recent.percentile(WAIT_PERCENTILE)
+            });
+        let result_to_idle = self.result_to_idle.histogram();
+        let exits = self
+            .exits
+            .try_lock()
+            .map_or_else(|| previous.exits.clone(), |exits| exits.clone());
+
+        self.snapshot.store(Arc::new(PoolSnapshot {
+            workers,
+            boot_id: Arc::clone(&previous.boot_id),
+            queue_depth,
+            oldest_queued,
+            recent_wait,
+            result_to_idle,
+            exits,
+            draining,
+            drained,
+            maintenance: self.maintenance.try_lock().map_or_else(
+                || previous.maintenance.clone(),
+                |maintenance| maintenance.clone(),
+            ),
+            promotions: self.promotions.load(Ordering::Relaxed),
+            counted_at,
+            refreshed_at: now,
+        }));
+    }
+}
+
+/// Whether a worker that said goodbye for `reason` is replaced at once,
+/// rather than left `Dead`
+const fn replaced_after(reason: GoodbyeReason) -> bool {
+    match reason {
+        // Its replacement starts small again
+        GoodbyeReason::MemoryGrown { .. } => true,
+    }
+}
+
+/// Peak memory of an execution `oom` saw the kernel OOM-kill, from its
+/// result if the worker answered, else from the cgroup's `memory.peak`
+fn oom_peak(oom: &OomEventReceiver, outcome: &Result<ExecutionResult>) -> Option<u64> {
+    if !oom.kills().is_ok_and(|kills| kills > 0) {
+        return None;
+    }
+    Some(outcome.as_ref().map_or_else(
+        |_| {
+            oom.cgroup()
+                .memory_peak()
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        },
+        |result| result.memory_peak,
+    ))
+}
+
+/// What a mock worker answers: `code` on stdout, after `latency`
+fn echo(worker: &mut Worker, code: &str, latency: Duration) -> ExecutionResult {
+    std::thread::sleep(latency);
+    worker.execution_count += 1;
+    ExecutionResult {
+        exit_code: 0,
+        stdout: code.as_bytes().to_vec(),
+        duration: latency,
+        ..ExecutionResult::default()
+    }
 }
 
 /// Status of the worker pool
 #[derive(Debug, Clone)]
 pub struct PoolStatus {
+    /// Workers normal scheduling uses, standbys aside
     pub total: usize,
     pub idle: usize,
     pub busy: usize,
-    pub recycling: usize,
     pub dead: usize,
+    /// Workers spawned under an older config
+    pub stale: usize,
+    /// Workers still to be recycled by a drain
+    pub draining: usize,
+    /// Workers recycled by drains so far
+    pub drained: u64,
+}
+
+/// Pool state as last seen by [`WorkerPool::refresh_snapshot`]
+#[derive(Debug, Clone)]
+pub struct PoolSnapshot {
+    /// By worker id
+    pub workers: Vec<WorkerSnapshot>,
+    /// Boot id the workers' uids are under
+    pub boot_id: Arc<str>,
+    /// Requests waiting for a worker
+    pub queue_depth: usize,
+    /// When the request queued longest joined the queue
+    pub oldest_queued: Option<Instant>,
+    /// The [`WAIT_PERCENTILE`]th percentile of recent queue waits
+    pub recent_wait: Duration,
+    /// Time from reading each result to releasing its worker, so far
+    pub result_to_idle: ReleaseHistogram,
+    /// Worker processes gone so far, by [`DIED`] or goodbye reason
+    pub exits: BTreeMap<&'static str, u64>,
+    /// Workers still to be recycled by a drain
+    pub draining: usize,
+    /// Workers recycled by drains so far
+    pub drained: u64,
+    /// Why the daemon is in maintenance, if it is
+    pub maintenance: Option<Arc<str>>,
+    /// Standby workers promoted so far
+    pub promotions: u64,
+    /// When the queue and drain counts were seen
+    pub counted_at: Instant,
+    /// When the snapshot was last refreshed
+    pub refreshed_at: Instant,
+}
+
+/// One worker in a [`PoolSnapshot`]
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerSnapshot {
+    pub state: WorkerState,
+    /// Held back for high-priority requests
+    pub standby: bool,
+    /// Spawned under an older config
+    pub stale: bool,
+    /// `seq` of the process's uid, under [`PoolSnapshot::boot_id`]
+    pub seq: Option<u64>,
+    /// When the worker was seen in this state
+    pub seen_at: Instant,
+    /// Bytes waiting in the code pipe for the worker to read, as last
+    /// sampled
+    pub code_queued: usize,
+    /// Bytes waiting in the result pipe for the daemon to read, as last
+    /// sampled
+    pub result_queued: usize,
+}
+
+impl PoolSnapshot {
+    /// How long ago its oldest part was seen; a few refresh intervals at
+    /// most, unless a lock is wedged or the refresher is stuck
+    pub fn age(&self) -> Duration {
+        self.workers
+            .iter()
+            .map(|worker| worker.seen_at)
+            .chain([self.counted_at, self.refreshed_at])
+            .min()
+            .map_or(Duration::ZERO, |oldest| oldest.elapsed())
+    }
+
+    /// Workers in `state`, standbys aside
+    pub fn count(&self, state: WorkerState) -> usize {
+        self.workers
+            .iter()
+            .filter(|worker| !worker.standby && worker.state == state)
+            .count()
+    }
+
+    /// Workers held back as standbys
+    pub fn standby(&self) -> usize {
+        self.workers.iter().filter(|worker| worker.standby).count()
+    }
+
+    /// Workers normal scheduling uses
+    pub fn serving(&self) -> usize {
+        self.workers.len() - self.standby()
+    }
+
+    /// Whether at least `min_idle` workers are idle, outside maintenance,
+    /// and how long a new request would wait for one
+    pub fn health(&self, min_idle: usize) -> Health {
+        let idle = self.count(WorkerState::Idle);
+        let live = self.serving() - self.count(WorkerState::Dead);
+        let est_wait = if idle > self.queue_depth {
+            Duration::ZERO
+        } else {
+            let oldest = self
+                .oldest_queued
+                .map_or(Duration::ZERO, |joined| joined.elapsed());
+            self.recent_wait.max(oldest)
+        };
+        Health {
+            healthy: self.maintenance.is_none() && live > 0 && idle >= min_idle,
+            idle,
+            queue_depth: self.queue_depth,
+            est_wait,
+        }
+    }
+
+    /// The counts of [`PoolStatus`]
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            total: self.serving(),
+            idle: self.count(WorkerState::Idle),
+            busy: self.count(WorkerState::Busy),
+            dead: self.count(WorkerState::Dead),
+            stale: self.workers.iter().filter(|worker| worker.stale).count(),
+            draining: self.draining,
+            drained: self.drained,
+        }
+    }
+
+    /// Each worker's pool index, with the uid of its process
+    pub fn slots(&self) -> Vec<WorkerSlot> {
+        (0..)
+            .zip(&self.workers)
+            .map(|(id, worker)| WorkerSlot {
+                id,
+                uid: worker.seq.map(|seq| WorkerUid {
+                    boot_id: self.boot_id.to_string(),
+                    seq,
+                }),
+            })
+            .collect()
+    }
+}
+
+/// Capacity of the pool as a load balancer sees it, from
+/// [`PoolSnapshot::health`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    pub healthy: bool,
+    pub idle: usize,
+    pub queue_depth: usize,
+    /// How long a request sent now would likely wait for a worker
+    pub est_wait: Duration,
+}
+
+impl Health {
+    /// The answer to a health probe
+    pub fn response(self) -> Response {
+        Response::Health {
+            healthy: self.healthy,
+            idle: self.idle,
+            queue_depth: self.queue_depth,
+            est_wait_ms: u64::try_from(self.est_wait.as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// Cumulative counts of worker release times, in
+/// [`RESULT_TO_IDLE_BUCKETS`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReleaseHistogram {
+    /// Releases no slower than each bucket's bound, by bucket
+    pub buckets: [u64; RESULT_TO_IDLE_BUCKETS.len()],
+    pub count: u64,
+    pub sum_micros: u64,
+}
+
+/// Worker release times, observed without locking
+#[derive(Debug, Default)]
+struct ReleaseTimes {
+    buckets: [AtomicU64; RESULT_TO_IDLE_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl ReleaseTimes {
+    fn observe(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        for (bucket, bound) in self.buckets.iter().zip(RESULT_TO_IDLE_BUCKETS) {
+            if micros <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn histogram(&self) -> ReleaseHistogram {
+        ReleaseHistogram {
+            buckets: self
+                .buckets
+                .each_ref()
+                .map(|bucket| bucket.load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Requests waiting for a worker, by arrival
+#[derive(Debug, Default)]
+struct Queue {
+    waiting: Mutex<BTreeMap<u64, Instant>>,
+    next_ticket: AtomicU64,
+    /// How long the latest requests to leave it waited
+    recent: Mutex<RecentWaits>,
+}
+
+/// Waits of the latest requests to leave the queue, with when they left
+#[derive(Debug, Default)]
+struct RecentWaits {
+    waits: VecDeque<(Instant, Duration)>,
+}
+
+impl RecentWaits {
+    fn record(&mut self, wait: Duration) {
+        if self.waits.len() == RECENT_WAITS {
+            self.waits.pop_front();
+        }
+        self.waits.push_back((Instant::now(), wait));
+    }
+
+    /// The `percentile`th percentile of the waits younger than
+    /// [`RECENT_WAIT_AGE`], zero without any
+    fn percentile(&self, percentile: usize) -> Duration {
+        let mut waits: Vec<Duration> = self
+            .waits
+            .iter()
+            .filter(|(left, _)| left.elapsed() < RECENT_WAIT_AGE)
+            .map(|&(_, wait)| wait)
+            .collect();
+        if waits.is_empty() {
+            return Duration::ZERO;
+        }
+        waits.sort_unstable();
+        waits[(waits.len() - 1) * percentile / 100]
+    }
+}
+
+impl Queue {
+    /// Enter the queue until the returned ticket is dropped
+    fn join(&self) -> QueueTicket<'_> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        self.waiting.lock().insert(ticket, Instant::now());
+        QueueTicket {
+            queue: self,
+            ticket,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.waiting.lock().len()
+    }
+
+    /// How long the oldest waiting request has been queued
+    fn oldest_wait(&self) -> Duration {
+        self.waiting
+            .lock()
+            .first_key_value()
+            .map_or(Duration::ZERO, |(_, joined)| joined.elapsed())
+    }
+}
+
+/// A request's place in the [`Queue`]
+struct QueueTicket<'a> {
+    queue: &'a Queue,
+    ticket: u64,
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        let joined = self.queue.waiting.lock().remove(&self.ticket);
+        if let Some(joined) = joined {
+            self.queue.recent.lock().record(joined.elapsed());
+        }
+    }
+}
+
+/// Counts executions running inline, up to a limit
+#[derive(Debug, Default)]
+struct InlineSlots {
+    in_use: AtomicUsize,
+    limit: usize,
+}
+
+impl InlineSlots {
+    /// Take a slot until the returned guard is dropped, if one is free
+    fn try_acquire(&self) -> Option<InlineSlot<'_>> {
+        if self.in_use.fetch_add(1, Ordering::Acquire) >= self.limit {
+            self.in_use.fetch_sub(1, Ordering::Release);
+            return None;
+        }
+        Some(InlineSlot { slots: self })
+    }
+}
+
+/// A held [`InlineSlots`] slot
+struct InlineSlot<'a> {
+    slots: &'a InlineSlots,
+}
+
+impl Drop for InlineSlot<'_> {
+    fn drop(&mut self) {
+        self.slots.in_use.fetch_sub(1, Ordering::Release);
+    }
 }
@@ -0,0 +1,49 @@
+//! Reaping leaked template roots, their mounts and worker cgroups, on a
+//! timer off the request path
+
+use crate::metrics::Metrics;
+use crate::pool::WorkerPool;
+use crate::supervisor;
+use leeward_core::isolation::registry::{self, Reaped};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Reconcile the roots dir against the roots in use every `interval`,
+/// reaping orphans older than `grace`, and the pool's cgroups against its
+/// running worker processes
+///
+/// Each removal is logged and counted in `leeward_leaked_reaped_total`; anything
+/// but zero there points at a cleanup bug or a crashed daemon.
+pub async fn run(
+    interval: Duration,
+    grace: Duration,
+    pool: Arc<WorkerPool>,
+    metrics: Arc<Metrics>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+
+        let dir = registry::roots_dir();
+        let pool = Arc::clone(&pool);
+        let reaped = supervisor::blocking(move || {
+            let mut reaped = registry::reconcile(&dir, grace);
+            reaped.extend(pool.remove_stale_cgroups().into_iter().map(Reaped::Cgroup));
+            reaped
+        })
+        .await
+        .unwrap_or_default();
+        for orphan in reaped {
+            tracing::warn!(
+                kind = orphan.kind(),
+                path = ?orphan.path(),
+                "reaped leaked {}",
+                orphan.kind()
+            );
+            metrics.leak_reaped(&orphan);
+        }
+    }
+}
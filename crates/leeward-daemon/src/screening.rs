@@ -0,0 +1,507 @@
+//! Screening code before it reaches a worker
+//!
+//! Every execution is passed to a [`Screening`] hook after the daemon has
+//! adjusted it and before it is dispatched. [`ScreenVerdict::Deny`] turns
+//! it away with [`ErrorKind::PolicyRejected`](leeward_core::protocol::ErrorKind::PolicyRejected)
+//! and the reason as the message; [`ScreenVerdict::Flag`] lets it run and
+//! logs the note with the request id, for the audit trail. Embedders plug
+//! in their own hook with [`Daemon::with_screening`](crate::Daemon::with_screening).
+//!
+//! The built-in hook is a list of [`Rules`] read from the TOML file named by
+//! `screening_rules` (`LEEWARD_SCREENING_RULES`), and read again on every
+//! `SIGHUP`:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "no-ctypes"
+//! import = "ctypes"
+//! reason = "ctypes reaches native code"
+//!
+//! [[rule]]
+//! name = "no-sockets"
+//! import = "socket"
+//! without_network = true
+//!
+//! [[rule]]
+//! name = "long"
+//! action = "flag"
+//! max_lines = 5000
+//! ```
+//!
+//! Each rule has a `name`, an `action` (`deny`, the default, or `flag`)
+//! and one test: `import` (a Python module or any submodule of it, through
+//! `import`, `from ... import`, `__import__` or `importlib.import_module`
+//! with a literal name), `substring`, `regex`, `max_bytes`, `max_lines` or
+//! `max_line_length`. `interpreters` limits a rule to some languages
+//! (`import` rules only ever apply to Python), `without_network` to
+//! sandboxes with networking off, and `reason` replaces the one the rule
+//! would give. The first rule that denies decides; flags add up.
+//!
+//! Tests other than the size limits look at the code as Python would read
+//! it, more or less: line continuations joined, string escapes such as
+//! `\x63` decoded, NFKC normalized (so fullwidth `ｃｔｙｐｅｓ` is `ctypes`,
+//! as it is to Python), invisible characters dropped, each line trimmed and
+//! every run of spaces and tabs in it made one space. Substrings are
+//! normalized the same way before they are looked for; regexes are not.
+//!
+//! This is a tripwire, not a security boundary. Code that builds a module
+//! name at run time (`"ct" + "ypes"`, `getattr`, `base64`, `exec`), reaches
+//! a module through another (`os.sys.modules`) or through a file it writes
+//! gets past every rule here. The sandbox is what contains code; screening
+//! only saves a worker from the code that does not bother to hide.
+
+use arc_swap::ArcSwapOption;
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::ExecuteRequest;
+use leeward_core::{LeewardError, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use unicode_normalization::UnicodeNormalization;
+
+/// Decides, before dispatch, whether an execution may run
+pub trait Screening: Send + Sync {
+    /// Verdict on `req`, after the daemon's adjustments; code passed in a
+    /// shared memory slot has been moved into `req.code`
+    fn screen(&self, req: &ExecuteRequest) -> ScreenVerdict;
+}
+
+/// What a [`Screening`] hook made of an execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenVerdict {
+    Allow,
+    /// Turn it away, telling the client why
+    Deny(String),
+    /// Run it, noting why it stood out
+    Flag(String),
+}
+
+/// Imports, with the module names they import in group `from`, `names` or
+/// `dynamic`
+static IMPORT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"\bfrom (?P<from>[\w.]+) import\b",
+        r"|\bimport (?P<names>[\w.]+(?: as \w+)?(?: ?, ?[\w.]+(?: as \w+)?)*)",
+        r#"|\b(?:__import__|import_module) ?\( ?(?:name ?= ?)?['"](?P<dynamic>[\w.]+)"#,
+    ))
+    .expect("import pattern is valid")
+});
+
+/// Rules as written in the file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFileContents {
+    #[serde(default)]
+    rule: Vec<RawRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    name: String,
+    #[serde(default)]
+    action: Action,
+    reason: Option<String>,
+    interpreters: Option<Vec<Interpreter>>,
+    #[serde(default)]
+    without_network: bool,
+    import: Option<String>,
+    substring: Option<String>,
+    regex: Option<String>,
+    max_bytes: Option<usize>,
+    max_lines: Option<usize>,
+    max_line_length: Option<usize>,
+}
+
+/// What a matching rule does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    #[default]
+    Deny,
+    Flag,
+}
+
+/// What a rule looks for
+#[derive(Debug, Clone)]
+enum Test {
+    Import(String),
+    Substring(String),
+    Regex(Regex),
+    MaxBytes(usize),
+    MaxLines(usize),
+    MaxLineLength(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    action: Action,
+    reason: Option<String>,
+    interpreters: Option<Vec<Interpreter>>,
+    without_network: bool,
+    test: Test,
+}
+
+/// A list of screening rules, as read from a rules file
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Rules from the TOML in `text`, for a sandbox with networking on if
+    /// `allow_network`, which drops the `without_network` ones
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] if `text` is not valid rules.
+    pub fn parse(text: &str, allow_network: bool) -> Result<Self> {
+        let contents: RuleFileContents = toml::from_str(text)
+            .map_err(|e| LeewardError::Config(format!("screening rules: {e}")))?;
+        let rules = contents
+            .rule
+            .into_iter()
+            .map(Rule::compile)
+            .filter(|rule| !(allow_network && rule.as_ref().is_ok_and(|rule| rule.without_network)))
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Rules from the file at `path`; see [`Self::parse`]
+    ///
+    /// # Errors
+    ///
+    /// [`LeewardError::Config`] if the file cannot be read or is not valid
+    /// rules.
+    pub fn load(path: &Path, allow_network: bool) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            LeewardError::Config(format!("screening rules {}: {e}", path.display()))
+        })?;
+        Self::parse(&text, allow_network)
+    }
+
+    /// Rules in the list
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether the list lets everything through
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Verdict on `code` in `interpreter`
+    #[must_use]
+    pub fn check(&self, code: &str, interpreter: Interpreter) -> ScreenVerdict {
+        let mut canonical = None;
+        let mut flags = Vec::new();
+        for rule in &self.rules {
+            if rule
+                .interpreters
+                .as_ref()
+                .is_some_and(|only| !only.contains(&interpreter))
+            {
+                continue;
+            }
+            let Some(found) = rule.test.find(code, interpreter, || {
+                canonical.get_or_insert_with(|| canonicalize(code)).as_str()
+            }) else {
+                continue;
+            };
+            let reason = rule.reason.clone().unwrap_or(found);
+            match rule.action {
+                Action::Deny => {
+                    return ScreenVerdict::Deny(format!(
+                        "code screening rule '{}' refused this code: {reason}",
+                        rule.name
+                    ));
+                }
+                Action::Flag => flags.push(format!("rule '{}': {reason}", rule.name)),
+            }
+        }
+        if flags.is_empty() {
+            ScreenVerdict::Allow
+        } else {
+            ScreenVerdict::Flag(flags.join("; "))
+        }
+    }
+}
+
+impl Screening for Rules {
+    fn screen(&self, req: &ExecuteRequest) -> ScreenVerdict {
+        req.code.as_deref().map_or(ScreenVerdict::Allow, |code| {
+            self.check(code, req.interpreter)
+        })
+    }
+}
+
+impl Rule {
+    fn compile(raw: RawRule) -> Result<Self> {
+        let fail = |message: &str| {
+            LeewardError::Config(format!("screening rule '{}': {message}", raw.name))
+        };
+        let mut tests = Vec::new();
+        if let Some(module) = &raw.import {
+            if module.is_empty() || !module.split('.').all(is_identifier) {
+                return Err(fail(
+                    "`import` must be a module name, such as `ctypes` or `os.path`",
+                ));
+            }
+            tests.push(Test::Import(module.clone()));
+        }
+        if let Some(substring) = &raw.substring {
+            let substring = canonicalize(substring);
+            if substring.is_empty() {
+                return Err(fail("`substring` is empty once normalized"));
+            }
+            tests.push(Test::Substring(substring));
+        }
+        if let Some(pattern) = &raw.regex {
+            tests.push(Test::Regex(
+                Regex::new(pattern).map_err(|e| fail(&e.to_string()))?,
+            ));
+        }
+        tests.extend(raw.max_bytes.map(Test::MaxBytes));
+        tests.extend(raw.max_lines.map(Test::MaxLines));
+        tests.extend(raw.max_line_length.map(Test::MaxLineLength));
+
+        let test = match tests.len() {
+            1 => tests.remove(0),
+            0 => {
+                return Err(fail(
+                    "needs one of `import`, `substring`, `regex`, `max_bytes`, `max_lines` or \
+                     `max_line_length`",
+                ));
+            }
+            _ => {
+                return Err(fail(
+                    "has more than one test; split it into one rule per test",
+                ));
+            }
+        };
+        Ok(Self {
+            name: raw.name,
+            action: raw.action,
+            reason: raw.reason,
+            interpreters: raw.interpreters,
+            without_network: raw.without_network,
+            test,
+        })
+    }
+}
+
+impl Test {
+    /// Why `code` matches, if it does, with `canonical` giving the form
+    /// that text tests look at
+    fn find<'a>(
+        &self,
+        code: &str,
+        interpreter: Interpreter,
+        canonical: impl FnOnce() -> &'a str,
+    ) -> Option<String> {
+        match self {
+            Self::Import(module) => {
+                if interpreter != Interpreter::Python {
+                    return None;
+                }
+                imports(canonical())
+                    .find(|name| {
+                        name == module
+                            || name
+                                .strip_prefix(module.as_str())
+                                .is_some_and(|rest| rest.starts_with('.'))
+                    })
+                    .map(|name| format!("imports {name}"))
+            }
+            Self::Substring(substring) => canonical()
+                .contains(substring.as_str())
+                .then(|| format!("contains {substring:?}")),
+            Self::Regex(regex) => regex
+                .find(canonical())
+                .map(|found| format!("matches /{}/ at {:?}", regex.as_str(), found.as_str())),
+            Self::MaxBytes(max) => {
+                (code.len() > *max).then(|| format!("is {} bytes, over {max}", code.len()))
+            }
+            Self::MaxLines(max) => {
+                let lines = code.lines().count();
+                (lines > *max).then(|| format!("is {lines} lines, over {max}"))
+            }
+            Self::MaxLineLength(max) => {
+                let longest = code.lines().map(str::len).max().unwrap_or(0);
+                (longest > *max).then(|| format!("has a line of {longest} bytes, over {max}"))
+            }
+        }
+    }
+}
+
+/// Modules `canonical` code imports by name
+fn imports(canonical: &str) -> impl Iterator<Item = String> + '_ {
+    IMPORT.captures_iter(canonical).flat_map(|captures| {
+        captures.name("names").map_or_else(
+            || {
+                captures
+                    .name("from")
+                    .or_else(|| captures.name("dynamic"))
+                    .map(|name| name.as_str().to_owned())
+                    .into_iter()
+                    .collect::<Vec<_>>()
+            },
+            |names| {
+                names
+                    .as_str()
+                    .split(',')
+                    .filter_map(|name| name.split_whitespace().next())
+                    .map(str::to_owned)
+                    .collect()
+            },
+        )
+    })
+}
+
+fn is_identifier(part: &str) -> bool {
+    let mut chars = part.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// `code` as text tests see it; see the module docs
+#[must_use]
+pub fn canonicalize(code: &str) -> String {
+    let decoded = decode_escapes(&code.replace("\\\r\n", " ").replace("\\\n", " "));
+    let mut out = String::with_capacity(decoded.len());
+    let mut space = false;
+    for c in decoded.nfkc() {
+        match c {
+            '\n' => {
+                out.push('\n');
+                space = false;
+            }
+            // Zero-width spaces and joiners, word joiner, byte order mark,
+            // soft hyphen
+            '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}' | '\u{ad}' => {}
+            c if c.is_whitespace() => space = true,
+            c => {
+                if space && !out.is_empty() && !out.ends_with('\n') {
+                    out.push(' ');
+                }
+                space = false;
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+/// `code` with `\xNN`, `\uNNNN`, `\UNNNNNNNN` and octal escapes replaced by
+/// the characters they stand for, wherever they are
+fn decode_escapes(code: &str) -> String {
+    if !code.contains('\\') {
+        return code.to_owned();
+    }
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        let escape = &rest[at + 1..];
+        let (digits, radix, max) = match escape.chars().next() {
+            Some('x') => (&escape[1..], 16, 2),
+            Some('u') => (&escape[1..], 16, 4),
+            Some('U') => (&escape[1..], 16, 8),
+            Some('0'..='7') => (escape, 8, 3),
+            _ => {
+                out.push('\\');
+                rest = escape;
+                continue;
+            }
+        };
+        let len = digits
+            .char_indices()
+            .take(max)
+            .take_while(|(_, c)| c.is_digit(radix))
+            .count();
+        let exact = radix == 8 || len == max;
+        if let Some(c) = u32::from_str_radix(&digits[..len], radix)
+            .ok()
+            .filter(|_| exact && len > 0)
+            .and_then(char::from_u32)
+        {
+            out.push(c);
+            rest = &digits[len..];
+        } else {
+            out.push('\\');
+            rest = escape;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rules read from a file, swapped out whole when it is read again
+#[derive(Debug)]
+pub struct RuleFile {
+    path: PathBuf,
+    /// `None` until the file has been read once
+    rules: ArcSwapOption<Rules>,
+}
+
+impl RuleFile {
+    /// Read the rules at `path`
+    ///
+    /// A file that cannot be read or parsed is logged and leaves every
+    /// execution refused until it is fixed and read again: the daemon
+    /// fails closed rather than run unscreened.
+    #[must_use]
+    pub fn open(path: &Path, allow_network: bool) -> Self {
+        let file = Self {
+            path: path.to_owned(),
+            rules: ArcSwapOption::empty(),
+        };
+        file.reload(allow_network);
+        file
+    }
+
+    /// Read the rules again, keeping the ones in use if the file cannot be
+    /// read or parsed; returns whether the new ones were taken
+    pub fn reload(&self, allow_network: bool) -> bool {
+        match Rules::load(&self.path, allow_network) {
+            Ok(rules) => {
+                tracing::info!(path = ?self.path, rules = rules.len(), "screening rules loaded");
+                self.rules.store(Some(Arc::new(rules)));
+                true
+            }
+            Err(e) => {
+                tracing::error!(
+                    path = ?self.path,
+                    error = %e,
+                    "keeping the current screening rules"
+                );
+                false
+            }
+        }
+    }
+
+    /// The rules in use, `None` if the file has never been read
+    #[must_use]
+    pub fn rules(&self) -> Option<Arc<Rules>> {
+        self.rules.load_full()
+    }
+}
+
+impl Screening for RuleFile {
+    fn screen(&self, req: &ExecuteRequest) -> ScreenVerdict {
+        self.rules.load().as_ref().map_or_else(
+            || {
+                ScreenVerdict::Deny(format!(
+                    "the screening rules in {} could not be loaded; no code runs until they are \
+                     fixed",
+                    self.path.display()
+                ))
+            },
+            |rules| rules.screen(req),
+        )
+    }
+}
@@ -1,111 +1,1881 @@
 //! Unix socket server
+//!
+//! Idle connections are cheap: nothing is buffered between requests, and
+//! with `idle_connection_timeout` set, a connection with no request in
+//! flight and no subscription is closed once it has been quiet that long.
+//!
+//! Every request is answered: one still unhandled after
+//! `max_request_wall` gets an [`ErrorKind::Internal`] naming the
+//! stage it was stuck in, and the worker it was stuck on is reclaimed.
+//! One whose handler panics gets the same error, with the panic message,
+//! and the connection carries on.
+//!
+//! While the daemon is in maintenance, after one of its tasks kept
+//! panicking, executions are refused with [`ErrorKind::Maintenance`]; see
+//! [`crate::supervisor`].
+//!
+//! Executions beyond `max_inflight_per_connection` or
+//! `max_inflight_per_peer_uid` are turned away with [`ErrorKind::Busy`]
+//! before they reach the pool; other requests are never limited.
+//!
+//! Parts of an execution request the daemon cannot honour as asked, such
+//! as a timeout above `max_timeout`, are adjusted rather than refused,
+//! and each adjustment is listed in the response. So is each option filled
+//! in from the defaults a connection stored with [`Request::SetDefaults`],
+//! which are checked when set and cleared by the next `Hello`.
+//! [`Request::ExplainPolicy`] runs a request through the same steps to
+//! report where each setting it would run with came from.
+//!
+//! Input files too large to send inline are uploaded first, in chunks, and
+//! referenced from the execution by upload id; see [`crate::uploads`].
+//!
+//! Code can instead be written to a shared memory slot leased with
+//! [`Request::ShmSetup`]; the slot belongs to the connection and is taken
+//! back when it closes, see [`crate::shm`].
+//!
+//! A detached execution is answered with its id as soon as it is admitted
+//! and keeps its in-flight slot and deadline while it runs; its answer
+//! waits in the result spool until fetched, see [`crate::spool`].
+//!
+//! Executions pass the daemon's code screening, if any, after they are
+//! adjusted and before they reach the pool; refused ones are answered with
+//! [`ErrorKind::PolicyRejected`]. See [`crate::screening`].
+//!
+//! After [`Request::Handover`], the listener goes to the new daemon and
+//! this one stops accepting, closes connections as they fall quiet and
+//! returns once the last one is gone; see [`crate::handover`].
 
-use crate::{config::DaemonConfig, pool::WorkerPool};
-use leeward_core::protocol::{self, Request, Response};
+use crate::arena::RequestArena;
+use crate::audit::Audit;
+use crate::config::{DaemonConfig, PriorityScheduling};
+use crate::handover::{HANDOVER_LINGER, HANDOVER_PROGRESS_INTERVAL, Handover};
+use crate::hello::Identity;
+use crate::inflight::{Admitted, ConnectionInflight, InflightLimits};
+use crate::journal::Journal;
+use crate::logging::LogControl;
+use crate::metrics::{Dispatch, Metrics};
+use crate::pool::WorkerPool;
+use crate::screening::{ScreenVerdict, Screening};
+use crate::shm::ShmSlots;
+use crate::spool::Spool;
+use crate::supervisor::{self, Supervisor};
+use crate::uploads::Uploads;
+use leeward_core::config::Interpreter;
+use leeward_core::policy::{PolicyActor, PolicyTrace, Provenance};
+use leeward_core::protocol::{
+    self, Adjustment, DrainStatus, ErrorKind, Event, EventKind, ExecuteDefaults, HandoverState,
+    Request, RequestPriority, RequestStage, Response, ShmOverflowPolicy, SizeLimits,
+};
+use leeward_core::worker::{ExecuteOptions, WorkerState};
+use leeward_core::{LeewardError, OutcomeCode};
+use parking_lot::Mutex;
+use std::future::Future;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{UnixListener, UnixStream},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Interest},
+    net::{UnixListener, UnixStream, unix::OwnedReadHalf},
+    sync::broadcast::{self, error::RecvError},
 };
 
-/// Run the daemon server
+/// Pause between recycling stale workers, so reloads don't stall the pool
+const STALE_RECYCLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Events buffered per subscriber before a slow one starts missing them
+pub const EVENT_BUFFER: usize = 256;
+
+/// Bytes reserved per read of a JSON connection
+const READ_CHUNK: usize = 8 * 1024;
+
+/// How long the goodbye to an idle client may take before it is dropped
+const IDLE_NOTICE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often expired uploads and detached results are dropped when no new
+/// upload or detached execution does it first
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pause before accepting again after a connection failed on its way in
+const ACCEPT_RETRY: Duration = Duration::from_millis(10);
+
+/// Pause before accepting again while out of descriptors or memory, long
+/// enough for some connections to close
+const ACCEPT_EXHAUSTED_RETRY: Duration = Duration::from_millis(200);
+
+/// Publishes daemon events to subscribed connections
+pub type EventBus = broadcast::Sender<Event>;
+
+/// Daemon state the server shares with the rest of the daemon
+pub struct Shared {
+    pub pool: Arc<WorkerPool>,
+    pub events: EventBus,
+    pub metrics: Arc<Metrics>,
+    /// Input files staged ahead of executions
+    pub uploads: Arc<Uploads>,
+    /// Results of detached executions
+    pub spool: Arc<Spool>,
+    /// Shared memory slots leased to connections
+    pub shm: Arc<ShmSlots>,
+    pub handover: Arc<Handover>,
+    pub log: LogControl,
+    /// Hook executions are screened with before dispatch
+    pub screening: Option<Arc<dyn Screening>>,
+    pub audit: Arc<Audit>,
+    /// Restarts the sweeps if they panic
+    pub supervisor: Supervisor,
+}
+
+/// What every connection handler shares
+struct Context {
+    pool: Arc<WorkerPool>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    /// Close connections idle for this long (`None` = never)
+    idle_timeout: Option<Duration>,
+    scheduling: PriorityScheduling,
+    /// Answer requests still unhandled after this long (`None` = never)
+    request_deadline: Option<Duration>,
+    /// Cut longer request timeouts down to this
+    max_timeout: Option<Duration>,
+    /// What requests and their output are held to, as advertised
+    sizes: SizeLimits,
+    /// Idle workers a health probe needs, unless it says
+    health_min_idle: usize,
+    /// Source of request ids for the journal
+    next_request_id: AtomicU64,
+    /// Answers `Hello`
+    identity: Identity,
+    /// Executions in flight per connection and client
+    inflight: Arc<InflightLimits>,
+    /// Input files staged ahead of executions
+    uploads: Arc<Uploads>,
+    /// Results of detached executions
+    spool: Arc<Spool>,
+    /// Shared memory slots leased to connections
+    shm: Arc<ShmSlots>,
+    /// What to do with output too big for a slot, unless the request says
+    shm_overflow: ShmOverflowPolicy,
+    /// Detached executions still running
+    detached: AtomicUsize,
+    handover: Arc<Handover>,
+    /// The socket being served, for handing over
+    listener: OwnedFd,
+    log: LogControl,
+    /// Hook executions are screened with before dispatch
+    screening: Option<Arc<dyn Screening>>,
+    audit: Arc<Audit>,
+    /// Buffers for reading requests and writing responses
+    arena: RequestArena,
+}
+
+impl Context {
+    /// How long `request` may take before it is answered with an error
+    ///
+    /// Requests with a longer timeout than the sandbox's get the difference,
+    /// and batch requests the time they may spend frozen.
+    fn deadline(&self, request: &Request) -> Option<Duration> {
+        let deadline = self.request_deadline?;
+        let extra = match request {
+            Request::Execute(req) => {
+                let sandbox_timeout = self.pool.timeout();
+                let timeout = req
+                    .timeout
+                    .map_or(sandbox_timeout, |timeout| self.capped(timeout));
+                let frozen = match self.pool.time_slicing() {
+                    Some(slicing) if req.priority == RequestPriority::Batch => {
+                        slicing.frozen_budget(timeout)
+                    }
+                    _ => Duration::ZERO,
+                };
+                timeout
+                    .saturating_sub(sandbox_timeout)
+                    .saturating_add(frozen)
+            }
+            _ => Duration::ZERO,
+        };
+        Some(deadline.saturating_add(extra))
+    }
+
+    /// Largest request read, in bytes
+    fn max_request(&self) -> usize {
+        usize::try_from(self.sizes.max_request.bytes()).unwrap_or(usize::MAX)
+    }
+
+    /// Most output an execution returns, in bytes
+    fn max_output(&self) -> usize {
+        usize::try_from(self.sizes.max_output.bytes()).unwrap_or(usize::MAX)
+    }
+
+    /// `timeout`, cut down to `max_timeout`
+    fn capped(&self, timeout: Duration) -> Duration {
+        self.max_timeout.map_or(timeout, |max| timeout.min(max))
+    }
+}
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone, Copy)]
+struct Peer {
+    /// Runs as the daemon's own user or root, so may make requests that
+    /// run code without Landlock or a syscall filter
+    trusted: bool,
+    /// Effective uid, if the kernel told us
+    uid: Option<u32>,
+    /// Id of the connection, which owns the shared memory slots it leased
+    connection: u64,
+}
+
+impl Peer {
+    fn of(stream: &UnixStream, connection: u64) -> Self {
+        // SAFETY: geteuid has no failure modes
+        let own = unsafe { libc::geteuid() };
+        let uid = stream.peer_cred().ok().map(|cred| cred.uid());
+        let trusted = uid.is_some_and(|uid| uid == 0 || uid == own);
+        Self {
+            trusted,
+            uid,
+            connection,
+        }
+    }
+}
+
+/// A connection's peer, the executions it has in flight and the defaults
+/// it set for them
+struct Client {
+    peer: Peer,
+    inflight: Arc<ConnectionInflight>,
+    defaults: Mutex<ExecuteDefaults>,
+}
+
+/// How long to pause before accepting again after `error`, or `None` if
+/// the listener cannot recover from it
+fn accept_retry(error: &std::io::Error) -> Option<Duration> {
+    match error.raw_os_error()? {
+        libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM => Some(ACCEPT_EXHAUSTED_RETRY),
+        // The connection failed, not the listener
+        libc::ECONNABORTED | libc::EPROTO | libc::EPERM | libc::EINTR => Some(ACCEPT_RETRY),
+        _ => None,
+    }
+}
+
+/// Run the daemon server, until accepting fails for good or the socket has
+/// been handed over and every connection closed
 pub async fn run(
     listener: UnixListener,
-    pool: WorkerPool,
-    _config: DaemonConfig,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let pool = Arc::new(pool);
+    shared: Shared,
+    config: DaemonConfig,
+) -> leeward_core::Result<()> {
+    let Shared {
+        pool,
+        events,
+        metrics,
+        uploads,
+        spool,
+        shm,
+        handover,
+        log,
+        screening,
+        audit,
+        supervisor,
+    } = shared;
+    let idle_timeout = config.idle_connection_timeout.non_zero();
+    let request_deadline = config.max_request_wall.non_zero();
+    let boot_id = pool.boot_id().to_owned();
+    let context = Arc::new(Context {
+        pool,
+        events,
+        metrics: Arc::clone(&metrics),
+        idle_timeout,
+        scheduling: config.priority_scheduling,
+        request_deadline,
+        max_timeout: config.max_timeout(),
+        sizes: config.sizes.within_protocol(),
+        health_min_idle: config.health_min_idle,
+        // Ids of detached results already spooled stay taken
+        next_request_id: AtomicU64::new(spool.first_free_id()),
+        identity: Identity::new(&config, boot_id, spool.enabled(), shm.enabled()),
+        inflight: Arc::new(InflightLimits::new(
+            config.max_inflight_per_connection,
+            config.max_inflight_per_peer_uid,
+            Arc::clone(&metrics),
+        )),
+        uploads,
+        spool,
+        shm,
+        shm_overflow: config.shm_overflow,
+        detached: AtomicUsize::new(0),
+        handover,
+        listener: listener.as_fd().try_clone_to_owned()?,
+        log,
+        screening,
+        audit,
+        arena: RequestArena::default(),
+    });
+
+    let uploads = Arc::clone(&context.uploads);
+    let spool = Arc::clone(&context.spool);
+    supervisor.spawn("sweep", move || {
+        sweep(Arc::clone(&uploads), Arc::clone(&spool))
+    });
+
+    // Often enough that no slot outlives its TTL by much
+    if let Some(ttl) = context.shm.sweep_interval() {
+        let shm = Arc::clone(&context.shm);
+        supervisor.spawn("shm_sweep", move || {
+            let shm = Arc::clone(&shm);
+            async move {
+                let mut interval = tokio::time::interval(ttl.min(SWEEP_INTERVAL));
+                loop {
+                    interval.tick().await;
+                    shm.sweep();
+                }
+            }
+        });
+    }
 
     loop {
-        let (stream, _) = listener.accept().await?;
-        let pool = Arc::clone(&pool);
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    let Some(pause) = accept_retry(&e) else {
+                        return Err(e.into());
+                    };
+                    tracing::warn!(error = %e, ?pause, "accept failed; retrying");
+                    context.metrics.accept_error();
+                    tokio::time::sleep(pause).await;
+                    continue;
+                }
+            },
+            () = context.handover.wait() => break,
+        };
+        let context = Arc::clone(&context);
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, pool).await {
+            let open = context.metrics.connection_opened();
+            if let Err(e) = handle_connection(stream, open.id(), &context).await {
                 tracing::error!(error = %e, "connection error");
             }
+            // However the connection ended, its slots are no one's now
+            context.shm.release(open.id());
         });
     }
+
+    // The successor accepts from here on
+    drop(listener);
+    // Detached executions finish here, their results picked up by the
+    // successor from the spool
+    while context.metrics.open_connections() > 0 || context.detached.load(Ordering::Relaxed) > 0 {
+        tokio::time::sleep(HANDOVER_PROGRESS_INTERVAL).await;
+    }
+    tracing::info!("last connection closed after the handover");
+    Ok(())
+}
+
+/// Drop expired uploads and detached results every [`SWEEP_INTERVAL`]
+async fn sweep(uploads: Arc<Uploads>, spool: Arc<Spool>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        uploads.sweep();
+        let spool = Arc::clone(&spool);
+        supervisor::blocking(move || spool.sweep()).await;
+    }
+}
+
+/// Mark every worker of `profile` for recycling, and recycle the idle ones
+/// in the background
+async fn drain(profile: String, reason: Option<String>, peer: Peer, context: &Context) -> Response {
+    let pool = &context.pool;
+    if profile != protocol::DEFAULT_PROFILE {
+        return Response::error(format!(
+            "unknown profile '{profile}'; this daemon has only '{}'",
+            protocol::DEFAULT_PROFILE
+        ));
+    }
+
+    // Rebuilding the template forks and mounts
+    let draining = Arc::clone(pool);
+    let why = reason.clone();
+    let template_rebuilt = match tokio::task::spawn_blocking(move || draining.drain(why.as_deref()))
+        .await
+    {
+        Ok(Ok(rebuilt)) => rebuilt,
+        Ok(Err(e)) => return Response::error(format!("failed to rebuild the root template: {e}")),
+        Err(e) => return Response::error(format!("drain task failed: {e}")),
+    };
+    let scheduled = pool.draining();
+    // The config stays, but workers come back with what is on disk now
+    let config = pool.config();
+    let actor = PolicyActor::Request {
+        request: "drain_profile".into(),
+        peer_uid: peer.uid,
+    };
+    context
+        .audit
+        .policy_change(actor, Some(&config), &config, reason);
+
+    // One worker at a time, so most of the pool stays available; busy
+    // workers are recycled as they finish
+    let draining = Arc::clone(pool);
+    tokio::spawn(async move {
+        while draining.draining() > 0 {
+            draining.drain_idle(1);
+            tokio::time::sleep(STALE_RECYCLE_INTERVAL).await;
+        }
+    });
+
+    Response::Drain(DrainStatus {
+        profile,
+        scheduled,
+        pending: pool.draining(),
+        template_rebuilt,
+    })
+}
+
+/// Encoding a connection speaks, fixed by its first byte
+#[derive(Debug, Clone, Copy)]
+enum Wire {
+    Msgpack,
+    Json,
+}
+
+impl Wire {
+    /// Encode a response as one complete frame or line
+    fn frame(self, response: &Response) -> leeward_core::Result<Vec<u8>> {
+        let mut frame = Vec::new();
+        self.frame_into(response, &mut frame)?;
+        Ok(frame)
+    }
+
+    /// Encode a response as one complete frame or line into `out`,
+    /// replacing what it held
+    fn frame_into(self, response: &Response, out: &mut Vec<u8>) -> leeward_core::Result<()> {
+        out.clear();
+        match self {
+            Self::Msgpack => {
+                out.extend_from_slice(&[0; 4]);
+                protocol::encode_into(response, out)?;
+                let len = u32::try_from(out.len() - 4)
+                    .map_err(|_| LeewardError::Execution("response too large to frame".into()))?;
+                out[..4].copy_from_slice(&len.to_be_bytes());
+            }
+            Self::Json => {
+                protocol::encode_json_into(response, out)?;
+                out.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a connection with nothing in flight is being closed
+#[derive(Debug, Clone, Copy)]
+enum Quiet {
+    /// Idle for `idle_timeout`
+    Idle,
+    /// Quiet for [`HANDOVER_LINGER`] after the socket was handed over
+    HandedOver,
+}
+
+/// Wait for the start of the next request, unless the connection stays
+/// quiet too long
+async fn idle_wait<T>(context: &Context, read: impl Future<Output = T>) -> Result<T, Quiet> {
+    let idle = async {
+        match context.idle_timeout {
+            Some(limit) => tokio::time::sleep(limit).await,
+            None => std::future::pending().await,
+        }
+    };
+    let handed_over = async {
+        context.handover.wait().await;
+        tokio::time::sleep(HANDOVER_LINGER).await;
+    };
+
+    tokio::select! {
+        read = read => Ok(read),
+        () = idle => Err(Quiet::Idle),
+        () = handed_over => Err(Quiet::HandedOver),
+    }
+}
+
+/// Tell a quiet client why it is being disconnected, without waiting on it
+async fn close_quiet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    wire: Wire,
+    quiet: Quiet,
+    metrics: &Metrics,
+) -> leeward_core::Result<()> {
+    let response = match quiet {
+        Quiet::Idle => {
+            metrics.connection_idle_closed();
+            tracing::debug!("closing idle connection");
+            Response::Error {
+                message: "connection closed after being idle".into(),
+                kind: ErrorKind::IdleTimeout,
+            }
+        }
+        Quiet::HandedOver => {
+            tracing::debug!("closing connection after the handover");
+            Response::Error {
+                message: "the daemon handed its socket to a new process; reconnect".into(),
+                kind: ErrorKind::HandedOver,
+            }
+        }
+    };
+    let _ = tokio::time::timeout(
+        IDLE_NOTICE_TIMEOUT,
+        writer.write_all(&wire.frame(&response)?),
+    )
+    .await;
+    Ok(())
+}
+
+/// Give the listening socket and uploads to the daemon process asking, then
+/// report this daemon's other connections to it until they have all closed
+async fn hand_over(
+    stream: &mut UnixStream,
+    peer: Peer,
+    context: &Context,
+) -> leeward_core::Result<()> {
+    let refusal = if peer.trusted {
+        let successor = stream
+            .peer_cred()
+            .ok()
+            .and_then(|cred| cred.pid())
+            .and_then(|pid| u32::try_from(pid).ok());
+        (!context.handover.begin(successor))
+            .then_some("this daemon has already handed over its socket")
+    } else {
+        Some("only the daemon's own user may take over its socket")
+    };
+    if let Some(refusal) = refusal {
+        stream
+            .write_all(&Wire::Msgpack.frame(&Response::error(refusal))?)
+            .await?;
+        return Ok(());
+    }
+
+    let (uploads, files) = context.uploads.export(leeward_core::socket::MAX_FDS - 1);
+    let handed = uploads.len();
+    let state = HandoverState {
+        pid: std::process::id(),
+        uploads,
+    };
+    let frame = Wire::Msgpack.frame(&Response::Handover(state))?;
+    let fds: Vec<_> = std::iter::once(context.listener.as_fd())
+        .chain(files.iter().map(|file| file.as_fd()))
+        .collect();
+
+    let sent = match send_fds(stream, &frame, &fds).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            context.handover.abort();
+            return Err(e.into());
+        }
+    };
+    drop(fds);
+    context.handover.finish();
+    tracing::info!(
+        successor = ?context.handover.status().and_then(|status| status.peer_pid),
+        uploads = handed,
+        "handed the socket over"
+    );
+    stream.write_all(&frame[sent..]).await?;
+
+    let mut reported = None;
+    loop {
+        // Not counting this one
+        let connections = context.metrics.open_connections().saturating_sub(1);
+        context.handover.set_predecessor_connections(connections);
+        if reported != Some(connections) {
+            let progress = Response::HandoverProgress { connections };
+            stream.write_all(&Wire::Msgpack.frame(&progress)?).await?;
+            reported = Some(connections);
+        }
+        if connections == 0 {
+            return Ok(());
+        }
+        tokio::time::sleep(HANDOVER_PROGRESS_INTERVAL).await;
+    }
+}
+
+/// Lease a shared memory slot to the connection, sending the region's
+/// memfd with the answer
+async fn shm_setup(
+    stream: &mut UnixStream,
+    peer: Peer,
+    context: &Context,
+) -> leeward_core::Result<()> {
+    let (lease, fd) = match context.shm.lease(peer.connection) {
+        Ok(leased) => leased,
+        Err(message) => {
+            stream
+                .write_all(&Wire::Msgpack.frame(&Response::error(message))?)
+                .await?;
+            return Ok(());
+        }
+    };
+    tracing::debug!(
+        connection = peer.connection,
+        slot_id = lease.slot_id,
+        "leased a shared memory slot"
+    );
+    let frame = Wire::Msgpack.frame(&Response::ShmSlot(lease))?;
+    let sent = send_fds(stream, &frame, &[fd]).await?;
+    stream.write_all(&frame[sent..]).await?;
+    Ok(())
+}
+
+/// Send the start of `frame` with `fds` attached, returning how many bytes
+/// went; the descriptors ride on those, and the rest is a plain write
+async fn send_fds(stream: &UnixStream, frame: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+    loop {
+        stream.writable().await?;
+        match stream.try_io(Interest::WRITABLE, || {
+            leeward_core::socket::send_with_fds(stream.as_fd(), frame, fds)
+        }) {
+            Ok(sent) => return Ok(sent),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Push events of the given kinds (all if empty) until the client leaves
+/// or the socket is handed over
+///
+/// A subscribed connection only listens: any input from the client, or EOF,
+/// ends the subscription.
+async fn stream_events<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    wire: Wire,
+    kinds: &[EventKind],
+    context: &Context,
+) -> leeward_core::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Subscribe before acknowledging, so nothing after the ack is missed
+    let mut rx = context.events.subscribe();
+    writer
+        .write_all(&wire.frame(&Response::Subscribed)?)
+        .await?;
+
+    let mut input = [0u8; 1];
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(event) if kinds.is_empty() || kinds.contains(&event.kind) => {
+                    writer.write_all(&wire.frame(&Response::Event(event))?).await?;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "event subscriber fell behind");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = reader.read(&mut input) => break,
+            // Subscribers reconnect to the new daemon
+            () = context.handover.wait() => {
+                return close_quiet(writer, wire, Quiet::HandedOver, &context.metrics).await;
+            }
+        }
+    }
+
+    Ok(())
 }
 
 /// Handle a single client connection
+///
+/// The first byte picks the encoding: `{` starts a line of JSON, anything
+/// else is the first byte of a msgpack frame's length prefix.
 async fn handle_connection(
     mut stream: UnixStream,
-    pool: Arc<WorkerPool>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut buf = vec![0u8; 64 * 1024]; // 64KB buffer
+    connection: u64,
+    context: &Arc<Context>,
+) -> leeward_core::Result<()> {
+    let peer = Peer::of(&stream, connection);
+    let mut first = [0u8; 1];
+    let read = match idle_wait(context, stream.read_exact(&mut first)).await {
+        Ok(read) => read,
+        // Nothing said yet, so answer in the default encoding
+        Err(quiet) => {
+            return close_quiet(&mut stream, Wire::Msgpack, quiet, &context.metrics).await;
+        }
+    };
+    if read.is_err() {
+        return Ok(()); // Client disconnected
+    }
+
+    let client = Client {
+        peer,
+        inflight: Arc::new(ConnectionInflight::default()),
+        defaults: Mutex::new(ExecuteDefaults::default()),
+    };
+    if first[0] == b'{' {
+        handle_json_connection(stream, first[0], &client, context).await
+    } else {
+        handle_msgpack_connection(stream, first[0], &client, context).await
+    }
+}
+
+/// Serve length-prefixed msgpack frames
+///
+/// Each message is read into a buffer borrowed from the arena, and its
+/// answer encoded into another, both handed back before the next one.
+async fn handle_msgpack_connection(
+    mut stream: UnixStream,
+    first: u8,
+    client: &Client,
+    context: &Arc<Context>,
+) -> leeward_core::Result<()> {
+    let mut first = Some(first);
 
     loop {
         // Read length prefix (4 bytes, big-endian)
         let mut len_buf = [0u8; 4];
-        if stream.read_exact(&mut len_buf).await.is_err() {
+        let rest = match first.take() {
+            Some(byte) => {
+                len_buf[0] = byte;
+                &mut len_buf[1..]
+            }
+            None => &mut len_buf[..],
+        };
+        let read = match idle_wait(context, stream.read_exact(rest)).await {
+            Ok(read) => read,
+            Err(quiet) => {
+                return close_quiet(&mut stream, Wire::Msgpack, quiet, &context.metrics).await;
+            }
+        };
+        if read.is_err() {
             break; // Client disconnected
         }
         let len = u32::from_be_bytes(len_buf) as usize;
 
-        if len > buf.len() {
-            buf.resize(len, 0);
+        // The frame can't be skipped without reading it, so the connection ends here
+        if let Err(e) = context.sizes.check_request_len(len) {
+            let response = malformed(e.to_string(), context);
+            stream.write_all(&Wire::Msgpack.frame(&response)?).await?;
+            return Ok(());
         }
 
-        // Read message
-        stream.read_exact(&mut buf[..len]).await?;
-
-        // Decode request
-        let request: Request = protocol::decode(&buf[..len])?;
+        // Read and decode the message; the next frame follows regardless
+        let mut buf = context.arena.take();
+        buf.resize(len, 0);
+        stream.read_exact(&mut buf).await?;
+        let decoded = protocol::decode::<Request>(&buf);
+        drop(buf);
+        let request = match decoded {
+            Ok(request) => request,
+            Err(e) => {
+                let response = malformed(format!("invalid request: {e}"), context);
+                stream.write_all(&Wire::Msgpack.frame(&response)?).await?;
+                continue;
+            }
+        };
         tracing::debug!(?request, "received request");
 
-        // Handle request
-        let response = handle_request(request, &pool).await;
+        match request {
+            Request::Subscribe { kinds } => {
+                let (mut reader, mut writer) = stream.split();
+                return stream_events(&mut reader, &mut writer, Wire::Msgpack, &kinds, context)
+                    .await;
+            }
+            Request::Handover => return hand_over(&mut stream, client.peer, context).await,
+            Request::ShmSetup => {
+                shm_setup(&mut stream, client.peer, context).await?;
+                continue;
+            }
+            _ => {}
+        }
 
-        // Encode response
-        let response_bytes = protocol::encode(&response)?;
+        // Handle request
+        let response = answer(request, client, context).await;
 
         // Write length prefix + response
-        let len_bytes = (response_bytes.len() as u32).to_be_bytes();
-        stream.write_all(&len_bytes).await?;
-        stream.write_all(&response_bytes).await?;
+        let mut frame = context.arena.take();
+        Wire::Msgpack.frame_into(&response, &mut frame)?;
+        drop(response);
+        stream.write_all(&frame).await?;
     }
 
     Ok(())
 }
 
-/// Handle a single request
-async fn handle_request(request: Request, pool: &WorkerPool) -> Response {
-    match request {
-        Request::Execute(req) => {
-            // TODO: Handle shared memory mode (shm_slot_id)
-            let code = match req.code {
-                Some(ref code) => code,
-                None => {
-                    return Response::Execute(protocol::ExecuteResponse {
-                        success: false,
-                        result: None,
-                        error: Some("no code provided (shared memory not yet implemented)".into()),
-                    });
+/// Answer to a request that could not be decoded, counted in the metrics
+fn malformed(message: String, context: &Context) -> Response {
+    context.metrics.protocol_error();
+    tracing::debug!(%message, "malformed request");
+    Response::Error {
+        message,
+        kind: ErrorKind::Malformed,
+    }
+}
+
+/// Newline-delimited input that holds no buffer while the client is quiet
+///
+/// A `BufReader` would keep its buffer for the life of the connection. Here
+/// bytes are only held from the read that brought them in until their line
+/// is complete, plus anything the client pipelined after it.
+struct LineReader {
+    half: OwnedReadHalf,
+    /// Bytes read past the end of the last line
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+impl LineReader {
+    const fn new(half: OwnedReadHalf) -> Self {
+        Self {
+            half,
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Wait until there is input (or EOF) to read
+    async fn ready(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() && !self.eof {
+            self.fill().await?;
+        }
+        Ok(())
+    }
+
+    /// Read whatever has arrived into `pending`, waiting for something
+    async fn fill(&mut self) -> io::Result<()> {
+        loop {
+            self.half.readable().await?;
+            self.pending.reserve(READ_CHUNK);
+            match self.half.try_read_buf(&mut self.pending) {
+                Ok(0) => self.eof = true,
+                Ok(_) => {}
+                // Readiness was stale; don't hold the buffer while waiting again
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.pending.is_empty() {
+                        self.pending = Vec::new();
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            return Ok(());
+        }
+    }
+
+    /// Append the next line, newline included, to `line`, taking at most
+    /// `limit` bytes; returns how many were appended, 0 at EOF
+    async fn read_line(&mut self, line: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+        let start = line.len();
+
+        loop {
+            let room = limit - (line.len() - start);
+            let newline = self.pending.iter().position(|&b| b == b'\n').map(|i| i + 1);
+            let take = newline.unwrap_or(self.pending.len()).min(room);
+            line.extend(self.pending.drain(..take));
+            if newline.is_some_and(|end| end <= room) || line.len() - start == limit || self.eof {
+                break;
+            }
+            self.fill().await?;
+        }
+
+        if self.pending.is_empty() {
+            self.pending = Vec::new();
+        }
+        Ok(line.len() - start)
+    }
+}
+
+/// Serve newline-delimited JSON, one request per line
+///
+/// Malformed lines get a `Response::Error` instead of closing the
+/// connection, since these clients are usually typed by hand.
+async fn handle_json_connection(
+    stream: UnixStream,
+    first: u8,
+    client: &Client,
+    context: &Arc<Context>,
+) -> leeward_core::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = LineReader::new(reader);
+    let mut line = vec![first];
+
+    loop {
+        if line.is_empty() {
+            if let Err(quiet) = idle_wait(context, reader.ready()).await {
+                return close_quiet(&mut writer, Wire::Json, quiet, &context.metrics).await;
+            }
+        }
+
+        // One byte past the limit tells an oversized line from a full one
+        let max = context.max_request();
+        let limit = max + 1 - line.len();
+        let read = reader.read_line(&mut line, limit).await?;
+        if read == 0 && line.is_empty() {
+            break; // Client disconnected
+        }
+        if read > 0 && line.trim_ascii().is_empty() {
+            line.clear();
+            continue;
+        }
+
+        let oversized = line.last() != Some(&b'\n') && line.len() > max;
+        let response = if oversized {
+            let message = format!("request exceeds the {max} byte message size limit");
+            malformed(message, context)
+        } else {
+            match protocol::decode_json::<Request>(line.trim_ascii()) {
+                Ok(Request::Subscribe { kinds }) => {
+                    return stream_events(
+                        &mut reader.half,
+                        &mut writer,
+                        Wire::Json,
+                        &kinds,
+                        context,
+                    )
+                    .await;
+                }
+                Ok(request) => {
+                    tracing::debug!(?request, "received JSON request");
+                    answer(request, client, context).await
                 }
+                Err(e) => malformed(format!("invalid JSON request: {e}"), context),
+            }
+        };
+
+        let mut frame = context.arena.take();
+        Wire::Json.frame_into(&response, &mut frame)?;
+        drop(response);
+        writer.write_all(&frame).await?;
+        drop(frame);
+
+        // The rest of an oversized line can't be resynchronized
+        if oversized || read == 0 {
+            break;
+        }
+        line = Vec::new();
+    }
+
+    Ok(())
+}
+
+/// How much room the pool has, from its snapshot
+fn health(min_idle: Option<usize>, context: &Context) -> Response {
+    let min_idle = min_idle.unwrap_or(context.health_min_idle);
+    context.pool.snapshot().health(min_idle).response()
+}
+
+/// Handle a request, answering with an error if it overruns its deadline
+///
+/// The request runs on a task of its own, since executions block the
+/// thread they run on and would hold up the deadline with it. An execution
+/// stays counted against the client's in-flight limits until that task
+/// ends, even if the client was answered before.
+async fn answer(request: Request, client: &Client, context: &Arc<Context>) -> Response {
+    let (request, prefilled) = match request {
+        // Touches nothing, so it answers however busy or stuck the pool is
+        Request::Ping => return Response::Pong,
+        // Reads the pool's snapshot, so it answers however stuck the pool is
+        Request::Health { min_idle } => return health(min_idle, context),
+        Request::SetDefaults { defaults } => return set_defaults(defaults, client, context),
+        Request::ExplainPolicy { request } => return explain(request, client, context),
+        Request::Execute(mut req) => {
+            let prefilled = prefill(&mut req, &client.defaults.lock());
+            (Request::Execute(req), prefilled)
+        }
+        Request::Hello => {
+            *client.defaults.lock() = ExecuteDefaults::default();
+            (Request::Hello, Vec::new())
+        }
+        request => (request, Vec::new()),
+    };
+
+    if matches!(request, Request::Execute(_)) {
+        if let Some(reason) = context.pool.maintenance() {
+            return Response::Error {
+                message: format!("daemon in maintenance until restarted: {reason}"),
+                kind: ErrorKind::Maintenance,
             };
+        }
+    }
+    let admitted = match &request {
+        Request::Execute(_) => match context.inflight.admit(&client.inflight, client.peer.uid) {
+            Ok(admitted) => Some(admitted),
+            Err(rejected) => {
+                tracing::debug!(
+                    uid = client.peer.uid,
+                    scope = %rejected.scope,
+                    "execution turned away"
+                );
+                return rejected.response();
+            }
+        },
+        _ => None,
+    };
+
+    let journal = Arc::new(Journal::new(
+        context.next_request_id.fetch_add(1, Ordering::Relaxed),
+    ));
+    let peer = client.peer;
+    if matches!(&request, Request::Execute(req) if req.detach) {
+        return detach(request, prefilled, admitted, peer, context, journal);
+    }
+    with_prefilled(
+        settle(request, admitted, peer, context, journal).await,
+        prefilled,
+    )
+}
+
+/// Check `defaults`, bring them within this daemon's limits and store them
+/// for `client`'s executions, answering with what was stored
+///
+/// Defaults that cannot be used are refused whole, leaving the ones set
+/// before in place.
+fn set_defaults(mut defaults: ExecuteDefaults, client: &Client, context: &Context) -> Response {
+    if let Some(Err(e)) = defaults
+        .timezone
+        .as_deref()
+        .map(leeward_core::config::zoneinfo_path)
+    {
+        return Response::error(format!("default timezone refused: {e}"));
+    }
+    if let Some((name, _)) = defaults
+        .env
+        .iter()
+        .find(|(name, value)| name.is_empty() || name.contains(['=', '\0']) || value.contains('\0'))
+    {
+        return Response::error(format!(
+            "default environment variable {name:?} cannot be set"
+        ));
+    }
+
+    let mut adjustments = Vec::new();
+    if let Some(requested) = defaults.timeout {
+        let applied = context.capped(requested);
+        if applied < requested {
+            adjustments.push(Adjustment::new(
+                "timeout",
+                format!("{requested:?}"),
+                format!("{applied:?}"),
+                format!("this daemon caps timeouts at {applied:?} (max_timeout)"),
+            ));
+            defaults.timeout = Some(applied);
+        }
+    }
+    if defaults.priority == Some(RequestPriority::Normal) {
+        defaults.priority = None;
+    }
+
+    tracing::debug!(uid = client.peer.uid, ?defaults, "connection defaults set");
+    client.defaults.lock().clone_from(&defaults);
+    Response::Defaults {
+        defaults,
+        adjustments,
+    }
+}
+
+/// Fill in what `req` leaves unset from `defaults`, returning what was
+/// filled in
+fn prefill(req: &mut protocol::ExecuteRequest, defaults: &ExecuteDefaults) -> Vec<Adjustment> {
+    const REASON: &str = "this connection's default";
+    let mut prefilled = Vec::new();
+
+    if let (None, Some(timeout)) = (req.timeout, defaults.timeout) {
+        prefilled.push(Adjustment::new(
+            "timeout",
+            "unset",
+            format!("{timeout:?}"),
+            REASON,
+        ));
+        req.timeout = Some(timeout);
+    }
+    if let (None, Some(limit)) = (req.memory_limit, defaults.memory_limit) {
+        prefilled.push(Adjustment::new(
+            "memory_limit",
+            "unset",
+            limit.to_string(),
+            REASON,
+        ));
+        req.memory_limit = Some(limit);
+    }
+    if let (None, Some(timezone)) = (&req.timezone, &defaults.timezone) {
+        prefilled.push(Adjustment::new(
+            "timezone",
+            "unset",
+            timezone.as_str(),
+            REASON,
+        ));
+        req.timezone = Some(timezone.clone());
+    }
+    if let (RequestPriority::Normal, Some(priority)) = (req.priority, defaults.priority) {
+        prefilled.push(Adjustment::new(
+            "priority",
+            "Normal",
+            format!("{priority:?}"),
+            REASON,
+        ));
+        req.priority = priority;
+    }
+
+    // Variables the request sets itself win
+    let added: Vec<_> = defaults
+        .env
+        .iter()
+        .filter(|(name, _)| !req.env.iter().any(|(set, _)| set == name))
+        .cloned()
+        .collect();
+    if !added.is_empty() {
+        let names: Vec<_> = added.iter().map(|(name, _)| name.as_str()).collect();
+        prefilled.push(Adjustment::new("env", "unset", names.join(", "), REASON));
+        req.env.splice(0..0, added);
+    }
+
+    prefilled
+}
+
+/// `response` with `prefilled` listed ahead of its other adjustments, if
+/// it is an execution's
+fn with_prefilled(response: Response, mut prefilled: Vec<Adjustment>) -> Response {
+    match response {
+        Response::Execute(mut response) if !prefilled.is_empty() => {
+            prefilled.append(&mut response.adjustments);
+            Response::Execute(response.with_adjustments(prefilled))
+        }
+        other => other,
+    }
+}
+
+/// Start a detached execution, answering with its id; its answer goes to
+/// the spool
+fn detach(
+    request: Request,
+    prefilled: Vec<Adjustment>,
+    admitted: Option<Admitted>,
+    peer: Peer,
+    context: &Arc<Context>,
+    journal: Arc<Journal>,
+) -> Response {
+    let execution_id = journal.id;
+    if let Err(e) = context.spool.reserve(peer.uid, execution_id) {
+        return e.response();
+    }
+    tracing::debug!(execution_id, uid = peer.uid, "execution detached");
+
+    context.detached.fetch_add(1, Ordering::Relaxed);
+    let context = Arc::clone(context);
+    tokio::spawn(async move {
+        let response = with_prefilled(
+            settle(request, admitted, peer, &context, journal).await,
+            prefilled,
+        );
+        let spool = Arc::clone(&context.spool);
+        if let Err(e) =
+            tokio::task::spawn_blocking(move || spool.store(execution_id, response)).await
+        {
+            tracing::error!(execution_id, error = %e, "spool task failed");
+        }
+        context.detached.fetch_sub(1, Ordering::Relaxed);
+    });
+    Response::Detached { execution_id }
+}
+
+/// Run `request` on a task of its own, answering with an error if it
+/// overruns its deadline
+async fn settle(
+    request: Request,
+    admitted: Option<Admitted>,
+    peer: Peer,
+    context: &Arc<Context>,
+    journal: Arc<Journal>,
+) -> Response {
+    let deadline = context.deadline(&request);
+    let handler = tokio::spawn({
+        let context = Arc::clone(context);
+        let journal = Arc::clone(&journal);
+        async move {
+            let _admitted = admitted;
+            handle_request(request, peer, &context, &journal).await
+        }
+    });
 
-            match pool.execute(code).await {
-                Ok(result) => Response::Execute(protocol::ExecuteResponse {
-                    success: true,
-                    result: Some(result),
-                    error: None,
-                }),
-                Err(e) => Response::Execute(protocol::ExecuteResponse {
-                    success: false,
-                    result: None,
-                    error: Some(e.to_string()),
-                }),
+    let outcome = match deadline {
+        Some(deadline) => tokio::time::timeout(deadline, handler)
+            .await
+            .map_err(|_| deadline),
+        None => Ok(handler.await),
+    };
+    match outcome {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            let error = if e.is_panic() {
+                format!("panicked: {}", supervisor::panic_message(&*e.into_panic()))
+            } else {
+                e.to_string()
+            };
+            tracing::error!(execution_id = journal.id, %error, "request handler failed");
+            Response::Error {
+                message: format!("request {} failed inside the daemon: {error}", journal.id),
+                kind: ErrorKind::Internal {
+                    stage: journal.last().stage,
+                },
             }
         }
+        Err(deadline) => overran(&journal, deadline, context),
+    }
+}
+
+/// Give up on a request that overran its deadline, reclaiming its worker
+fn overran(journal: &Journal, deadline: Duration, context: &Context) -> Response {
+    let last = journal.last();
+    context.metrics.request_deadline_exceeded();
+    tracing::error!(
+        execution_id = journal.id,
+        stage = %last.stage,
+        in_stage_ms = u64::try_from(last.elapsed().as_millis()).unwrap_or(u64::MAX),
+        worker_pid = last.worker_pid,
+        ?deadline,
+        "request overran its deadline"
+    );
+
+    if let (RequestStage::Running { worker_id }, Some(pid)) = (last.stage, last.worker_pid) {
+        context.pool.reclaim(worker_id, pid);
+    }
+
+    Response::Error {
+        message: format!(
+            "request {} got no answer within {:?}; {} for the last {:?}",
+            journal.id,
+            deadline,
+            last.stage,
+            last.elapsed()
+        ),
+        kind: ErrorKind::Internal { stage: last.stage },
+    }
+}
+
+/// Run a `profile_mode` request in an observed worker of its own
+async fn profile(
+    code: &str,
+    options: ExecuteOptions,
+    peer: Peer,
+    pool: &Arc<WorkerPool>,
+) -> Response {
+    if !peer.trusted {
+        return Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::InvalidRequest,
+            "profile mode runs code without Landlock or a syscall filter and is only available to \
+             the daemon's own user",
+        ));
+    }
+    tracing::info!(uid = peer.uid, "running a profiling request");
+
+    let pool = Arc::clone(pool);
+    let code = code.to_owned();
+    match tokio::task::spawn_blocking(move || pool.execute_profiled(&code, &options)).await {
+        Ok(Ok((result, profile))) => {
+            Response::Execute(protocol::ExecuteResponse::ok(result).with_profile(profile))
+        }
+        Ok(Err(e)) => Response::Execute(protocol::ExecuteResponse::from(&e)),
+        Err(e) => Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::Daemon,
+            format!("profiling task failed: {e}"),
+        )),
+    }
+}
+
+/// Run a `debug_profile` request in a worker of its own
+async fn debug(
+    code: &str,
+    options: ExecuteOptions,
+    peer: Peer,
+    pool: &Arc<WorkerPool>,
+) -> Response {
+    if !peer.trusted {
+        return Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::InvalidRequest,
+            "the debug profile lets sandboxed processes trace one another and is only available \
+             to the daemon's own user",
+        ));
+    }
+    tracing::info!(uid = peer.uid, "running a request under the debug profile");
+
+    let pool = Arc::clone(pool);
+    let code = code.to_owned();
+    match tokio::task::spawn_blocking(move || pool.execute_debug(&code, &options)).await {
+        Ok(Ok(result)) => Response::Execute(protocol::ExecuteResponse::ok(result)),
+        Ok(Err(e)) => Response::Execute(protocol::ExecuteResponse::from(&e)),
+        Err(e) => Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::Daemon,
+            format!("debug task failed: {e}"),
+        )),
+    }
+}
+
+/// Bring `req` within what this daemon honours, returning what was changed
+fn adjust(req: &mut protocol::ExecuteRequest, context: &Context) -> Vec<Adjustment> {
+    let mut adjustments = Vec::new();
+
+    if let Some(requested) = req.timeout {
+        let applied = context.capped(requested);
+        if applied < requested {
+            adjustments.push(Adjustment::new(
+                "timeout",
+                format!("{requested:?}"),
+                format!("{applied:?}"),
+                format!("this daemon caps timeouts at {applied:?} (max_timeout)"),
+            ));
+            req.timeout = Some(applied);
+        }
+    }
+
+    if req.soft_timeout_traceback && req.interpreter != Interpreter::Python {
+        adjustments.push(Adjustment::new(
+            "soft_timeout_traceback",
+            "true",
+            "false",
+            format!(
+                "only Python can dump a traceback, not {}",
+                req.interpreter.wire_name()
+            ),
+        ));
+        req.soft_timeout_traceback = false;
+    }
+
+    if let Some(requested) = req.max_connections {
+        if !context.pool.config().allow_network {
+            adjustments.push(Adjustment::new(
+                "max_connections",
+                requested.to_string(),
+                "none",
+                "networking is disabled in this sandbox, so no sockets can be opened",
+            ));
+            req.max_connections = None;
+        }
+    }
+
+    if let Some(code) = req.code.as_mut().filter(|_| req.normalize_code) {
+        let normalized = leeward_core::source::normalize(code, req.interpreter);
+        if normalized.bom {
+            adjustments.push(Adjustment::new(
+                "code",
+                "a leading byte order mark",
+                "none",
+                "the interpreter would read it as an invalid character",
+            ));
+        }
+        if normalized.crlf_lines > 0 {
+            adjustments.push(Adjustment::new(
+                "code",
+                format!("CRLF line endings on {} lines", normalized.crlf_lines),
+                "LF",
+                "a carriage return would end up in string literals and shell words",
+            ));
+        }
+        if let Some(encoding) = normalized.encoding {
+            adjustments.push(Adjustment::new(
+                "code",
+                format!("source encoding {encoding}"),
+                "UTF-8, declaration blanked out",
+                "code arrives as UTF-8 text, so no other encoding can apply",
+            ));
+        }
+    }
+
+    adjustments
+}
+
+/// Run one execution request, noting its progress in `journal`, and list
+/// how it was adjusted in the response
+async fn execute(
+    mut req: protocol::ExecuteRequest,
+    peer: Peer,
+    context: &Context,
+    journal: &Journal,
+) -> Response {
+    if let Some(refused) = oversized(&req, peer, journal.id, context) {
+        return refused;
+    }
+    let adjustments = adjust(&mut req, context);
+    for adjustment in &adjustments {
+        tracing::debug!(execution_id = journal.id, %adjustment, "adjusted request");
+    }
+    match run_execution(req, peer, context, journal).await {
+        Response::Execute(mut response) => {
+            let mut adjustments = adjustments;
+            adjustments.append(&mut response.adjustments);
+            Response::Execute(response.with_adjustments(adjustments))
+        }
+        other => other,
+    }
+}
+
+/// The refusal for `req` if any part of it, as sent, is over the daemon's
+/// size limits
+fn oversized(
+    req: &protocol::ExecuteRequest,
+    peer: Peer,
+    execution_id: u64,
+    context: &Context,
+) -> Option<Response> {
+    match context.sizes.check(req) {
+        Ok(()) => None,
+        Err(LeewardError::Refused { kind, message }) => {
+            tracing::debug!(execution_id, uid = peer.uid, %message, "execution over a size limit");
+            Some(Response::Error { message, kind })
+        }
+        Err(e) => Some(Response::error(e.to_string())),
+    }
+}
+
+/// Hold `result` to the daemon's output limit and log what the sandbox
+/// denied it
+fn finish(
+    result: &mut leeward_core::ExecutionResult,
+    peer: Peer,
+    execution_id: u64,
+    context: &Context,
+) {
+    if result.truncate_output(context.max_output()) {
+        tracing::debug!(execution_id, "output cut off at the size limit");
+    }
+    if !result.denials.is_empty() {
+        tracing::warn!(
+            execution_id,
+            uid = peer.uid,
+            denials = %leeward_core::denial::summary(&result.denials),
+            "sandbox denied operations"
+        );
+    }
+}
+
+/// Run one execution request as given
+async fn run_execution(
+    mut req: protocol::ExecuteRequest,
+    peer: Peer,
+    context: &Context,
+    journal: &Journal,
+) -> Response {
+    let pool = &context.pool;
+    // Decided before the inputs are moved out of the request
+    let fast_path = req.fits_fast_path();
+    let slot = req
+        .shm_slot_id
+        .map(|slot_id| (slot_id, req.shm_generation.unwrap_or_default()));
+
+    let code = match (req.code.take(), slot) {
+        (Some(code), _) => code,
+        (None, Some((slot_id, generation))) => {
+            match context.shm.code(peer.connection, slot_id, generation) {
+                Ok(code) => code,
+                Err(message) => {
+                    tracing::debug!(
+                        connection = peer.connection,
+                        %message,
+                        "shared memory execution refused"
+                    );
+                    return Response::Execute(protocol::ExecuteResponse::failed(
+                        OutcomeCode::InvalidRequest,
+                        message,
+                    ));
+                }
+            }
+        }
+        (None, None) => {
+            return Response::Execute(protocol::ExecuteResponse::failed(
+                OutcomeCode::InvalidRequest,
+                "no code provided, inline or in a shared memory slot",
+            ));
+        }
+    };
+
+    if let Some(at) = leeward_core::source::find_nul(&code) {
+        return Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::InvalidRequest,
+            format!("code has a NUL byte at offset {at}, which no interpreter can be given"),
+        ));
+    }
+
+    let code = match screen(code, &mut req, peer, context, journal) {
+        Ok(code) => code,
+        Err(reason) => {
+            return Response::Error {
+                message: reason,
+                kind: ErrorKind::PolicyRejected,
+            };
+        }
+    };
+
+    if let Some(refused) = refuse_inputs(&req) {
+        return refused;
+    }
+    let uploads = match context.uploads.take(peer.uid, &req.uploads) {
+        Ok(uploads) => uploads,
+        Err(message) => {
+            return Response::Execute(protocol::ExecuteResponse::failed(
+                OutcomeCode::InvalidRequest,
+                message,
+            ));
+        }
+    };
+
+    let options = ExecuteOptions {
+        execution_id: Some(journal.id),
+        ..options(&mut req, uploads, context)
+    };
+
+    if req.profile_mode {
+        journal.record(RequestStage::Profiling);
+        return profile(&code, options, peer, pool).await;
+    }
+    if req.debug_profile {
+        journal.record(RequestStage::Debugging);
+        return debug(&code, options, peer, pool).await;
+    }
+
+    // Small snippets skip the queue when a worker is free right now
+    let inline = if fast_path {
+        pool.try_execute_inline(&code, &options, journal)
+    } else {
+        None
+    };
+    let outcome = if let Some(outcome) = inline {
+        context.metrics.execution(Dispatch::Inline);
+        outcome
+    } else {
+        let outcome = pool.execute(&code, &options, journal, req.priority).await;
+        context.metrics.execution(Dispatch::Queued);
+        outcome
+    };
+
+    match outcome {
+        Ok(mut result) => {
+            finish(&mut result, peer, journal.id, context);
+            let response = match slot {
+                Some((slot_id, generation)) => {
+                    let policy = req.shm_overflow.unwrap_or(context.shm_overflow);
+                    context
+                        .shm
+                        .deliver(peer.connection, slot_id, generation, result, policy)
+                }
+                None => protocol::ExecuteResponse::ok(result),
+            };
+            Response::Execute(response)
+        }
+        Err(e) => Response::Execute(protocol::ExecuteResponse::from(&e)),
+    }
+}
+
+/// The response refusing the input file names or timezone of `req`, if
+/// any is invalid
+fn refuse_inputs(req: &protocol::ExecuteRequest) -> Option<Response> {
+    let names = req
+        .files
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .chain(req.uploads.iter().map(|(name, _)| name.as_str()));
+    if let Err(e) = leeward_core::workspace::check_paths(names) {
+        return Some(Response::Execute(protocol::ExecuteResponse::from(&e)));
+    }
+    if let Some(Err(e)) = req
+        .timezone
+        .as_deref()
+        .map(leeward_core::config::zoneinfo_path)
+    {
+        return Some(Response::Execute(protocol::ExecuteResponse::from(&e)));
+    }
+    if req.profile_mode && !req.uploads.is_empty() {
+        return Some(Response::Execute(protocol::ExecuteResponse::failed(
+            OutcomeCode::InvalidRequest,
+            "profile mode does not take uploaded input files",
+        )));
+    }
+    None
+}
+
+/// Pass `req`, its `code` put back in it wherever it came from, through
+/// the daemon's screening, returning the code unless it was refused and
+/// why if it was
+fn screen(
+    code: String,
+    req: &mut protocol::ExecuteRequest,
+    peer: Peer,
+    context: &Context,
+    journal: &Journal,
+) -> Result<String, String> {
+    let Some(screening) = &context.screening else {
+        return Ok(code);
+    };
+    req.code = Some(code);
+    let verdict = screening.screen(req);
+    let code = req.code.take().unwrap_or_default();
+
+    match verdict {
+        ScreenVerdict::Allow => {}
+        ScreenVerdict::Deny(reason) => {
+            context.metrics.screened(true);
+            tracing::warn!(
+                execution_id = journal.id,
+                uid = peer.uid,
+                %reason,
+                "execution refused by code screening"
+            );
+            return Err(reason);
+        }
+        ScreenVerdict::Flag(note) => {
+            context.metrics.screened(false);
+            tracing::warn!(
+                execution_id = journal.id,
+                uid = peer.uid,
+                %note,
+                "execution flagged by code screening"
+            );
+        }
+    }
+    Ok(code)
+}
+
+/// What the worker runs `req` with, its inputs taken out of it
+fn options(
+    req: &mut protocol::ExecuteRequest,
+    uploads: Vec<(String, Arc<std::fs::File>)>,
+    context: &Context,
+) -> ExecuteOptions {
+    let scheduling = context.scheduling.get(req.priority);
+    ExecuteOptions {
+        max_connections: req.max_connections,
+        timeout: req.timeout,
+        soft_timeout_traceback: req.soft_timeout_traceback,
+        stdin: req.stdin.take(),
+        env: std::mem::take(&mut req.env),
+        memory_limit: req.memory_limit,
+        nice: scheduling.nice,
+        sched_policy: scheduling.sched_policy,
+        files: std::mem::take(&mut req.files),
+        uploads,
+        timezone: req.timezone.take(),
+        interpreter: req.interpreter,
+        args: std::mem::take(&mut req.args),
+        preemptible: req.priority == RequestPriority::Batch
+            && context.pool.time_slicing().is_some(),
+        suppress_pressure_signal: !req.pressure_signal,
+        ..ExecuteOptions::default()
+    }
+}
+
+/// Resolve the settings `req` would run with on `client`'s connection,
+/// noting where each came from, through the merges an execution goes
+/// through but without running it
+fn explain(mut req: protocol::ExecuteRequest, client: &Client, context: &Context) -> Response {
+    if let Some(Err(e)) = req
+        .timezone
+        .as_deref()
+        .map(leeward_core::config::zoneinfo_path)
+    {
+        return Response::error(format!("timezone refused: {e}"));
+    }
+    // Nothing runs, so the code is not normalized either
+    req.code = None;
+
+    let mut trace = PolicyTrace::recording();
+    trace.adjusted(&prefill(&mut req, &client.defaults.lock()), |_| {
+        Provenance::ConnectionDefault
+    });
+    trace.adjusted(&adjust(&mut req, context), |adjustment| {
+        Provenance::Clamped {
+            limit: adjustment.reason.clone(),
+        }
+    });
+
+    if req.priority == RequestPriority::Normal {
+        trace.record("priority", Provenance::Default, || "Normal".into());
+    } else {
+        trace.requested("priority", || format!("{:?}", req.priority));
+    }
+    // Scheduling comes from the daemon's config for the request's priority
+    let (scheduling, built_in) = (
+        context.scheduling.get(req.priority),
+        PriorityScheduling::default().get(req.priority),
+    );
+    let from = |is_default: bool| {
+        if is_default {
+            Provenance::Default
+        } else {
+            Provenance::DaemonConfig
+        }
+    };
+    if let Some(nice) = scheduling.nice {
+        trace.record("nice", from(scheduling.nice == built_in.nice), || {
+            nice.to_string()
+        });
+    }
+    if let Some(policy) = scheduling.sched_policy {
+        trace.record(
+            "sched_policy",
+            from(scheduling.sched_policy == built_in.sched_policy),
+            || format!("{policy:?}"),
+        );
+    }
+    if let Some(limit) = req.max_connections {
+        trace.requested("max_connections", || limit.to_string());
+    }
+
+    let options = options(&mut req, Vec::new(), context);
+    leeward_core::worker::explain(&context.pool.config(), &options, &mut trace);
+    Response::Policy {
+        fields: trace.into_fields(),
+    }
+}
+
+/// Change the log filter or debug flags, if `peer` may
+fn adjust_logging(
+    peer: Peer,
+    log: &LogControl,
+    adjust: impl FnOnce(&LogControl) -> Result<(), String>,
+) -> Response {
+    if !peer.trusted {
+        return Response::error("only the daemon's own user may change its logging");
+    }
+    match adjust(log) {
+        Ok(()) => Response::LogSettings {
+            filter: log.filter(),
+            debug_flags: log.debug_flags(),
+        },
+        Err(message) => Response::error(message),
+    }
+}
+
+/// Handle a single request, noting its progress in `journal`
+/// Pool status with executions in flight per client, and the daemon's
+/// log settings
+fn status_detailed(context: &Context) -> Response {
+    let snapshot = context.pool.snapshot();
+    Response::StatusDetailed {
+        total: snapshot.serving(),
+        busy: snapshot.count(WorkerState::Busy),
+        dead: snapshot.count(WorkerState::Dead),
+        snapshot_age_ms: u64::try_from(snapshot.age().as_millis()).unwrap_or(u64::MAX),
+        workers: snapshot.slots(),
+        inflight: context.inflight.by_uid(),
+        max_inflight_per_connection: context.inflight.per_connection(),
+        max_inflight_per_peer_uid: context.inflight.per_peer_uid(),
+        log_filter: context.log.filter(),
+        debug_flags: context.log.debug_flags(),
+        shm_slots: context.shm.occupancy(),
+        standby: snapshot.standby(),
+    }
+}
+
+async fn handle_request(
+    request: Request,
+    peer: Peer,
+    context: &Context,
+    journal: &Journal,
+) -> Response {
+    let pool = &context.pool;
+    match request {
+        Request::Execute(req) => execute(req, peer, context, journal).await,
+        // Both read the pool's snapshot, so they answer even while a pool
+        // lock is wedged
         Request::Status => {
-            let status = pool.status();
+            let snapshot = pool.snapshot();
+            let status = snapshot.status();
             Response::Status {
                 total: status.total,
                 idle: status.idle,
                 busy: status.busy,
+                stale: status.stale,
+                draining: status.draining,
+                drained: status.drained,
+                handover: context.handover.status(),
+                snapshot_age_ms: u64::try_from(snapshot.age().as_millis()).unwrap_or(u64::MAX),
             }
         }
+        Request::StatusDetailed => status_detailed(context),
+        Request::ListWorkers => Response::WorkerList {
+            workers: pool.worker_info(),
+            current_fingerprint: pool.current_fingerprint(),
+        },
+        Request::RecycleStale => {
+            let scheduled = pool.status().stale;
+            let pool = Arc::clone(pool);
+
+            // One worker at a time, so most of the pool stays available
+            tokio::spawn(async move {
+                while pool.recycle_stale(1) > 0 {
+                    tokio::time::sleep(STALE_RECYCLE_INTERVAL).await;
+                }
+            });
+
+            Response::RecycleStale { scheduled }
+        }
+        Request::DrainProfile { profile, reason } => drain(profile, reason, peer, context).await,
+        Request::SetLogFilter { directive, persist } => adjust_logging(peer, &context.log, |log| {
+            log.set_filter(&directive, persist)
+        }),
+        Request::SetDebug {
+            flag,
+            enabled,
+            persist,
+        } => adjust_logging(peer, &context.log, |log| {
+            log.set_debug(flag, enabled, persist);
+            Ok(())
+        }),
+        // Switches the connection to streaming before it gets here
+        Request::Subscribe { .. } => Response::error("subscriptions are handled per connection"),
+        // Taken over by the connection before it gets here, if it can carry
+        // descriptors
+        Request::Handover => Response::error("a handover needs a msgpack connection"),
+        Request::ShmSetup => Response::error("a shared memory slot needs a msgpack connection"),
+        Request::ShmFree {
+            slot_id,
+            generation,
+        } => match context.shm.free(peer.connection, slot_id, generation) {
+            Ok(()) => Response::ShmFreed { slot_id },
+            Err(message) => Response::error(message),
+        },
+        // Answered by the connection before it gets here
         Request::Ping => Response::Pong,
+        Request::Health { min_idle } => health(min_idle, context),
+        Request::Hello => Response::Hello(context.identity.daemon_info(&pool.config())),
+        Request::PolicyChanges => Response::PolicyChanges {
+            changes: context.audit.policy_changes(),
+        },
+        Request::UploadBegin {
+            name,
+            total_len,
+            sha256,
+            reusable,
+        } => match context
+            .uploads
+            .begin(peer.uid, name, total_len, &sha256, reusable)
+        {
+            Ok(status) => Response::Upload(status),
+            Err(e) => e.response(),
+        },
+        Request::UploadChunk { id, offset, data } => {
+            match context.uploads.chunk(peer.uid, id, offset, &data) {
+                Ok(status) => Response::Upload(status),
+                Err(e) => e.response(),
+            }
+        }
+        Request::UploadCommit { id } => {
+            let uploads = Arc::clone(&context.uploads);
+            match tokio::task::spawn_blocking(move || uploads.commit(peer.uid, id)).await {
+                Ok(Ok(status)) => Response::Upload(status),
+                Ok(Err(e)) => e.response(),
+                Err(e) => Response::error(format!("commit task failed: {e}")),
+            }
+        }
+        // Answered by the connection before it gets here
+        Request::SetDefaults { .. } => Response::error("defaults are handled per connection"),
+        Request::ExplainPolicy { .. } => Response::error("policies are explained per connection"),
+        Request::FetchResult { execution_id } => {
+            let spool = Arc::clone(&context.spool);
+            tokio::task::spawn_blocking(move || spool.fetch(peer.uid, execution_id))
+                .await
+                .unwrap_or_else(|e| Response::error(format!("fetch task failed: {e}")))
+        }
     }
 }
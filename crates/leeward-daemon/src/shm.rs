@@ -0,0 +1,236 @@
+//! Shared memory slots leased to client connections
+//!
+//! A client asks for a slot with `ShmSetup`, gets the region's memfd with
+//! the answer, writes its code to the request slot and executes it by slot
+//! id and generation instead of sending it. Every slot belongs to the
+//! connection that leased it: it is given back when the client frees it,
+//! when the connection closes however it ends, and when it has gone
+//! `shm_slot_ttl` without an execution touching it. Giving a slot back
+//! bumps its generation, so a client that lost its slot and writes to it
+//! later is refused instead of running someone else's code.
+//!
+//! The output goes back through the same slot, what does not fit handled
+//! by the execution's or the daemon's `shm_overflow` policy. A slot lost
+//! while its code ran gets nothing; the output goes in the answer instead.
+
+use crate::metrics::{Metrics, Reclaimed};
+use leeward_core::protocol::{
+    Adjustment, ExecuteResponse, ShmLease, ShmOccupancy, ShmOverflowPolicy,
+};
+use leeward_core::shm::{
+    self, LeaseError, MappedSharedMemory, Owner, SharedMemoryRegion, SlotPair,
+};
+use leeward_core::{ExecutionResult, OutcomeCode};
+use std::os::fd::BorrowedFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The daemon's shared memory region and who holds its slots
+pub struct ShmSlots {
+    /// `None` if the region could not be made
+    region: Option<Region>,
+    /// Reclaim slots untouched this long (`None` = never)
+    ttl: Option<Duration>,
+    /// Slots released or reclaimed rather than freed by their client
+    reclaimed: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+struct Region {
+    slots: SharedMemoryRegion,
+    /// The daemon's own mapping, for reading requests
+    mapped: MappedSharedMemory,
+}
+
+impl ShmSlots {
+    /// Make the region, logging and going without if that fails
+    pub fn open(ttl: Option<Duration>, metrics: Arc<Metrics>) -> Self {
+        let region = SharedMemoryRegion::new().and_then(|slots| {
+            let mapped = MappedSharedMemory::new(slots.as_raw_fd(), false)?;
+            Ok(Region { slots, mapped })
+        });
+        let region = match region {
+            Ok(region) => Some(region),
+            Err(e) => {
+                tracing::warn!(error = %e, "no shared memory region; clients must send their code");
+                None
+            }
+        };
+        let slots = Self {
+            region,
+            ttl,
+            reclaimed: AtomicU64::new(0),
+            metrics,
+        };
+        slots.report();
+        slots
+    }
+
+    /// Whether clients can lease slots
+    pub const fn enabled(&self) -> bool {
+        self.region.is_some()
+    }
+
+    /// Lease a slot to connection `owner`, with the memfd to send along
+    pub fn lease(&self, owner: Owner) -> Result<(ShmLease, BorrowedFd<'_>), String> {
+        let region = self.region()?;
+        let slot = region
+            .slots
+            .allocate_slot(owner)
+            .map_err(|e| e.to_string())?;
+        self.report();
+        let lease = ShmLease {
+            slot_id: slot.slot_id,
+            generation: slot.generation,
+            request_offset: slot.request_offset as u64,
+            request_len: shm::REQUEST_SLOT_SIZE as u64,
+            response_offset: slot.response_offset as u64,
+            response_len: shm::RESPONSE_SLOT_SIZE as u64,
+            region_len: shm::REGION_SIZE as u64,
+        };
+        // SAFETY: The memfd lives as long as the region, which `self` holds
+        let fd = unsafe { BorrowedFd::borrow_raw(region.slots.as_raw_fd()) };
+        Ok((lease, fd))
+    }
+
+    /// Give back a slot connection `owner` holds
+    pub fn free(&self, owner: Owner, slot_id: u32, generation: u32) -> Result<(), String> {
+        let region = self.region()?;
+        let slot = Self::slot(region, slot_id, generation)?;
+        region
+            .slots
+            .free_slot(owner, &slot)
+            .map_err(|e| refusal(slot_id, e))?;
+        self.report();
+        Ok(())
+    }
+
+    /// The code connection `owner` wrote to its slot
+    pub fn code(&self, owner: Owner, slot_id: u32, generation: u32) -> Result<String, String> {
+        let region = self.region()?;
+        let slot = Self::slot(region, slot_id, generation)?;
+        region
+            .slots
+            .check(owner, &slot)
+            .map_err(|e| refusal(slot_id, e))?;
+        let bytes = region
+            .mapped
+            .read_request(&slot)
+            .map_err(|e| e.to_string())?;
+        String::from_utf8(bytes)
+            .map_err(|_| format!("shared memory slot {slot_id} holds code that is not UTF-8"))
+    }
+
+    /// Answer an execution that ran the code in a slot of connection
+    /// `owner`, its output written to the slot under `policy`
+    pub fn deliver(
+        &self,
+        owner: Owner,
+        slot_id: u32,
+        generation: u32,
+        mut result: ExecutionResult,
+        policy: ShmOverflowPolicy,
+    ) -> ExecuteResponse {
+        let held = self.region().and_then(|region| {
+            let slot = Self::slot(region, slot_id, generation)?;
+            region
+                .slots
+                .check(owner, &slot)
+                .map_err(|e| refusal(slot_id, e))?;
+            Ok((region, slot))
+        });
+        let (region, slot) = match held {
+            Ok(held) => held,
+            Err(message) => {
+                let lost = Adjustment::new("shm_slot_id", slot_id.to_string(), "socket", message);
+                return ExecuteResponse::ok(result).with_adjustments(vec![lost]);
+            }
+        };
+
+        let packed = match shm::pack_output(&mut result, shm::RESPONSE_CAPACITY, policy) {
+            Ok(packed) => packed,
+            Err(overflow) => {
+                return ExecuteResponse::failed(OutcomeCode::Daemon, overflow.to_string())
+                    .with_adjustments(vec![overflow.adjustment()]);
+            }
+        };
+        if let Err(e) = region.mapped.write_response(&slot, &packed.slot) {
+            return ExecuteResponse::failed(OutcomeCode::Daemon, e.to_string());
+        }
+        ExecuteResponse::ok(result)
+            .with_shm_payload(packed.payload)
+            .with_adjustments(packed.adjustment.into_iter().collect())
+    }
+
+    /// Give back every slot of connection `owner`, which has closed
+    pub fn release(&self, owner: Owner) {
+        let Some(region) = &self.region else { return };
+        let released = region.slots.release_owner(owner);
+        if released > 0 {
+            tracing::debug!(
+                connection = owner,
+                released,
+                "released the shared memory slots of a closed connection"
+            );
+            self.reclaimed(Reclaimed::Disconnect, released);
+        }
+    }
+
+    /// Reclaim slots untouched for the TTL
+    pub fn sweep(&self) {
+        let (Some(region), Some(ttl)) = (&self.region, self.ttl) else {
+            return;
+        };
+        let reclaimed = region.slots.reclaim_idle(ttl);
+        if reclaimed > 0 {
+            tracing::info!(reclaimed, "reclaimed idle shared memory slots");
+            self.reclaimed(Reclaimed::Idle, reclaimed);
+        }
+    }
+
+    /// How often [`sweep`](Self::sweep) should run to keep to the TTL
+    pub fn sweep_interval(&self) -> Option<Duration> {
+        self.ttl.filter(|_| self.enabled())
+    }
+
+    /// How many slots are leased, for a detailed status
+    pub fn occupancy(&self) -> ShmOccupancy {
+        ShmOccupancy {
+            leased: self
+                .region
+                .as_ref()
+                .map_or(0, |region| region.slots.leased()),
+            total: if self.enabled() { shm::MAX_SLOTS } else { 0 },
+            reclaimed: self.reclaimed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn region(&self) -> Result<&Region, String> {
+        self.region
+            .as_ref()
+            .ok_or_else(|| "this daemon has no shared memory region".to_owned())
+    }
+
+    fn slot(region: &Region, slot_id: u32, generation: u32) -> Result<SlotPair, String> {
+        region
+            .slots
+            .slot_pair(slot_id, generation)
+            .ok_or_else(|| refusal(slot_id, LeaseError::NoSuchSlot))
+    }
+
+    fn reclaimed(&self, reason: Reclaimed, count: usize) {
+        self.reclaimed.fetch_add(count as u64, Ordering::Relaxed);
+        self.metrics.shm_reclaimed(reason, count);
+        self.report();
+    }
+
+    fn report(&self) {
+        let occupancy = self.occupancy();
+        self.metrics.shm_slots(occupancy.leased, occupancy.total);
+    }
+}
+
+fn refusal(slot_id: u32, e: LeaseError) -> String {
+    format!("shared memory slot {slot_id} refused: {e}")
+}
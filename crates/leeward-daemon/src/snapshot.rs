@@ -0,0 +1,20 @@
+//! Pool status snapshots, refreshed on a timer off the request path
+
+use crate::pool::WorkerPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Refresh the pool's snapshot every `interval`
+///
+/// State changes refresh it too; this catches what changes without one,
+/// such as the queue, and keeps its age honest while nothing happens.
+pub async fn run(pool: Arc<WorkerPool>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        pool.refresh_snapshot();
+    }
+}
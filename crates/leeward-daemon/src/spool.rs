@@ -0,0 +1,400 @@
+//! Results of detached executions, kept on disk until fetched
+//!
+//! A detached execution is answered at once with its id. Its file in
+//! `spool_dir`, `<id>.result`, is created then with no response in it, and
+//! filled in once the execution finishes; [`Request::FetchResult`] reads
+//! and deletes it. Results count against their client uid's
+//! `spool_quota`: a client whose results already fill it cannot
+//! detach, and a result that would take it past is replaced by an error
+//! saying so. A result not fetched `spool_ttl` after it was written is
+//! deleted.
+//!
+//! Results outlive the daemon process. A daemon opening the spool picks up
+//! every file there, including those of executions a predecessor is still
+//! running during a handover, and numbers its own executions past the
+//! highest id it found, so a client can fetch across a restart.
+//!
+//! Files are written through [`storage`], so each is whole. What a crash
+//! left staged is removed at open, and a file that still cannot be read is
+//! quarantined rather than deleted.
+//!
+//! [`Request::FetchResult`]: leeward_core::protocol::Request::FetchResult
+
+use crate::storage;
+use leeward_core::OutcomeCode;
+use leeward_core::protocol::{self, ErrorKind, ExecuteResponse, Response};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Extension of a spool file
+const EXTENSION: &str = "result";
+
+/// Results of detached executions for every client
+#[derive(Debug)]
+pub struct Spool {
+    /// `None` when detaching is disabled
+    dir: Option<PathBuf>,
+    quota_bytes: u64,
+    ttl: Duration,
+    /// First execution id not taken by a file found at open
+    first_free_id: u64,
+    entries: Mutex<BTreeMap<u64, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Client uid that started it; peers of unknown uid share one quota
+    owner: Option<u32>,
+    /// Size of its file
+    bytes: u64,
+    /// When its result was written, `None` while this daemon runs it
+    written: Option<SystemTime>,
+}
+
+/// Why a detached execution was refused
+#[derive(Debug)]
+pub enum SpoolError {
+    /// The client's results already fill its quota
+    Quota { used_bytes: u64, quota_bytes: u64 },
+    /// Anything else, with the message for the client
+    Request(String),
+}
+
+impl SpoolError {
+    pub fn response(self) -> Response {
+        match self {
+            Self::Quota {
+                used_bytes,
+                quota_bytes,
+            } => Response::Error {
+                message: format!(
+                    "detached results use {used_bytes} bytes of this client's {quota_bytes} byte \
+                     quota"
+                ),
+                kind: ErrorKind::QuotaExceeded {
+                    used_bytes,
+                    quota_bytes,
+                },
+            },
+            Self::Request(message) => Response::error(message),
+        }
+    }
+}
+
+/// What a spool file holds
+#[derive(Debug, Serialize, Deserialize)]
+struct Spooled {
+    owner: Option<u32>,
+    /// `None` until the execution finishes
+    response: Option<Response>,
+}
+
+impl Spool {
+    /// Spool in `dir` limited to `quota_bytes` per client (0 = detaching
+    /// disabled), each result kept `ttl` after it was written
+    ///
+    /// Detaching is disabled, with a warning, if `dir` cannot be created.
+    pub fn open(dir: &Path, quota_bytes: u64, ttl: Duration) -> Self {
+        let mut spool = Self {
+            dir: None,
+            quota_bytes,
+            ttl,
+            first_free_id: 0,
+            entries: Mutex::new(BTreeMap::new()),
+        };
+        if quota_bytes == 0 {
+            return spool;
+        }
+        if let Err(e) = std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+        {
+            tracing::warn!(dir = %dir.display(), error = %e, "detached executions disabled");
+            return spool;
+        }
+
+        let staged = storage::remove_staged(dir);
+        if staged > 0 {
+            tracing::warn!(
+                dir = %dir.display(),
+                files = staged,
+                "removed results a crash left half-written"
+            );
+        }
+        let entries = spool.entries.get_mut();
+        for (id, path) in spool_files(dir) {
+            spool.first_free_id = spool.first_free_id.max(id + 1);
+            match read(&path) {
+                Ok((spooled, metadata)) => {
+                    entries.insert(
+                        id,
+                        Entry {
+                            owner: spooled.owner,
+                            bytes: metadata.len(),
+                            // A predecessor's execution still running ages too
+                            written: Some(
+                                metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+                            ),
+                        },
+                    );
+                }
+                Err(e) => match storage::quarantine(&path) {
+                    Ok(moved) => {
+                        tracing::warn!(
+                            path = %path.display(),
+                            to = %moved.display(),
+                            error = %e,
+                            "quarantined an unreadable spooled result"
+                        );
+                    }
+                    Err(quarantine) => {
+                        tracing::warn!(
+                            path = %path.display(),
+                            error = %e,
+                            %quarantine,
+                            "dropping an unreadable spooled result"
+                        );
+                        let _ = std::fs::remove_file(&path);
+                    }
+                },
+            }
+        }
+        tracing::info!(dir = %dir.display(), results = entries.len(), "opened the result spool");
+        spool.dir = Some(dir.to_owned());
+        spool
+    }
+
+    /// Whether executions may detach
+    pub const fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// First execution id not taken by a result already in the spool
+    pub const fn first_free_id(&self) -> u64 {
+        self.first_free_id
+    }
+
+    /// Take `id` for a detached execution of `owner`'s, refused if detaching
+    /// is disabled or `owner`'s results already fill its quota
+    pub fn reserve(&self, owner: Option<u32>, id: u64) -> Result<(), SpoolError> {
+        let Some(dir) = &self.dir else {
+            return Err(SpoolError::Request(
+                "detached executions are disabled on this daemon".into(),
+            ));
+        };
+        let mut entries = self.entries.lock();
+        self.sweep_locked(&mut entries);
+        let used_bytes = used(&entries, owner);
+        if used_bytes >= self.quota_bytes {
+            drop(entries);
+            return Err(SpoolError::Quota {
+                used_bytes,
+                quota_bytes: self.quota_bytes,
+            });
+        }
+
+        let bytes = create(
+            &path(dir, id),
+            &Spooled {
+                owner,
+                response: None,
+            },
+        )
+        .map_err(|e| SpoolError::Request(format!("failed to spool execution {id}: {e}")))?;
+        entries.insert(
+            id,
+            Entry {
+                owner,
+                bytes,
+                written: None,
+            },
+        );
+        drop(entries);
+        Ok(())
+    }
+
+    /// Keep `response` as the result of detached execution `id`
+    pub fn store(&self, id: u64, response: Response) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let Some(owner) = self.entries.lock().get(&id).map(|entry| entry.owner) else {
+            tracing::warn!(
+                execution_id = id,
+                "detached execution finished without a spool entry"
+            );
+            return;
+        };
+
+        let spooled = Spooled {
+            owner,
+            response: Some(response),
+        };
+        let written = protocol::encode(&spooled).map_err(std::io::Error::other).and_then(|bytes| {
+            let used_bytes = used(&self.entries.lock(), owner);
+            if used_bytes.saturating_add(bytes.len() as u64) <= self.quota_bytes {
+                return write(&path(dir, id), &bytes);
+            }
+            let refused = Response::Execute(ExecuteResponse::failed(
+                OutcomeCode::Daemon,
+                format!(
+                    "the result of {} bytes would take this client's detached results past their \
+                     {} byte quota",
+                    bytes.len(),
+                    self.quota_bytes
+                ),
+            ));
+            let bytes = protocol::encode(&Spooled {
+                owner,
+                response: Some(refused),
+            })
+            .map_err(std::io::Error::other)?;
+            write(&path(dir, id), &bytes)
+        });
+
+        let mut entries = self.entries.lock();
+        match written {
+            Ok(bytes) => {
+                if let Some(entry) = entries.get_mut(&id) {
+                    entry.bytes = bytes;
+                    entry.written = Some(SystemTime::now());
+                }
+            }
+            Err(e) => {
+                tracing::error!(execution_id = id, error = %e, "failed to spool a detached result");
+                entries.remove(&id);
+                let _ = std::fs::remove_file(path(dir, id));
+            }
+        }
+        drop(entries);
+    }
+
+    /// Take the result of detached execution `id`, if `owner` started it
+    /// and it has finished
+    pub fn fetch(&self, owner: Option<u32>, id: u64) -> Response {
+        let Some(dir) = &self.dir else {
+            return Response::error("detached executions are disabled on this daemon");
+        };
+        let gone = || {
+            Response::error(format!(
+                "no result of execution {id}; it may have expired or been fetched"
+            ))
+        };
+
+        let mut entries = self.entries.lock();
+        self.sweep_locked(&mut entries);
+        if entries.get(&id).is_some_and(|entry| entry.owner != owner) {
+            return gone();
+        }
+        // A predecessor may have detached it after this daemon opened the spool
+        let path = path(dir, id);
+        let spooled = match read(&path) {
+            Ok((spooled, _)) if spooled.owner == owner => spooled,
+            Ok(_) => return gone(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                entries.remove(&id);
+                return gone();
+            }
+            Err(e) => {
+                return Response::error(format!(
+                    "failed to read the result of execution {id}: {e}"
+                ));
+            }
+        };
+        let Some(response) = spooled.response else {
+            return Response::Error {
+                message: format!("execution {id} is still running"),
+                kind: ErrorKind::Pending,
+            };
+        };
+
+        entries.remove(&id);
+        drop(entries);
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::warn!(execution_id = id, error = %e, "failed to delete a fetched result");
+        }
+        response
+    }
+
+    /// Delete results kept past their time
+    pub fn sweep(&self) {
+        self.sweep_locked(&mut self.entries.lock());
+    }
+
+    fn sweep_locked(&self, entries: &mut BTreeMap<u64, Entry>) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let before = entries.len();
+        entries.retain(|&id, entry| {
+            let expired = entry
+                .written
+                .is_some_and(|written| written.elapsed().is_ok_and(|age| age > self.ttl));
+            if expired {
+                let _ = std::fs::remove_file(path(dir, id));
+            }
+            !expired
+        });
+        let expired = before - entries.len();
+        if expired > 0 {
+            tracing::debug!(expired, "deleted expired detached results");
+        }
+    }
+}
+
+/// Bytes of `owner`'s results
+fn used(entries: &BTreeMap<u64, Entry>, owner: Option<u32>) -> u64 {
+    entries
+        .values()
+        .filter(|entry| entry.owner == owner)
+        .map(|entry| entry.bytes)
+        .sum()
+}
+
+fn path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.{EXTENSION}"))
+}
+
+/// Spool files in `dir` with their execution ids
+fn spool_files(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(listing) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    listing
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != EXTENSION {
+                return None;
+            }
+            let id = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((id, path))
+        })
+        .collect()
+}
+
+fn read(path: &Path) -> std::io::Result<(Spooled, std::fs::Metadata)> {
+    let bytes = std::fs::read(path)?;
+    let metadata = std::fs::metadata(path)?;
+    let spooled = protocol::decode(&bytes).map_err(std::io::Error::other)?;
+    Ok((spooled, metadata))
+}
+
+/// Create the file of an execution at `path`, which must not exist yet,
+/// returning its size
+fn create(path: &Path, spooled: &Spooled) -> std::io::Result<u64> {
+    let bytes = protocol::encode(spooled).map_err(std::io::Error::other)?;
+    storage::create(path, &bytes)?;
+    Ok(bytes.len() as u64)
+}
+
+/// Replace the file at `path` whole with `bytes`, so a reader sees either
+/// the old contents or the new, returning its size
+fn write(path: &Path, bytes: &[u8]) -> std::io::Result<u64> {
+    storage::replace(path, bytes)?;
+    Ok(bytes.len() as u64)
+}
@@ -0,0 +1,18 @@
+//! Replacing promoted standby workers, off the request path
+
+use crate::pool::WorkerPool;
+use crate::supervisor;
+use std::sync::Arc;
+
+/// Respawn a free worker as a standby whenever one was promoted
+///
+/// Under load no worker may be free for a while; the replacement then
+/// waits for the next one to finish.
+pub async fn run(pool: Arc<WorkerPool>) {
+    loop {
+        pool.standby_wanted().await;
+        // Respawning forks and waits for isolation setup
+        let refilling = Arc::clone(&pool);
+        supervisor::blocking(move || refilling.refill_standby()).await;
+    }
+}
@@ -0,0 +1,274 @@
+//! Writing the daemon's on-disk state so a crash never leaves it torn
+//!
+//! Two shapes of state live on disk: the audit log, a file of JSON lines
+//! only ever appended to, and spooled results, files replaced whole.
+//!
+//! A [`JsonlWriter`] writes each line with a single `write` on a file
+//! opened for appending and syncs it before saying it was written, so a
+//! line it acknowledged is on disk whole. A crash mid-line leaves only a
+//! partial last line, which [`JsonlWriter::open`] cuts off before anything
+//! more is appended.
+//!
+//! [`replace`] and [`create`] write a file whole under a staging name next
+//! to it, sync it, move it into place and sync the directory, so the name
+//! leads to the old contents or the new, never to part of either. What a
+//! crash leaves under a staging name is removed by [`remove_staged`], and
+//! a file that still cannot be read is moved aside by [`quarantine`] for
+//! someone to look at rather than stopping the daemon.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Appended to a file's name while it is being written
+pub const STAGING_SUFFIX: &str = ".tmp";
+
+/// Directory, next to the files, that unreadable ones are moved into
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Bytes read at a time looking back for the end of the last whole line
+const SCAN_CHUNK: usize = 64 * 1024;
+
+/// Appends JSON lines to a file, each one whole or not at all
+#[derive(Debug)]
+pub struct JsonlWriter {
+    file: File,
+    /// Bytes of whole lines in the file
+    len: u64,
+    /// Bytes left before a simulated crash
+    #[cfg(feature = "testing")]
+    crash_after: Option<usize>,
+}
+
+impl JsonlWriter {
+    /// Open `path` for appending, creating it with mode 0600, and cut off a
+    /// partial line a crash left at its end
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error opening, reading or truncating the file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        let end = file.metadata()?.len();
+        let len = whole_lines(&file, end)?;
+        if len < end {
+            tracing::warn!(
+                path = %path.display(),
+                bytes = end - len,
+                "cut off a partial line at the end"
+            );
+            file.set_len(len)?;
+            file.sync_data()?;
+        }
+        Ok(Self {
+            file,
+            len,
+            #[cfg(feature = "testing")]
+            crash_after: None,
+        })
+    }
+
+    /// Append `line`, which must not hold a newline, and sync it
+    ///
+    /// Once this returns the line is on disk. On an error the file is cut
+    /// back to the lines before it.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidInput` if `line` holds a newline, and any I/O error writing or
+    /// syncing it.
+    pub fn append(&mut self, line: &str) -> io::Result<()> {
+        if line.contains('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a JSON line cannot hold a newline",
+            ));
+        }
+        let mut bytes = Vec::with_capacity(line.len() + 1);
+        bytes.extend_from_slice(line.as_bytes());
+        bytes.push(b'\n');
+
+        #[cfg(feature = "testing")]
+        if let Some(budget) = self.crash_after.as_mut() {
+            let torn = (*budget).min(bytes.len());
+            *budget -= torn;
+            if torn < bytes.len() {
+                // As a killed process would: what got out stays, nothing is undone
+                self.file.write_all(&bytes[..torn])?;
+                return Err(io::Error::other("simulated crash"));
+            }
+        }
+
+        match self
+            .file
+            .write_all(&bytes)
+            .and_then(|()| self.file.sync_data())
+        {
+            Ok(()) => {
+                self.len += bytes.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                // Out of space part way, say; the next line starts clean
+                let _ = self.file.set_len(self.len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Let the next `bytes` bytes through, then write no more and fail, as
+    /// if the process had been killed part way through a line
+    #[cfg(feature = "testing")]
+    pub const fn crash_after(&mut self, bytes: usize) {
+        self.crash_after = Some(bytes);
+    }
+}
+
+/// Length of the whole lines at the start of the first `end` bytes of `file`
+fn whole_lines(file: &File, end: u64) -> io::Result<u64> {
+    let mut chunk = vec![0; SCAN_CHUNK];
+    let mut to = end;
+    while to > 0 {
+        let from = to.saturating_sub(SCAN_CHUNK as u64);
+        let window = &mut chunk[..usize::try_from(to - from).unwrap_or(SCAN_CHUNK)];
+        file.read_exact_at(window, from)?;
+        if let Some(newline) = window.iter().rposition(|&b| b == b'\n') {
+            return Ok(from + newline as u64 + 1);
+        }
+        to = from;
+    }
+    Ok(0)
+}
+
+/// Replace the file at `path` with `bytes`, so it holds either all of the
+/// old contents or all of the new, even across a crash
+///
+/// # Errors
+///
+/// Any I/O error staging the new contents or renaming them into place; the old
+/// contents are then left as they were.
+pub fn replace(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let staged = stage(path, bytes)?;
+    if let Err(e) = std::fs::rename(&staged, path) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(e);
+    }
+    sync_parent(path)
+}
+
+/// Create the file at `path` holding `bytes`, failing if it exists
+///
+/// Like [`replace`], the file appears whole or not at all.
+///
+/// # Errors
+///
+/// `AlreadyExists` if `path` exists, and any I/O error staging or linking the
+/// file.
+pub fn create(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let staged = stage(path, bytes)?;
+    // A link fails where a rename would replace
+    let linked = std::fs::hard_link(&staged, path);
+    let _ = std::fs::remove_file(&staged);
+    linked?;
+    sync_parent(path)
+}
+
+/// Stage `bytes` for `path` and stop after `offset` of them, as a crash
+/// part way through [`replace`] would
+///
+/// # Errors
+///
+/// Any I/O error creating or writing the staged file.
+#[cfg(feature = "testing")]
+pub fn crash_replacing(path: &Path, bytes: &[u8], offset: usize) -> io::Result<()> {
+    let mut file = File::create(staging_path(path))?;
+    file.write_all(&bytes[..offset.min(bytes.len())])
+}
+
+/// Write `bytes` under the staging name of `path` and sync them
+fn stage(path: &Path, bytes: &[u8]) -> io::Result<PathBuf> {
+    let staged = staging_path(path);
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&staged)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        });
+    match written {
+        Ok(()) => Ok(staged),
+        Err(e) => {
+            let _ = std::fs::remove_file(&staged);
+            Err(e)
+        }
+    }
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(STAGING_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Sync the directory `path` is in, so a name just made there lasts
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+/// Remove what crashes left under staging names in `dir`, returning how
+/// many files that was
+#[must_use]
+pub fn remove_staged(dir: &Path) -> usize {
+    let Ok(listing) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    listing
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.to_string_lossy().ends_with(STAGING_SUFFIX))
+        .filter(|path| match std::fs::remove_file(path) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(
+                    path = %path.display(),
+                    error = %e,
+                    "failed to remove a staged file"
+                );
+                false
+            }
+        })
+        .count()
+}
+
+/// Move the file at `path` into [`QUARANTINE_DIR`] next to it, returning
+/// where it went
+///
+/// # Errors
+///
+/// Any I/O error creating the quarantine directory or moving the file.
+pub fn quarantine(path: &Path) -> io::Result<PathBuf> {
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(QUARANTINE_DIR);
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    let moved = dir.join(path.file_name().unwrap_or_default());
+    std::fs::rename(path, &moved)?;
+    sync_parent(path)?;
+    Ok(moved)
+}
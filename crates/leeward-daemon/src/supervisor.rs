@@ -0,0 +1,235 @@
+//! Supervision of the daemon's long-lived tasks
+//!
+//! Background tasks run under a [`Supervisor`], which starts a task again
+//! when it panics: the panic is logged with its message and counted in
+//! `task_restarts_total`, and the task comes back after the restart
+//! backoff, doubled for each panic in a row up to [`MAX_BACKOFF`].
+//!
+//! A task that panics `task_failure_limit` times in a row, each time within
+//! `task_failure_window` of starting, is left stopped instead. The daemon
+//! goes into maintenance, refusing executions and failing health probes
+//! until it is restarted, and fires a `task_failing` alert, rather than
+//! answering as if it were whole. A task that returns is done.
+//!
+//! All of this needs panics to unwind, as the release profile has them do:
+//! built with `panic = "abort"`, the first panic ends the daemon.
+
+use crate::config::RestartPolicy;
+use crate::metrics::Metrics;
+use crate::pool::WorkerPool;
+use crate::server::EventBus;
+use leeward_core::protocol::{AlertEvent, AlertKind, AlertState, Event};
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Longest wait before a restart
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Every supervised task, by the name its metrics carry
+pub const TASKS: [&str; 10] = [
+    "alerts",
+    "liveness",
+    "metrics",
+    "reconcile",
+    "reload",
+    "shm_sweep",
+    "snapshot",
+    "standby",
+    "sweep",
+    "timeslice",
+];
+
+/// Restarts the daemon's tasks when they panic
+#[derive(Clone)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    pool: Arc<WorkerPool>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    faults: Arc<Faults>,
+}
+
+impl Supervisor {
+    pub fn new(
+        policy: RestartPolicy,
+        pool: Arc<WorkerPool>,
+        events: EventBus,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            policy,
+            pool,
+            events,
+            metrics,
+            faults: Arc::default(),
+        }
+    }
+
+    /// Run the future `start` returns as `task`, calling it again for a
+    /// fresh one each time the task panics
+    pub fn spawn<F, Fut>(&self, task: &'static str, mut start: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let started = Instant::now();
+                let Err(e) = tokio::spawn(supervisor.armed(task, start())).await else {
+                    return;
+                };
+                // Cancelled only as the runtime shuts down
+                if !e.is_panic() {
+                    return;
+                }
+                let payload = e.into_panic();
+                let message = panic_message(&*payload);
+
+                failures = if started.elapsed() < supervisor.policy.failure_window {
+                    failures + 1
+                } else {
+                    1
+                };
+                if supervisor.policy.failure_limit != 0
+                    && failures >= supervisor.policy.failure_limit
+                {
+                    supervisor.trip(task, failures, message);
+                    return;
+                }
+
+                let backoff = supervisor.backoff(failures);
+                tracing::error!(
+                    task,
+                    panic = message,
+                    failures,
+                    ?backoff,
+                    "daemon task panicked; restarting it"
+                );
+                supervisor.metrics.task_restarted(task);
+                tokio::time::sleep(backoff).await;
+            }
+        });
+    }
+
+    /// Wait before restarting a task that panicked `failures` times in a row
+    fn backoff(&self, failures: u32) -> Duration {
+        self.policy
+            .backoff
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+
+    /// Leave `task` stopped and put the daemon in maintenance
+    fn trip(&self, task: &'static str, failures: u32, message: &str) {
+        tracing::error!(
+            task,
+            panic = message,
+            failures,
+            "daemon task keeps panicking; leaving it stopped and the daemon in maintenance"
+        );
+        let reason =
+            format!("task {task} panicked {failures} times in a row, last with: {message}");
+        self.pool.enter_maintenance(&reason);
+        self.metrics.alert_fired(AlertKind::TaskFailing);
+
+        let alert = AlertEvent {
+            alert: AlertKind::TaskFailing,
+            state: AlertState::Firing,
+            value: u64::from(failures),
+            threshold: u64::from(self.policy.failure_limit),
+        };
+        // Fails only when nobody is subscribed
+        let _ = self.events.send(Event {
+            message: format!("daemon in maintenance: {reason}"),
+            ..Event::alert(alert)
+        });
+    }
+
+    /// `run`, panicking whenever a panic is injected into `task`
+    fn armed<Fut>(&self, task: &'static str, run: Fut) -> impl Future<Output = ()> + Send + 'static
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let faults = Arc::clone(&self.faults);
+        async move {
+            tokio::select! {
+                () = run => {}
+                () = faults.hit(task) => panic!("injected panic in {task}"),
+            }
+        }
+    }
+
+    /// Make `task` panic the next `times` times it runs
+    #[cfg(feature = "testing")]
+    pub fn inject_panic(&self, task: &str, times: u32) {
+        *self
+            .faults
+            .pending
+            .lock()
+            .entry(task.to_owned())
+            .or_default() += times;
+        self.faults.injected.notify_waiters();
+    }
+}
+
+/// Panics injected into tasks by tests, by task
+#[derive(Debug, Default)]
+struct Faults {
+    pending: Mutex<BTreeMap<String, u32>>,
+    injected: Notify,
+}
+
+impl Faults {
+    /// Wait for a panic injected into `task`, taking it
+    async fn hit(&self, task: &str) {
+        loop {
+            let injected = self.injected.notified();
+            tokio::pin!(injected);
+            injected.as_mut().enable();
+            if self.take(task) {
+                return;
+            }
+            injected.await;
+        }
+    }
+
+    fn take(&self, task: &str) -> bool {
+        match self.pending.lock().get_mut(task) {
+            Some(pending) if *pending > 0 => {
+                *pending -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Run `f` on the blocking pool, passing a panic on to the supervised
+/// task awaiting it; `None` if the runtime is shutting down
+pub async fn blocking<T, F>(f: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => Some(value),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => None,
+    }
+}
+
+/// What a task panicked with, when it panicked with a message
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("a value that is not a message")
+}
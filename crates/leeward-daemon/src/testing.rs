@@ -0,0 +1,416 @@
+//! In-process daemon for integration tests
+//!
+//! [`TestDaemon`] runs the real server on a socket in a fresh temporary
+//! directory, on its own Tokio runtime, and tears everything down on drop:
+//!
+//! ```no_run
+//! use leeward_core::SandboxConfig;
+//! use leeward_daemon::testing::TestDaemon;
+//!
+//! let daemon = TestDaemon::builder()
+//!     .workers(2)
+//!     .sandbox(SandboxConfig::minimal_for_tests())
+//!     .spawn()
+//!     .unwrap();
+//! let mut client = daemon.client().unwrap();
+//! ```
+//!
+//! # Mock workers
+//!
+//! With [`TestDaemonBuilder::mock`], workers spawn no process and answer
+//! every execution with its code on stdout and exit code 0, after an
+//! optional latency. This exercises everything between the socket and the
+//! worker for real: the protocol and both wire encodings, queueing and the
+//! fast path, in-flight limits, request deadlines, upload bookkeeping,
+//! detached results, events and metrics. It exercises nothing inside the
+//! worker: there is no isolation, no interpreter, no resource limits or
+//! timeouts, no input or output files and no network, and stdin, args and
+//! env are ignored. Tests of sandbox behaviour need real workers, and should skip when
+//! [`TestDaemon::live_workers`] is 0 because the host cannot run them.
+
+use crate::logging::LogControl;
+use crate::metrics::{self, HttpAnswer, Metrics};
+use crate::pool::WorkerPool;
+use crate::server::EventBus;
+use crate::supervisor::Supervisor;
+use crate::{Daemon, DaemonConfig};
+use leeward_core::SandboxConfig;
+use leeward_core::client::Client;
+use leeward_core::protocol::{Event, EventKind};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// Runtime threads; inline executions may take all but one
+const RUNTIME_THREADS: usize = 4;
+
+/// Pause between checks while waiting on an event or metric
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Tells apart the directories of daemons started by one process
+static NEXT_DAEMON: AtomicU32 = AtomicU32::new(0);
+
+/// Configures a [`TestDaemon`]
+#[derive(Debug, Clone)]
+pub struct TestDaemonBuilder {
+    config: DaemonConfig,
+    mock: Option<Duration>,
+    take_over: Option<PathBuf>,
+    log: Option<LogControl>,
+}
+
+impl Default for TestDaemonBuilder {
+    fn default() -> Self {
+        let config = DaemonConfig {
+            num_workers: 2,
+            sandbox_config: SandboxConfig::minimal_for_tests(),
+            metrics_enabled: false,
+            ..DaemonConfig::default()
+        };
+        Self {
+            config,
+            mock: None,
+            take_over: None,
+            log: None,
+        }
+    }
+}
+
+impl TestDaemonBuilder {
+    /// Workers in the pool (default 2)
+    #[must_use]
+    pub const fn workers(mut self, workers: usize) -> Self {
+        self.config.num_workers = workers;
+        self
+    }
+
+    /// Sandbox config for the workers (default
+    /// [`SandboxConfig::minimal_for_tests`])
+    #[must_use]
+    pub fn sandbox(mut self, sandbox: SandboxConfig) -> Self {
+        self.config.sandbox_config = sandbox;
+        self
+    }
+
+    /// Use mock workers that echo code back at once; see the module docs
+    #[must_use]
+    pub const fn mock(self) -> Self {
+        self.mock_latency(Duration::ZERO)
+    }
+
+    /// Use mock workers that echo code back after `latency`, to hold them
+    /// busy for queueing and limit tests
+    #[must_use]
+    pub const fn mock_latency(mut self, latency: Duration) -> Self {
+        self.mock = Some(latency);
+        self
+    }
+
+    /// Take over the socket of the daemon serving `socket`, as
+    /// `leeward-daemon --takeover` does, instead of binding a new one
+    #[must_use]
+    pub fn take_over(mut self, socket: impl Into<PathBuf>) -> Self {
+        self.take_over = Some(socket.into());
+        self
+    }
+
+    /// Let clients change the log filter through `log`, from
+    /// [`logging::init`](crate::logging::init) with a writer the test reads
+    #[must_use]
+    pub fn log_control(mut self, log: LogControl) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Change any other daemon setting; the socket path is always replaced,
+    /// and the result spool goes in the test's own directory unless set
+    #[must_use]
+    pub fn config(mut self, configure: impl FnOnce(&mut DaemonConfig)) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    /// Start the daemon, which accepts connections once this returns
+    ///
+    /// Real workers that fail to start are left `Dead`, as in the daemon;
+    /// check [`TestDaemon::live_workers`].
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error starting the daemon or binding its socket.
+    pub fn spawn(self) -> io::Result<TestDaemon> {
+        let Self {
+            mut config,
+            mock,
+            take_over,
+            log,
+        } = self;
+        let dir = std::env::temp_dir().join(format!(
+            "leeward-test-daemon-{}-{}",
+            std::process::id(),
+            NEXT_DAEMON.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        config.socket_path = take_over
+            .clone()
+            .unwrap_or_else(|| dir.join("leeward.sock"));
+        if config.spool_dir == DaemonConfig::default().spool_dir {
+            config.spool_dir = dir.join("spool");
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(RUNTIME_THREADS)
+            .thread_name("leeward-test-daemon")
+            .enable_all()
+            .build()?;
+        let entered = runtime.enter();
+
+        let socket = config.socket_path.clone();
+        let health_min_idle = config.health_min_idle;
+        let listener = if take_over.is_some() {
+            None
+        } else {
+            let listener = leeward_core::socket::bind(&socket).map_err(io::Error::other)?;
+            listener.set_nonblocking(true)?;
+            Some(tokio::net::UnixListener::from_std(listener)?)
+        };
+
+        let daemon = match mock {
+            Some(latency) => {
+                let workers = config.num_workers + config.standby_workers;
+                let pool = WorkerPool::mock(workers, config.sandbox_config.clone(), latency);
+                Daemon::with_pool(config, pool)
+            }
+            None => Daemon::new(config, None),
+        };
+        let daemon = match log {
+            Some(log) => daemon.with_log_control(log),
+            None => daemon,
+        };
+        let listener = match listener {
+            Some(listener) => listener,
+            None => daemon.take_over(&socket)?,
+        };
+        let pool = Arc::clone(&daemon.pool);
+        let events = daemon.events.clone();
+        let metrics = Arc::clone(&daemon.metrics);
+        let supervisor = daemon.supervisor.clone();
+        let served = runtime.spawn(async move {
+            if let Err(e) = daemon.serve(listener).await {
+                tracing::error!(error = %e, "test daemon stopped");
+            }
+        });
+        drop(entered);
+
+        Ok(TestDaemon {
+            runtime: Some(runtime),
+            served,
+            dir,
+            socket,
+            pool,
+            events,
+            metrics,
+            supervisor,
+            health_min_idle,
+        })
+    }
+}
+
+/// A daemon serving a temporary socket from this process, stopped on drop
+pub struct TestDaemon {
+    runtime: Option<tokio::runtime::Runtime>,
+    /// The server, which ends after a handover
+    served: tokio::task::JoinHandle<()>,
+    dir: PathBuf,
+    socket: PathBuf,
+    pool: Arc<WorkerPool>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    supervisor: Supervisor,
+    health_min_idle: usize,
+}
+
+impl TestDaemon {
+    /// Configure a new test daemon
+    #[must_use]
+    pub fn builder() -> TestDaemonBuilder {
+        TestDaemonBuilder::default()
+    }
+
+    /// The socket the daemon listens on
+    #[must_use]
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Open a new connection
+    ///
+    /// # Errors
+    ///
+    /// As [`Client::connect`].
+    pub fn client(&self) -> leeward_core::Result<Client> {
+        Client::connect(&self.socket)
+    }
+
+    /// Workers not `Dead`; 0 means the host cannot run real workers
+    #[must_use]
+    pub fn live_workers(&self) -> usize {
+        let status = self.pool.status();
+        status.total - status.dead
+    }
+
+    /// Hold the pool's locks for `duration` on a thread of its own, as a
+    /// wedged pool would, returning once they are held
+    pub fn wedge(&self, duration: Duration) -> std::thread::JoinHandle<()> {
+        let pool = Arc::clone(&self.pool);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            pool.wedge(duration, || {
+                let _ = tx.send(());
+            });
+        });
+        let _ = rx.recv();
+        thread
+    }
+
+    /// Make the background task named `task`, such as `snapshot`, panic
+    /// the next `times` times it runs
+    pub fn inject_panic(&self, task: &str, times: u32) {
+        self.supervisor.inject_panic(task, times);
+    }
+
+    /// Record events published from now on, as subscribers would get them
+    #[must_use]
+    pub fn subscribe(&self) -> EventRecorder {
+        EventRecorder {
+            events: self.events.subscribe(),
+            seen: Vec::new(),
+        }
+    }
+
+    /// Every metric in the Prometheus text format, as the endpoint serves
+    /// them
+    #[must_use]
+    pub fn metrics(&self) -> String {
+        self.metrics.render(&self.pool.snapshot())
+    }
+
+    /// What the metrics endpoint answers a GET of `target`, e.g.
+    /// `/healthz?min_idle=2`
+    #[must_use]
+    pub fn http(&self, target: &str) -> HttpAnswer {
+        let head = format!("GET {target} HTTP/1.1\r\n\r\n");
+        metrics::answer_http(
+            &self.metrics,
+            &head,
+            &self.pool.snapshot(),
+            self.health_min_idle,
+        )
+    }
+
+    /// Current value of one series, named with its labels as exported, e.g.
+    /// `leeward_executions_total{path="queued"}`
+    #[must_use]
+    pub fn metric(&self, series: &str) -> Option<f64> {
+        self.metrics().lines().find_map(|line| {
+            let (name, value) = line.rsplit_once(' ')?;
+            (name == series).then(|| value.parse().ok())?
+        })
+    }
+
+    /// Wait up to `timeout` for the server to stop, as it does once it has
+    /// handed its socket over and closed its last connection
+    pub fn wait_stopped(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.served.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        true
+    }
+
+    /// Wait up to `timeout` for `series` to reach `value`, returning its
+    /// last value
+    pub fn wait_for_metric(&self, series: &str, value: f64, timeout: Duration) -> Option<f64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let current = self.metric(series);
+            let reached = current.is_some_and(|current| (current - value).abs() < f64::EPSILON);
+            if reached || Instant::now() >= deadline {
+                return current;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+        // Executions still running end by their timeout and release their worker
+        for pid in self
+            .pool
+            .worker_info()
+            .into_iter()
+            .filter_map(|info| info.pid)
+        {
+            // SAFETY: Killing and reaping worker processes this daemon spawned
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+                libc::waitpid(pid, std::ptr::null_mut(), 0);
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Events published by a [`TestDaemon`] since [`TestDaemon::subscribe`]
+#[derive(Debug)]
+pub struct EventRecorder {
+    events: broadcast::Receiver<Event>,
+    seen: Vec<Event>,
+}
+
+impl EventRecorder {
+    /// Wait up to `timeout` for an event of `kind`, returning the first
+    /// one not returned before
+    pub fn wait_for(&mut self, kind: EventKind, timeout: Duration) -> Option<Event> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.collect();
+            if let Some(at) = self.seen.iter().position(|event| event.kind == kind) {
+                return Some(self.seen.remove(at));
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Events received and not yet returned by [`Self::wait_for`]
+    pub fn drain(&mut self) -> Vec<Event> {
+        self.collect();
+        std::mem::take(&mut self.seen)
+    }
+
+    fn collect(&mut self) {
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.seen.push(event),
+                Err(TryRecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "test event recorder fell behind");
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return,
+            }
+        }
+    }
+}
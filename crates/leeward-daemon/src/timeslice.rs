@@ -0,0 +1,34 @@
+//! Batch time slicing, applied on a timer off the request path
+
+use crate::pool::WorkerPool;
+use crate::server::EventBus;
+use leeward_core::protocol::Event;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Time slice the pool's batch executions every `interval`
+///
+/// Each freeze and thaw is logged and sent to event subscribers.
+pub async fn run(pool: Arc<WorkerPool>, interval: Duration, events: EventBus) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+
+        for (preemption, frozen) in pool.time_slice() {
+            tracing::info!(
+                worker_id = preemption.worker_id,
+                execution_id = preemption.execution_id,
+                preempted_count = preemption.preempted_count,
+                frozen_ms = preemption.frozen_ms,
+                "batch execution {}",
+                if frozen { "frozen" } else { "thawed" }
+            );
+
+            // Fails only when nobody is subscribed
+            let _ = events.send(Event::preemption(preemption, frozen));
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! Input files uploaded ahead of an execution
+//!
+//! Each upload is staged in a sealed memfd of its declared length, so
+//! chunks can land in any order and nothing touches the disk. An upload
+//! counts against its client uid's quota from the moment it begins, and is
+//! dropped once it has gone `upload_ttl` without a chunk, commit or
+//! execution touching it. Beginning again with the same name, length and
+//! hash as an unfinished upload resumes it, which is how a client picks up
+//! after a dropped connection. Uploads, files and all, survive a handover
+//! to a new daemon process; see [`crate::handover`].
+
+use leeward_core::protocol::{ErrorKind, HandedUpload, Response, UploadRange, UploadStatus};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bytes hashed per read when an upload is committed
+const HASH_CHUNK: usize = 64 * 1024;
+
+/// Uploads staged for every client
+#[derive(Debug)]
+pub struct Uploads {
+    quota_bytes: u64,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    uploads: BTreeMap<u64, Upload>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    /// Client uid that began it; peers of unknown uid share one quota
+    owner: Option<u32>,
+    name: String,
+    sha256: String,
+    total_len: u64,
+    reusable: bool,
+    file: Arc<File>,
+    /// Ranges received so far, start -> end, merged and disjoint
+    received: BTreeMap<u64, u64>,
+    stage: Stage,
+    touched: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Receiving,
+    /// Being hashed; chunks and other commits are refused
+    Verifying,
+    Committed,
+}
+
+/// Why an upload request failed
+#[derive(Debug)]
+pub enum UploadError {
+    /// Beginning the upload would take its client past the quota
+    Quota { used_bytes: u64, quota_bytes: u64 },
+    /// Anything else, with the message for the client
+    Request(String),
+}
+
+impl UploadError {
+    pub fn response(self) -> Response {
+        match self {
+            Self::Quota {
+                used_bytes,
+                quota_bytes,
+            } => Response::Error {
+                message: format!(
+                    "uploads would use {used_bytes} bytes of this client's {quota_bytes} byte quota"
+                ),
+                kind: ErrorKind::QuotaExceeded {
+                    used_bytes,
+                    quota_bytes,
+                },
+            },
+            Self::Request(message) => Response::error(message),
+        }
+    }
+}
+
+impl Upload {
+    fn status(&self, id: u64) -> UploadStatus {
+        let mut missing = Vec::new();
+        let mut next = 0;
+        for (&start, &end) in &self.received {
+            if start > next {
+                missing.push(UploadRange {
+                    start: next,
+                    end: start,
+                });
+            }
+            next = end;
+        }
+        if next < self.total_len {
+            missing.push(UploadRange {
+                start: next,
+                end: self.total_len,
+            });
+        }
+
+        UploadStatus {
+            id,
+            name: self.name.clone(),
+            total_len: self.total_len,
+            received_bytes: self.received.iter().map(|(start, end)| end - start).sum(),
+            missing,
+            committed: self.stage == Stage::Committed,
+        }
+    }
+
+    /// Note `[start, end)` as received, merging it with its neighbours
+    fn receive(&mut self, mut start: u64, mut end: u64) {
+        if start == end {
+            return;
+        }
+        let touching: Vec<(u64, u64)> = self
+            .received
+            .range(..=end)
+            .rev()
+            .take_while(|&(_, &other_end)| other_end >= start)
+            .map(|(&other_start, &other_end)| (other_start, other_end))
+            .collect();
+        for (other_start, other_end) in touching {
+            self.received.remove(&other_start);
+            start = start.min(other_start);
+            end = end.max(other_end);
+        }
+        self.received.insert(start, end);
+    }
+}
+
+impl Uploads {
+    /// Uploads limited to `quota_bytes` per client (0 = disabled), each
+    /// kept `ttl` after it was last touched
+    pub fn new(quota_bytes: u64, ttl: Duration) -> Self {
+        Self {
+            quota_bytes,
+            ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Start an upload, or resume the owner's unfinished one of the same
+    /// name, length and hash
+    pub fn begin(
+        &self,
+        owner: Option<u32>,
+        name: String,
+        total_len: u64,
+        sha256: &str,
+        reusable: bool,
+    ) -> Result<UploadStatus, UploadError> {
+        if self.quota_bytes == 0 {
+            return Err(UploadError::Request(
+                "uploads are disabled on this daemon".into(),
+            ));
+        }
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(UploadError::Request(format!(
+                "upload {name:?}: sha256 must be 64 hex digits"
+            )));
+        }
+        let sha256 = sha256.to_ascii_lowercase();
+        leeward_core::workspace::check_paths([name.as_str()])
+            .map_err(|e| UploadError::Request(e.to_string()))?;
+
+        let mut state = self.state.lock();
+        self.sweep_locked(&mut state);
+        if let Some((&id, upload)) = state.uploads.iter_mut().find(|(_, upload)| {
+            upload.owner == owner
+                && upload.name == name
+                && upload.total_len == total_len
+                && upload.sha256 == sha256
+                && upload.reusable == reusable
+        }) {
+            upload.touched = Instant::now();
+            return Ok(upload.status(id));
+        }
+
+        let used_bytes: u64 = state
+            .uploads
+            .values()
+            .filter(|upload| upload.owner == owner)
+            .map(|upload| upload.total_len)
+            .sum();
+        if used_bytes.saturating_add(total_len) > self.quota_bytes {
+            drop(state);
+            return Err(UploadError::Quota {
+                used_bytes,
+                quota_bytes: self.quota_bytes,
+            });
+        }
+
+        let file = staging_file(total_len)
+            .map_err(|e| UploadError::Request(format!("upload {name:?}: failed to stage: {e}")))?;
+        state.next_id += 1;
+        let id = state.next_id;
+        let upload = Upload {
+            owner,
+            name,
+            sha256,
+            total_len,
+            reusable,
+            file: Arc::new(file),
+            received: BTreeMap::new(),
+            stage: Stage::Receiving,
+            touched: Instant::now(),
+        };
+        let status = upload.status(id);
+        state.uploads.insert(id, upload);
+        drop(state);
+        Ok(status)
+    }
+
+    /// Write `data` at `offset` of upload `id`
+    pub fn chunk(
+        &self,
+        owner: Option<u32>,
+        id: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<UploadStatus, UploadError> {
+        let file = {
+            let mut state = self.state.lock();
+            let upload = find(&mut state, owner, id)?;
+            if upload.stage != Stage::Receiving {
+                return Err(UploadError::Request(format!(
+                    "upload {id} is already committed"
+                )));
+            }
+            let end = offset
+                .checked_add(data.len() as u64)
+                .filter(|&end| end <= upload.total_len);
+            if end.is_none() {
+                return Err(UploadError::Request(format!(
+                    "chunk at {offset} of {} bytes runs past the end of upload {id} ({} bytes)",
+                    data.len(),
+                    upload.total_len
+                )));
+            }
+            upload.touched = Instant::now();
+            let file = Arc::clone(&upload.file);
+            drop(state);
+            file
+        };
+
+        // Written outside the lock; a chunk only counts once it is in place
+        file.write_all_at(data, offset).map_err(|e| {
+            UploadError::Request(format!("upload {id}: failed to stage chunk: {e}"))
+        })?;
+
+        let mut state = self.state.lock();
+        let upload = find(&mut state, owner, id)?;
+        upload.receive(offset, offset + data.len() as u64);
+        let status = upload.status(id);
+        drop(state);
+        Ok(status)
+    }
+
+    /// Check upload `id` against its hash, making it usable once it
+    /// matches and discarding it otherwise
+    ///
+    /// Hashes the whole upload, so call it off the async runtime.
+    pub fn commit(&self, owner: Option<u32>, id: u64) -> Result<UploadStatus, UploadError> {
+        let (file, total_len) = {
+            let mut state = self.state.lock();
+            let upload = find(&mut state, owner, id)?;
+            match upload.stage {
+                Stage::Committed => return Ok(upload.status(id)),
+                Stage::Verifying => {
+                    return Err(UploadError::Request(format!(
+                        "upload {id} is already being committed"
+                    )));
+                }
+                Stage::Receiving => {}
+            }
+            let status = upload.status(id);
+            if status.received_bytes < upload.total_len {
+                return Err(UploadError::Request(format!(
+                    "upload {id} is missing {} of its {} bytes",
+                    upload.total_len - status.received_bytes,
+                    upload.total_len
+                )));
+            }
+            upload.stage = Stage::Verifying;
+            let staged = (Arc::clone(&upload.file), upload.total_len);
+            drop(state);
+            staged
+        };
+
+        let actual = hash(&file, total_len);
+
+        let mut state = self.state.lock();
+        let upload = find(&mut state, owner, id)?;
+        match actual {
+            Ok(actual) if actual == upload.sha256 => {
+                upload.stage = Stage::Committed;
+                upload.touched = Instant::now();
+                let status = upload.status(id);
+                drop(state);
+                Ok(status)
+            }
+            Ok(actual) => {
+                state.uploads.remove(&id);
+                drop(state);
+                Err(UploadError::Request(format!(
+                    "upload {id} has sha256 {actual}, not the one it was begun with; discarded"
+                )))
+            }
+            Err(e) => {
+                upload.stage = Stage::Receiving;
+                drop(state);
+                Err(UploadError::Request(format!(
+                    "upload {id}: failed to hash: {e}"
+                )))
+            }
+        }
+    }
+
+    /// The files of committed uploads `uploads` (path, id) of `owner`, for
+    /// one execution
+    ///
+    /// Nothing is taken unless every upload is usable; single-use uploads
+    /// are then gone.
+    pub fn take(
+        &self,
+        owner: Option<u32>,
+        uploads: &[(String, u64)],
+    ) -> Result<Vec<(String, Arc<File>)>, String> {
+        let mut state = self.state.lock();
+        let mut files = Vec::with_capacity(uploads.len());
+        for (path, id) in uploads {
+            let upload = find(&mut state, owner, *id)
+                .map_err(|_| format!("input file {path:?}: no upload {id}"))?;
+            if upload.stage != Stage::Committed {
+                return Err(format!("input file {path:?}: upload {id} is not committed"));
+            }
+            files.push((path.clone(), Arc::clone(&upload.file)));
+        }
+
+        for (_, id) in uploads {
+            let Some(upload) = state.uploads.get_mut(id) else {
+                continue;
+            };
+            if upload.reusable {
+                upload.touched = Instant::now();
+            } else {
+                state.uploads.remove(id);
+            }
+        }
+        drop(state);
+        Ok(files)
+    }
+
+    /// Up to `max` uploads with their files, most recently touched first,
+    /// for handing over to a new daemon
+    ///
+    /// An upload being committed is handed over uncommitted.
+    pub fn export(&self, max: usize) -> (Vec<HandedUpload>, Vec<Arc<File>>) {
+        let state = self.state.lock();
+        let mut uploads: Vec<(&u64, &Upload)> = state.uploads.iter().collect();
+        uploads.sort_by_key(|(_, upload)| std::cmp::Reverse(upload.touched));
+        let (handed, files) = uploads
+            .into_iter()
+            .take(max)
+            .map(|(&id, upload)| {
+                let handed = HandedUpload {
+                    id,
+                    owner: upload.owner,
+                    name: upload.name.clone(),
+                    sha256: upload.sha256.clone(),
+                    total_len: upload.total_len,
+                    reusable: upload.reusable,
+                    received: upload
+                        .received
+                        .iter()
+                        .map(|(&start, &end)| UploadRange { start, end })
+                        .collect(),
+                    committed: upload.stage == Stage::Committed,
+                };
+                (handed, Arc::clone(&upload.file))
+            })
+            .unzip();
+        drop(state);
+        (handed, files)
+    }
+
+    /// Take on uploads handed over by the daemon this one replaced, keeping
+    /// their ids
+    pub fn adopt(&self, uploads: Vec<HandedUpload>, files: Vec<File>) {
+        let mut state = self.state.lock();
+        for (handed, file) in uploads.into_iter().zip(files) {
+            state.next_id = state.next_id.max(handed.id);
+            let upload = Upload {
+                owner: handed.owner,
+                name: handed.name,
+                sha256: handed.sha256,
+                total_len: handed.total_len,
+                reusable: handed.reusable,
+                file: Arc::new(file),
+                received: handed
+                    .received
+                    .iter()
+                    .map(|range| (range.start, range.end))
+                    .collect(),
+                stage: if handed.committed {
+                    Stage::Committed
+                } else {
+                    Stage::Receiving
+                },
+                touched: Instant::now(),
+            };
+            state.uploads.insert(handed.id, upload);
+        }
+    }
+
+    /// Drop uploads untouched for longer than the TTL
+    pub fn sweep(&self) {
+        self.sweep_locked(&mut self.state.lock());
+    }
+
+    fn sweep_locked(&self, state: &mut State) {
+        let before = state.uploads.len();
+        state
+            .uploads
+            .retain(|_, upload| upload.touched.elapsed() <= self.ttl);
+        let expired = before - state.uploads.len();
+        if expired > 0 {
+            tracing::debug!(expired, "dropped expired uploads");
+        }
+    }
+}
+
+/// Upload `id`, if `owner` began it
+fn find(state: &mut State, owner: Option<u32>, id: u64) -> Result<&mut Upload, UploadError> {
+    state
+        .uploads
+        .get_mut(&id)
+        .filter(|upload| upload.owner == owner)
+        .ok_or_else(|| UploadError::Request(format!("no upload {id}; it may have expired")))
+}
+
+/// A memfd of exactly `len` bytes that can never be resized
+fn staging_file(len: u64) -> std::io::Result<File> {
+    use memfd::{FileSeal, MemfdOptions};
+
+    let memfd = MemfdOptions::default()
+        .allow_sealing(true)
+        .create("leeward-upload")
+        .map_err(std::io::Error::other)?;
+    memfd.as_file().set_len(len)?;
+    memfd
+        .add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow, FileSeal::SealSeal])
+        .map_err(std::io::Error::other)?;
+    Ok(memfd.into_file())
+}
+
+/// Hex-encoded SHA-256 of the first `len` bytes of `file`
+fn hash(file: &File, len: u64) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_CHUNK];
+    let mut offset = 0;
+    while offset < len {
+        let take = usize::try_from(len - offset).map_or(HASH_CHUNK, |left| left.min(HASH_CHUNK));
+        file.read_exact_at(&mut buf[..take], offset)?;
+        hasher.update(&buf[..take]);
+        offset += take as u64;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
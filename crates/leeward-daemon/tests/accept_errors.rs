@@ -0,0 +1,75 @@
+//! Running out of descriptors pauses accepting rather than ending the server
+
+use leeward_core::protocol::{self, Request, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Set the soft descriptor limit, returning the old one
+fn set_fd_limit(soft: libc::rlim_t) -> libc::rlim_t {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit and setrlimit with a valid struct
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &raw mut limit), 0);
+        let old = limit.rlim_cur;
+        limit.rlim_cur = soft;
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &raw const limit), 0);
+        old
+    }
+}
+
+/// One past the highest descriptor open in this process
+fn fd_ceiling() -> libc::rlim_t {
+    std::fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .max()
+        .map_or(0, |fd: libc::rlim_t| fd + 1)
+}
+
+fn ping(stream: &mut UnixStream) -> Response {
+    let body = protocol::encode(&Request::Ping).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+#[test]
+fn accepting_resumes_once_descriptors_are_free() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+    let mut warm = UnixStream::connect(daemon.socket()).unwrap();
+    assert!(matches!(ping(&mut warm), Response::Pong));
+
+    // Take every descriptor below the limit but one, and that one goes to
+    // the client's end, leaving none for the daemon to accept with
+    let saved = set_fd_limit(fd_ceiling() + 8);
+    let mut filler = Vec::new();
+    while let Ok(file) = File::open("/dev/null") {
+        filler.push(file);
+    }
+    filler.pop();
+    let stream = UnixStream::connect(daemon.socket());
+    let series = "leeward_accept_errors_total";
+    let failed = daemon.wait_for_metric(series, 1.0, Duration::from_secs(5));
+    drop(filler);
+    set_fd_limit(saved);
+
+    assert!(failed.is_some_and(|count| count >= 1.0), "{failed:?}");
+    let mut stream = stream.unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert!(matches!(ping(&mut stream), Response::Pong));
+    assert!(matches!(ping(&mut warm), Response::Pong));
+}
@@ -0,0 +1,81 @@
+//! Parts of a request the daemon changes or ignores are listed in the
+//! response instead of silently applied
+
+use leeward_core::DurationSecs;
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::{Adjustment, ExecuteResponse, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+fn daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .mock()
+        .config(|config| config.max_timeout = DurationSecs::from_secs(30))
+        .spawn()
+        .unwrap()
+}
+
+fn execute(daemon: &TestDaemon, builder: RequestBuilder) -> ExecuteResponse {
+    let request = Request::Execute(builder.build().unwrap());
+    match daemon.client().unwrap().request(&request).unwrap() {
+        Response::Execute(response) => response,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn over_limit_timeout_is_capped_and_noted() {
+    let daemon = daemon();
+    let response = execute(
+        &daemon,
+        RequestBuilder::new("pass").timeout(Duration::from_secs(300)),
+    );
+    assert!(response.success, "{response:?}");
+
+    let [adjustment] = response.adjustments.as_slice() else {
+        panic!("expected one adjustment: {:?}", response.adjustments);
+    };
+    assert_eq!(
+        (
+            adjustment.field.as_str(),
+            adjustment.requested.as_str(),
+            adjustment.applied.as_str()
+        ),
+        ("timeout", "300s", "30s")
+    );
+    assert!(adjustment.reason.contains("(max_timeout)"), "{adjustment}");
+}
+
+#[test]
+fn requests_within_limits_are_not_adjusted() {
+    let daemon = daemon();
+    let response = execute(
+        &daemon,
+        RequestBuilder::new("pass").timeout(Duration::from_secs(30)),
+    );
+    assert_eq!(response.adjustments, Vec::<Adjustment>::new());
+}
+
+#[test]
+fn ignored_options_are_noted() {
+    let daemon = daemon();
+    let response = execute(
+        &daemon,
+        RequestBuilder::new("true")
+            .interpreter(Interpreter::Sh)
+            .soft_timeout_traceback(true)
+            .max_connections(4),
+    );
+
+    let fields: Vec<&str> = response
+        .adjustments
+        .iter()
+        .map(|adjustment| adjustment.field.as_str())
+        .collect();
+    assert_eq!(fields, ["soft_timeout_traceback", "max_connections"]);
+    assert!(
+        response.adjustments[1]
+            .reason
+            .contains("networking is disabled")
+    );
+}
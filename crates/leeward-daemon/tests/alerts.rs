@@ -0,0 +1,116 @@
+//! Saturating the pool fires one queue-depth alert and resolves it once
+
+use leeward_core::DurationSecs;
+use leeward_daemon::testing::TestDaemon;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long each mock execution holds the single worker
+const LATENCY: Duration = Duration::from_millis(500);
+
+/// Concurrent requests; all but one wait in the queue
+const CLIENTS: usize = 4;
+
+const EXECUTE: &str = r#"{"type": "Execute", "code": "pass", "files": []}"#;
+
+/// Start a one-worker daemon alerting at a queue depth of 2
+fn start_daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(1)
+        .mock_latency(LATENCY)
+        .config(|config| {
+            config.alert_queue_depth = 2;
+            config.alert_queue_wait = DurationSecs::ZERO;
+            config.alert_worker_dead_count = 0;
+            config.alert_sample_interval = DurationSecs::from_millis(50);
+            config.alert_clear_samples = 3;
+        })
+        .spawn()
+        .unwrap()
+}
+
+fn connect(socket: &Path) -> BufReader<UnixStream> {
+    let stream = UnixStream::connect(socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .unwrap();
+    BufReader::new(stream)
+}
+
+fn send_line(reader: &mut BufReader<UnixStream>, line: &str) {
+    let stream = reader.get_mut();
+    stream.write_all(line.as_bytes()).unwrap();
+    stream.write_all(b"\n").unwrap();
+}
+
+/// Read one JSON line, or `None` once the read timeout passes
+fn read_line(reader: &mut BufReader<UnixStream>) -> Option<Value> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => panic!("daemon closed the connection"),
+        Ok(_) => Some(serde_json::from_str(&line).unwrap()),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => None,
+        Err(e) => panic!("read failed: {e}"),
+    }
+}
+
+fn execute(socket: &Path, line: &str) -> Value {
+    let mut reader = connect(socket);
+    send_line(&mut reader, line);
+    read_line(&mut reader).expect("no response to execute")
+}
+
+#[test]
+fn saturation_fires_and_resolves_once() {
+    let daemon = start_daemon();
+    let socket = daemon.socket().to_path_buf();
+
+    let mut events = connect(&socket);
+    send_line(&mut events, r#"{"type": "Subscribe", "kinds": ["alert"]}"#);
+    assert_eq!(read_line(&mut events).unwrap()["type"], "Subscribed");
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let socket = socket.clone();
+            std::thread::spawn(move || execute(&socket, EXECUTE))
+        })
+        .collect();
+    for client in clients {
+        let response = client.join().unwrap();
+        assert_eq!(
+            response["success"], true,
+            "queued request failed: {response}"
+        );
+    }
+
+    // Collect until the episode resolves, then make sure nothing else follows
+    let mut states = Vec::new();
+    events
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    while let Some(event) = read_line(&mut events) {
+        assert_eq!(event["type"], "Event");
+        assert_eq!(event["kind"], "alert");
+        assert_eq!(
+            event["alert"]["alert"], "queue_depth",
+            "unexpected alert: {event}"
+        );
+        states.push(event["alert"]["state"].as_str().unwrap().to_string());
+        if states.last().is_some_and(|state| state == "resolved") {
+            events
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+        }
+    }
+
+    assert_eq!(states, ["firing", "resolved"]);
+    assert_eq!(
+        daemon.metric(r#"leeward_alerts_total{kind="queue_depth"}"#),
+        Some(1.0)
+    );
+}
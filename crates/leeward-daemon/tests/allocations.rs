@@ -0,0 +1,98 @@
+//! The daemon's side of a request allocates a bounded number of times once
+//! its buffers are warm
+//!
+//! A binary of its own, since it installs a counting global allocator and
+//! counts everything the process allocates apart from the client's thread.
+
+use leeward_core::protocol::{Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Requests sent before counting, to warm buffers and the runtime
+const WARMUP: u64 = 200;
+
+/// Requests counted
+const REQUESTS: u64 = 1000;
+
+/// Allocations allowed per request on the daemon's side: the decoded
+/// request, its journal and task, and the result
+const BUDGET: u64 = 10;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Allocations made by this thread
+    static OWN: Cell<u64> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+// SAFETY: Defers to the system allocator, only counting calls
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        // SAFETY: Forwarded as given
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: Forwarded as given
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        // SAFETY: Forwarded as given
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+fn count() {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    // Gone once the thread is being torn down
+    let _ = OWN.try_with(|own| own.set(own.get() + 1));
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Allocations made by every other thread, which is the daemon's
+fn others() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed) - OWN.with(Cell::get)
+}
+
+#[test]
+fn warm_requests_stay_within_the_allocation_budget() {
+    for fast_path in [false, true] {
+        let daemon = TestDaemon::builder()
+            .workers(1)
+            .mock()
+            .config(|config| config.fast_path = fast_path)
+            .spawn()
+            .unwrap();
+        let mut client = daemon.client().unwrap();
+        let request = Request::Execute(RequestBuilder::new("print('hello')").build().unwrap());
+        let mut send = || {
+            let Response::Execute(response) = client.request(&request).unwrap() else {
+                panic!("not an execute response");
+            };
+            assert!(response.success, "{response:?}");
+        };
+
+        for _ in 0..WARMUP {
+            send();
+        }
+        let before = others();
+        for _ in 0..REQUESTS {
+            send();
+        }
+        let per_request = (others() - before) / REQUESTS;
+        eprintln!("fast path {fast_path}: {per_request} allocations per request");
+        assert!(
+            per_request <= BUDGET,
+            "fast path {fast_path}: {per_request} allocations per request, budget {BUDGET}"
+        );
+    }
+}
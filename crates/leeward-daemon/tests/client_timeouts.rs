@@ -0,0 +1,126 @@
+//! A client that gives up on a request never reads its late response as
+//! the answer to a later one: it reconnects first, keeping its defaults
+
+use leeward_core::LeewardError;
+use leeward_core::client::Client;
+use leeward_core::protocol::{Adjustment, ExecuteDefaults, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+/// How long each mock execution takes
+const LATENCY: Duration = Duration::from_millis(40);
+
+/// Timeouts cycled through, some shorter and some longer than [`LATENCY`]
+const TIMEOUTS: [Option<Duration>; 5] = [
+    Some(Duration::from_millis(5)),
+    Some(Duration::from_millis(30)),
+    None,
+    Some(Duration::from_millis(45)),
+    Some(Duration::from_secs(5)),
+];
+
+fn timed_out(error: &LeewardError) -> bool {
+    matches!(error, LeewardError::Io(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+/// Run distinct code under each timeout in turn, checking every result
+/// that arrives is the one asked for; returns (answered, timed out)
+fn race(client: &mut Client, tag: usize, rounds: usize) -> (usize, usize) {
+    let (mut answered, mut abandoned) = (0, 0);
+    for round in 0..rounds {
+        let code = format!("print({tag}, {round})");
+        client
+            .set_timeout(TIMEOUTS[(tag + round) % TIMEOUTS.len()])
+            .unwrap();
+        match client.execute(RequestBuilder::new(code.clone()).build().unwrap()) {
+            Ok(response) => {
+                let stdout = response.result.expect("no result").stdout;
+                assert_eq!(
+                    String::from_utf8_lossy(&stdout),
+                    code,
+                    "answer misattributed"
+                );
+                assert!(!client.is_poisoned());
+                answered += 1;
+            }
+            Err(e) => {
+                assert!(timed_out(&e), "{e}");
+                assert!(client.is_poisoned());
+                abandoned += 1;
+            }
+        }
+    }
+    (answered, abandoned)
+}
+
+#[test]
+fn results_of_abandoned_executions_never_answer_later_requests() {
+    let daemon = TestDaemon::builder()
+        .mock_latency(LATENCY)
+        .workers(4)
+        .spawn()
+        .unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|tag| {
+            let mut client = daemon.client().unwrap();
+            std::thread::spawn(move || race(&mut client, tag, 20))
+        })
+        .collect();
+    let (answered, abandoned) = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .fold((0, 0), |(a, b), (c, d)| (a + c, b + d));
+    assert!(
+        answered > 0 && abandoned > 0,
+        "{answered} answered, {abandoned} abandoned"
+    );
+}
+
+#[test]
+fn the_next_request_gets_its_own_answer_after_a_timeout() {
+    let daemon = TestDaemon::builder().mock_latency(LATENCY).spawn().unwrap();
+    let mut client = daemon.client().unwrap();
+    client.set_timeout(Some(Duration::from_millis(1))).unwrap();
+    let error = client
+        .execute(RequestBuilder::new("slow").build().unwrap())
+        .unwrap_err();
+    assert!(timed_out(&error), "{error}");
+    assert!(client.is_poisoned());
+
+    // The late result would be in the way of this answer on the old connection
+    client.set_timeout(None).unwrap();
+    std::thread::sleep(LATENCY * 2);
+    assert!(matches!(
+        client.request(&Request::Ping).unwrap(),
+        Response::Pong
+    ));
+    assert!(!client.is_poisoned());
+}
+
+#[test]
+fn defaults_are_set_again_on_the_new_connection() {
+    let daemon = TestDaemon::builder().mock_latency(LATENCY).spawn().unwrap();
+    let mut client = daemon
+        .client()
+        .unwrap()
+        .with_defaults(ExecuteDefaults::default().timeout(Duration::from_secs(5)))
+        .unwrap();
+    client.set_timeout(Some(Duration::from_millis(1))).unwrap();
+    assert!(
+        client
+            .execute(RequestBuilder::new("slow").build().unwrap())
+            .is_err()
+    );
+
+    client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+    let response = client
+        .execute(RequestBuilder::new("fast").build().unwrap())
+        .unwrap();
+    let filled: Vec<_> = response
+        .adjustments
+        .iter()
+        .map(|Adjustment { field, applied, .. }| (field.as_str(), applied.as_str()))
+        .collect();
+    assert_eq!(filled, [("timeout", "5s")]);
+    assert_eq!(response.result.unwrap().stdout, b"fast");
+}
@@ -0,0 +1,134 @@
+//! Code with a byte order mark, CRLF line endings or a foreign encoding
+//! declaration runs as its plain form would, noting what was normalized
+//!
+//! Mock workers echo the code they are given, so the output is the code
+//! the interpreter would have seen.
+
+use leeward_core::OutcomeCode;
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::{ExecuteResponse, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+
+const PLAIN: &str = "import sys\nprint('hi')\n";
+
+fn send(daemon: &TestDaemon, request: leeward_core::protocol::ExecuteRequest) -> ExecuteResponse {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::Execute(request))
+        .unwrap()
+    {
+        Response::Execute(response) => response,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn execute(daemon: &TestDaemon, builder: RequestBuilder) -> ExecuteResponse {
+    send(daemon, builder.build().unwrap())
+}
+
+fn stdout(response: &ExecuteResponse) -> &[u8] {
+    assert!(response.success, "{response:?}");
+    &response.result.as_ref().unwrap().stdout
+}
+
+#[test]
+fn every_shape_runs_as_the_plain_code() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let plain = execute(&daemon, RequestBuilder::new(PLAIN));
+    assert_eq!(stdout(&plain), PLAIN.as_bytes());
+    assert!(plain.adjustments.is_empty(), "{:?}", plain.adjustments);
+
+    for (variant, noted) in [
+        (
+            "\u{feff}import sys\nprint('hi')\n",
+            &["a leading byte order mark"][..],
+        ),
+        (
+            "import sys\r\nprint('hi')\r\n",
+            &["CRLF line endings on 2 lines"],
+        ),
+        (
+            "\u{feff}import sys\r\nprint('hi')\r\n",
+            &["a leading byte order mark", "CRLF line endings on 2 lines"],
+        ),
+    ] {
+        let response = execute(&daemon, RequestBuilder::new(variant));
+        assert_eq!(stdout(&response), PLAIN.as_bytes(), "{variant:?}");
+        let requested: Vec<_> = response
+            .adjustments
+            .iter()
+            .map(|adjustment| adjustment.requested.as_str())
+            .collect();
+        assert_eq!(requested, noted, "{variant:?}");
+        assert!(
+            response
+                .adjustments
+                .iter()
+                .all(|adjustment| adjustment.field == "code")
+        );
+    }
+}
+
+#[test]
+fn foreign_encoding_declarations_are_blanked() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+
+    let response = execute(
+        &daemon,
+        RequestBuilder::new("# -*- coding: latin-1 -*-\r\nprint('hi')\r\n"),
+    );
+    assert_eq!(stdout(&response), b"\nprint('hi')\n");
+    let requested: Vec<_> = response
+        .adjustments
+        .iter()
+        .map(|adjustment| adjustment.requested.as_str())
+        .collect();
+    assert_eq!(
+        requested,
+        ["CRLF line endings on 2 lines", "source encoding latin-1"]
+    );
+
+    let utf8 = "# -*- coding: utf-8 -*-\nprint('hi')\n";
+    let response = execute(&daemon, RequestBuilder::new(utf8));
+    assert_eq!(stdout(&response), utf8.as_bytes());
+    assert!(
+        response.adjustments.is_empty(),
+        "{:?}",
+        response.adjustments
+    );
+
+    // Shells have no declaration to blank
+    let shell = "# coding: latin-1\necho hi\n";
+    let response = execute(
+        &daemon,
+        RequestBuilder::new(shell).interpreter(Interpreter::Sh),
+    );
+    assert_eq!(stdout(&response), shell.as_bytes());
+}
+
+#[test]
+fn code_runs_as_given_when_normalization_is_off() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let crlf = "\u{feff}print('hi')\r\n";
+    let response = execute(&daemon, RequestBuilder::new(crlf).normalize_code(false));
+    assert_eq!(stdout(&response), crlf.as_bytes());
+    assert!(
+        response.adjustments.is_empty(),
+        "{:?}",
+        response.adjustments
+    );
+}
+
+#[test]
+fn nul_bytes_are_an_invalid_request() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    // Past the builder, which refuses them too
+    let mut request = RequestBuilder::new("print(1)").build().unwrap();
+    request.code = Some("print(1)\0".into());
+
+    let response = send(&daemon, request);
+    assert!(!response.success);
+    assert_eq!(response.outcome(), OutcomeCode::InvalidRequest);
+    assert!(response.error.unwrap().contains("NUL byte at offset 8"));
+}
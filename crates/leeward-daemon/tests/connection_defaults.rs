@@ -0,0 +1,200 @@
+//! Defaults a connection sets once fill in what its executions leave
+//! unset, each filled-in field listed with the execution's adjustments
+
+use leeward_core::client::Client;
+use leeward_core::protocol::{
+    Adjustment, ExecuteDefaults, ExecuteResponse, Request, RequestBuilder, RequestPriority,
+    Response,
+};
+use leeward_core::{ByteSize, DurationSecs};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+fn daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .mock()
+        .config(|config| config.max_timeout = DurationSecs::from_secs(30))
+        .spawn()
+        .unwrap()
+}
+
+fn set(client: &mut Client, defaults: ExecuteDefaults) -> Response {
+    client.request(&Request::SetDefaults { defaults }).unwrap()
+}
+
+fn execute(client: &mut Client, builder: RequestBuilder) -> ExecuteResponse {
+    match client
+        .request(&Request::Execute(builder.build().unwrap()))
+        .unwrap()
+    {
+        Response::Execute(response) => {
+            assert!(response.success, "{response:?}");
+            response
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+/// (field, requested, applied) of each adjustment
+fn applied(adjustments: &[Adjustment]) -> Vec<(&str, &str, &str)> {
+    adjustments
+        .iter()
+        .map(|adjustment| {
+            (
+                adjustment.field.as_str(),
+                adjustment.requested.as_str(),
+                adjustment.applied.as_str(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn defaults_fill_in_a_minimal_execute() {
+    let daemon = daemon();
+    let mut client = daemon.client().unwrap();
+    let defaults = ExecuteDefaults::default()
+        .timeout(Duration::from_secs(5))
+        .memory_limit(ByteSize::mib(256))
+        .priority(RequestPriority::Low)
+        .env("LANG", "C.UTF-8");
+    let Response::Defaults { adjustments, .. } = set(&mut client, defaults) else {
+        panic!("defaults not stored");
+    };
+    assert_eq!(adjustments, Vec::<Adjustment>::new());
+
+    let response = execute(&mut client, RequestBuilder::new("pass"));
+    assert_eq!(
+        applied(&response.adjustments),
+        [
+            ("timeout", "unset", "5s"),
+            ("memory_limit", "unset", "256MiB"),
+            ("priority", "Normal", "Low"),
+            ("env", "unset", "LANG"),
+        ]
+    );
+    assert!(
+        response
+            .adjustments
+            .iter()
+            .all(|adjustment| adjustment.reason.contains("default"))
+    );
+}
+
+#[test]
+fn fields_the_request_sets_win() {
+    let daemon = daemon();
+    let mut client = daemon.client().unwrap();
+    let defaults = ExecuteDefaults::default()
+        .timeout(Duration::from_secs(5))
+        .env("LANG", "C.UTF-8")
+        .env("TZ", "UTC");
+    set(&mut client, defaults);
+
+    let request = RequestBuilder::new("pass")
+        .timeout(Duration::from_secs(10))
+        .priority(RequestPriority::High)
+        .env("LANG", "en_US.UTF-8");
+    let response = execute(&mut client, request);
+    assert_eq!(applied(&response.adjustments), [("env", "unset", "TZ")]);
+}
+
+#[test]
+fn defaults_are_clamped_when_set_and_still_checked_per_request() {
+    let daemon = daemon();
+    let mut client = daemon.client().unwrap();
+    let Response::Defaults {
+        defaults,
+        adjustments,
+    } = set(
+        &mut client,
+        ExecuteDefaults::default().timeout(Duration::from_secs(300)),
+    )
+    else {
+        panic!("defaults not stored");
+    };
+    assert_eq!(defaults.timeout, Some(Duration::from_secs(30)));
+    assert_eq!(applied(&adjustments), [("timeout", "300s", "30s")]);
+
+    let response = execute(&mut client, RequestBuilder::new("pass"));
+    assert_eq!(
+        applied(&response.adjustments),
+        [("timeout", "unset", "30s")]
+    );
+
+    // A request's own timeout is capped as ever
+    let response = execute(
+        &mut client,
+        RequestBuilder::new("pass").timeout(Duration::from_secs(60)),
+    );
+    assert_eq!(applied(&response.adjustments), [("timeout", "60s", "30s")]);
+}
+
+#[test]
+fn unusable_defaults_are_refused_whole() {
+    let daemon = daemon();
+    let mut client = daemon.client().unwrap();
+    set(
+        &mut client,
+        ExecuteDefaults::default().timeout(Duration::from_secs(5)),
+    );
+
+    for refused in [
+        ExecuteDefaults::default().timezone("Not/AZone"),
+        ExecuteDefaults::default().env("A=B", "c"),
+        ExecuteDefaults::default().env("", "c"),
+    ] {
+        let response = set(&mut client, refused.clone());
+        assert!(
+            matches!(&response, Response::Error { message, .. } if message.contains("default")),
+            "{refused:?}: {response:?}"
+        );
+    }
+
+    let response = execute(&mut client, RequestBuilder::new("pass"));
+    assert_eq!(applied(&response.adjustments), [("timeout", "unset", "5s")]);
+}
+
+#[test]
+fn hello_and_empty_defaults_clear_them() {
+    let daemon = daemon();
+    let mut client = daemon.client().unwrap();
+    let defaults = ExecuteDefaults::default().timeout(Duration::from_secs(5));
+
+    set(&mut client, defaults.clone());
+    assert!(matches!(
+        client.request(&Request::Hello).unwrap(),
+        Response::Hello(_)
+    ));
+    assert_eq!(
+        execute(&mut client, RequestBuilder::new("pass")).adjustments,
+        Vec::<Adjustment>::new()
+    );
+
+    set(&mut client, defaults);
+    set(&mut client, ExecuteDefaults::default());
+    assert_eq!(
+        execute(&mut client, RequestBuilder::new("pass")).adjustments,
+        Vec::<Adjustment>::new()
+    );
+}
+
+#[test]
+fn defaults_belong_to_one_connection() {
+    let daemon = daemon();
+    let mut configured = daemon
+        .client()
+        .unwrap()
+        .with_defaults(ExecuteDefaults::default().timeout(Duration::from_secs(5)))
+        .unwrap();
+    // Asking for the daemon's info again must not clear them
+    assert!(configured.daemon_info().is_ok());
+    let response = execute(&mut configured, RequestBuilder::new("pass"));
+    assert_eq!(applied(&response.adjustments), [("timeout", "unset", "5s")]);
+
+    let mut other = daemon.client().unwrap();
+    assert_eq!(
+        execute(&mut other, RequestBuilder::new("pass")).adjustments,
+        Vec::<Adjustment>::new()
+    );
+}
@@ -0,0 +1,34 @@
+//! Debug-profile requests run outside the pool and are marked as such
+
+use leeward_core::protocol::{Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+
+fn execute(daemon: &TestDaemon, request: RequestBuilder) -> leeward_core::ExecutionResult {
+    let request = Request::Execute(request.build().unwrap());
+    let Response::Execute(response) = daemon.client().unwrap().request(&request).unwrap() else {
+        panic!("not an execute response");
+    };
+    assert!(response.success, "{response:?}");
+    response.result.unwrap()
+}
+
+#[test]
+fn results_say_whether_the_debug_profile_was_used() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+
+    let result = execute(&daemon, RequestBuilder::new("print(1)").debug_profile(true));
+    assert!(result.debug);
+    assert_eq!(result.stdout, b"print(1)");
+    // Not dispatched to the pool
+    assert_eq!(
+        daemon.metric(r#"leeward_executions_total{path="queued"}"#),
+        Some(0.0)
+    );
+    assert_eq!(
+        daemon.metric(r#"leeward_executions_total{path="inline"}"#),
+        Some(0.0)
+    );
+
+    let result = execute(&daemon, RequestBuilder::new("print(1)"));
+    assert!(!result.debug);
+}
@@ -0,0 +1,234 @@
+//! Detached executions: answered with an id at once, their results kept in
+//! the spool until fetched, expired or past a client's quota
+
+use leeward_core::protocol::{
+    ErrorKind, ExecuteResponse, InflightScope, Request, RequestBuilder, Response,
+};
+use leeward_core::{ByteSize, DurationSecs};
+use leeward_daemon::testing::TestDaemon;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+fn send(daemon: &TestDaemon, request: &Request) -> Response {
+    daemon.client().unwrap().request(request).unwrap()
+}
+
+/// Start a detached execution of `code`, returning its id
+fn detach(daemon: &TestDaemon, code: &str) -> u64 {
+    let request = Request::Execute(RequestBuilder::new(code).detach(true).build().unwrap());
+    match send(daemon, &request) {
+        Response::Detached { execution_id } => execution_id,
+        other => panic!("not detached: {other:?}"),
+    }
+}
+
+fn fetch(daemon: &TestDaemon, execution_id: u64) -> Response {
+    send(daemon, &Request::FetchResult { execution_id })
+}
+
+/// Fetch until the execution has finished
+fn fetch_finished(daemon: &TestDaemon, execution_id: u64) -> ExecuteResponse {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match fetch(daemon, execution_id) {
+            Response::Execute(response) => return response,
+            Response::Error {
+                kind: ErrorKind::Pending,
+                ..
+            } if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+}
+
+fn assert_gone(response: &Response) {
+    assert!(
+        matches!(
+            response,
+            Response::Error { kind: ErrorKind::Request, message } if message.contains("no result")
+        ),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn results_are_fetched_once_after_the_client_disconnects() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock_latency(Duration::from_millis(300))
+        .spawn()
+        .unwrap();
+    let code = "print('detached')";
+
+    let mut client = daemon.client().unwrap();
+    let request = Request::Execute(RequestBuilder::new(code).detach(true).build().unwrap());
+    let Response::Detached { execution_id } = client.request(&request).unwrap() else {
+        panic!("not detached");
+    };
+    drop(client);
+
+    let pending = fetch(&daemon, execution_id);
+    assert!(
+        matches!(
+            pending,
+            Response::Error {
+                kind: ErrorKind::Pending,
+                ..
+            }
+        ),
+        "{pending:?}"
+    );
+
+    let response = fetch_finished(&daemon, execution_id);
+    assert!(response.success, "{response:?}");
+    assert_eq!(response.result.unwrap().stdout, code.as_bytes());
+    assert_gone(&fetch(&daemon, execution_id));
+}
+
+#[test]
+fn unknown_executions_have_no_result() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+    assert_gone(&fetch(&daemon, 4242));
+}
+
+#[test]
+fn results_expire_after_their_ttl() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config(|config| config.spool_ttl = DurationSecs::from_secs(1))
+        .spawn()
+        .unwrap();
+
+    let fresh = detach(&daemon, "print('fresh')");
+    assert!(fetch_finished(&daemon, fresh).success);
+
+    let stale = detach(&daemon, "print('stale')");
+    std::thread::sleep(Duration::from_millis(2500));
+    assert_gone(&fetch(&daemon, stale));
+}
+
+#[test]
+fn results_survive_a_restart() {
+    let spool = std::env::temp_dir().join(format!("leeward-test-spool-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&spool);
+    let spawn = |spool: PathBuf| {
+        TestDaemon::builder()
+            .workers(1)
+            .mock()
+            .config(move |config| config.spool_dir = spool)
+            .spawn()
+            .unwrap()
+    };
+
+    let first = spawn(spool.clone());
+    let execution_id = detach(&first, "print('before')");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let path = spool.join(format!("{execution_id}.result"));
+    // Written whole by a rename, and far bigger than the placeholder
+    while std::fs::metadata(&path).unwrap().len() < 32 {
+        assert!(Instant::now() < deadline, "result never spooled");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    drop(first);
+
+    let second = spawn(spool.clone());
+    let response = fetch_finished(&second, execution_id);
+    assert_eq!(response.result.unwrap().stdout, b"print('before')");
+    assert!(detach(&second, "print('after')") > execution_id);
+
+    drop(second);
+    let _ = std::fs::remove_dir_all(&spool);
+}
+
+#[test]
+fn detaching_is_refused_when_disabled() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config(|config| config.spool_quota = ByteSize::ZERO)
+        .spawn()
+        .unwrap();
+
+    let Response::Hello(info) = send(&daemon, &Request::Hello) else {
+        panic!("not a hello");
+    };
+    assert!(!info.supports("exec.detach"));
+
+    let request = Request::Execute(
+        RequestBuilder::new("print(1)")
+            .detach(true)
+            .build()
+            .unwrap(),
+    );
+    let response = send(&daemon, &request);
+    assert!(
+        matches!(&response, Response::Error { message, .. } if message.contains("disabled")),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn results_past_the_quota_are_replaced_and_block_further_detaching() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config(|config| config.spool_quota = ByteSize::from_bytes(64))
+        .spawn()
+        .unwrap();
+
+    let big = format!("print('{}')", "x".repeat(1024));
+    let execution_id = detach(&daemon, &big);
+    // Refused once the replacement is written, which fills the quota
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let request = Request::Execute(
+            RequestBuilder::new("print(1)")
+                .detach(true)
+                .build()
+                .unwrap(),
+        );
+        match send(&daemon, &request) {
+            Response::Error {
+                kind: ErrorKind::QuotaExceeded { .. },
+                ..
+            } => break,
+            Response::Detached { execution_id } => {
+                assert!(Instant::now() < deadline, "quota never reached");
+                fetch_finished(&daemon, execution_id);
+            }
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    let replaced = fetch_finished(&daemon, execution_id);
+    assert!(!replaced.success);
+    assert!(replaced.error.unwrap().contains("quota"));
+    detach(&daemon, "print(1)");
+}
+
+#[test]
+fn detached_executions_hold_their_inflight_slot() {
+    let daemon = TestDaemon::builder()
+        .workers(2)
+        .mock_latency(Duration::from_millis(500))
+        .config(|config| config.max_inflight_per_peer_uid = 1)
+        .spawn()
+        .unwrap();
+
+    let execution_id = detach(&daemon, "print('slow')");
+    let request = Request::Execute(RequestBuilder::new("print(1)").build().unwrap());
+    match send(&daemon, &request) {
+        Response::Error {
+            kind: ErrorKind::Busy { scope, .. },
+            ..
+        } => assert_eq!(scope, InflightScope::PeerUid),
+        other => panic!("not turned away: {other:?}"),
+    }
+
+    assert!(fetch_finished(&daemon, execution_id).success);
+    let Response::Execute(response) = send(&daemon, &request) else {
+        panic!("not an execute response");
+    };
+    assert!(response.success, "{response:?}");
+}
@@ -0,0 +1,198 @@
+//! Replacing the interpreter on disk raises an alert, and draining the pool
+//! moves every worker, busy or not, onto the new binary
+
+use leeward_core::DurationSecs;
+use leeward_core::SandboxConfig;
+use leeward_core::protocol::{
+    AlertKind, AlertState, DEFAULT_PROFILE, EventKind, InterpreterStamp, Request, RequestBuilder,
+    Response,
+};
+use leeward_daemon::testing::{EventRecorder, TestDaemon};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Directory holding a fake interpreter, removed on drop
+struct Interpreter {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Interpreter {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("leeward-drain-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("python3");
+        std::fs::write(&path, "#!/bin/sh\necho old\n").unwrap();
+        Self { dir, path }
+    }
+
+    /// Replace the binary the way a package upgrade does, by renaming a
+    /// new file over it
+    fn upgrade(&self) -> InterpreterStamp {
+        let staged = self.dir.join("python3.new");
+        std::fs::write(&staged, "#!/bin/sh\necho new\n").unwrap();
+        std::fs::rename(&staged, &self.path).unwrap();
+        self.stamp()
+    }
+
+    fn stamp(&self) -> InterpreterStamp {
+        InterpreterStamp::of(&self.path).unwrap()
+    }
+}
+
+fn start(interpreter: &Interpreter, mock: bool) -> TestDaemon {
+    let sandbox = SandboxConfig {
+        python_path: interpreter.path.clone(),
+        ..SandboxConfig::minimal_for_tests()
+    };
+    let builder = TestDaemon::builder()
+        .workers(2)
+        .sandbox(sandbox)
+        .config(|config| {
+            config.alert_sample_interval = DurationSecs::from_millis(20);
+            config.alert_clear_samples = 2;
+        });
+    let builder = if mock {
+        builder.mock_latency(Duration::from_millis(300))
+    } else {
+        builder
+    };
+    builder.spawn().unwrap()
+}
+
+fn request(daemon: &TestDaemon, request: &Request) -> Response {
+    daemon.client().unwrap().request(request).unwrap()
+}
+
+fn interpreters(daemon: &TestDaemon) -> Vec<Option<InterpreterStamp>> {
+    match request(daemon, &Request::ListWorkers) {
+        Response::WorkerList { workers, .. } => {
+            workers.iter().map(|worker| worker.interpreter).collect()
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn drain(daemon: &TestDaemon) -> usize {
+    let drain = Request::DrainProfile {
+        profile: DEFAULT_PROFILE.into(),
+        reason: Some("interpreter upgraded".into()),
+    };
+    match request(daemon, &drain) {
+        Response::Drain(status) => {
+            assert_eq!(status.profile, DEFAULT_PROFILE);
+            assert!(!status.template_rebuilt);
+            status.scheduled
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+/// Wait for the drain to finish, returning how many workers it recycled
+fn wait_drained(daemon: &TestDaemon) -> u64 {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let Response::Status {
+            draining, drained, ..
+        } = request(daemon, &Request::Status)
+        else {
+            panic!("not a status response");
+        };
+        if draining == 0 {
+            return drained;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{draining} workers still draining"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn wait_alert(events: &mut EventRecorder, state: AlertState) {
+    let event = events
+        .wait_for(EventKind::Alert, Duration::from_secs(5))
+        .unwrap_or_else(|| panic!("no {state:?} alert"));
+    let alert = event.alert.unwrap();
+    assert_eq!(
+        (alert.alert, alert.state),
+        (AlertKind::InterpreterChanged, state)
+    );
+    if state == AlertState::Firing {
+        assert!(event.message.contains("drain"), "{}", event.message);
+    }
+}
+
+#[test]
+fn drained_workers_use_the_upgraded_interpreter() {
+    let interpreter = Interpreter::new("mock");
+    let daemon = start(&interpreter, true);
+    let mut events = daemon.subscribe();
+    let old = interpreter.stamp();
+    assert_eq!(interpreters(&daemon), [Some(old), Some(old)]);
+
+    let new = interpreter.upgrade();
+    assert_ne!(new, old);
+    wait_alert(&mut events, AlertState::Firing);
+
+    // A worker busy with an execution finishes it before it is recycled
+    let busy = std::thread::scope(|scope| {
+        let running = scope.spawn(|| {
+            let code = RequestBuilder::new("still running").build().unwrap();
+            request(&daemon, &Request::Execute(code))
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(drain(&daemon), 2);
+        running.join().unwrap()
+    });
+    let Response::Execute(busy) = busy else {
+        panic!("not an execute response: {busy:?}");
+    };
+    assert_eq!(busy.result.unwrap().stdout, b"still running");
+
+    assert_eq!(wait_drained(&daemon), 2);
+    assert_eq!(interpreters(&daemon), [Some(new), Some(new)]);
+    wait_alert(&mut events, AlertState::Resolved);
+}
+
+#[test]
+fn real_workers_respawn_from_the_upgraded_interpreter() {
+    let interpreter = Interpreter::new("real");
+    let daemon = start(&interpreter, false);
+    if daemon.live_workers() < 2 {
+        eprintln!("skipping: workers cannot start here");
+        return;
+    }
+    let old = interpreter.stamp();
+    assert_eq!(interpreters(&daemon), [Some(old), Some(old)]);
+
+    let new = interpreter.upgrade();
+    assert_eq!(drain(&daemon), 2);
+    assert_eq!(wait_drained(&daemon), 2);
+    assert_eq!(interpreters(&daemon), [Some(new), Some(new)]);
+    assert_eq!(daemon.live_workers(), 2);
+}
+
+#[test]
+fn unknown_profiles_are_refused() {
+    let interpreter = Interpreter::new("profiles");
+    let daemon = start(&interpreter, true);
+    let drain = Request::DrainProfile {
+        profile: "gpu".into(),
+        reason: None,
+    };
+    match request(&daemon, &drain) {
+        Response::Error { message, .. } => {
+            assert!(message.contains("unknown profile 'gpu'"), "{message}");
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert_eq!(wait_drained(&daemon), 0);
+}
@@ -0,0 +1,177 @@
+//! Fast-path and queued requests share the pool under load, neither
+//! starving the other, and both are counted the same way
+
+use leeward_core::protocol::{self, FAST_PATH_MAX_BYTES, Request, RequestBuilder, Response};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Clients of each kind, all running at once
+const CLIENTS: usize = 4;
+
+/// Requests sent by each client
+const REQUESTS: usize = 25;
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+    metrics_port: u16,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    /// Start a two-worker daemon with the fast path on
+    fn start() -> Option<Self> {
+        let dir = std::env::temp_dir().join(format!("leeward-fast-path-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("leeward.sock");
+        let metrics_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+            .env("LEEWARD_SOCKET", &socket)
+            .env("LEEWARD_WORKERS", "2")
+            .env("LEEWARD_FAST_PATH", "true")
+            .env("LEEWARD_METRICS_PORT", metrics_port.to_string())
+            // Inline slots come from the runtime's threads; don't depend on the host's CPUs
+            .env("TOKIO_WORKER_THREADS", "4")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Self {
+            child,
+            dir,
+            socket,
+            metrics_port,
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if UnixStream::connect(&daemon.socket).is_ok()
+                && TcpStream::connect(("127.0.0.1", daemon.metrics_port)).is_ok()
+            {
+                return Some(daemon);
+            }
+            if daemon.child.try_wait().unwrap().is_some() {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    /// Value of a metric, including its labels
+    fn metric(&self, name: &str) -> u64 {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.metrics_port)).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {name} in metrics:\n{body}"))
+            .parse()
+            .unwrap()
+    }
+}
+
+fn execute(socket: &Path, code: &str) -> Response {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .unwrap();
+
+    let request = Request::Execute(RequestBuilder::new(code).build().unwrap());
+    let body = protocol::encode(&request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+/// Whether the response carries a result from a worker
+const fn executed(response: &Response) -> bool {
+    matches!(response, Response::Execute(resp) if resp.result.is_some())
+}
+
+#[test]
+fn fast_and_queued_requests_share_the_pool() {
+    let Some(daemon) = Daemon::start() else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    let small = "import time; time.sleep(0.01)".to_owned();
+    let large = format!("{small}\n#{}", "x".repeat(FAST_PATH_MAX_BYTES));
+    let first = execute(&daemon.socket, &small);
+    if !executed(&first) {
+        eprintln!("skipping: no worker could run code here: {first:?}");
+        return;
+    }
+
+    // More clients than workers, so both kinds contend for them throughout,
+    // all started before any is joined
+    #[allow(clippy::needless_collect)]
+    let clients: Vec<_> = [&small, &large]
+        .into_iter()
+        .flat_map(|code| std::iter::repeat_n(code, CLIENTS))
+        .map(|code| {
+            let socket = daemon.socket.clone();
+            let code = code.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                for _ in 0..REQUESTS {
+                    let response = execute(&socket, &code);
+                    assert!(executed(&response), "request failed: {response:?}");
+                }
+                start.elapsed()
+            })
+        })
+        .collect();
+    let elapsed: Vec<Duration> = clients
+        .into_iter()
+        .map(|client| client.join().unwrap())
+        .collect();
+
+    // Every client finished; neither kind waited far longer than the other
+    let (fast, queued) = elapsed.split_at(CLIENTS);
+    let slowest = |times: &[Duration]| times.iter().max().copied().unwrap_or_default();
+    assert!(
+        slowest(queued) < slowest(fast) * 4 + Duration::from_secs(1),
+        "queued clients took {queued:?}, fast-path clients {fast:?}"
+    );
+    assert!(
+        slowest(fast) < slowest(queued) * 4 + Duration::from_secs(1),
+        "fast-path clients took {fast:?}, queued clients {queued:?}"
+    );
+
+    let inline = daemon.metric("leeward_executions_total{path=\"inline\"}");
+    let queued = daemon.metric("leeward_executions_total{path=\"queued\"}");
+    assert_eq!(inline + queued, (2 * CLIENTS * REQUESTS + 1) as u64);
+    assert!(inline > 0, "no request took the fast path");
+    assert!(
+        queued >= (CLIENTS * REQUESTS) as u64,
+        "a large request took the fast path"
+    );
+}
@@ -0,0 +1,199 @@
+//! A new daemon takes the socket over from a running one under load,
+//! without a single connection refused, and picks up its uploads
+
+use leeward_core::LeewardError;
+use leeward_core::client::Client;
+use leeward_core::protocol::{self, ErrorKind, HandoverRole, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[derive(Debug, Default)]
+struct Load {
+    ok: AtomicUsize,
+    refused: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// One execution on a fresh connection, as the CLI makes them
+fn execute(socket: &std::path::Path, load: &Load) {
+    let mut client = match Client::connect(socket) {
+        Ok(client) => client,
+        Err(LeewardError::Io(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            load.refused.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        Err(e) => panic!("connect failed: {e}"),
+    };
+    let request = Request::Execute(RequestBuilder::new("pass").build().unwrap());
+    match client.request(&request) {
+        Ok(Response::Execute(response)) if response.success => {
+            load.ok.fetch_add(1, Ordering::Relaxed);
+        }
+        other => {
+            eprintln!("execution failed: {other:?}");
+            load.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Stops the load when dropped, even by a failed assertion
+struct StopOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+fn status(client: &mut Client) -> Response {
+    client.request(&Request::Status).unwrap()
+}
+
+fn begin_upload() -> Request {
+    Request::UploadBegin {
+        name: "input.txt".into(),
+        total_len: 3,
+        sha256: SHA256_ABC.into(),
+        reusable: false,
+    }
+}
+
+/// Send `request` on a raw msgpack connection and read the answer
+fn request(stream: &mut UnixStream, request: &Request) -> Response {
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+    read_frame(stream)
+}
+
+#[test]
+fn takeover_under_load_refuses_no_connections() {
+    let old = TestDaemon::builder()
+        .mock_latency(Duration::from_millis(5))
+        .spawn()
+        .unwrap();
+    let socket = old.socket().to_path_buf();
+
+    // An upload half sent to the old daemon
+    let mut uploader = UnixStream::connect(&socket).unwrap();
+    let Response::Upload(upload) = request(&mut uploader, &begin_upload()) else {
+        panic!("upload not begun");
+    };
+    let chunk = Request::UploadChunk {
+        id: upload.id,
+        offset: 0,
+        data: b"ab".to_vec(),
+    };
+    assert!(matches!(
+        request(&mut uploader, &chunk),
+        Response::Upload(_)
+    ));
+
+    let load = Load::default();
+    let stop = AtomicBool::new(false);
+    // The new daemon outlives the load, so no execution is cut off by its
+    // shutdown
+    let _new = std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    execute(&socket, &load);
+                }
+            });
+        }
+        let stop = StopOnDrop(&stop);
+        std::thread::sleep(Duration::from_millis(200));
+
+        let new = TestDaemon::builder()
+            .mock()
+            .take_over(&socket)
+            .spawn()
+            .unwrap();
+
+        // Until the old daemon exits, both report the overlap
+        let Response::Status { handover, .. } = status(&mut new.client().unwrap()) else {
+            panic!("not a status response");
+        };
+        let handover = handover.expect("no handover in the new daemon's status");
+        assert_eq!(handover.role, HandoverRole::Successor);
+        assert_eq!(handover.peer_pid, Some(std::process::id()));
+        let Response::Status { handover, .. } = request(&mut uploader, &Request::Status) else {
+            panic!("not a status response");
+        };
+        assert_eq!(handover.unwrap().role, HandoverRole::Predecessor);
+
+        // The quiet connection is closed, and then the old daemon stops
+        expect_handed_over(&mut uploader);
+        assert!(
+            old.wait_stopped(Duration::from_secs(10)),
+            "old daemon still serving"
+        );
+
+        std::thread::sleep(Duration::from_millis(200));
+        drop(stop);
+
+        let Response::Status { handover, .. } = status(&mut new.client().unwrap()) else {
+            panic!("not a status response");
+        };
+        assert_eq!(handover, None);
+
+        // The upload carried over, id and received bytes included
+        let resumed = new.client().unwrap().request(&begin_upload()).unwrap();
+        let Response::Upload(resumed) = resumed else {
+            panic!("upload not resumed");
+        };
+        assert_eq!((resumed.id, resumed.received_bytes), (upload.id, 2));
+
+        let served = new.metric(r#"leeward_executions_total{path="queued"}"#);
+        assert!(served.is_some_and(|served| served > 0.0), "{served:?}");
+        new
+    });
+
+    assert_eq!(load.refused.load(Ordering::Relaxed), 0);
+    assert_eq!(load.failed.load(Ordering::Relaxed), 0);
+    assert!(load.ok.load(Ordering::Relaxed) > 0);
+}
+
+fn read_frame(stream: &mut UnixStream) -> Response {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+/// Wait for the goodbye a quiet connection of the old daemon gets, then EOF
+fn expect_handed_over(stream: &mut UnixStream) {
+    let response = read_frame(stream);
+    assert!(
+        matches!(
+            response,
+            Response::Error { kind: ErrorKind::HandedOver, ref message }
+                if message.contains("reconnect")
+        ),
+        "{response:?}"
+    );
+    assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+}
+
+#[test]
+fn json_connections_cannot_take_over() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+    stream.write_all(b"{\"type\":\"Handover\"}\n").unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    assert!(line.contains("needs a msgpack connection"), "{line}");
+
+    let Response::Status { handover, .. } = status(&mut daemon.client().unwrap()) else {
+        panic!("not a status response");
+    };
+    assert_eq!(handover, None);
+}
@@ -0,0 +1,148 @@
+//! The health probe flips unhealthy once idle workers fall under the
+//! threshold, estimates the wait of what queues, and recovers after the
+//! queue drains, over the socket and over HTTP alike
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+const LATENCY: Duration = Duration::from_millis(600);
+
+struct Health {
+    healthy: bool,
+    idle: usize,
+    queue_depth: usize,
+    est_wait_ms: u64,
+}
+
+fn daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(2)
+        .mock_latency(LATENCY)
+        .config(|config| config.status_refresh_interval = DurationSecs::from_millis(20))
+        .spawn()
+        .unwrap()
+}
+
+fn health(daemon: &TestDaemon, min_idle: Option<usize>) -> Health {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::Health { min_idle })
+        .unwrap()
+    {
+        Response::Health {
+            healthy,
+            idle,
+            queue_depth,
+            est_wait_ms,
+        } => Health {
+            healthy,
+            idle,
+            queue_depth,
+            est_wait_ms,
+        },
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+/// Poll the health until `done` holds, for up to five seconds
+fn wait_for(
+    daemon: &TestDaemon,
+    min_idle: Option<usize>,
+    done: impl Fn(&Health) -> bool,
+) -> Health {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let health = health(daemon, min_idle);
+        if done(&health) {
+            return health;
+        }
+        assert!(Instant::now() < deadline, "health never got there");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Run `count` executions at once, each on a connection of its own
+fn saturate(daemon: &TestDaemon, count: usize) -> Vec<std::thread::JoinHandle<()>> {
+    (0..count)
+        .map(|i| {
+            let mut client = daemon.client().unwrap();
+            std::thread::spawn(move || {
+                let request =
+                    Request::Execute(RequestBuilder::new(format!("job {i}")).build().unwrap());
+                match client.request(&request).unwrap() {
+                    Response::Execute(response) => assert!(response.success, "{response:?}"),
+                    other => panic!("unexpected response: {other:?}"),
+                }
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn health_flips_at_the_threshold_and_recovers() {
+    let daemon = daemon();
+    let idle = wait_for(&daemon, None, |health| health.idle == 2);
+    assert!(idle.healthy);
+    assert_eq!((idle.queue_depth, idle.est_wait_ms), (0, 0));
+    assert!(
+        !health(&daemon, Some(3)).healthy,
+        "2 idle workers cannot meet a threshold of 3"
+    );
+
+    let jobs = saturate(&daemon, 4);
+    let saturated = wait_for(&daemon, None, |health| {
+        health.queue_depth > 0 && health.est_wait_ms > 0
+    });
+    assert!(!saturated.healthy);
+    assert_eq!(saturated.idle, 0);
+    // Busy but alive is healthy enough for a threshold of none
+    assert!(health(&daemon, Some(0)).healthy);
+
+    for job in jobs {
+        job.join().unwrap();
+    }
+    let recovered = wait_for(&daemon, None, |health| health.healthy);
+    assert_eq!(recovered.idle, 2);
+    assert_eq!((recovered.queue_depth, recovered.est_wait_ms), (0, 0));
+}
+
+#[test]
+fn healthz_answers_503_until_the_queue_drains() {
+    let daemon = daemon();
+    wait_for(&daemon, None, |health| health.idle == 2);
+    let answer = daemon.http("/healthz");
+    assert_eq!(answer.status, 200);
+    assert_eq!(answer.content_type, "application/json");
+    assert!(answer.body.contains(r#""healthy":true"#), "{}", answer.body);
+    assert_eq!(daemon.http("/healthz?min_idle=3").status, 503);
+
+    let jobs = saturate(&daemon, 4);
+    wait_for(&daemon, None, |health| health.queue_depth > 0);
+    let answer = daemon.http("/healthz");
+    assert_eq!(answer.status, 503);
+    assert!(answer.body.contains(r#""idle":0"#), "{}", answer.body);
+    assert_eq!(daemon.http("/healthz?min_idle=0").status, 200);
+
+    for job in jobs {
+        job.join().unwrap();
+    }
+    wait_for(&daemon, None, |health| health.healthy);
+    assert_eq!(daemon.http("/healthz").status, 200);
+}
+
+#[test]
+fn healthz_refuses_a_bad_threshold_and_other_paths_get_the_metrics() {
+    let daemon = daemon();
+    assert_eq!(daemon.http("/healthz?min_idle=lots").status, 400);
+
+    let answer = daemon.http("/metrics");
+    assert_eq!(answer.status, 200);
+    assert!(
+        answer.body.contains("leeward_pool_snapshot_age_seconds"),
+        "{}",
+        answer.body
+    );
+}
@@ -0,0 +1,144 @@
+//! A daemon advertises the same features, languages and limits for the
+//! same config, and a fresh boot id each time it starts
+
+use leeward_core::escape::KernelFeature;
+use leeward_core::protocol::{DaemonInfo, PROTOCOL_VERSION, Request, Response, feature};
+use leeward_core::{ByteSize, DurationSecs};
+use leeward_daemon::testing::TestDaemon;
+
+/// A daemon configured the way the goldens below expect
+fn start() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(2)
+        .mock()
+        .config(|config| {
+            config.fast_path = true;
+            config.max_request_wall = DurationSecs::from_secs(90);
+        })
+        .spawn()
+        .unwrap()
+}
+
+fn info(daemon: &TestDaemon) -> DaemonInfo {
+    daemon.client().unwrap().daemon_info().unwrap().clone()
+}
+
+#[test]
+fn advertisement_matches_the_config() {
+    let info = info(&start());
+
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(
+        info.build.as_ref().map(|build| &build.version),
+        Some(&info.version)
+    );
+
+    let mut golden = vec![
+        "daemon.handover",
+        "daemon.log_control",
+        "events.alerts",
+        "events.policy_change",
+        "events.worker_died",
+        "events.worker_exited",
+        "exec.args",
+        "exec.batch",
+        "exec.debug",
+        "exec.defaults",
+        "exec.detach",
+        "exec.env",
+        "exec.explain",
+        "exec.fast_path",
+        "exec.files",
+        "exec.max_connections",
+        "exec.memory_limit",
+        "exec.priority",
+        "exec.profile",
+        "exec.shm",
+        "exec.soft_timeout_traceback",
+        "exec.stdin",
+        "exec.timezone",
+        "exec.uploads",
+        "history.policy_changes",
+        "pool.drain",
+        "pool.health",
+        "pool.recycle_stale",
+        "sandbox.seccomp",
+        "wire.json",
+    ];
+    if KernelFeature::Landlock.available() {
+        golden.push(feature::SANDBOX_LANDLOCK);
+        golden.sort_unstable();
+    }
+    assert_eq!(info.features.iter().collect::<Vec<_>>(), golden);
+
+    assert_eq!(info.languages.first().map(String::as_str), Some("python"));
+    assert!(info.languages.iter().any(|name| name == "sh"));
+
+    let limits = info.limits;
+    assert_eq!(limits.workers, 2);
+    assert_eq!(limits.fast_path_max_bytes, Some(1024));
+    assert_eq!(limits.max_request_wall, DurationSecs::from_secs(90));
+    assert_eq!(limits.tmp_size, ByteSize::mib(8));
+    assert_eq!(limits.default_timeout_ms, 10_000);
+    assert_eq!(limits.max_timeout_ms, None);
+    assert_eq!(limits.max_code_bytes, 1000 * 1024);
+    assert_eq!(limits.upload_quota, ByteSize::gib(1));
+    assert_eq!(limits.upload_ttl, DurationSecs::from_secs(600));
+}
+
+#[test]
+fn info_is_kept_per_connection_and_boot_id_per_start() {
+    let first = start();
+
+    // Asked once, then served from the connection without another request
+    let mut client = first.client().unwrap();
+    let info = client.daemon_info().unwrap().clone();
+    assert_eq!(client.daemon_info().unwrap(), &info);
+    assert!(matches!(
+        client.request(&Request::Ping).unwrap(),
+        Response::Pong
+    ));
+    assert_eq!(self::info(&first).boot_id, info.boot_id);
+    assert_ne!(info.boot_id, "");
+
+    let second = start();
+    assert_ne!(self::info(&second).boot_id, info.boot_id);
+}
+
+#[test]
+fn build_provenance_is_parseable() {
+    let info = info(&start());
+    let build = info.build.unwrap();
+
+    assert!(
+        build
+            .version
+            .split('.')
+            .all(|part| part.parse::<u32>().is_ok())
+    );
+    assert_ne!(build.git, "");
+    assert!(build.rustc.starts_with("rustc "), "{}", build.rustc);
+    assert_ne!(build.target, "");
+    assert!(["debug", "release"].contains(&build.profile.as_str()));
+    assert!(build.features.iter().any(|name| name == "testing"));
+    assert!(
+        build
+            .features
+            .iter()
+            .any(|name| name == "leeward-core/protocol")
+    );
+
+    // YYYY-MM-DDTHH:MM:SSZ
+    let fields: Vec<u32> = build
+        .built_at
+        .strip_suffix('Z')
+        .unwrap()
+        .split(['-', 'T', ':'])
+        .map(|field| field.parse().unwrap())
+        .collect();
+    assert_eq!(fields.len(), 6, "{}", build.built_at);
+    assert!((1..=12).contains(&fields[1]) && (1..=31).contains(&fields[2]));
+
+    assert!(info.kernel.is_some_and(|release| !release.is_empty()));
+}
@@ -0,0 +1,229 @@
+//! Idle connections cost little memory and are closed after the idle timeout
+
+use leeward_core::protocol::{self, ErrorKind, Request, Response};
+use std::io::{BufRead, BufReader, ErrorKind as IoErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Idle connections opened by the test, half msgpack and half JSON
+const CONNECTIONS: usize = 500;
+
+/// Allowed daemon RSS growth for all of them together
+const RSS_GROWTH_LIMIT: u64 = 8 * 1024 * 1024;
+
+const IDLE_TIMEOUT_MS: u64 = 5000;
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    metrics_port: u16,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    fn rss_bytes(&self) -> u64 {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.child.id())).unwrap();
+        let kib = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .unwrap();
+        kib * 1024
+    }
+
+    /// Value of an unlabelled metric
+    fn metric(&self, name: &str) -> u64 {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.metrics_port)).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {name} in metrics:\n{body}"))
+            .parse()
+            .unwrap()
+    }
+}
+
+/// Start a one-worker daemon with a short idle timeout
+fn start_daemon() -> Option<(Daemon, PathBuf)> {
+    let dir = std::env::temp_dir().join(format!("leeward-idle-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("leeward.sock");
+    let metrics_port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+        .env("LEEWARD_SOCKET", &socket)
+        .env("LEEWARD_WORKERS", "1")
+        .env(
+            "LEEWARD_IDLE_CONNECTION_TIMEOUT_MS",
+            IDLE_TIMEOUT_MS.to_string(),
+        )
+        .env("LEEWARD_METRICS_PORT", metrics_port.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut daemon = Daemon {
+        child,
+        dir,
+        metrics_port,
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        if UnixStream::connect(&socket).is_ok()
+            && TcpStream::connect(("127.0.0.1", metrics_port)).is_ok()
+        {
+            return Some((daemon, socket));
+        }
+        if daemon.child.try_wait().unwrap().is_some() {
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+fn send_frame(stream: &mut UnixStream, request: &Request) {
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+fn read_frame(stream: &mut UnixStream) -> Response {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+/// A connection that has made one request and gone quiet
+enum Idle {
+    Msgpack(UnixStream),
+    Json(BufReader<UnixStream>),
+}
+
+impl Idle {
+    fn open(socket: &PathBuf, json: bool) -> Self {
+        let mut stream = UnixStream::connect(socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        if json {
+            stream.write_all(b"{\"type\": \"Ping\"}\n").unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains("Pong"), "unexpected response: {line}");
+            Self::Json(reader)
+        } else {
+            send_frame(&mut stream, &Request::Ping);
+            assert!(matches!(read_frame(&mut stream), Response::Pong));
+            Self::Msgpack(stream)
+        }
+    }
+
+    /// Wait for the idle-timeout goodbye, then EOF
+    fn expect_reaped(self) {
+        let (response, mut stream) = match self {
+            Self::Msgpack(mut stream) => (read_frame(&mut stream), stream),
+            Self::Json(mut reader) => {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                (
+                    protocol::decode_json(line.as_bytes()).unwrap(),
+                    reader.into_inner(),
+                )
+            }
+        };
+        assert!(
+            matches!(
+                response,
+                Response::Error {
+                    kind: ErrorKind::IdleTimeout,
+                    ..
+                }
+            ),
+            "expected an idle timeout, got {response:?}"
+        );
+        assert_eq!(
+            stream.read(&mut [0u8; 1]).unwrap(),
+            0,
+            "connection left open"
+        );
+    }
+}
+
+#[test]
+fn idle_connections_are_cheap_and_reaped() {
+    let Some((daemon, socket)) = start_daemon() else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    // Warm up both code paths before taking the baseline
+    Idle::open(&socket, false);
+    Idle::open(&socket, true);
+    std::thread::sleep(Duration::from_millis(200));
+    let baseline = daemon.rss_bytes();
+
+    let mut subscriber = UnixStream::connect(&socket).unwrap();
+    send_frame(&mut subscriber, &Request::Subscribe { kinds: Vec::new() });
+    assert!(matches!(read_frame(&mut subscriber), Response::Subscribed));
+
+    let idle: Vec<_> = (0..CONNECTIONS)
+        .map(|i| Idle::open(&socket, i % 2 == 1))
+        .collect();
+    let growth = daemon.rss_bytes().saturating_sub(baseline);
+    assert!(
+        growth < RSS_GROWTH_LIMIT,
+        "{CONNECTIONS} idle connections grew RSS by {growth} bytes"
+    );
+    assert!(daemon.metric("leeward_connections_open") > CONNECTIONS as u64);
+
+    for connection in idle {
+        connection.expect_reaped();
+    }
+
+    // Subscriptions are exempt
+    subscriber.set_nonblocking(true).unwrap();
+    let pending = subscriber.read(&mut [0u8; 1]);
+    assert!(
+        matches!(&pending, Err(e) if e.kind() == IoErrorKind::WouldBlock),
+        "subscription was closed: {pending:?}"
+    );
+
+    // The daemon drops its side just after the client sees EOF
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while daemon.metric("leeward_connections_open") > 1 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(daemon.metric("leeward_connections_open"), 1);
+    assert!(daemon.metric("leeward_connections_idle_closed_total") >= CONNECTIONS as u64);
+}
@@ -0,0 +1,306 @@
+//! One client cannot take every worker: executions past its in-flight limit
+//! are turned away with `Busy`, while other clients keep being served
+
+use leeward_core::protocol::{
+    self, ErrorKind, InflightScope, PeerInflight, Request, RequestBuilder, Response,
+};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Uid of the second, well-behaved client
+const OTHER_UID: u32 = 65534;
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+    metrics_port: u16,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    fn start(name: &str, workers: usize, limits: &[(&str, &str)]) -> Option<Self> {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-inflight-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let socket = dir.join("leeward.sock");
+        let metrics_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+            .env("LEEWARD_SOCKET", &socket)
+            .env("LEEWARD_WORKERS", workers.to_string())
+            .env("LEEWARD_METRICS_PORT", metrics_port.to_string())
+            .envs(limits.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Self {
+            child,
+            dir,
+            socket,
+            metrics_port,
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if UnixStream::connect(&daemon.socket).is_ok()
+                && TcpStream::connect(("127.0.0.1", daemon.metrics_port)).is_ok()
+            {
+                return Some(daemon);
+            }
+            if daemon.child.try_wait().unwrap().is_some() {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    fn connect(&self) -> UnixStream {
+        let stream = UnixStream::connect(&self.socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(60)))
+            .unwrap();
+        stream
+    }
+
+    fn send(&self, request: &Request) -> Response {
+        let mut stream = self.connect();
+        write_request(&mut stream, request);
+        read_response(&mut stream)
+    }
+
+    /// Clients with executions in flight
+    fn inflight(&self) -> Vec<PeerInflight> {
+        match self.send(&Request::StatusDetailed) {
+            Response::StatusDetailed { inflight, .. } => inflight,
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    /// Value of a metric, labels included in `name`
+    fn metric(&self, name: &str) -> u64 {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.metrics_port)).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {name} in metrics:\n{body}"))
+            .parse()
+            .unwrap()
+    }
+}
+
+fn write_request(stream: &mut UnixStream, request: &Request) {
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+fn read_response(stream: &mut UnixStream) -> Response {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+fn execute_request(code: &str) -> Request {
+    Request::Execute(RequestBuilder::new(code).build().unwrap())
+}
+
+/// Whether the response carries a result from a worker
+const fn executed(response: &Response) -> bool {
+    matches!(response, Response::Execute(resp) if resp.result.is_some())
+}
+
+/// Run one JSON execution as `uid` and return the response line
+fn execute_as(uid: u32, socket: &Path) -> String {
+    let script = r#"
+import socket, sys
+s = socket.socket(socket.AF_UNIX)
+s.connect(sys.argv[1])
+s.sendall(b'{"type": "Execute", "code": "pass", "files": []}\n')
+print(s.makefile().readline(), end="")
+"#;
+    let output = Command::new("python3")
+        .args(["-c", script])
+        .arg(socket)
+        .uid(uid)
+        .gid(uid)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Wait until `daemon` reports `expected` executions in flight
+fn wait_for_inflight(daemon: &Daemon, expected: &[PeerInflight]) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while daemon.inflight() != expected {
+        assert!(
+            Instant::now() < deadline,
+            "in flight: {:?}",
+            daemon.inflight()
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn greedy_client_is_capped_while_another_proceeds() {
+    // SAFETY: geteuid has no failure modes
+    let uid = unsafe { libc::geteuid() };
+    if uid != 0 {
+        eprintln!("skipping: a second client uid needs root");
+        return;
+    }
+    let Some(daemon) = Daemon::start("greedy", 2, &[("LEEWARD_MAX_INFLIGHT_PER_PEER_UID", "1")])
+    else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+    std::fs::set_permissions(&daemon.socket, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+    let probe = daemon.send(&execute_request("pass"));
+    if !executed(&probe) {
+        eprintln!("skipping: no worker could run code here: {probe:?}");
+        return;
+    }
+    let pid = match daemon.send(&Request::ListWorkers) {
+        Response::WorkerList { workers, .. } => workers[0].pid.unwrap(),
+        other => panic!("unexpected response: {other:?}"),
+    };
+
+    // Idle workers are claimed in order, so a stopped worker 0 holds the
+    // greedy client's first execution in flight
+    // SAFETY: Stopping the daemon's worker; it is continued below
+    unsafe { libc::kill(pid, libc::SIGSTOP) };
+    let mut held = daemon.connect();
+    write_request(&mut held, &execute_request("pass"));
+    wait_for_inflight(&daemon, &[PeerInflight { uid, inflight: 1 }]);
+
+    // Its next execution, on another connection, is turned away
+    match daemon.send(&execute_request("pass")) {
+        Response::Error { kind, .. } => assert_eq!(
+            kind,
+            ErrorKind::Busy {
+                scope: InflightScope::PeerUid,
+                current_inflight: 1,
+                limit: 1,
+            }
+        ),
+        other => panic!("greedy client was not capped: {other:?}"),
+    }
+    // Requests that run no code are never limited
+    assert!(matches!(daemon.send(&Request::Ping), Response::Pong));
+    let mut events = daemon.connect();
+    write_request(&mut events, &Request::Subscribe { kinds: Vec::new() });
+    assert!(matches!(read_response(&mut events), Response::Subscribed));
+
+    // Meanwhile the other client runs on the free worker
+    let line = execute_as(OTHER_UID, &daemon.socket);
+    assert!(line.starts_with(r#"{"type":"Execute""#), "{line}");
+    assert_eq!(
+        daemon.metric(r#"leeward_inflight_rejected_total{limit="peer_uid"}"#),
+        1
+    );
+    assert_eq!(
+        daemon.metric(&format!(r#"leeward_inflight_executions{{uid="{uid}"}}"#)),
+        1
+    );
+
+    // SAFETY: Continuing the worker stopped above
+    unsafe { libc::kill(pid, libc::SIGCONT) };
+    assert!(executed(&read_response(&mut held)));
+    wait_for_inflight(&daemon, &[]);
+    assert!(executed(&daemon.send(&execute_request("pass"))));
+}
+
+#[test]
+fn failed_executions_give_their_slot_back() {
+    let Some(daemon) = Daemon::start(
+        "failed",
+        1,
+        &[
+            ("LEEWARD_MAX_INFLIGHT_PER_CONNECTION", "1"),
+            ("LEEWARD_MAX_INFLIGHT_PER_PEER_UID", "1"),
+        ],
+    ) else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    let mut stream = daemon.connect();
+    let rejected = RequestBuilder::new("pass").timezone("Not/AZone").build();
+    let requests = [
+        rejected.map_or_else(|_| execute_request("pass"), Request::Execute),
+        execute_request("pass"),
+        execute_request("raise SystemExit(3)"),
+        execute_request("pass"),
+    ];
+    for request in &requests {
+        write_request(&mut stream, request);
+        let response = read_response(&mut stream);
+        assert!(
+            !matches!(
+                response,
+                Response::Error {
+                    kind: ErrorKind::Busy { .. },
+                    ..
+                }
+            ),
+            "{response:?}"
+        );
+    }
+    assert_eq!(daemon.inflight(), Vec::new());
+
+    match daemon.send(&Request::StatusDetailed) {
+        Response::StatusDetailed {
+            total,
+            max_inflight_per_connection,
+            max_inflight_per_peer_uid,
+            ..
+        } => {
+            assert_eq!(total, 1);
+            assert_eq!(max_inflight_per_connection, 1);
+            assert_eq!(max_inflight_per_peer_uid, 1);
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert_eq!(
+        daemon.metric(r#"leeward_inflight_rejected_total{limit="connection"}"#),
+        0
+    );
+    assert_eq!(
+        daemon.metric(r#"leeward_inflight_rejected_total{limit="peer_uid"}"#),
+        0
+    );
+}
@@ -0,0 +1,99 @@
+//! A connection whose first byte is `{` speaks newline-delimited JSON
+
+use leeward_core::protocol::{self, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Hand-written, as a client without a msgpack library would send it
+const EXECUTE_LINE: &str =
+    r#"{"type": "Execute", "code": "print('hi')", "files": [], "stdin": "aWdub3JlZA=="}"#;
+
+fn connect(socket: &Path) -> UnixStream {
+    let stream = UnixStream::connect(socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .unwrap();
+    stream
+}
+
+fn round_trip(reader: &mut BufReader<UnixStream>, line: &str) -> Value {
+    let stream = reader.get_mut();
+    stream.write_all(line.as_bytes()).unwrap();
+    stream.write_all(b"\n").unwrap();
+
+    let mut response = String::new();
+    reader.read_line(&mut response).unwrap();
+    assert!(
+        response.ends_with('\n'),
+        "response is not newline-terminated: {response:?}"
+    );
+    serde_json::from_str(&response).unwrap()
+}
+
+#[test]
+fn json_connection_executes_and_reports_errors() {
+    let daemon = TestDaemon::builder().spawn().unwrap();
+    let socket = daemon.socket();
+
+    let mut reader = BufReader::new(connect(socket));
+
+    let pong = round_trip(&mut reader, r#"{"type": "Ping"}"#);
+    assert_eq!(pong["type"], "Pong");
+
+    let execute = round_trip(&mut reader, EXECUTE_LINE);
+    assert_eq!(execute["type"], "Execute", "unexpected response: {execute}");
+    if execute["result"]["exit_code"] == 0 {
+        // Binary fields come back base64-encoded
+        assert_eq!(
+            execute["result"]["stdout"], "aGkK",
+            "unexpected result: {execute}"
+        );
+    } else {
+        eprintln!("skipping result check, execution failed here: {execute}");
+    }
+
+    // A bad line gets an error and the connection stays usable
+    let error = round_trip(&mut reader, r#"{"type": "Nope"}"#);
+    assert_eq!(error["type"], "Error");
+    let pong = round_trip(&mut reader, r#"{"type": "Ping"}"#);
+    assert_eq!(pong["type"], "Pong");
+}
+
+#[test]
+fn msgpack_remains_the_default() {
+    let daemon = TestDaemon::builder().spawn().unwrap();
+    let socket = daemon.socket();
+
+    let mut stream = connect(socket);
+    let ping = msgpack_round_trip(&mut stream, &Request::Ping);
+    assert!(matches!(ping, Response::Pong));
+
+    // Byte fields keep their msgpack form inside the tagged response
+    let request = RequestBuilder::new("print('hi')")
+        .stdin(b"ignored".to_vec())
+        .build()
+        .unwrap();
+    let execute = msgpack_round_trip(&mut stream, &Request::Execute(request));
+    assert!(
+        matches!(execute, Response::Execute(_)),
+        "unexpected response: {execute:?}"
+    );
+}
+
+fn msgpack_round_trip(stream: &mut UnixStream, request: &Request) -> Response {
+    let request = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(request.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&request).unwrap();
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut response = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).unwrap();
+    protocol::decode(&response).unwrap()
+}
@@ -0,0 +1,25 @@
+//! The daemon reports the Landlock workers get on this kernel at startup,
+//! and refuses to start without it only when told to
+
+use leeward_core::escape::KernelFeature;
+use leeward_core::isolation::Enforcement;
+use leeward_daemon::config::DaemonConfig;
+
+#[test]
+fn startup_fails_without_landlock_only_when_it_is_required() {
+    let available = KernelFeature::Landlock.available();
+    let status = DaemonConfig::default().check_landlock().unwrap();
+    assert_eq!(status.enforced(), available);
+
+    let required = DaemonConfig {
+        require_landlock: true,
+        ..DaemonConfig::default()
+    };
+    match required.check_landlock() {
+        Ok(status) => assert!(available && status.enforcement != Enforcement::NotEnforced),
+        Err(e) => {
+            assert!(!available);
+            assert!(e.contains("require_landlock"), "{e}");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Clients change the log filter and debug flags of a running daemon, and
+//! a config reload puts back what they did not persist
+
+use leeward_core::protocol::{DebugFlag, Request, RequestBuilder, Response};
+use leeward_daemon::logging::{self, LogControl};
+use leeward_daemon::testing::TestDaemon;
+use std::io::Write;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tracing_subscriber::EnvFilter;
+
+/// Everything logged by this process
+static LOGS: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Flags and the filter are per process, so tests take turns
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Capture;
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        LOGS.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Install the capturing subscriber once, then wait for our turn
+fn log_control() -> (MutexGuard<'static, ()>, LogControl) {
+    static CONTROL: OnceLock<LogControl> = OnceLock::new();
+    let control =
+        CONTROL.get_or_init(|| logging::init(EnvFilter::new("leeward=info"), || Capture).unwrap());
+    let turn = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    (turn, control.clone())
+}
+
+/// Lines logged so far that contain `needle`
+fn lines_with(needle: &str) -> usize {
+    String::from_utf8_lossy(&LOGS.lock().unwrap())
+        .lines()
+        .filter(|line| line.contains(needle))
+        .count()
+}
+
+fn send(daemon: &TestDaemon, request: &Request) -> Response {
+    daemon.client().unwrap().request(request).unwrap()
+}
+
+fn execute(daemon: &TestDaemon) {
+    let request = Request::Execute(RequestBuilder::new("print(1)").build().unwrap());
+    let Response::Execute(response) = send(daemon, &request) else {
+        panic!("not an execute response");
+    };
+    assert!(response.success, "{response:?}");
+}
+
+fn set_debug(daemon: &TestDaemon, flag: DebugFlag, enabled: bool, persist: bool) -> Vec<DebugFlag> {
+    match send(
+        daemon,
+        &Request::SetDebug {
+            flag,
+            enabled,
+            persist,
+        },
+    ) {
+        Response::LogSettings { debug_flags, .. } => debug_flags,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+/// Log filter and debug flags as `StatusDetailed` reports them
+fn status(daemon: &TestDaemon) -> (Option<String>, Vec<DebugFlag>) {
+    match send(daemon, &Request::StatusDetailed) {
+        Response::StatusDetailed {
+            log_filter,
+            debug_flags,
+            ..
+        } => (log_filter, debug_flags),
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn scheduler_lines_are_logged_only_while_the_flag_is_on() {
+    let (_turn, log) = log_control();
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .log_control(log)
+        .spawn()
+        .unwrap();
+    let dispatched = "dispatched to a worker";
+
+    let before = lines_with(dispatched);
+    execute(&daemon);
+    assert_eq!(lines_with(dispatched), before);
+
+    assert_eq!(
+        set_debug(&daemon, DebugFlag::Scheduler, true, false),
+        [DebugFlag::Scheduler]
+    );
+    execute(&daemon);
+    execute(&daemon);
+    assert_eq!(lines_with(dispatched), before + 2);
+
+    assert_eq!(set_debug(&daemon, DebugFlag::Scheduler, false, false), []);
+    execute(&daemon);
+    assert_eq!(lines_with(dispatched), before + 2);
+}
+
+#[test]
+fn reload_puts_back_what_was_not_persisted() {
+    let (_turn, log) = log_control();
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .log_control(log.clone())
+        .spawn()
+        .unwrap();
+    assert_eq!(status(&daemon), (Some("leeward=info".into()), Vec::new()));
+
+    match send(
+        &daemon,
+        &Request::SetLogFilter {
+            directive: "leeward=debug".into(),
+            persist: false,
+        },
+    ) {
+        Response::LogSettings { filter, .. } => {
+            assert_eq!(filter.as_deref(), Some("leeward=debug"));
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+    set_debug(&daemon, DebugFlag::PipeFrames, true, true);
+    set_debug(&daemon, DebugFlag::SeccompDenials, true, false);
+    assert_eq!(
+        status(&daemon),
+        (
+            Some("leeward=debug".into()),
+            vec![DebugFlag::PipeFrames, DebugFlag::SeccompDenials]
+        )
+    );
+
+    log.reset();
+    assert_eq!(
+        status(&daemon),
+        (Some("leeward=info".into()), vec![DebugFlag::PipeFrames])
+    );
+
+    set_debug(&daemon, DebugFlag::PipeFrames, false, true);
+    log.reset();
+    assert_eq!(status(&daemon), (Some("leeward=info".into()), Vec::new()));
+}
+
+#[test]
+fn invalid_filters_are_refused() {
+    let (_turn, log) = log_control();
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .log_control(log)
+        .spawn()
+        .unwrap();
+
+    let response = send(
+        &daemon,
+        &Request::SetLogFilter {
+            directive: "leeward=loud".into(),
+            persist: false,
+        },
+    );
+    assert!(matches!(response, Response::Error { .. }), "{response:?}");
+    assert_eq!(status(&daemon).0.as_deref(), Some("leeward=info"));
+}
+
+#[test]
+fn without_a_control_only_flags_change() {
+    let (_turn, _) = log_control();
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+
+    let response = send(
+        &daemon,
+        &Request::SetLogFilter {
+            directive: "leeward=debug".into(),
+            persist: false,
+        },
+    );
+    assert!(matches!(response, Response::Error { .. }), "{response:?}");
+    assert_eq!(status(&daemon), (None, Vec::new()));
+
+    assert_eq!(
+        set_debug(&daemon, DebugFlag::Scheduler, true, false),
+        [DebugFlag::Scheduler]
+    );
+    assert_eq!(set_debug(&daemon, DebugFlag::Scheduler, false, false), []);
+}
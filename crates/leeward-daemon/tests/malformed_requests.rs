@@ -0,0 +1,100 @@
+//! Requests that cannot be decoded get a structured error instead of a
+//! dropped connection, and are counted
+
+use leeward_core::protocol::{self, ErrorKind, Request, Response};
+use leeward_daemon::testing::TestDaemon;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+
+const PROTOCOL_ERRORS: &str = "leeward_protocol_errors_total";
+
+fn send_frame(stream: &mut UnixStream, body: &[u8]) {
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(body).unwrap();
+}
+
+fn read_frame(stream: &mut UnixStream) -> Response {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+fn assert_malformed(response: &Response) {
+    assert!(
+        matches!(
+            response,
+            Response::Error {
+                kind: ErrorKind::Malformed,
+                ..
+            }
+        ),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn garbage_frame_gets_an_error_and_the_connection_survives() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+
+    // 0xc1 is never used by msgpack
+    send_frame(&mut stream, &[0xc1, 0xff, 0x00]);
+    assert_malformed(&read_frame(&mut stream));
+
+    send_frame(&mut stream, &protocol::encode(&Request::Ping).unwrap());
+    assert!(matches!(read_frame(&mut stream), Response::Pong));
+    assert_eq!(daemon.metric(PROTOCOL_ERRORS), Some(1.0));
+}
+
+#[test]
+fn oversized_frame_gets_an_error_before_the_connection_closes() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+
+    let len = u32::try_from(protocol::MAX_MESSAGE_SIZE + 1).unwrap();
+    stream.write_all(&len.to_be_bytes()).unwrap();
+    let response = read_frame(&mut stream);
+    assert_malformed(&response);
+    let Response::Error { message, .. } = response else {
+        unreachable!()
+    };
+    assert!(message.contains("message size limit"), "{message}");
+    assert_eq!(stream.read(&mut [0u8; 1]).unwrap(), 0);
+    assert_eq!(daemon.metric(PROTOCOL_ERRORS), Some(1.0));
+}
+
+#[test]
+fn garbage_json_line_is_counted() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let mut reader = BufReader::new(UnixStream::connect(daemon.socket()).unwrap());
+    assert_eq!(daemon.metric(PROTOCOL_ERRORS), Some(0.0));
+
+    for line in [
+        &b"{\"type\": \"Nope\"}\n"[..],
+        b"{not json\n",
+        b"{\"type\": \"Ping\"}\n",
+    ] {
+        reader.get_mut().write_all(line).unwrap();
+    }
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        responses.push(serde_json::from_str::<Value>(&line).unwrap());
+    }
+
+    for error in &responses[..2] {
+        assert_eq!(
+            (&error["type"], &error["kind"]),
+            (&Value::from("Error"), &Value::from("malformed")),
+            "{error}"
+        );
+    }
+    assert_eq!(responses[2]["type"], "Pong");
+    assert_eq!(daemon.metric(PROTOCOL_ERRORS), Some(2.0));
+}
@@ -0,0 +1,75 @@
+//! Mock workers echo code back through the real server, so protocol,
+//! queueing, events and metrics can be tested on any host
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{AlertState, EventKind, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+fn execute(daemon: &TestDaemon, code: &str) -> Response {
+    let request = RequestBuilder::new(code).build().unwrap();
+    daemon
+        .client()
+        .unwrap()
+        .request(&Request::Execute(request))
+        .unwrap()
+}
+
+#[test]
+fn code_is_echoed_and_counted() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+    assert_eq!(daemon.live_workers(), 1);
+
+    let Response::Execute(response) = execute(&daemon, "print('hi')") else {
+        panic!("not an execute response");
+    };
+    assert!(response.success, "{response:?}");
+    let result = response.result.unwrap();
+    assert_eq!(result.exit_code, 0);
+    assert_eq!(result.stdout, b"print('hi')");
+
+    assert_eq!(
+        daemon.metric(r#"leeward_executions_total{path="queued"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        daemon.metric(r#"leeward_executions_total{path="inline"}"#),
+        Some(0.0)
+    );
+    assert_eq!(daemon.metric("leeward_connections_total"), Some(1.0));
+    assert_eq!(
+        daemon.wait_for_metric("leeward_connections_open", 0.0, Duration::from_secs(5)),
+        Some(0.0)
+    );
+    assert_eq!(daemon.metric("leeward_no_such_series"), None);
+}
+
+#[test]
+fn recorder_sees_alerts_from_a_saturated_pool() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock_latency(Duration::from_millis(300))
+        .config(|config| {
+            config.alert_queue_depth = 1;
+            config.alert_sample_interval = DurationSecs::from_millis(20);
+        })
+        .spawn()
+        .unwrap();
+    let mut events = daemon.subscribe();
+
+    std::thread::scope(|scope| {
+        for _ in 0..3 {
+            scope.spawn(|| assert!(matches!(execute(&daemon, "pass"), Response::Execute(_))));
+        }
+    });
+
+    let alert = events
+        .wait_for(EventKind::Alert, Duration::from_secs(5))
+        .expect("no alert");
+    assert_eq!(alert.alert.unwrap().state, AlertState::Firing);
+    assert!(
+        events
+            .wait_for(EventKind::WorkerDied, Duration::ZERO)
+            .is_none()
+    );
+}
@@ -0,0 +1,76 @@
+//! An execution the kernel OOM-kills fails as over its memory limit, with
+//! its peak, and its worker is recycled
+//!
+//! Real cgroups need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+use leeward_core::isolation::CgroupHandle;
+use leeward_core::protocol::{Request, RequestBuilder, Response, WorkerInfo};
+use leeward_core::{ByteSize, OutcomeCode};
+use leeward_daemon::testing::TestDaemon;
+use std::path::Path;
+
+fn workers(daemon: &TestDaemon) -> Vec<WorkerInfo> {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::ListWorkers)
+        .unwrap()
+    {
+        Response::WorkerList { workers, .. } => workers,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn oom_killed_executions_fail_over_the_limit_and_recycle_their_worker() {
+    let Ok(parent) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return;
+    };
+    // Held to 16 MiB from above, so the interpreter gets no address space
+    // limit and only the cgroup stops it
+    let root = CgroupHandle::create(
+        Path::new(&parent),
+        &format!("daemon-oom-{}", std::process::id()),
+    )
+    .unwrap();
+    root.set_memory_max(Some(ByteSize::mib(16))).unwrap();
+    root.set_swap_max(Some(ByteSize::from_bytes(0))).unwrap();
+
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .config(|config| config.cgroup_root = Some(root.path().to_path_buf()))
+        .spawn()
+        .unwrap();
+    if daemon.live_workers() < 1 {
+        eprintln!("skipping: workers cannot start here");
+        return;
+    }
+    let before = workers(&daemon)[0].uid.clone();
+
+    let request = RequestBuilder::new("data = bytearray(b'x' * (64 * 1024 * 1024))")
+        .build()
+        .unwrap();
+    let response = daemon.client().unwrap().execute(request).unwrap();
+    assert!(!response.success, "{response:?}");
+    assert_eq!(
+        response.error_code,
+        Some(OutcomeCode::Killed),
+        "{response:?}"
+    );
+    assert!(
+        response
+            .error
+            .as_deref()
+            .is_some_and(|error| error.starts_with("memory limit exceeded")),
+        "{response:?}"
+    );
+    assert_ne!(workers(&daemon)[0].uid, before);
+
+    // The new worker runs what fits
+    let request = RequestBuilder::new("print('ok')").build().unwrap();
+    assert!(daemon.client().unwrap().execute(request).unwrap().success);
+    drop(daemon);
+    let _ = std::fs::remove_dir(root.path());
+}
@@ -0,0 +1,177 @@
+//! Worker pipes are sampled for how full they are: the gauges and peaks
+//! show a side that stopped reading, and a pipe left full past the stall
+//! threshold is logged with its execution id under `pipe-stalls`
+//!
+//! The stalls are staged on a bare pipe pair, with the child end standing
+//! in for a worker that sleeps instead of reading.
+
+use leeward_core::pipe::{self, PipeGauge, WorkerPipe};
+use leeward_core::protocol::DebugFlag;
+use leeward_core::{ByteSize, LeewardError, SandboxConfig};
+use leeward_daemon::logging;
+use leeward_daemon::testing::TestDaemon;
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use tracing_subscriber::EnvFilter;
+
+/// Everything logged by this process
+static LOGS: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Flags are per process, so tests take turns
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Capture;
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        LOGS.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Install the capturing subscriber once, then wait for our turn
+fn capture() -> MutexGuard<'static, ()> {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        logging::init(EnvFilter::new("leeward=info"), || Capture).unwrap();
+    });
+    SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Stall lines logged so far for execution `id`
+fn stalls_of(id: u64) -> usize {
+    let needle = format!("execution_id={id}");
+    String::from_utf8_lossy(&LOGS.lock().unwrap())
+        .lines()
+        .map(plain)
+        .filter(|line| line.contains("worker pipe stalled full") && line.contains(&needle))
+        .count()
+}
+
+/// `line` without its terminal colors
+fn plain(line: &str) -> String {
+    let mut plain = String::new();
+    let mut rest = line;
+    while let Some((before, after)) = rest.split_once('\x1b') {
+        plain.push_str(before);
+        rest = after.split_once('m').map_or("", |(_, after)| after);
+    }
+    plain + rest
+}
+
+/// Wait up to 5s for `done`
+fn eventually(done: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    true
+}
+
+#[test]
+fn a_worker_not_reading_its_code_shows_in_the_gauge_and_is_logged() {
+    let _turn = capture();
+    DebugFlag::PipeStalls.set(true);
+
+    let (mut parent, mut child) = WorkerPipe::new().unwrap().split();
+    let capacity = pipe::capacity(parent.code_tx_fd()).unwrap();
+    let gauge = Arc::new(PipeGauge::default());
+    let watch = parent
+        .watch(Arc::clone(&gauge), Duration::from_millis(50), 3, Some(41))
+        .unwrap();
+
+    // Twice what the pipe holds, so the write blocks on the sleeping reader
+    let code = vec![7u8; capacity * 2];
+    let writer = std::thread::spawn(move || parent.send_code(&code).map(|()| parent));
+    assert!(
+        eventually(|| gauge.code() + 4096 > capacity),
+        "{}",
+        gauge.code()
+    );
+    assert!(eventually(|| stalls_of(41) == 1));
+    // A stall is logged once however long it lasts
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(stalls_of(41), 1);
+
+    assert_eq!(child.recv_code().unwrap().len(), capacity * 2);
+    let _parent = writer.join().unwrap().unwrap();
+    let peaks = watch.stop();
+    assert!(peaks.code + 4096 > capacity, "{peaks:?}");
+    assert_eq!(peaks.result, 0);
+    assert_eq!(gauge.code(), 0);
+    DebugFlag::PipeStalls.set(false);
+}
+
+#[test]
+fn results_the_daemon_does_not_read_are_logged_only_under_the_flag() {
+    let _turn = capture();
+    DebugFlag::PipeStalls.set(false);
+
+    let (mut parent, mut child) = WorkerPipe::new().unwrap().split();
+    let capacity = pipe::capacity(parent.result_rx_fd()).unwrap();
+    let gauge = Arc::new(PipeGauge::default());
+    let watch = parent
+        .watch(Arc::clone(&gauge), Duration::from_millis(20), 3, Some(42))
+        .unwrap();
+
+    let result = vec![9u8; capacity * 2];
+    let writer = std::thread::spawn(move || child.send_result(&result));
+    assert!(
+        eventually(|| gauge.result() + 4096 > capacity),
+        "{}",
+        gauge.result()
+    );
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(stalls_of(42), 0);
+
+    assert_eq!(parent.recv_result().unwrap().len(), capacity * 2);
+    writer.join().unwrap().unwrap();
+    assert!(watch.stop().result + 4096 > capacity);
+    assert_eq!(gauge.result(), 0);
+}
+
+#[test]
+fn pipes_are_resized_from_the_config() {
+    let pipes = WorkerPipe::new().unwrap();
+    pipes.resize(ByteSize::kib(256)).unwrap();
+    let (parent, _child) = pipes.split();
+    assert_eq!(pipe::capacity(parent.code_tx_fd()).unwrap(), 256 * 1024);
+    assert_eq!(pipe::capacity(parent.result_rx_fd()).unwrap(), 256 * 1024);
+
+    for size in [ByteSize::from_bytes(0), ByteSize::gib(2)] {
+        let config = SandboxConfig::builder().pipe_buffer_size(size).build();
+        assert!(
+            matches!(config.validate(), Err(LeewardError::Config(_))),
+            "{size}"
+        );
+    }
+    assert!(
+        SandboxConfig::builder()
+            .pipe_buffer_size(ByteSize::mib(1))
+            .build()
+            .validate()
+            .is_ok()
+    );
+}
+
+#[test]
+fn every_worker_has_pipe_gauges() {
+    let daemon = TestDaemon::builder().workers(2).mock().spawn().unwrap();
+    for worker in 0..2 {
+        for pipe in ["code", "result"] {
+            let series =
+                format!("leeward_worker_pipe_queued_bytes{{worker=\"{worker}\",pipe=\"{pipe}\"}}");
+            assert_eq!(daemon.metric(&series), Some(0.0), "{series}");
+        }
+    }
+}
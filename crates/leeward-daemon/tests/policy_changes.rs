@@ -0,0 +1,178 @@
+//! Each change to the effective policy is kept for `PolicyChanges`,
+//! published to subscribers and appended to the audit log
+
+use leeward_core::SandboxConfig;
+use leeward_core::policy::{PolicyActor, Provenance, REDACTED};
+use leeward_core::protocol::{DEFAULT_PROFILE, EventKind, Request, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// An audit log path, removed on drop
+struct AuditLog(PathBuf);
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl AuditLog {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("leeward-audit-{name}-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Self(path)
+    }
+
+    /// The log's lines once there are `count` of them
+    fn wait_lines(&self, count: usize) -> Vec<serde_json::Value> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let lines = read_lines(&self.0);
+            if lines.len() >= count {
+                return lines;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "{} of {count} audit lines",
+                lines.len()
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+fn read_lines(path: &Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn sandbox() -> SandboxConfig {
+    SandboxConfig::builder()
+        .timeout(Duration::from_secs(10))
+        .env("API_TOKEN", "hunter2")
+        .build()
+}
+
+fn policy_changes(daemon: &TestDaemon) -> Vec<leeward_core::policy::PolicyChange> {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::PolicyChanges)
+        .unwrap()
+    {
+        Response::PolicyChanges { changes } => changes,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn startup_is_recorded_against_the_built_in_config() {
+    let log = AuditLog::new("startup");
+    let daemon = TestDaemon::builder()
+        .mock()
+        .sandbox(sandbox())
+        .config(|config| config.audit_log = Some(log.0.clone()))
+        .spawn()
+        .unwrap();
+
+    let changes = policy_changes(&daemon);
+    assert_eq!(changes.len(), 1, "{changes:?}");
+    let startup = &changes[0];
+    assert_eq!(startup.actor, PolicyActor::Startup);
+    assert_eq!(startup.fingerprint_before, None);
+    assert_eq!(startup.fingerprint_after, sandbox().fingerprint());
+
+    let timeout = startup
+        .changes
+        .iter()
+        .find(|change| change.field == "timeout.secs")
+        .unwrap();
+    assert_eq!(timeout.before.as_deref(), Some("30"));
+    assert_eq!(timeout.after.as_deref(), Some("10"));
+    assert_eq!(timeout.provenance, Provenance::DaemonConfig);
+    let env: Vec<_> = startup
+        .changes
+        .iter()
+        .filter(|change| change.field.starts_with("env."))
+        .collect();
+    assert_ne!(env.len(), 0);
+    assert!(
+        env.iter()
+            .all(|change| change.after.as_deref() == Some(REDACTED))
+    );
+
+    let lines = log.wait_lines(1);
+    assert_eq!(lines[0]["type"], "policy_change");
+    assert_eq!(lines[0]["actor"], "startup");
+    let raw = std::fs::read_to_string(&log.0).unwrap();
+    assert!(!raw.contains("hunter2"), "{raw}");
+
+    let mode = std::fs::metadata(&log.0).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn draining_a_profile_is_recorded_with_who_and_why() {
+    let log = AuditLog::new("drain");
+    let daemon = TestDaemon::builder()
+        .mock()
+        .config(|config| config.audit_log = Some(log.0.clone()))
+        .spawn()
+        .unwrap();
+    let mut events = daemon.subscribe();
+
+    let drain = Request::DrainProfile {
+        profile: DEFAULT_PROFILE.into(),
+        reason: Some("interpreter upgraded".into()),
+    };
+    let response = daemon.client().unwrap().request(&drain).unwrap();
+    assert!(matches!(response, Response::Drain(_)), "{response:?}");
+
+    let event = events
+        .wait_for(EventKind::PolicyChange, Duration::from_secs(5))
+        .expect("no policy change event");
+    let change = event.policy_change.unwrap();
+    // SAFETY: geteuid has no failure modes
+    let uid = unsafe { libc::geteuid() };
+    assert_eq!(
+        change.actor,
+        PolicyActor::Request {
+            request: "drain_profile".into(),
+            peer_uid: Some(uid),
+        }
+    );
+    assert_eq!(change.reason.as_deref(), Some("interpreter upgraded"));
+    // Draining moves workers, not the config
+    assert_eq!(
+        change.fingerprint_before.as_ref(),
+        Some(&change.fingerprint_after)
+    );
+    assert_eq!(change.changes, Vec::new());
+
+    let changes = policy_changes(&daemon);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[1], change);
+
+    let lines = log.wait_lines(2);
+    assert_eq!(lines[1]["type"], "policy_change");
+    assert_eq!(lines[1]["actor"], "request");
+    assert_eq!(lines[1]["request"], "drain_profile");
+    assert_eq!(lines[1]["peer_uid"], uid);
+    assert_eq!(lines[1]["reason"], "interpreter upgraded");
+}
+
+#[test]
+fn an_unwritable_audit_log_leaves_the_daemon_serving() {
+    let daemon = TestDaemon::builder()
+        .mock()
+        .config(|config| config.audit_log = Some("/nonexistent/leeward/audit.jsonl".into()))
+        .spawn()
+        .unwrap();
+    assert_eq!(policy_changes(&daemon).len(), 1);
+}
@@ -0,0 +1,165 @@
+//! Explaining a request's policy names where each setting it would run
+//! with came from, through the merges an execution goes through
+
+use leeward_core::config::{Interpreter, SchedPolicy};
+use leeward_core::policy::{PolicyField, Provenance};
+use leeward_core::protocol::{ExecuteDefaults, RequestBuilder, RequestPriority};
+use leeward_core::{ByteSize, DurationSecs};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+fn daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .mock()
+        .config(|config| {
+            config.max_timeout = DurationSecs::from_secs(30);
+            config.sandbox_config.timeout = Duration::from_secs(20);
+            config.sandbox_config.memory_limit = Some(ByteSize::mib(512));
+        })
+        .spawn()
+        .unwrap()
+}
+
+/// (value, provenance) of `field`
+fn field<'a>(fields: &'a [PolicyField], name: &str) -> (&'a str, &'a Provenance) {
+    let field = fields
+        .iter()
+        .find(|field| field.field == name)
+        .unwrap_or_else(|| panic!("{name} not explained: {fields:?}"));
+    (field.value.as_str(), &field.provenance)
+}
+
+#[test]
+fn config_values_are_told_apart_from_built_in_ones() {
+    let daemon = daemon();
+    let fields = daemon
+        .client()
+        .unwrap()
+        .explain_policy(RequestBuilder::new("").build().unwrap())
+        .unwrap();
+
+    assert_eq!(field(&fields, "timezone"), ("UTC", &Provenance::Default));
+    assert_eq!(
+        field(&fields, "allow_network"),
+        ("false", &Provenance::Default)
+    );
+    assert_eq!(field(&fields, "priority"), ("Normal", &Provenance::Default));
+    assert_eq!(
+        field(&fields, "timeout"),
+        ("20s", &Provenance::DaemonConfig)
+    );
+    assert_eq!(
+        field(&fields, "memory_limit"),
+        ("512MiB", &Provenance::DaemonConfig)
+    );
+}
+
+#[test]
+fn request_values_win_and_limits_clamp_them() {
+    let daemon = daemon();
+    let request = RequestBuilder::new("")
+        .timeout(Duration::from_secs(300))
+        .memory_limit(ByteSize::mib(64))
+        .timezone("Europe/Paris")
+        .soft_timeout_traceback(true)
+        .interpreter(Interpreter::Sh)
+        .build()
+        .unwrap();
+    let fields = daemon.client().unwrap().explain_policy(request).unwrap();
+
+    assert_eq!(
+        field(&fields, "memory_limit"),
+        ("64MiB", &Provenance::Request)
+    );
+    assert_eq!(
+        field(&fields, "timezone"),
+        ("Europe/Paris", &Provenance::Request)
+    );
+    let (timeout, provenance) = field(&fields, "timeout");
+    assert_eq!(timeout, "30s");
+    assert!(
+        matches!(provenance, Provenance::Clamped { limit } if limit.contains("max_timeout")),
+        "{provenance:?}"
+    );
+    let (traceback, provenance) = field(&fields, "soft_timeout_traceback");
+    assert_eq!(traceback, "false");
+    assert!(
+        matches!(provenance, Provenance::Clamped { .. }),
+        "{provenance:?}"
+    );
+}
+
+#[test]
+fn connection_defaults_are_named_as_such() {
+    let daemon = daemon();
+    let mut client = daemon
+        .client()
+        .unwrap()
+        .with_defaults(
+            ExecuteDefaults::default()
+                .timeout(Duration::from_secs(5))
+                .priority(RequestPriority::Low),
+        )
+        .unwrap();
+    let fields = client
+        .explain_policy(RequestBuilder::new("").build().unwrap())
+        .unwrap();
+
+    assert_eq!(
+        field(&fields, "timeout"),
+        ("5s", &Provenance::ConnectionDefault)
+    );
+    assert_eq!(
+        field(&fields, "priority"),
+        ("Low", &Provenance::ConnectionDefault)
+    );
+    // Low priority runs under the built-in background scheduling
+    assert_eq!(field(&fields, "nice"), ("10", &Provenance::Default));
+    assert_eq!(
+        field(&fields, "sched_policy"),
+        ("Batch", &Provenance::Default)
+    );
+
+    // The request's own value still wins over the connection's
+    let request = RequestBuilder::new("")
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    let fields = client.explain_policy(request).unwrap();
+    assert_eq!(field(&fields, "timeout"), ("10s", &Provenance::Request));
+}
+
+#[test]
+fn priority_scheduling_from_the_config_is_the_daemons() {
+    let daemon = TestDaemon::builder()
+        .mock()
+        .config(|config| config.priority_scheduling.high.sched_policy = Some(SchedPolicy::Other))
+        .spawn()
+        .unwrap();
+    let request = RequestBuilder::new("")
+        .priority(RequestPriority::High)
+        .build()
+        .unwrap();
+    let fields = daemon.client().unwrap().explain_policy(request).unwrap();
+
+    assert_eq!(field(&fields, "priority"), ("High", &Provenance::Request));
+    assert_eq!(
+        field(&fields, "sched_policy"),
+        ("Other", &Provenance::DaemonConfig)
+    );
+}
+
+#[test]
+fn unknown_timezones_are_refused() {
+    let daemon = daemon();
+    let request = RequestBuilder::new("")
+        .timezone("Not/AZone")
+        .build()
+        .unwrap();
+    let error = daemon
+        .client()
+        .unwrap()
+        .explain_policy(request)
+        .unwrap_err();
+    assert!(error.to_string().contains("timezone"), "{error}");
+}
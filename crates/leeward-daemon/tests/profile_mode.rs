@@ -0,0 +1,162 @@
+//! Profile-mode requests come back with a workload profile, and only
+//! clients running as the daemon's own user may make them
+
+use leeward_core::OutcomeCode;
+use leeward_core::protocol::{self, Request, RequestBuilder, Response};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    fn start() -> Option<Self> {
+        let dir = std::env::temp_dir().join(format!("leeward-profile-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("leeward.sock");
+
+        let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+            .env("LEEWARD_SOCKET", &socket)
+            .env("LEEWARD_WORKERS", "1")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Self { child, dir, socket };
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if UnixStream::connect(&daemon.socket).is_ok() {
+                return Some(daemon);
+            }
+            if daemon.child.try_wait().unwrap().is_some() {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+}
+
+fn execute(socket: &Path, request: RequestBuilder) -> protocol::ExecuteResponse {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .unwrap();
+
+    let body = protocol::encode(&Request::Execute(request.build().unwrap())).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    match protocol::decode(&body).unwrap() {
+        Response::Execute(response) => response,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn profile_is_attached_for_the_daemons_user() {
+    let Some(daemon) = Daemon::start() else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    let response = execute(
+        &daemon.socket,
+        RequestBuilder::new("import os; print(os.getpid() > 0)").profile_mode(true),
+    );
+    let Some(profile) = response.profile else {
+        eprintln!("skipping: cannot profile here: {:?}", response.error);
+        return;
+    };
+    let result = response.result.unwrap();
+    assert_eq!(result.exit_code, 0, "{}", result.stderr_str());
+    assert_eq!(result.stdout_str().trim(), "True");
+    assert!(profile.syscalls[&libc::SYS_getpid] >= 1);
+    // The worker cgroup's, where the daemon accounts workers in cgroups
+    assert!(profile.memory_peak == 0 || profile.memory_peak == result.memory_peak);
+
+    // Ordinary requests carry none
+    let response = execute(&daemon.socket, RequestBuilder::new("pass"));
+    assert!(response.profile.is_none());
+}
+
+#[test]
+fn other_users_cannot_profile() {
+    // SAFETY: geteuid has no failure modes
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: switching to another user needs root");
+        return;
+    }
+    let Some(daemon) = Daemon::start() else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    // Let anyone connect, so only the daemon's own check stands in the way
+    for (path, mode) in [(&daemon.dir, 0o755), (&daemon.socket, 0o777)] {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    // A client as nobody, speaking JSON so it needs no msgpack library
+    let client = r#"
+import socket, sys
+s = socket.socket(socket.AF_UNIX)
+s.connect(sys.argv[1])
+s.sendall(b'{"type": "Execute", "code": "print(1)", "files": [], "profile_mode": true}\n')
+print(s.makefile().readline())
+"#;
+    let output = Command::new("python3")
+        .arg("-c")
+        .arg(client)
+        .arg(&daemon.socket)
+        .uid(65534)
+        .gid(65534)
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            eprintln!(
+                "skipping: client could not reach the daemon as nobody: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return;
+        }
+        Err(e) => {
+            eprintln!("skipping: no python3 for the client: {e}");
+            return;
+        }
+    };
+
+    let response: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(response["success"], false, "{response}");
+    assert_eq!(
+        response["error_code"],
+        OutcomeCode::InvalidRequest.code(),
+        "{response}"
+    );
+    assert!(response["profile"].is_null(), "{response}");
+}
@@ -0,0 +1,41 @@
+//! The daemon reaps template roots left behind by a daemon that is gone,
+//! and counts what it reaped
+
+use leeward_core::DurationSecs;
+use leeward_core::isolation::registry::{self, ROOT_PREFIX};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+const SERIES: &str = r#"leeward_leaked_reaped_total{kind="root"}"#;
+
+#[test]
+fn orphaned_roots_are_reaped_and_counted() {
+    // A root named after a daemon that has exited
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    let orphan = registry::roots_dir().join(format!("{ROOT_PREFIX}{dead}-0"));
+    std::fs::create_dir_all(&orphan).unwrap();
+
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config(|config| {
+            config.reconcile_interval = DurationSecs::from_secs(1);
+            config.reconcile_grace = DurationSecs::ZERO;
+        })
+        .spawn()
+        .unwrap();
+
+    // Other leftovers in the shared temp dir may be reaped alongside it
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !daemon.metric(SERIES).is_some_and(|reaped| reaped >= 1.0) {
+        assert!(Instant::now() < deadline, "nothing reaped");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!orphan.exists(), "{} was not reaped", orphan.display());
+    assert_eq!(
+        daemon.metric(r#"leeward_leaked_reaped_total{kind="mount"}"#),
+        Some(0.0)
+    );
+}
@@ -0,0 +1,203 @@
+//! A request the daemon cannot finish is still answered, with a typed
+//! error naming where it got stuck, once its deadline passes
+
+use leeward_core::protocol::{self, ErrorKind, Request, RequestBuilder, RequestStage, Response};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const DEADLINE: Duration = Duration::from_secs(2);
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+    metrics_port: u16,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    /// Start a one-worker daemon with a short request deadline
+    fn start(name: &str) -> Option<Self> {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-deadline-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("leeward.sock");
+        let metrics_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+            .env("LEEWARD_SOCKET", &socket)
+            .env("LEEWARD_WORKERS", "1")
+            .env(
+                "LEEWARD_MAX_REQUEST_WALL_SECS",
+                DEADLINE.as_secs().to_string(),
+            )
+            .env("LEEWARD_METRICS_PORT", metrics_port.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Self {
+            child,
+            dir,
+            socket,
+            metrics_port,
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if UnixStream::connect(&daemon.socket).is_ok()
+                && TcpStream::connect(("127.0.0.1", daemon.metrics_port)).is_ok()
+            {
+                return Some(daemon);
+            }
+            if daemon.child.try_wait().unwrap().is_some() {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    /// Value of an unlabelled metric
+    fn metric(&self, name: &str) -> u64 {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.metrics_port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(60)))
+            .unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {name} in metrics:\n{body}"))
+            .parse()
+            .unwrap()
+    }
+
+    /// Process of the only worker, if it is running
+    fn worker_pid(&self) -> Option<i32> {
+        match send(&self.socket, &Request::ListWorkers) {
+            Response::WorkerList { workers, .. } => workers.first().and_then(|worker| worker.pid),
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+}
+
+fn send(socket: &Path, request: &Request) -> Response {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .unwrap();
+
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+fn execute(socket: &Path, code: &str) -> Response {
+    send(
+        socket,
+        &Request::Execute(RequestBuilder::new(code).build().unwrap()),
+    )
+}
+
+/// Whether the response carries a result from a worker
+const fn executed(response: &Response) -> bool {
+    matches!(response, Response::Execute(resp) if resp.result.is_some())
+}
+
+#[test]
+fn stuck_worker_is_answered_and_reclaimed() {
+    let Some(daemon) = Daemon::start("stuck") else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+    let first = execute(&daemon.socket, "pass");
+    if !executed(&first) {
+        eprintln!("skipping: no worker could run code here: {first:?}");
+        return;
+    }
+    let Some(pid) = daemon.worker_pid() else {
+        eprintln!("skipping: worker has no process");
+        return;
+    };
+    assert_eq!(daemon.metric("leeward_request_deadline_exceeded_total"), 0);
+
+    // A stopped worker never answers, so the pool never resolves
+    // SAFETY: Stopping the daemon's worker, which it will reclaim
+    unsafe {
+        libc::kill(pid, libc::SIGSTOP);
+    }
+    let start = Instant::now();
+    let response = execute(&daemon.socket, "pass");
+    let elapsed = start.elapsed();
+
+    match &response {
+        Response::Error { message, kind } => {
+            assert_eq!(
+                *kind,
+                ErrorKind::Internal {
+                    stage: RequestStage::Running { worker_id: 0 }
+                },
+                "{message}"
+            );
+            assert!(message.contains("running on worker 0"), "{message}");
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert!(elapsed >= DEADLINE, "answered after only {elapsed:?}");
+    assert!(
+        elapsed < DEADLINE + Duration::from_secs(3),
+        "answered after {elapsed:?}"
+    );
+    assert_eq!(daemon.metric("leeward_request_deadline_exceeded_total"), 1);
+
+    // The stuck worker is killed and replaced, and serves again
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while daemon.worker_pid().is_none_or(|new| new == pid) {
+        assert!(Instant::now() < deadline, "worker {pid} was never replaced");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let response = execute(&daemon.socket, "pass");
+    assert!(executed(&response), "{response:?}");
+}
+
+#[test]
+fn prompt_requests_are_unaffected() {
+    let Some(daemon) = Daemon::start("prompt") else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+
+    assert!(matches!(
+        send(&daemon.socket, &Request::Ping),
+        Response::Pong
+    ));
+    let response = execute(&daemon.socket, "pass");
+    assert!(!matches!(response, Response::Error { .. }), "{response:?}");
+    assert_eq!(daemon.metric("leeward_request_deadline_exceeded_total"), 0);
+}
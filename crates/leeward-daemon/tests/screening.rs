@@ -0,0 +1,310 @@
+//! Code screening: the rule engine against the usual ways of hiding an
+//! import, reloading rules, and the daemon refusing or flagging executions
+//! before they reach a worker
+
+use leeward_core::config::Interpreter;
+use leeward_core::protocol::{ErrorKind, Request, RequestBuilder, Response};
+use leeward_daemon::screening::{RuleFile, Rules, ScreenVerdict, canonicalize};
+use leeward_daemon::testing::TestDaemon;
+use std::path::PathBuf;
+
+const RULES: &str = r#"
+[[rule]]
+name = "no-ctypes"
+import = "ctypes"
+
+[[rule]]
+name = "no-sockets"
+import = "socket"
+without_network = true
+
+[[rule]]
+name = "rm-rf"
+substring = "rm -rf /"
+
+[[rule]]
+name = "long"
+action = "flag"
+max_lines = 3
+"#;
+
+fn rules() -> Rules {
+    Rules::parse(RULES, false).unwrap()
+}
+
+fn denied(code: &str) -> bool {
+    matches!(
+        rules().check(code, Interpreter::Python),
+        ScreenVerdict::Deny(_)
+    )
+}
+
+/// A file of `contents` in a directory of its own
+fn rule_file(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("leeward-screening-{}-{name}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rules.toml");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn imports_are_found_however_they_are_written() {
+    for code in [
+        "import ctypes",
+        "import ctypes.util",
+        "import os, ctypes",
+        "import os as o, ctypes as c",
+        "from ctypes import CDLL",
+        "from ctypes.util import find_library",
+        "x = 1; import ctypes",
+        "if True: import ctypes",
+        "    import ctypes\n",
+        "import\tctypes",
+        "import   os,\t ctypes",
+        "import os, \\\n    ctypes",
+        "from  ctypes  import  *",
+        "__import__('ctypes')",
+        "__import__ ( \"ctypes\" )",
+        "importlib.import_module('ctypes.wintypes')",
+        "importlib.import_module(name='ctypes')",
+        // Encodings: escapes, fullwidth letters Python reads as ASCII,
+        // invisible characters
+        "__import__('\\x63types')",
+        "__import__('\\u0063types')",
+        "__import__('\\143types')",
+        "import ｃｔｙｐｅｓ",
+        "__import__('ct\u{200b}ypes')",
+        "\u{feff}import ctypes\r\n",
+    ] {
+        assert!(denied(code), "{code:?} got through");
+    }
+}
+
+#[test]
+fn look_alikes_are_not_imports() {
+    for code in [
+        "import ctypes_helper",
+        "import myctypes",
+        "from os import path  # ctypes is not imported",
+        "from foo import ctypes_like",
+        "ctypes = 1",
+        "print('ctypes')",
+    ] {
+        assert!(!denied(code), "{code:?} was refused");
+    }
+}
+
+#[test]
+fn import_rules_apply_to_python_only() {
+    assert_eq!(
+        rules().check("import ctypes", Interpreter::Sh),
+        ScreenVerdict::Allow
+    );
+}
+
+#[test]
+fn substrings_survive_whitespace_and_encoding_tricks() {
+    for code in [
+        "rm -rf /",
+        "rm  -rf   /",
+        "rm\t-rf /",
+        "rm -rf \\\n/",
+        "ｒｍ －ｒｆ ／",
+        "rm \\x2drf /",
+    ] {
+        let verdict = rules().check(code, Interpreter::Sh);
+        assert!(
+            matches!(verdict, ScreenVerdict::Deny(_)),
+            "{code:?}: {verdict:?}"
+        );
+    }
+    assert_eq!(
+        rules().check("rm -rf ./build", Interpreter::Sh),
+        ScreenVerdict::Allow
+    );
+}
+
+#[test]
+fn canonical_form_is_trimmed_single_spaced_lines() {
+    assert_eq!(canonicalize("  a \t b\n\tc  \\\n d"), "a b\nc d");
+    assert_eq!(canonicalize("\\xzz \\u12"), "\\xzz \\u12");
+}
+
+#[test]
+fn denials_name_the_rule_and_flags_add_up() {
+    let rules = Rules::parse(
+        r#"
+        [[rule]]
+        name = "sockets"
+        action = "flag"
+        import = "socket"
+
+        [[rule]]
+        name = "long"
+        action = "flag"
+        max_line_length = 10
+
+        [[rule]]
+        name = "pickle"
+        import = "pickle"
+        reason = "pickle runs code on load"
+        "#,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        rules.check("import socket, os", Interpreter::Python),
+        ScreenVerdict::Flag(
+            "rule 'sockets': imports socket; rule 'long': has a line of 17 bytes, over 10".into()
+        )
+    );
+    assert_eq!(
+        rules.check("import socket, pickle", Interpreter::Python),
+        ScreenVerdict::Deny(
+            "code screening rule 'pickle' refused this code: pickle runs code on load".into()
+        )
+    );
+}
+
+#[test]
+fn network_rules_only_apply_without_network() {
+    assert!(denied("import socket"));
+    let rules = Rules::parse(RULES, true).unwrap();
+    assert!(matches!(
+        rules.check("import socket", Interpreter::Python),
+        ScreenVerdict::Allow
+    ));
+    assert!(matches!(
+        rules.check("import ctypes", Interpreter::Python),
+        ScreenVerdict::Deny(_)
+    ));
+}
+
+#[test]
+fn bad_rules_are_refused() {
+    for (rules, expected) in [
+        ("[[rule]]\nname = \"x\"\n", "needs one of"),
+        (
+            "[[rule]]\nname = \"x\"\nimport = \"a\"\nmax_bytes = 1\n",
+            "more than one test",
+        ),
+        (
+            "[[rule]]\nname = \"x\"\nregex = \"(\"\n",
+            "screening rule 'x'",
+        ),
+        (
+            "[[rule]]\nname = \"x\"\nimport = \"os path\"\n",
+            "module name",
+        ),
+        (
+            "[[rule]]\nname = \"x\"\nimport = \"os\"\naction = \"warn\"\n",
+            "screening rules",
+        ),
+        (
+            "[[rule]]\nname = \"x\"\nimprot = \"os\"\n",
+            "screening rules",
+        ),
+    ] {
+        let error = Rules::parse(rules, false).unwrap_err().to_string();
+        assert!(error.contains(expected), "{rules:?}: {error}");
+    }
+}
+
+#[test]
+fn rules_reload_and_keep_the_last_good_ones() {
+    let path = rule_file("reload", "[[rule]]\nname = \"a\"\nimport = \"ctypes\"\n");
+    let file = RuleFile::open(&path, false);
+    assert_eq!(file.rules().unwrap().len(), 1);
+
+    std::fs::write(
+        &path,
+        "[[rule]]\nname = \"a\"\nimport = \"ctypes\"\n[[rule]]\nname = \"b\"\nimport = \"os\"\n",
+    )
+    .unwrap();
+    assert!(file.reload(false));
+    assert_eq!(file.rules().unwrap().len(), 2);
+
+    std::fs::write(&path, "[[rule]\n").unwrap();
+    assert!(!file.reload(false));
+    assert_eq!(file.rules().unwrap().len(), 2);
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn unreadable_rules_refuse_everything() {
+    let daemon = TestDaemon::builder()
+        .mock()
+        .config(|config| config.screening_rules = Some("/nonexistent/leeward/rules.toml".into()))
+        .spawn()
+        .unwrap();
+    let response = daemon
+        .client()
+        .unwrap()
+        .request(&Request::Execute(
+            RequestBuilder::new("print(1)").build().unwrap(),
+        ))
+        .unwrap();
+    assert!(
+        matches!(
+            &response,
+            Response::Error { message, kind: ErrorKind::PolicyRejected }
+                if message.contains("could not be loaded")
+        ),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn the_daemon_refuses_and_flags_before_dispatch() {
+    let path = rule_file("daemon", RULES);
+    let daemon = TestDaemon::builder()
+        .mock()
+        .config(|config| config.screening_rules = Some(path.clone()))
+        .spawn()
+        .unwrap();
+    let mut client = daemon.client().unwrap();
+
+    let response = client
+        .request(&Request::Execute(
+            RequestBuilder::new("import os, ctypes\n").build().unwrap(),
+        ))
+        .unwrap();
+    match response {
+        Response::Error { message, kind } => {
+            assert_eq!(kind, ErrorKind::PolicyRejected);
+            assert_eq!(
+                message,
+                "code screening rule 'no-ctypes' refused this code: imports ctypes"
+            );
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert_eq!(
+        daemon.metric("leeward_executions_total{path=\"queued\"}"),
+        Some(0.0)
+    );
+
+    // Flagged code still runs
+    let long = "a = 1\nb = 2\nc = 3\nprint(a + b + c)\n";
+    match client
+        .request(&Request::Execute(
+            RequestBuilder::new(long).build().unwrap(),
+        ))
+        .unwrap()
+    {
+        Response::Execute(response) => assert!(response.success, "{response:?}"),
+        other => panic!("unexpected response: {other:?}"),
+    }
+
+    assert_eq!(
+        daemon.metric("leeward_screening_total{verdict=\"deny\"}"),
+        Some(1.0)
+    );
+    assert_eq!(
+        daemon.metric("leeward_screening_total{verdict=\"flag\"}"),
+        Some(1.0)
+    );
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
@@ -0,0 +1,229 @@
+//! Shared memory slots belong to the connection that leased them: they
+//! come back when it closes or leaves them unused, and a client still
+//! holding the old lease is refused
+
+use leeward_core::protocol::{
+    self, PayloadRef, Request, RequestBuilder, Response, ShmLease, ShmOccupancy,
+};
+use leeward_core::shm::{MappedSharedMemory, SlotPair};
+use leeward_core::{DurationSecs, OutcomeCode, socket};
+use leeward_daemon::testing::TestDaemon;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+fn send(stream: &mut UnixStream, request: &Request) {
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+/// The next answer on `stream`, with any descriptors that came with it
+fn receive(stream: &mut UnixStream) -> (Response, Vec<OwnedFd>) {
+    let mut len = [0u8; 4];
+    let (read, fds) = socket::recv_with_fds(stream.as_fd(), &mut len).unwrap();
+    stream.read_exact(&mut len[read..]).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    (protocol::decode(&body).unwrap(), fds)
+}
+
+fn request(stream: &mut UnixStream, request: &Request) -> Response {
+    send(stream, request);
+    receive(stream).0
+}
+
+/// A slot leased to `stream`'s connection, and the region mapped
+fn lease(stream: &mut UnixStream) -> (ShmLease, MappedSharedMemory) {
+    send(stream, &Request::ShmSetup);
+    let (response, fds) = receive(stream);
+    let Response::ShmSlot(lease) = response else {
+        panic!("unexpected response: {response:?}");
+    };
+    let mapped = MappedSharedMemory::new(fds[0].as_raw_fd(), false).unwrap();
+    (lease, mapped)
+}
+
+fn slot(lease: &ShmLease) -> SlotPair {
+    SlotPair {
+        slot_id: lease.slot_id,
+        generation: lease.generation,
+        request_offset: usize::try_from(lease.request_offset).unwrap(),
+        response_offset: usize::try_from(lease.response_offset).unwrap(),
+        memfd_fd: -1,
+    }
+}
+
+fn write(mapped: &MappedSharedMemory, lease: &ShmLease, code: &str) {
+    mapped.write_request(&slot(lease), code.as_bytes()).unwrap();
+}
+
+fn execute(stream: &mut UnixStream, lease: &ShmLease) -> protocol::ExecuteResponse {
+    let execute = Request::Execute(RequestBuilder::shm(lease).build().unwrap());
+    match request(stream, &execute) {
+        Response::Execute(response) => response,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn occupancy(daemon: &TestDaemon) -> ShmOccupancy {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::StatusDetailed)
+        .unwrap()
+    {
+        Response::StatusDetailed { shm_slots, .. } => shm_slots,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn wait_until_free(daemon: &TestDaemon) -> ShmOccupancy {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let occupancy = occupancy(daemon);
+        if occupancy.leased == 0 {
+            return occupancy;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "slots still leased: {occupancy:?}"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn assert_refused_as_reclaimed(response: &protocol::ExecuteResponse) {
+    assert!(!response.success, "{response:?}");
+    assert_eq!(response.error_code, Some(OutcomeCode::InvalidRequest));
+    assert!(
+        response
+            .error
+            .as_deref()
+            .is_some_and(|e| e.contains("reclaimed")),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn code_written_to_a_slot_runs_until_the_slot_is_freed() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+    let (lease, mapped) = lease(&mut stream);
+    assert_eq!(occupancy(&daemon).leased, 1);
+
+    write(&mapped, &lease, "print('from the slot')");
+    let response = execute(&mut stream, &lease);
+    assert!(response.success, "{response:?}");
+    assert!(response.adjustments.is_empty(), "{response:?}");
+
+    // The output came back through the slot, not the socket
+    let payload = response.shm_payload.unwrap();
+    assert_eq!(payload.stdout, PayloadRef::Slot { offset: 0, len: 22 });
+    let mut result = response.result.unwrap();
+    assert_eq!(result.stdout, [] as [u8; 0]);
+    let output = mapped.read_response(&slot(&lease)).unwrap();
+    assert!(payload.restore(&output, &mut result));
+    assert_eq!(result.stdout, b"print('from the slot')");
+
+    // Another connection cannot use it
+    let mut other = UnixStream::connect(daemon.socket()).unwrap();
+    assert!(!execute(&mut other, &lease).success);
+
+    let free = Request::ShmFree {
+        slot_id: lease.slot_id,
+        generation: lease.generation,
+    };
+    assert!(matches!(
+        request(&mut stream,
+        &free), Response::ShmFreed { slot_id } if slot_id == lease.slot_id
+    ));
+    assert_refused_as_reclaimed(&execute(&mut stream, &lease));
+    assert_eq!(occupancy(&daemon).leased, 0);
+}
+
+#[test]
+fn slots_of_a_vanished_client_return_to_the_pool() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+    let mut crashed = UnixStream::connect(daemon.socket()).unwrap();
+    let (first, mapped) = lease(&mut crashed);
+    lease(&mut crashed);
+    lease(&mut crashed);
+    assert_eq!(occupancy(&daemon).leased, 3);
+    assert_eq!(
+        daemon.metric(r#"leeward_shm_slots{state="leased"}"#),
+        Some(3.0)
+    );
+
+    // Gone without freeing anything, mid-request
+    send(&mut crashed, &Request::Ping);
+    drop(crashed);
+    let after = wait_until_free(&daemon);
+    assert_eq!(after.reclaimed, 3);
+    assert_eq!(
+        daemon.metric(r#"leeward_shm_slots_reclaimed_total{reason="disconnect"}"#),
+        Some(3.0)
+    );
+
+    // The next client gets the same slot in a new generation, and the old
+    // lease, written to by a client that kept its mapping, is refused
+    let mut next = UnixStream::connect(daemon.socket()).unwrap();
+    let (reused, _mapped) = lease(&mut next);
+    assert_eq!(reused.slot_id, first.slot_id);
+    assert_ne!(reused.generation, first.generation);
+    write(&mapped, &first, "print('zombie')");
+    let response = execute(&mut next, &first);
+    assert_refused_as_reclaimed(&response);
+    assert!(
+        response
+            .error
+            .unwrap()
+            .contains(&format!("generation {}", first.generation))
+    );
+}
+
+#[test]
+fn slots_left_unused_are_reclaimed_after_the_ttl() {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config(|config| config.shm_slot_ttl = DurationSecs::from_millis(100))
+        .spawn()
+        .unwrap();
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+    let (lease, mapped) = lease(&mut stream);
+
+    assert_eq!(wait_until_free(&daemon).reclaimed, 1);
+    assert_eq!(
+        daemon.metric(r#"leeward_shm_slots_reclaimed_total{reason="idle"}"#),
+        Some(1.0)
+    );
+    write(&mapped, &lease, "print('late')");
+    assert_refused_as_reclaimed(&execute(&mut stream, &lease));
+}
+
+#[test]
+fn json_connections_cannot_lease_slots() {
+    let daemon = TestDaemon::builder().mock().spawn().unwrap();
+    let mut client = daemon.client().unwrap();
+    assert!(
+        client
+            .daemon_info()
+            .unwrap()
+            .supports(protocol::feature::EXEC_SHM)
+    );
+
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+    stream.write_all(b"{\"type\": \"ShmSetup\"}\n").unwrap();
+    let mut line = String::new();
+    let mut byte = [0u8; 1];
+    while byte[0] != b'\n' {
+        stream.read_exact(&mut byte).unwrap();
+        line.push(char::from(byte[0]));
+    }
+    assert!(line.contains("msgpack"), "{line}");
+    assert_eq!(occupancy(&daemon).leased, 0);
+}
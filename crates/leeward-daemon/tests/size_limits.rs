@@ -0,0 +1,217 @@
+//! The daemon advertises every size it holds requests to, refuses what is
+//! over them with `ErrorKind::TooLarge`, and the client refuses the same
+//! requests the same way before sending them
+
+use leeward_core::client::Client;
+use leeward_core::protocol::{
+    ErrorKind, ExecuteRequest, Request, RequestBuilder, Response, SizeLimit, SizeLimits,
+};
+use leeward_core::{ByteSize, LeewardError};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+const fn small() -> SizeLimits {
+    SizeLimits {
+        max_request: ByteSize::kib(8),
+        max_code: ByteSize::from_bytes(64),
+        max_file: ByteSize::from_bytes(8),
+        max_files: ByteSize::from_bytes(12),
+        max_stdin: ByteSize::from_bytes(16),
+        max_output: ByteSize::from_bytes(10),
+    }
+}
+
+fn daemon(sizes: SizeLimits) -> TestDaemon {
+    TestDaemon::builder()
+        .mock()
+        .config(|config| config.sizes = sizes)
+        .spawn()
+        .unwrap()
+}
+
+/// What the client says about `request`, and what the daemon says when
+/// it is sent anyway
+fn refusals(
+    client: &mut Client,
+    request: &ExecuteRequest,
+) -> ((ErrorKind, String), (ErrorKind, String)) {
+    let local = match client.execute(request.clone()) {
+        Err(LeewardError::Refused { kind, message }) => (kind, message),
+        other => panic!("not refused: {other:?}"),
+    };
+    let daemon = match client.request(&Request::Execute(request.clone())).unwrap() {
+        Response::Error { kind, message } => (kind, message),
+        other => panic!("not refused: {other:?}"),
+    };
+    (local, daemon)
+}
+
+#[test]
+fn advertised_sizes_are_the_ones_enforced() {
+    let daemon = daemon(small());
+    let mut client = daemon.client().unwrap();
+    let limits = client.daemon_info().unwrap().limits;
+    assert_eq!(limits.sizes, small());
+    assert_eq!(limits.max_message_bytes, 8 * 1024);
+    assert_eq!(limits.max_code_bytes, 64);
+
+    // Raised past what the protocol carries, they are advertised as held
+    let huge = SizeLimits {
+        max_request: ByteSize::gib(1),
+        max_output: ByteSize::gib(1),
+        ..SizeLimits::default()
+    };
+    let daemon = self::daemon(huge);
+    let sizes = daemon.client().unwrap().daemon_info().unwrap().limits.sizes;
+    assert_eq!(sizes, SizeLimits::default());
+}
+
+#[test]
+fn client_refuses_what_the_daemon_would_refuse_alike() {
+    let daemon = daemon(small());
+    let mut client = daemon.client().unwrap();
+    let cases = [
+        (RequestBuilder::new("x".repeat(65)), SizeLimit::Code, 65, 64),
+        (
+            RequestBuilder::new("pass").with_file("big", vec![0; 9]),
+            SizeLimit::File,
+            9,
+            8,
+        ),
+        (
+            RequestBuilder::new("pass")
+                .with_file("a", vec![0; 8])
+                .with_file("b", vec![0; 8]),
+            SizeLimit::Files,
+            16,
+            12,
+        ),
+        (
+            RequestBuilder::new("pass").stdin(vec![0; 17]),
+            SizeLimit::Stdin,
+            17,
+            16,
+        ),
+    ];
+    for (builder, limit, bytes, max_bytes) in cases {
+        let request = builder.build().unwrap();
+        let (local, remote) = refusals(&mut client, &request);
+        assert_eq!(local, remote);
+        assert_eq!(
+            local.0,
+            ErrorKind::TooLarge {
+                limit,
+                bytes,
+                max_bytes
+            }
+        );
+        assert!(
+            local.1.contains(&format!("limit of {max_bytes} bytes")),
+            "{}",
+            local.1
+        );
+        if limit == SizeLimit::File {
+            assert!(
+                local.1.starts_with("input file big is 9 bytes"),
+                "{}",
+                local.1
+            );
+        }
+    }
+
+    // At the limits the request runs
+    let request = RequestBuilder::new("pass")
+        .with_file("a", vec![0; 8])
+        .with_file("b", vec![0; 4])
+        .stdin(vec![0; 16])
+        .build()
+        .unwrap();
+    assert!(client.execute(request).unwrap().success);
+}
+
+#[test]
+fn requests_over_the_message_size_are_refused_unsent() {
+    let daemon = daemon(SizeLimits {
+        max_request: ByteSize::kib(4),
+        ..SizeLimits::default()
+    });
+    let mut client = daemon.client().unwrap();
+    client.daemon_info().unwrap();
+
+    let request = RequestBuilder::new("x".repeat(8 * 1024)).build().unwrap();
+    let Err(LeewardError::Refused { kind, message }) = client.execute(request) else {
+        panic!("not refused");
+    };
+    assert_eq!(kind, ErrorKind::Malformed);
+    assert!(
+        message.contains("4096 byte message size limit"),
+        "{message}"
+    );
+    // Nothing was sent, so the connection carries on
+    assert!(!client.is_poisoned());
+    assert!(matches!(
+        client.request(&Request::Ping).unwrap(),
+        Response::Pong
+    ));
+}
+
+#[test]
+fn output_past_the_limit_is_cut_off_and_flagged() {
+    let daemon = daemon(small());
+    let mut client = daemon.client().unwrap();
+    // Mock workers answer with the code on stdout
+    let response = client
+        .execute(RequestBuilder::new("x".repeat(32)).build().unwrap())
+        .unwrap();
+    let result = response.result.unwrap();
+    assert_eq!(result.stdout, b"x".repeat(10));
+    assert!(result.stdout_truncated);
+    assert!(!result.stderr_truncated);
+
+    let response = client
+        .execute(RequestBuilder::new("x".repeat(10)).build().unwrap())
+        .unwrap();
+    assert!(!response.result.unwrap().stdout_truncated);
+}
+
+#[test]
+fn a_raised_limit_reaches_a_client_reconnecting_after_handover() {
+    let old = daemon(small());
+    let mut client = old.client().unwrap();
+    let request = RequestBuilder::new("pass")
+        .stdin(vec![0; 64])
+        .build()
+        .unwrap();
+    let Err(LeewardError::Refused { kind, .. }) = client.execute(request.clone()) else {
+        panic!("not refused");
+    };
+    assert!(matches!(
+        kind,
+        ErrorKind::TooLarge {
+            limit: SizeLimit::Stdin,
+            ..
+        }
+    ));
+
+    // A new daemon with the default limits takes the socket over
+    let new = TestDaemon::builder()
+        .mock()
+        .take_over(old.socket())
+        .spawn()
+        .unwrap();
+    assert!(old.wait_stopped(Duration::from_secs(10)));
+
+    // The old connection is gone; the next request finds that out and the
+    // one after runs on a new connection, under the new daemon's limits
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !matches!(client.request(&Request::Ping), Ok(Response::Pong)) {
+        assert!(Instant::now() < deadline, "client did not reconnect");
+    }
+    assert_eq!(
+        client.daemon_info().unwrap().limits.sizes,
+        SizeLimits::default()
+    );
+    let response = client.execute(request).unwrap();
+    assert!(response.success, "{response:?}");
+    drop(new);
+}
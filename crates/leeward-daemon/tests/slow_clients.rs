@@ -0,0 +1,75 @@
+//! A client slow to read its response holds up only itself: the worker is
+//! released once its result is read, not once the response is written
+
+use leeward_core::protocol::{self, Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Far more than a socket buffers, so writing the echo blocks until read
+const BIG: usize = 900 * 1024;
+
+fn send_frame(stream: &mut UnixStream, request: &Request) {
+    let body = protocol::encode(request).unwrap();
+    stream
+        .write_all(&u32::try_from(body.len()).unwrap().to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+fn read_frame(stream: &mut UnixStream) -> Response {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    protocol::decode(&body).unwrap()
+}
+
+#[test]
+fn a_slow_reader_does_not_hold_the_worker() {
+    let daemon = TestDaemon::builder().workers(1).mock().spawn().unwrap();
+
+    let code = format!("#{}\n", "x".repeat(BIG));
+    let mut slow = UnixStream::connect(daemon.socket()).unwrap();
+    send_frame(
+        &mut slow,
+        &Request::Execute(RequestBuilder::new(&code).build().unwrap()),
+    );
+    assert_eq!(
+        daemon.wait_for_metric(
+            "leeward_result_to_idle_seconds_count",
+            1.0,
+            Duration::from_secs(10)
+        ),
+        Some(1.0)
+    );
+
+    // The only worker serves someone else while the echo sits unread
+    let mut other = UnixStream::connect(daemon.socket()).unwrap();
+    other
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    send_frame(
+        &mut other,
+        &Request::Execute(RequestBuilder::new("print(1)").build().unwrap()),
+    );
+    match read_frame(&mut other) {
+        Response::Execute(response) => assert!(response.success, "{response:?}"),
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert_eq!(
+        daemon.metric("leeward_result_to_idle_seconds_count"),
+        Some(2.0)
+    );
+    assert_eq!(
+        daemon.metric("leeward_result_to_idle_seconds_bucket{le=\"+Inf\"}"),
+        Some(2.0)
+    );
+
+    // And the slow reader still gets all of its response
+    match read_frame(&mut slow) {
+        Response::Execute(response) => assert_eq!(response.result.unwrap().stdout, code.as_bytes()),
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
@@ -0,0 +1,107 @@
+//! Standby workers are held back from normal scheduling: a high-priority
+//! request that would queue takes one at once, a replacement stands by
+//! again once a worker is free, and other requests queue as before
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{Request, RequestBuilder, RequestPriority, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+const LATENCY: Duration = Duration::from_millis(600);
+
+fn start() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(2)
+        .mock_latency(LATENCY)
+        .config(|config| {
+            config.standby_workers = 1;
+            config.status_refresh_interval = DurationSecs::from_millis(10);
+        })
+        .spawn()
+        .unwrap()
+}
+
+/// Run `pass` at `priority`, returning how long it took
+fn execute(daemon: &TestDaemon, priority: RequestPriority) -> Duration {
+    let request = RequestBuilder::new("pass")
+        .priority(priority)
+        .build()
+        .unwrap();
+    let started = Instant::now();
+    let response = daemon
+        .client()
+        .unwrap()
+        .request(&Request::Execute(request))
+        .unwrap();
+    assert!(matches!(response, Response::Execute(_)), "{response:?}");
+    started.elapsed()
+}
+
+#[test]
+fn standbys_are_counted_apart() {
+    let daemon = start();
+    let Response::StatusDetailed { total, standby, .. } = daemon
+        .client()
+        .unwrap()
+        .request(&Request::StatusDetailed)
+        .unwrap()
+    else {
+        panic!("not a detailed status response");
+    };
+    assert_eq!((total, standby), (2, 1));
+    assert_eq!(daemon.metric("leeward_pool_standby_workers"), Some(1.0));
+    assert_eq!(
+        daemon.metric("leeward_pool_workers{state=\"idle\"}"),
+        Some(2.0)
+    );
+
+    // With a worker idle, a high-priority request leaves the standby be
+    execute(&daemon, RequestPriority::High);
+    assert_eq!(
+        daemon.metric("leeward_pool_standby_promotions_total"),
+        Some(0.0)
+    );
+}
+
+#[test]
+fn a_high_priority_request_takes_a_standby_while_batch_ones_queue() {
+    let daemon = start();
+    let (high, batch) = std::thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(|| execute(&daemon, RequestPriority::Normal));
+        }
+        let busy = "leeward_pool_workers{state=\"busy\"}";
+        assert_eq!(
+            daemon.wait_for_metric(busy, 2.0, Duration::from_secs(5)),
+            Some(2.0)
+        );
+
+        let batch = scope.spawn(|| execute(&daemon, RequestPriority::Batch));
+        let high = scope.spawn(|| execute(&daemon, RequestPriority::High));
+        (high.join().unwrap(), batch.join().unwrap())
+    });
+
+    // Served by the standby at once, while the batch request waited for a
+    // worker to finish first
+    assert!(
+        high < LATENCY + LATENCY / 2,
+        "high-priority request took {high:?}"
+    );
+    assert!(
+        batch >= LATENCY + LATENCY / 2,
+        "batch request took only {batch:?}"
+    );
+    assert_eq!(
+        daemon.metric("leeward_pool_standby_promotions_total"),
+        Some(1.0)
+    );
+
+    // A replacement stands by once the queue is empty
+    let standby =
+        daemon.wait_for_metric("leeward_pool_standby_workers", 1.0, Duration::from_secs(5));
+    assert_eq!(standby, Some(1.0));
+    assert_eq!(
+        daemon.metric("leeward_pool_workers{state=\"idle\"}"),
+        Some(2.0)
+    );
+}
@@ -0,0 +1,124 @@
+//! Status, metrics and pings answer from the pool's snapshot, so they keep
+//! answering while a pool lock is wedged and say how stale they are
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{Request, RequestBuilder, Response};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+/// Longest a status request may take while the pool is wedged
+const PROMPT: Duration = Duration::from_millis(500);
+
+fn daemon() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(2)
+        .mock()
+        .config(|config| config.status_refresh_interval = DurationSecs::from_millis(20))
+        .spawn()
+        .unwrap()
+}
+
+/// `request`'s response, checking it came back within [`PROMPT`]
+fn promptly(daemon: &TestDaemon, request: &Request) -> Response {
+    let mut client = daemon.client().unwrap();
+    let started = Instant::now();
+    let response = client.request(request).unwrap();
+    assert!(
+        started.elapsed() < PROMPT,
+        "{request:?} took {:?}",
+        started.elapsed()
+    );
+    response
+}
+
+fn execute(daemon: &TestDaemon, code: &str) {
+    let request = Request::Execute(RequestBuilder::new(code).build().unwrap());
+    match daemon.client().unwrap().request(&request).unwrap() {
+        Response::Execute(response) => assert!(response.success, "{response:?}"),
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn status_age(daemon: &TestDaemon) -> u64 {
+    match promptly(daemon, &Request::Status) {
+        Response::Status {
+            snapshot_age_ms, ..
+        } => snapshot_age_ms,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn status_answers_with_its_age_while_the_pool_is_wedged() {
+    let daemon = daemon();
+    execute(&daemon, "warm");
+
+    let wedge = daemon.wedge(Duration::from_secs(2));
+    std::thread::sleep(Duration::from_millis(800));
+
+    let Response::Status {
+        total,
+        idle,
+        snapshot_age_ms,
+        ..
+    } = promptly(&daemon, &Request::Status)
+    else {
+        panic!("not a status response");
+    };
+    // Last seen idle, before the locks were taken
+    assert_eq!((total, idle), (2, 2));
+    assert!(
+        snapshot_age_ms >= 700,
+        "status only {snapshot_age_ms}ms old"
+    );
+
+    let Response::StatusDetailed {
+        total,
+        snapshot_age_ms,
+        ..
+    } = promptly(&daemon, &Request::StatusDetailed)
+    else {
+        panic!("not a detailed status response");
+    };
+    assert_eq!(total, 2);
+    assert!(
+        snapshot_age_ms >= 700,
+        "detailed status only {snapshot_age_ms}ms old"
+    );
+
+    assert!(matches!(promptly(&daemon, &Request::Ping), Response::Pong));
+    let age = daemon.metric("leeward_pool_snapshot_age_seconds").unwrap();
+    assert!(age >= 0.7, "metrics only {age}s old");
+
+    wedge.join().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while status_age(&daemon) >= 200 {
+        assert!(
+            Instant::now() < deadline,
+            "status still stale after the pool came unstuck"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn status_follows_executions_without_going_stale() {
+    let daemon = daemon();
+    for _ in 0..3 {
+        execute(&daemon, "hello");
+    }
+
+    let Response::Status {
+        total, idle, busy, ..
+    } = promptly(&daemon, &Request::Status)
+    else {
+        panic!("not a status response");
+    };
+    assert_eq!((total, idle, busy), (2, 2, 0));
+    assert!(status_age(&daemon) < 200);
+    assert_eq!(
+        daemon.metric(r#"leeward_pool_workers{state="idle"}"#),
+        Some(2.0)
+    );
+    assert_eq!(daemon.metric("leeward_pool_queue_depth"), Some(0.0));
+}
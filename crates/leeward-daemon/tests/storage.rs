@@ -0,0 +1,162 @@
+//! On-disk state survives a writer killed at any byte: an appended line
+//! that was acknowledged is never lost, a file replaced whole holds its old
+//! contents or its new, and a spool file that cannot be read is moved aside
+//! rather than stopping the daemon
+
+mod common;
+
+use leeward_core::protocol::{Request, RequestBuilder, Response};
+use leeward_daemon::storage::{self, JsonlWriter, QUARANTINE_DIR};
+use leeward_daemon::testing::TestDaemon;
+use std::path::Path;
+
+fn entry(n: usize) -> String {
+    format!(
+        r#"{{"type":"entry","n":{n},"padding":"{}"}}"#,
+        "x".repeat(n % 7)
+    )
+}
+
+/// Lines in the log at `path`, each of which must parse
+fn read_entries(path: &Path) -> Vec<String> {
+    let contents = std::fs::read_to_string(path).unwrap();
+    assert!(
+        contents.is_empty() || contents.ends_with('\n'),
+        "torn tail: {contents:?}"
+    );
+    contents
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+            line.to_owned()
+        })
+        .collect()
+}
+
+/// A tiny deterministic generator, so a failure can be replayed
+fn offsets(seed: u64, count: usize, below: usize) -> impl Iterator<Item = usize> {
+    let mut state = seed;
+    (0..count).map(move |_| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        usize::try_from(state >> 33).unwrap() % below
+    })
+}
+
+#[test]
+fn a_line_torn_at_any_byte_is_cut_off_and_nothing_before_it_lost() {
+    let dir = common::Scratch::new("storage-every-byte");
+    let path = dir.join("audit.jsonl");
+    let next = entry(3);
+
+    for offset in 0..=next.len() {
+        let _ = std::fs::remove_file(&path);
+        let mut writer = JsonlWriter::open(&path).unwrap();
+        let acknowledged: Vec<String> = (0..3).map(entry).collect();
+        for line in &acknowledged {
+            writer.append(line).unwrap();
+        }
+        writer.crash_after(offset);
+        assert!(writer.append(&next).is_err());
+        drop(writer);
+
+        let mut writer = JsonlWriter::open(&path).unwrap();
+        assert_eq!(read_entries(&path), acknowledged, "torn at byte {offset}");
+        writer.append(&entry(4)).unwrap();
+        assert_eq!(read_entries(&path).len(), 4);
+    }
+}
+
+#[test]
+fn acknowledged_lines_survive_crashes_at_random_offsets() {
+    let dir = common::Scratch::new("storage-random");
+    let path = dir.join("audit.jsonl");
+    let mut acknowledged = Vec::new();
+    let mut n = 0;
+
+    for budget in offsets(0x1ee_4a2d, 200, 400) {
+        let mut writer = JsonlWriter::open(&path).unwrap();
+        assert_eq!(read_entries(&path), acknowledged);
+        writer.crash_after(budget);
+        loop {
+            let line = entry(n);
+            n += 1;
+            match writer.append(&line) {
+                Ok(()) => acknowledged.push(line),
+                Err(_) => break,
+            }
+        }
+    }
+    drop(JsonlWriter::open(&path).unwrap());
+    assert_eq!(read_entries(&path), acknowledged);
+    assert!(acknowledged.len() > 100);
+}
+
+#[test]
+fn lines_cannot_hold_newlines() {
+    let dir = common::Scratch::new("storage-newline");
+    let path = dir.join("audit.jsonl");
+    let mut writer = JsonlWriter::open(&path).unwrap();
+    assert!(writer.append("{}\n{}").is_err());
+    writer.append("{}").unwrap();
+    assert_eq!(read_entries(&path), ["{}"]);
+}
+
+#[test]
+fn a_file_replaced_whole_holds_the_old_or_the_new() {
+    let dir = common::Scratch::new("storage-replace");
+    let path = dir.join("7.result");
+    let old = b"the old contents".repeat(10);
+    let new = b"the new contents, longer".repeat(10);
+    storage::create(&path, &old).unwrap();
+    assert_eq!(
+        storage::create(&path, &new).unwrap_err().kind(),
+        std::io::ErrorKind::AlreadyExists
+    );
+
+    for offset in offsets(7, 20, new.len()) {
+        storage::crash_replacing(&path, &new, offset).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), old);
+        assert_eq!(storage::remove_staged(&dir), 1);
+    }
+    storage::replace(&path, &new).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), new);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn unreadable_spool_files_are_quarantined_at_startup() {
+    let spool = common::Scratch::new("storage-spool");
+    std::fs::write(spool.join("5.result"), b"\xc1 not a spooled result").unwrap();
+    storage::crash_replacing(&spool.join("6.result"), b"half of a result", 4).unwrap();
+
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .mock()
+        .config({
+            let spool = spool.to_path_buf();
+            move |config| config.spool_dir = spool
+        })
+        .spawn()
+        .unwrap();
+    assert!(!spool.join("5.result").exists());
+    assert!(!spool.join("6.result.tmp").exists());
+    assert_eq!(
+        std::fs::read(spool.join(QUARANTINE_DIR).join("5.result")).unwrap(),
+        b"\xc1 not a spooled result"
+    );
+
+    // Ids past the quarantined file's, which a client may still ask after
+    let request = Request::Execute(
+        RequestBuilder::new("print(1)")
+            .detach(true)
+            .build()
+            .unwrap(),
+    );
+    match daemon.client().unwrap().request(&request).unwrap() {
+        Response::Detached { execution_id } => assert!(execution_id > 5),
+        other => panic!("not detached: {other:?}"),
+    }
+    drop(daemon);
+}
@@ -0,0 +1,149 @@
+//! Background tasks that panic are restarted and counted, and one that
+//! keeps panicking puts the daemon in maintenance while requests are still
+//! answered
+
+mod common;
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{
+    AlertKind, AlertState, ErrorKind, EventKind, Request, RequestBuilder, Response,
+};
+use leeward_daemon::testing::TestDaemon;
+use std::time::Duration;
+
+fn start() -> TestDaemon {
+    TestDaemon::builder()
+        .workers(2)
+        .mock()
+        .config(|config| {
+            config.task_restart_backoff = DurationSecs::from_millis(5);
+            config.task_failure_limit = 3;
+            config.status_refresh_interval = DurationSecs::from_millis(10);
+            config.alert_sample_interval = DurationSecs::from_millis(10);
+        })
+        .spawn()
+        .unwrap()
+}
+
+fn restarts(task: &str) -> String {
+    format!("leeward_task_restarts_total{{task=\"{task}\"}}")
+}
+
+fn healthy(daemon: &TestDaemon) -> bool {
+    match daemon
+        .client()
+        .unwrap()
+        .request(&Request::Health { min_idle: None })
+        .unwrap()
+    {
+        Response::Health { healthy, .. } => healthy,
+        other => panic!("not a health response: {other:?}"),
+    }
+}
+
+fn execute(daemon: &TestDaemon) -> Response {
+    let request = Request::Execute(RequestBuilder::new("print(1)").build().unwrap());
+    daemon.client().unwrap().request(&request).unwrap()
+}
+
+#[test]
+fn a_panicking_task_is_restarted_and_counted() {
+    let daemon = start();
+    daemon.inject_panic("snapshot", 2);
+
+    let series = restarts("snapshot");
+    assert_eq!(
+        daemon.wait_for_metric(&series, 2.0, Duration::from_secs(5)),
+        Some(2.0)
+    );
+    // Below the limit, so the daemon carries on as before
+    assert!(healthy(&daemon));
+    assert!(matches!(execute(&daemon), Response::Execute(_)));
+    assert_eq!(daemon.metric(&restarts("alerts")), Some(0.0));
+
+    // The restarted task keeps the snapshot fresh again
+    std::thread::sleep(Duration::from_millis(200));
+    let age = daemon.metric("leeward_pool_snapshot_age_seconds").unwrap();
+    assert!(age < 0.1, "{age}");
+}
+
+#[test]
+fn a_task_that_keeps_panicking_puts_the_daemon_in_maintenance() {
+    let daemon = start();
+    let mut events = daemon.subscribe();
+    assert!(healthy(&daemon));
+    daemon.inject_panic("alerts", 3);
+
+    let event = events
+        .wait_for(EventKind::Alert, Duration::from_secs(5))
+        .expect("no alert");
+    let alert = event.alert.unwrap();
+    assert_eq!(
+        (alert.alert, alert.state, alert.value),
+        (AlertKind::TaskFailing, AlertState::Firing, 3)
+    );
+    assert!(
+        event.message.contains("alerts") && event.message.contains("injected panic"),
+        "{}",
+        event.message
+    );
+
+    // Restarted twice, then left stopped on the third panic
+    assert_eq!(daemon.metric(&restarts("alerts")), Some(2.0));
+    assert_eq!(
+        daemon.metric("leeward_alerts_total{kind=\"task_failing\"}"),
+        Some(1.0)
+    );
+
+    // Still answering, but unhealthy and running nothing
+    assert!(!healthy(&daemon));
+    match execute(&daemon) {
+        Response::Error { kind, message } => {
+            assert_eq!(kind, ErrorKind::Maintenance);
+            assert!(message.contains("alerts"), "{message}");
+        }
+        other => panic!("not refused: {other:?}"),
+    }
+    assert!(matches!(
+        daemon.client().unwrap().request(&Request::Ping).unwrap(),
+        Response::Pong
+    ));
+}
+
+#[test]
+fn the_release_profile_lets_panics_unwind() {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/../../Cargo.toml");
+    let manifest: toml::Table = std::fs::read_to_string(manifest).unwrap().parse().unwrap();
+    let strategy = manifest["profile"]["release"]
+        .get("panic")
+        .and_then(toml::Value::as_str)
+        .unwrap_or("unwind");
+
+    // Tests always unwind, so build a program of their own with the
+    // release strategy: a thread that panics, joined as a task would be
+    let dir = common::Scratch::new("panic");
+    let source = dir.join("probe.rs");
+    std::fs::write(
+        &source,
+        "fn main() { assert!(std::thread::spawn(|| panic!()).join().is_err()); }",
+    )
+    .unwrap();
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let built = std::process::Command::new(rustc)
+        .arg("-C")
+        .arg(format!("panic={strategy}"))
+        .arg("-o")
+        .arg(dir.join("probe"))
+        .arg(&source)
+        .status()
+        .unwrap();
+    assert!(built.success());
+    let ran = std::process::Command::new(dir.join("probe"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(
+        ran.success(),
+        "a panic under panic = \"{strategy}\" ended the process: {ran}"
+    );
+}
@@ -0,0 +1,301 @@
+//! Input files too large to send inline are uploaded in chunks, in any
+//! order, resumed after a dropped connection, verified against their hash
+//! and held to a per-client quota
+
+use leeward_core::client::Client;
+use leeward_core::protocol::{
+    self, ErrorKind, Request, RequestBuilder, Response, UploadRange, UploadStatus,
+};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const MIB: usize = 1024 * 1024;
+
+/// Running daemon, killed on drop
+struct Daemon {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Daemon {
+    fn start(name: &str, settings: &[(&str, &str)]) -> Option<Self> {
+        let dir =
+            std::env::temp_dir().join(format!("leeward-uploads-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("leeward.sock");
+        let child = Command::new(env!("CARGO_BIN_EXE_leeward-daemon"))
+            .env("LEEWARD_SOCKET", &socket)
+            .env("LEEWARD_WORKERS", "1")
+            .env("LEEWARD_METRICS_PORT", "0")
+            .envs(settings.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Self { child, dir, socket };
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if Client::connect(&daemon.socket).is_ok() {
+                return Some(daemon);
+            }
+            if daemon.child.try_wait().unwrap().is_some() {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    fn client(&self) -> Client {
+        Client::connect(&self.socket).unwrap()
+    }
+}
+
+/// Bytes that differ from chunk to chunk, so misplaced chunks show
+fn contents(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| u8::try_from((i * 7 + i / 4096) % 251).unwrap())
+        .collect()
+}
+
+fn begin(client: &mut Client, name: &str, data: &[u8]) -> Response {
+    client
+        .request(&Request::UploadBegin {
+            name: name.into(),
+            total_len: data.len() as u64,
+            sha256: protocol::sha256_hex(data),
+            reusable: false,
+        })
+        .unwrap()
+}
+
+fn status(response: Response) -> UploadStatus {
+    match response {
+        Response::Upload(status) => status,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn chunk(client: &mut Client, id: u64, data: &[u8], start: usize, end: usize) -> UploadStatus {
+    status(
+        client
+            .request(&Request::UploadChunk {
+                id,
+                offset: start as u64,
+                data: data[start..end].to_vec(),
+            })
+            .unwrap(),
+    )
+}
+
+/// Run a script that prints the length and hash of `name` in the workdir
+fn hash_in_sandbox(client: &mut Client, name: &str, id: u64) -> Response {
+    let code = format!(
+        "import hashlib\ndata = open({name:?}, 'rb').read()\nprint(len(data), \
+         hashlib.sha256(data).hexdigest())"
+    );
+    let request = RequestBuilder::new(code)
+        .with_upload(name, id)
+        .build()
+        .unwrap();
+    client.request(&Request::Execute(request)).unwrap()
+}
+
+/// Check the sandbox saw exactly `data`, unless this host cannot stage
+/// input files at all
+fn assert_hashed(response: Response, data: &[u8]) {
+    let Response::Execute(resp) = response else {
+        panic!("unexpected response: {response:?}");
+    };
+    let result = resp
+        .result
+        .unwrap_or_else(|| panic!("not run: {:?}", resp.error));
+    if result.exit_code != 0 {
+        eprintln!("skipping the sandbox check: {}", result.stderr_str());
+        return;
+    }
+    assert_eq!(
+        result.stdout_str(),
+        format!("{} {}\n", data.len(), protocol::sha256_hex(data))
+    );
+}
+
+#[test]
+fn chunks_out_of_order_arrive_intact() {
+    let Some(daemon) = Daemon::start("order", &[]) else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+    let data = contents(3 * MIB + 12_345);
+    let mut client = daemon.client();
+
+    let begun = status(begin(&mut client, "big.bin", &data));
+    assert!(!begun.committed);
+    assert_eq!(begun.received_bytes, 0);
+    assert_eq!(
+        begun.missing,
+        [UploadRange {
+            start: 0,
+            end: data.len() as u64
+        }]
+    );
+
+    // Last chunk first, leaving a hole, then one overlapping two others
+    let last = chunk(&mut client, begun.id, &data, 3 * MIB, data.len());
+    assert_eq!(
+        last.missing,
+        [UploadRange {
+            start: 0,
+            end: 3 * MIB as u64
+        }]
+    );
+    chunk(&mut client, begun.id, &data, MIB, 2 * MIB);
+    let holed = chunk(&mut client, begun.id, &data, 0, MIB / 2);
+    assert_eq!(
+        holed.missing,
+        [
+            UploadRange {
+                start: MIB as u64 / 2,
+                end: MIB as u64
+            },
+            UploadRange {
+                start: 2 * MIB as u64,
+                end: 3 * MIB as u64
+            },
+        ]
+    );
+    chunk(&mut client, begun.id, &data, 2 * MIB, 3 * MIB);
+    let whole = chunk(&mut client, begun.id, &data, MIB / 4, MIB + MIB / 4);
+    assert_eq!(whole.received_bytes, data.len() as u64);
+    assert_eq!(whole.missing, []);
+
+    let committed = status(
+        client
+            .request(&Request::UploadCommit { id: begun.id })
+            .unwrap(),
+    );
+    assert!(committed.committed);
+
+    assert_hashed(hash_in_sandbox(&mut client, "big.bin", begun.id), &data);
+
+    // Single use: the first execution consumed it
+    match hash_in_sandbox(&mut client, "big.bin", begun.id) {
+        Response::Execute(resp) => {
+            assert!(!resp.success);
+            assert!(resp.error.unwrap().contains("no upload"));
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+
+    // The one worker read the whole stream, so it is in step for the next job
+    let next = RequestBuilder::new("print('next')").build().unwrap();
+    match client.request(&Request::Execute(next)).unwrap() {
+        Response::Execute(resp) => {
+            let result = resp
+                .result
+                .unwrap_or_else(|| panic!("not run: {:?}", resp.error));
+            if result.exit_code == 0 {
+                assert_eq!(result.stdout_str(), "next\n");
+            }
+        }
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+#[test]
+fn upload_resumes_after_disconnect() {
+    let Some(daemon) = Daemon::start("resume", &[]) else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+    let data = contents(2 * MIB + 100);
+
+    let id = {
+        let mut client = daemon.client();
+        let begun = status(begin(&mut client, "resumed.bin", &data));
+        chunk(&mut client, begun.id, &data, 0, MIB);
+        begun.id
+    };
+
+    // A new connection beginning the same upload picks it up
+    let mut client = daemon.client();
+    let resumed = status(begin(&mut client, "resumed.bin", &data));
+    assert_eq!(resumed.id, id);
+    assert_eq!(resumed.received_bytes, MIB as u64);
+    assert_eq!(
+        resumed.missing,
+        [UploadRange {
+            start: MIB as u64,
+            end: data.len() as u64
+        }]
+    );
+    assert_eq!(client.upload("resumed.bin", &data, false).unwrap(), id);
+    assert_hashed(hash_in_sandbox(&mut client, "resumed.bin", id), &data);
+
+    // Contents that do not match the declared hash are discarded at commit
+    let declared = contents(1000);
+    let begun = status(begin(&mut client, "corrupt.bin", &declared));
+    let mut corrupt = declared.clone();
+    corrupt[500] ^= 0xff;
+    chunk(&mut client, begun.id, &corrupt, 0, corrupt.len());
+    match client
+        .request(&Request::UploadCommit { id: begun.id })
+        .unwrap()
+    {
+        Response::Error { message, .. } => assert!(message.contains("discarded"), "{message}"),
+        other => panic!("unexpected response: {other:?}"),
+    }
+    assert!(matches!(
+        client
+            .request(&Request::UploadCommit { id: begun.id })
+            .unwrap(),
+        Response::Error { .. }
+    ));
+}
+
+#[test]
+fn uploads_past_the_quota_are_rejected_until_others_expire() {
+    let Some(daemon) = Daemon::start(
+        "quota",
+        &[
+            ("LEEWARD_UPLOAD_QUOTA", "1MiB"),
+            ("LEEWARD_UPLOAD_TTL", "1s"),
+        ],
+    ) else {
+        eprintln!("skipping: leeward-daemon exited during startup");
+        return;
+    };
+    let mut client = daemon.client();
+    let first = contents(768 * 1024);
+    let second = contents(512 * 1024);
+
+    let begun = status(begin(&mut client, "first.bin", &first));
+    // Resuming does not count the upload twice
+    assert_eq!(status(begin(&mut client, "first.bin", &first)).id, begun.id);
+
+    match begin(&mut client, "second.bin", &second) {
+        Response::Error { kind, .. } => assert_eq!(
+            kind,
+            ErrorKind::QuotaExceeded {
+                used_bytes: 768 * 1024,
+                quota_bytes: 1024 * 1024
+            }
+        ),
+        other => panic!("unexpected response: {other:?}"),
+    }
+
+    // Abandoned, the first upload expires and frees its share
+    std::thread::sleep(Duration::from_millis(2500));
+    assert!(!status(begin(&mut client, "second.bin", &second)).committed);
+}
@@ -0,0 +1,168 @@
+//! A worker that exits on its own says goodbye and is reported as exited;
+//! one whose pipe closes without a goodbye crashed, and is reported as
+//! dead. Both are replaced.
+
+use leeward_core::protocol::{EventKind, Request, RequestBuilder, Response};
+use leeward_core::worker::{GoodbyeReason, WorkerState};
+use leeward_core::{ByteSize, DurationSecs, SandboxConfig};
+use leeward_daemon::testing::TestDaemon;
+use std::time::{Duration, Instant};
+
+fn start(sandbox: SandboxConfig, check_interval: DurationSecs) -> Option<TestDaemon> {
+    let daemon = TestDaemon::builder()
+        .workers(1)
+        .sandbox(sandbox)
+        .config(|config| config.worker_check_interval = check_interval)
+        .spawn()
+        .unwrap();
+    if daemon.live_workers() == 0 {
+        eprintln!("skipping: workers cannot start here");
+        return None;
+    }
+    Some(daemon)
+}
+
+fn run(daemon: &TestDaemon) {
+    let request = Request::Execute(RequestBuilder::new("print(1)").build().unwrap());
+    match daemon.client().unwrap().request(&request).unwrap() {
+        Response::Execute(response) => assert!(response.success, "{response:?}"),
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+/// Pid of the only worker, once it is idle
+fn idle_pid(daemon: &TestDaemon) -> i32 {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let Response::WorkerList { workers, .. } = daemon
+            .client()
+            .unwrap()
+            .request(&Request::ListWorkers)
+            .unwrap()
+        else {
+            panic!("not a worker list");
+        };
+        if let (WorkerState::Idle, Some(pid)) = (workers[0].state, workers[0].pid) {
+            return pid;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "worker never came back: {:?}",
+            workers[0]
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn kill(pid: i32) {
+    // SAFETY: Killing a worker process of the daemon under test
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGKILL) }, 0);
+}
+
+#[test]
+fn a_worker_saying_goodbye_is_replaced_without_a_death() {
+    let sandbox = SandboxConfig {
+        worker_max_rss: Some(ByteSize::from_bytes(1)),
+        ..SandboxConfig::minimal_for_tests()
+    };
+    let Some(daemon) = start(sandbox, DurationSecs::from_millis(20)) else {
+        return;
+    };
+    let mut events = daemon.subscribe();
+    let before = idle_pid(&daemon);
+
+    run(&daemon);
+    let event = events
+        .wait_for(EventKind::WorkerExited, Duration::from_secs(10))
+        .unwrap();
+    let exited = event.worker_exited.unwrap();
+    assert_eq!(exited.worker_id, 0);
+    assert!(
+        matches!(
+            exited.reason,
+            GoodbyeReason::MemoryGrown { limit, .. } if limit == ByteSize::from_bytes(1)
+        ),
+        "{exited:?}"
+    );
+    assert_ne!(idle_pid(&daemon), before);
+
+    // Its replacement serves, then says goodbye in turn
+    run(&daemon);
+    assert!(
+        events
+            .wait_for(EventKind::WorkerExited, Duration::from_secs(10))
+            .is_some()
+    );
+    assert!(
+        events
+            .drain()
+            .iter()
+            .all(|event| event.kind != EventKind::WorkerDied)
+    );
+    assert_eq!(
+        daemon.metric("leeward_worker_exits_total{cause=\"memory_grown\"}"),
+        Some(2.0)
+    );
+    assert_eq!(
+        daemon.metric("leeward_worker_exits_total{cause=\"died\"}"),
+        Some(0.0)
+    );
+}
+
+#[test]
+fn an_idle_crash_is_reported_and_replaced() {
+    let Some(daemon) = start(
+        SandboxConfig::minimal_for_tests(),
+        DurationSecs::from_millis(20),
+    ) else {
+        return;
+    };
+    let mut events = daemon.subscribe();
+    let before = idle_pid(&daemon);
+
+    kill(before);
+    let event = events
+        .wait_for(EventKind::WorkerDied, Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(event.worker_died.unwrap().worker_id, 0);
+    assert!(
+        event.message.contains("killed by signal 9"),
+        "{}",
+        event.message
+    );
+    assert_ne!(idle_pid(&daemon), before);
+    assert_eq!(
+        daemon.metric("leeward_worker_exits_total{cause=\"died\"}"),
+        Some(1.0)
+    );
+
+    run(&daemon);
+    assert!(
+        events
+            .drain()
+            .iter()
+            .all(|event| event.kind != EventKind::WorkerExited)
+    );
+}
+
+#[test]
+fn a_crash_found_at_dispatch_does_not_fail_the_request() {
+    // No timer, so only dispatch can notice
+    let Some(daemon) = start(SandboxConfig::minimal_for_tests(), DurationSecs::ZERO) else {
+        return;
+    };
+    let mut events = daemon.subscribe();
+    kill(idle_pid(&daemon));
+    std::thread::sleep(Duration::from_millis(100));
+
+    run(&daemon);
+    assert!(
+        events
+            .wait_for(EventKind::WorkerDied, Duration::ZERO)
+            .is_some()
+    );
+    assert_eq!(
+        daemon.metric("leeward_worker_exits_total{cause=\"died\"}"),
+        Some(1.0)
+    );
+}
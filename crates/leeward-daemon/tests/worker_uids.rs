@@ -0,0 +1,155 @@
+//! Workers keep their pool index across respawns but each process gets a
+//! uid of its own, under the daemon's boot id
+//!
+//! Real cgroups need a delegated cgroup v2 directory in
+//! `LEEWARD_TEST_CGROUP_ROOT` and are skipped without one.
+
+use leeward_core::DurationSecs;
+use leeward_core::protocol::{DEFAULT_PROFILE, Request, Response, WorkerInfo, WorkerSlot};
+use leeward_core::worker::WorkerUid;
+use leeward_daemon::testing::TestDaemon;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+fn request(daemon: &TestDaemon, request: &Request) -> Response {
+    daemon.client().unwrap().request(request).unwrap()
+}
+
+fn workers(daemon: &TestDaemon) -> Vec<WorkerInfo> {
+    match request(daemon, &Request::ListWorkers) {
+        Response::WorkerList { workers, .. } => workers,
+        other => panic!("unexpected response: {other:?}"),
+    }
+}
+
+fn uids(daemon: &TestDaemon) -> Vec<WorkerUid> {
+    workers(daemon)
+        .into_iter()
+        .map(|worker| worker.uid.unwrap())
+        .collect()
+}
+
+/// The slots `StatusDetailed` reports once they match `expected`
+fn wait_slots(daemon: &TestDaemon, expected: &[WorkerSlot]) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let Response::StatusDetailed { workers, .. } = request(daemon, &Request::StatusDetailed)
+        else {
+            panic!("not a detailed status response");
+        };
+        if workers == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{workers:?} is not {expected:?}");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Drain the default profile and wait for every worker to be recycled
+fn drain(daemon: &TestDaemon) {
+    let drain = Request::DrainProfile {
+        profile: DEFAULT_PROFILE.into(),
+        reason: None,
+    };
+    assert!(matches!(request(daemon, &drain), Response::Drain(_)));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !matches!(
+        request(daemon, &Request::Status),
+        Response::Status { draining: 0, .. }
+    ) {
+        assert!(Instant::now() < deadline, "drain did not finish");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn drains_give_each_slot_a_process_with_a_new_uid() {
+    let daemon = TestDaemon::builder().mock().workers(3).spawn().unwrap();
+    let boot_id = daemon
+        .client()
+        .unwrap()
+        .daemon_info()
+        .unwrap()
+        .boot_id
+        .clone();
+
+    let mut seen = BTreeSet::new();
+    for _ in 0..3 {
+        let workers = workers(&daemon);
+        assert_eq!(
+            workers.iter().map(|worker| worker.id).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        let slots: Vec<_> = workers
+            .iter()
+            .map(|worker| WorkerSlot {
+                id: worker.id,
+                uid: worker.uid.clone(),
+            })
+            .collect();
+        wait_slots(&daemon, &slots);
+
+        for uid in uids(&daemon) {
+            assert_eq!(uid.boot_id, boot_id);
+            assert!(seen.insert(uid.seq), "{uid} reused");
+        }
+        drain(&daemon);
+    }
+    assert_eq!(seen.len(), 9);
+}
+
+#[test]
+fn worker_cgroups_follow_their_process_and_stale_ones_are_reaped() {
+    let Ok(parent) = std::env::var("LEEWARD_TEST_CGROUP_ROOT") else {
+        eprintln!("skipping: LEEWARD_TEST_CGROUP_ROOT is not set");
+        return;
+    };
+    let root = Path::new(&parent).join(format!("daemon-uids-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    // Left by a daemon that crashed
+    let leaked = root.join(
+        WorkerUid {
+            boot_id: "crashed".into(),
+            seq: 1,
+        }
+        .cgroup_name(),
+    );
+    std::fs::create_dir_all(&leaked).unwrap();
+
+    let daemon = TestDaemon::builder()
+        .config(|config| {
+            config.cgroup_root = Some(root.clone());
+            config.reconcile_interval = DurationSecs::from_millis(50);
+        })
+        .spawn()
+        .unwrap();
+    if daemon.live_workers() < 2 {
+        eprintln!("skipping: workers cannot start here");
+        return;
+    }
+
+    let mut old = Vec::new();
+    for _ in 0..3 {
+        let paths: Vec<_> = uids(&daemon)
+            .iter()
+            .map(|uid| root.join(uid.cgroup_name()))
+            .collect();
+        assert!(paths.iter().all(|path| path.exists()), "{paths:?}");
+        assert!(
+            !paths.iter().any(|path| old.contains(path)),
+            "{paths:?} reused"
+        );
+        drain(&daemon);
+        old.extend(paths);
+    }
+    assert!(!old.iter().any(|path| path.exists()), "{old:?} left behind");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while leaked.exists() {
+        assert!(Instant::now() < deadline, "stale cgroup not reaped");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    drop(daemon);
+    let _ = std::fs::remove_dir(&root);
+}
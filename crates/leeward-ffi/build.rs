@@ -1,8 +1,16 @@
-//! Build script to generate C header using cbindgen
+//! Build script to generate C header using cbindgen, and to embed build
+//! provenance for `leeward_version_info()`
+
+include!("../leeward-core/build/provenance.rs");
 
 fn main() {
+    emit_provenance();
+
     let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-    let output_dir = std::path::Path::new(&crate_dir).join("..").join("..").join("include");
+    let output_dir = std::path::Path::new(&crate_dir)
+        .join("..")
+        .join("..")
+        .join("include");
 
     // Create include directory
     std::fs::create_dir_all(&output_dir).ok();
@@ -10,8 +18,7 @@ fn main() {
     let output_file = output_dir.join("leeward.h");
 
     // Generate header
-    let config = cbindgen::Config::from_file("cbindgen.toml")
-        .unwrap_or_default();
+    let config = cbindgen::Config::from_file("cbindgen.toml").unwrap_or_default();
 
     cbindgen::Builder::new()
         .with_crate(&crate_dir)
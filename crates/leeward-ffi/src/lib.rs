@@ -15,11 +15,14 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-use libc::{c_char, c_int, size_t};
-use once_cell::sync::Lazy;
+use leeward_core::OutcomeCode;
+use leeward_core::protocol::MAX_CODE_SIZE;
+use libc::{c_char, c_int, c_void, size_t};
 use std::cell::RefCell;
 use std::ffi::{CStr, CString};
+use std::io::Read;
 use std::ptr;
+use std::sync::LazyLock;
 
 /// Opaque handle to a leeward connection
 pub struct LeewardHandle {
@@ -52,34 +55,93 @@ pub struct LeewardResult {
 
 /// Execution options
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct LeewardOptions {
     /// Timeout in seconds (0 = default)
     pub timeout_secs: u64,
     /// Memory limit in bytes (0 = default)
     pub memory_limit: u64,
+    /// Largest script accepted by `leeward_execute_file()` (0 = 1 MB)
+    pub max_code_size: size_t,
 }
 
+/// Completion callback for the async execute functions
+///
+/// Receives the result (NULL on failure) and the caller's `user_data`. It
+/// runs on a background thread, where `leeward_last_error()` reports the
+/// failure. The callback owns the result and must free it.
+pub type LeewardCallback =
+    Option<unsafe extern "C" fn(result: *mut LeewardResult, user_data: *mut c_void)>;
+
 /// Error codes
+///
+/// Each is the code of a `leeward_core::OutcomeCode`, so the values match
+/// the exit codes of `leeward exec`.
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LeewardError {
     /// Success
     Ok = 0,
+    /// Timeout
+    Timeout = 124,
+    /// Out of memory
+    OutOfMemory = 137,
     /// Null pointer argument
-    NullPointer = 1,
+    NullPointer = 200,
     /// Invalid UTF-8
-    InvalidUtf8 = 2,
+    InvalidUtf8 = 201,
+    /// Script file missing, unreadable, or too large
+    InvalidScript = 202,
     /// Connection failed
-    ConnectionFailed = 3,
+    ConnectionFailed = 203,
     /// Execution failed
-    ExecutionFailed = 4,
-    /// Timeout
-    Timeout = 5,
-    /// Out of memory
-    OutOfMemory = 6,
+    ExecutionFailed = 205,
     /// Unknown error
-    Unknown = 99,
+    Unknown = 255,
 }
 
+impl LeewardError {
+    const ALL: [Self; 9] = [
+        Self::Ok,
+        Self::Timeout,
+        Self::OutOfMemory,
+        Self::NullPointer,
+        Self::InvalidUtf8,
+        Self::InvalidScript,
+        Self::ConnectionFailed,
+        Self::ExecutionFailed,
+        Self::Unknown,
+    ];
+
+    /// The outcome this error code stands for
+    const fn outcome(self) -> OutcomeCode {
+        match self {
+            Self::Ok => OutcomeCode::Exited(0),
+            Self::Timeout => OutcomeCode::Timeout,
+            Self::OutOfMemory => OutcomeCode::Killed,
+            Self::NullPointer => OutcomeCode::InvalidArgument,
+            Self::InvalidUtf8 => OutcomeCode::InvalidEncoding,
+            Self::InvalidScript => OutcomeCode::InvalidRequest,
+            Self::ConnectionFailed => OutcomeCode::ConnectionFailed,
+            Self::ExecutionFailed => OutcomeCode::Daemon,
+            Self::Unknown => OutcomeCode::Unknown,
+        }
+    }
+}
+
+// cbindgen needs literal discriminants, so check them against the mapping here
+const _: () = {
+    let mut i = 0;
+    while i < LeewardError::ALL.len() {
+        let error = LeewardError::ALL[i];
+        assert!(
+            error as i32 == error.outcome().code() as i32,
+            "LeewardError code out of step with OutcomeCode"
+        );
+        i += 1;
+    }
+};
+
 // Thread-local error message
 thread_local! {
     static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
@@ -102,18 +164,14 @@ pub extern "C" fn leeward_last_error() -> *const c_char {
     }
 
     LAST_ERROR.with(|e| {
-        let err = e.borrow();
-        match &*err {
-            Some(msg) => {
-                ERROR_BUF.with(|buf| {
-                    let cstr = CString::new(msg.as_str()).unwrap_or_default();
-                    let ptr = cstr.as_ptr();
-                    *buf.borrow_mut() = Some(cstr);
-                    ptr
-                })
-            }
-            None => ptr::null(),
-        }
+        e.borrow().as_ref().map_or(ptr::null(), |msg| {
+            ERROR_BUF.with(|buf| {
+                let cstr = CString::new(msg.as_str()).unwrap_or_default();
+                let ptr = cstr.as_ptr();
+                *buf.borrow_mut() = Some(cstr);
+                ptr
+            })
+        })
     })
 }
 
@@ -128,17 +186,16 @@ pub unsafe extern "C" fn leeward_connect(socket_path: *const c_char) -> *mut Lee
     }
 
     // SAFETY: Caller guarantees socket_path is a valid C string
-    let path = match unsafe { CStr::from_ptr(socket_path) }.to_str() {
-        Ok(s) => s.to_string(),
-        Err(_) => {
-            set_last_error("invalid UTF-8 in socket_path".into());
-            return ptr::null_mut();
-        }
+    let Ok(path) = unsafe { CStr::from_ptr(socket_path) }.to_str() else {
+        set_last_error("invalid UTF-8 in socket_path".into());
+        return ptr::null_mut();
     };
 
     // TODO: Actually connect to socket
 
-    Box::into_raw(Box::new(LeewardHandle { _socket_path: path }))
+    Box::into_raw(Box::new(LeewardHandle {
+        _socket_path: path.to_string(),
+    }))
 }
 
 /// Disconnect from the daemon
@@ -171,21 +228,176 @@ pub unsafe extern "C" fn leeward_execute(
     }
 
     // SAFETY: Caller guarantees code is a valid C string
-    let _code = match unsafe { CStr::from_ptr(code) }.to_str() {
-        Ok(s) => s,
-        Err(_) => {
-            set_last_error("invalid UTF-8 in code".into());
+    let Ok(code) = unsafe { CStr::from_ptr(code) }.to_str() else {
+        set_last_error("invalid UTF-8 in code".into());
+        return ptr::null_mut();
+    };
+
+    // SAFETY: Caller guarantees handle and options are valid
+    unsafe { execute_code(&*handle, code, options.as_ref()) }
+}
+
+/// Execute a Python script read from a file
+///
+/// The script may be at most `options->max_code_size` bytes (1 MB when
+/// zero or when `options` is NULL). Returns NULL if the file is missing,
+/// unreadable, too large, or not UTF-8, or if execution fails. Call
+/// `leeward_last_error()` for details. The caller must free the result
+/// with `leeward_result_free()`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leeward_execute_file(
+    handle: *mut LeewardHandle,
+    script_path: *const c_char,
+    options: *const LeewardOptions,
+) -> *mut LeewardResult {
+    if handle.is_null() {
+        set_last_error("handle is null".into());
+        return ptr::null_mut();
+    }
+
+    // SAFETY: Caller guarantees script_path and options are valid
+    let (code, opts) = match unsafe { read_script(script_path, options) } {
+        Ok(script) => script,
+        Err((_, msg)) => {
+            set_last_error(msg);
             return ptr::null_mut();
         }
     };
 
-    let _opts = if options.is_null() {
-        None
-    } else {
-        // SAFETY: Caller guarantees options pointer is valid
-        Some(unsafe { &*options })
+    // SAFETY: Caller guarantees handle is valid
+    unsafe { execute_code(&*handle, &code, opts.as_ref()) }
+}
+
+/// Execute a Python script read from a file, without blocking
+///
+/// The script is read and validated before returning, so file errors are
+/// reported here and `callback` is not called. Otherwise the script runs on
+/// a background thread and `callback` receives the result as with
+/// `leeward_execute_file()`. `handle` must stay connected until the
+/// callback has run.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn leeward_execute_file_async(
+    handle: *mut LeewardHandle,
+    script_path: *const c_char,
+    options: *const LeewardOptions,
+    callback: LeewardCallback,
+    user_data: *mut c_void,
+) -> LeewardError {
+    if handle.is_null() {
+        set_last_error("handle is null".into());
+        return LeewardError::NullPointer;
+    }
+
+    let Some(callback) = callback else {
+        set_last_error("callback is null".into());
+        return LeewardError::NullPointer;
+    };
+
+    // SAFETY: Caller guarantees script_path and options are valid
+    let (code, opts) = match unsafe { read_script(script_path, options) } {
+        Ok(script) => script,
+        Err((err, msg)) => {
+            set_last_error(msg);
+            return err;
+        }
+    };
+
+    let handle = SendPtr(handle);
+    let user_data = SendPtr(user_data);
+    let spawned = std::thread::Builder::new()
+        .name("leeward-execute".into())
+        .spawn(move || {
+            let (handle, user_data) = (handle.into_inner(), user_data.into_inner());
+            // SAFETY: Caller keeps the handle alive until the callback runs
+            let result = unsafe { execute_code(&*handle, &code, opts.as_ref()) };
+            // SAFETY: Caller guarantees callback is a valid function pointer
+            unsafe { callback(result, user_data) };
+        });
+
+    match spawned {
+        Ok(_) => LeewardError::Ok,
+        Err(e) => {
+            set_last_error(format!("failed to spawn execution thread: {e}"));
+            LeewardError::ExecutionFailed
+        }
+    }
+}
+
+/// Raw pointer moved onto the async execution thread
+struct SendPtr<T>(*mut T);
+
+// SAFETY: The C caller owns the pointee and keeps it alive for the thread
+unsafe impl<T> Send for SendPtr<T> {}
+
+impl<T> SendPtr<T> {
+    /// Take the pointer back out (keeps closures capturing the whole wrapper)
+    const fn into_inner(self) -> *mut T {
+        self.0
+    }
+}
+
+/// Read and validate a script for `leeward_execute_file()`
+///
+/// Returns the script and a copy of the options, or an error code and message.
+unsafe fn read_script(
+    script_path: *const c_char,
+    options: *const LeewardOptions,
+) -> Result<(String, Option<LeewardOptions>), (LeewardError, String)> {
+    if script_path.is_null() {
+        return Err((LeewardError::NullPointer, "script_path is null".into()));
+    }
+
+    // SAFETY: Caller guarantees script_path is a valid C string
+    let Ok(path) = unsafe { CStr::from_ptr(script_path) }.to_str() else {
+        return Err((
+            LeewardError::InvalidUtf8,
+            "invalid UTF-8 in script_path".into(),
+        ));
+    };
+
+    // SAFETY: Caller guarantees options pointer is valid
+    let opts = unsafe { options.as_ref() }.copied();
+    let limit = match opts.map_or(0, |o| o.max_code_size) {
+        0 => MAX_CODE_SIZE,
+        n => n,
     };
 
+    let invalid = |msg: String| (LeewardError::InvalidScript, msg);
+    let file =
+        std::fs::File::open(path).map_err(|e| invalid(format!("failed to open {path}: {e}")))?;
+    let metadata = file
+        .metadata()
+        .map_err(|e| invalid(format!("failed to stat {path}: {e}")))?;
+    if !metadata.is_file() {
+        return Err(invalid(format!("{path} is not a regular file")));
+    }
+
+    // Read one byte past the limit so a file that grew after stat is caught
+    let mut bytes = Vec::new();
+    file.take(limit as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| invalid(format!("failed to read {path}: {e}")))?;
+    if bytes.len() > limit {
+        return Err(invalid(format!(
+            "{path} is larger than the {limit} byte limit"
+        )));
+    }
+
+    let code = String::from_utf8(bytes).map_err(|_| {
+        (
+            LeewardError::InvalidUtf8,
+            format!("invalid UTF-8 in {path}"),
+        )
+    })?;
+    Ok((code, opts))
+}
+
+/// Run code over a connected handle
+fn execute_code(
+    _handle: &LeewardHandle,
+    _code: &str,
+    _options: Option<&LeewardOptions>,
+) -> *mut LeewardResult {
     // TODO: Actually execute via socket
 
     // Return dummy result for now
@@ -225,7 +437,69 @@ pub unsafe extern "C" fn leeward_result_free(result: *mut LeewardResult) {
 /// Get library version
 #[unsafe(no_mangle)]
 pub extern "C" fn leeward_version() -> *const c_char {
-    static VERSION: Lazy<CString> =
-        Lazy::new(|| CString::new(env!("CARGO_PKG_VERSION")).unwrap());
+    static VERSION: LazyLock<CString> =
+        LazyLock::new(|| CString::new(env!("CARGO_PKG_VERSION")).unwrap());
     VERSION.as_ptr()
 }
+
+/// Where the library came from, from `leeward_version_info()`
+///
+/// Every field is a null-terminated string owned by the library, valid for
+/// the life of the process.
+#[repr(C)]
+pub struct LeewardVersionInfo {
+    /// Release, as from `leeward_version()`
+    pub version: *const c_char,
+    /// `git describe` of the tree it was built from, or `unknown`
+    pub git: *const c_char,
+    /// Build time, as RFC 3339 UTC
+    pub built_at: *const c_char,
+    /// `rustc --version` of the compiler
+    pub rustc: *const c_char,
+    /// Target triple
+    pub target: *const c_char,
+    /// Cargo profile
+    pub profile: *const c_char,
+    /// Comma-separated cargo features
+    pub features: *const c_char,
+}
+
+// SAFETY: The pointers only point into strings that live as long as the
+// process and are never mutated
+unsafe impl Send for LeewardVersionInfo {}
+unsafe impl Sync for LeewardVersionInfo {}
+
+/// Get the library's build provenance
+///
+/// Never null; the struct must not be freed.
+#[unsafe(no_mangle)]
+pub extern "C" fn leeward_version_info() -> *const LeewardVersionInfo {
+    static STRINGS: LazyLock<[CString; 7]> = LazyLock::new(|| {
+        let build = leeward_core::build_info!();
+        let features = build.features.join(",");
+        [
+            &build.version,
+            &build.git,
+            &build.built_at,
+            &build.rustc,
+            &build.target,
+            &build.profile,
+            &features,
+        ]
+        .map(|value| CString::new(value.replace('\0', "")).unwrap_or_default())
+    });
+    static INFO: LazyLock<LeewardVersionInfo> = LazyLock::new(|| {
+        let [version, git, built_at, rustc, target, profile, features] =
+            STRINGS.each_ref().map(|value| value.as_ptr());
+        LeewardVersionInfo {
+            version,
+            git,
+            built_at,
+            rustc,
+            target,
+            profile,
+            features,
+        }
+    });
+    &raw const *INFO
+}
@@ -0,0 +1,56 @@
+//! Compiling and running the C programs in `tests/` against the shared
+//! library and header
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Build `tests/<name>.c` against a current libleeward and run it with a
+/// scratch directory as its only argument
+///
+/// `None` if there is no C compiler to build it with.
+pub fn run_c(name: &str) -> Option<Output> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let include_dir = manifest_dir.join("../../include");
+    // Test binaries live in target/<profile>/deps, next to the cdylib's parent
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().and_then(Path::parent).unwrap();
+
+    // `cargo test` does not build the cdylib, so make sure it is current
+    let profile = if lib_dir.ends_with("release") {
+        "release"
+    } else {
+        "dev"
+    };
+    let built = Command::new(env!("CARGO"))
+        .args(["build", "-p", "leeward-ffi", "--lib", "--profile", profile])
+        .status()
+        .unwrap();
+    assert!(built.success(), "failed to build libleeward");
+
+    let scratch = std::env::temp_dir().join(format!("leeward-ffi-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).unwrap();
+    let binary = scratch.join(name);
+
+    let compiled = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .arg(manifest_dir.join(format!("tests/{name}.c")))
+        .arg("-std=c11")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg(format!("-I{}", include_dir.display()))
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .args(["-lleeward", "-lpthread", "-o"])
+        .arg(&binary)
+        .status();
+
+    let Ok(compiled) = compiled else {
+        eprintln!("skipping: no C compiler available");
+        let _ = std::fs::remove_dir_all(&scratch);
+        return None;
+    };
+    assert!(compiled.success(), "failed to compile {name}.c");
+
+    let output = Command::new(&binary).arg(&scratch).output().unwrap();
+    let _ = std::fs::remove_dir_all(&scratch);
+    Some(output)
+}
@@ -0,0 +1,60 @@
+//! The generated header's error codes follow `OutcomeCode`
+
+use leeward_core::OutcomeCode;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Every `LeewardError` and the outcome it stands for
+const EXPECTED: &[(&str, OutcomeCode)] = &[
+    ("LEEWARD_ERROR_OK", OutcomeCode::Exited(0)),
+    ("LEEWARD_ERROR_TIMEOUT", OutcomeCode::Timeout),
+    ("LEEWARD_ERROR_OUT_OF_MEMORY", OutcomeCode::Killed),
+    ("LEEWARD_ERROR_NULL_POINTER", OutcomeCode::InvalidArgument),
+    ("LEEWARD_ERROR_INVALID_UTF8", OutcomeCode::InvalidEncoding),
+    ("LEEWARD_ERROR_INVALID_SCRIPT", OutcomeCode::InvalidRequest),
+    (
+        "LEEWARD_ERROR_CONNECTION_FAILED",
+        OutcomeCode::ConnectionFailed,
+    ),
+    ("LEEWARD_ERROR_EXECUTION_FAILED", OutcomeCode::Daemon),
+    ("LEEWARD_ERROR_UNKNOWN", OutcomeCode::Unknown),
+];
+
+/// `LEEWARD_ERROR_*` enumerators and their values, from the header
+fn header_codes() -> BTreeMap<String, u8> {
+    // The build script regenerates the header before tests run
+    let header = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../include/leeward.h");
+    let header = std::fs::read_to_string(header).unwrap();
+
+    header
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.trim().trim_end_matches(',').split_once(" = ")?;
+            name.starts_with("LEEWARD_ERROR_")
+                .then(|| (name.to_string(), value.parse().unwrap()))
+        })
+        .collect()
+}
+
+#[test]
+fn header_error_codes_match_outcomes() {
+    let codes = header_codes();
+
+    let names: Vec<_> = codes.keys().map(String::as_str).collect();
+    let mut expected_names: Vec<_> = EXPECTED.iter().map(|(name, _)| *name).collect();
+    expected_names.sort_unstable();
+    assert_eq!(names, expected_names, "LeewardError variants changed");
+
+    for (name, outcome) in EXPECTED {
+        assert_eq!(
+            codes[*name],
+            outcome.code(),
+            "{name} does not match {outcome:?}"
+        );
+        assert_eq!(
+            OutcomeCode::from_code(codes[*name]),
+            Some(*outcome),
+            "{name}"
+        );
+    }
+}
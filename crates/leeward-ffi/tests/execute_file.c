@@ -0,0 +1,97 @@
+/*
+ * Exercises leeward_execute_file() and leeward_execute_file_async()
+ *
+ * Usage: execute_file <scratch dir>
+ * Exits 0 on success; prints the failed check otherwise.
+ */
+
+#include <leeward.h>
+
+#include <pthread.h>
+#include <stdio.h>
+#include <string.h>
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            const char *err = leeward_last_error();                        \
+            fprintf(stderr, "%s:%d: check failed: %s (last error: %s)\n",  \
+                    __FILE__, __LINE__, #cond, err ? err : "none");        \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+static pthread_mutex_t done_lock = PTHREAD_MUTEX_INITIALIZER;
+static pthread_cond_t done_cond = PTHREAD_COND_INITIALIZER;
+static int done = 0;
+static int async_exit_code = -1;
+
+static void on_result(LeewardResult *result, void *user_data) {
+    pthread_mutex_lock(&done_lock);
+    if (result != NULL && user_data == &async_exit_code) {
+        async_exit_code = result->exit_code;
+    }
+    done = 1;
+    pthread_cond_signal(&done_cond);
+    pthread_mutex_unlock(&done_lock);
+    leeward_result_free(result);
+}
+
+static int write_file(const char *path, const char *contents) {
+    FILE *f = fopen(path, "w");
+    if (f == NULL) {
+        return 0;
+    }
+    fputs(contents, f);
+    return fclose(f) == 0;
+}
+
+int main(int argc, char **argv) {
+    char script[4096];
+    char missing[4096];
+
+    CHECK(argc == 2);
+    snprintf(script, sizeof(script), "%s/script.py", argv[1]);
+    snprintf(missing, sizeof(missing), "%s/missing.py", argv[1]);
+    CHECK(write_file(script, "print('hello from a file')\n"));
+
+    LeewardHandle *handle = leeward_connect("/var/run/leeward.sock");
+    CHECK(handle != NULL);
+
+    /* Default options: the script fits under the 1 MB limit */
+    LeewardResult *result = leeward_execute_file(handle, script, NULL);
+    CHECK(result != NULL);
+    CHECK(result->exit_code == 0);
+    leeward_result_free(result);
+
+    /* Missing file */
+    CHECK(leeward_execute_file(handle, missing, NULL) == NULL);
+    CHECK(leeward_last_error() != NULL);
+    CHECK(strstr(leeward_last_error(), "missing.py") != NULL);
+
+    /* Larger than max_code_size */
+    LeewardOptions small = {0};
+    small.max_code_size = 8;
+    CHECK(leeward_execute_file(handle, script, &small) == NULL);
+    CHECK(strstr(leeward_last_error(), "limit") != NULL);
+
+    /* A directory is not a script */
+    CHECK(leeward_execute_file(handle, argv[1], NULL) == NULL);
+
+    /* Async: file errors are reported up front */
+    CHECK(leeward_execute_file_async(handle, missing, NULL, on_result, NULL) ==
+          LEEWARD_ERROR_INVALID_SCRIPT);
+
+    /* Async: the callback receives the result */
+    CHECK(leeward_execute_file_async(handle, script, NULL, on_result, &async_exit_code) ==
+          LEEWARD_ERROR_OK);
+    pthread_mutex_lock(&done_lock);
+    while (!done) {
+        pthread_cond_wait(&done_cond, &done_lock);
+    }
+    pthread_mutex_unlock(&done_lock);
+    CHECK(async_exit_code == 0);
+
+    leeward_disconnect(handle);
+    return 0;
+}
@@ -0,0 +1,15 @@
+//! Builds and runs `execute_file.c` against the shared library and header
+
+mod common;
+
+#[test]
+fn c_execute_file() {
+    let Some(output) = common::run_c("execute_file") else {
+        return;
+    };
+    assert!(
+        output.status.success(),
+        "execute_file.c failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,39 @@
+/*
+ * Prints leeward_version_info(), one `field=value` line per field
+ *
+ * Usage: version_info
+ * Exits 0 on success; prints the failed check otherwise.
+ */
+
+#include <leeward.h>
+
+#include <stdio.h>
+#include <string.h>
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n",                   \
+                    __FILE__, __LINE__, #cond);                            \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+int main(void) {
+    const LeewardVersionInfo *info = leeward_version_info();
+    CHECK(info != NULL);
+    CHECK(leeward_version_info() == info);
+    CHECK(strcmp(info->version, leeward_version()) == 0);
+
+    const char *fields[][2] = {
+        {"version", info->version},   {"git", info->git},
+        {"built_at", info->built_at}, {"rustc", info->rustc},
+        {"target", info->target},     {"profile", info->profile},
+        {"features", info->features},
+    };
+    for (size_t i = 0; i < sizeof(fields) / sizeof(fields[0]); i++) {
+        CHECK(fields[i][1] != NULL);
+        printf("%s=%s\n", fields[i][0], fields[i][1]);
+    }
+    return 0;
+}
@@ -0,0 +1,46 @@
+//! `leeward_version_info()` reports where the library came from, in strings
+//! a C caller can parse
+
+mod common;
+
+use std::collections::BTreeMap;
+
+#[test]
+fn c_version_info() {
+    let Some(output) = common::run_c("version_info") else {
+        return;
+    };
+    assert!(
+        output.status.success(),
+        "version_info.c failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let fields: BTreeMap<_, _> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+
+    assert_eq!(fields["version"], env!("CARGO_PKG_VERSION"));
+    for name in ["git", "built_at", "rustc", "target", "profile"] {
+        assert_ne!(fields[name], "", "{name} is empty");
+    }
+    assert!(fields["rustc"].starts_with("rustc "), "{}", fields["rustc"]);
+    assert!(fields["profile"] == "debug" || fields["profile"] == "release");
+
+    // YYYY-MM-DDTHH:MM:SSZ
+    let built_at = fields["built_at"];
+    let digits: Vec<u32> = built_at
+        .trim_end_matches('Z')
+        .split(['-', 'T', ':'])
+        .map(|part| part.parse().unwrap())
+        .collect();
+    assert_eq!(digits.len(), 6, "{built_at}");
+    assert!(digits[0] >= 2024 && (1..=12).contains(&digits[1]) && (1..=31).contains(&digits[2]));
+
+    assert!(
+        fields["features"]
+            .split(',')
+            .any(|name| name == "leeward-core/protocol")
+    );
+}